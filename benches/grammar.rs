@@ -1,42 +1,127 @@
-#![feature(test)]
+#![cfg(feature = "nightly")]
+#![cfg_attr(feature = "nightly", feature(test))]
 
 extern crate mvp;
 extern crate test;
 
-use mvp::parser::grammar::{self, CompleteStr};
+use mvp::parser::grammar::{self, Input};
 use test::Bencher;
 
 #[bench]
 fn identifier(b: &mut Bencher) {
-    b.iter(|| grammar::identifier(CompleteStr("LDA")));
+    b.iter(|| grammar::parse_identifier(Input::new("LDA")));
 }
 
 #[bench]
 fn address(b: &mut Bencher) {
-    b.iter(|| grammar::statement(CompleteStr("LDA $19")));
+    b.iter(|| grammar::parse_statement(Input::new("LDA $19")));
 }
 
 #[bench]
 fn address_ambiguous_parse(b: &mut Bencher) {
-    b.iter(|| grammar::statement(CompleteStr("LDA ($19)+2")));
+    b.iter(|| grammar::parse_statement(Input::new("LDA ($19)+2")));
 }
 
 #[bench]
 fn immediate(b: &mut Bencher) {
-    b.iter(|| grammar::statement(CompleteStr("LDA #$19")));
+    b.iter(|| grammar::parse_statement(Input::new("LDA #$19")));
 }
 
 #[bench]
 fn expression_simple(b: &mut Bencher) {
-    b.iter(|| grammar::expression(CompleteStr("$19")));
+    b.iter(|| grammar::parse_expression(Input::new("$19")));
 }
 
 #[bench]
 fn expression_complex(b: &mut Bencher) {
-    b.iter(|| grammar::expression(CompleteStr("($19)+2")));
+    b.iter(|| grammar::parse_expression(Input::new("($19)+2")));
 }
 
 #[bench]
 fn integer_parsing(b: &mut Bencher) {
-    b.iter(|| grammar::hex_number(CompleteStr("$19")));
+    b.iter(|| {
+        for &literal in &["$7E2000", "%0110100110010110", "1234567"] {
+            test::black_box(grammar::parse_expression(Input::new(literal)).is_ok());
+        }
+    });
+}
+
+#[cfg(feature = "arena")]
+fn large_expression() -> String {
+    vec!["($19 + 2) * label"; 500].join(" - ")
+}
+
+#[cfg(feature = "arena")]
+#[bench]
+fn expression_large_boxed(b: &mut Bencher) {
+    let source = large_expression();
+    b.iter(|| grammar::parse_expression(Input::new(&source)));
+}
+
+#[cfg(feature = "arena")]
+#[bench]
+fn expression_large_arena(b: &mut Bencher) {
+    use mvp::parser::arena::{self, Arena};
+
+    let source = large_expression();
+    b.iter(|| {
+        let arena = Arena::new();
+        arena::parse_expression(&arena, Input::new(&source)).is_ok()
+    });
+}
+
+/// A program resembling hand-written code, repeated to a realistic size.
+fn realistic_program() -> String {
+    let routine = "\
+; Updates the player, called once per frame.
+UpdatePlayer{0}:
+    PHP
+    REP #$20            ; 16-bit accumulator
+    LDA.w PlayerX{0}
+    CLC
+    ADC PlayerSpeed{0}+2
+    STA PlayerX{0}
+    if Difficulty >= 2
+        LDA ($10),y     ; Enemy table
+        STA $7E0000+{0}*2
+    endif
+.wait:
+    LDX #{0}
+    DEX
+    BNE .wait
+    PLP
+    RTS
+";
+    (0..200)
+        .map(|index| routine.replace("{0}", &index.to_string()))
+        .collect()
+}
+
+#[bench]
+fn program_realistic(b: &mut Bencher) {
+    let source = realistic_program();
+    assert!(grammar::parse_program(Input::new(&source)).is_ok());
+    b.iter(|| grammar::parse_program(Input::new(&source)));
+}
+
+#[bench]
+fn program_whitespace(b: &mut Bencher) {
+    let source = format!(
+        "{}LDA {}$19{}; {}\n",
+        " ".repeat(64),
+        "\t".repeat(16),
+        " ".repeat(64),
+        "comment ".repeat(16)
+    )
+    .repeat(500);
+    assert!(grammar::parse_program(Input::new(&source)).is_ok());
+    b.iter(|| grammar::parse_program(Input::new(&source)));
+}
+
+#[bench]
+fn program_long_identifiers(b: &mut Bencher) {
+    let source =
+        "SomeRatherLongRoutineName_WithUnderscores: JSR AnotherRatherLongRoutineName\n".repeat(500);
+    assert!(grammar::parse_program(Input::new(&source)).is_ok());
+    b.iter(|| grammar::parse_program(Input::new(&source)));
 }
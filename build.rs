@@ -0,0 +1,183 @@
+//! Generates instruction tables of the encoder from data files in
+//! `src/encoder/tables`.
+//!
+//! Every line of a data file describes one instruction as fields separated
+//! by whitespace, and lines starting with `#` are comments. Fields are
+//! checked here, so that a typo in a table is reported with its line
+//! rather than as a confusing error in generated code. Each table is
+//! written as a Rust slice expression, which is included by the module
+//! declaring its type.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// A table, with its file name and names of its fields.
+struct Table {
+    name: &'static str,
+    fields: &'static [Field],
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Field {
+    /// Mnemonic in upper case.
+    Mnemonic,
+    /// A single addressing mode.
+    Mode,
+    /// Modes of operands separated by commas, or `-` for none.
+    Modes,
+    /// `ALT1`, `ALT2` or `ALT3`, or `-` for none.
+    Prefix,
+    /// Opcode in hexadecimal, like `$EA`.
+    Opcode,
+}
+
+const TABLES: &[Table] = &[
+    Table {
+        name: "w65816",
+        fields: &[Field::Mnemonic, Field::Mode, Field::Opcode],
+    },
+    Table {
+        name: "spc700",
+        fields: &[Field::Mnemonic, Field::Modes, Field::Opcode],
+    },
+    Table {
+        name: "superfx",
+        fields: &[Field::Mnemonic, Field::Modes, Field::Prefix, Field::Opcode],
+    },
+];
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    for table in TABLES {
+        let path = format!("src/encoder/tables/{}.txt", table.name);
+        println!("cargo:rerun-if-changed={}", path);
+        let data = fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        let code = generate(table, &data).unwrap_or_else(|e| panic!("{}:{}", path, e));
+        fs::write(Path::new(&out_dir).join(format!("{}.rs", table.name)), code).unwrap();
+    }
+}
+
+/// Converts a data file into a slice of tuples, or reports a line with
+/// an error.
+fn generate(table: &Table, data: &str) -> Result<String, String> {
+    let mut code = String::from("&[\n");
+    let mut seen = HashSet::new();
+    for (index, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("{}: {}", index + 1, message);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() != table.fields.len() {
+            return Err(error(format!(
+                "expected {} fields, found {}",
+                table.fields.len(),
+                words.len()
+            )));
+        }
+        let key: Vec<&str> = table
+            .fields
+            .iter()
+            .zip(&words)
+            .filter(|&(&field, _)| field != Field::Opcode)
+            .map(|(_, &word)| word)
+            .collect();
+        if !seen.insert(key) {
+            return Err(error(format!("duplicate instruction {}", line)));
+        }
+        let fields = table
+            .fields
+            .iter()
+            .zip(&words)
+            .map(|(&field, &word)| convert(field, word))
+            .collect::<Result<Vec<String>, String>>()
+            .map_err(error)?;
+        code += &format!("    ({}),\n", fields.join(", "));
+    }
+    code += "]\n";
+    Ok(code)
+}
+
+/// Converts a field into a Rust expression.
+fn convert(field: Field, word: &str) -> Result<String, String> {
+    match field {
+        Field::Mnemonic => {
+            if word
+                .bytes()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+            {
+                Ok(format!("{:?}", word))
+            } else {
+                Err(format!("invalid mnemonic {}", word))
+            }
+        }
+        Field::Mode => mode(word),
+        Field::Modes if word == "-" => Ok("&[]".to_string()),
+        Field::Modes => {
+            let modes = split_modes(word)
+                .into_iter()
+                .map(mode)
+                .collect::<Result<Vec<String>, String>>()?;
+            Ok(format!("&[{}]", modes.join(", ")))
+        }
+        Field::Prefix => match word {
+            "-" => Ok("None".to_string()),
+            "ALT1" | "ALT2" | "ALT3" => Ok(format!("Some({})", word)),
+            _ => Err(format!("invalid prefix {}", word)),
+        },
+        Field::Opcode => {
+            let digits = word.trim_start_matches('$');
+            match u8::from_str_radix(digits, 16) {
+                Ok(opcode) if word.starts_with('$') && digits.len() == 2 => {
+                    Ok(format!("0x{:02X}", opcode))
+                }
+                _ => Err(format!("invalid opcode {}", word)),
+            }
+        }
+    }
+}
+
+/// Splits modes at commas outside of parentheses, as in
+/// `Register(1,15),Word`.
+fn split_modes(word: &str) -> Vec<&str> {
+    let mut modes = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in word.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                modes.push(&word[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    modes.push(&word[start..]);
+    modes
+}
+
+/// Checks a mode, which is a name optionally followed by numbers in
+/// parentheses, like `Nibble(0,15)`.
+fn mode(word: &str) -> Result<String, String> {
+    let (name, arguments) = match word.find('(') {
+        Some(open) if word.ends_with(')') => (&word[..open], Some(&word[open + 1..word.len() - 1])),
+        _ => (word, None),
+    };
+    let valid_name = name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name.bytes().all(|c| c.is_ascii_alphanumeric());
+    let valid_arguments = arguments.is_none_or(|arguments| {
+        arguments
+            .split(',')
+            .all(|argument| argument.parse::<u8>().is_ok())
+    });
+    if valid_name && valid_arguments {
+        Ok(word.replace(',', ", "))
+    } else {
+        Err(format!("invalid mode {}", word))
+    }
+}
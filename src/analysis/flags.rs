@@ -0,0 +1,384 @@
+//! Consistency of register widths between paths.
+//!
+//! Code following a label is assembled for one accumulator and index
+//! register width, but can be reached from several places. When one of
+//! them sets the M or X flag and another clears it, as below, immediate
+//! operands are read with a wrong size on one of the paths, which usually
+//! crashes.
+//!
+//! ```asm
+//! SEP #$20
+//! BEQ Store   ; reaches Store with an 8-bit accumulator
+//! REP #$20
+//! Store:      ; but falls through with a 16-bit one
+//! ```
+//!
+//! Code after a named label is assembled for the widths code starts with,
+//! or ones set by width directives, as in `assembler::flags`. A named
+//! label reached with other widths is reported too, as its immediate
+//! operands get a wrong size on every path:
+//!
+//! ```asm
+//! REP #$20
+//! Sub:        ; assembled for an 8-bit accumulator
+//! LDA #$12    ; but reached with a 16-bit one
+//! ```
+//!
+//! Flags are followed along fall through, branches, jumps and calls to
+//! labels. Any block of an if statement is assumed to be possible. Named
+//! labels which aren't reached from other code, like interrupt handlers,
+//! are entered with unknown flags.
+
+use std::collections::HashMap;
+
+use assembler::flags::{self, State};
+use parser::ast::{
+    normalize_mnemonic, Expression, Label, OpcodeMode, Register, Span, Spanned, Statement,
+    VariableName,
+};
+
+/// A label reached with different widths of a register, or with a width
+/// code after it isn't assembled for.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Conflict<'a> {
+    /// Label declaration.
+    pub label: Span<'a>,
+    pub register: Register,
+    /// Statement reaching a label with an 8-bit register, or `None` when
+    /// code after a label is assembled for an 8-bit register.
+    pub narrow: Option<Span<'a>>,
+    /// Statement reaching a label with a 16-bit register, or `None` when
+    /// code after a label is assembled for a 16-bit register.
+    pub wide: Option<Span<'a>>,
+}
+
+/// Finds labels reached with conflicting widths of registers.
+///
+/// `entry` is the state at the start of statements, and after named labels
+/// until changed by width directives.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::analysis::flags;
+/// use mvp::assembler::flags::State;
+/// use mvp::parser::ast::Register;
+/// use mvp::parser::grammar::parse_program;
+///
+/// let source = "SEP #$20\nBEQ Store\nREP #$20\nStore:\nSTA $12\n";
+/// let statements = parse_program(source).unwrap();
+/// let conflicts = flags::check(&statements, State::default());
+/// assert_eq!(conflicts.len(), 1);
+/// assert_eq!(conflicts[0].label.fragment(), "Store:");
+/// assert_eq!(conflicts[0].register, Register::Accumulator);
+/// assert_eq!(conflicts[0].narrow.unwrap().fragment(), "BEQ Store");
+/// assert_eq!(conflicts[0].wide.unwrap().fragment(), "REP #$20");
+/// ```
+pub fn check<'a>(statements: &[Spanned<'a, Statement<'a>>], entry: State) -> Vec<Conflict<'a>> {
+    let mut graph = Graph::default();
+    let mut assumed = entry;
+    graph.flatten(statements, &mut assumed);
+    if graph.nodes.is_empty() {
+        return Vec::new();
+    }
+    graph.link();
+    let states = graph.states(entry);
+    let mut predecessors = vec![Vec::new(); graph.nodes.len()];
+    for (index, node) in graph.nodes.iter().enumerate() {
+        for &successor in &node.successors {
+            predecessors[successor].push(index);
+        }
+    }
+    let mut conflicts = Vec::new();
+    for (index, node) in graph.nodes.iter().enumerate() {
+        if node.kind != Kind::Statement {
+            continue;
+        }
+        if let Statement::Label(_) = **node.statement {
+            for &register in &[Register::Accumulator, Register::Index] {
+                let flag = |state: State| match register {
+                    Register::Accumulator => state.m_flag,
+                    Register::Index => state.x_flag,
+                };
+                let reaching = |narrow| {
+                    predecessors[index]
+                        .iter()
+                        .find(|&&predecessor| states[predecessor].and_then(flag) == Some(narrow))
+                        .map(|&predecessor| graph.nodes[predecessor].statement.span)
+                };
+                let (narrow, wide) = match (reaching(true), reaching(false)) {
+                    (Some(narrow), Some(wide)) => (Some(narrow), Some(wide)),
+                    (narrow, wide) => match node.assumed.and_then(flag) {
+                        Some(true) if wide.is_some() => (None, wide),
+                        Some(false) if narrow.is_some() => (narrow, None),
+                        _ => continue,
+                    },
+                };
+                conflicts.push(Conflict {
+                    label: node.statement.span,
+                    register,
+                    narrow,
+                    wide,
+                });
+            }
+        }
+    }
+    conflicts
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Kind {
+    Statement,
+    /// Start of an if statement, continuing in any of its blocks.
+    Fork,
+    /// End of a block of an if statement, continuing after it.
+    Join,
+}
+
+struct Node<'s, 'a: 's> {
+    /// Statement of a node, an if statement for forks and joins.
+    statement: &'s Spanned<'a, Statement<'a>>,
+    kind: Kind,
+    successors: Vec<usize>,
+    /// State code after a named label is assembled for.
+    assumed: Option<State>,
+}
+
+/// Statements with if blocks flattened, with edges of control flow.
+#[derive(Default)]
+struct Graph<'s, 'a: 's> {
+    nodes: Vec<Node<'s, 'a>>,
+    /// Named label each node follows.
+    scopes: Vec<Option<&'a str>>,
+}
+
+impl<'s, 'a> Graph<'s, 'a> {
+    fn push(&mut self, statement: &'s Spanned<'a, Statement<'a>>, kind: Kind) -> usize {
+        self.push_assumed(statement, kind, None)
+    }
+
+    fn push_assumed(
+        &mut self,
+        statement: &'s Spanned<'a, Statement<'a>>,
+        kind: Kind,
+        assumed: Option<State>,
+    ) -> usize {
+        let scope = match **statement {
+            Statement::Label(Label::Named(VariableName(name))) if kind == Kind::Statement => {
+                Some(name)
+            }
+            _ => self.scopes.last().cloned().unwrap_or(None),
+        };
+        self.nodes.push(Node {
+            statement,
+            kind,
+            successors: Vec::new(),
+            assumed,
+        });
+        self.scopes.push(scope);
+        self.nodes.len() - 1
+    }
+
+    /// Adds nodes of statements.
+    ///
+    /// `entry` is the state after named labels, which width directives
+    /// change, as in `assembler::flags`.
+    fn flatten(&mut self, statements: &'s [Spanned<'a, Statement<'a>>], entry: &mut State) {
+        for statement in statements {
+            let conditions = match **statement {
+                Statement::If(ref conditions) => conditions,
+                Statement::Expansion(ref statements) => {
+                    self.flatten(statements, entry);
+                    continue;
+                }
+                Statement::Label(Label::Named(_)) => {
+                    self.push_assumed(statement, Kind::Statement, Some(*entry));
+                    continue;
+                }
+                _ => {
+                    match **statement {
+                        Statement::RegisterWidth(register, width) => {
+                            *entry = entry.with_width(register, width);
+                        }
+                        Statement::Arch(arch) => *entry = State::of_arch(arch).unwrap_or(*entry),
+                        _ => {}
+                    }
+                    self.push(statement, Kind::Statement);
+                    continue;
+                }
+            };
+            let fork = self.push(statement, Kind::Fork);
+            let mut joins = Vec::new();
+            let mut joined = None::<State>;
+            for condition in conditions {
+                let start = self.nodes.len();
+                self.nodes[fork].successors.push(start);
+                let mut block_entry = *entry;
+                self.flatten(&condition.statements, &mut block_entry);
+                joined = Some(joined.map_or(block_entry, |joined| joined.join(block_entry)));
+                joins.push(self.push(statement, Kind::Join));
+            }
+            let after = self.nodes.len();
+            // Without an else block, none of the blocks may run.
+            if conditions
+                .last()
+                .is_none_or(|last| last.predicate.is_some())
+            {
+                self.nodes[fork].successors.push(after);
+                joined = Some(joined.map_or(*entry, |joined| joined.join(*entry)));
+            }
+            if let Some(joined) = joined {
+                *entry = joined;
+            }
+            for join in joins {
+                self.nodes[join].successors.push(after);
+            }
+        }
+    }
+
+    /// Adds edges of statements, to following statements and to labels.
+    fn link(&mut self) {
+        let mut named = HashMap::new();
+        let mut scoped = HashMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.kind != Kind::Statement {
+                continue;
+            }
+            match **node.statement {
+                Statement::Label(Label::Named(VariableName(name))) => {
+                    named.entry(name).or_insert(index);
+                }
+                Statement::Label(Label::Scoped(VariableName(name))) => {
+                    scoped.entry((self.scopes[index], name)).or_insert(index);
+                }
+                _ => {}
+            }
+        }
+        for index in 0..self.nodes.len() {
+            let node = &self.nodes[index];
+            if node.kind != Kind::Statement {
+                continue;
+            }
+            let opcode = match **node.statement {
+                Statement::Opcode(ref opcode) => opcode,
+                Statement::Error(..) => continue,
+                _ => {
+                    self.nodes[index].successors.push(index + 1);
+                    continue;
+                }
+            };
+            let name = normalize_mnemonic(opcode.name).unwrap_or(opcode.name);
+            let target = match (&opcode.mode, opcode.value.as_deref()) {
+                (OpcodeMode::Address, Some(Expression::Variable(label))) => match *label {
+                    Label::Named(VariableName(name)) => named.get(name).cloned(),
+                    Label::Scoped(VariableName(name)) => {
+                        scoped.get(&(self.scopes[index], name)).cloned()
+                    }
+                    Label::Relative(n) => self.find_relative(index, n, label),
+                },
+                _ => None,
+            };
+            let transfers = matches!(
+                name,
+                "BCC"
+                    | "BCS"
+                    | "BEQ"
+                    | "BMI"
+                    | "BNE"
+                    | "BPL"
+                    | "BVC"
+                    | "BVS"
+                    | "BRA"
+                    | "BRL"
+                    | "JMP"
+                    | "JML"
+                    | "JSR"
+                    | "JSL"
+            );
+            let falls_through = !matches!(
+                name,
+                "BRA" | "BRL" | "JMP" | "JML" | "RTS" | "RTL" | "RTI" | "STP"
+            );
+            let successors = &mut self.nodes[index].successors;
+            if falls_through {
+                successors.push(index + 1);
+            }
+            if let (true, Some(target)) = (transfers, target) {
+                successors.push(target);
+            }
+        }
+        // Code after the last statement isn't followed.
+        let end = self.nodes.len();
+        for node in &mut self.nodes {
+            node.successors.retain(|&successor| successor < end);
+        }
+    }
+
+    /// Finds a relative label, searching backward for negative labels.
+    fn find_relative(&self, from: usize, n: i32, label: &Label<'a>) -> Option<usize> {
+        let is_label = |index: &usize| {
+            self.nodes[*index].kind == Kind::Statement
+                && match **self.nodes[*index].statement {
+                    Statement::Label(ref declared) => declared == label,
+                    _ => false,
+                }
+        };
+        if n < 0 {
+            (0..from).rev().find(is_label)
+        } else {
+            (from + 1..self.nodes.len()).find(is_label)
+        }
+    }
+
+    /// States after every node, or `None` for unreached nodes.
+    ///
+    /// Named labels not reached from the start are entered with unknown
+    /// flags, one at a time.
+    fn states(&self, entry: State) -> Vec<Option<State>> {
+        let mut incoming = vec![None; self.nodes.len()];
+        let mut outgoing = vec![None; self.nodes.len()];
+        let mut pending = Vec::new();
+        let mut seed = Some((0, entry));
+        while let Some((start, state)) = seed {
+            incoming[start] = Some(state);
+            pending.push(start);
+            while let Some(index) = pending.pop() {
+                let state = self.transfer(index, incoming[index].unwrap());
+                outgoing[index] = Some(state);
+                for &successor in &self.nodes[index].successors {
+                    let joined =
+                        incoming[successor].map_or(state, |incoming: State| incoming.join(state));
+                    if incoming[successor] != Some(joined) {
+                        incoming[successor] = Some(joined);
+                        pending.push(successor);
+                    }
+                }
+            }
+            seed = self
+                .nodes
+                .iter()
+                .enumerate()
+                .position(|(index, node)| {
+                    incoming[index].is_none()
+                        && node.kind == Kind::Statement
+                        && matches!(**node.statement, Statement::Label(Label::Named(_)))
+                })
+                .map(|index| (index, State::default()));
+        }
+        outgoing
+    }
+
+    /// State after a node runs.
+    fn transfer(&self, index: usize, state: State) -> State {
+        let node = &self.nodes[index];
+        if node.kind != Kind::Statement {
+            return state;
+        }
+        match **node.statement {
+            Statement::Opcode(ref opcode) => flags::next_state(opcode, state),
+            Statement::RegisterWidth(register, width) => state.with_width(register, width),
+            Statement::Arch(arch) => State::of_arch(arch).unwrap_or(state),
+            _ => state,
+        }
+    }
+}
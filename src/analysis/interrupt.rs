@@ -0,0 +1,158 @@
+//! Interrupt safety lint.
+//!
+//! A routine called both from the main loop and from an interrupt
+//! handler can be interrupted by itself. When such a routine writes a
+//! multi-byte variable one byte at a time, as in
+//!
+//! ```asm
+//! STA Timer
+//! STX Timer+1
+//! ```
+//!
+//! an interrupt may see the variable half written. This lint reports
+//! such writes, unless they happen while interrupts are disabled, either
+//! with `SEI`, or by clearing `NMITIMEN` (`STZ $4200`).
+//!
+//! Variables known to be safe, for instance because the interrupt
+//! handler never reads them, can be excluded with `interruptsafe Timer`.
+
+use std::collections::{HashMap, HashSet};
+
+use analysis;
+use parser::ast::{BinaryOperator, Expression, Label, Number, Spanned, Statement, VariableName};
+
+/// Address of `NMITIMEN` register, which enables NMI.
+const NMITIMEN: u32 = 0x4200;
+
+/// A multi-byte variable written non-atomically by a routine reachable
+/// from both an interrupt handler and the main loop.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Warning<'a> {
+    /// Name of a routine.
+    pub routine: &'a str,
+    /// Name of a variable.
+    pub variable: &'a str,
+}
+
+/// Checks routines reachable from both `interrupt` and `main` entry points.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::analysis::interrupt::{self, Warning};
+/// use mvp::parser::grammar::{parse_program, Input};
+///
+/// let source = "
+/// Main:
+///     JSR Tick
+///     RTS
+/// Nmi:
+///     JSR Tick
+///     RTI
+/// Tick:
+///     STA Timer
+///     STX Timer+1
+///     RTS
+/// ";
+/// let statements = parse_program(Input::new(source)).unwrap();
+/// assert_eq!(
+///     interrupt::check(&statements, "Nmi", "Main"),
+///     vec![Warning { routine: "Tick", variable: "Timer" }],
+/// );
+/// ```
+pub fn check<'a>(
+    statements: &[Spanned<'a, Statement<'a>>],
+    interrupt: &'a str,
+    main: &'a str,
+) -> Vec<Warning<'a>> {
+    let graph = analysis::call_graph(statements);
+    let from_interrupt = analysis::reachable(&graph, interrupt);
+    let from_main = analysis::reachable(&graph, main);
+    let allowed: HashSet<_> = statements
+        .iter()
+        .filter_map(|statement| match **statement {
+            Statement::InterruptSafe(ref names) => Some(names.iter().map(|name| name.0)),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    let mut warnings = Vec::new();
+    for (routine, statements) in analysis::routines(statements) {
+        if !from_interrupt.contains(routine) || !from_main.contains(routine) {
+            continue;
+        }
+        for variable in unguarded_split_writes(statements) {
+            if !allowed.contains(variable) {
+                warnings.push(Warning { routine, variable });
+            }
+        }
+    }
+    warnings
+}
+
+/// Finds variables written in more than one unguarded store, in order of
+/// their first write.
+fn unguarded_split_writes<'a>(statements: &[Spanned<'a, Statement<'a>>]) -> Vec<&'a str> {
+    let mut guarded = false;
+    let mut writes: Vec<&'a str> = Vec::new();
+    let mut offsets: HashMap<&'a str, HashSet<i64>> = HashMap::new();
+    for statement in statements {
+        let opcode = match **statement {
+            Statement::Opcode(ref opcode) => opcode,
+            _ => continue,
+        };
+        let name = opcode.name.to_ascii_uppercase();
+        match &*name {
+            "SEI" => guarded = true,
+            "CLI" | "PLP" => guarded = false,
+            "STA" | "STX" | "STY" | "STZ" => {
+                let operand = match opcode.value {
+                    Some(ref operand) => operand,
+                    None => continue,
+                };
+                if is_nmitimen(operand) {
+                    guarded = name == "STZ";
+                } else if let Some((variable, offset)) = variable_offset(operand) {
+                    if !guarded {
+                        if !offsets.contains_key(variable) {
+                            writes.push(variable);
+                        }
+                        offsets.entry(variable).or_default().insert(offset);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    writes
+        .into_iter()
+        .filter(|variable| offsets[variable].len() > 1)
+        .collect()
+}
+
+fn is_nmitimen(operand: &Expression) -> bool {
+    match *operand {
+        Expression::Number(Number { value, .. }) => value == NMITIMEN,
+        Expression::Variable(Label::Named(VariableName(name))) => {
+            name.eq_ignore_ascii_case("NMITIMEN")
+        }
+        _ => false,
+    }
+}
+
+/// Splits an operand like `Timer+1` into a variable name and an offset.
+fn variable_offset<'a>(operand: &Expression<'a>) -> Option<(&'a str, i64)> {
+    match *operand {
+        Expression::Variable(Label::Named(VariableName(name))) => Some((name, 0)),
+        Expression::Binary(BinaryOperator::Add, ref operands) => {
+            match (&operands.0.node, &operands.1.node) {
+                (
+                    &Expression::Variable(Label::Named(VariableName(name))),
+                    &Expression::Number(Number { value, .. }),
+                ) => Some((name, value.into())),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
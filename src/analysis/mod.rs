@@ -0,0 +1,87 @@
+//! Static analyses of assembled programs.
+//!
+//! Analyses work on parsed statements, and split a program into routines,
+//! where a routine starts at a named label and continues until the next
+//! one.
+
+pub mod flags;
+pub mod interrupt;
+pub mod stack;
+pub mod width;
+
+use std::collections::{HashMap, HashSet};
+
+use parser::ast::{Expression, Label, Opcode, OpcodeMode, Spanned, Statement, VariableName};
+
+/// Splits top-level statements into routines.
+///
+/// Statements before the first named label don't belong to any routine.
+pub fn routines<'s, 'a>(
+    statements: &'s [Spanned<'a, Statement<'a>>],
+) -> Vec<(&'a str, &'s [Spanned<'a, Statement<'a>>])> {
+    let mut routines = Vec::new();
+    let mut current: Option<(&'a str, usize)> = None;
+    for (index, statement) in statements.iter().enumerate() {
+        if let Statement::Label(Label::Named(VariableName(name))) = **statement {
+            if let Some((previous, start)) = current {
+                routines.push((previous, &statements[start..index]));
+            }
+            current = Some((name, index + 1));
+        }
+    }
+    if let Some((name, start)) = current {
+        routines.push((name, &statements[start..]));
+    }
+    routines
+}
+
+/// Returns a named label an opcode refers to, as in `JSR Routine`.
+fn target<'a>(opcode: &Opcode<'a>) -> Option<&'a str> {
+    match (&opcode.mode, opcode.value.as_deref()) {
+        (&OpcodeMode::Address, Some(&Expression::Variable(Label::Named(VariableName(name))))) => {
+            Some(name)
+        }
+        _ => None,
+    }
+}
+
+/// Builds a call graph, mapping routines to routines they call or jump to.
+pub fn call_graph<'a>(statements: &[Spanned<'a, Statement<'a>>]) -> HashMap<&'a str, Vec<&'a str>> {
+    let routines = routines(statements);
+    let names: HashSet<_> = routines.iter().map(|&(name, _)| name).collect();
+    routines
+        .iter()
+        .map(|&(name, statements)| {
+            let callees = statements
+                .iter()
+                .filter_map(|statement| match **statement {
+                    Statement::Opcode(ref opcode) if is_transfer(opcode.name) => target(opcode),
+                    _ => None,
+                })
+                .filter(|callee| names.contains(callee))
+                .collect();
+            (name, callees)
+        })
+        .collect()
+}
+
+fn is_transfer(name: &str) -> bool {
+    ["JSR", "JSL", "JMP", "JML"]
+        .iter()
+        .any(|transfer| name.eq_ignore_ascii_case(transfer))
+}
+
+/// Finds routines reachable from an entry point in a call graph, including
+/// the entry point itself.
+pub fn reachable<'a>(graph: &HashMap<&'a str, Vec<&'a str>>, entry: &'a str) -> HashSet<&'a str> {
+    let mut visited = HashSet::new();
+    let mut pending = vec![entry];
+    while let Some(name) = pending.pop() {
+        if visited.insert(name) {
+            if let Some(callees) = graph.get(name) {
+                pending.extend(callees);
+            }
+        }
+    }
+    visited
+}
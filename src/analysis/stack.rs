@@ -0,0 +1,141 @@
+//! Stack usage estimation.
+//!
+//! The estimate follows statements of a routine in order, adding bytes
+//! pushed by stack instructions and subtracting bytes pulled, and adds
+//! usage of called routines at every `JSR` or `JSL`. Branches aren't
+//! followed, so the result is an approximation, which is usually good
+//! enough for routines that balance their pushes and pulls.
+//!
+//! As the size of registers depends on processor flags, which aren't
+//! tracked, `PHA`, `PHX` and `PHY` are assumed to push 16-bit values.
+
+use std::collections::HashMap;
+
+use analysis;
+use parser::ast::{Spanned, Statement};
+
+/// Estimated stack usage of a routine.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Usage<'a> {
+    /// Name of a routine.
+    pub name: &'a str,
+    /// Maximum number of bytes pushed by a routine and routines it calls,
+    /// including return addresses of calls.
+    pub bytes: u32,
+    /// Whether all calls were accounted for. Indirect calls, calls nested
+    /// deeper than the call depth bound and code which couldn't be parsed
+    /// make an estimate inexact.
+    pub exact: bool,
+}
+
+impl<'a> Usage<'a> {
+    /// Checks whether usage exceeds a budget in bytes.
+    pub fn exceeds(&self, budget: u32) -> bool {
+        self.bytes > budget
+    }
+}
+
+/// Estimates stack usage of every routine in a program.
+///
+/// `call_depth` bounds how many nested calls are followed, which keeps
+/// recursive routines from being followed forever.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::analysis::stack;
+/// use mvp::parser::grammar::{parse_program, Input};
+///
+/// let source = "Main:\n PHP\n JSR Helper\n PLP\n RTS\nHelper:\n PHA\n PLA\n RTS\n";
+/// let statements = parse_program(Input::new(source)).unwrap();
+/// let usage = stack::estimate(&statements, 8);
+/// assert_eq!(usage[0].name, "Main");
+/// assert_eq!(usage[0].bytes, 5);
+/// assert!(!usage[0].exceeds(8));
+/// ```
+pub fn estimate<'a>(statements: &[Spanned<'a, Statement<'a>>], call_depth: u32) -> Vec<Usage<'a>> {
+    let mut estimator = Estimator {
+        routines: analysis::routines(statements).into_iter().collect(),
+        cache: HashMap::new(),
+    };
+    analysis::routines(statements)
+        .into_iter()
+        .map(|(name, _)| {
+            let (bytes, exact) = estimator.usage(name, call_depth);
+            Usage { name, bytes, exact }
+        })
+        .collect()
+}
+
+struct Estimator<'s, 'a: 's> {
+    routines: HashMap<&'a str, &'s [Spanned<'a, Statement<'a>>]>,
+    cache: HashMap<(&'a str, u32), (u32, bool)>,
+}
+
+impl<'s, 'a> Estimator<'s, 'a> {
+    fn usage(&mut self, name: &'a str, call_depth: u32) -> (u32, bool) {
+        if let Some(&usage) = self.cache.get(&(name, call_depth)) {
+            return usage;
+        }
+        let statements = self.routines[name];
+        let mut depth = 0;
+        let mut maximum = 0;
+        let mut exact = true;
+        for statement in statements {
+            let opcode = match **statement {
+                Statement::Opcode(ref opcode) => opcode,
+                Statement::Error(..) => {
+                    exact = false;
+                    continue;
+                }
+                _ => continue,
+            };
+            let name = opcode.name.to_ascii_uppercase();
+            if let Some(bytes) = pushed_bytes(&name) {
+                depth += bytes;
+                maximum = maximum.max(depth);
+            } else if let Some(bytes) = pulled_bytes(&name) {
+                depth = depth.saturating_sub(bytes);
+            } else if let Some(bytes) = return_address_bytes(&name) {
+                let called = match analysis::target(opcode) {
+                    Some(callee) if call_depth > 0 && self.routines.contains_key(callee) => {
+                        let (bytes, callee_exact) = self.usage(callee, call_depth - 1);
+                        exact &= callee_exact;
+                        bytes
+                    }
+                    _ => {
+                        exact = false;
+                        0
+                    }
+                };
+                maximum = maximum.max(depth + bytes + called);
+            }
+        }
+        self.cache.insert((name, call_depth), (maximum, exact));
+        (maximum, exact)
+    }
+}
+
+fn pushed_bytes(name: &str) -> Option<u32> {
+    match name {
+        "PHP" | "PHB" | "PHK" => Some(1),
+        "PHA" | "PHX" | "PHY" | "PHD" | "PEA" | "PEI" | "PER" => Some(2),
+        _ => None,
+    }
+}
+
+fn pulled_bytes(name: &str) -> Option<u32> {
+    match name {
+        "PLP" | "PLB" => Some(1),
+        "PLA" | "PLX" | "PLY" | "PLD" => Some(2),
+        _ => None,
+    }
+}
+
+fn return_address_bytes(name: &str) -> Option<u32> {
+    match name {
+        "JSR" => Some(2),
+        "JSL" => Some(3),
+        _ => None,
+    }
+}
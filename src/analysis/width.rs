@@ -0,0 +1,116 @@
+//! Validation of opcode width suffixes.
+//!
+//! A suffix like `.b` in `LDA.b $12` selects the size of an operand. When
+//! a constant operand doesn't fit in the selected size, as in
+//! `LDA.b $1234`, writing it would silently drop its higher bytes, so
+//! such opcodes are reported instead, which the assembler does when it
+//! would truncate an operand.
+
+use std::fmt;
+
+use eval;
+use parser::ast::{Label, Opcode, OpcodeMode, Span, Spanned, Statement};
+use parser::visit::{self, Visitor};
+
+/// An opcode with an operand too wide for its width suffix.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mismatch<'a> {
+    /// Statement with an opcode.
+    pub span: Span<'a>,
+    /// Size in bytes selected by a suffix.
+    pub suffix: u32,
+    /// Size in bytes needed by an operand.
+    pub required: u32,
+}
+
+impl<'a> fmt::Display for Mismatch<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "operand needs {} bytes, but its {} suffix selects {}",
+            self.required,
+            suffix_name(self.suffix),
+            self.suffix
+        )
+    }
+}
+
+fn suffix_name(size: u32) -> &'static str {
+    match size {
+        1 => ".b",
+        2 => ".w",
+        _ => ".l",
+    }
+}
+
+/// Finds opcodes whose constant operands don't fit their width suffixes,
+/// including ones in if blocks.
+///
+/// Operands referring to labels are not checked, as their values aren't
+/// known before assembly.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::analysis::width;
+/// use mvp::parser::grammar::{parse_program, Input};
+///
+/// let statements = parse_program(Input::new("LDA.b $12\nLDA.b $1234,x\n")).unwrap();
+/// let mismatches = width::check(&statements);
+/// assert_eq!(mismatches.len(), 1);
+/// assert_eq!(mismatches[0].span.fragment(), "LDA.b $1234,x");
+/// assert_eq!((mismatches[0].suffix, mismatches[0].required), (1, 2));
+/// ```
+pub fn check<'a>(statements: &[Spanned<'a, Statement<'a>>]) -> Vec<Mismatch<'a>> {
+    let mut checker = Checker(Vec::new());
+    visit::walk_statements(&mut checker, statements);
+    checker.0
+}
+
+struct Checker<'a>(Vec<Mismatch<'a>>);
+
+impl<'a> Visitor<'a> for Checker<'a> {
+    fn visit_statement(&mut self, statement: &Spanned<'a, Statement<'a>>) {
+        if let Statement::Opcode(ref opcode) = **statement {
+            if let Some((suffix, required)) = mismatch(opcode) {
+                self.0.push(Mismatch {
+                    span: statement.span,
+                    suffix,
+                    required,
+                });
+            }
+        }
+        visit::walk_statement(self, statement);
+    }
+}
+
+fn mismatch(opcode: &Opcode) -> Option<(u32, u32)> {
+    let suffix = opcode.width?;
+    match opcode.mode {
+        OpcodeMode::Move { .. } if opcode.index_register().is_none() => return None,
+        OpcodeMode::Implied | OpcodeMode::Accumulator => return None,
+        _ => {}
+    }
+    let value = eval::evaluate(opcode.value.as_ref()?, &no_labels).ok()?;
+    let required = required_bytes(value);
+    if required > suffix {
+        Some((suffix, required))
+    } else {
+        None
+    }
+}
+
+/// Number of bytes needed to write a value, allowing negative values in
+/// two's complement.
+fn required_bytes(value: i64) -> u32 {
+    (1..8)
+        .find(|&size| {
+            let bits = 8 * size;
+            value >= -(1 << (bits - 1)) && value < 1 << bits
+        })
+        .unwrap_or(8)
+}
+
+fn no_labels(_: &Label) -> Option<i64> {
+    None
+}
@@ -0,0 +1,148 @@
+//! Compatibility with Asar.
+//!
+//! Patches written for Asar can pin a version of Asar with the `asar`
+//! directive, which makes following lines parse like in Asar:
+//!
+//! ```asm
+//! asar 1.81
+//! !speed = 2+1*2 ; 6, as operators are evaluated from left to right
+//! math pri on
+//! !speed #= 2+1*2 ; 4
+//! org $008000    ; unsupported Asar feature
+//! ```
+//!
+//! Asar evaluates operators from left to right unless `math pri on`
+//! enables their precedence, and `!name #= value` assigns a value, like
+//! `=` in mvp. Defines holding text which isn't an expression, and other
+//! Asar features mvp doesn't have, like `org` and `freecode`, are parsed
+//! as `Statement::AsarFeature`, which fails assembly with a name of the
+//! feature, rather than with a syntax error.
+//!
+//! Asar finds freespace filled with `FREESPACE_FILL`, which can be given
+//! to `freespace::Allocator` to place blocks where Asar would.
+
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Newest version of Asar whose behaviour is followed, newer versions
+/// are rejected by the `asar` directive.
+pub const VERSION: AsarVersion = AsarVersion {
+    major: 1,
+    minor: 81,
+};
+
+/// Bytes of freespace found by Asar's `freecode` and `freedata`.
+pub const FREESPACE_FILL: &[u8] = &[0x00];
+
+/// Names of Asar directives mvp doesn't have.
+const UNSUPPORTED: &[&str] = &[
+    "autoclean",
+    "base",
+    "bankcross",
+    "check",
+    "cleartable",
+    "db",
+    "dd",
+    "dl",
+    "dw",
+    "error",
+    "fill",
+    "fillbyte",
+    "freecode",
+    "freedata",
+    "freespace",
+    "freespacebyte",
+    "incbin",
+    "math",
+    "namespace",
+    "norom",
+    "optimize",
+    "org",
+    "pad",
+    "padbyte",
+    "prot",
+    "pullbase",
+    "pullpc",
+    "pulltable",
+    "pushbase",
+    "pushpc",
+    "pushtable",
+    "skip",
+    "struct",
+    "table",
+    "warn",
+    "while",
+];
+
+/// Finds an Asar directive mvp doesn't have, ignoring case.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::asar;
+///
+/// assert_eq!(asar::unsupported_directive("ORG"), Some("org"));
+/// assert_eq!(asar::unsupported_directive("incsrc"), None);
+/// ```
+pub fn unsupported_directive(name: &str) -> Option<&'static str> {
+    UNSUPPORTED
+        .iter()
+        .find(|directive| directive.eq_ignore_ascii_case(name))
+        .cloned()
+}
+
+/// A version of Asar, as in `asar 1.81`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct AsarVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl fmt::Display for AsarVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:02}", self.major, self.minor)
+    }
+}
+
+/// A version which isn't two numbers separated by a dot.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct InvalidVersion;
+
+impl fmt::Display for InvalidVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Asar version must look like 1.81")
+    }
+}
+
+impl error::Error for InvalidVersion {}
+
+/// Parses a version, like `1.81`. Minor versions have two digits, so
+/// `1.8` is the same as `1.80`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::asar::AsarVersion;
+///
+/// let version: AsarVersion = "1.8".parse().unwrap();
+/// assert_eq!(version, AsarVersion { major: 1, minor: 80 });
+/// assert!(version < "1.81".parse().unwrap());
+/// assert!("1.801".parse::<AsarVersion>().is_err());
+/// ```
+impl FromStr for AsarVersion {
+    type Err = InvalidVersion;
+
+    fn from_str(version: &str) -> Result<AsarVersion, InvalidVersion> {
+        let (major, minor) = version.split_once('.').ok_or(InvalidVersion)?;
+        let digits = |part: &str| !part.is_empty() && part.bytes().all(|c| c.is_ascii_digit());
+        if !digits(major) || !digits(minor) || minor.len() > 2 {
+            return Err(InvalidVersion);
+        }
+        let scale = if minor.len() == 1 { 10 } else { 1 };
+        Ok(AsarVersion {
+            major: major.parse().map_err(|_| InvalidVersion)?,
+            minor: minor.parse::<u32>().map_err(|_| InvalidVersion)? * scale,
+        })
+    }
+}
@@ -0,0 +1,383 @@
+//! Rewriting of out-of-range branches.
+//!
+//! A short branch reaches 128 bytes backward and 127 bytes forward, from
+//! the end of the branch. Branches to labels further away can be rewritten
+//! into a jump: `BRA` becomes a jump by itself, while a conditional branch
+//! becomes a branch with an inverted condition over the jump.
+//!
+//! ```asm
+//! BEQ Far     ; becomes BNE $+5
+//!             ;         BRL Far
+//! ```
+//!
+//! Rewriting a branch makes code longer, which can move other branches out
+//! of range, so branches are rewritten until all remaining ones fit.
+//!
+//! Code is laid out before if conditions are evaluated, so statements of
+//! every if block between a branch and its target count towards the
+//! distance, and a branch is rewritten when any of them could put the
+//! target out of range.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use encoder::{self, AddressingMode};
+use parser::ast::{
+    BinaryOperator, Condition, Expression, Label, Number, NumberWidth, Opcode, OpcodeMode, Span,
+    Spanned, Statement, VariableName,
+};
+
+/// Instruction used to jump to a distant label.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LongJump {
+    /// `BRL`, which keeps code position independent.
+    Brl,
+    /// `JMP`, which only reaches labels in the same bank.
+    Jmp,
+}
+
+/// A failure to lay out code.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error<'a> {
+    /// Size of an instruction isn't known.
+    UnknownSize(Span<'a>),
+}
+
+impl<'a> Error<'a> {
+    /// Statement an error is about.
+    pub fn span(&self) -> Span<'a> {
+        match *self {
+            Error::UnknownSize(span) => span,
+        }
+    }
+}
+
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownSize(_) => write!(f, "size of instruction isn't known"),
+        }
+    }
+}
+
+impl<'a> error::Error for Error<'a> {}
+
+/// Conditional branches along with branches with an inverted condition.
+const BRANCHES: &[(&str, &str)] = &[
+    ("BCC", "BCS"),
+    ("BCS", "BCC"),
+    ("BEQ", "BNE"),
+    ("BMI", "BPL"),
+    ("BNE", "BEQ"),
+    ("BPL", "BMI"),
+    ("BVC", "BVS"),
+    ("BVS", "BVC"),
+];
+
+/// Size of a short branch.
+const SHORT: i64 = 2;
+
+/// Size of a jump, either `BRL` or `JMP`.
+const JUMP: i64 = 3;
+
+/// Rewrites branches to labels out of their range into jumps.
+///
+/// `size` returns the size of an instruction in bytes, which is needed to
+/// find distances between labels, like `estimated_size`. Only branches to
+/// labels are rewritten, as other targets aren't known before assembly.
+/// Branches in if blocks and macro expansions are rewritten too.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::branch::{self, LongJump};
+/// use mvp::parser::grammar::parse_program;
+///
+/// let source = format!("Start:\n{}BEQ Start\n", "LDA $1234\n".repeat(50));
+/// let statements = parse_program(source.as_str()).unwrap();
+/// let rewritten = branch::rewrite_long_branches(statements, LongJump::Brl, |_| Some(3)).unwrap();
+/// let expected = parse_program("BNE $+5\nBRL Start\n").unwrap();
+/// assert_eq!(rewritten[51..], expected[..]);
+/// ```
+pub fn rewrite_long_branches<'a, F>(
+    statements: Vec<Spanned<'a, Statement<'a>>>,
+    jump: LongJump,
+    mut size: F,
+) -> Result<Vec<Spanned<'a, Statement<'a>>>, Error<'a>>
+where
+    F: FnMut(&Opcode<'a>) -> Option<u32>,
+{
+    let long = {
+        let mut flattened = Vec::new();
+        flatten(&statements, &mut flattened);
+        long_branches(&flattened, &mut size)?
+    };
+    Ok(rewrite_all(statements, jump, &mut long.into_iter()))
+}
+
+/// Estimates the size of an instruction before its operand is known,
+/// like the first pass of the assembler, with immediate operands of
+/// 16 bits unless a width is given.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::branch;
+/// use mvp::parser::ast::Statement;
+/// use mvp::parser::grammar::parse_statement;
+///
+/// let size = |source| match parse_statement(source) {
+///     Ok((Statement::Opcode(opcode), _)) => branch::estimated_size(&opcode),
+///     _ => unreachable!(),
+/// };
+/// assert_eq!(size("LDA #0"), Some(3));
+/// assert_eq!(size("LDA $12"), Some(2));
+/// assert_eq!(size("LDA Table,x"), Some(3));
+/// assert_eq!(size("JSL Routine"), Some(4));
+/// assert_eq!(size("BRK"), Some(2));
+/// ```
+pub fn estimated_size(opcode: &Opcode) -> Option<u32> {
+    let mode = match encoder::resolve_mode(opcode, None, None) {
+        Ok(mode) => mode,
+        Err(_) => {
+            let size = encoder::instruction_size(opcode.name, AddressingMode::Implied, true, true)?;
+            return Some(size as u32);
+        }
+    };
+    let operand = match mode.operand_size() {
+        Some(size) => size,
+        None => opcode.width.unwrap_or(2),
+    };
+    Some(1 + operand)
+}
+
+/// Lists statements in order, with statements of if blocks and macro
+/// expansions following them.
+fn flatten<'s, 'a>(
+    statements: &'s [Spanned<'a, Statement<'a>>],
+    flattened: &mut Vec<&'s Spanned<'a, Statement<'a>>>,
+) {
+    for statement in statements {
+        flattened.push(statement);
+        match **statement {
+            Statement::If(ref conditions) => {
+                for condition in conditions {
+                    flatten(&condition.statements, flattened);
+                }
+            }
+            Statement::Expansion(ref statements) => flatten(statements, flattened),
+            _ => {}
+        }
+    }
+}
+
+/// Finds which of flattened statements are branches needing a jump.
+fn long_branches<'a, F>(
+    statements: &[&Spanned<'a, Statement<'a>>],
+    size: &mut F,
+) -> Result<Vec<bool>, Error<'a>>
+where
+    F: FnMut(&Opcode<'a>) -> Option<u32>,
+{
+    let mut sizes = Vec::with_capacity(statements.len());
+    for statement in statements {
+        sizes.push(match ***statement {
+            Statement::Opcode(ref opcode) if branch(opcode).is_some() => SHORT,
+            Statement::Opcode(ref opcode) => {
+                i64::from(size(opcode).ok_or(Error::UnknownSize(statement.span))?)
+            }
+            _ => 0,
+        });
+    }
+    let labels = Labels::new(statements);
+    let branches: Vec<_> = statements
+        .iter()
+        .enumerate()
+        .filter_map(|(index, statement)| match ***statement {
+            Statement::Opcode(ref opcode) => {
+                let target = branch(opcode)?;
+                Some((index, labels.find(statements, index, target)?))
+            }
+            _ => None,
+        })
+        .collect();
+    let mut long = vec![false; statements.len()];
+    loop {
+        let mut addresses = Vec::with_capacity(statements.len());
+        let mut address = 0;
+        for (index, &size) in sizes.iter().enumerate() {
+            addresses.push(address);
+            address += size;
+            if long[index] {
+                address += long_size(statements[index]) - SHORT;
+            }
+        }
+        let mut changed = false;
+        for &(index, target) in &branches {
+            let distance = addresses[target] - (addresses[index] + SHORT);
+            if !long[index] && !(-128..128).contains(&distance) {
+                long[index] = true;
+                changed = true;
+            }
+        }
+        if !changed {
+            return Ok(long);
+        }
+    }
+}
+
+/// Rewrites branches, with `long` telling which of flattened statements
+/// need a jump.
+fn rewrite_all<'a, I: Iterator<Item = bool>>(
+    statements: Vec<Spanned<'a, Statement<'a>>>,
+    jump: LongJump,
+    long: &mut I,
+) -> Vec<Spanned<'a, Statement<'a>>> {
+    let mut rewritten = Vec::with_capacity(statements.len());
+    for Spanned { node, span } in statements {
+        let is_long = long.next() == Some(true);
+        let node = match node {
+            Statement::If(conditions) => Statement::If(
+                conditions
+                    .into_iter()
+                    .map(|condition| Condition {
+                        predicate: condition.predicate,
+                        statements: rewrite_all(condition.statements, jump, long),
+                    })
+                    .collect(),
+            ),
+            Statement::Expansion(statements) => {
+                Statement::Expansion(rewrite_all(statements, jump, long))
+            }
+            node => node,
+        };
+        let statement = Spanned { node, span };
+        if is_long {
+            rewrite(statement, jump, &mut rewritten);
+        } else {
+            rewritten.push(statement);
+        }
+    }
+    rewritten
+}
+
+/// Returns a target of a branch to a label.
+fn branch<'s, 'a>(opcode: &'s Opcode<'a>) -> Option<&'s Label<'a>> {
+    let is_branch = opcode.name.eq_ignore_ascii_case("BRA")
+        || BRANCHES
+            .iter()
+            .any(|&(name, _)| opcode.name.eq_ignore_ascii_case(name));
+    match (&opcode.mode, opcode.value.as_deref()) {
+        (&OpcodeMode::Address, Some(Expression::Variable(label))) if is_branch => Some(label),
+        _ => None,
+    }
+}
+
+fn long_size(statement: &Statement) -> i64 {
+    match *statement {
+        Statement::Opcode(ref opcode) if opcode.name.eq_ignore_ascii_case("BRA") => JUMP,
+        _ => SHORT + JUMP,
+    }
+}
+
+fn rewrite<'a>(
+    statement: Spanned<'a, Statement<'a>>,
+    jump: LongJump,
+    rewritten: &mut Vec<Spanned<'a, Statement<'a>>>,
+) {
+    let span = statement.span;
+    let opcode = match statement.node {
+        Statement::Opcode(opcode) => opcode,
+        _ => unreachable!(),
+    };
+    let inverted = BRANCHES
+        .iter()
+        .find(|&&(name, _)| opcode.name.eq_ignore_ascii_case(name));
+    if let Some(&(_, inverted)) = inverted {
+        // Skips the jump, with `$` being the address of the branch.
+        let skip = Expression::Binary(
+            BinaryOperator::Add,
+            Box::new((
+                Expression::ProgramCounter.into(),
+                Expression::Number(Number {
+                    value: (SHORT + JUMP) as u32,
+                    width: NumberWidth::None,
+                })
+                .into(),
+            )),
+        );
+        rewritten.push(Spanned {
+            node: Statement::Opcode(Opcode {
+                name: inverted,
+                width: None,
+                mode: OpcodeMode::Address,
+                value: Some(skip.into()),
+            }),
+            span,
+        });
+    }
+    let name = match jump {
+        LongJump::Brl => "BRL",
+        LongJump::Jmp => "JMP",
+    };
+    rewritten.push(Spanned {
+        node: Statement::Opcode(Opcode { name, ..opcode }),
+        span,
+    });
+}
+
+/// Positions of named labels, for finding targets of branches.
+struct Labels<'a> {
+    named: HashMap<&'a str, usize>,
+    /// Scoped labels, by the named label they follow.
+    scoped: HashMap<(Option<&'a str>, &'a str), usize>,
+    /// Named label each statement follows.
+    scopes: Vec<Option<&'a str>>,
+}
+
+impl<'a> Labels<'a> {
+    fn new(statements: &[&Spanned<'a, Statement<'a>>]) -> Labels<'a> {
+        let mut labels = Labels {
+            named: HashMap::new(),
+            scoped: HashMap::new(),
+            scopes: Vec::with_capacity(statements.len()),
+        };
+        let mut scope = None;
+        for (index, statement) in statements.iter().enumerate() {
+            match ***statement {
+                Statement::Label(Label::Named(VariableName(name))) => {
+                    labels.named.entry(name).or_insert(index);
+                    scope = Some(name);
+                }
+                Statement::Label(Label::Scoped(VariableName(name))) => {
+                    labels.scoped.entry((scope, name)).or_insert(index);
+                }
+                _ => {}
+            }
+            labels.scopes.push(scope);
+        }
+        labels
+    }
+
+    /// Finds a statement declaring a label used by a statement at `from`.
+    fn find(
+        &self,
+        statements: &[&Spanned<'a, Statement<'a>>],
+        from: usize,
+        label: &Label<'a>,
+    ) -> Option<usize> {
+        let is_label = |index: &usize| match **statements[*index] {
+            Statement::Label(ref declared) => declared == label,
+            _ => false,
+        };
+        match *label {
+            Label::Named(VariableName(name)) => self.named.get(name).cloned(),
+            Label::Scoped(VariableName(name)) => {
+                self.scoped.get(&(self.scopes[from], name)).cloned()
+            }
+            Label::Relative(n) if n < 0 => (0..from).rev().find(is_label),
+            Label::Relative(_) => (from + 1..statements.len()).find(is_label),
+        }
+    }
+}
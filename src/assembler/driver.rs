@@ -0,0 +1,399 @@
+//! Assembly configured in one place.
+//!
+//! An `Assembler` is created with `Assembler::builder`, which collects
+//! options of every stage, from parsing to writing output, so that new
+//! options don't change signatures of functions doing the work.
+
+use std::io::Write;
+use std::iter;
+use std::path::PathBuf;
+
+use assembler::branch::LongJump;
+use assembler::flags::State;
+use assembler::output::Output;
+use assembler::sfc;
+use encoder::Aliases;
+use include::{self, Disk, FileSystem};
+use mapping::Mapper;
+use parser::grammar::{Dialect, ParserOptions};
+use project::{self, Project};
+use rom::Header;
+use signature::{Manifest, Sha256};
+use warning::{Category, Level};
+
+/// What `Assembler::write` produces.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OutputKind {
+    /// Assembled bytes, starting at the origin.
+    Raw,
+    /// ROM image with an internal header, see `sfc::build`, or the base
+    /// ROM patched with `sfc::patch` when there is one.
+    Sfc,
+}
+
+/// Options of an `Assembler`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::{Assembler, OutputKind};
+/// use mvp::include::{Disk, Overlay};
+/// use mvp::mapping::Mapper;
+///
+/// let mut files = Overlay::new(Disk);
+/// files.insert("/game/main.asm", "LDA #!lives\nSTA $10\n");
+/// let mut assembler = Assembler::builder()
+///     .file_system(files)
+///     .root("/game/main.asm")
+///     .define("!lives", "3")
+///     .mapper(Mapper::HiRom)
+///     .origin(0xC00000)
+///     .output(OutputKind::Raw)
+///     .build();
+/// assembler.load().unwrap();
+/// let output = assembler.assemble().unwrap();
+/// assert_eq!(output.data, [0xA9, 0x03, 0x85, 0x10]);
+/// assert_eq!(output.mapper, Mapper::HiRom);
+/// assert_eq!(assembler.write(&output).unwrap(), output.data);
+/// ```
+#[derive(Debug)]
+pub struct Builder<F> {
+    file_system: F,
+    roots: Vec<(PathBuf, Option<String>)>,
+    directories: Vec<PathBuf>,
+    defines: Vec<(String, String)>,
+    mapper: Option<Mapper>,
+    options: ParserOptions,
+    levels: Vec<(Category, Level)>,
+    aliases: Aliases,
+    long_branches: Option<LongJump>,
+    origin: u32,
+    state: State,
+    output: OutputKind,
+    title: String,
+    base_rom: Option<Vec<u8>>,
+    signature_block: bool,
+    stack_budget: Option<(u32, u32)>,
+    interrupt_entries: Option<(String, String)>,
+}
+
+impl<F: FileSystem> Builder<F> {
+    /// Reads files from another file system, like an overlay of editor
+    /// buffers. Files are read from disk by default.
+    pub fn file_system<G: FileSystem>(self, file_system: G) -> Builder<G> {
+        Builder {
+            file_system,
+            roots: self.roots,
+            directories: self.directories,
+            defines: self.defines,
+            mapper: self.mapper,
+            options: self.options,
+            levels: self.levels,
+            aliases: self.aliases,
+            long_branches: self.long_branches,
+            origin: self.origin,
+            state: self.state,
+            output: self.output,
+            title: self.title,
+            base_rom: self.base_rom,
+            signature_block: self.signature_block,
+            stack_budget: self.stack_budget,
+            interrupt_entries: self.interrupt_entries,
+        }
+    }
+
+    /// Adds a root file, see `Project::add_root`.
+    pub fn root<P: Into<PathBuf>>(mut self, path: P) -> Builder<F> {
+        self.roots.push((path.into(), None));
+        self
+    }
+
+    /// Adds a root file with given contents, like source read from
+    /// standard input, see `Project::add_source`.
+    pub fn source<P: Into<PathBuf>, S: Into<String>>(mut self, path: P, contents: S) -> Builder<F> {
+        self.roots.push((path.into(), Some(contents.into())));
+        self
+    }
+
+    /// Adds a directory searched for included files.
+    pub fn include_directory<P: Into<PathBuf>>(mut self, directory: P) -> Builder<F> {
+        self.directories.push(directory.into());
+        self
+    }
+
+    /// Assigns an expression to a name before assembly, see
+    /// `Project::define`.
+    pub fn define<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Builder<F> {
+        self.defines.push((name.into(), value.into()));
+        self
+    }
+
+    /// Rewrites branches to labels out of their range into jumps, see
+    /// `Project::set_long_branches`.
+    pub fn long_branches(mut self, jump: LongJump) -> Builder<F> {
+        self.long_branches = Some(jump);
+        self
+    }
+
+    /// Selects a mapper at the start of code. LoROM is used by default.
+    pub fn mapper(mut self, mapper: Mapper) -> Builder<F> {
+        self.mapper = Some(mapper);
+        self
+    }
+
+    /// Accepts syntax of a dialect, Asar by default.
+    pub fn dialect(mut self, dialect: Dialect) -> Builder<F> {
+        self.options = ParserOptions::dialect(dialect);
+        self
+    }
+
+    /// Sets parser options, for more control than `dialect` gives.
+    pub fn parser_options(mut self, options: ParserOptions) -> Builder<F> {
+        self.options = options;
+        self
+    }
+
+    /// Sets the level of a warning category, see
+    /// `Project::set_warning_level`.
+    pub fn warning_level(mut self, category: Category, level: Level) -> Builder<F> {
+        self.levels.push((category, level));
+        self
+    }
+
+    /// Sets whether alias mnemonics are accepted, see
+    /// `Project::set_aliases`.
+    pub fn aliases(mut self, aliases: Aliases) -> Builder<F> {
+        self.aliases = aliases;
+        self
+    }
+
+    /// Sets an address of the start of code, `$008000` by default.
+    pub fn origin(mut self, origin: u32) -> Builder<F> {
+        self.origin = origin;
+        self
+    }
+
+    /// Sets register widths at the start of code, 8-bit by default.
+    pub fn state(mut self, state: State) -> Builder<F> {
+        self.state = state;
+        self
+    }
+
+    /// Selects what `Assembler::write` produces, a ROM image by default.
+    pub fn output(mut self, output: OutputKind) -> Builder<F> {
+        self.output = output;
+        self
+    }
+
+    /// Sets a title of a ROM image written into its header.
+    pub fn title<T: Into<String>>(mut self, title: T) -> Builder<F> {
+        self.title = title.into();
+        self
+    }
+
+    /// Sets a ROM being patched, see `Project::set_base_rom`.
+    pub fn base_rom(mut self, rom: Vec<u8>) -> Builder<F> {
+        self.base_rom = Some(rom);
+        self
+    }
+
+    /// Appends a signature block to written ROMs, see
+    /// `Assembler::manifest`.
+    pub fn signature_block(mut self, signature_block: bool) -> Builder<F> {
+        self.signature_block = signature_block;
+        self
+    }
+
+    /// Reports routines using too much stack, see
+    /// `Project::set_stack_budget`.
+    pub fn stack_budget(mut self, budget: u32, call_depth: u32) -> Builder<F> {
+        self.stack_budget = Some((budget, call_depth));
+        self
+    }
+
+    /// Checks interrupt safety of routines called from an interrupt
+    /// handler and the main loop, see `Project::set_interrupt_entries`.
+    pub fn interrupt_entries<I: Into<String>, M: Into<String>>(
+        mut self,
+        interrupt: I,
+        main: M,
+    ) -> Builder<F> {
+        self.interrupt_entries = Some((interrupt.into(), main.into()));
+        self
+    }
+
+    /// Creates an assembler with files which aren't loaded yet.
+    pub fn build(self) -> Assembler<F> {
+        let mut project = Project::new(self.file_system);
+        for (path, contents) in self.roots {
+            match contents {
+                Some(contents) => project.add_source(path, contents),
+                None => project.add_root(path),
+            }
+        }
+        for directory in self.directories {
+            project.add_directory(directory);
+        }
+        project.set_options(self.options);
+        if let Some(mapper) = self.mapper {
+            project.set_mapper(mapper);
+        }
+        for (name, value) in &self.defines {
+            project.define(name, value);
+        }
+        for (category, level) in self.levels {
+            project.set_warning_level(category, level);
+        }
+        project.set_aliases(self.aliases);
+        if let Some(jump) = self.long_branches {
+            project.set_long_branches(jump);
+        }
+        if let Some(rom) = self.base_rom {
+            project.set_base_rom(rom);
+        }
+        if let Some((budget, call_depth)) = self.stack_budget {
+            project.set_stack_budget(budget, call_depth);
+        }
+        if let Some((interrupt, main)) = self.interrupt_entries {
+            project.set_interrupt_entries(interrupt, main);
+        }
+        Assembler {
+            project,
+            origin: self.origin,
+            state: self.state,
+            output: self.output,
+            title: self.title,
+            signature_block: self.signature_block,
+        }
+    }
+}
+
+/// Assembly of a project with options given by a `Builder`.
+#[derive(Debug)]
+pub struct Assembler<F> {
+    project: Project<F>,
+    origin: u32,
+    state: State,
+    output: OutputKind,
+    title: String,
+    signature_block: bool,
+}
+
+impl Assembler<Disk> {
+    /// Starts configuring an assembler reading files from disk.
+    pub fn builder() -> Builder<Disk> {
+        Builder {
+            file_system: Disk,
+            roots: Vec::new(),
+            directories: Vec::new(),
+            defines: Vec::new(),
+            mapper: None,
+            options: ParserOptions::default(),
+            levels: Vec::new(),
+            aliases: Aliases::default(),
+            long_branches: None,
+            origin: 0x8000,
+            state: State::new(true, true),
+            output: OutputKind::Sfc,
+            title: String::new(),
+            base_rom: None,
+            signature_block: false,
+            stack_budget: None,
+            interrupt_entries: None,
+        }
+    }
+}
+
+impl<F: FileSystem> Assembler<F> {
+    /// Project of files being assembled.
+    pub fn project(&self) -> &Project<F> {
+        &self.project
+    }
+
+    /// Mutable access to the project, for updating files between builds.
+    pub fn project_mut(&mut self) -> &mut Project<F> {
+        &mut self.project
+    }
+
+    /// Reads root files and files they include, see `Project::load`.
+    pub fn load(&mut self) -> Result<(), include::Error> {
+        self.project.load()
+    }
+
+    /// Assembles loaded files.
+    pub fn assemble(&self) -> Result<Output<'_>, project::Error<'_>> {
+        self.project.assemble(self.origin, self.state)
+    }
+
+    /// Turns assembled code into bytes of the selected output kind, with
+    /// a signature block appended when one was requested.
+    pub fn write<'a>(&self, output: &Output<'a>) -> Result<Vec<u8>, sfc::Error<'a>> {
+        let mut rom = match self.output {
+            OutputKind::Raw => output.data.clone(),
+            OutputKind::Sfc => match self.project.base_rom() {
+                Some(rom) => {
+                    let mut rom = rom.to_vec();
+                    sfc::patch(&mut rom, output)?;
+                    rom
+                }
+                None => sfc::build(output, &Header::new(&self.title, output.mapper))?,
+            },
+        };
+        if self.signature_block {
+            self.manifest(output, &rom)?
+                .append_block(&mut rom)
+                .map_err(sfc::Error::Signature)?;
+        }
+        Ok(rom)
+    }
+
+    /// Signs ranges of written bytes which assembled code was written to,
+    /// so that `Manifest::verify` can check that a ROM has code of
+    /// a build.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::assembler::{Assembler, OutputKind};
+    /// use mvp::include::{Disk, Overlay};
+    /// use mvp::signature::{Manifest, Sha256};
+    ///
+    /// let mut files = Overlay::new(Disk);
+    /// files.insert("/game/main.asm", "LDA #$12\n");
+    /// let mut assembler = Assembler::builder()
+    ///     .file_system(files)
+    ///     .root("/game/main.asm")
+    ///     .signature_block(true)
+    ///     .build();
+    /// assembler.load().unwrap();
+    /// let output = assembler.assemble().unwrap();
+    /// let mut rom = assembler.write(&output).unwrap();
+    /// let manifest = assembler.manifest(&output, &rom).unwrap();
+    /// assert_eq!(manifest.ranges, [0..2]);
+    /// assert_eq!(Manifest::find_block(&rom), Ok(manifest));
+    /// rom[1] = 0x34;
+    /// assert_eq!(Manifest::find_block(&rom).unwrap().verify(&rom, &Sha256), Ok(false));
+    /// ```
+    pub fn manifest<'a>(
+        &self,
+        output: &Output<'a>,
+        rom: &[u8],
+    ) -> Result<Manifest, sfc::Error<'a>> {
+        let ranges = match self.output {
+            OutputKind::Raw => iter::once(0..output.data.len()).collect(),
+            OutputKind::Sfc => sfc::written_ranges(output)?,
+        };
+        Manifest::sign(rom, ranges, &Sha256).map_err(sfc::Error::Signature)
+    }
+
+    /// Writes assembled code into a writer, like standard output, see
+    /// `write`.
+    pub fn write_to<'a, W: Write>(
+        &self,
+        output: &Output<'a>,
+        mut writer: W,
+    ) -> Result<(), sfc::Error<'a>> {
+        writer.write_all(&self.write(output)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+//! Tracking of register widths for immediate operands.
+//!
+//! An immediate operand of `LDA` is one byte with an 8-bit accumulator,
+//! and two bytes with a 16-bit one, but the opcode is the same. Widths
+//! are selected at runtime by the M and X processor flags, which `REP` and
+//! `SEP` clear and set:
+//!
+//! ```asm
+//! REP #$20    ; 16-bit accumulator
+//! LDA #$1234  ; becomes LDA.w #$1234
+//! SEP #$30    ; 8-bit accumulator and index registers
+//! LDX #$12    ; becomes LDX.b #$12
+//! ```
+//!
+//! Flags are followed in order of statements. A named label may be
+//! reached from anywhere, so the state after it is the one code starts
+//! with, while scoped and relative labels are assumed to be reached with
+//! the state they follow. Labels reached with different widths, or with
+//! widths other than ones assumed after them, are reported by
+//! `analysis::flags`. Subroutine calls are assumed to
+//! preserve flags, and `PLP`, `RTI` and `XCE` make them unknown.
+//!
+//! Widths can also be stated with `a8`, `a16`, `i8` and `i16`, which
+//! apply from that point on, including after named labels. Registers of
+//! the 6502 and the 65C02, selected with `arch`, are always 8-bit.
+
+use encoder::{self, AddressingMode, Arch, Flags};
+use eval;
+use parser::ast::{
+    normalize_mnemonic, Label, Opcode, OpcodeMode, Register, Span, Spanned, Statement,
+};
+
+/// Known widths of registers, with `Some(true)` for 8-bit registers, as
+/// when a flag is set.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct State {
+    /// Accumulator and memory width, the M flag.
+    pub m_flag: Option<bool>,
+    /// Index register width, the X flag.
+    pub x_flag: Option<bool>,
+}
+
+impl State {
+    /// State with both flags known.
+    pub fn new(m_flag: bool, x_flag: bool) -> State {
+        State {
+            m_flag: Some(m_flag),
+            x_flag: Some(x_flag),
+        }
+    }
+
+    /// State with a register width in bytes, as set by `a16` and similar
+    /// directives.
+    pub(crate) fn with_width(self, register: Register, width: u32) -> State {
+        let flag = Some(width == 1);
+        match register {
+            Register::Accumulator => State {
+                m_flag: flag,
+                ..self
+            },
+            Register::Index => State {
+                x_flag: flag,
+                ..self
+            },
+        }
+    }
+
+    /// State of an instruction set whose registers have a fixed width.
+    pub(crate) fn of_arch(arch: Arch) -> Option<State> {
+        match arch {
+            Arch::W6502 | Arch::W65C02 => Some(State::new(true, true)),
+            Arch::W65816 | Arch::Spc700 | Arch::SuperFx => None,
+        }
+    }
+
+    /// Keeps flags with the same value in both states.
+    pub(crate) fn join(self, other: State) -> State {
+        let same = |a, b| if a == b { a } else { None };
+        State {
+            m_flag: same(self.m_flag, other.m_flag),
+            x_flag: same(self.x_flag, other.x_flag),
+        }
+    }
+}
+
+/// An immediate operand whose size couldn't be selected, as the width of
+/// a register isn't known.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnknownWidth<'a> {
+    /// Statement with an opcode.
+    pub span: Span<'a>,
+    pub register: Register,
+}
+
+/// Gives immediate operands without a width suffix a suffix matching the
+/// width of their register, including ones in if blocks and macro
+/// expansions.
+///
+/// `state` is the state at the start of statements, and after named
+/// labels until changed by width directives. Operands whose register
+/// width isn't known are left unchanged and reported.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::flags::{self, State};
+/// use mvp::parser::grammar::parse_program;
+///
+/// let mut statements = parse_program("REP #$20\nLDA #$1234\nLDX #$12\n").unwrap();
+/// let unknown = flags::size_immediates(&mut statements, State::new(true, true));
+/// assert!(unknown.is_empty());
+/// assert_eq!(statements, parse_program("REP #$20\nLDA.w #$1234\nLDX.b #$12\n").unwrap());
+/// ```
+pub fn size_immediates<'a>(
+    statements: &mut [Spanned<'a, Statement<'a>>],
+    state: State,
+) -> Vec<UnknownWidth<'a>> {
+    let mut unknown = Vec::new();
+    let mut entry = state;
+    walk(statements, &mut entry, state, &mut unknown);
+    unknown
+}
+
+/// Sizes immediates in statements, returning the state after them.
+///
+/// `entry` is the state after named labels, which width directives change.
+fn walk<'a>(
+    statements: &mut [Spanned<'a, Statement<'a>>],
+    entry: &mut State,
+    mut state: State,
+    unknown: &mut Vec<UnknownWidth<'a>>,
+) -> State {
+    for statement in statements {
+        let span = statement.span;
+        match statement.node {
+            Statement::Label(Label::Named(_)) => state = *entry,
+            Statement::RegisterWidth(register, width) => {
+                state = state.with_width(register, width);
+                *entry = entry.with_width(register, width);
+            }
+            Statement::Arch(arch) => {
+                if let Some(fixed) = State::of_arch(arch) {
+                    state = fixed;
+                    *entry = fixed;
+                }
+            }
+            Statement::Opcode(ref mut opcode) => {
+                if let Some(register) = size_immediate(opcode, state) {
+                    unknown.push(UnknownWidth { span, register });
+                }
+                state = next_state(opcode, state);
+            }
+            Statement::Expansion(ref mut statements) => {
+                state = walk(statements, entry, state, unknown);
+            }
+            Statement::If(ref mut conditions) => {
+                let mut after = None::<(State, State)>;
+                for condition in conditions.iter_mut() {
+                    let mut block_entry = *entry;
+                    let end = walk(&mut condition.statements, &mut block_entry, state, unknown);
+                    after = Some(join(after, (block_entry, end)));
+                }
+                // Without an else block, none of the blocks may run.
+                if conditions
+                    .last()
+                    .is_none_or(|last| last.predicate.is_some())
+                {
+                    after = Some(join(after, (*entry, state)));
+                }
+                if let Some((block_entry, end)) = after {
+                    *entry = block_entry;
+                    state = end;
+                }
+            }
+            _ => {}
+        }
+    }
+    state
+}
+
+/// Joins entry states and states after if blocks.
+fn join(joined: Option<(State, State)>, (entry, state): (State, State)) -> (State, State) {
+    match joined {
+        Some((joined_entry, joined_state)) => (joined_entry.join(entry), joined_state.join(state)),
+        None => (entry, state),
+    }
+}
+
+/// Gives an immediate operand a width suffix, or returns a register
+/// whose width isn't known.
+fn size_immediate(opcode: &mut Opcode, state: State) -> Option<Register> {
+    if opcode.width.is_some() {
+        return None;
+    }
+    let register = immediate_register(opcode)?;
+    let flag = match register {
+        Register::Accumulator => state.m_flag,
+        Register::Index => state.x_flag,
+    };
+    match flag {
+        Some(flag) => {
+            opcode.width = Some(if flag { 1 } else { 2 });
+            None
+        }
+        None => Some(register),
+    }
+}
+
+/// Returns a register whose width selects the size of an immediate
+/// operand of an opcode.
+pub(crate) fn immediate_register(opcode: &Opcode) -> Option<Register> {
+    if opcode.mode != OpcodeMode::Immediate {
+        return None;
+    }
+    let name = normalize_mnemonic(opcode.name).unwrap_or(opcode.name);
+    let size =
+        |m_flag, x_flag| encoder::instruction_size(name, AddressingMode::Immediate, m_flag, x_flag);
+    if size(true, true) != size(false, true) {
+        Some(Register::Accumulator)
+    } else if size(true, true) != size(true, false) {
+        Some(Register::Index)
+    } else {
+        None
+    }
+}
+
+/// State after an opcode runs.
+pub(crate) fn next_state(opcode: &Opcode, state: State) -> State {
+    let name = normalize_mnemonic(opcode.name).unwrap_or(opcode.name);
+    let set = match name {
+        "REP" => false,
+        "SEP" => true,
+        "PLP" | "RTI" | "XCE" => return State::default(),
+        _ => return state,
+    };
+    let flags = match (&opcode.mode, &opcode.value) {
+        (OpcodeMode::Immediate, Some(value)) => eval::evaluate(value, &no_labels).ok(),
+        _ => None,
+    };
+    let flags = match flags {
+        Some(flags) => Flags(flags as u8),
+        None => return State::default(),
+    };
+    let update = |flag, old| if flags.contains(flag) { Some(set) } else { old };
+    State {
+        m_flag: update(Flags::MEMORY, state.m_flag),
+        x_flag: update(Flags::INDEX, state.x_flag),
+    }
+}
+
+fn no_labels(_: &Label) -> Option<i64> {
+    None
+}
@@ -0,0 +1,642 @@
+//! Expansion of macros.
+//!
+//! A macro call is replaced with statements of its macro, where
+//! parameters are replaced with arguments of the call:
+//!
+//! ```asm
+//! macro wait(frames)
+//!     LDX #frames
+//! -:
+//!     WAI
+//!     DEX
+//!     BNE -
+//! endmacro
+//! %wait(4)    ; becomes LDX #4, WAI, DEX, BNE -
+//! ```
+//!
+//! Macros are hygienic: named and scoped labels declared in a macro are
+//! renamed in every expansion, so a macro declaring a label can be called
+//! more than once, and its labels don't clash with labels of a program.
+//! Renamed labels get a `#` and a number of an expansion appended, which
+//! can't appear in a label written in source code. Relative labels are
+//! kept, as they can already be declared many times.
+//!
+//! Named labels listed with `global` are kept as well, for labels a macro
+//! declares for the rest of a program, or takes from it:
+//!
+//! ```asm
+//! macro handler()
+//!     global Handler, Return
+//! Handler:
+//!     JSR Update
+//!     JMP Return
+//! endmacro
+//! ```
+//!
+//! A macro referring to a named label of a program without listing it, or
+//! declaring a label a program declares too, is likely a mistake, and is
+//! found by `captures`.
+//!
+//! Calls in if blocks whose condition is a constant are only expanded
+//! when the block is taken, so that a macro can call itself until its
+//! arguments run out.
+//!
+//! Arguments are substituted as written, so they are evaluated where
+//! a parameter is used. Parameters declared with `#` take arguments
+//! evaluated once, at a call, which matters for arguments using `$` or
+//! names assigned again later:
+//!
+//! ```asm
+//! macro back(#target)
+//!     NOP
+//!     BRA target
+//! endmacro
+//! %back($)    ; branches to the NOP, rather than to the BRA itself
+//! ```
+//!
+//! A constant argument of such a parameter is replaced with its value,
+//! and other arguments are assigned at the start of an expansion to
+//! names starting with `#`, which can't appear in source code.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+
+use eval;
+use parser::ast::{
+    Expression, Label, Macro, Number, NumberWidth, Opcode, Span, Spanned, Statement, VariableName,
+};
+use parser::fold::{self, Folder};
+use parser::visit::{self, Visitor};
+
+/// Default limit of nested macro calls, which stops infinite recursion.
+pub const DEFAULT_DEPTH_LIMIT: usize = 64;
+
+/// Storage of names of labels renamed by expansion, which have to live as
+/// long as statements referring to them.
+#[derive(Debug, Default)]
+pub struct Names {
+    names: RefCell<Vec<Box<str>>>,
+}
+
+impl Names {
+    /// Creates empty storage.
+    pub fn new() -> Names {
+        Names::default()
+    }
+
+    fn alloc(&self, name: String) -> &str {
+        let name = name.into_boxed_str();
+        let pointer: *const str = &*name;
+        self.names.borrow_mut().push(name);
+        // SAFETY: Contents of a boxed string don't move when the box
+        // does, and boxes are only dropped along with the storage.
+        unsafe { &*pointer }
+    }
+}
+
+/// A failure to expand macros.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error<'a> {
+    /// Call of a macro which isn't defined.
+    Undefined(Span<'a>, &'a str),
+    /// Call with a wrong number of arguments.
+    Arguments {
+        span: Span<'a>,
+        expected: usize,
+        /// Whether more than `expected` arguments are accepted.
+        variadic: bool,
+        found: usize,
+    },
+    /// Variadic arguments passed with `<...>` outside of a variadic
+    /// macro.
+    Splice(Span<'a>),
+    /// Calls are nested deeper than the depth limit.
+    DepthLimit(Span<'a>),
+    /// Macro with the same name as an earlier one.
+    Redefined(Span<'a>, &'a str),
+    /// Macro defined inside another macro or a block.
+    Nested(Span<'a>),
+}
+
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Undefined(_, name) => write!(f, "macro `{}` is not defined", name),
+            Error::Arguments {
+                expected,
+                variadic,
+                found,
+                ..
+            } => write!(
+                f,
+                "macro takes {}{} arguments, but {} were given",
+                if variadic { "at least " } else { "" },
+                expected,
+                found
+            ),
+            Error::Splice(_) => write!(f, "`<...>` can only be used in a variadic macro"),
+            Error::DepthLimit(_) => write!(f, "macro calls are nested too deeply"),
+            Error::Redefined(_, name) => write!(f, "macro `{}` is already defined", name),
+            Error::Nested(_) => write!(f, "macros can only be defined at top level"),
+        }
+    }
+}
+
+impl<'a> error::Error for Error<'a> {}
+
+impl<'a> Error<'a> {
+    /// Statement which failed to expand.
+    pub fn span(&self) -> Span<'a> {
+        match *self {
+            Error::Undefined(span, _)
+            | Error::Arguments { span, .. }
+            | Error::Splice(span)
+            | Error::DepthLimit(span)
+            | Error::Redefined(span, _)
+            | Error::Nested(span) => span,
+        }
+    }
+}
+
+/// Replaces macro calls with `Statement::Expansion`, including calls in
+/// if blocks and in other macros.
+///
+/// Macros are defined at top level, and can be called before their
+/// definition. Definitions are kept in statements, and are ignored by
+/// assembly. Calls nested deeper than `depth_limit` fail, see
+/// `DEFAULT_DEPTH_LIMIT`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::macros::{self, Names, DEFAULT_DEPTH_LIMIT};
+/// use mvp::parser::ast::Statement;
+/// use mvp::parser::grammar::parse_program;
+///
+/// let names = Names::new();
+/// let statements = parse_program("macro add(value)\nCLC\nADC #value\nendmacro\n%add(2)\n").unwrap();
+/// let statements = macros::expand(statements, &names, DEFAULT_DEPTH_LIMIT).unwrap();
+/// match statements[1].node {
+///     Statement::Expansion(ref expansion) => {
+///         assert_eq!(expansion[..], parse_program("CLC\nADC #2\n").unwrap()[..]);
+///     }
+///     ref statement => panic!("unexpected statement: {:?}", statement),
+/// }
+/// ```
+pub fn expand<'a>(
+    statements: Vec<Spanned<'a, Statement<'a>>>,
+    names: &'a Names,
+    depth_limit: usize,
+) -> Result<Vec<Spanned<'a, Statement<'a>>>, Error<'a>> {
+    let mut macros = HashMap::new();
+    for statement in &statements {
+        if let Statement::Macro(ref definition) = **statement {
+            if let Some(nested) = find_definition(&definition.statements) {
+                return Err(Error::Nested(nested));
+            }
+            let VariableName(name) = definition.name;
+            if macros.insert(name, definition.clone()).is_some() {
+                return Err(Error::Redefined(statement.span, name));
+            }
+        }
+    }
+    let mut expander = Expander {
+        macros,
+        names,
+        depth_limit,
+        expansions: 0,
+    };
+    expander.statements(statements, 0, true)
+}
+
+/// Finds a macro definition in statements, including ones in blocks.
+fn find_definition<'a>(statements: &[Spanned<'a, Statement<'a>>]) -> Option<Span<'a>> {
+    statements.iter().find_map(|statement| match **statement {
+        Statement::Macro(_) => Some(statement.span),
+        Statement::If(ref conditions) => conditions
+            .iter()
+            .find_map(|condition| find_definition(&condition.statements)),
+        Statement::SpcBlock(ref block) => find_definition(&block.statements),
+        _ => None,
+    })
+}
+
+struct Expander<'a> {
+    macros: HashMap<&'a str, Macro<'a>>,
+    names: &'a Names,
+    depth_limit: usize,
+    /// Number of expansions so far, which makes renamed labels unique.
+    expansions: usize,
+}
+
+impl<'a> Expander<'a> {
+    fn statements(
+        &mut self,
+        statements: Vec<Spanned<'a, Statement<'a>>>,
+        depth: usize,
+        top_level: bool,
+    ) -> Result<Vec<Spanned<'a, Statement<'a>>>, Error<'a>> {
+        statements
+            .into_iter()
+            .map(|statement| self.statement(statement, depth, top_level))
+            .collect()
+    }
+
+    fn statement(
+        &mut self,
+        statement: Spanned<'a, Statement<'a>>,
+        depth: usize,
+        top_level: bool,
+    ) -> Result<Spanned<'a, Statement<'a>>, Error<'a>> {
+        let Spanned { node, span } = statement;
+        let node = match node {
+            Statement::MacroCall(call) => {
+                let VariableName(name) = call.name;
+                let definition = match self.macros.get(name) {
+                    Some(definition) => definition.clone(),
+                    None => return Err(Error::Undefined(span, name)),
+                };
+                if call.splice {
+                    return Err(Error::Splice(span));
+                }
+                let expected = definition.parameters.len();
+                let found = call.arguments.len();
+                if found < expected || found > expected && !definition.variadic {
+                    return Err(Error::Arguments {
+                        span,
+                        expected,
+                        variadic: definition.variadic,
+                        found,
+                    });
+                }
+                if depth >= self.depth_limit {
+                    return Err(Error::DepthLimit(span));
+                }
+                self.expansions += 1;
+                let statements = fold::fold_statements(
+                    &mut Rename {
+                        labels: renamed_labels(&definition.statements),
+                        names: self.names,
+                        expansion: self.expansions,
+                    },
+                    definition.statements,
+                );
+                let mut arguments = call.arguments;
+                let variadic = arguments.split_off(expected);
+                let mut assignments = Vec::new();
+                let evaluated = &definition.evaluated;
+                let arguments = definition
+                    .parameters
+                    .iter()
+                    .zip(arguments)
+                    .map(|(parameter, argument)| {
+                        let VariableName(name) = *parameter;
+                        if !evaluated.contains(parameter) {
+                            return (name, argument);
+                        }
+                        let constant = eval::evaluate(&argument, &|_: &Label| None).ok();
+                        if let Some(value) = constant.and_then(|value| u32::try_from(value).ok()) {
+                            let width = argument.width();
+                            return (name, Expression::Number(Number { value, width }));
+                        }
+                        let assigned = VariableName(
+                            self.names.alloc(format!("#{}#{}", name, self.expansions)),
+                        );
+                        assignments.push(Spanned {
+                            node: Statement::Assignment(assigned.clone(), argument),
+                            span,
+                        });
+                        (name, Expression::Variable(Label::Named(assigned)))
+                    })
+                    .collect();
+                let statements = fold::fold_statements(
+                    &mut Substitute {
+                        arguments,
+                        variadic: if definition.variadic {
+                            Some(variadic)
+                        } else {
+                            None
+                        },
+                    },
+                    statements,
+                );
+                assignments.extend(statements);
+                Statement::Expansion(self.statements(assignments, depth + 1, false)?)
+            }
+            Statement::If(conditions) => {
+                let mut taken = false;
+                let mut expanded = Vec::with_capacity(conditions.len());
+                for mut condition in conditions {
+                    let constant = match condition.predicate {
+                        Some(ref predicate) => eval::evaluate(predicate, &|_: &Label| None).ok(),
+                        None => Some(1),
+                    };
+                    if !taken && constant != Some(0) {
+                        condition.statements =
+                            self.statements(condition.statements, depth, false)?;
+                        taken = constant.is_some();
+                    }
+                    expanded.push(condition);
+                }
+                Statement::If(expanded)
+            }
+            Statement::SpcBlock(mut block) => {
+                block.statements = self.statements(block.statements, depth, false)?;
+                Statement::SpcBlock(block)
+            }
+            Statement::Macro(_) if !top_level => return Err(Error::Nested(span)),
+            node => node,
+        };
+        Ok(Spanned { node, span })
+    }
+}
+
+/// Finds names of named and scoped labels declared in statements.
+fn declared_labels<'a>(statements: &[Spanned<'a, Statement<'a>>]) -> HashSet<Label<'a>> {
+    let mut labels = HashSet::new();
+    for statement in statements {
+        match **statement {
+            Statement::Label(ref label @ Label::Named(_))
+            | Statement::Label(ref label @ Label::Scoped(_)) => {
+                labels.insert(label.clone());
+            }
+            Statement::If(ref conditions) => {
+                for condition in conditions {
+                    labels.extend(declared_labels(&condition.statements));
+                }
+            }
+            _ => {}
+        }
+    }
+    labels
+}
+
+/// Finds names of labels listed with `global` in statements.
+fn global_labels<'a>(statements: &[Spanned<'a, Statement<'a>>]) -> HashSet<&'a str> {
+    let mut names = HashSet::new();
+    for statement in statements {
+        match **statement {
+            Statement::Global(ref globals) => {
+                names.extend(globals.iter().map(|&VariableName(name)| name));
+            }
+            Statement::If(ref conditions) => {
+                for condition in conditions {
+                    names.extend(global_labels(&condition.statements));
+                }
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+/// Finds labels declared in a macro which are renamed in expansions, which
+/// are ones not listed with `global`.
+fn renamed_labels<'a>(statements: &[Spanned<'a, Statement<'a>>]) -> HashSet<Label<'a>> {
+    let globals = global_labels(statements);
+    declared_labels(statements)
+        .into_iter()
+        .filter(|label| match *label {
+            Label::Named(VariableName(name)) => !globals.contains(name),
+            _ => true,
+        })
+        .collect()
+}
+
+/// Named label of a macro which may be confused with a label of a
+/// program.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Capture<'a> {
+    /// Statement of a macro declaring or referring to a label.
+    pub span: Span<'a>,
+    /// Name of a macro.
+    pub name: &'a str,
+    pub label: &'a str,
+    /// Whether a macro declares its own label, rather than referring to
+    /// one of a program.
+    pub declared: bool,
+}
+
+impl<'a> fmt::Display for Capture<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.declared {
+            write!(
+                f,
+                "macro {} declares its own {}, which hides a label of the program",
+                self.name, self.label
+            )
+        } else {
+            write!(
+                f,
+                "macro {} refers to {} of the program, without listing it with `global`",
+                self.name, self.label
+            )
+        }
+    }
+}
+
+/// Finds named labels of macros which may be confused with labels of a
+/// program.
+///
+/// A macro referring to a named label it doesn't declare refers to a label
+/// of a program, which is only expected when the label is listed with
+/// `global`. A macro declaring a label a program declares too renames
+/// it, so references in the macro don't reach the label of the program.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::macros;
+/// use mvp::parser::grammar::parse_program;
+///
+/// let source = "macro wait()\nLoop:\nWAI\nBRA Done\nendmacro\nLoop:\nDone:\n";
+/// let statements = parse_program(source).unwrap();
+/// let captures: Vec<_> = macros::captures(&statements)
+///     .iter()
+///     .map(|capture| (capture.label, capture.declared))
+///     .collect();
+/// assert_eq!(captures, [("Loop", true), ("Done", false)]);
+/// ```
+pub fn captures<'a>(statements: &[Spanned<'a, Statement<'a>>]) -> Vec<Capture<'a>> {
+    let mut labels = HashSet::new();
+    program_labels(statements, &mut labels);
+    let mut captures = Vec::new();
+    for statement in statements {
+        if let Statement::Macro(ref definition) = **statement {
+            let VariableName(name) = definition.name;
+            let mut visitor = Captures {
+                labels: &labels,
+                name,
+                declared: declared_labels(&definition.statements),
+                globals: global_labels(&definition.statements),
+                parameters: definition
+                    .parameters
+                    .iter()
+                    .map(|&VariableName(name)| name)
+                    .collect(),
+                span: statement.span,
+                captures: &mut captures,
+            };
+            visit::walk_statements(&mut visitor, &definition.statements);
+        }
+    }
+    captures
+}
+
+/// Finds names of named labels declared outside of macros, including ones
+/// declared by expansions with `global`.
+fn program_labels<'a>(statements: &[Spanned<'a, Statement<'a>>], labels: &mut HashSet<&'a str>) {
+    for statement in statements {
+        match **statement {
+            Statement::Label(Label::Named(VariableName(name))) => {
+                labels.insert(name);
+            }
+            Statement::If(ref conditions) => {
+                for condition in conditions {
+                    program_labels(&condition.statements, labels);
+                }
+            }
+            Statement::Expansion(ref statements) => program_labels(statements, labels),
+            _ => {}
+        }
+    }
+}
+
+/// Finds labels of a macro which are also labels of a program.
+struct Captures<'c, 'a: 'c> {
+    /// Named labels of a program.
+    labels: &'c HashSet<&'a str>,
+    /// Name of a macro.
+    name: &'a str,
+    declared: HashSet<Label<'a>>,
+    globals: HashSet<&'a str>,
+    parameters: HashSet<&'a str>,
+    /// Statement being visited.
+    span: Span<'a>,
+    captures: &'c mut Vec<Capture<'a>>,
+}
+
+impl<'c, 'a> Captures<'c, 'a> {
+    fn check(&mut self, label: &'a str, declared: bool) {
+        if self.labels.contains(label) && !self.globals.contains(label) {
+            self.captures.push(Capture {
+                span: self.span,
+                name: self.name,
+                label,
+                declared,
+            });
+        }
+    }
+}
+
+impl<'c, 'a> Visitor<'a> for Captures<'c, 'a> {
+    fn visit_statement(&mut self, statement: &Spanned<'a, Statement<'a>>) {
+        self.span = statement.span;
+        match **statement {
+            Statement::Label(Label::Named(VariableName(name))) => self.check(name, true),
+            Statement::Label(_) => {}
+            _ => visit::walk_statement(self, statement),
+        }
+    }
+
+    fn visit_opcode(&mut self, opcode: &Opcode<'a>) {
+        // Index registers, as in `LDA $00,x`, are parsed as names.
+        if opcode.index_register().is_some() {
+            if let Some(ref value) = opcode.value {
+                self.visit_expression(value);
+            }
+        } else {
+            visit::walk_opcode(self, opcode);
+        }
+    }
+
+    fn visit_label(&mut self, label: &Label<'a>) {
+        if let Label::Named(VariableName(name)) = *label {
+            if !self.declared.contains(label) && !self.parameters.contains(name) {
+                self.check(name, false);
+            }
+        }
+    }
+}
+
+/// Renames labels declared in a macro, in declarations and references.
+struct Rename<'a> {
+    labels: HashSet<Label<'a>>,
+    names: &'a Names,
+    expansion: usize,
+}
+
+impl<'a> Folder<'a> for Rename<'a> {
+    fn fold_label(&mut self, label: Label<'a>) -> Label<'a> {
+        if !self.labels.contains(&label) {
+            return label;
+        }
+        let rename = |VariableName(name)| {
+            VariableName(self.names.alloc(format!("{}#{}", name, self.expansion)))
+        };
+        match label {
+            Label::Named(name) => Label::Named(rename(name)),
+            Label::Scoped(name) => Label::Scoped(rename(name)),
+            Label::Relative(_) => label,
+        }
+    }
+}
+
+/// Replaces parameters of a macro with arguments.
+struct Substitute<'a> {
+    arguments: HashMap<&'a str, Expression<'a>>,
+    /// Variadic arguments, if a macro is variadic.
+    variadic: Option<Vec<Expression<'a>>>,
+}
+
+impl<'a> Folder<'a> for Substitute<'a> {
+    fn fold_statement(
+        &mut self,
+        statement: Spanned<'a, Statement<'a>>,
+    ) -> Spanned<'a, Statement<'a>> {
+        let mut statement = fold::fold_statement(self, statement);
+        if let Statement::MacroCall(ref mut call) = statement.node {
+            if let (true, Some(variadic)) = (call.splice, self.variadic.as_ref()) {
+                call.arguments.extend(variadic.iter().cloned());
+                call.splice = false;
+            }
+        }
+        statement
+    }
+
+    fn fold_opcode(&mut self, opcode: Opcode<'a>) -> Opcode<'a> {
+        // Index registers, as in `LDA $00,x`, are parsed as names, which
+        // must not be replaced by a parameter called `x`.
+        if opcode.index_register().is_some() {
+            Opcode {
+                value: opcode.value.map(|value| fold::fold_spanned(self, value)),
+                ..opcode
+            }
+        } else {
+            fold::fold_opcode(self, opcode)
+        }
+    }
+
+    fn fold_expression(&mut self, expression: Expression<'a>) -> Expression<'a> {
+        match expression {
+            Expression::Variable(Label::Named(VariableName(name))) => {
+                if let Some(argument) = self.arguments.get(name) {
+                    return argument.clone();
+                }
+            }
+            Expression::ArgumentCount => {
+                if let Some(ref variadic) = self.variadic {
+                    return Expression::Number(Number {
+                        value: variadic.len() as u32,
+                        width: NumberWidth::None,
+                    });
+                }
+            }
+            _ => {}
+        }
+        fold::fold_expression(self, expression)
+    }
+}
@@ -0,0 +1,16 @@
+//! Passes of assembly.
+//!
+//! Passes take statements of a program and prepare them for encoding, like
+//! rewriting instructions which can't be encoded as written. Prepared
+//! statements are encoded by `output`, and written into a ROM image by
+//! `sfc`. An `Assembler` runs every step with options given in one place.
+
+pub mod branch;
+pub mod driver;
+pub mod flags;
+pub mod macros;
+pub mod output;
+pub mod sfc;
+pub mod spcblock;
+
+pub use self::driver::{Assembler, Builder, OutputKind};
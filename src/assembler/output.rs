@@ -0,0 +1,1119 @@
+//! Assembly of 65816 code into bytes.
+//!
+//! Code is assembled in two passes over a symbol table, see `symbols`.
+//! The first pass lays out code, choosing an addressing mode of every
+//! instruction, and the second pass encodes instructions with the same
+//! modes, so that addresses of labels don't change between passes.
+//!
+//! Operands using labels which aren't defined anywhere in a program don't
+//! fail assembly. Instead, they are written as zeros and listed as
+//! fixups, which a linker or a loader can patch once the addresses are
+//! known:
+//!
+//! ```asm
+//! JSL ExternalRoutine ; fixup of 3 bytes at offset 1
+//! ```
+//!
+//! Code which may not do what was meant is reported with warnings, whose
+//! levels are given by `assemble_with_levels` and `warnings` directives,
+//! see `warning`.
+//!
+//! `findpattern` searches the ROM being patched, given by
+//! `Symbols::set_base_rom`, and assigns an address of the only match of
+//! a pattern to a name, failing when a pattern matches no place or more
+//! than one:
+//!
+//! ```asm
+//! findpattern "A9 ?? 8D 00 21" -> SetBrightness
+//! JSL SetBrightness
+//! ```
+//!
+//! `expectcrc` checks the base ROM against a list of CRC-32 checksums
+//! before it's patched, see `rom::check_base_rom`.
+//!
+//! A program declaring base ROM variants, see `variant`, needs a base ROM
+//! with a checksum of one of them. Expressions are evaluated in the
+//! context of the detected variant.
+//!
+//! Absolute addresses are assumed to be in the bank of code, unless
+//! a `bank` directive says where the data bank register points to.
+//! Addresses in the assumed bank and in bank zero use absolute
+//! addressing, and an absolute address elsewhere is a `bank-mismatch`:
+//!
+//! ```asm
+//! Buffer = $7E2000
+//! bank $7E
+//! LDA Buffer    ; absolute
+//! LDA.w $7F2000 ; bank mismatch
+//! bank auto
+//! ```
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::mem;
+use std::ops::Range;
+
+use arrayvec::ArrayVec;
+
+use analysis;
+use analysis::flags::Conflict;
+use analysis::width::{self, Mismatch};
+use asar::{self, AsarVersion};
+use assembler::flags::{self, State};
+use assembler::spcblock;
+use encoder::{self, AddressingMode, Arch, EncodeError};
+use eval::{self, Context};
+use mapping::Mapper;
+use parser::ast::{
+    normalize_mnemonic, Expression, Function, Label, Opcode, OpcodeMode, Register, Span, Spanned,
+    SpcBlock, Statement, VariableName,
+};
+use parser::visit::{self, Visitor};
+use rom::{self, BaseRomError, Pattern};
+use symbols::{self, Pass, Symbol, Symbols};
+use variant::{self, Variant, VariantContext, Variants};
+use warning::{Category, Level, Levels, Warning};
+
+/// How a fixup is patched.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FixupKind {
+    /// Value of an expression, truncated to the width of a fixup.
+    Absolute,
+    /// Value of an expression minus an address, which is the end of
+    /// a branch instruction.
+    Relative(u32),
+}
+
+/// An operand which couldn't be resolved during assembly.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Fixup<'a> {
+    /// Offset of an operand in output data.
+    pub offset: usize,
+    /// Width of an operand in bytes.
+    pub width: u32,
+    pub expression: Expression<'a>,
+    pub kind: FixupKind,
+    /// Statement with an operand.
+    pub span: Span<'a>,
+}
+
+/// Code assembled from a statement, for mapping addresses back to source
+/// code.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mapping<'a> {
+    /// Addresses of assembled bytes.
+    pub addresses: Range<u32>,
+    pub span: Span<'a>,
+    /// Spans of macro calls which expanded into the statement, with the
+    /// innermost call first.
+    pub expansion: Vec<Span<'a>>,
+}
+
+/// Assembled code.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Output<'a> {
+    /// Address of the first byte of data.
+    pub origin: u32,
+    pub data: Vec<u8>,
+    /// Operands to patch, in order of their offsets.
+    pub fixups: Vec<Fixup<'a>>,
+    /// Labels and assignments, see `Symbols::symbols`.
+    pub symbols: Vec<Symbol<'a>>,
+    /// Mapper selected by the last mapper directive.
+    pub mapper: Mapper,
+    /// Statements which produced code, in order of their addresses.
+    pub mappings: Vec<Mapping<'a>>,
+    /// Text of `print` statements, see `eval::print`.
+    pub messages: Vec<String>,
+    /// Warnings of categories at the warn level, in order of statements.
+    pub warnings: Vec<Warning<'a>>,
+}
+
+/// An assembly failure.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error<'a> {
+    /// Symbol couldn't be defined, or an expression couldn't be evaluated.
+    Symbol(Span<'a>, symbols::Error<'a>),
+    /// Instruction couldn't be encoded.
+    Encode(Span<'a>, EncodeError),
+    /// Width of an immediate operand isn't known, see `flags`.
+    UnknownWidth(Span<'a>, Register),
+    /// Condition of an if block uses a label defined after it.
+    UnresolvedCondition(Span<'a>),
+    /// Code reached past an address given to `warnpc`.
+    WarnPc(Span<'a>, u32),
+    /// Bank given to `bank` isn't known, or doesn't fit in a byte.
+    InvalidBank(Span<'a>),
+    /// Statement can't be assembled as 65816 code, like an `incsrc` or
+    /// a macro call which wasn't resolved, or an instruction of another
+    /// instruction set.
+    UnsupportedStatement(Span<'a>),
+    /// Code requires a newer version of Asar than `asar::VERSION`.
+    AsarVersion(Span<'a>, AsarVersion),
+    /// Code uses an Asar feature mvp doesn't have, see
+    /// `Statement::AsarFeature`.
+    AsarFeature(Span<'a>, &'static str),
+    /// Statement needs a ROM being patched, and there is none.
+    NoBaseRom(Span<'a>),
+    /// Pattern given to `findpattern` isn't valid, see `rom::Pattern`.
+    InvalidPattern(Span<'a>),
+    /// Pattern given to `findpattern` matches a number of places in the
+    /// base ROM other than one.
+    PatternMatches(Span<'a>, usize),
+    /// Operand doesn't fit in the size selected by a width suffix.
+    WidthMismatch(Mismatch<'a>),
+    /// Base ROM doesn't have a checksum given to `expectcrc`.
+    BaseRom(Span<'a>, BaseRomError),
+    /// Variants couldn't be declared, or the base ROM isn't one of them.
+    Variant(Span<'a>, variant::Error<'a>),
+    /// SPC700 block couldn't be assembled.
+    SpcBlock(spcblock::Error<'a>),
+    /// Warning of a denied category.
+    Denied(Warning<'a>),
+}
+
+impl<'a> Error<'a> {
+    /// Statement which failed to assemble.
+    pub fn span(&self) -> Span<'a> {
+        match *self {
+            Error::Symbol(span, _)
+            | Error::Encode(span, _)
+            | Error::UnknownWidth(span, _)
+            | Error::UnresolvedCondition(span)
+            | Error::WarnPc(span, _)
+            | Error::InvalidBank(span)
+            | Error::UnsupportedStatement(span)
+            | Error::AsarVersion(span, _)
+            | Error::AsarFeature(span, _)
+            | Error::NoBaseRom(span)
+            | Error::InvalidPattern(span)
+            | Error::PatternMatches(span, _)
+            | Error::BaseRom(span, _)
+            | Error::Variant(span, _) => span,
+            Error::WidthMismatch(ref mismatch) => mismatch.span,
+            Error::SpcBlock(ref error) => error.span(),
+            Error::Denied(ref warning) => warning.span,
+        }
+    }
+}
+
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Symbol(_, ref error) => write!(f, "{}", error),
+            Error::Encode(_, ref error) => write!(f, "{}", error),
+            Error::UnknownWidth(_, register) => write!(
+                f,
+                "width of {} isn't known, set it with REP, SEP or a width suffix",
+                register_name(register)
+            ),
+            Error::UnresolvedCondition(_) => {
+                write!(f, "condition uses a label defined after it")
+            }
+            Error::WarnPc(_, address) => write!(f, "code reached {:#X}, past warnpc", address),
+            Error::InvalidBank(_) => {
+                write!(f, "bank must be a known number from $00 to $FF")
+            }
+            Error::UnsupportedStatement(_) => write!(f, "statement can't be assembled here"),
+            Error::AsarVersion(_, version) => write!(
+                f,
+                "code requires Asar {}, mvp is compatible with Asar {} and older",
+                version,
+                asar::VERSION
+            ),
+            Error::AsarFeature(_, feature) => {
+                write!(f, "Asar feature `{}` isn't supported", feature)
+            }
+            Error::NoBaseRom(_) => write!(f, "statement needs a base ROM to patch"),
+            Error::InvalidPattern(_) => write!(
+                f,
+                "pattern must be hexadecimal bytes or ?? separated by spaces"
+            ),
+            Error::PatternMatches(_, 0) => write!(f, "pattern isn't found in the base ROM"),
+            Error::PatternMatches(_, count) => write!(
+                f,
+                "pattern is found {} times in the base ROM, it must be found once",
+                count
+            ),
+            Error::WidthMismatch(ref mismatch) => write!(f, "{}", mismatch),
+            Error::BaseRom(_, ref error) => write!(f, "{}", error),
+            Error::Variant(_, ref error) => write!(f, "{}", error),
+            Error::SpcBlock(ref error) => write!(f, "{}", error),
+            Error::Denied(ref warning) => write!(f, "{}", warning),
+        }
+    }
+}
+
+impl<'a> error::Error for Error<'a> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Symbol(_, ref error) => error.source(),
+            Error::Encode(_, ref error) => error.source(),
+            Error::BaseRom(_, ref error) => error.source(),
+            Error::Variant(_, ref error) => error.source(),
+            Error::SpcBlock(ref error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+/// Assembles statements into code starting at `origin`.
+///
+/// `state` gives widths of registers at the start of code, for sizing
+/// immediate operands. SPC700 blocks are assembled into transfer tables,
+/// see `spcblock::Block::transfer_entries`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::flags::State;
+/// use mvp::assembler::output::{self, FixupKind};
+/// use mvp::parser::grammar::parse_program;
+///
+/// let statements = parse_program("Main:\nLDA #$12\nBRA Main\nJSL External\n").unwrap();
+/// let output = output::assemble(statements, 0x808000, State::new(true, true)).unwrap();
+/// assert_eq!(output.data, [0xA9, 0x12, 0x80, 0xFC, 0x22, 0x00, 0x00, 0x00]);
+/// assert_eq!(output.fixups.len(), 1);
+/// assert_eq!((output.fixups[0].offset, output.fixups[0].width), (5, 3));
+/// assert_eq!(output.fixups[0].kind, FixupKind::Absolute);
+/// ```
+pub fn assemble<'a>(
+    statements: Vec<Spanned<'a, Statement<'a>>>,
+    origin: u32,
+    state: State,
+) -> Result<Output<'a>, Error<'a>> {
+    assemble_with_symbols(statements, origin, state, Symbols::new())
+}
+
+/// Assembles statements like `assemble`, starting with a symbol table
+/// which can provide files read by expressions.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::flags::State;
+/// use mvp::assembler::output;
+/// use mvp::parser::grammar::parse_program;
+/// use mvp::symbols::Symbols;
+///
+/// let statements = parse_program("LDA #readfile1(\"rom.sfc\", 1)\n").unwrap();
+/// let mut symbols = Symbols::new();
+/// symbols.add_file("rom.sfc", vec![0x12, 0x34]);
+/// let output =
+///     output::assemble_with_symbols(statements, 0x8000, State::new(true, true), symbols).unwrap();
+/// assert_eq!(output.data, [0xA9, 0x34]);
+/// ```
+pub fn assemble_with_symbols<'a>(
+    statements: Vec<Spanned<'a, Statement<'a>>>,
+    origin: u32,
+    state: State,
+    symbols: Symbols<'a>,
+) -> Result<Output<'a>, Error<'a>> {
+    assemble_with_levels(statements, origin, state, symbols, Levels::default())
+}
+
+/// Assembles statements like `assemble_with_symbols`, with levels of
+/// warning categories at the start of code.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::flags::State;
+/// use mvp::assembler::output;
+/// use mvp::parser::grammar::parse_program;
+/// use mvp::symbols::Symbols;
+/// use mvp::warning::{Category, Level, Levels};
+///
+/// let statements = parse_program("Value = $12\nLDA Value\n").unwrap();
+/// let mut levels = Levels::default();
+/// levels.set(Category::ImplicitDirectPage, Level::Warn);
+/// let state = State::new(true, true);
+/// let output =
+///     output::assemble_with_levels(statements, 0x8000, state, Symbols::new(), levels).unwrap();
+/// assert_eq!(output.data, [0xA5, 0x12]);
+/// assert_eq!(output.warnings[0].category, Category::ImplicitDirectPage);
+/// ```
+pub fn assemble_with_levels<'a>(
+    mut statements: Vec<Spanned<'a, Statement<'a>>>,
+    origin: u32,
+    state: State,
+    symbols: Symbols<'a>,
+    levels: Levels,
+) -> Result<Output<'a>, Error<'a>> {
+    // Suffixes are told apart from widths given to immediates here.
+    let mismatches = width::check(&statements);
+    let conflicts = analysis::flags::check(&statements, state);
+    // Immediates of unknown width are reported while assembling, where
+    // levels of warning categories are known.
+    flags::size_immediates(&mut statements, state);
+    let (variants, variant) = detect_variant(&statements, &symbols)?;
+    let mut assembler = Assembler {
+        symbols,
+        variants,
+        variant,
+        mismatches,
+        conflicts,
+        origin,
+        data: Vec::new(),
+        fixups: Vec::new(),
+        mappings: Vec::new(),
+        messages: Vec::new(),
+        initial_levels: levels,
+        levels,
+        warnings: Vec::new(),
+        labels: Vec::new(),
+        expansion: Vec::new(),
+        arch: Arch::W65816,
+        mapper: Mapper::default(),
+        bank: None,
+        modes: Vec::new(),
+        opcodes: 0,
+        spc_sizes: Vec::new(),
+        spc_blocks: 0,
+    };
+    for &pass in &[Pass::First, Pass::Second] {
+        assembler.begin_pass(pass);
+        assembler.statements(&statements)?;
+        let end = assembler.address();
+        assembler.symbols.set_end(i64::from(end));
+    }
+    assembler.unused_labels(&statements)?;
+    Ok(Output {
+        origin,
+        data: assembler.data,
+        fixups: assembler.fixups,
+        symbols: assembler.symbols.symbols(),
+        mapper: assembler.mapper,
+        mappings: assembler.mappings,
+        messages: assembler.messages,
+        warnings: assembler.warnings,
+    })
+}
+
+/// Collects declared variants, and detects the variant of the base ROM
+/// when there are any.
+fn detect_variant<'a>(
+    statements: &[Spanned<'a, Statement<'a>>],
+    symbols: &Symbols<'a>,
+) -> Result<(Variants<'a>, Option<Variant<'a>>), Error<'a>> {
+    let variants = Variants::new(statements).map_err(|e| Error::Variant(e.span, e.node))?;
+    let declaration = statements
+        .iter()
+        .find(|statement| matches!(***statement, Statement::RomVariant(..)));
+    let variant = match declaration {
+        Some(declaration) => {
+            let span = declaration.span;
+            let rom = symbols.base_rom().ok_or(Error::NoBaseRom(span))?;
+            variants
+                .detect(rom)
+                .map_err(|e| Error::Variant(span, e))?
+                .cloned()
+        }
+        None => None,
+    };
+    Ok((variants, variant))
+}
+
+struct Assembler<'a> {
+    symbols: Symbols<'a>,
+    variants: Variants<'a>,
+    /// Variant of the base ROM, which expressions are evaluated for.
+    variant: Option<Variant<'a>>,
+    /// Opcodes with constant operands which don't fit their width
+    /// suffixes, see `width::check`.
+    mismatches: Vec<Mismatch<'a>>,
+    /// Labels reached with different register widths, reported where
+    /// they are declared.
+    conflicts: Vec<Conflict<'a>>,
+    origin: u32,
+    data: Vec<u8>,
+    fixups: Vec<Fixup<'a>>,
+    mappings: Vec<Mapping<'a>>,
+    messages: Vec<String>,
+    /// Levels given to `assemble_with_levels`, which directives change.
+    initial_levels: Levels,
+    levels: Levels,
+    warnings: Vec<Warning<'a>>,
+    /// Named labels defined in the second pass, with the level of unused
+    /// labels at their definitions.
+    labels: Vec<(&'a str, Span<'a>, Level)>,
+    /// Spans of macro calls being assembled, with the outermost call
+    /// first.
+    expansion: Vec<Span<'a>>,
+    arch: Arch,
+    mapper: Mapper,
+    /// Data bank set with `bank`, or `None` for the bank of code.
+    bank: Option<u8>,
+    /// Addressing modes chosen in the first pass, in order of opcodes.
+    modes: Vec<AddressingMode>,
+    /// Number of opcodes assembled in the current pass.
+    opcodes: usize,
+    /// Sizes of SPC700 blocks in the first pass, in order of blocks.
+    spc_sizes: Vec<usize>,
+    /// Number of SPC700 blocks assembled in the current pass.
+    spc_blocks: usize,
+}
+
+impl<'a> Assembler<'a> {
+    fn begin_pass(&mut self, pass: Pass) {
+        self.symbols.begin_pass(pass);
+        self.data.clear();
+        self.fixups.clear();
+        self.mappings.clear();
+        self.messages.clear();
+        self.levels = self.initial_levels;
+        self.warnings.clear();
+        self.labels.clear();
+        self.arch = Arch::W65816;
+        self.mapper = Mapper::default();
+        self.symbols.set_mapper(self.mapper);
+        self.bank = None;
+        self.opcodes = 0;
+        self.spc_blocks = 0;
+    }
+
+    /// Evaluation context of the detected variant.
+    fn variant_context(&self) -> Option<VariantContext<'_, 'a, Symbols<'a>>> {
+        self.variant
+            .as_ref()
+            .map(|variant| self.variants.context(variant, &self.symbols))
+    }
+
+    /// Evaluates an expression with `Symbols::resolve`, in the context of
+    /// the detected variant.
+    ///
+    /// Errors point at the operand which caused them, or at `span` when
+    /// there is none, see `symbol_error`.
+    fn resolve(
+        &self,
+        expression: &Expression<'a>,
+        span: Span<'a>,
+    ) -> Result<Option<i64>, Error<'a>> {
+        let result = match self.variant_context() {
+            Some(context) => self.symbols.resolve_in(expression, &context),
+            None => self.symbols.resolve(expression),
+        };
+        result.map_err(|e| self.symbol_error(expression, span, e))
+    }
+
+    /// Evaluates an expression to text like `resolve`.
+    fn resolve_string(
+        &self,
+        expression: &Expression<'a>,
+        span: Span<'a>,
+    ) -> Result<Option<String>, Error<'a>> {
+        let result = match self.variant_context() {
+            Some(context) => self.symbols.resolve_string_in(expression, &context),
+            None => self.symbols.resolve_string(expression),
+        };
+        result.map_err(|e| self.symbol_error(expression, span, e))
+    }
+
+    /// Reports an error in evaluating an expression at the innermost
+    /// operand which fails with it, see `eval::error_span`, or at `span`.
+    fn symbol_error(
+        &self,
+        expression: &Expression<'a>,
+        span: Span<'a>,
+        error: symbols::Error<'a>,
+    ) -> Error<'a> {
+        let cause = match error {
+            symbols::Error::Undefined(ref label, _) => eval::Error::UndefinedLabel(label.clone()),
+            symbols::Error::Eval(ref error) => error.clone(),
+            symbols::Error::Redefined(..) => return Error::Symbol(span, error),
+        };
+        let operand = match self.variant_context() {
+            Some(context) => eval::error_span(expression, &context, &cause),
+            None => eval::error_span(expression, &self.symbols, &cause),
+        };
+        Error::Symbol(operand.unwrap_or(span), error)
+    }
+
+    fn address(&self) -> u32 {
+        self.origin.wrapping_add(self.data.len() as u32)
+    }
+
+    fn statements(&mut self, statements: &[Spanned<'a, Statement<'a>>]) -> Result<(), Error<'a>> {
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Spanned<'a, Statement<'a>>) -> Result<(), Error<'a>> {
+        let span = statement.span;
+        let symbol_error = |e| Error::Symbol(span, e);
+        let second_pass = self.symbols.pass() == Pass::Second;
+        let address = self.address();
+        self.symbols.set_program_counter(Some(address));
+        match **statement {
+            Statement::Label(ref label) => {
+                self.symbols
+                    .define_label(label, i64::from(address), span)
+                    .map_err(symbol_error)?;
+                if let (Label::Named(VariableName(name)), true) = (label, second_pass) {
+                    let level = self.levels.get(Category::UnusedLabel);
+                    self.labels.push((name, span, level));
+                }
+                if second_pass {
+                    self.width_conflicts(label, span)?;
+                }
+            }
+            Statement::Assignment(ref name, ref value) => {
+                if let Some(value) = self.resolve(value, span)? {
+                    self.symbols
+                        .assign(name, value, span)
+                        .map_err(symbol_error)?;
+                }
+            }
+            Statement::StringAssignment(ref name, ref value) => {
+                if let Some(text) = self.resolve_string(value, span)? {
+                    self.symbols
+                        .assign_string(name, text)
+                        .map_err(symbol_error)?;
+                }
+            }
+            Statement::Print(ref values) if second_pass => {
+                let mut message = String::new();
+                for value in values {
+                    if let Some(text) = self.resolve_string(value, span)? {
+                        message += &text;
+                    }
+                }
+                self.messages.push(message);
+            }
+            Statement::Undef(ref name) => self.symbols.undefine(name.0),
+            Statement::Function(ref function) => self.symbols.define_function(function),
+            Statement::Opcode(ref opcode) => self.opcode(opcode, span)?,
+            Statement::If(ref conditions) => {
+                for condition in conditions {
+                    let taken = match condition.predicate {
+                        Some(ref predicate) => match self.resolve(predicate, span)? {
+                            Some(value) => value != 0,
+                            None => return Err(Error::UnresolvedCondition(span)),
+                        },
+                        None => true,
+                    };
+                    if taken {
+                        self.statements(&condition.statements)?;
+                        break;
+                    }
+                }
+            }
+            Statement::WarnPc(ref limit) if second_pass => {
+                let limit = self.resolve(limit, span)?;
+                if limit.is_some_and(|limit| i64::from(address) > limit) {
+                    return Err(Error::WarnPc(span, address));
+                }
+            }
+            Statement::Assert(ref condition, message) if second_pass => {
+                let result = match self.variant_context() {
+                    Some(context) => eval::assert(condition, message, &context),
+                    None => eval::assert(condition, message, &self.symbols),
+                };
+                result.map_err(|e| self.symbol_error(condition, span, symbols::Error::Eval(e)))?;
+            }
+            Statement::Expansion(ref statements) => {
+                self.expansion.push(span);
+                let result = self.statements(statements);
+                self.expansion.pop();
+                result?;
+            }
+            Statement::Arch(arch) => self.arch = arch,
+            Statement::Bank(ref bank) => {
+                self.bank = match *bank {
+                    Some(ref bank) => match self.resolve(bank, span)? {
+                        Some(bank @ 0..=0xFF) => Some(bank as u8),
+                        _ => return Err(Error::InvalidBank(span)),
+                    },
+                    None => None,
+                };
+            }
+            Statement::Warnings(category, level) => self.levels.set(category, level),
+            Statement::Asar(version) if version > asar::VERSION => {
+                return Err(Error::AsarVersion(span, version));
+            }
+            Statement::AsarFeature(feature) => return Err(Error::AsarFeature(span, feature)),
+            Statement::Mapper(mapper) => {
+                self.mapper = mapper;
+                self.symbols.set_mapper(mapper);
+            }
+            Statement::FindPattern(pattern, ref name) => {
+                let address = self.find_pattern(pattern, span)?;
+                self.symbols
+                    .assign(name, address.into(), span)
+                    .map_err(symbol_error)?;
+            }
+            Statement::ExpectCrc(ref checksums) if second_pass => {
+                self.expect_crc(checksums, span)?
+            }
+            Statement::SpcBlock(ref block) => self.spc_block(block, span)?,
+            Statement::Include(_)
+            | Statement::MacroCall(_)
+            | Statement::Spc700(_)
+            | Statement::SuperFx(_) => {
+                return Err(Error::UnsupportedStatement(span));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Writes an SPC700 block as a part of a transfer table.
+    ///
+    /// Labels of the program defined after a block are zero in the first
+    /// pass, and a block must keep its size once they are known.
+    fn spc_block(&mut self, block: &SpcBlock<'a>, span: Span<'a>) -> Result<(), Error<'a>> {
+        let address = self.address();
+        let pass = self.symbols.pass();
+        let assembled = match self.variant_context() {
+            Some(context) => assemble_spc_block(block, span, &context, pass),
+            None => assemble_spc_block(block, span, &self.symbols, pass),
+        };
+        let entries = assembled.map_err(Error::SpcBlock)?.transfer_entries();
+        match pass {
+            Pass::First => self.spc_sizes.push(entries.len()),
+            Pass::Second if self.spc_sizes[self.spc_blocks] != entries.len() => {
+                return Err(Error::SpcBlock(spcblock::Error::UnstableLayout(span)));
+            }
+            Pass::Second => {}
+        }
+        self.spc_blocks += 1;
+        self.data.extend_from_slice(&entries);
+        self.mappings.push(Mapping {
+            addresses: address..self.address(),
+            span,
+            expansion: self.expansion.iter().rev().cloned().collect(),
+        });
+        Ok(())
+    }
+
+    /// Checks that the base ROM has one of the checksums.
+    fn expect_crc(&self, checksums: &[Expression<'a>], span: Span<'a>) -> Result<(), Error<'a>> {
+        let rom = self.symbols.base_rom().ok_or(Error::NoBaseRom(span))?;
+        let symbol_error = |e| Error::Symbol(span, e);
+        let mut allowed = Vec::new();
+        for checksum in checksums {
+            if let Some(value) = self.resolve(checksum, span)? {
+                let crc =
+                    eval::truncate(value, 4).map_err(|e| symbol_error(symbols::Error::Eval(e)))?;
+                allowed.push(crc);
+            }
+        }
+        rom::check_base_rom(rom, &allowed).map_err(|e| Error::BaseRom(span, e))
+    }
+
+    /// Finds the address of the only match of a pattern in the base ROM.
+    fn find_pattern(&self, pattern: &str, span: Span<'a>) -> Result<u32, Error<'a>> {
+        let rom = self.symbols.base_rom().ok_or(Error::NoBaseRom(span))?;
+        let pattern: Pattern = pattern.parse().map_err(|_| Error::InvalidPattern(span))?;
+        let mut matches = pattern.find_all(rom);
+        let offset = match (matches.next(), matches.count()) {
+            (Some(offset), 0) => offset,
+            (first, rest) => {
+                return Err(Error::PatternMatches(span, first.map_or(0, |_| 1 + rest)))
+            }
+        };
+        let unmapped = eval::Error::Unmapped(offset as i64);
+        self.mapper
+            .address(offset)
+            .ok_or(Error::Symbol(span, symbols::Error::Eval(unmapped)))
+    }
+
+    fn opcode(&mut self, opcode: &Opcode<'a>, span: Span<'a>) -> Result<(), Error<'a>> {
+        if let (None, Some(register)) = (opcode.width, flags::immediate_register(opcode)) {
+            if self.levels.get(Category::UnknownState) == Level::Deny {
+                return Err(Error::UnknownWidth(span, register));
+            }
+            let message = format!(
+                "width of {} isn't known, assuming 8 bits",
+                register_name(register)
+            );
+            self.warn(Category::UnknownState, span, message)?;
+        }
+        let address = self.address();
+        // Undefined labels are left to fixups.
+        let resolve = |assembler: &Assembler<'a>, operand: &Spanned<'a, Expression<'a>>| {
+            let operand_span = Some(operand.span)
+                .filter(|operand_span| !operand_span.fragment().is_empty())
+                .unwrap_or(span);
+            assembler
+                .resolve(operand, operand_span)
+                .or_else(|e| match e {
+                    Error::Symbol(_, symbols::Error::Undefined(..)) => Ok(None),
+                    e => Err(e),
+                })
+        };
+        let value = match opcode.value {
+            Some(ref value) => resolve(self, value)?,
+            None => None,
+        };
+        let mode = match self.symbols.pass() {
+            Pass::First => {
+                let value = value.and_then(|value| u32::try_from(value).ok());
+                let bank = Some(self.assumed_bank(opcode));
+                let mode = match encoder::resolve_mode_in(self.arch, opcode, value, bank) {
+                    // `BRK` and `COP` can be written without a signature.
+                    Err(_) if is_bare_interrupt(opcode) => AddressingMode::Implied,
+                    result => result.map_err(|e| Error::Encode(span, EncodeError::Lookup(e)))?,
+                };
+                self.modes.push(mode);
+                mode
+            }
+            Pass::Second => self.modes[self.opcodes],
+        };
+        self.opcodes += 1;
+        if self.symbols.pass() == Pass::Second && is_implicit_direct_page(opcode, mode) {
+            let message = format!(
+                "`{}` uses direct page addressing because of the value of its operand",
+                span.fragment().trim()
+            );
+            self.warn(Category::ImplicitDirectPage, span, message)?;
+        }
+        let encode_error = |e| Error::Encode(span, e);
+        let bytes = if let OpcodeMode::Move { ref second } = opcode.mode {
+            if mode == AddressingMode::BlockMove {
+                let second_value = resolve(self, second)?;
+                for &(expression, value, offset) in &[
+                    (opcode.value.as_deref(), value, 2),
+                    (Some(second), second_value, 1),
+                ] {
+                    if let (Some(expression), None) = (expression, value) {
+                        self.fixup(offset, 1, expression, FixupKind::Absolute, span);
+                    }
+                }
+                encoder::encode_block_move(
+                    &opcode.name.to_ascii_uppercase(),
+                    value.unwrap_or(0),
+                    second_value.unwrap_or(0),
+                )
+                .map_err(encode_error)?
+            } else {
+                self.encode(opcode, mode, value, span)?
+            }
+        } else {
+            self.encode(opcode, mode, value, span)?
+        };
+        self.data.extend_from_slice(&bytes);
+        self.mappings.push(Mapping {
+            addresses: address..self.address(),
+            span,
+            expansion: self.expansion.iter().rev().cloned().collect(),
+        });
+        Ok(())
+    }
+
+    /// Encodes an instruction other than a block move, adding a fixup for
+    /// an unknown operand.
+    fn encode(
+        &mut self,
+        opcode: &Opcode<'a>,
+        mode: AddressingMode,
+        value: Option<i64>,
+        span: Span<'a>,
+    ) -> Result<ArrayVec<u8, 4>, Error<'a>> {
+        let width = match mode.operand_size() {
+            Some(size) => size,
+            None => opcode.width.unwrap_or(1),
+        };
+        let width = match (mode, &opcode.value) {
+            (AddressingMode::Implied, _) | (_, &None) => 0,
+            _ => width,
+        };
+        let end = self.address().wrapping_add(1 + width);
+        let bank = self.assumed_bank(opcode);
+        let kind = match mode {
+            AddressingMode::Relative | AddressingMode::RelativeLong => FixupKind::Relative(end),
+            _ => FixupKind::Absolute,
+        };
+        let operand = match (value, opcode.value.as_ref()) {
+            (Some(value), _) => match kind {
+                FixupKind::Relative(end) => (value - i64::from(end)) as u32,
+                FixupKind::Absolute if width == 0 => 0,
+                // Absolute addresses are in the assumed bank, as in
+                // `JMP Label`.
+                FixupKind::Absolute
+                    if width == 2
+                        && is_address(mode)
+                        && (0x1_0000..0x100_0000).contains(&value) =>
+                {
+                    if value >> 16 != i64::from(bank) {
+                        let message = format!(
+                            "absolute address ${:06X} is outside of assumed bank ${:02X}",
+                            value, bank
+                        );
+                        self.warn(Category::BankMismatch, span, message)?;
+                    }
+                    value as u32 & 0xFFFF
+                }
+                FixupKind::Absolute => self.truncate(value, width, span)?,
+            },
+            (None, Some(expression)) if width > 0 => {
+                self.fixup(1, width, expression, kind, span);
+                0
+            }
+            (None, _) => 0,
+        };
+        let name = opcode.name.to_ascii_uppercase();
+        encoder::encode(&name, mode, operand, width).map_err(|e| Error::Encode(span, e))
+    }
+
+    /// Bank which absolute addresses of an instruction are in, which is
+    /// the bank of code for jumps, and the data bank otherwise.
+    fn assumed_bank(&self, opcode: &Opcode) -> u8 {
+        let code_bank = (self.address() >> 16) as u8;
+        match normalize_mnemonic(opcode.name) {
+            Some("JMP") | Some("JSR") => code_bank,
+            _ => self.bank.unwrap_or(code_bank),
+        }
+    }
+
+    /// Truncates an operand to its width, dropping higher bytes of an
+    /// operand which doesn't fit unless truncated operands are denied.
+    ///
+    /// Operands which don't fit their width suffixes are reported as
+    /// a `Mismatch` with the suffix.
+    fn truncate(&mut self, value: i64, width: u32, span: Span<'a>) -> Result<u32, Error<'a>> {
+        let error = match eval::truncate(value, width) {
+            Ok(operand) => return Ok(operand),
+            Err(error) => error,
+        };
+        let mismatch = self.mismatches.iter().find(|m| m.span == span).cloned();
+        if self.levels.get(Category::TruncatedOperand) == Level::Deny {
+            return Err(match mismatch {
+                Some(mismatch) => Error::WidthMismatch(mismatch),
+                None => Error::Symbol(span, symbols::Error::Eval(error)),
+            });
+        }
+        let operand = (value as u64 & ((1 << (8 * width)) - 1)) as u32;
+        let message = match mismatch {
+            Some(mismatch) => format!("{}, truncated to ${:X}", mismatch, operand),
+            None => format!(
+                "operand ${:X} doesn't fit in {} bytes, truncated to ${:X}",
+                value, width, operand
+            ),
+        };
+        self.warn(Category::TruncatedOperand, span, message)?;
+        Ok(operand)
+    }
+
+    /// Reports conflicting register widths a label is reached with.
+    fn width_conflicts(&mut self, label: &Label<'a>, span: Span<'a>) -> Result<(), Error<'a>> {
+        let conflicts: Vec<_> = self
+            .conflicts
+            .iter()
+            .filter(|conflict| conflict.label.fragment().as_ptr() == span.fragment().as_ptr())
+            .cloned()
+            .collect();
+        for conflict in conflicts {
+            let register = match conflict.register {
+                Register::Accumulator => "accumulator",
+                Register::Index => "index register",
+            };
+            let message = match (conflict.narrow, conflict.wide) {
+                (Some(narrow), Some(wide)) => format!(
+                    "label {} is reached with an 8-bit {} from `{}`, and a 16-bit one from `{}`",
+                    label,
+                    register,
+                    narrow.fragment(),
+                    wide.fragment()
+                ),
+                (Some(narrow), None) => format!(
+                    "label {} is reached with an 8-bit {} from `{}`, but is assembled for a \
+                     16-bit one",
+                    label,
+                    register,
+                    narrow.fragment()
+                ),
+                (None, Some(wide)) => format!(
+                    "label {} is reached with a 16-bit {} from `{}`, but is assembled for an \
+                     8-bit one",
+                    label,
+                    register,
+                    wide.fragment()
+                ),
+                (None, None) => continue,
+            };
+            self.warn(Category::WidthConflict, span, message)?;
+        }
+        Ok(())
+    }
+
+    /// Reports a warning with the current level of its category.
+    fn warn(
+        &mut self,
+        category: Category,
+        span: Span<'a>,
+        message: String,
+    ) -> Result<(), Error<'a>> {
+        let level = self.levels.get(category);
+        self.report(
+            level,
+            Warning {
+                category,
+                span,
+                message,
+            },
+        )
+    }
+
+    /// Keeps a warning in the second pass, or fails when it's denied.
+    fn report(&mut self, level: Level, warning: Warning<'a>) -> Result<(), Error<'a>> {
+        match level {
+            Level::Allow => {}
+            Level::Warn if self.symbols.pass() == Pass::Second => self.warnings.push(warning),
+            Level::Warn => {}
+            Level::Deny => return Err(Error::Denied(warning)),
+        }
+        Ok(())
+    }
+
+    /// Reports named labels defined in the second pass which expressions
+    /// don't refer to.
+    fn unused_labels(
+        &mut self,
+        statements: &[Spanned<'a, Statement<'a>>],
+    ) -> Result<(), Error<'a>> {
+        let mut references = References(HashSet::new());
+        visit::walk_statements(&mut references, statements);
+        for (name, span, level) in mem::take(&mut self.labels) {
+            if !references.0.contains(name) {
+                let warning = Warning {
+                    category: Category::UnusedLabel,
+                    span,
+                    message: format!("label {} is never used", name),
+                };
+                self.report(level, warning)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a fixup at an offset from the current instruction, in the
+    /// second pass.
+    fn fixup(
+        &mut self,
+        offset: usize,
+        width: u32,
+        expression: &Expression<'a>,
+        kind: FixupKind,
+        span: Span<'a>,
+    ) {
+        if self.symbols.pass() == Pass::Second {
+            self.fixups.push(Fixup {
+                offset: self.data.len() + offset,
+                width,
+                expression: expression.clone(),
+                kind,
+                span,
+            });
+        }
+    }
+}
+
+/// Names of named labels which expressions refer to.
+struct References<'a>(HashSet<&'a str>);
+
+impl<'a> Visitor<'a> for References<'a> {
+    fn visit_statement(&mut self, statement: &Spanned<'a, Statement<'a>>) {
+        if let Statement::Label(_) = **statement {
+            return;
+        }
+        visit::walk_statement(self, statement);
+    }
+
+    fn visit_label(&mut self, label: &Label<'a>) {
+        if let Label::Named(VariableName(name)) = *label {
+            self.0.insert(name);
+        }
+    }
+}
+
+fn assemble_spc_block<'a, C: Context<'a>>(
+    block: &SpcBlock<'a>,
+    span: Span<'a>,
+    context: &C,
+    pass: Pass,
+) -> Result<spcblock::Block, spcblock::Error<'a>> {
+    match pass {
+        Pass::First => spcblock::assemble(block, span, &FirstPass(context)),
+        Pass::Second => spcblock::assemble(block, span, context),
+    }
+}
+
+/// Context of the first pass, where labels which aren't defined yet are
+/// zero.
+struct FirstPass<'c, C: 'c>(&'c C);
+
+impl<'c, 'a, C: Context<'a>> Context<'a> for FirstPass<'c, C> {
+    fn label(&self, label: &Label) -> Option<i64> {
+        self.0.label(label).or(Some(0))
+    }
+
+    fn function(&self, name: &VariableName) -> Option<&Function<'a>> {
+        self.0.function(name)
+    }
+
+    fn program_counter(&self) -> Option<u32> {
+        self.0.program_counter()
+    }
+
+    fn file(&self, path: &str) -> Option<&[u8]> {
+        self.0.file(path)
+    }
+
+    fn defined(&self, name: &str) -> bool {
+        self.0.defined(name)
+    }
+
+    fn mapper(&self) -> Mapper {
+        self.0.mapper()
+    }
+
+    fn datasize(&self, label: &Label) -> Option<i64> {
+        self.0.datasize(label)
+    }
+
+    fn text(&self, name: &str) -> Option<&str> {
+        self.0.text(name)
+    }
+}
+
+/// Name of a register in messages.
+fn register_name(register: Register) -> &'static str {
+    match register {
+        Register::Accumulator => "accumulator",
+        Register::Index => "index registers",
+    }
+}
+
+/// Checks whether direct page addressing was chosen by the value of an
+/// operand, rather than by a width suffix or the width of a literal.
+fn is_implicit_direct_page(opcode: &Opcode, mode: AddressingMode) -> bool {
+    let literal = match opcode.value.as_deref() {
+        Some(Expression::Number(number)) => number.width.bytes().is_some(),
+        _ => false,
+    };
+    opcode.width.is_none()
+        && !literal
+        && matches!(
+            mode,
+            AddressingMode::DirectPage | AddressingMode::DpIndexedX | AddressingMode::DpIndexedY
+        )
+}
+
+/// Checks whether an instruction is `BRK` or `COP` without a signature.
+fn is_bare_interrupt(opcode: &Opcode) -> bool {
+    opcode.mode == OpcodeMode::Implied
+        && (opcode.name.eq_ignore_ascii_case("BRK") || opcode.name.eq_ignore_ascii_case("COP"))
+}
+
+/// Checks whether an operand of a mode is an address.
+fn is_address(mode: AddressingMode) -> bool {
+    !matches!(
+        mode,
+        AddressingMode::Immediate | AddressingMode::BlockMove | AddressingMode::Implied
+    )
+}
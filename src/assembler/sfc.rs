@@ -0,0 +1,205 @@
+//! Writing of assembled code into `.sfc` ROM images.
+//!
+//! Code is placed at file offsets given by the mapper selected in a
+//! program, and the image is padded to a valid ROM size, with an internal
+//! header generated from `rom::Header` and a fixed checksum, so that
+//! a program doesn't need to write its header itself.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use assembler::output::Output;
+use parser::ast::Span;
+use rom::{self, Header, SizePolicy};
+use signature;
+
+/// Size of internal header, including interrupt vectors.
+const HEADER_SIZE: usize = 0x40;
+
+/// A failure to build a ROM image.
+#[derive(Debug)]
+pub enum Error<'a> {
+    /// Code is placed at an address which isn't mapped to ROM.
+    Unmapped(u32),
+    /// Code is placed at an address of internal header.
+    HeaderOverlap(u32),
+    /// Operand of a statement uses a label which isn't defined, see
+    /// `output::Fixup`.
+    Unresolved(Span<'a>),
+    /// ROM couldn't be written.
+    Io(io::Error),
+    /// ROM couldn't be signed.
+    Signature(signature::Error),
+}
+
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Unmapped(address) => write!(f, "address ${:06X} is not mapped to ROM", address),
+            Error::HeaderOverlap(address) => write!(
+                f,
+                "code at ${:06X} overlaps internal header, which is generated",
+                address
+            ),
+            Error::Unresolved(span) => write!(
+                f,
+                "`{}` uses a label which is not defined",
+                span.fragment().trim()
+            ),
+            Error::Io(ref e) => e.fmt(f),
+            Error::Signature(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl<'a> error::Error for Error<'a> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> From<io::Error> for Error<'a> {
+    fn from(e: io::Error) -> Error<'a> {
+        Error::Io(e)
+    }
+}
+
+/// Builds a ROM image out of assembled code.
+///
+/// The mapper of a header is replaced with the one selected in code. When
+/// the reset vector isn't set, it points to the start of code, so that
+/// code starting in bank `$00` or its mirror boots.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::flags::State;
+/// use mvp::assembler::{output, sfc};
+/// use mvp::mapping::Mapper;
+/// use mvp::parser::grammar::parse_program;
+/// use mvp::rom::Header;
+///
+/// let statements = parse_program("hirom\nReset:\nWAI\nBRA Reset\n").unwrap();
+/// let output = output::assemble(statements, 0xC08000, State::new(true, true)).unwrap();
+/// let rom = sfc::build(&output, &Header::new("example", Mapper::LoRom)).unwrap();
+/// assert_eq!(rom.len(), 0x10000);
+/// assert_eq!(rom[0x8000..0x8003], [0xCB, 0x80, 0xFD]);
+/// assert_eq!(rom[0xFFD5], 0x21);
+/// assert_eq!(rom[0xFFFC..0xFFFE], [0x00, 0x80]);
+/// ```
+pub fn build<'a>(output: &Output<'a>, header: &Header) -> Result<Vec<u8>, Error<'a>> {
+    if let Some(fixup) = output.fixups.first() {
+        return Err(Error::Unresolved(fixup.span));
+    }
+    let mapper = output.mapper;
+    let layout = mapper.layout();
+    let headers = layout.header..layout.header + HEADER_SIZE;
+    let mut image = vec![0; headers.end];
+    for (address, &byte) in (output.origin..).zip(&output.data) {
+        let offset = mapper
+            .file_offset(address)
+            .ok_or(Error::Unmapped(address))?;
+        if headers.contains(&offset) {
+            return Err(Error::HeaderOverlap(address));
+        }
+        if offset >= image.len() {
+            image.resize(offset + 1, 0);
+        }
+        image[offset] = byte;
+    }
+    rom::resize(&mut image, SizePolicy::PadToValid, &layout, 0);
+    let mut header = Header {
+        mapper,
+        ..header.clone()
+    };
+    if header.vectors.reset == 0 {
+        header.vectors.reset = output.origin as u16;
+    }
+    header.write(&mut image);
+    rom::fix_checksum(&mut image, &layout);
+    Ok(image)
+}
+
+/// Writes assembled code into an existing ROM image.
+///
+/// An image is extended with zeros when code is placed past its end.
+/// Unlike `build`, the header is kept as is, but its checksum is fixed
+/// when an image contains one.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::flags::State;
+/// use mvp::assembler::{output, sfc};
+/// use mvp::parser::grammar::parse_program;
+///
+/// let statements = parse_program("LDA #$12\n").unwrap();
+/// let output = output::assemble(statements, 0x818000, State::new(true, true)).unwrap();
+/// let mut rom = vec![0xFF; 0x8000];
+/// sfc::patch(&mut rom, &output).unwrap();
+/// assert_eq!(rom.len(), 0x8002);
+/// assert_eq!(rom[0x8000..], [0xA9, 0x12]);
+/// ```
+pub fn patch<'a>(rom: &mut Vec<u8>, output: &Output<'a>) -> Result<(), Error<'a>> {
+    if let Some(fixup) = output.fixups.first() {
+        return Err(Error::Unresolved(fixup.span));
+    }
+    let layout = output.mapper.layout();
+    for (address, &byte) in (output.origin..).zip(&output.data) {
+        let offset = output
+            .mapper
+            .file_offset(address)
+            .ok_or(Error::Unmapped(address))?;
+        if offset >= rom.len() {
+            rom.resize(offset + 1, 0);
+        }
+        rom[offset] = byte;
+    }
+    if rom.len() >= layout.header + HEADER_SIZE {
+        rom::fix_checksum(rom, &layout);
+    }
+    Ok(())
+}
+
+/// File offsets assembled code is written to by `build` and `patch`, with
+/// adjacent offsets merged into ranges, which are signed by a
+/// `signature::Manifest`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::flags::State;
+/// use mvp::assembler::{output, sfc};
+/// use mvp::parser::grammar::parse_program;
+///
+/// let statements = parse_program("LDA #$12\nRTS\n").unwrap();
+/// let output = output::assemble(statements, 0x818000, State::new(true, true)).unwrap();
+/// assert_eq!(sfc::written_ranges(&output).unwrap(), [0x8000..0x8003]);
+/// ```
+pub fn written_ranges<'a>(output: &Output<'a>) -> Result<Vec<Range<usize>>, Error<'a>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for (address, _) in (output.origin..).zip(&output.data) {
+        let offset = output
+            .mapper
+            .file_offset(address)
+            .ok_or(Error::Unmapped(address))?;
+        match ranges.last_mut() {
+            Some(range) if range.end == offset => range.end += 1,
+            _ => ranges.push(offset..offset + 1),
+        }
+    }
+    Ok(ranges)
+}
+
+/// Builds a ROM image and writes it into a file, see `build`.
+pub fn write<'a>(path: &Path, output: &Output<'a>, header: &Header) -> Result<(), Error<'a>> {
+    fs::write(path, build(output, header)?)?;
+    Ok(())
+}
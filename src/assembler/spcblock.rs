@@ -0,0 +1,468 @@
+//! Assembly of SPC700 code for audio RAM.
+//!
+//! The sound processor runs code from its own 64 KiB of RAM, which the
+//! main CPU fills through the APU ports. The boot ROM of the sound
+//! processor runs a loop receiving blocks of data, each preceded by its
+//! size and address, until a block of size zero, which is followed by an
+//! address to jump to. Code for audio RAM is written in SPC700 blocks:
+//!
+//! ```asm
+//! spcblock $0400
+//! Start:
+//!     MOV A,#$12
+//!     BRA Start
+//! endspcblock execute Start
+//! ```
+//!
+//! Labels declared in a block are only visible within it, other names are
+//! looked up in the context of the main program. Forward references are
+//! resolved by laying out a block until addresses of its labels stop
+//! changing.
+//!
+//! Assembled code contains every block in its place, as a part of
+//! a transfer table for the upload loop, see `Block::transfer_entries`.
+//! Consecutive blocks form one table, which a block with `execute` ends.
+
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+
+use encoder::spc700::{self, Operand};
+use encoder::Arch;
+use eval::{self, Context};
+use mapping::Mapper;
+use parser::ast::{Function, Label, Span, Spanned, SpcBlock, Statement, VariableName};
+
+/// Size of audio RAM.
+const RAM_SIZE: u32 = 0x1_0000;
+
+/// Maximum number of layouts of a block before giving up.
+const MAX_PASSES: usize = 16;
+
+/// An assembled SPC700 block.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Block {
+    /// Address of data in audio RAM.
+    pub address: u16,
+    pub data: Vec<u8>,
+    /// Entry point given with `endspcblock execute`.
+    pub execute: Option<u16>,
+}
+
+impl Block {
+    /// Encodes a block as a part of a transfer table, see
+    /// `Upload::transfer_table`, which is ended when a block has
+    /// `execute`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::assembler::spcblock::Block;
+    ///
+    /// let block = Block { address: 0x0400, data: vec![0x2F, 0xFE], execute: Some(0x0400) };
+    /// assert_eq!(
+    ///     block.transfer_entries(),
+    ///     [0x02, 0x00, 0x00, 0x04, 0x2F, 0xFE, 0x00, 0x00, 0x00, 0x04],
+    /// );
+    /// ```
+    pub fn transfer_entries(&self) -> Vec<u8> {
+        let mut entries = self.chunks();
+        if let Some(execute) = self.execute {
+            entries.extend_from_slice(&[0, 0]);
+            entries.extend_from_slice(&execute.to_le_bytes());
+        }
+        entries
+    }
+
+    /// Data of a block in chunks preceded by their sizes and addresses.
+    fn chunks(&self) -> Vec<u8> {
+        let mut chunks = Vec::new();
+        let mut address = self.address;
+        for chunk in self.data.chunks(0xFFFF) {
+            chunks.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+            chunks.extend_from_slice(&address.to_le_bytes());
+            chunks.extend_from_slice(chunk);
+            address = address.wrapping_add(chunk.len() as u16);
+        }
+        chunks
+    }
+}
+
+/// Blocks to upload into audio RAM, along with an address to start
+/// running code at.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Upload {
+    pub blocks: Vec<Block>,
+    pub entry: u16,
+}
+
+impl Upload {
+    /// Combines assembled blocks, or returns `None` when there are none.
+    ///
+    /// The entry point is given by the last block with `execute`, and
+    /// otherwise is the start of the first block.
+    pub fn new(blocks: Vec<Block>) -> Option<Upload> {
+        let entry = blocks
+            .iter()
+            .rev()
+            .find_map(|block| block.execute)
+            .or_else(|| blocks.first().map(|block| block.address))?;
+        Some(Upload { blocks, entry })
+    }
+
+    /// Encodes an upload as read by the upload loop of the boot ROM.
+    ///
+    /// Every block is preceded by its size and address, and the last one
+    /// is followed by a zero size and the entry point, all as 16-bit little
+    /// endian words. Blocks larger than 64 KiB are split.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::assembler::spcblock::{Block, Upload};
+    ///
+    /// let block = Block { address: 0x0400, data: vec![0x2F, 0xFE], execute: None };
+    /// let upload = Upload::new(vec![block]).unwrap();
+    /// assert_eq!(
+    ///     upload.transfer_table(),
+    ///     [0x02, 0x00, 0x00, 0x04, 0x2F, 0xFE, 0x00, 0x00, 0x00, 0x04],
+    /// );
+    /// ```
+    pub fn transfer_table(&self) -> Vec<u8> {
+        let mut table = Vec::new();
+        for block in &self.blocks {
+            table.extend(block.chunks());
+        }
+        table.extend_from_slice(&[0, 0]);
+        table.extend_from_slice(&self.entry.to_le_bytes());
+        table
+    }
+}
+
+/// A failure to assemble an SPC700 block.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error<'a> {
+    /// An expression of a statement couldn't be evaluated.
+    Eval(Span<'a>, eval::Error<'a>),
+    /// An instruction couldn't be encoded.
+    Encode(Span<'a>, spc700::Error),
+    /// Statement can't be used in an SPC700 block, like a 65816 opcode.
+    UnsupportedStatement(Span<'a>),
+    /// Label was declared more than once in a block.
+    DuplicateLabel(Span<'a>),
+    /// Address is outside of audio RAM, or code doesn't fit in it.
+    OutOfRam(Span<'a>),
+    /// Sizes of instructions kept changing along with label addresses.
+    UnstableLayout(Span<'a>),
+}
+
+impl<'a> Error<'a> {
+    /// Statement which failed to assemble.
+    pub fn span(&self) -> Span<'a> {
+        match *self {
+            Error::Eval(span, _)
+            | Error::Encode(span, _)
+            | Error::UnsupportedStatement(span)
+            | Error::DuplicateLabel(span)
+            | Error::OutOfRam(span)
+            | Error::UnstableLayout(span) => span,
+        }
+    }
+}
+
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Eval(_, ref error) => write!(f, "{}", error),
+            Error::Encode(_, ref error) => write!(f, "{}", error),
+            Error::UnsupportedStatement(_) => {
+                write!(f, "statement can't be used in an SPC700 block")
+            }
+            Error::DuplicateLabel(_) => write!(f, "label is already declared in this block"),
+            Error::OutOfRam(_) => write!(f, "code doesn't fit in audio RAM"),
+            Error::UnstableLayout(_) => write!(f, "addresses of labels in a block keep changing"),
+        }
+    }
+}
+
+impl<'a> error::Error for Error<'a> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Eval(_, ref error) => error.source(),
+            Error::Encode(_, ref error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+/// Assembles SPC700 blocks of a program into an upload, or returns
+/// `None` when a program has none.
+///
+/// Only blocks outside of if statements are assembled.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::spcblock;
+/// use mvp::parser::ast::Label;
+/// use mvp::parser::grammar::parse_program;
+///
+/// let source = "spcblock $0400\nStart:\nMOV A,#$12\nBRA Start\nendspcblock\n";
+/// let statements = parse_program(source).unwrap();
+/// let upload = spcblock::upload(&statements, &|_: &Label| None).unwrap().unwrap();
+/// assert_eq!(upload.entry, 0x0400);
+/// assert_eq!(upload.blocks[0].data, [0xE8, 0x12, 0x2F, 0xFC]);
+/// ```
+pub fn upload<'a, C: Context<'a>>(
+    statements: &[Spanned<'a, Statement<'a>>],
+    context: &C,
+) -> Result<Option<Upload>, Error<'a>> {
+    let mut blocks = Vec::new();
+    for statement in statements {
+        if let Statement::SpcBlock(ref block) = **statement {
+            blocks.push(assemble(block, statement.span, context)?);
+        }
+    }
+    Ok(Upload::new(blocks))
+}
+
+/// Assembles a single SPC700 block, whose statement has `span`.
+pub fn assemble<'a, C: Context<'a>>(
+    block: &SpcBlock<'a>,
+    span: Span<'a>,
+    context: &C,
+) -> Result<Block, Error<'a>> {
+    let address = eval::evaluate(&block.address, context).map_err(|e| Error::Eval(span, e))?;
+    let address = u16::try_from(address).map_err(|_| Error::OutOfRam(span))?;
+    let code = Code::new(&block.statements, context)?;
+    let mut addresses = vec![None; block.statements.len()];
+    let mut passes = 0;
+    loop {
+        let layout = code.pass(u32::from(address), &addresses, None)?;
+        if layout == addresses {
+            break;
+        }
+        passes += 1;
+        if passes == MAX_PASSES {
+            return Err(Error::UnstableLayout(span));
+        }
+        addresses = layout;
+    }
+    let mut data = Vec::new();
+    code.pass(u32::from(address), &addresses, Some(&mut data))?;
+    let execute = match block.execute {
+        Some(ref execute) => {
+            let end = u32::from(address) + data.len() as u32;
+            let context = code.context(&addresses, block.statements.len(), end);
+            let execute = eval::evaluate(execute, &context).map_err(|e| Error::Eval(span, e))?;
+            Some(u16::try_from(execute).map_err(|_| Error::OutOfRam(span))?)
+        }
+        None => None,
+    };
+    Ok(Block {
+        address,
+        data,
+        execute,
+    })
+}
+
+/// Statements of a block, with scopes of their labels.
+struct Code<'b, 'a: 'b, C: 'b> {
+    statements: &'b [Spanned<'a, Statement<'a>>],
+    /// Named label each statement follows.
+    scopes: Vec<Option<&'a str>>,
+    parent: &'b C,
+}
+
+impl<'b, 'a, C: Context<'a>> Code<'b, 'a, C> {
+    fn new(statements: &'b [Spanned<'a, Statement<'a>>], parent: &'b C) -> Result<Self, Error<'a>> {
+        let mut scopes = Vec::with_capacity(statements.len());
+        let mut scope = None;
+        for (index, statement) in statements.iter().enumerate() {
+            if let Statement::Label(ref label) = **statement {
+                if let Label::Named(VariableName(name)) = *label {
+                    scope = Some(name);
+                }
+                let declared = statements[..index].iter().enumerate().any(|(i, other)| {
+                    **other == Statement::Label(label.clone()) && scopes[i] == scope
+                });
+                if declared && !matches!(*label, Label::Relative(_)) {
+                    return Err(Error::DuplicateLabel(statement.span));
+                }
+            }
+            scopes.push(scope);
+        }
+        Ok(Code {
+            statements,
+            scopes,
+            parent,
+        })
+    }
+
+    /// Lays out statements from `start`, returning their addresses.
+    ///
+    /// `addresses` are addresses from a previous layout, with `None` for
+    /// statements which weren't laid out yet. Labels without an address
+    /// are assumed to be at the current instruction, unless encoded
+    /// instructions are written into `output`, which requires all of them.
+    fn pass(
+        &self,
+        start: u32,
+        addresses: &[Option<u32>],
+        mut output: Option<&mut Vec<u8>>,
+    ) -> Result<Vec<Option<u32>>, Error<'a>> {
+        let mut layout = Vec::with_capacity(self.statements.len());
+        let mut address = start;
+        for (index, statement) in self.statements.iter().enumerate() {
+            layout.push(Some(address));
+            let instruction = match **statement {
+                Statement::Spc700(ref instruction) => instruction,
+                Statement::Label(_) | Statement::Arch(Arch::Spc700) => continue,
+                _ => return Err(Error::UnsupportedStatement(statement.span)),
+            };
+            let context = self.context(addresses, index, address);
+            let encode = |unknown| {
+                let operands = instruction
+                    .operands
+                    .iter()
+                    .map(|operand| {
+                        operand
+                            .as_ref()
+                            .try_map(|value| match eval::evaluate(value, &context) {
+                                Ok(value) => {
+                                    u32::try_from(value).map_err(|_| eval::Error::Overflow)
+                                }
+                                Err(eval::Error::UndefinedLabel(ref label))
+                                    if output.is_none() && self.declares(label) =>
+                                {
+                                    Ok(unknown)
+                                }
+                                Err(e) => Err(e),
+                            })
+                    })
+                    .collect::<Result<Vec<Operand<u32>>, _>>()
+                    .map_err(|e| Error::Eval(statement.span, e))?;
+                spc700::encode(instruction.name, &operands, address)
+                    .map_err(|e| Error::Encode(statement.span, e))
+            };
+            // An unknown label may also be in the direct page, which some
+            // instructions require.
+            let bytes = match encode(address) {
+                Err(_) if output.is_none() => encode(0),
+                result => result,
+            }?;
+            address += bytes.len() as u32;
+            if address > RAM_SIZE {
+                return Err(Error::OutOfRam(statement.span));
+            }
+            if let Some(ref mut output) = output {
+                output.extend_from_slice(&bytes);
+            }
+        }
+        Ok(layout)
+    }
+
+    /// Checks whether a label is declared within a block.
+    fn declares(&self, label: &Label) -> bool {
+        self.statements.iter().any(
+            |statement| matches!(**statement, Statement::Label(ref declared) if declared == label),
+        )
+    }
+
+    fn context<'c>(
+        &'c self,
+        addresses: &'c [Option<u32>],
+        index: usize,
+        address: u32,
+    ) -> BlockContext<'c, 'b, 'a, C> {
+        BlockContext {
+            code: self,
+            addresses,
+            index,
+            address,
+        }
+    }
+
+    /// Finds a declaration of a label as seen from a statement.
+    fn find(&self, index: usize, label: &Label) -> Option<usize> {
+        let scope = self
+            .scopes
+            .get(index)
+            .cloned()
+            .unwrap_or_else(|| self.scopes.last().cloned().unwrap_or(None));
+        let is_label = |i: &usize| match *self.statements[*i] {
+            Statement::Label(ref declared) => declared == label,
+            _ => false,
+        };
+        match *label {
+            Label::Named(_) => (0..self.statements.len()).find(is_label),
+            Label::Scoped(_) => {
+                (0..self.statements.len()).find(|i| is_label(i) && self.scopes[*i] == scope)
+            }
+            Label::Relative(n) if n < 0 => (0..index).rev().find(is_label),
+            Label::Relative(_) => (index..self.statements.len()).find(is_label),
+        }
+    }
+}
+
+/// Labels of a block as seen from one of its statements, with other names
+/// looked up in the context of the main program.
+struct BlockContext<'c, 'b: 'c, 'a: 'b, C: 'b> {
+    code: &'c Code<'b, 'a, C>,
+    addresses: &'c [Option<u32>],
+    index: usize,
+    address: u32,
+}
+
+impl<'c, 'b, 'a, C: Context<'a>> Context<'a> for BlockContext<'c, 'b, 'a, C> {
+    fn label(&self, label: &Label) -> Option<i64> {
+        match self.code.find(self.index, label) {
+            Some(index) => self.addresses[index].map(i64::from),
+            None => self.code.parent.label(label),
+        }
+    }
+
+    fn function(&self, name: &VariableName) -> Option<&Function<'a>> {
+        self.code.parent.function(name)
+    }
+
+    fn program_counter(&self) -> Option<u32> {
+        Some(self.address)
+    }
+
+    fn file(&self, path: &str) -> Option<&[u8]> {
+        self.code.parent.file(path)
+    }
+
+    fn defined(&self, name: &str) -> bool {
+        match self
+            .code
+            .find(self.index, &Label::Named(VariableName(name)))
+        {
+            Some(index) => self.addresses[index].is_some(),
+            None => self.code.parent.defined(name),
+        }
+    }
+
+    fn mapper(&self) -> Mapper {
+        self.code.parent.mapper()
+    }
+
+    /// Labels of a block are measured to the next label of the block.
+    fn datasize(&self, label: &Label) -> Option<i64> {
+        let index = match self.code.find(self.index, label) {
+            Some(index) => index,
+            None => return self.code.parent.datasize(label),
+        };
+        let start = self.addresses[index]?;
+        let end = (index + 1..self.code.statements.len())
+            .filter(|&i| matches!(*self.code.statements[i], Statement::Label(_)))
+            .filter_map(|i| self.addresses[i])
+            .find(|&address| address > start)?;
+        Some(i64::from(end - start))
+    }
+
+    fn text(&self, name: &str) -> Option<&str> {
+        self.code.parent.text(name)
+    }
+}
@@ -0,0 +1,562 @@
+extern crate mvp;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use mvp::asar::AsarVersion;
+use mvp::assembler::branch::LongJump;
+use mvp::assembler::{sfc, Assembler};
+use mvp::debugger;
+use mvp::encoder::Aliases;
+use mvp::format::{self, Options as FormatOptions};
+#[cfg(feature = "lsp")]
+use mvp::include::Disk;
+#[cfg(feature = "lsp")]
+use mvp::lsp::Server;
+use mvp::mapping::Mapper;
+use mvp::parser::grammar::{Dialect, ParserOptions};
+use mvp::project::Diagnostic;
+use mvp::scaffold::{Settings, Template, MANIFEST};
+use mvp::signature::{Manifest, Sha256};
+use mvp::warning::{Category, Level};
+
+const USAGE: &str = "\
+usage: mvp [options] <asm> [<rom>]
+       mvp verify <rom> [<manifest>]
+       mvp init <directory> [--mapper <mapper>]
+       mvp build [<directory>]
+       mvp fmt [--check] <asm>...
+       mvp lsp
+
+Assembles <asm> and patches <rom>, which is <asm> with .sfc extension by
+default. A new ROM image is created when <rom> doesn't exist. mvp build
+assembles a project created by mvp init, in the current directory by
+default, into a new ROM image named by its mvp.json. mvp fmt
+formats source files in place, or lists files which aren't formatted
+with --check. mvp verify checks written code of a ROM against a manifest,
+or a signature block in the ROM without one. mvp lsp runs a language
+server over standard input and output.
+
+<asm> can be - to read source from standard input. <rom> or a path given
+to -o can be - to write a new ROM image to standard output, which is the
+default for source from standard input. Printed messages go to standard
+error then.
+
+options:
+  -h, --help          print this help
+  -o <path>           write the patched ROM to a path instead of <rom>
+  --title <title>     title of a new ROM image, <asm> file name by default
+  -I <directory>      search for included files in a directory
+  -D<name>[=<value>]  assign a value to !name before assembly, 1 by default
+  --origin <address>  start code at a hexadecimal address, 8000 by default
+  --dialect <name>    accept syntax of mvp, xkas or asar, asar by default
+  --asar <version>    parse code like Asar of a version, as after asar 1.81
+  --reject-aliases    fail on alias mnemonics, like DEA for DEC A
+  --long-branches <jump>
+                      rewrite branches out of range into jumps, either brl
+                      or jmp
+  --symbols <path>    write symbols in bsnes-plus .sym format
+  --listing <path>    write a listing of assembled code
+  --manifest <path>   write a manifest signing code written to the ROM
+  --embed-manifest    append a signature block to the ROM
+  --allow <category>  ignore warnings of a category
+  --warn <category>   report warnings of a category
+  --deny <category>   fail on warnings of a category
+  --stack-budget <bytes>
+                      warn about routines using more stack than a budget
+  --call-depth <count>
+                      follow nested calls up to a depth estimating stack
+                      usage, 8 by default
+  --interrupt-safety <handler>,<main>
+                      warn about variables written one byte at a time by
+                      routines called from labels of an interrupt handler
+                      and the main loop
+
+warning categories: implicit-dp and unused-label, allowed by default,
+unknown-state, truncated-operand and bank-mismatch, denied by default,
+and stack-usage, interrupt-safety, width-conflict and macro-capture";
+
+/// Depth of nested calls followed when estimating stack usage.
+const DEFAULT_CALL_DEPTH: u32 = 8;
+
+/// Path standing for standard input or output.
+const STDIO: &str = "-";
+
+/// Path of source read from standard input, which includes are relative
+/// to, as it has no directory.
+const STDIN_PATH: &str = "<stdin>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = match args.first().map(String::as_str) {
+        _ if args.iter().any(|arg| arg == "-h" || arg == "--help") => {
+            println!("{}", USAGE);
+            0
+        }
+        Some("verify") if args.len() == 2 => verify(&args[1], None),
+        Some("verify") if args.len() == 3 => verify(&args[1], Some(&args[2])),
+        Some("init") if args.len() == 2 => init(&args[1], "lorom"),
+        Some("init") if args.len() == 4 && args[2] == "--mapper" => init(&args[1], &args[3]),
+        Some("build") if args.len() == 1 => build("."),
+        Some("build") if args.len() == 2 => build(&args[1]),
+        Some("fmt") if args.len() > 1 => fmt(&args[1..]),
+        #[cfg(feature = "lsp")]
+        Some("lsp") if args.len() == 1 => lsp(),
+        Some(_) => match Options::parse(&args) {
+            Ok(options) => patch(&options),
+            Err(message) => {
+                eprintln!("error: {}\n\n{}", message, USAGE);
+                2
+            }
+        },
+        None => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    };
+    process::exit(code);
+}
+
+/// Options of assembling a patch.
+struct Options {
+    asm: PathBuf,
+    rom: PathBuf,
+    output: Option<PathBuf>,
+    title: Option<String>,
+    directories: Vec<PathBuf>,
+    defines: Vec<(String, String)>,
+    origin: u32,
+    dialect: Dialect,
+    asar: Option<AsarVersion>,
+    aliases: Aliases,
+    long_branches: Option<LongJump>,
+    levels: Vec<(Category, Level)>,
+    symbols: Option<PathBuf>,
+    listing: Option<PathBuf>,
+    manifest: Option<PathBuf>,
+    embed_manifest: bool,
+    stack_budget: Option<u32>,
+    call_depth: u32,
+    interrupt_entries: Option<(String, String)>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut paths = Vec::new();
+        let mut output = None;
+        let mut title = None;
+        let mut directories = Vec::new();
+        let mut defines = Vec::new();
+        let mut origin = 0x8000;
+        let mut dialect = Dialect::Asar;
+        let mut asar = None;
+        let mut aliases = Aliases::Accept;
+        let mut long_branches = None;
+        let mut levels = Vec::new();
+        let mut symbols = None;
+        let mut listing = None;
+        let mut manifest = None;
+        let mut embed_manifest = false;
+        let mut stack_budget = None;
+        let mut call_depth = DEFAULT_CALL_DEPTH;
+        let mut interrupt_entries = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("{} requires a value", arg))
+            };
+            match arg.as_str() {
+                "-o" => output = Some(PathBuf::from(value()?)),
+                "--title" => title = Some(value()?.to_string()),
+                "-I" => directories.push(PathBuf::from(value()?)),
+                "--origin" => {
+                    let address = value()?;
+                    origin = u32::from_str_radix(address.trim_start_matches('$'), 16)
+                        .map_err(|_| format!("invalid origin {}", address))?;
+                }
+                "--dialect" => {
+                    dialect = match value()?.as_str() {
+                        "mvp" => Dialect::Mvp,
+                        "xkas" => Dialect::Xkas,
+                        "asar" => Dialect::Asar,
+                        name => return Err(format!("unknown dialect {}", name)),
+                    }
+                }
+                "--asar" => {
+                    let version = value()?;
+                    asar = Some(
+                        version
+                            .parse()
+                            .map_err(|_| format!("invalid Asar version {}", version))?,
+                    );
+                }
+                "--reject-aliases" => aliases = Aliases::Reject,
+                "--long-branches" => {
+                    long_branches = match value()?.as_str() {
+                        "brl" => Some(LongJump::Brl),
+                        "jmp" => Some(LongJump::Jmp),
+                        jump => return Err(format!("unknown jump {}", jump)),
+                    };
+                }
+                "--allow" | "--warn" | "--deny" => {
+                    let name = value()?;
+                    let category = Category::from_name(name)
+                        .ok_or_else(|| format!("unknown warning category {}", name))?;
+                    let level = match arg.as_str() {
+                        "--allow" => Level::Allow,
+                        "--warn" => Level::Warn,
+                        _ => Level::Deny,
+                    };
+                    levels.push((category, level));
+                }
+                "--symbols" => symbols = Some(PathBuf::from(value()?)),
+                "--listing" => listing = Some(PathBuf::from(value()?)),
+                "--manifest" => manifest = Some(PathBuf::from(value()?)),
+                "--embed-manifest" => embed_manifest = true,
+                "--stack-budget" => {
+                    let bytes = value()?;
+                    stack_budget = Some(
+                        bytes
+                            .parse()
+                            .map_err(|_| format!("invalid stack budget {}", bytes))?,
+                    );
+                }
+                "--call-depth" => {
+                    let depth = value()?;
+                    call_depth = depth
+                        .parse()
+                        .map_err(|_| format!("invalid call depth {}", depth))?;
+                }
+                "--interrupt-safety" => {
+                    let entries = value()?;
+                    let (handler, main) = entries
+                        .split_once(',')
+                        .ok_or_else(|| format!("expected <handler>,<main>, got {}", entries))?;
+                    interrupt_entries = Some((handler.to_string(), main.to_string()));
+                }
+                _ if arg.starts_with("-D") => {
+                    let define = match &arg[2..] {
+                        "" => value()?,
+                        define => define,
+                    };
+                    let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+                    let name = if name.starts_with('!') {
+                        name.to_string()
+                    } else {
+                        format!("!{}", name)
+                    };
+                    defines.push((name, value.to_string()));
+                }
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(format!("unknown option {}", arg))
+                }
+                _ => paths.push(PathBuf::from(arg)),
+            }
+        }
+        let (asm, rom) = match paths.len() {
+            1 if paths[0] == Path::new(STDIO) => (paths[0].clone(), paths[0].clone()),
+            1 => (paths[0].clone(), paths[0].with_extension("sfc")),
+            2 => (paths[0].clone(), paths[1].clone()),
+            _ => return Err("expected <asm> and optional <rom>".to_string()),
+        };
+        Ok(Options {
+            asm,
+            rom,
+            output,
+            title,
+            directories,
+            defines,
+            origin,
+            dialect,
+            asar,
+            aliases,
+            long_branches,
+            levels,
+            symbols,
+            listing,
+            manifest,
+            embed_manifest,
+            stack_budget,
+            call_depth,
+            interrupt_entries,
+        })
+    }
+}
+
+fn patch(options: &Options) -> i32 {
+    match assemble(options) {
+        Ok(()) => 0,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            1
+        }
+    }
+}
+
+fn assemble(options: &Options) -> Result<(), String> {
+    let stdin = options.asm == Path::new(STDIO);
+    let title = match (&options.title, options.asm.file_stem()) {
+        (Some(title), _) => title.clone(),
+        (None, Some(stem)) if !stdin => stem.to_string_lossy().into_owned(),
+        _ => String::new(),
+    };
+    let mut builder = Assembler::builder();
+    builder = if stdin {
+        builder.source(STDIN_PATH, read_source(STDIO)?)
+    } else {
+        builder.root(&options.asm)
+    };
+    let mut parser_options = ParserOptions::dialect(options.dialect);
+    if let Some(version) = options.asar {
+        parser_options = parser_options.for_asar(version);
+    }
+    if options.rom != Path::new(STDIO) {
+        match fs::read(&options.rom) {
+            Ok(rom) => builder = builder.base_rom(rom),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("{}: {}", options.rom.display(), e)),
+        }
+    }
+    let mut builder = builder
+        .origin(options.origin)
+        .parser_options(parser_options)
+        .aliases(options.aliases)
+        .signature_block(options.embed_manifest)
+        .title(title);
+    if let Some(jump) = options.long_branches {
+        builder = builder.long_branches(jump);
+    }
+    for directory in &options.directories {
+        builder = builder.include_directory(directory);
+    }
+    for (name, value) in &options.defines {
+        builder = builder.define(name.as_str(), value.as_str());
+    }
+    for &(category, level) in &options.levels {
+        builder = builder.warning_level(category, level);
+    }
+    if let Some(budget) = options.stack_budget {
+        builder = builder.stack_budget(budget, options.call_depth);
+    }
+    if let Some((ref handler, ref main)) = options.interrupt_entries {
+        builder = builder.interrupt_entries(handler.as_str(), main.as_str());
+    }
+    let mut assembler = builder.build();
+    assembler.load().map_err(|e| e.to_string())?;
+    let output = assembler.assemble().map_err(|error| {
+        let diagnostics: Vec<_> = assembler
+            .project()
+            .diagnostics(&error)
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect();
+        diagnostics.join("\nerror: ")
+    })?;
+    for warning in assembler.project().warnings(&output.warnings) {
+        eprintln!("warning: {}", warning);
+    }
+    let path = options.output.as_ref().unwrap_or(&options.rom);
+    let stdout = path == Path::new(STDIO);
+    for message in &output.messages {
+        if stdout {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+    let rom = assembler.write(&output).map_err(|error| match error {
+        // Undefined labels are reported at statements using them.
+        sfc::Error::Unresolved(_) => {
+            let diagnostics: Vec<_> = assembler
+                .project()
+                .unresolved(&output.fixups)
+                .iter()
+                .map(Diagnostic::to_string)
+                .collect();
+            diagnostics.join("\nerror: ")
+        }
+        error => error.to_string(),
+    })?;
+    write(path, &rom)?;
+    if let Some(ref path) = options.symbols {
+        write(path, debugger::bsnes_plus(&output.symbols, &[]))?;
+    }
+    if let Some(ref path) = options.listing {
+        write(path, debugger::listing(&output))?;
+    }
+    if let Some(ref path) = options.manifest {
+        let manifest = assembler
+            .manifest(&output, &rom)
+            .map_err(|e| e.to_string())?;
+        write(path, manifest.to_json())?;
+    }
+    Ok(())
+}
+
+/// Writes a file, or standard output for `-`.
+fn write<C: AsRef<[u8]>>(path: &Path, contents: C) -> Result<(), String> {
+    if path == Path::new(STDIO) {
+        let mut stdout = io::stdout();
+        stdout
+            .write_all(contents.as_ref())
+            .and_then(|()| stdout.flush())
+            .map_err(|e| format!("standard output: {}", e))
+    } else {
+        fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Formats files, or standard input to standard output for `-`.
+fn fmt(args: &[String]) -> i32 {
+    let check = args[0] == "--check";
+    let paths = if check { &args[1..] } else { args };
+    if paths.is_empty() {
+        eprintln!("error: no files to format\n\n{}", USAGE);
+        return 2;
+    }
+    let mut code = 0;
+    for path in paths {
+        let result = read_source(path).and_then(|source| {
+            let formatted = format::format(&*source, &FormatOptions::default())
+                .map_err(|e| format!("{}: {}", path, e))?;
+            Ok((source != formatted, formatted))
+        });
+        match result {
+            Ok((true, _)) if check => {
+                println!("{}", path);
+                code = 1;
+            }
+            Ok((changed, formatted)) => {
+                if !check && (changed || path == STDIO) {
+                    if let Err(message) = write(Path::new(path), formatted) {
+                        eprintln!("error: {}", message);
+                        code = 1;
+                    }
+                }
+            }
+            Err(message) => {
+                eprintln!("error: {}", message);
+                code = 1;
+            }
+        }
+    }
+    code
+}
+
+/// Reads a file, or standard input for `-`.
+fn read_source(path: &str) -> Result<String, String> {
+    if path == STDIO {
+        let mut source = String::new();
+        io::stdin()
+            .read_to_string(&mut source)
+            .map_err(|e| format!("standard input: {}", e))?;
+        Ok(source)
+    } else {
+        fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+#[cfg(feature = "lsp")]
+fn lsp() -> i32 {
+    let stdin = io::stdin();
+    match Server::new(Disk).run(stdin.lock(), io::stdout()) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+fn verify(rom_path: &str, manifest_path: Option<&str>) -> i32 {
+    let result = fs::read(rom_path)
+        .map_err(|e| format!("{}: {}", rom_path, e))
+        .and_then(|rom| {
+            let manifest = match manifest_path {
+                Some(path) => {
+                    let json = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+                    Manifest::from_json(&json).map_err(|e| format!("{}: {}", path, e))?
+                }
+                None => Manifest::find_block(&rom).map_err(|e| format!("{}: {}", rom_path, e))?,
+            };
+            manifest.verify(&rom, &Sha256).map_err(|e| e.to_string())
+        });
+    match result {
+        Ok(true) => {
+            println!("{}: OK", rom_path);
+            0
+        }
+        Ok(false) => {
+            println!("{}: digest mismatch", rom_path);
+            1
+        }
+        Err(message) => {
+            eprintln!("error: {}", message);
+            2
+        }
+    }
+}
+
+/// Assembles a project into a new ROM image, as configured by its
+/// manifest.
+fn build(directory: &str) -> i32 {
+    let directory = Path::new(directory);
+    let result = Settings::read(directory)
+        .map_err(|e| format!("{}: {}", directory.join(MANIFEST).display(), e))
+        .and_then(|settings| {
+            let mut options = Options::parse(&[settings.main.to_string_lossy().into_owned()])?;
+            options.rom = PathBuf::from(STDIO);
+            options.title = Some(settings.name);
+            if let Some(parent) = settings.output.parent() {
+                fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+            }
+            options.output = Some(settings.output);
+            assemble(&options)
+        });
+    match result {
+        Ok(()) => 0,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            1
+        }
+    }
+}
+
+fn init(directory: &str, mapper: &str) -> i32 {
+    let path = Path::new(directory);
+    // Names of existing directories, like `.`, come from their full paths.
+    let absolute = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = match absolute.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => {
+            eprintln!("error: {}: invalid project directory", directory);
+            return 2;
+        }
+    };
+    let mapper = match Mapper::from_directive(mapper) {
+        Some(mapper) => mapper,
+        None => {
+            eprintln!("error: unknown mapper {}", mapper);
+            return 2;
+        }
+    };
+    let result = Template::new(name, mapper).and_then(|template| {
+        template.write(path)?;
+        Ok(template)
+    });
+    match result {
+        Ok(template) => {
+            println!("created {} project in {}", mapper.directive(), directory);
+            println!("initial build written to {}", template.output());
+            0
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            2
+        }
+    }
+}
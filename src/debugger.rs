@@ -0,0 +1,189 @@
+//! Export of symbols for debuggers.
+//!
+//! Emulators with debuggers can show names of labels in disassembly and
+//! let a user set breakpoints on them, once given a symbol file. Some
+//! also support stepping through source code, given a source map.
+
+use assembler::output::{Mapping, Output};
+use include::Source;
+use parser::ast::Span;
+use symbols::Symbol;
+
+/// Largest SNES address.
+const MAX_ADDRESS: i64 = 0xFF_FFFF;
+
+/// Writes symbols and comments in the `.sym` format of bsnes-plus.
+///
+/// A file starts with a signature, which is followed by a `[SYMBOL]`
+/// section listing labels and assignments, and a `[COMMENT]` section
+/// listing comments shown next to instructions at given addresses.
+/// Symbols with values outside of SNES address space are skipped.
+/// Comments are written in quotes, so quotes within them are replaced
+/// with apostrophes, and line endings with spaces.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::debugger;
+/// use mvp::symbols::{Symbol, SymbolKind};
+///
+/// let symbols = [Symbol { scope: None, name: "Main", value: 0x808000, kind: SymbolKind::Label }];
+/// let file = debugger::bsnes_plus(&symbols, &[(0x808000, "entry point")]);
+/// assert_eq!(
+///     file,
+///     "#SNES65816\n\n[SYMBOL]\n80:8000 Main ANY 1\n\n[COMMENT]\n80:8000 \"entry point\"\n",
+/// );
+/// ```
+pub fn bsnes_plus(symbols: &[Symbol], comments: &[(u32, &str)]) -> String {
+    let mut file = String::from("#SNES65816\n\n[SYMBOL]\n");
+    for symbol in symbols {
+        if (0..=MAX_ADDRESS).contains(&symbol.value) {
+            file += &format!("{} {} ANY 1\n", address(symbol.value as u32), symbol);
+        }
+    }
+    file += "\n[COMMENT]\n";
+    for &(value, comment) in comments {
+        let comment: String = comment
+            .chars()
+            .map(|c| match c {
+                '"' => '\'',
+                '\r' | '\n' => ' ',
+                c => c,
+            })
+            .collect();
+        file += &format!("{} \"{}\"\n", address(value), comment.trim());
+    }
+    file
+}
+
+/// Writes a source map, which maps ranges of addresses to source code.
+///
+/// A source map is a JSON object, with paths of source files in `files`
+/// and ranges of addresses in `mappings`, ordered by their addresses:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "files": ["main.asm", "macros.asm"],
+///   "mappings": [
+///     {
+///       "start": 8421376,
+///       "end": 8421378,
+///       "file": 1,
+///       "line": 3,
+///       "column": 5,
+///       "expansion": [{ "file": 0, "line": 10, "column": 1 }]
+///     }
+///   ]
+/// }
+/// ```
+///
+/// `end` is exclusive, `file` is an index in `files`, and lines and
+/// columns are counted from one. `expansion` lists macro calls which
+/// expanded into the code, innermost first. Locations of spans which
+/// don't come from any of `sources` are `null`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::flags::State;
+/// use mvp::assembler::output;
+/// use mvp::debugger;
+/// use mvp::include::Source;
+/// use mvp::parser::grammar::parse_program;
+///
+/// let sources = [Source { path: "main.asm".into(), contents: "NOP\n  RTL\n".to_string() }];
+/// let statements = parse_program(sources[0].contents.as_str()).unwrap();
+/// let output = output::assemble(statements, 0x808000, State::new(true, true)).unwrap();
+/// let map = debugger::source_map(&output.mappings, &sources);
+/// assert!(map.starts_with(r#"{"files":["main.asm"],"mappings":[{"#));
+/// assert!(map.contains(r#""column":3,"end":8421378,"expansion":[],"file":0,"line":2"#));
+/// ```
+pub fn source_map(mappings: &[Mapping], sources: &[Source]) -> String {
+    let files: Vec<_> = sources
+        .iter()
+        .map(|source| source.path.to_string_lossy())
+        .collect();
+    let mappings: Vec<_> = mappings
+        .iter()
+        .map(|mapping| {
+            let position = location(mapping.span, sources);
+            let expansion: Vec<_> = mapping
+                .expansion
+                .iter()
+                .map(|&span| {
+                    location(span, sources).map(|(file, line, column)| {
+                        json!({ "file": file, "line": line, "column": column })
+                    })
+                })
+                .collect();
+            json!({
+                "start": mapping.addresses.start,
+                "end": mapping.addresses.end,
+                "file": position.map(|(file, _, _)| file),
+                "line": position.map(|(_, line, _)| line),
+                "column": position.map(|(_, _, column)| column),
+                "expansion": expansion,
+            })
+        })
+        .collect();
+    json!({
+        "version": 1,
+        "files": files,
+        "mappings": mappings,
+    })
+    .to_string()
+}
+
+/// Writes a listing of assembled code, with a line for every statement
+/// which produced code.
+///
+/// A line has an address, up to 4 assembled bytes and source code of
+/// a statement, with further bytes on following lines.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::flags::State;
+/// use mvp::assembler::output;
+/// use mvp::debugger;
+/// use mvp::parser::grammar::parse_program;
+///
+/// let statements = parse_program("Main:\n  LDA #$12\n  RTS\n").unwrap();
+/// let output = output::assemble(statements, 0x808000, State::new(true, true)).unwrap();
+/// assert_eq!(
+///     debugger::listing(&output),
+///     "80:8000 A9 12        LDA #$12\n80:8002 60           RTS\n",
+/// );
+/// ```
+pub fn listing(output: &Output) -> String {
+    let mut listing = String::new();
+    for mapping in &output.mappings {
+        let start = mapping.addresses.start.wrapping_sub(output.origin) as usize;
+        let end = mapping.addresses.end.wrapping_sub(output.origin) as usize;
+        let bytes = output.data.get(start..end).unwrap_or_default();
+        let mut source = mapping.span.fragment();
+        for (i, chunk) in bytes.chunks(4).enumerate() {
+            let hex: Vec<_> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let at = mapping.addresses.start.wrapping_add(4 * i as u32);
+            let line = format!("{} {:12} {}", address(at), hex.join(" "), source);
+            listing += line.trim_end();
+            listing += "\n";
+            source = "";
+        }
+    }
+    listing
+}
+
+/// Finds an index of a file, a line and a column where a span starts.
+fn location(span: Span, sources: &[Source]) -> Option<(usize, usize, usize)> {
+    sources.iter().enumerate().find_map(|(file, source)| {
+        let (line, column) = span.line_column(&source.contents)?;
+        Some((file, line, column))
+    })
+}
+
+/// Formats an address as a bank and an offset within it.
+fn address(value: u32) -> String {
+    format!("{:02X}:{:04X}", value >> 16 & 0xFF, value & 0xFFFF)
+}
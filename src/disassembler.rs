@@ -0,0 +1,276 @@
+//! Disassembly of 65816 code, and verification of round trips.
+//!
+//! `disassemble` decodes instructions into text the assembler accepts,
+//! with sizes of operands written out, so that assembling the text again
+//! gives the same bytes. Sizes of immediate operands follow `REP` and
+//! `SEP`, like in `assembler::flags`. Code run with other widths than
+//! tracked decodes into other instructions, which still assemble into the
+//! same bytes.
+//!
+//! `verify_roundtrip` assembles code, disassembles it and assembles the
+//! result again, checking that hand-written code doesn't rely on anything
+//! that isn't in its bytes.
+
+use std::error;
+use std::fmt;
+
+use assembler::flags::State;
+use assembler::output;
+use encoder::{self, AddressingMode};
+use parser::grammar::{self, SyntaxError};
+
+/// A decoded instruction.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Instruction {
+    /// Address of an opcode.
+    pub address: u32,
+    pub name: &'static str,
+    pub mode: AddressingMode,
+    /// Operand bytes as a little-endian number, so banks of block moves
+    /// are in reverse order.
+    pub operand: u32,
+    /// Size in bytes, including an opcode.
+    pub size: usize,
+}
+
+impl Instruction {
+    /// Address a branch goes to, or `None` for other instructions.
+    pub fn target(&self) -> Option<u32> {
+        let offset = match self.mode {
+            AddressingMode::Relative => i32::from(self.operand as u8 as i8),
+            AddressingMode::RelativeLong => i32::from(self.operand as u16 as i16),
+            _ => return None,
+        };
+        let next = self.address.wrapping_add(self.size as u32);
+        let address = (next as i32).wrapping_add(offset) as u32;
+        Some(self.address & 0xFF_0000 | address & 0xFFFF)
+    }
+}
+
+/// Writes an instruction in assembler syntax.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use encoder::AddressingMode::*;
+        let digits = 2 * (self.size - 1);
+        let (before, after) = match self.mode {
+            Implied => return f.write_str(self.name),
+            Accumulator => return write!(f, "{} A", self.name),
+            Immediate => {
+                let suffix = if self.size == 2 { 'b' } else { 'w' };
+                return write!(
+                    f,
+                    "{}.{} #${:0digits$X}",
+                    self.name,
+                    suffix,
+                    self.operand,
+                    digits = digits
+                );
+            }
+            Relative | RelativeLong => {
+                return write!(f, "{} ${:06X}", self.name, self.target().unwrap());
+            }
+            BlockMove => {
+                let (source, destination) = (self.operand >> 8, self.operand & 0xFF);
+                return write!(f, "{} ${:02X},${:02X}", self.name, source, destination);
+            }
+            DirectPage | Absolute | AbsoluteLong => ("", ""),
+            DpIndexedX | AbsoluteIndexedX | AbsoluteLongIndexedX => ("", ",x"),
+            DpIndexedY | AbsoluteIndexedY => ("", ",y"),
+            DpIndirect | AbsoluteIndirect => ("(", ")"),
+            DpIndexedIndirectX | AbsoluteIndexedIndirect => ("(", ",x)"),
+            DpIndirectIndexedIndexY => ("(", "),y"),
+            DpIndirectLong | AbsoluteIndirectLong => ("[", "]"),
+            DpIndirectLongIndexedY => ("[", "],y"),
+            StackRelative => ("", ",s"),
+            SrIndirectIndexedY => ("(", ",s),y"),
+        };
+        // Digits of an operand select its size.
+        write!(
+            f,
+            "{} {}${:0digits$X}{}",
+            self.name,
+            before,
+            self.operand,
+            after,
+            digits = digits
+        )
+    }
+}
+
+/// Decodes code starting at `origin`, with register widths at the start
+/// given by `state`. Unknown widths are assumed to be 8-bit.
+///
+/// Decoding stops at an instruction cut off by the end of code.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::flags::State;
+/// use mvp::disassembler;
+///
+/// let code = [0xC2, 0x20, 0xA9, 0x34, 0x12, 0xBD, 0x34, 0x12, 0x80, 0xF6];
+/// let instructions = disassembler::disassemble(&code, 0x808000, State::new(true, true));
+/// let text: Vec<_> = instructions.iter().map(ToString::to_string).collect();
+/// assert_eq!(text, ["REP.b #$20", "LDA.w #$1234", "LDA $1234,x", "BRA $808000"]);
+/// ```
+pub fn disassemble(code: &[u8], origin: u32, mut state: State) -> Vec<Instruction> {
+    let matrix = encoder::opcode_matrix();
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while let Some(&opcode) = code.get(offset) {
+        let (name, mode) = matrix[usize::from(opcode >> 4)][usize::from(opcode & 0xF)];
+        let m_flag = state.m_flag.unwrap_or(true);
+        let x_flag = state.x_flag.unwrap_or(true);
+        let size = encoder::instruction_size(name, mode, m_flag, x_flag).unwrap();
+        let operand = match code.get(offset + 1..offset + size) {
+            Some(bytes) => bytes
+                .iter()
+                .rev()
+                .fold(0, |operand, &byte| operand << 8 | u32::from(byte)),
+            None => break,
+        };
+        state = match name {
+            "REP" | "SEP" => {
+                let set = Some(name == "SEP");
+                State {
+                    m_flag: if operand & 0x20 != 0 {
+                        set
+                    } else {
+                        state.m_flag
+                    },
+                    x_flag: if operand & 0x10 != 0 {
+                        set
+                    } else {
+                        state.x_flag
+                    },
+                }
+            }
+            "PLP" | "RTI" | "XCE" => State::default(),
+            _ => state,
+        };
+        instructions.push(Instruction {
+            address: origin.wrapping_add(offset as u32),
+            name,
+            mode,
+            operand,
+            size,
+        });
+        offset += size;
+    }
+    instructions
+}
+
+/// A failed round trip.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error<'a> {
+    Syntax(SyntaxError<'a>),
+    Assemble(output::Error<'a>),
+    /// An instruction at an address is cut off by the end of code.
+    Truncated(u32),
+    /// Disassembled code doesn't assemble, with a message of an error.
+    Reassemble(String),
+    /// Assembling a disassembled instruction gives other bytes.
+    Mismatch {
+        address: u32,
+        instruction: String,
+    },
+}
+
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Syntax(ref error) => write!(f, "{}", error),
+            Error::Assemble(ref error) => write!(f, "{}", error),
+            Error::Truncated(address) => {
+                write!(f, "instruction at ${:06X} is cut off", address)
+            }
+            Error::Reassemble(ref message) => {
+                write!(f, "disassembled code doesn't assemble: {}", message)
+            }
+            Error::Mismatch {
+                address,
+                ref instruction,
+            } => write!(
+                f,
+                "`{}` at ${:06X} assembles into other bytes",
+                instruction, address
+            ),
+        }
+    }
+}
+
+impl<'a> error::Error for Error<'a> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Assemble(ref error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+/// Checks that code assembled at $8000 with 8-bit registers assembles
+/// into the same bytes after disassembly, see `verify_roundtrip_at`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::disassembler::{self, Error};
+///
+/// assert_eq!(disassembler::verify_roundtrip("Main:\nREP #$20\nLDA #$1234\nBRA Main\n"), Ok(()));
+/// assert_eq!(disassembler::verify_roundtrip("LDA #$12\nRTS\n"), Ok(()));
+/// assert_eq!(
+///     disassembler::verify_roundtrip("REP #$20\nLDA.b #$12\n"),
+///     Err(Error::Truncated(0x8002)),
+/// );
+/// ```
+pub fn verify_roundtrip(source: &str) -> Result<(), Error<'_>> {
+    verify_roundtrip_at(source, 0x8000, State::new(true, true))
+}
+
+/// Assembles code, disassembles it and assembles the result again,
+/// checking that bytes stay the same.
+pub fn verify_roundtrip_at(source: &str, origin: u32, state: State) -> Result<(), Error<'_>> {
+    let statements = grammar::parse_program(source).map_err(Error::Syntax)?;
+    let code = output::assemble(statements, origin, state)
+        .map_err(Error::Assemble)?
+        .data;
+    let instructions = disassemble(&code, origin, state);
+    let decoded: usize = instructions
+        .iter()
+        .map(|instruction| instruction.size)
+        .sum();
+    if decoded < code.len() {
+        return Err(Error::Truncated(origin.wrapping_add(decoded as u32)));
+    }
+    let text: String = instructions
+        .iter()
+        .map(|instruction| format!("{}\n", instruction))
+        .collect();
+    let reassembled = grammar::parse_program(&*text)
+        .map_err(|error| Error::Reassemble(error.to_string()))
+        .and_then(|statements| {
+            output::assemble(statements, origin, state).map_err(|error| {
+                Error::Reassemble(format!("`{}`: {}", error.span().fragment(), error))
+            })
+        })?
+        .data;
+    let mut offset = 0;
+    for instruction in &instructions {
+        let range = offset..offset + instruction.size;
+        if reassembled.get(range.clone()) != Some(&code[range]) {
+            return Err(Error::Mismatch {
+                address: instruction.address,
+                instruction: instruction.to_string(),
+            });
+        }
+        offset += instruction.size;
+    }
+    if reassembled.len() != code.len() {
+        return Err(Error::Reassemble(format!(
+            "{} bytes instead of {}",
+            reassembled.len(),
+            code.len()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,325 @@
+//! Timing and flag effects of instructions.
+//!
+//! Cycle counts follow the WDC 65C816 datasheet. An instruction has a base
+//! count, for 8-bit registers with a direct page aligned to a page, and
+//! penalties which apply depending on processor state and on the operand.
+
+use std::ops::BitOr;
+
+use super::AddressingMode::{self, *};
+use super::{lookup, resolve_alias, Aliases};
+use parser::ast::MNEMONICS;
+
+/// A set of processor status flags, with bits as in the `P` register.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Flags(pub u8);
+
+impl Flags {
+    pub const NONE: Flags = Flags(0);
+    pub const CARRY: Flags = Flags(0x01);
+    pub const ZERO: Flags = Flags(0x02);
+    pub const IRQ_DISABLE: Flags = Flags(0x04);
+    pub const DECIMAL: Flags = Flags(0x08);
+    /// Index register width, `x`.
+    pub const INDEX: Flags = Flags(0x10);
+    /// Accumulator and memory width, `m`.
+    pub const MEMORY: Flags = Flags(0x20);
+    pub const OVERFLOW: Flags = Flags(0x40);
+    pub const NEGATIVE: Flags = Flags(0x80);
+    pub const ALL: Flags = Flags(0xFF);
+
+    /// Checks whether all flags of `other` are in a set.
+    pub fn contains(self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Flags;
+
+    fn bitor(self, other: Flags) -> Flags {
+        Flags(self.0 | other.0)
+    }
+}
+
+/// Processor state and operand properties affecting cycle counts.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Conditions {
+    /// 8-bit accumulator and memory.
+    pub m_flag: bool,
+    /// 8-bit index registers.
+    pub x_flag: bool,
+    /// 6502 emulation mode.
+    pub emulation: bool,
+    /// Low byte of the direct page register isn't zero.
+    pub unaligned_direct_page: bool,
+    /// Indexing, or a taken branch in emulation mode, crosses a page.
+    pub page_crossed: bool,
+    /// A conditional branch is taken.
+    pub branch_taken: bool,
+}
+
+/// Cycle count of an instruction.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Cycles {
+    /// Cycles with 8-bit registers, an aligned direct page and no page
+    /// crossing. For block moves, this is per moved byte.
+    pub base: u8,
+    /// Extra cycles with a 16-bit accumulator and memory.
+    pub wide_memory: u8,
+    /// Extra cycles with 16-bit index registers.
+    pub wide_index: u8,
+    /// One more cycle with an unaligned direct page.
+    pub direct_page: bool,
+    /// One more cycle when indexing crosses a page, which is always the
+    /// case with 16-bit index registers.
+    pub page_cross: bool,
+    /// One more cycle when a branch is taken, and another one when it
+    /// crosses a page in emulation mode.
+    pub branch: bool,
+    /// One more cycle in native mode, for pushing or pulling the program
+    /// bank.
+    pub native: bool,
+}
+
+impl Cycles {
+    fn new(base: u8) -> Cycles {
+        Cycles {
+            base,
+            ..Cycles::default()
+        }
+    }
+
+    /// Counts cycles taken in given conditions.
+    pub fn count(&self, conditions: &Conditions) -> u8 {
+        let mut cycles = self.base;
+        if !conditions.m_flag {
+            cycles += self.wide_memory;
+        }
+        if !conditions.x_flag {
+            cycles += self.wide_index;
+        }
+        if self.direct_page && conditions.unaligned_direct_page {
+            cycles += 1;
+        }
+        if self.page_cross && (conditions.page_crossed || !conditions.x_flag) {
+            cycles += 1;
+        }
+        if self.branch && conditions.branch_taken {
+            cycles += 1;
+            if conditions.emulation && conditions.page_crossed {
+                cycles += 1;
+            }
+        }
+        if self.native && !conditions.emulation {
+            cycles += 1;
+        }
+        cycles
+    }
+}
+
+/// Description of an instruction.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct InstructionInfo {
+    pub name: &'static str,
+    pub mode: AddressingMode,
+    pub opcode: u8,
+    pub cycles: Cycles,
+    /// Flags an instruction may change.
+    pub flags: Flags,
+}
+
+/// Instructions operating on the accumulator and memory, with most
+/// addressing modes.
+const ALU: &[&str] = &["ADC", "AND", "CMP", "EOR", "LDA", "ORA", "SBC", "STA"];
+
+/// Read-modify-write instructions.
+const READ_MODIFY_WRITE: &[&str] = &["ASL", "DEC", "INC", "LSR", "ROL", "ROR", "TRB", "TSB"];
+
+/// Describes an instruction, or returns `None` when there is no such
+/// instruction.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{instruction_info, AddressingMode, Conditions, Flags};
+///
+/// let info = instruction_info("LDA", AddressingMode::AbsoluteIndexedX).unwrap();
+/// assert_eq!(info.opcode, 0xBD);
+/// assert_eq!(info.flags, Flags::NEGATIVE | Flags::ZERO);
+/// let conditions = Conditions { m_flag: true, x_flag: true, ..Conditions::default() };
+/// assert_eq!(info.cycles.count(&conditions), 4);
+/// let conditions = Conditions { page_crossed: true, ..conditions };
+/// assert_eq!(info.cycles.count(&conditions), 5);
+/// ```
+pub fn instruction_info(name: &str, mode: AddressingMode) -> Option<InstructionInfo> {
+    let (name, mode) = resolve_alias(name, mode, Aliases::Accept).ok()?;
+    let opcode = lookup(name, mode).ok()?;
+    let name = MNEMONICS[MNEMONICS.binary_search(&name).ok()?];
+    Some(InstructionInfo {
+        name,
+        mode,
+        opcode,
+        cycles: cycles(name, mode),
+        flags: flags(name, mode),
+    })
+}
+
+fn cycles(name: &str, mode: AddressingMode) -> Cycles {
+    let direct = matches!(
+        mode,
+        DirectPage
+            | DpIndexedX
+            | DpIndexedY
+            | DpIndirect
+            | DpIndexedIndirectX
+            | DpIndirectIndexedIndexY
+            | DpIndirectLong
+            | DpIndirectLongIndexedY
+    );
+    let mut cycles = if ALU.contains(&name) || name == "BIT" {
+        alu_cycles(name, mode)
+    } else if READ_MODIFY_WRITE.contains(&name) {
+        read_modify_write_cycles(mode)
+    } else if let Some(cycles) = index_cycles(name, mode) {
+        cycles
+    } else {
+        other_cycles(name, mode)
+    };
+    cycles.direct_page = direct;
+    cycles
+}
+
+fn alu_cycles(name: &str, mode: AddressingMode) -> Cycles {
+    let store = name == "STA";
+    let (base, page_cross) = match mode {
+        Immediate => (2, false),
+        DirectPage => (3, false),
+        StackRelative => (4, false),
+        Absolute | DpIndexedX => (4, false),
+        AbsoluteLong | DpIndirect => (5, false),
+        AbsoluteLongIndexedX => (5, false),
+        AbsoluteIndexedX | AbsoluteIndexedY if store => (5, false),
+        AbsoluteIndexedX | AbsoluteIndexedY => (4, true),
+        DpIndirectIndexedIndexY if store => (6, false),
+        DpIndirectIndexedIndexY => (5, true),
+        DpIndexedIndirectX | DpIndirectLong | DpIndirectLongIndexedY => (6, false),
+        SrIndirectIndexedY => (7, false),
+        _ => unreachable!("{} {:?}", name, mode),
+    };
+    Cycles {
+        wide_memory: 1,
+        page_cross,
+        ..Cycles::new(base)
+    }
+}
+
+fn read_modify_write_cycles(mode: AddressingMode) -> Cycles {
+    let base = match mode {
+        Accumulator => return Cycles::new(2),
+        DirectPage => 5,
+        Absolute | DpIndexedX => 6,
+        AbsoluteIndexedX => 7,
+        _ => unreachable!("{:?}", mode),
+    };
+    Cycles {
+        wide_memory: 2,
+        ..Cycles::new(base)
+    }
+}
+
+/// Cycles of instructions whose operand is as wide as index registers,
+/// or of stores of zero, which behave like them.
+fn index_cycles(name: &str, mode: AddressingMode) -> Option<Cycles> {
+    let wide = match name {
+        "STZ" => Cycles {
+            wide_memory: 1,
+            ..Cycles::default()
+        },
+        "CPX" | "CPY" | "LDX" | "LDY" | "STX" | "STY" => Cycles {
+            wide_index: 1,
+            ..Cycles::default()
+        },
+        _ => return None,
+    };
+    let (base, page_cross) = match mode {
+        Immediate => (2, false),
+        DirectPage => (3, false),
+        Absolute | DpIndexedX | DpIndexedY => (4, false),
+        AbsoluteIndexedX if name == "STZ" => (5, false),
+        AbsoluteIndexedX | AbsoluteIndexedY => (4, true),
+        _ => unreachable!("{} {:?}", name, mode),
+    };
+    Some(Cycles {
+        base,
+        page_cross,
+        ..wide
+    })
+}
+
+fn other_cycles(name: &str, mode: AddressingMode) -> Cycles {
+    let base = match (name, mode) {
+        ("BRA", _) => 3,
+        (_, Relative) => {
+            return Cycles {
+                branch: true,
+                ..Cycles::new(2)
+            };
+        }
+        ("RTI", _) | ("BRK", _) | ("COP", _) => {
+            return Cycles {
+                native: true,
+                ..Cycles::new(if name == "RTI" { 6 } else { 7 })
+            };
+        }
+        ("PHA", _) | ("PLA", _) | ("PHX", _) | ("PHY", _) | ("PLX", _) | ("PLY", _) => {
+            let wide = if name.ends_with('A') {
+                Cycles {
+                    wide_memory: 1,
+                    ..Cycles::default()
+                }
+            } else {
+                Cycles {
+                    wide_index: 1,
+                    ..Cycles::default()
+                }
+            };
+            let base = if name.starts_with("PH") { 3 } else { 4 };
+            return Cycles { base, ..wide };
+        }
+        ("BRL", _) | ("JML", AbsoluteLong) => 4,
+        ("JMP", Absolute) => 3,
+        ("JMP", AbsoluteIndirect) | ("PEA", _) | ("PLD", _) => 5,
+        ("JMP", AbsoluteIndexedIndirect) | ("JML", AbsoluteIndirectLong) => 6,
+        ("JSR", Absolute) | ("RTS", _) | ("RTL", _) | ("PER", _) | ("PEI", _) => 6,
+        ("JSR", AbsoluteIndexedIndirect) | ("JSL", _) => 8,
+        ("MVN", _) | ("MVP", _) => 7,
+        ("PHD", _) | ("PLB", _) | ("PLP", _) => 4,
+        ("PHB", _) | ("PHK", _) | ("PHP", _) | ("REP", _) | ("SEP", _) => 3,
+        ("XBA", _) | ("STP", _) | ("WAI", _) => 3,
+        _ => 2,
+    };
+    Cycles::new(base)
+}
+
+fn flags(name: &str, mode: AddressingMode) -> Flags {
+    let nz = Flags::NEGATIVE | Flags::ZERO;
+    match name {
+        "ADC" | "SBC" => nz | Flags::OVERFLOW | Flags::CARRY,
+        "BIT" if mode == Immediate => Flags::ZERO,
+        "BIT" => nz | Flags::OVERFLOW,
+        "CMP" | "CPX" | "CPY" | "ASL" | "LSR" | "ROL" | "ROR" => nz | Flags::CARRY,
+        "TRB" | "TSB" => Flags::ZERO,
+        "CLC" | "SEC" => Flags::CARRY,
+        "CLI" | "SEI" => Flags::IRQ_DISABLE,
+        "CLD" | "SED" => Flags::DECIMAL,
+        "CLV" => Flags::OVERFLOW,
+        "PLP" | "RTI" | "REP" | "SEP" => Flags::ALL,
+        "XCE" => Flags::CARRY | Flags::MEMORY | Flags::INDEX,
+        "BRK" | "COP" => Flags::DECIMAL | Flags::IRQ_DISABLE,
+        "AND" | "EOR" | "ORA" | "LDA" | "LDX" | "LDY" | "PLA" | "PLX" | "PLY" | "PLB" | "PLD"
+        | "DEC" | "DEX" | "DEY" | "INC" | "INX" | "INY" | "TAX" | "TAY" | "TCD" | "TDC" | "TSC"
+        | "TSX" | "TXA" | "TXY" | "TYA" | "TYX" | "XBA" => nz,
+        _ => Flags::NONE,
+    }
+}
@@ -0,0 +1,888 @@
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AddressingMode {
+    Implied,
+    DirectPage,              // dp
+    Absolute,                // addr
+    AbsoluteLong,            // long
+    Immediate,               // #const
+    DpIndexedX,              // dp,x
+    AbsoluteIndexedX,        // addr,x
+    AbsoluteIndexedY,        // addr,y
+    AbsoluteLongIndexedX,    // long,x
+    DpIndirect,              // (dp)
+    DpIndexedIndirectX,      // (dp,x)
+    DpIndirectIndexedIndexY, // (dp),y
+    DpIndirectLong,          // [dp]
+    DpIndirectLongIndexedY,  // [dp],y
+    StackRelative,           // sr,s
+    SrIndirectIndexedY,      // (sr,s),y
+    DpIndexedY,              // dp,y
+    Accumulator,             // A
+    Relative,                // nearlabel
+    RelativeLong,            // label
+    BlockMove,               // srcbk,destbk
+    AbsoluteIndirect,        // (addr)
+    AbsoluteIndexedIndirect, // (addr,x)
+    AbsoluteIndirectLong,    // [addr]
+}
+
+impl AddressingMode {
+    /// Every addressing mode, in declaration order.
+    pub const ALL: &'static [AddressingMode] = &[
+        Implied,
+        DirectPage,
+        Absolute,
+        AbsoluteLong,
+        Immediate,
+        DpIndexedX,
+        AbsoluteIndexedX,
+        AbsoluteIndexedY,
+        AbsoluteLongIndexedX,
+        DpIndirect,
+        DpIndexedIndirectX,
+        DpIndirectIndexedIndexY,
+        DpIndirectLong,
+        DpIndirectLongIndexedY,
+        StackRelative,
+        SrIndirectIndexedY,
+        DpIndexedY,
+        Accumulator,
+        Relative,
+        RelativeLong,
+        BlockMove,
+        AbsoluteIndirect,
+        AbsoluteIndexedIndirect,
+        AbsoluteIndirectLong,
+    ];
+
+    /// Size of an operand in bytes, or `None` for immediate operands,
+    /// whose size depends on an instruction and processor flags.
+    pub fn operand_size(self) -> Option<u32> {
+        Some(match self {
+            Implied | Accumulator => 0,
+            DirectPage
+            | DpIndexedX
+            | DpIndexedY
+            | DpIndirect
+            | DpIndexedIndirectX
+            | DpIndirectIndexedIndexY
+            | DpIndirectLong
+            | DpIndirectLongIndexedY
+            | StackRelative
+            | SrIndirectIndexedY
+            | Relative => 1,
+            Absolute
+            | AbsoluteIndexedX
+            | AbsoluteIndexedY
+            | AbsoluteIndirect
+            | AbsoluteIndexedIndirect
+            | AbsoluteIndirectLong
+            | RelativeLong
+            | BlockMove => 2,
+            AbsoluteLong | AbsoluteLongIndexedX => 3,
+            Immediate => return None,
+        })
+    }
+
+    /// Syntax of an addressing mode, as used in documentation.
+    pub fn syntax(self) -> &'static str {
+        match self {
+            Implied => "",
+            DirectPage => "dp",
+            Absolute => "addr",
+            AbsoluteLong => "long",
+            Immediate => "#const",
+            DpIndexedX => "dp,x",
+            AbsoluteIndexedX => "addr,x",
+            AbsoluteIndexedY => "addr,y",
+            AbsoluteLongIndexedX => "long,x",
+            DpIndirect => "(dp)",
+            DpIndexedIndirectX => "(dp,x)",
+            DpIndirectIndexedIndexY => "(dp),y",
+            DpIndirectLong => "[dp]",
+            DpIndirectLongIndexedY => "[dp],y",
+            StackRelative => "sr,s",
+            SrIndirectIndexedY => "(sr,s),y",
+            DpIndexedY => "dp,y",
+            Accumulator => "A",
+            Relative => "nearlabel",
+            RelativeLong => "label",
+            BlockMove => "srcbk,destbk",
+            AbsoluteIndirect => "(addr)",
+            AbsoluteIndexedIndirect => "(addr,x)",
+            AbsoluteIndirectLong => "[addr]",
+        }
+    }
+}
+
+mod info;
+mod mode;
+mod mos6502;
+pub mod spc700;
+pub mod superfx;
+
+/// An instruction set, selected with the `arch` directive.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Arch {
+    /// The SNES processor.
+    #[default]
+    W65816,
+    /// The 6502, with 8-bit registers and no 65816 extensions.
+    W6502,
+    /// The 65C02, a 6502 with additional instructions like `BRA` and `STZ`.
+    W65C02,
+    /// The SNES sound processor.
+    Spc700,
+    /// The Super FX coprocessor.
+    SuperFx,
+}
+
+impl Arch {
+    /// Parses an argument of the `arch` directive, ignoring case.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::encoder::Arch;
+    ///
+    /// assert_eq!(Arch::from_directive("SPC700"), Some(Arch::Spc700));
+    /// assert_eq!(Arch::from_directive("z80"), None);
+    /// ```
+    pub fn from_directive(name: &str) -> Option<Arch> {
+        Some(match &*name.to_ascii_lowercase() {
+            "65816" => Arch::W65816,
+            "6502" => Arch::W6502,
+            "65c02" => Arch::W65C02,
+            "spc700" => Arch::Spc700,
+            "superfx" => Arch::SuperFx,
+            _ => return None,
+        })
+    }
+
+    /// Argument of the `arch` directive selecting this instruction set.
+    pub fn directive(self) -> &'static str {
+        match self {
+            Arch::W65816 => "65816",
+            Arch::W6502 => "6502",
+            Arch::W65C02 => "65c02",
+            Arch::Spc700 => "spc700",
+            Arch::SuperFx => "superfx",
+        }
+    }
+}
+
+pub use self::info::{instruction_info, Conditions, Cycles, Flags, InstructionInfo};
+pub use self::mode::{resolve_mode, resolve_mode_in};
+pub use self::mos6502::lookup_in;
+use self::AddressingMode::*;
+
+use std::error;
+use std::fmt;
+
+use arrayvec::ArrayVec;
+
+use parser::ast::{normalize_mnemonic, IndexRegister, MNEMONICS};
+use serde_json::Value;
+
+/// Every 65816 instruction along with its opcode, indexed by `OPCODES`.
+///
+/// Generated by the build script from `tables/w65816.txt`.
+const INSTRUCTIONS: &[(&str, AddressingMode, u8)] =
+    include!(concat!(env!("OUT_DIR"), "/w65816.rs"));
+
+/// Opcodes by position of a mnemonic in `MNEMONICS` and an addressing
+/// mode, generated from `INSTRUCTIONS` at compile time.
+///
+/// As both indexes are known without probing, this is a perfect hash
+/// table, and a lookup costs a binary search over mnemonics.
+static OPCODES: [[Option<u8>; AddressingMode::ALL.len()]; MNEMONICS.len()] = opcode_table();
+
+const fn opcode_table() -> [[Option<u8>; AddressingMode::ALL.len()]; MNEMONICS.len()] {
+    let mut table = [[None; AddressingMode::ALL.len()]; MNEMONICS.len()];
+    let mut i = 0;
+    while i < INSTRUCTIONS.len() {
+        let (name, mode, opcode) = INSTRUCTIONS[i];
+        let mnemonic = mnemonic_index(name);
+        assert!(
+            table[mnemonic][mode as usize].is_none(),
+            "duplicate instruction"
+        );
+        table[mnemonic][mode as usize] = Some(opcode);
+        i += 1;
+    }
+    table
+}
+
+const fn mnemonic_index(name: &str) -> usize {
+    let mut i = 0;
+    while i < MNEMONICS.len() {
+        if bytes_equal(MNEMONICS[i].as_bytes(), name.as_bytes()) {
+            return i;
+        }
+        i += 1;
+    }
+    panic!("instruction with unknown mnemonic")
+}
+
+const fn bytes_equal(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Why an instruction couldn't be found.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LookupError {
+    /// No instruction has a mnemonic. A mnemonic differing by case, or
+    /// else mnemonics differing by a single letter, are suggested.
+    UnknownMnemonic(Vec<&'static str>),
+    /// An instruction has no opcode for an addressing mode. Modes it
+    /// supports are listed.
+    UnsupportedMode(&'static str, Vec<AddressingMode>),
+    /// An alias was used with `Aliases::Reject`. The instruction it stands
+    /// for is included.
+    Alias(&'static str, &'static str, AddressingMode),
+    /// An instruction of the 65816 isn't available on an older processor.
+    UnavailableInstruction(&'static str, Arch),
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LookupError::UnknownMnemonic(ref suggestions) => {
+                f.write_str("unknown instruction")?;
+                if !suggestions.is_empty() {
+                    write!(f, ", did you mean {}?", suggestions.join(" or "))?;
+                }
+                Ok(())
+            }
+            LookupError::UnsupportedMode(name, ref supported) => {
+                write!(f, "{} doesn't support this addressing mode", name)?;
+                if !supported.is_empty() {
+                    let modes: Vec<_> = supported
+                        .iter()
+                        .map(|mode| match mode.syntax() {
+                            "" => "implied",
+                            syntax => syntax,
+                        })
+                        .collect();
+                    write!(f, ", it supports {}", modes.join(", "))?;
+                }
+                Ok(())
+            }
+            LookupError::Alias(alias, name, mode) => {
+                write!(f, "{} is an alias, use {}", alias, name)?;
+                if mode == Accumulator {
+                    f.write_str(" A")?;
+                }
+                Ok(())
+            }
+            LookupError::UnavailableInstruction(name, arch) => {
+                write!(f, "{} isn't available with arch {}", name, arch.directive())
+            }
+        }
+    }
+}
+
+impl error::Error for LookupError {}
+
+/// Lists every instruction, as a mnemonic, an addressing mode and an
+/// opcode.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{self, AddressingMode};
+///
+/// assert_eq!(encoder::instructions().len(), 256);
+/// assert!(encoder::instructions().contains(&("XBA", AddressingMode::Implied, 0xEB)));
+/// ```
+pub fn instructions() -> &'static [(&'static str, AddressingMode, u8)] {
+    INSTRUCTIONS
+}
+
+/// Finds an opcode of an instruction, ignoring case of its mnemonic.
+///
+/// Errors are the same as those of `lookup`, listing mnemonics differing
+/// by a single letter, or addressing modes an instruction supports.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{get_opcode, AddressingMode, LookupError};
+///
+/// assert_eq!(get_opcode("lda", AddressingMode::Immediate), Ok(0xA9));
+/// assert_eq!(get_opcode("Dea", AddressingMode::Implied), Ok(0x3A));
+/// assert_eq!(
+///     get_opcode("ldq", AddressingMode::Immediate),
+///     Err(LookupError::UnknownMnemonic(vec!["LDA", "LDX", "LDY"])),
+/// );
+/// assert_eq!(
+///     get_opcode("pea", AddressingMode::DirectPage),
+///     Err(LookupError::UnsupportedMode("PEA", vec![AddressingMode::Absolute])),
+/// );
+/// ```
+pub fn get_opcode(name: &str, addressing_mode: AddressingMode) -> Result<u8, LookupError> {
+    lookup(&name.to_ascii_uppercase(), addressing_mode)
+}
+
+/// Lists every mnemonic in alphabetical order, without aliases.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder;
+///
+/// assert_eq!(encoder::mnemonics().len(), 92);
+/// assert_eq!(encoder::mnemonics()[0], "ADC");
+/// ```
+pub fn mnemonics() -> &'static [&'static str] {
+    MNEMONICS
+}
+
+/// Lists addressing modes supported by an instruction, ignoring case of
+/// its mnemonic, in order of `AddressingMode::ALL`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{supported_modes, AddressingMode, LookupError};
+///
+/// assert_eq!(
+///     supported_modes("jml"),
+///     Ok(vec![AddressingMode::AbsoluteLong, AddressingMode::AbsoluteIndirectLong]),
+/// );
+/// assert_eq!(supported_modes("JMX"), Err(LookupError::UnknownMnemonic(vec!["JML", "JMP"])));
+/// ```
+pub fn supported_modes(name: &str) -> Result<Vec<AddressingMode>, LookupError> {
+    find_mnemonic(&name.to_ascii_uppercase()).map(modes_of)
+}
+
+/// Maps every opcode to its instruction, with rows indexed by the high
+/// nibble of an opcode and columns by the low nibble.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{self, AddressingMode};
+///
+/// let matrix = encoder::opcode_matrix();
+/// assert_eq!(matrix[0xE][0xA], ("NOP", AddressingMode::Implied));
+/// assert_eq!(matrix[0x6][0x9], ("ADC", AddressingMode::Immediate));
+/// ```
+pub fn opcode_matrix() -> [[(&'static str, AddressingMode); 16]; 16] {
+    let mut matrix = [[("", Implied); 16]; 16];
+    for &(name, mode, opcode) in INSTRUCTIONS {
+        matrix[usize::from(opcode >> 4)][usize::from(opcode & 0xF)] = (name, mode);
+    }
+    matrix
+}
+
+/// Finds an opcode of an instruction, explaining why it doesn't exist.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{lookup, AddressingMode, LookupError};
+///
+/// assert_eq!(lookup("ADC", AddressingMode::Immediate), Ok(0x69));
+/// assert_eq!(
+///     lookup("ADD", AddressingMode::Immediate),
+///     Err(LookupError::UnknownMnemonic(vec!["ADC", "AND"])),
+/// );
+/// ```
+pub fn lookup(name: &str, addressing_mode: AddressingMode) -> Result<u8, LookupError> {
+    let (name, addressing_mode) = resolve_alias(name, addressing_mode, Aliases::Accept)?;
+    let mnemonic = find_mnemonic(name)?;
+    OPCODES[mnemonic][addressing_mode as usize].ok_or_else(|| unsupported_mode(mnemonic))
+}
+
+/// Finds an index of an uppercase mnemonic in `MNEMONICS`.
+fn find_mnemonic(name: &str) -> Result<usize, LookupError> {
+    MNEMONICS
+        .binary_search(&name)
+        .map_err(|_| LookupError::UnknownMnemonic(near_mnemonics(name)))
+}
+
+/// Reports an instruction at an index as not supporting a mode.
+fn unsupported_mode(mnemonic: usize) -> LookupError {
+    LookupError::UnsupportedMode(MNEMONICS[mnemonic], modes_of(mnemonic))
+}
+
+/// Addressing modes of a mnemonic at an index.
+fn modes_of(mnemonic: usize) -> Vec<AddressingMode> {
+    let opcodes = &OPCODES[mnemonic];
+    AddressingMode::ALL
+        .iter()
+        .cloned()
+        .filter(|&mode| opcodes[mode as usize].is_some())
+        .collect()
+}
+
+/// Whether alternative mnemonics are accepted.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Aliases {
+    #[default]
+    Accept,
+    /// Rejects aliases, for code which should only use standard
+    /// mnemonics.
+    Reject,
+}
+
+/// Alternative mnemonics used by other assemblers, along with
+/// instructions they stand for, and an addressing mode replacing implied
+/// addressing.
+pub(crate) const ALIASES: &[(&str, &str, AddressingMode)] = &[
+    ("BGE", "BCS", Relative),
+    ("BLT", "BCC", Relative),
+    ("DEA", "DEC", Accumulator),
+    ("INA", "INC", Accumulator),
+    ("SWA", "XBA", Implied),
+    ("TAD", "TCD", Implied),
+    ("TDA", "TDC", Implied),
+];
+
+/// Replaces an alias, in any case, with an instruction it stands for.
+///
+/// Other instructions are returned unchanged. `lookup`, `encode` and other
+/// functions of this module accept aliases, so this is only needed to
+/// reject them.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{resolve_alias, AddressingMode, Aliases, LookupError};
+///
+/// let resolved = resolve_alias("DEA", AddressingMode::Implied, Aliases::Accept);
+/// assert_eq!(resolved, Ok(("DEC", AddressingMode::Accumulator)));
+/// let resolved = resolve_alias("dea", AddressingMode::Implied, Aliases::Accept);
+/// assert_eq!(resolved, Ok(("DEC", AddressingMode::Accumulator)));
+/// let resolved = resolve_alias("DEA", AddressingMode::Implied, Aliases::Reject);
+/// assert_eq!(resolved, Err(LookupError::Alias("DEA", "DEC", AddressingMode::Accumulator)));
+/// let resolved = resolve_alias("LDA", AddressingMode::Immediate, Aliases::Reject);
+/// assert_eq!(resolved, Ok(("LDA", AddressingMode::Immediate)));
+/// ```
+pub fn resolve_alias(
+    name: &str,
+    mode: AddressingMode,
+    aliases: Aliases,
+) -> Result<(&str, AddressingMode), LookupError> {
+    let alias = ALIASES
+        .iter()
+        .find(|alias| alias.0.eq_ignore_ascii_case(name));
+    let &(alias, resolved, alias_mode) = match alias {
+        Some(alias) => alias,
+        None => return Ok((name, mode)),
+    };
+    let mode = if mode == Implied { alias_mode } else { mode };
+    match aliases {
+        Aliases::Accept => Ok((resolved, mode)),
+        Aliases::Reject => Err(LookupError::Alias(alias, resolved, mode)),
+    }
+}
+
+/// Mnemonics which differ from a name by case or, when none does, by
+/// a single letter.
+fn near_mnemonics(name: &str) -> Vec<&'static str> {
+    if let Some(mnemonic) = normalize_mnemonic(name) {
+        return vec![mnemonic];
+    }
+    MNEMONICS
+        .iter()
+        .cloned()
+        .filter(|mnemonic| {
+            mnemonic.len() == name.len()
+                && mnemonic
+                    .bytes()
+                    .zip(name.bytes())
+                    .filter(|&(a, b)| !a.eq_ignore_ascii_case(&b))
+                    .count()
+                    <= 1
+        })
+        .collect()
+}
+
+/// Instructions whose immediate operand is always one byte, regardless
+/// of processor flags.
+const BYTE_IMMEDIATES: &[&str] = &["BRK", "COP", "REP", "SEP", "WDM"];
+
+/// An instruction encoding failure.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EncodeError {
+    /// There is no such instruction.
+    Lookup(LookupError),
+    /// Operand size in bytes isn't valid for an addressing mode.
+    InvalidWidth(u32),
+    /// Operand doesn't fit in its size, or a branch target is out of
+    /// range.
+    OutOfRange(u32),
+    /// Bank of a block move doesn't fit in a byte.
+    InvalidBank(i64),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EncodeError::Lookup(ref e) => e.fmt(f),
+            EncodeError::InvalidWidth(width) => {
+                write!(f, "{}-byte operand is invalid for this instruction", width)
+            }
+            EncodeError::OutOfRange(operand) => write!(f, "operand ${:X} is out of range", operand),
+            EncodeError::InvalidBank(bank) => write!(f, "bank {} doesn't fit in a byte", bank),
+        }
+    }
+}
+
+impl error::Error for EncodeError {}
+
+impl From<LookupError> for EncodeError {
+    fn from(e: LookupError) -> EncodeError {
+        EncodeError::Lookup(e)
+    }
+}
+
+/// Encodes an instruction as an opcode followed by a little-endian operand
+/// of `width` bytes.
+///
+/// `width` must match the operand size of an addressing mode, while an
+/// immediate operand can be one or two bytes, depending on processor
+/// flags. Relative branches take a signed displacement from the end of an
+/// instruction, as two's complement. Block moves take the destination bank
+/// in the low byte and the source bank in the high byte, which is the
+/// order they are encoded in.
+///
+/// `BRK` and `COP` take a signature byte as an immediate operand. As the
+/// processor skips over the signature when returning from an interrupt,
+/// they can be written without one, and a zero byte is emitted instead.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{encode, AddressingMode, EncodeError};
+///
+/// let bytes = encode("LDA", AddressingMode::AbsoluteLong, 0x7E2000, 3).unwrap();
+/// assert_eq!(bytes.as_slice(), &[0xAF, 0x00, 0x20, 0x7E]);
+/// let bytes = encode("BNE", AddressingMode::Relative, -3i32 as u32, 1).unwrap();
+/// assert_eq!(bytes.as_slice(), &[0xD0, 0xFD]);
+/// let bytes = encode("BRK", AddressingMode::Implied, 0, 0).unwrap();
+/// assert_eq!(bytes.as_slice(), &[0x00, 0x00]);
+/// let error = encode("LDA", AddressingMode::Immediate, 0x123, 1);
+/// assert_eq!(error, Err(EncodeError::OutOfRange(0x123)));
+/// ```
+pub fn encode(
+    name: &str,
+    mode: AddressingMode,
+    operand: u32,
+    width: u32,
+) -> Result<ArrayVec<u8, 4>, EncodeError> {
+    let (name, mode) = resolve_alias(name, mode, Aliases::Accept)?;
+    if is_bare_interrupt(name, mode) {
+        if width != 0 {
+            return Err(EncodeError::InvalidWidth(width));
+        }
+        return encode(name, Immediate, operand, 1);
+    }
+    let opcode = lookup(name, mode)?;
+    let valid_width = match mode.operand_size() {
+        Some(size) => width == size,
+        None if BYTE_IMMEDIATES.contains(&name) => width == 1,
+        None => width == 1 || width == 2,
+    };
+    if !valid_width {
+        return Err(EncodeError::InvalidWidth(width));
+    }
+    let bits = 8 * width;
+    let fits = match mode {
+        Relative | RelativeLong => {
+            let limit = 1 << (bits - 1);
+            (-limit..limit).contains(&i64::from(operand as i32))
+        }
+        _ => u64::from(operand) < 1 << bits,
+    };
+    if !fits {
+        return Err(EncodeError::OutOfRange(operand));
+    }
+    let mut bytes = ArrayVec::new();
+    bytes.push(opcode);
+    bytes.extend(operand.to_le_bytes()[..width as usize].iter().cloned());
+    Ok(bytes)
+}
+
+/// Checks whether an instruction is `BRK` or `COP` without a signature.
+fn is_bare_interrupt(name: &str, mode: AddressingMode) -> bool {
+    mode == Implied && (name == "BRK" || name == "COP")
+}
+
+/// Encodes a block move, `MVN` or `MVP`, with banks in the order they
+/// are written, as in `MVN source,destination`.
+///
+/// Banks are encoded in reverse order, with the destination bank first.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{encode_block_move, EncodeError};
+///
+/// let bytes = encode_block_move("MVN", 0x7E, 0x7F).unwrap();
+/// assert_eq!(bytes.as_slice(), &[0x54, 0x7F, 0x7E]);
+/// assert_eq!(encode_block_move("MVP", 0x100, 0), Err(EncodeError::InvalidBank(0x100)));
+/// ```
+pub fn encode_block_move(
+    name: &str,
+    source: i64,
+    destination: i64,
+) -> Result<ArrayVec<u8, 4>, EncodeError> {
+    for &bank in &[source, destination] {
+        if !(0..=0xFF).contains(&bank) {
+            return Err(EncodeError::InvalidBank(bank));
+        }
+    }
+    encode(name, BlockMove, (source << 8 | destination) as u32, 2)
+}
+
+/// Instructions whose immediate operand is as wide as the accumulator.
+const ACCUMULATOR_IMMEDIATES: &[&str] = &["ADC", "AND", "BIT", "CMP", "EOR", "LDA", "ORA", "SBC"];
+
+/// Instructions whose immediate operand is as wide as index registers.
+const INDEX_IMMEDIATES: &[&str] = &["CPX", "CPY", "LDX", "LDY"];
+
+/// Size of an instruction in bytes, including its operand, or `None` when
+/// there is no such instruction.
+///
+/// Size of an immediate operand depends on processor flags: `m_flag` set
+/// means an 8-bit accumulator, and `x_flag` set means 8-bit index
+/// registers. This allows laying out code before operands are known.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{instruction_size, AddressingMode};
+///
+/// assert_eq!(instruction_size("LDA", AddressingMode::Immediate, true, false), Some(2));
+/// assert_eq!(instruction_size("LDA", AddressingMode::Immediate, false, true), Some(3));
+/// assert_eq!(instruction_size("LDX", AddressingMode::Immediate, false, true), Some(2));
+/// assert_eq!(instruction_size("JSL", AddressingMode::AbsoluteLong, true, true), Some(4));
+/// assert_eq!(instruction_size("JSL", AddressingMode::Immediate, true, true), None);
+/// ```
+pub fn instruction_size(
+    name: &str,
+    mode: AddressingMode,
+    m_flag: bool,
+    x_flag: bool,
+) -> Option<usize> {
+    let (name, mode) = resolve_alias(name, mode, Aliases::Accept).ok()?;
+    if is_bare_interrupt(name, mode) {
+        return Some(2);
+    }
+    lookup(name, mode).ok()?;
+    let operand = match mode.operand_size() {
+        Some(size) => size,
+        None if ACCUMULATOR_IMMEDIATES.contains(&name) && !m_flag => 2,
+        None if INDEX_IMMEDIATES.contains(&name) && !x_flag => 2,
+        None => 1,
+    };
+    Some(1 + operand as usize)
+}
+
+/// What the size of an operand without a width suffix depends on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum OperandKind {
+    /// Numeric literal with two hexadecimal digits, like `$12`.
+    ByteLiteral,
+    /// Numeric literal with four hexadecimal digits, like `$0012`.
+    WordLiteral,
+    /// Numeric literal with six hexadecimal digits, like `$000012`.
+    LongLiteral,
+    /// Any other operand with a value below `$100`.
+    DirectPage,
+    /// Any other operand with a value in bank zero or the current bank.
+    InBank,
+    /// Any other operand with a value in another bank.
+    OtherBank,
+    /// Operand which can't be evaluated yet, like a label defined later.
+    Unknown,
+}
+
+impl OperandKind {
+    /// Number of bytes needed by an operand of this kind.
+    pub fn size(self) -> u32 {
+        match self {
+            OperandKind::ByteLiteral | OperandKind::DirectPage => 1,
+            OperandKind::WordLiteral | OperandKind::InBank | OperandKind::Unknown => 2,
+            OperandKind::LongLiteral | OperandKind::OtherBank => 3,
+        }
+    }
+}
+
+/// Chooses an addressing mode for an operand written as `value`,
+/// `value,x`, `value,y` or `value,s`.
+///
+/// An explicit width suffix (`LDA.b`, `LDA.w`, `LDA.l`) always wins,
+/// and stack relative addressing has only one size. Otherwise, literals
+/// use their width, and other operands use the smallest mode fitting
+/// their value, with unknown values treated as absolute. `None` is
+/// returned when no mode can encode an operand, like `LDA.l $12,y`.
+///
+/// This is what the assembler uses. An instruction without the chosen
+/// mode, like `JMP $12`, uses the next larger one instead.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{infer_mode, AddressingMode, OperandKind};
+/// use mvp::parser::ast::IndexRegister;
+///
+/// let mode = infer_mode(OperandKind::DirectPage, None, None);
+/// assert_eq!(mode, Some(AddressingMode::DirectPage));
+/// let mode = infer_mode(OperandKind::Unknown, Some(3), Some(IndexRegister::X));
+/// assert_eq!(mode, Some(AddressingMode::AbsoluteLongIndexedX));
+/// ```
+pub fn infer_mode(
+    operand: OperandKind,
+    suffix: Option<u32>,
+    index: Option<IndexRegister>,
+) -> Option<AddressingMode> {
+    match suffix {
+        Some(1..=3) | None => sized_mode(index, operand_size(operand, suffix, index)),
+        Some(_) => None,
+    }
+}
+
+/// Size of an operand, before checking whether any mode has that size.
+fn operand_size(operand: OperandKind, suffix: Option<u32>, index: Option<IndexRegister>) -> u32 {
+    match suffix {
+        Some(size) => size,
+        None if index == Some(IndexRegister::S) => 1,
+        None => operand.size(),
+    }
+}
+
+/// Addressing mode of an operand of `size` bytes, optionally indexed.
+fn sized_mode(index: Option<IndexRegister>, size: u32) -> Option<AddressingMode> {
+    Some(match (index, size) {
+        (None, 1) => DirectPage,
+        (None, 2) => Absolute,
+        (None, 3) => AbsoluteLong,
+        (Some(IndexRegister::X), 1) => DpIndexedX,
+        (Some(IndexRegister::X), 2) => AbsoluteIndexedX,
+        (Some(IndexRegister::X), 3) => AbsoluteLongIndexedX,
+        (Some(IndexRegister::Y), 1) => DpIndexedY,
+        (Some(IndexRegister::Y), 2) => AbsoluteIndexedY,
+        (Some(IndexRegister::S), 1) => StackRelative,
+        _ => return None,
+    })
+}
+
+/// A row of addressing mode inference table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ModeInference {
+    pub operand: OperandKind,
+    pub suffix: Option<u32>,
+    pub index: Option<IndexRegister>,
+    pub mode: Option<AddressingMode>,
+}
+
+/// Lists every combination of `infer_mode` arguments with its result.
+pub fn mode_table() -> Vec<ModeInference> {
+    let mut table = Vec::new();
+    for &index in &[
+        None,
+        Some(IndexRegister::X),
+        Some(IndexRegister::Y),
+        Some(IndexRegister::S),
+    ] {
+        for &operand in &OPERAND_KINDS {
+            for &suffix in &[None, Some(1), Some(2), Some(3)] {
+                table.push(ModeInference {
+                    operand,
+                    suffix,
+                    index,
+                    mode: infer_mode(operand, suffix, index),
+                });
+            }
+        }
+    }
+    table
+}
+
+const OPERAND_KINDS: [OperandKind; 7] = [
+    OperandKind::ByteLiteral,
+    OperandKind::WordLiteral,
+    OperandKind::LongLiteral,
+    OperandKind::DirectPage,
+    OperandKind::InBank,
+    OperandKind::OtherBank,
+    OperandKind::Unknown,
+];
+
+fn operand_name(operand: OperandKind) -> &'static str {
+    match operand {
+        OperandKind::ByteLiteral => "byte literal",
+        OperandKind::WordLiteral => "word literal",
+        OperandKind::LongLiteral => "long literal",
+        OperandKind::DirectPage => "direct page",
+        OperandKind::InBank => "in bank",
+        OperandKind::OtherBank => "other bank",
+        OperandKind::Unknown => "unknown",
+    }
+}
+
+fn suffix_name(suffix: Option<u32>) -> &'static str {
+    match suffix {
+        None => "",
+        Some(1) => ".b",
+        Some(2) => ".w",
+        Some(3) => ".l",
+        Some(_) => "?",
+    }
+}
+
+fn index_name(index: Option<IndexRegister>) -> &'static str {
+    match index {
+        None => "",
+        Some(IndexRegister::X) => "x",
+        Some(IndexRegister::Y) => "y",
+        Some(IndexRegister::S) => "s",
+    }
+}
+
+/// Formats `mode_table` as a Markdown table.
+pub fn mode_table_markdown() -> String {
+    let mut markdown = String::from(
+        "| Operand | Suffix | Index | Mode |\n\
+         |---------|--------|-------|------|\n",
+    );
+    for row in mode_table() {
+        markdown += &format!(
+            "| {} | {} | {} | {} |\n",
+            operand_name(row.operand),
+            suffix_name(row.suffix),
+            index_name(row.index),
+            row.mode.map_or("invalid", AddressingMode::syntax),
+        );
+    }
+    markdown
+}
+
+/// Formats `mode_table` as a JSON array.
+pub fn mode_table_json() -> Value {
+    mode_table()
+        .into_iter()
+        .map(|row| {
+            json!({
+                "operand": operand_name(row.operand),
+                "suffix": suffix_name(row.suffix),
+                "index": index_name(row.index),
+                "mode": row.mode.map(AddressingMode::syntax),
+            })
+        })
+        .collect()
+}
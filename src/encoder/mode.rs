@@ -0,0 +1,183 @@
+//! Selection of addressing modes for parsed instructions.
+//!
+//! The parser only knows how an operand is written, so `LDA $12` and
+//! `LDA $1234` both use `OpcodeMode::Address`. Which of direct page,
+//! absolute or long addressing is used depends on a width suffix, on the
+//! width of a literal, and otherwise on the value of an operand.
+
+use arrayvec::ArrayVec;
+
+use super::mos6502::unsupported_mode_in;
+use super::AddressingMode::{self, *};
+use super::{
+    find_mnemonic, lookup_in, operand_size, resolve_alias, sized_mode, Aliases, Arch, LookupError,
+    OperandKind,
+};
+use parser::ast::MNEMONICS;
+use parser::ast::{
+    normalize_mnemonic, Expression, Label, NumberWidth, Opcode, OpcodeMode, VariableName,
+};
+
+/// Chooses an addressing mode of an instruction.
+///
+/// A width suffix (`.b`, `.w`, `.l`) selects an operand size, and an
+/// instruction without a mode of that size is an error. Otherwise, stack
+/// relative operands have one byte, then the width of a numeric literal
+/// is used, then `value` of an operand when it is known: values below
+/// `$100` use direct page, values in `bank` or in bank zero use absolute
+/// addressing, and other values use long addressing. Operands with
+/// unknown values, like labels which weren't defined yet, are assumed to
+/// be absolute. These are the rules of `infer_mode`. When an instruction doesn't
+/// support an operand size without a suffix, a larger one is used, as in
+/// `JMP $12`, and for indirect modes a size the instruction supports.
+/// An operand written as `A` is the accumulator.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{resolve_mode, AddressingMode, LookupError};
+/// use mvp::parser::ast::Statement;
+/// use mvp::parser::grammar::parse_statement;
+///
+/// let mode = |source, value| match parse_statement(source) {
+///     Ok((Statement::Opcode(opcode), _)) => resolve_mode(&opcode, value, Some(0x7E)),
+///     _ => unreachable!(),
+/// };
+/// assert_eq!(mode("LDA Value", Some(0x12)), Ok(AddressingMode::DirectPage));
+/// assert_eq!(mode("LDA Value", Some(0x7E1234)), Ok(AddressingMode::Absolute));
+/// assert_eq!(mode("LDA Value", Some(0x7F1234)), Ok(AddressingMode::AbsoluteLong));
+/// assert_eq!(mode("LDA.w Value", Some(0x12)), Ok(AddressingMode::Absolute));
+/// assert_eq!(mode("JSL Value", None), Ok(AddressingMode::AbsoluteLong));
+/// assert_eq!(
+///     mode("STX.w Value,y", None),
+///     Err(LookupError::UnsupportedMode("STX", vec![
+///         AddressingMode::DirectPage,
+///         AddressingMode::Absolute,
+///         AddressingMode::DpIndexedY,
+///     ])),
+/// );
+/// ```
+pub fn resolve_mode(
+    opcode: &Opcode,
+    value: Option<u32>,
+    bank: Option<u8>,
+) -> Result<AddressingMode, LookupError> {
+    resolve_mode_in(Arch::W65816, opcode, value, bank)
+}
+
+/// Chooses an addressing mode of an instruction of an instruction set,
+/// like `resolve_mode`.
+///
+/// Only modes available in an instruction set are chosen, so on the 6502
+/// an operand which doesn't fit in 16 bits is an error.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{resolve_mode_in, AddressingMode, Arch};
+/// use mvp::parser::ast::Statement;
+/// use mvp::parser::grammar::parse_statement;
+///
+/// let mode = |arch, source| match parse_statement(source) {
+///     Ok((Statement::Opcode(opcode), _)) => resolve_mode_in(arch, &opcode, None, None),
+///     _ => unreachable!(),
+/// };
+/// assert_eq!(mode(Arch::W65816, "LDA ($12)"), Ok(AddressingMode::DpIndirect));
+/// assert_eq!(mode(Arch::W65C02, "LDA ($12)"), Ok(AddressingMode::DpIndirect));
+/// assert!(mode(Arch::W6502, "LDA ($12)").is_err());
+/// ```
+pub fn resolve_mode_in(
+    arch: Arch,
+    opcode: &Opcode,
+    value: Option<u32>,
+    bank: Option<u8>,
+) -> Result<AddressingMode, LookupError> {
+    let name = normalize_mnemonic(opcode.name).unwrap_or(opcode.name);
+    let size = operand_size(
+        operand_kind(opcode, value, bank),
+        opcode.width,
+        opcode.index_register(),
+    );
+    let widen = |modes: &mut ArrayVec<AddressingMode, 5>, index| {
+        if opcode.width.is_some() {
+            modes.extend(sized_mode(index, size));
+        } else {
+            modes.extend((size..=3).filter_map(|size| sized_mode(index, size)));
+        }
+    };
+    let sized = |modes: &mut ArrayVec<AddressingMode, 5>, dp, absolute| match opcode.width {
+        Some(1) => modes.push(dp),
+        Some(2) => modes.push(absolute),
+        Some(_) => {}
+        None if size == 1 => modes.extend([dp, absolute].iter().cloned()),
+        None => modes.extend([absolute, dp].iter().cloned()),
+    };
+    let mut modes = ArrayVec::new();
+    match opcode.mode {
+        OpcodeMode::Implied => modes.push(Implied),
+        OpcodeMode::Immediate => modes.push(Immediate),
+        OpcodeMode::Accumulator => modes.push(Accumulator),
+        OpcodeMode::Address if is_accumulator(opcode) => modes.push(Accumulator),
+        OpcodeMode::Address => {
+            // A suffix is an operand size, which branches don't allow to pick.
+            if opcode.width.is_none() {
+                modes.extend([Relative, RelativeLong].iter().cloned());
+            }
+            widen(&mut modes, None);
+        }
+        OpcodeMode::Indirect => sized(&mut modes, DpIndirect, AbsoluteIndirect),
+        OpcodeMode::XIndirect => sized(&mut modes, DpIndexedIndirectX, AbsoluteIndexedIndirect),
+        OpcodeMode::IndirectY => modes.push(DpIndirectIndexedIndexY),
+        OpcodeMode::StackIndirectY => modes.push(SrIndirectIndexedY),
+        OpcodeMode::LongIndirect => sized(&mut modes, DpIndirectLong, AbsoluteIndirectLong),
+        OpcodeMode::LongIndirectY => modes.push(DpIndirectLongIndexedY),
+        OpcodeMode::Move { .. } => match opcode.index_register() {
+            Some(index) => widen(&mut modes, Some(index)),
+            None => modes.push(BlockMove),
+        },
+    }
+    let mut error = None;
+    for &mode in &modes {
+        match lookup_in(arch, name, mode) {
+            Ok(_) => return Ok(mode),
+            Err(e) => error = error.or(Some(e)),
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        // No mode has an operand of a size given by a suffix.
+        None => {
+            let (name, _) = resolve_alias(name, Implied, Aliases::Accept)?;
+            Err(unsupported_mode_in(arch, MNEMONICS[find_mnemonic(name)?]))
+        }
+    }
+}
+
+/// Checks whether an operand is the accumulator, as in `INC A`, which is
+/// parsed as a label named `A`.
+fn is_accumulator(opcode: &Opcode) -> bool {
+    match opcode.value.as_deref() {
+        Some(&Expression::Variable(Label::Named(VariableName(name)))) => {
+            opcode.width.is_none() && name.eq_ignore_ascii_case("A")
+        }
+        _ => false,
+    }
+}
+
+/// Classifies an operand by its literal width or its value.
+fn operand_kind(opcode: &Opcode, value: Option<u32>, bank: Option<u8>) -> OperandKind {
+    if let Some(Expression::Number(number)) = opcode.value.as_deref() {
+        match number.width {
+            NumberWidth::OneByte => return OperandKind::ByteLiteral,
+            NumberWidth::TwoBytes => return OperandKind::WordLiteral,
+            NumberWidth::ThreeBytes => return OperandKind::LongLiteral,
+            NumberWidth::None => {}
+        }
+    }
+    match value {
+        Some(value) if value < 0x100 => OperandKind::DirectPage,
+        Some(value) if value < 0x1_0000 || Some((value >> 16) as u8) == bank => OperandKind::InBank,
+        Some(_) => OperandKind::OtherBank,
+        None => OperandKind::Unknown,
+    }
+}
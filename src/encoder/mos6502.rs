@@ -0,0 +1,223 @@
+//! Instruction sets of the 6502 and the 65C02.
+//!
+//! The 65816 extends the 65C02, which extends the 6502, and opcodes of
+//! instructions they share are the same. Code for the older processors
+//! uses the same syntax, so these instruction sets only list which
+//! instructions of the 65816 are available. Registers are always 8-bit.
+
+use super::AddressingMode::{self, *};
+use super::{lookup, resolve_alias, Aliases, Arch, LookupError};
+use parser::ast::normalize_mnemonic;
+
+/// Addressing modes of `ADC`, `AND`, `CMP`, `EOR`, `LDA`, `ORA` and `SBC`
+/// on the 6502.
+const ALU: &[AddressingMode] = &[
+    Immediate,
+    DirectPage,
+    DpIndexedX,
+    Absolute,
+    AbsoluteIndexedX,
+    AbsoluteIndexedY,
+    DpIndexedIndirectX,
+    DpIndirectIndexedIndexY,
+];
+
+/// Addressing modes of shifts and rotations on the 6502.
+const SHIFT: &[AddressingMode] = &[
+    Accumulator,
+    DirectPage,
+    DpIndexedX,
+    Absolute,
+    AbsoluteIndexedX,
+];
+
+/// Instructions of the 6502, with their addressing modes.
+const MOS6502: &[(&str, &[AddressingMode])] = &[
+    ("ADC", ALU),
+    ("AND", ALU),
+    ("ASL", SHIFT),
+    ("BCC", &[Relative]),
+    ("BCS", &[Relative]),
+    ("BEQ", &[Relative]),
+    ("BIT", &[DirectPage, Absolute]),
+    ("BMI", &[Relative]),
+    ("BNE", &[Relative]),
+    ("BPL", &[Relative]),
+    ("BRK", &[Immediate]),
+    ("BVC", &[Relative]),
+    ("BVS", &[Relative]),
+    ("CLC", &[Implied]),
+    ("CLD", &[Implied]),
+    ("CLI", &[Implied]),
+    ("CLV", &[Implied]),
+    ("CMP", ALU),
+    ("CPX", &[Immediate, DirectPage, Absolute]),
+    ("CPY", &[Immediate, DirectPage, Absolute]),
+    ("DEC", &[DirectPage, DpIndexedX, Absolute, AbsoluteIndexedX]),
+    ("DEX", &[Implied]),
+    ("DEY", &[Implied]),
+    ("EOR", ALU),
+    ("INC", &[DirectPage, DpIndexedX, Absolute, AbsoluteIndexedX]),
+    ("INX", &[Implied]),
+    ("INY", &[Implied]),
+    ("JMP", &[Absolute, AbsoluteIndirect]),
+    ("JSR", &[Absolute]),
+    ("LDA", ALU),
+    (
+        "LDX",
+        &[
+            Immediate,
+            DirectPage,
+            DpIndexedY,
+            Absolute,
+            AbsoluteIndexedY,
+        ],
+    ),
+    (
+        "LDY",
+        &[
+            Immediate,
+            DirectPage,
+            DpIndexedX,
+            Absolute,
+            AbsoluteIndexedX,
+        ],
+    ),
+    ("LSR", SHIFT),
+    ("NOP", &[Implied]),
+    ("ORA", ALU),
+    ("PHA", &[Implied]),
+    ("PHP", &[Implied]),
+    ("PLA", &[Implied]),
+    ("PLP", &[Implied]),
+    ("ROL", SHIFT),
+    ("ROR", SHIFT),
+    ("RTI", &[Implied]),
+    ("RTS", &[Implied]),
+    ("SBC", ALU),
+    ("SEC", &[Implied]),
+    ("SED", &[Implied]),
+    ("SEI", &[Implied]),
+    (
+        "STA",
+        &[
+            DirectPage,
+            DpIndexedX,
+            Absolute,
+            AbsoluteIndexedX,
+            AbsoluteIndexedY,
+            DpIndexedIndirectX,
+            DpIndirectIndexedIndexY,
+        ],
+    ),
+    ("STX", &[DirectPage, DpIndexedY, Absolute]),
+    ("STY", &[DirectPage, DpIndexedX, Absolute]),
+    ("TAX", &[Implied]),
+    ("TAY", &[Implied]),
+    ("TSX", &[Implied]),
+    ("TXA", &[Implied]),
+    ("TXS", &[Implied]),
+    ("TYA", &[Implied]),
+];
+
+/// Instructions added by the 65C02, with their new addressing modes.
+const W65C02: &[(&str, &[AddressingMode])] = &[
+    ("ADC", &[DpIndirect]),
+    ("AND", &[DpIndirect]),
+    ("BIT", &[Immediate, DpIndexedX, AbsoluteIndexedX]),
+    ("BRA", &[Relative]),
+    ("CMP", &[DpIndirect]),
+    ("DEC", &[Accumulator]),
+    ("EOR", &[DpIndirect]),
+    ("INC", &[Accumulator]),
+    ("JMP", &[AbsoluteIndexedIndirect]),
+    ("LDA", &[DpIndirect]),
+    ("ORA", &[DpIndirect]),
+    ("PHX", &[Implied]),
+    ("PHY", &[Implied]),
+    ("PLX", &[Implied]),
+    ("PLY", &[Implied]),
+    ("SBC", &[DpIndirect]),
+    ("STA", &[DpIndirect]),
+    ("STP", &[Implied]),
+    ("STZ", &[DirectPage, DpIndexedX, Absolute, AbsoluteIndexedX]),
+    ("TRB", &[DirectPage, Absolute]),
+    ("TSB", &[DirectPage, Absolute]),
+    ("WAI", &[Implied]),
+];
+
+/// Finds an opcode of an instruction of an instruction set.
+///
+/// Instructions of the 6502 and the 65C02 are looked up like 65816 ones,
+/// but those missing on an older processor are rejected. SPC700 and Super
+/// FX code has no 65816 instructions.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::{lookup_in, AddressingMode, Arch, LookupError};
+///
+/// assert_eq!(lookup_in(Arch::W6502, "LDA", AddressingMode::Absolute), Ok(0xAD));
+/// assert_eq!(
+///     lookup_in(Arch::W6502, "BRA", AddressingMode::Relative),
+///     Err(LookupError::UnavailableInstruction("BRA", Arch::W6502)),
+/// );
+/// assert_eq!(lookup_in(Arch::W65C02, "BRA", AddressingMode::Relative), Ok(0x80));
+/// assert_eq!(
+///     lookup_in(Arch::W65C02, "JMP", AddressingMode::AbsoluteLong),
+///     Err(LookupError::UnsupportedMode("JMP", vec![
+///         AddressingMode::Absolute,
+///         AddressingMode::AbsoluteIndirect,
+///         AddressingMode::AbsoluteIndexedIndirect,
+///     ])),
+/// );
+/// ```
+pub fn lookup_in(arch: Arch, name: &str, mode: AddressingMode) -> Result<u8, LookupError> {
+    if arch == Arch::W65816 {
+        return lookup(name, mode);
+    }
+    let (name, mode) = resolve_alias(name, mode, Aliases::Accept)?;
+    let opcode = match lookup(name, mode) {
+        Err(LookupError::UnknownMnemonic(suggestions)) => {
+            return Err(LookupError::UnknownMnemonic(suggestions));
+        }
+        opcode => opcode.ok(),
+    };
+    let mnemonic = normalize_mnemonic(name).unwrap_or_default();
+    match opcode {
+        Some(opcode) if modes(arch, mnemonic).contains(&mode) => Ok(opcode),
+        _ => Err(unsupported_mode_in(arch, mnemonic)),
+    }
+}
+
+/// Lists addressing modes supported by an instruction in an instruction
+/// set, or reports it as unavailable when it has none.
+pub(super) fn unsupported_mode_in(arch: Arch, mnemonic: &'static str) -> LookupError {
+    match modes(arch, mnemonic) {
+        ref modes if modes.is_empty() => LookupError::UnavailableInstruction(mnemonic, arch),
+        modes => LookupError::UnsupportedMode(mnemonic, modes),
+    }
+}
+
+/// Addressing modes of an instruction in an instruction set, in order of
+/// the 65816 instruction table.
+fn modes(arch: Arch, name: &str) -> Vec<AddressingMode> {
+    let tables: &[&[(&str, &[AddressingMode])]] = match arch {
+        Arch::W6502 => &[MOS6502],
+        Arch::W65C02 => &[MOS6502, W65C02],
+        Arch::W65816 | Arch::Spc700 | Arch::SuperFx => &[],
+    };
+    let available: Vec<AddressingMode> = tables
+        .iter()
+        .flat_map(|table| table.iter())
+        .filter(|instruction| instruction.0 == name)
+        .flat_map(|instruction| instruction.1.iter().cloned())
+        .collect();
+    super::instructions()
+        .iter()
+        .filter(|instruction| {
+            instruction.0 == name && (arch == Arch::W65816 || available.contains(&instruction.1))
+        })
+        .map(|instruction| instruction.1)
+        .collect()
+}
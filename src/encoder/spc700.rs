@@ -0,0 +1,467 @@
+//! Encoding of SPC700 instructions.
+//!
+//! The SPC700 is the processor of the SNES sound module. Its instructions
+//! are written with register operands, like `MOV A,#$12`, with `addr+X`
+//! for indexing, `!addr` forcing absolute addressing and `addr.bit` for
+//! single bits. Code is selected with `arch spc700`.
+//!
+//! Operands are kept apart from their values, so that an `Operand` holds
+//! expressions in syntax trees and numbers when encoding.
+
+use std::convert::Infallible;
+use std::error;
+use std::fmt;
+
+use arrayvec::ArrayVec;
+
+use self::Mode::*;
+
+/// A register named in an operand.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    /// Y and A as a 16-bit register, with Y being the high byte.
+    YA,
+    SP,
+    PSW,
+    /// Carry flag, in bit instructions.
+    C,
+}
+
+impl Register {
+    /// Finds a register by its case insensitive name.
+    pub fn from_name(name: &str) -> Option<Register> {
+        Some(match &*name.to_ascii_uppercase() {
+            "A" => Register::A,
+            "X" => Register::X,
+            "Y" => Register::Y,
+            "YA" => Register::YA,
+            "SP" => Register::SP,
+            "PSW" => Register::PSW,
+            "C" => Register::C,
+            _ => return None,
+        })
+    }
+}
+
+/// An operand as written, with values of type `T`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Operand<T> {
+    /// `A`
+    Register(Register),
+    /// `#value`
+    Immediate(T),
+    /// `addr`, or `!addr` for absolute addressing.
+    Address { address: T, absolute: bool },
+    /// `addr+X` or `addr+Y`, or `!addr+X` for absolute addressing.
+    Indexed {
+        address: T,
+        index: Register,
+        absolute: bool,
+    },
+    /// `(X)`
+    IndirectX,
+    /// `(X)+`, incrementing X after access.
+    IndirectXIncrement,
+    /// `(Y)`
+    IndirectY,
+    /// `(dp+X)` or `[dp+X]`, with `!` for absolute addressing.
+    IndexedIndirect { address: T, absolute: bool },
+    /// `(dp)+Y` or `[dp]+Y`
+    IndirectIndexed(T),
+    /// `addr.bit`, or `/addr.bit` for an inverted bit.
+    Bit { address: T, bit: T, inverted: bool },
+}
+
+impl<T> Operand<T> {
+    /// Converts values of an operand.
+    pub fn map<U, F: FnMut(T) -> U>(self, mut f: F) -> Operand<U> {
+        match self.try_map(|value| Ok::<_, Infallible>(f(value))) {
+            Ok(operand) => operand,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Converts values of an operand, stopping at the first error.
+    pub fn try_map<U, E, F>(self, mut f: F) -> Result<Operand<U>, E>
+    where
+        F: FnMut(T) -> Result<U, E>,
+    {
+        Ok(match self {
+            Operand::Register(register) => Operand::Register(register),
+            Operand::Immediate(value) => Operand::Immediate(f(value)?),
+            Operand::Address { address, absolute } => Operand::Address {
+                address: f(address)?,
+                absolute,
+            },
+            Operand::Indexed {
+                address,
+                index,
+                absolute,
+            } => Operand::Indexed {
+                address: f(address)?,
+                index,
+                absolute,
+            },
+            Operand::IndirectX => Operand::IndirectX,
+            Operand::IndirectXIncrement => Operand::IndirectXIncrement,
+            Operand::IndirectY => Operand::IndirectY,
+            Operand::IndexedIndirect { address, absolute } => Operand::IndexedIndirect {
+                address: f(address)?,
+                absolute,
+            },
+            Operand::IndirectIndexed(address) => Operand::IndirectIndexed(f(address)?),
+            Operand::Bit {
+                address,
+                bit,
+                inverted,
+            } => Operand::Bit {
+                address: f(address)?,
+                bit: f(bit)?,
+                inverted,
+            },
+        })
+    }
+
+    /// Borrows values of an operand.
+    pub fn as_ref(&self) -> Operand<&T> {
+        match *self {
+            Operand::Register(register) => Operand::Register(register),
+            Operand::Immediate(ref value) => Operand::Immediate(value),
+            Operand::Address {
+                ref address,
+                absolute,
+            } => Operand::Address { address, absolute },
+            Operand::Indexed {
+                ref address,
+                index,
+                absolute,
+            } => Operand::Indexed {
+                address,
+                index,
+                absolute,
+            },
+            Operand::IndirectX => Operand::IndirectX,
+            Operand::IndirectXIncrement => Operand::IndirectXIncrement,
+            Operand::IndirectY => Operand::IndirectY,
+            Operand::IndexedIndirect {
+                ref address,
+                absolute,
+            } => Operand::IndexedIndirect { address, absolute },
+            Operand::IndirectIndexed(ref address) => Operand::IndirectIndexed(address),
+            Operand::Bit {
+                ref address,
+                ref bit,
+                inverted,
+            } => Operand::Bit {
+                address,
+                bit,
+                inverted,
+            },
+        }
+    }
+
+    /// Values of an operand, in order.
+    pub fn values(&self) -> ArrayVec<&T, 2> {
+        let mut values = ArrayVec::new();
+        match *self {
+            Operand::Immediate(ref value)
+            | Operand::Address {
+                address: ref value, ..
+            }
+            | Operand::Indexed {
+                address: ref value, ..
+            }
+            | Operand::IndexedIndirect {
+                address: ref value, ..
+            }
+            | Operand::IndirectIndexed(ref value) => values.push(value),
+            Operand::Bit {
+                ref address,
+                ref bit,
+                ..
+            } => {
+                values.push(address);
+                values.push(bit);
+            }
+            Operand::Register(_)
+            | Operand::IndirectX
+            | Operand::IndirectXIncrement
+            | Operand::IndirectY => {}
+        }
+        values
+    }
+}
+
+/// An addressing mode of a single operand.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Mode {
+    A,
+    X,
+    Y,
+    YA,
+    SP,
+    PSW,
+    C,
+    Immediate,               // #imm
+    Direct,                  // dp
+    DirectX,                 // dp+X
+    DirectY,                 // dp+Y
+    Absolute,                // !abs
+    AbsoluteX,               // !abs+X
+    AbsoluteY,               // !abs+Y
+    IndirectX,               // (X)
+    IndirectXIncrement,      // (X)+
+    IndirectY,               // (Y)
+    DirectIndexedIndirect,   // [dp+X]
+    DirectIndirectIndexed,   // [dp]+Y
+    AbsoluteIndexedIndirect, // [!abs+X]
+    DirectBit,               // dp.bit, with the bit in the opcode
+    AbsoluteBit,             // mem.bit
+    NotAbsoluteBit,          // /mem.bit
+    Relative,                // rel
+    TableIndex,              // n, in the opcode of TCALL
+    UpperPage,               // up, the low byte of $FFxx
+}
+
+impl Mode {
+    /// Size of an operand in bytes.
+    pub fn operand_size(self) -> u32 {
+        match self {
+            A | X | Y | YA | SP | PSW | C | IndirectX | IndirectXIncrement | IndirectY
+            | TableIndex => 0,
+            Immediate
+            | Direct
+            | DirectX
+            | DirectY
+            | DirectIndexedIndirect
+            | DirectIndirectIndexed
+            | DirectBit
+            | Relative
+            | UpperPage => 1,
+            Absolute
+            | AbsoluteX
+            | AbsoluteY
+            | AbsoluteIndexedIndirect
+            | AbsoluteBit
+            | NotAbsoluteBit => 2,
+        }
+    }
+}
+
+/// Instructions with modes of their operands, along with opcodes.
+///
+/// `SET1`, `CLR1`, `BBS` and `BBC` add the bit number times `$20` to the
+/// opcode, and `TCALL` adds the table index times `$10`. Generated by the
+/// build script from `tables/spc700.txt`.
+const INSTRUCTIONS: &[(&str, &[Mode], u8)] = include!(concat!(env!("OUT_DIR"), "/spc700.rs"));
+
+/// Lists every instruction, as a mnemonic, modes of operands and a base
+/// opcode.
+pub fn instructions() -> &'static [(&'static str, &'static [Mode], u8)] {
+    INSTRUCTIONS
+}
+
+/// An instruction encoding failure.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// No instruction has a mnemonic.
+    UnknownMnemonic,
+    /// An instruction doesn't accept operands as written.
+    UnsupportedOperands(&'static str),
+    /// An operand doesn't fit in its size, or a branch target is out of
+    /// range.
+    OutOfRange(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownMnemonic => f.write_str("unknown SPC700 instruction"),
+            Error::UnsupportedOperands(name) => {
+                write!(f, "{} doesn't support these operands", name)
+            }
+            Error::OutOfRange(operand) => write!(f, "operand ${:X} is out of range", operand),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Encodes an instruction at `address`, which is needed for branches.
+///
+/// Operands without `!` use direct page addressing when they fit in
+/// a byte and an instruction supports it.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::spc700::{self, Operand, Register};
+///
+/// let operands = [Operand::Register(Register::A), Operand::Immediate(0x12)];
+/// assert_eq!(spc700::encode("MOV", &operands, 0x200).unwrap().as_slice(), &[0xE8, 0x12]);
+/// let operands = [
+///     Operand::Address { address: 0x34, absolute: false },
+///     Operand::Immediate(0x12),
+/// ];
+/// assert_eq!(spc700::encode("mov", &operands, 0x200).unwrap().as_slice(), &[0x8F, 0x12, 0x34]);
+/// let operands = [
+///     Operand::Bit { address: 0x12, bit: 3, inverted: false },
+///     Operand::Address { address: 0x200, absolute: false },
+/// ];
+/// assert_eq!(spc700::encode("BBS", &operands, 0x200).unwrap().as_slice(), &[0x63, 0x12, 0xFD]);
+/// ```
+pub fn encode(
+    name: &str,
+    operands: &[Operand<u32>],
+    address: u32,
+) -> Result<ArrayVec<u8, 3>, Error> {
+    let mut known = false;
+    let mut best: Option<(usize, &'static [Mode], u8)> = None;
+    for &(mnemonic, modes, opcode) in INSTRUCTIONS {
+        if !mnemonic.eq_ignore_ascii_case(name) {
+            continue;
+        }
+        known = true;
+        if modes.len() != operands.len() {
+            continue;
+        }
+        let rank = modes
+            .iter()
+            .zip(operands)
+            .map(|(&mode, operand)| candidates(operand).iter().position(|&m| m == mode))
+            .sum::<Option<usize>>();
+        if let Some(rank) = rank {
+            if best.is_none_or(|best| rank < best.0) {
+                best = Some((rank, modes, opcode));
+            }
+        }
+    }
+    let (_, modes, mut opcode) = match best {
+        Some(best) => best,
+        None if known => {
+            let mnemonic = INSTRUCTIONS
+                .iter()
+                .find(|instruction| instruction.0.eq_ignore_ascii_case(name))
+                .unwrap()
+                .0;
+            return Err(Error::UnsupportedOperands(mnemonic));
+        }
+        None => return Err(Error::UnknownMnemonic),
+    };
+    let size = 1 + modes.iter().map(|mode| mode.operand_size()).sum::<u32>();
+    let mut fields = ArrayVec::<(u32, u32), 2>::new();
+    for (&mode, operand) in modes.iter().zip(operands) {
+        let value = operand.values().first().map_or(0, |&&value| value);
+        match (mode, operand) {
+            (DirectBit, &Operand::Bit { bit, .. }) => {
+                opcode += 0x20 * fits(bit, 8)? as u8;
+                fields.push((fits(value, 0x100)?, 1));
+            }
+            (AbsoluteBit, &Operand::Bit { bit, .. })
+            | (NotAbsoluteBit, &Operand::Bit { bit, .. }) => {
+                let bits = fits(value, 0x2000)? | fits(bit, 8)? << 13;
+                fields.push((bits, 2));
+            }
+            (TableIndex, _) => opcode += 0x10 * fits(value, 16)? as u8,
+            (UpperPage, _) => {
+                if !(value < 0x100 || (0xFF00..0x1_0000).contains(&value)) {
+                    return Err(Error::OutOfRange(value));
+                }
+                fields.push((value & 0xFF, 1));
+            }
+            (Relative, _) => {
+                let displacement = i64::from(value) - i64::from(address + size);
+                if !(-0x80..0x80).contains(&displacement) {
+                    return Err(Error::OutOfRange(value));
+                }
+                fields.push((displacement as u32 & 0xFF, 1));
+            }
+            _ => match mode.operand_size() {
+                0 => {}
+                size => fields.push((fits(value, 1 << (8 * size))?, size)),
+            },
+        }
+    }
+    // Operands are encoded source first, except for branch targets, which
+    // always come last.
+    if modes.last() != Some(&Relative) {
+        fields.reverse();
+    }
+    let mut bytes = ArrayVec::new();
+    bytes.push(opcode);
+    for (value, size) in fields {
+        bytes.extend(value.to_le_bytes()[..size as usize].iter().cloned());
+    }
+    Ok(bytes)
+}
+
+fn fits(value: u32, limit: u32) -> Result<u32, Error> {
+    if value < limit {
+        Ok(value)
+    } else {
+        Err(Error::OutOfRange(value))
+    }
+}
+
+/// Modes which can encode an operand, preferred ones first.
+fn candidates(operand: &Operand<u32>) -> ArrayVec<Mode, 5> {
+    let mut modes = ArrayVec::new();
+    match *operand {
+        Operand::Register(register) => modes.push(match register {
+            Register::A => A,
+            Register::X => X,
+            Register::Y => Y,
+            Register::YA => YA,
+            Register::SP => SP,
+            Register::PSW => PSW,
+            Register::C => C,
+        }),
+        Operand::Immediate(_) => modes.push(Immediate),
+        Operand::Address { absolute: true, .. } => modes.push(Absolute),
+        Operand::Address { address, .. } => {
+            if address < 0x100 {
+                modes.push(Direct);
+            }
+            modes.extend([Absolute, Relative, UpperPage].iter().cloned());
+            if address < 0x10 {
+                modes.push(TableIndex);
+            }
+        }
+        Operand::Indexed {
+            address,
+            index,
+            absolute,
+        } => {
+            let (direct, absolute_mode) = match index {
+                Register::X => (DirectX, AbsoluteX),
+                Register::Y => (DirectY, AbsoluteY),
+                _ => return modes,
+            };
+            if !absolute && address < 0x100 {
+                modes.push(direct);
+            }
+            modes.push(absolute_mode);
+        }
+        Operand::IndirectX => modes.push(IndirectX),
+        Operand::IndirectXIncrement => modes.push(IndirectXIncrement),
+        Operand::IndirectY => modes.push(IndirectY),
+        Operand::IndexedIndirect { address, absolute } => {
+            if !absolute && address < 0x100 {
+                modes.push(DirectIndexedIndirect);
+            }
+            modes.push(AbsoluteIndexedIndirect);
+        }
+        Operand::IndirectIndexed(_) => modes.push(DirectIndirectIndexed),
+        Operand::Bit { inverted: true, .. } => modes.push(NotAbsoluteBit),
+        Operand::Bit { address, .. } => {
+            if address < 0x100 {
+                modes.push(DirectBit);
+            }
+            modes.push(AbsoluteBit);
+        }
+    }
+    modes
+}
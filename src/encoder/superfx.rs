@@ -0,0 +1,324 @@
+//! Encoding of Super FX instructions.
+//!
+//! The Super FX, or GSU, is a coprocessor in cartridges of games like
+//! Star Fox. Most of its instructions take one register, with source and
+//! destination registers selected by prefixes written as separate
+//! instructions:
+//!
+//! ```asm
+//! arch superfx
+//! iwt r1,#$1234   ; r1 = $1234
+//! with r1         ; source and destination
+//! add r2          ; r1 = r1 + r2
+//! from r1
+//! to r3
+//! lsr             ; r3 = r1 >> 1
+//! ```
+//!
+//! Many opcodes are shared between several instructions, which are
+//! selected with a preceding `ALT1`, `ALT2` or `ALT3` byte. These are
+//! emitted as a part of an instruction.
+
+use std::convert::Infallible;
+use std::error;
+use std::fmt;
+
+use arrayvec::ArrayVec;
+
+use self::Mode::*;
+
+/// Prefix selecting alternate instructions, `ALT1`.
+const ALT1: u8 = 0x3D;
+/// Prefix selecting alternate instructions, `ALT2`.
+const ALT2: u8 = 0x3E;
+/// Prefix selecting alternate instructions, `ALT3`.
+const ALT3: u8 = 0x3F;
+
+/// An operand as written, with values of type `T`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Operand<T> {
+    /// `rN`, a register from `r0` to `r15`.
+    Register(u8),
+    /// `#value`
+    Immediate(T),
+    /// `(rN)`, memory at an address in a register.
+    RegisterIndirect(u8),
+    /// `(addr)`, memory at an address.
+    Memory(T),
+    /// `addr`, a branch target.
+    Address(T),
+}
+
+impl<T> Operand<T> {
+    /// Converts values of an operand.
+    pub fn map<U, F: FnMut(T) -> U>(self, mut f: F) -> Operand<U> {
+        match self.try_map(|value| Ok::<_, Infallible>(f(value))) {
+            Ok(operand) => operand,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Converts values of an operand, stopping at the first error.
+    pub fn try_map<U, E, F>(self, mut f: F) -> Result<Operand<U>, E>
+    where
+        F: FnMut(T) -> Result<U, E>,
+    {
+        Ok(match self {
+            Operand::Register(register) => Operand::Register(register),
+            Operand::Immediate(value) => Operand::Immediate(f(value)?),
+            Operand::RegisterIndirect(register) => Operand::RegisterIndirect(register),
+            Operand::Memory(address) => Operand::Memory(f(address)?),
+            Operand::Address(address) => Operand::Address(f(address)?),
+        })
+    }
+
+    /// Borrows a value of an operand.
+    pub fn as_ref(&self) -> Operand<&T> {
+        match *self {
+            Operand::Register(register) => Operand::Register(register),
+            Operand::Immediate(ref value) => Operand::Immediate(value),
+            Operand::RegisterIndirect(register) => Operand::RegisterIndirect(register),
+            Operand::Memory(ref address) => Operand::Memory(address),
+            Operand::Address(ref address) => Operand::Address(address),
+        }
+    }
+
+    /// Value of an operand, if it has one.
+    pub fn value(&self) -> Option<&T> {
+        match *self {
+            Operand::Immediate(ref value)
+            | Operand::Memory(ref value)
+            | Operand::Address(ref value) => Some(value),
+            Operand::Register(_) | Operand::RegisterIndirect(_) => None,
+        }
+    }
+}
+
+/// An addressing mode of a single operand.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Mode {
+    /// Register in a range, added to the opcode.
+    Register(u8, u8),
+    /// Register with an address in a range, added to the opcode.
+    RegisterIndirect(u8, u8),
+    /// Immediate value in a range, added to the opcode.
+    Nibble(u8, u8),
+    /// Immediate byte, sign extended to 16 bits.
+    Byte,
+    /// Immediate word.
+    Word,
+    /// Even address below `$200`, stored divided by two.
+    ShortAddress,
+    /// Address of a word.
+    Address,
+    /// Branch target.
+    Relative,
+}
+
+impl Mode {
+    /// Size of an operand in bytes, after an opcode.
+    pub fn operand_size(self) -> u32 {
+        match self {
+            Register(..) | RegisterIndirect(..) | Nibble(..) => 0,
+            Byte | ShortAddress | Relative => 1,
+            Word | Address => 2,
+        }
+    }
+}
+
+/// All registers.
+const R: Mode = Register(0, 15);
+
+/// Instructions with modes of their operands, the prefix selecting them,
+/// if any, and opcodes.
+///
+/// A register or a value in a nibble is added to the opcode. For
+/// instructions with a register and a value, the register is first.
+/// Generated by the build script from `tables/superfx.txt`.
+const INSTRUCTIONS: &[(&str, &[Mode], Option<u8>, u8)] =
+    include!(concat!(env!("OUT_DIR"), "/superfx.rs"));
+
+/// Lists every instruction, as a mnemonic, modes of operands, a prefix
+/// and a base opcode.
+///
+/// `MOVE` and `MOVES`, which are encoded as a pair of prefixes, aren't
+/// included.
+pub fn instructions() -> &'static [(&'static str, &'static [Mode], Option<u8>, u8)] {
+    INSTRUCTIONS
+}
+
+/// An instruction encoding failure.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// No instruction has a mnemonic.
+    UnknownMnemonic,
+    /// An instruction doesn't accept operands as written.
+    UnsupportedOperands(&'static str),
+    /// An operand doesn't fit in its size, or a branch target is out of
+    /// range.
+    OutOfRange(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownMnemonic => f.write_str("unknown Super FX instruction"),
+            Error::UnsupportedOperands(name) => {
+                write!(f, "{} doesn't support these operands", name)
+            }
+            Error::OutOfRange(operand) => write!(f, "operand ${:X} is out of range", operand),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Encodes an instruction at `address`, which is needed for branches.
+///
+/// `MOVE r1,r2` is encoded as `WITH r2`, `TO r1`, and `MOVES r1,r2` as
+/// `WITH r1`, `FROM r2`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::encoder::superfx::{self, Operand};
+///
+/// let operands = [Operand::Register(1), Operand::Immediate(0x1234)];
+/// assert_eq!(superfx::encode("IWT", &operands, 0).unwrap().as_slice(), &[0xF1, 0x34, 0x12]);
+/// let operands = [Operand::Immediate(3)];
+/// assert_eq!(superfx::encode("add", &operands, 0).unwrap().as_slice(), &[0x3E, 0x53]);
+/// let operands = [Operand::Register(1), Operand::Memory(0x0104)];
+/// assert_eq!(superfx::encode("LMS", &operands, 0).unwrap().as_slice(), &[0x3D, 0xA1, 0x82]);
+/// let operands = [Operand::Address(0x8000)];
+/// assert_eq!(superfx::encode("BRA", &operands, 0x8000).unwrap().as_slice(), &[0x05, 0xFE]);
+/// ```
+pub fn encode(
+    name: &str,
+    operands: &[Operand<u32>],
+    address: u32,
+) -> Result<ArrayVec<u8, 4>, Error> {
+    if let Some(bytes) = encode_move(name, operands)? {
+        return Ok(bytes);
+    }
+    let mut candidates = INSTRUCTIONS
+        .iter()
+        .filter(|instruction| instruction.0.eq_ignore_ascii_case(name))
+        .peekable();
+    let known = match candidates.peek() {
+        Some(instruction) => instruction.0,
+        None => return Err(Error::UnknownMnemonic),
+    };
+    let (modes, prefix, mut opcode) = candidates
+        .find(|instruction| {
+            instruction.1.len() == operands.len()
+                && instruction
+                    .1
+                    .iter()
+                    .zip(operands)
+                    .all(|(&mode, operand)| accepts(mode, operand))
+        })
+        .map(|&(_, modes, prefix, opcode)| (modes, prefix, opcode))
+        .ok_or(Error::UnsupportedOperands(known))?;
+    let size = prefix.map_or(1, |_| 2) + modes.iter().map(|mode| mode.operand_size()).sum::<u32>();
+    let mut fields = ArrayVec::<(u32, u32), 2>::new();
+    for (&mode, operand) in modes.iter().zip(operands) {
+        let value = operand.value().cloned();
+        match (mode, operand) {
+            (Register(low, high), &Operand::Register(register))
+            | (RegisterIndirect(low, high), &Operand::RegisterIndirect(register)) => {
+                opcode += in_range(u32::from(register), low, high)? as u8;
+            }
+            (Nibble(low, high), _) => opcode += in_range(value.unwrap_or(0), low, high)? as u8,
+            (Byte, _) => {
+                // Values from -128 to 127 can be written either way.
+                let value = value.unwrap_or(0);
+                if (0x100..0xFFFF_FF80).contains(&value) {
+                    return Err(Error::OutOfRange(value));
+                }
+                fields.push((value & 0xFF, 1));
+            }
+            (Word, _) | (Address, _) => {
+                let value = value.unwrap_or(0);
+                if value >= 0x1_0000 {
+                    return Err(Error::OutOfRange(value));
+                }
+                fields.push((value, 2));
+            }
+            (ShortAddress, _) => {
+                let value = value.unwrap_or(0);
+                if value >= 0x200 || value % 2 != 0 {
+                    return Err(Error::OutOfRange(value));
+                }
+                fields.push((value / 2, 1));
+            }
+            (Relative, _) => {
+                let value = value.unwrap_or(0);
+                let displacement = i64::from(value) - i64::from(address + size);
+                if !(-0x80..0x80).contains(&displacement) {
+                    return Err(Error::OutOfRange(value));
+                }
+                fields.push((displacement as u32 & 0xFF, 1));
+            }
+            _ => unreachable!("operand accepted by a wrong mode"),
+        }
+    }
+    let mut bytes = ArrayVec::new();
+    bytes.extend(prefix);
+    bytes.push(opcode);
+    for (value, size) in fields {
+        bytes.extend(value.to_le_bytes()[..size as usize].iter().cloned());
+    }
+    Ok(bytes)
+}
+
+/// Encodes `MOVE` and `MOVES` between registers, or returns `None` for
+/// other instructions.
+fn encode_move(name: &str, operands: &[Operand<u32>]) -> Result<Option<ArrayVec<u8, 4>>, Error> {
+    let (mnemonic, second) = if name.eq_ignore_ascii_case("MOVE") {
+        ("MOVE", 0x10)
+    } else if name.eq_ignore_ascii_case("MOVES") {
+        ("MOVES", 0xB0)
+    } else {
+        return Ok(None);
+    };
+    match *operands {
+        [Operand::Register(destination), Operand::Register(source)] => {
+            let mut bytes = ArrayVec::new();
+            if mnemonic == "MOVE" {
+                bytes.extend([0x20 + source, second + destination].iter().cloned());
+            } else {
+                bytes.extend([0x20 + destination, second + source].iter().cloned());
+            }
+            Ok(Some(bytes))
+        }
+        _ => Err(Error::UnsupportedOperands(mnemonic)),
+    }
+}
+
+fn in_range(value: u32, low: u8, high: u8) -> Result<u32, Error> {
+    if (u32::from(low)..=u32::from(high)).contains(&value) {
+        Ok(value)
+    } else {
+        Err(Error::OutOfRange(value))
+    }
+}
+
+/// Checks whether a mode can encode an operand, ignoring its value.
+///
+/// Registers outside of the range of a mode are rejected, so that an
+/// instruction with another range can be chosen.
+fn accepts(mode: Mode, operand: &Operand<u32>) -> bool {
+    match (mode, operand) {
+        (Register(low, high), &Operand::Register(register))
+        | (RegisterIndirect(low, high), &Operand::RegisterIndirect(register)) => {
+            (low..=high).contains(&register)
+        }
+        (Nibble(..), &Operand::Immediate(_))
+        | (Byte, &Operand::Immediate(_))
+        | (Word, &Operand::Immediate(_))
+        | (ShortAddress, &Operand::Memory(_))
+        | (Address, &Operand::Memory(_))
+        | (Relative, &Operand::Address(_)) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,842 @@
+//! Expression evaluation.
+//!
+//! Expressions are evaluated to signed 64-bit integers, so that
+//! intermediate results like `label - 4` near zero don't wrap around.
+//! Values are only truncated when written into a ROM, see `truncate`.
+//!
+//! Values of labels and user-defined functions are not known to the
+//! evaluator itself, instead they are provided by a `Context`, which is
+//! usually the assembler state.
+//!
+//! Built-in math functions are available for generating tables, unless
+//! shadowed by a user-defined function with the same name:
+//!
+//! - `sin(angle, amplitude)` and `cos(angle, amplitude)` take an angle in
+//!   256ths of a full turn, as in SNES sine tables, and return sine or
+//!   cosine multiplied by `amplitude`, rounded to the nearest integer,
+//! - `sqrt(value)` is the square root, rounded down,
+//! - `log(value[, base])` is the logarithm, in base 2 by default, rounded
+//!   down,
+//! - `min(values...)` and `max(values...)` take one or more values,
+//! - `clamp(value, min, max)` limits a value to a range,
+//! - `floor(value, divisor)` is division rounded down, unlike `/`, which
+//!   rounds towards zero,
+//! - `snestopc(address)` converts a SNES address into a file offset, and
+//!   `pctosnes(offset)` converts a file offset into a SNES address, with
+//!   the mapper given by a `Context`, see `mapping`.
+//!
+//! Text assigned with `name := expression` is used by `evaluate_string`,
+//! and building text is supported by string functions, see there.
+//!
+//! `datasize(label)` is the distance from a label to the next label after
+//! it, which can be a label marking the end of data, or to the end of code
+//! when there is no such label.
+//!
+//! `defined(name)` is 1 when a symbol with a name given as a string
+//! literal, like `"!feature"`, is defined so far, and 0 otherwise.
+//!
+//! Files provided by a `Context`, usually a ROM being patched, can be read
+//! by built-in functions taking a path as a string literal:
+//!
+//! - `readfile1(path, offset[, default])` reads a byte, and `readfile2`,
+//!   `readfile3` and `readfile4` read little endian values of 2 to 4
+//!   bytes; `default` is returned when a file can't be read that far,
+//! - `filesize(path)` is the size of a file,
+//! - `canread(path[, offset[, length]])` is 1 when a file can be read,
+//!   up to `length` bytes from `offset`, and 0 otherwise.
+
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+
+use mapping::Mapper;
+use parser::ast::{
+    BinaryOperator, Expression, Function, Label, Number, NumberWidth, Span, Spanned, Statement,
+    VariableName,
+};
+use parser::fold::{self, Folder};
+use parser::visit::{self, Visitor};
+
+/// Maximum nesting of user-defined function calls.
+const MAX_CALL_DEPTH: usize = 64;
+
+/// Names of built-in math functions.
+const BUILTINS: &[&str] = &[
+    "sin", "cos", "sqrt", "log", "min", "max", "clamp", "floor", "snestopc", "pctosnes",
+];
+
+/// Names of built-in functions reading files.
+const FILE_FUNCTIONS: &[&str] = &[
+    "readfile1",
+    "readfile2",
+    "readfile3",
+    "readfile4",
+    "filesize",
+    "canread",
+];
+
+/// A source of label values and functions.
+///
+/// This trait is implemented for closures returning label values, which is
+/// convenient when the values of labels are stored in a map, and no
+/// functions are defined.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::eval;
+/// use mvp::parser::ast::{Label, VariableName};
+/// use mvp::parser::grammar::{self, Input};
+///
+/// let (expression, _) = grammar::parse_expression(Input::new("base + 2")).unwrap();
+/// let context = |label: &Label| match *label {
+///     Label::Named(VariableName("base")) => Some(0x8000),
+///     _ => None,
+/// };
+/// assert_eq!(eval::evaluate(&expression, &context), Ok(0x8002));
+/// ```
+pub trait Context<'a> {
+    /// Value of a label, or `None` if a label isn't defined.
+    fn label(&self, label: &Label) -> Option<i64>;
+
+    /// User-defined function, or `None` if a function isn't defined.
+    fn function(&self, _name: &VariableName) -> Option<&Function<'a>> {
+        None
+    }
+
+    /// Current output address, or `None` outside of code.
+    fn program_counter(&self) -> Option<u32> {
+        None
+    }
+
+    /// Contents of a file read by built-in functions, or `None` if a file
+    /// can't be read.
+    fn file(&self, _path: &str) -> Option<&[u8]> {
+        None
+    }
+
+    /// Whether a symbol is defined, as checked by `defined`.
+    ///
+    /// By default, a symbol is defined when it has a value, but a context
+    /// which knows values of symbols defined later can limit this to
+    /// symbols defined so far.
+    fn defined(&self, name: &str) -> bool {
+        self.label(&Label::Named(VariableName(name))).is_some()
+    }
+
+    /// Mapper used by `snestopc` and `pctosnes`.
+    fn mapper(&self) -> Mapper {
+        Mapper::default()
+    }
+
+    /// Distance from a label to the next label, as returned by
+    /// `datasize`, or `None` if it isn't known.
+    fn datasize(&self, _label: &Label) -> Option<i64> {
+        None
+    }
+
+    /// Text assigned to a name, or `None` if a name has no text.
+    fn text(&self, _name: &str) -> Option<&str> {
+        None
+    }
+}
+
+impl<'a, F> Context<'a> for F
+where
+    F: Fn(&Label) -> Option<i64>,
+{
+    fn label(&self, label: &Label) -> Option<i64> {
+        self(label)
+    }
+}
+
+/// An evaluation failure.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error<'a> {
+    /// Label has no value in the context.
+    UndefinedLabel(Label<'a>),
+    /// There is no function with the given name.
+    UnknownFunction(VariableName<'a>),
+    /// Result doesn't fit in 64 bits, or a value doesn't fit in its
+    /// destination.
+    Overflow,
+    /// Division by zero.
+    DivisionByZero,
+    /// Condition of an `assert` statement evaluated to zero.
+    AssertionFailed(Option<&'a str>),
+    /// A string was used where a number was expected.
+    ExpectedNumber,
+    /// Function was called with a wrong number of arguments.
+    WrongArgumentCount(VariableName<'a>),
+    /// User-defined functions were nested too deeply, usually because of
+    /// a recursive function.
+    CallDepthExceeded(VariableName<'a>),
+    /// Program counter was used where there is no output address.
+    UnknownProgramCounter,
+    /// `sizeof(...)` was used outside of a variadic macro.
+    ArgumentCount,
+    /// Built-in function was called with an argument outside of its
+    /// domain, like a square root of a negative number.
+    InvalidArgument(VariableName<'a>),
+    /// A number was used where a string was expected, like a path of
+    /// a file.
+    ExpectedString,
+    /// File couldn't be read, or a read is past its end.
+    UnreadableFile(&'a str),
+    /// Address isn't mapped to ROM, or a file offset is past the end of
+    /// ROM, in the current mapper.
+    Unmapped(i64),
+    /// Expression has an invalid operand.
+    InvalidExpression(Span<'a>),
+}
+
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UndefinedLabel(ref label) => write!(f, "{} isn't defined", label),
+            Error::UnknownFunction(ref name) => write!(f, "function {} doesn't exist", name.0),
+            Error::Overflow => write!(f, "value doesn't fit"),
+            Error::DivisionByZero => write!(f, "division by zero"),
+            Error::AssertionFailed(Some(message)) => write!(f, "assertion failed: {}", message),
+            Error::AssertionFailed(None) => write!(f, "assertion failed"),
+            Error::ExpectedNumber => write!(f, "expected a number, found a string"),
+            Error::WrongArgumentCount(ref name) => {
+                write!(f, "wrong number of arguments given to {}", name.0)
+            }
+            Error::CallDepthExceeded(ref name) => {
+                write!(f, "calls of {} are nested too deeply", name.0)
+            }
+            Error::UnknownProgramCounter => write!(f, "program counter isn't known here"),
+            Error::ArgumentCount => write!(f, "sizeof(...) used outside of a variadic macro"),
+            Error::InvalidArgument(ref name) => write!(f, "invalid argument of {}", name.0),
+            Error::ExpectedString => write!(f, "expected a string, found a number"),
+            Error::UnreadableFile(path) => write!(f, "file {} couldn't be read", path),
+            // Negative values would be shown in two's complement in hex.
+            Error::Unmapped(address) if address < 0 => {
+                write!(f, "{} is negative, so it isn't mapped to ROM", address)
+            }
+            Error::Unmapped(address) => write!(f, "{:#X} isn't mapped to ROM", address),
+            Error::InvalidExpression(span) => {
+                write!(f, "invalid expression `{}`", span.fragment().trim())
+            }
+        }
+    }
+}
+
+impl<'a> error::Error for Error<'a> {}
+
+/// Evaluates an expression.
+pub fn evaluate<'a, C: Context<'a>>(
+    expression: &Expression<'a>,
+    context: &C,
+) -> Result<i64, Error<'a>> {
+    evaluate_nested(expression, context, 0)
+}
+
+impl<'a> Expression<'a> {
+    /// Evaluates an expression, same as `evaluate`.
+    pub fn evaluate<C: Context<'a>>(&self, context: &C) -> Result<i64, Error<'a>> {
+        evaluate(self, context)
+    }
+}
+
+/// Finds the innermost operand of an expression which fails to evaluate
+/// with the same error as the whole expression, so that the error can
+/// point at it.
+///
+/// Returns `None` when no operand fails that way, as in `1 / 0`, where
+/// the operator itself fails.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::eval;
+/// use mvp::parser::ast::Label;
+/// use mvp::parser::grammar;
+///
+/// let context = |_: &Label| None;
+/// let (expression, _) = grammar::parse_expression("2 * (1 + missing)").unwrap();
+/// let error = eval::evaluate(&expression, &context).unwrap_err();
+/// let span = eval::error_span(&expression, &context, &error).unwrap();
+/// assert_eq!(span.fragment(), "missing");
+///
+/// let (expression, _) = grammar::parse_expression("1 / 0").unwrap();
+/// let error = eval::evaluate(&expression, &context).unwrap_err();
+/// assert_eq!(eval::error_span(&expression, &context, &error), None);
+/// ```
+pub fn error_span<'a, C: Context<'a>>(
+    expression: &Expression<'a>,
+    context: &C,
+    error: &Error<'a>,
+) -> Option<Span<'a>> {
+    let operands: Vec<&Spanned<'a, Expression<'a>>> = match *expression {
+        Expression::Binary(_, ref operands) => vec![&operands.0, &operands.1],
+        Expression::Negate(ref operand) => vec![operand],
+        // Arguments which aren't evaluated as numbers, like a path given
+        // to `readfile1`, can't be the cause.
+        Expression::Call(ref name, ref arguments)
+            if context.function(name).is_some() || BUILTINS.contains(&name.0) =>
+        {
+            arguments.iter().collect()
+        }
+        Expression::Call(ref name, ref arguments) if FILE_FUNCTIONS.contains(&name.0) => {
+            arguments.iter().skip(1).collect()
+        }
+        _ => Vec::new(),
+    };
+    let operand = operands
+        .into_iter()
+        .find(|operand| evaluate(operand, context).as_ref() == Err(error))?;
+    error_span(operand, context, error)
+        .or_else(|| Some(operand.span).filter(|span| !span.fragment().is_empty()))
+}
+
+fn evaluate_nested<'a, C: Context<'a>>(
+    expression: &Expression<'a>,
+    context: &C,
+    depth: usize,
+) -> Result<i64, Error<'a>> {
+    match *expression {
+        Expression::Number(ref number) => Ok(number.value.into()),
+        Expression::Variable(Label::Named(VariableName(name))) if context.text(name).is_some() => {
+            Err(Error::ExpectedNumber)
+        }
+        Expression::Variable(ref label) => context
+            .label(label)
+            .ok_or_else(|| Error::UndefinedLabel(label.clone())),
+        Expression::Binary(operator, ref operands) => {
+            let left = evaluate_nested(&operands.0, context, depth)?;
+            let right = evaluate_nested(&operands.1, context, depth)?;
+            binary(operator, left, right)
+        }
+        Expression::Call(ref name, ref arguments) => match context.function(name) {
+            Some(function) => call(function, arguments, context, depth),
+            None if is_string_function(name) => Err(Error::ExpectedNumber),
+            None if name.0 == "defined" => match arguments[..] {
+                [Spanned {
+                    node: Expression::String(name),
+                    ..
+                }] => Ok(context.defined(name).into()),
+                [_] => Err(Error::ExpectedString),
+                _ => Err(Error::WrongArgumentCount(name.clone())),
+            },
+            None if name.0 == "datasize" => match arguments[..] {
+                [Spanned {
+                    node: Expression::Variable(ref label),
+                    ..
+                }] => context
+                    .datasize(label)
+                    .ok_or_else(|| Error::UndefinedLabel(label.clone())),
+                [_] => Err(Error::InvalidArgument(name.clone())),
+                _ => Err(Error::WrongArgumentCount(name.clone())),
+            },
+            None if FILE_FUNCTIONS.contains(&name.0) => {
+                file_function(name, arguments, context, depth)
+            }
+            None if BUILTINS.contains(&name.0) => {
+                let values = arguments
+                    .iter()
+                    .map(|argument| evaluate_nested(argument, context, depth))
+                    .collect::<Result<Vec<_>, _>>()?;
+                builtin(name, &values, context.mapper())
+            }
+            None => Err(Error::UnknownFunction(name.clone())),
+        },
+        Expression::String(_) => Err(Error::ExpectedNumber),
+        Expression::ProgramCounter => context
+            .program_counter()
+            .map(i64::from)
+            .ok_or(Error::UnknownProgramCounter),
+        Expression::Negate(ref operand) => evaluate_nested(operand, context, depth)?
+            .checked_neg()
+            .ok_or(Error::Overflow),
+        Expression::ArgumentCount => Err(Error::ArgumentCount),
+        Expression::Error(span) => Err(Error::InvalidExpression(span)),
+    }
+}
+
+/// Simplifies subexpressions consisting only of numbers.
+///
+/// Folding doesn't need a context, so it can be done right after parsing.
+/// Operations which would fail, like division by zero, are left as is, so
+/// that an error is reported when an expression is evaluated. Folded
+/// numbers keep width of a subexpression they replace, as described in
+/// `Expression::width`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::eval::ConstantFolder;
+/// use mvp::parser::fold::Folder;
+/// use mvp::parser::grammar::{self, Input};
+///
+/// let (expression, _) = grammar::parse_expression(Input::new("base + 2 * 3 - 10")).unwrap();
+/// let (expected, _) = grammar::parse_expression(Input::new("base + 6 - 10")).unwrap();
+/// assert_eq!(ConstantFolder.fold_expression(expression), expected);
+///
+/// let (expression, _) = grammar::parse_expression(Input::new("(1 - 3) / 0")).unwrap();
+/// let (expected, _) = grammar::parse_expression(Input::new("-2 / 0")).unwrap();
+/// assert_eq!(ConstantFolder.fold_expression(expression), expected);
+/// ```
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ConstantFolder;
+
+impl<'a> Folder<'a> for ConstantFolder {
+    fn fold_expression(&mut self, expression: Expression<'a>) -> Expression<'a> {
+        let expression = fold::fold_expression(self, expression);
+        let width = expression.width();
+        let value = match expression {
+            Expression::Binary(operator, ref operands) => {
+                match (constant(&operands.0), constant(&operands.1)) {
+                    (Some(left), Some(right)) => binary(operator, left, right).ok(),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        value
+            .and_then(|value| constant_expression(value, width))
+            .unwrap_or(expression)
+    }
+}
+
+/// Value of a number, or a negated number.
+fn constant(expression: &Expression) -> Option<i64> {
+    match *expression {
+        Expression::Number(ref number) => Some(number.value.into()),
+        Expression::Negate(ref operand) => match operand.node {
+            Expression::Number(ref number) => Some(-i64::from(number.value)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn constant_expression<'a>(value: i64, width: NumberWidth) -> Option<Expression<'a>> {
+    let number = |value| Expression::Number(Number { value, width });
+    match u32::try_from(value) {
+        Ok(value) => Some(number(value)),
+        Err(_) => u32::try_from(value.checked_neg()?)
+            .ok()
+            .map(|value| Expression::Negate(Box::new(number(value).into()))),
+    }
+}
+
+fn call<'a, C: Context<'a>>(
+    function: &Function<'a>,
+    arguments: &[Spanned<'a, Expression<'a>>],
+    context: &C,
+    depth: usize,
+) -> Result<i64, Error<'a>> {
+    if arguments.len() != function.parameters.len() {
+        return Err(Error::WrongArgumentCount(function.name.clone()));
+    }
+    if depth == MAX_CALL_DEPTH {
+        return Err(Error::CallDepthExceeded(function.name.clone()));
+    }
+    let values = arguments
+        .iter()
+        .map(|argument| evaluate_nested(argument, context, depth))
+        .collect::<Result<_, _>>()?;
+    let scope = Scope {
+        parameters: &function.parameters,
+        values,
+        parent: context,
+    };
+    evaluate_nested(&function.body, &scope, depth + 1)
+}
+
+/// Context of a function body, where parameters shadow labels.
+///
+/// Parent context is a trait object, as otherwise nested calls would
+/// require infinitely many instantiations of `evaluate_nested`.
+struct Scope<'s, 'a: 's> {
+    parameters: &'s [VariableName<'a>],
+    values: Vec<i64>,
+    parent: &'s dyn Context<'a>,
+}
+
+impl<'s, 'a> Context<'a> for Scope<'s, 'a> {
+    fn label(&self, label: &Label) -> Option<i64> {
+        if let Label::Named(VariableName(name)) = *label {
+            if let Some(index) = self.parameter(name) {
+                return Some(self.values[index]);
+            }
+        }
+        self.parent.label(label)
+    }
+
+    fn function(&self, name: &VariableName) -> Option<&Function<'a>> {
+        self.parent.function(name)
+    }
+
+    fn program_counter(&self) -> Option<u32> {
+        self.parent.program_counter()
+    }
+
+    fn file(&self, path: &str) -> Option<&[u8]> {
+        self.parent.file(path)
+    }
+
+    fn defined(&self, name: &str) -> bool {
+        self.parameter(name).is_some() || self.parent.defined(name)
+    }
+
+    fn mapper(&self) -> Mapper {
+        self.parent.mapper()
+    }
+
+    fn datasize(&self, label: &Label) -> Option<i64> {
+        match *label {
+            Label::Named(VariableName(name)) if self.parameter(name).is_some() => None,
+            _ => self.parent.datasize(label),
+        }
+    }
+
+    fn text(&self, name: &str) -> Option<&str> {
+        self.parent.text(name)
+    }
+}
+
+impl<'s, 'a> Scope<'s, 'a> {
+    /// Index of a parameter with a name.
+    fn parameter(&self, name: &str) -> Option<usize> {
+        self.parameters.iter().position(|p| p.0 == name)
+    }
+}
+
+fn binary<'a>(operator: BinaryOperator, left: i64, right: i64) -> Result<i64, Error<'a>> {
+    let result = match operator {
+        BinaryOperator::Add => left.checked_add(right),
+        BinaryOperator::Sub => left.checked_sub(right),
+        BinaryOperator::Mul => left.checked_mul(right),
+        BinaryOperator::Div => {
+            if right == 0 {
+                return Err(Error::DivisionByZero);
+            }
+            left.checked_div(right)
+        }
+        BinaryOperator::Shl => shift_amount(right).and_then(|right| left.checked_shl(right)),
+        BinaryOperator::Shr => shift_amount(right).and_then(|right| left.checked_shr(right)),
+        BinaryOperator::Xor => Some(left ^ right),
+        BinaryOperator::And => Some(left & right),
+        BinaryOperator::Or => Some(left | right),
+        BinaryOperator::Eq => Some((left == right).into()),
+        BinaryOperator::Ne => Some((left != right).into()),
+        BinaryOperator::Lt => Some((left < right).into()),
+        BinaryOperator::Le => Some((left <= right).into()),
+        BinaryOperator::Gt => Some((left > right).into()),
+        BinaryOperator::Ge => Some((left >= right).into()),
+    };
+    result.ok_or(Error::Overflow)
+}
+
+fn shift_amount(value: i64) -> Option<u32> {
+    u32::try_from(value).ok()
+}
+
+/// Calls a built-in math function, see the module documentation.
+fn builtin<'a>(
+    name: &VariableName<'a>,
+    arguments: &[i64],
+    mapper: Mapper,
+) -> Result<i64, Error<'a>> {
+    let invalid = || Error::InvalidArgument(name.clone());
+    match (name.0, arguments) {
+        ("sin", &[angle, amplitude]) => Ok(sine(angle, amplitude)),
+        ("cos", &[angle, amplitude]) => Ok(sine(angle.rem_euclid(256) + 64, amplitude)),
+        ("sqrt", &[value]) if value >= 0 => Ok(integer_sqrt(value)),
+        ("log", &[value]) => log(value, 2).ok_or_else(invalid),
+        ("log", &[value, base]) => log(value, base).ok_or_else(invalid),
+        ("min", values) if !values.is_empty() => Ok(values.iter().cloned().min().unwrap()),
+        ("max", values) if !values.is_empty() => Ok(values.iter().cloned().max().unwrap()),
+        ("clamp", &[value, min, max]) if min <= max => Ok(value.clamp(min, max)),
+        ("floor", &[_, 0]) => Err(Error::DivisionByZero),
+        ("floor", &[value, divisor]) => floor_division(value, divisor).ok_or(Error::Overflow),
+        ("snestopc", &[address]) => u32::try_from(address)
+            .ok()
+            .and_then(|address| mapper.file_offset(address))
+            .map(|offset| offset as i64)
+            .ok_or(Error::Unmapped(address)),
+        ("pctosnes", &[offset]) => usize::try_from(offset)
+            .ok()
+            .and_then(|offset| mapper.address(offset))
+            .map(i64::from)
+            .ok_or(Error::Unmapped(offset)),
+        ("sqrt", &[_]) | ("clamp", &[_, _, _]) => Err(invalid()),
+        _ => Err(Error::WrongArgumentCount(name.clone())),
+    }
+}
+
+/// Lists paths of files read by built-in functions in statements, so that
+/// they can be loaded before assembly.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::eval;
+/// use mvp::parser::grammar::parse_program;
+///
+/// let statements = parse_program("assert readfile1(\"rom.sfc\", $7FDC) != 0\n").unwrap();
+/// assert_eq!(eval::file_paths(&statements), ["rom.sfc"]);
+/// ```
+pub fn file_paths<'a>(statements: &[Spanned<'a, Statement<'a>>]) -> Vec<&'a str> {
+    let mut paths = FilePaths(Vec::new());
+    visit::walk_statements(&mut paths, statements);
+    paths.0
+}
+
+struct FilePaths<'a>(Vec<&'a str>);
+
+impl<'a> Visitor<'a> for FilePaths<'a> {
+    fn visit_expression(&mut self, expression: &Expression<'a>) {
+        if let Expression::Call(VariableName(name), ref arguments) = *expression {
+            if let (true, Some(Expression::String(path))) = (
+                FILE_FUNCTIONS.contains(&name),
+                arguments.first().map(|argument| &argument.node),
+            ) {
+                if !self.0.contains(path) {
+                    self.0.push(path);
+                }
+            }
+        }
+        visit::walk_expression(self, expression);
+    }
+}
+
+/// Calls a built-in function reading a file, see the module
+/// documentation.
+fn file_function<'a, C: Context<'a>>(
+    name: &VariableName<'a>,
+    arguments: &[Spanned<'a, Expression<'a>>],
+    context: &C,
+    depth: usize,
+) -> Result<i64, Error<'a>> {
+    let path = match arguments.first().map(|argument| &argument.node) {
+        Some(&Expression::String(path)) => path,
+        Some(_) => return Err(Error::ExpectedString),
+        None => return Err(Error::WrongArgumentCount(name.clone())),
+    };
+    let values = arguments[1..]
+        .iter()
+        .map(|argument| evaluate_nested(argument, context, depth))
+        .collect::<Result<Vec<_>, _>>()?;
+    let file = context.file(path);
+    let read = |offset: i64, length: i64| {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(usize::try_from(length).ok()?)?;
+        file?.get(start..end)
+    };
+    let size = match name.0 {
+        "readfile1" => 1,
+        "readfile2" => 2,
+        "readfile3" => 3,
+        "readfile4" => 4,
+        "filesize" if values.is_empty() => {
+            return file
+                .map(|file| file.len() as i64)
+                .ok_or(Error::UnreadableFile(path));
+        }
+        "canread" if values.len() <= 2 => {
+            let offset = values.first().cloned().unwrap_or(0);
+            let length = values.get(1).cloned().unwrap_or(0);
+            return Ok(read(offset, length).is_some().into());
+        }
+        _ => return Err(Error::WrongArgumentCount(name.clone())),
+    };
+    match (
+        &values[..],
+        read(values.first().cloned().unwrap_or(0), size),
+    ) {
+        (&[_], Some(bytes)) | (&[_, _], Some(bytes)) => Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | i64::from(byte))),
+        (&[_, default], None) => Ok(default),
+        (&[_], None) => Err(Error::UnreadableFile(path)),
+        _ => Err(Error::WrongArgumentCount(name.clone())),
+    }
+}
+
+/// Sine of an angle in 256ths of a turn, multiplied by an amplitude.
+fn sine(angle: i64, amplitude: i64) -> i64 {
+    let radians = (angle.rem_euclid(256) as f64) * std::f64::consts::PI / 128.0;
+    (radians.sin() * amplitude as f64).round() as i64
+}
+
+fn integer_sqrt(value: i64) -> i64 {
+    let mut root = (value as f64).sqrt() as i64;
+    // Floating point square root can be off by one for large values.
+    while root * root > value {
+        root -= 1;
+    }
+    while (root + 1)
+        .checked_mul(root + 1)
+        .is_some_and(|square| square <= value)
+    {
+        root += 1;
+    }
+    root
+}
+
+fn log(value: i64, base: i64) -> Option<i64> {
+    if value <= 0 || base < 2 {
+        return None;
+    }
+    Some(value.ilog(base).into())
+}
+
+fn floor_division(value: i64, divisor: i64) -> Option<i64> {
+    let quotient = value.checked_div(divisor)?;
+    if value % divisor != 0 && (value < 0) != (divisor < 0) {
+        Some(quotient - 1)
+    } else {
+        Some(quotient)
+    }
+}
+
+/// Truncates a value to `size` bytes, as it's written into a ROM.
+///
+/// Negative values are stored in two's complement, so `-1` written as
+/// a single byte is `$FF`. Values which fit in `size` bytes neither as
+/// signed nor unsigned integers are rejected, as is any value when `size`
+/// isn't between 1 and 4.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::eval::{self, Error};
+///
+/// assert_eq!(eval::truncate(-1, 1), Ok(0xFF));
+/// assert_eq!(eval::truncate(-2, 2), Ok(0xFFFE));
+/// assert_eq!(eval::truncate(0xFF, 1), Ok(0xFF));
+/// assert_eq!(eval::truncate(0x100, 1), Err(Error::Overflow));
+/// assert_eq!(eval::truncate(-0x81, 1), Err(Error::Overflow));
+/// assert_eq!(eval::truncate(0, 0), Err(Error::Overflow));
+/// ```
+pub fn truncate<'a>(value: i64, size: u32) -> Result<u32, Error<'a>> {
+    if !(1..=4).contains(&size) {
+        return Err(Error::Overflow);
+    }
+    let bits = 8 * size;
+    let minimum = -(1 << (bits - 1));
+    let maximum = (1 << bits) - 1;
+    if value < minimum || value > maximum {
+        return Err(Error::Overflow);
+    }
+    Ok((value & maximum) as u32)
+}
+
+/// Checks a condition of an `assert` statement.
+///
+/// Zero is treated as false, any other value as true. When the condition
+/// is false, the statement message is returned in an error.
+pub fn assert<'a, C: Context<'a>>(
+    condition: &Expression<'a>,
+    message: Option<&'a str>,
+    context: &C,
+) -> Result<(), Error<'a>> {
+    if evaluate(condition, context)? == 0 {
+        Err(Error::AssertionFailed(message))
+    } else {
+        Ok(())
+    }
+}
+
+fn is_string_function(name: &VariableName) -> bool {
+    matches!(name.0, "hex" | "dec" | "bin" | "concat" | "stringify")
+}
+
+/// Evaluates an expression to text.
+///
+/// String literals and names with text are returned as is, and numbers
+/// are written in decimal. In addition, string functions are available:
+///
+/// - `dec(value)` writes a number in decimal,
+/// - `hex(value[, digits])` writes a number in uppercase hexadecimal,
+/// - `bin(value[, digits])` writes a number in binary,
+/// - `concat(values...)` joins its arguments, each evaluated to text,
+/// - `stringify(expression)` writes an expression without evaluating it,
+///   which in a macro gives the source of an argument,
+///
+/// where `digits` is the minimal number of digits, padded with zeroes.
+/// Negative numbers are written with a minus sign.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::eval;
+/// use mvp::parser::ast::Label;
+/// use mvp::parser::grammar::{self, Input};
+///
+/// let (expression, _) = grammar::parse_expression(Input::new("hex(42 * 2, 4)")).unwrap();
+/// let context = |_: &Label| None;
+/// assert_eq!(eval::evaluate_string(&expression, &context), Ok("0054".to_string()));
+/// ```
+pub fn evaluate_string<'a, C: Context<'a>>(
+    expression: &Expression<'a>,
+    context: &C,
+) -> Result<String, Error<'a>> {
+    if let Expression::Variable(Label::Named(VariableName(name))) = *expression {
+        if let Some(text) = context.text(name) {
+            return Ok(text.to_string());
+        }
+    }
+    match *expression {
+        Expression::String(string) => Ok(string.to_string()),
+        Expression::Call(ref name, ref arguments)
+            if is_string_function(name) && context.function(name).is_none() =>
+        {
+            match (name.0, &arguments[..]) {
+                ("concat", _) => {
+                    return arguments
+                        .iter()
+                        .map(|argument| evaluate_string(argument, context))
+                        .collect();
+                }
+                ("stringify", [argument]) => return Ok(argument.to_string()),
+                ("stringify", _) => return Err(Error::WrongArgumentCount(name.clone())),
+                _ => {}
+            }
+            let (value, digits) = match arguments.len() {
+                1 => (evaluate(&arguments[0], context)?, 0),
+                2 if name.0 != "dec" => (
+                    evaluate(&arguments[0], context)?,
+                    digits(evaluate(&arguments[1], context)?)?,
+                ),
+                _ => return Err(Error::WrongArgumentCount(name.clone())),
+            };
+            let sign = if value < 0 { "-" } else { "" };
+            let magnitude = value.unsigned_abs();
+            Ok(match name.0 {
+                "hex" => format!("{}{:02$X}", sign, magnitude, digits),
+                "bin" => format!("{}{:02$b}", sign, magnitude, digits),
+                _ => value.to_string(),
+            })
+        }
+        _ => evaluate(expression, context).map(|value| value.to_string()),
+    }
+}
+
+fn digits<'a>(value: i64) -> Result<usize, Error<'a>> {
+    if (0..=64).contains(&value) {
+        Ok(value as usize)
+    } else {
+        Err(Error::Overflow)
+    }
+}
+
+/// Formats arguments of a `print` statement.
+///
+/// Every argument is evaluated with `evaluate_string`, and the results are
+/// concatenated.
+pub fn print<'a, C: Context<'a>>(
+    arguments: &[Expression<'a>],
+    context: &C,
+) -> Result<String, Error<'a>> {
+    let mut output = String::new();
+    for argument in arguments {
+        output += &evaluate_string(argument, context)?;
+    }
+    Ok(output)
+}
@@ -0,0 +1,461 @@
+//! Formatting of source code.
+//!
+//! `format` rewrites source code in one style:
+//!
+//! - labels and assignments start at the indentation of their block, and
+//!   other statements are indented one level deeper, with `if` and
+//!   `spcblock` bodies indented further,
+//! - operands are spaced the same way everywhere, with no spaces in
+//!   operands of instructions, as in `LDA Table+2,x`,
+//! - mnemonics get the same case,
+//! - operands and comments of consecutive statements are aligned in
+//!   columns.
+//!
+//! Formatting works on tokens of the lexer, which keep everything but
+//! whitespace, so comments and blank lines stay where they were. Formatted
+//! code is parsed again and compared with the original, and code whose
+//! meaning would change isn't formatted.
+
+use std::error;
+use std::fmt;
+use std::ops::Range;
+
+use parser::ast::{normalize_mnemonic, Span};
+use parser::grammar::{self, Input, ParserOptions, SyntaxError};
+use parser::lexer::{Lexer, Token, TokenKind};
+use parser::semantic;
+
+/// Case of instruction mnemonics.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Case {
+    /// `LDA.b`, with width suffixes in lowercase.
+    Upper,
+    Lower,
+    /// Mnemonics as written.
+    Preserve,
+}
+
+/// How code is formatted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Options {
+    /// Spaces per indentation level.
+    pub indent: usize,
+    pub case: Case,
+    /// Align operands of consecutive statements in a column.
+    pub align_operands: bool,
+    /// Align comments after consecutive statements in a column.
+    pub align_comments: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            indent: 4,
+            case: Case::Upper,
+            align_operands: true,
+            align_comments: true,
+        }
+    }
+}
+
+/// An error formatting code.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error<'a> {
+    /// Code doesn't parse.
+    Syntax(SyntaxError<'a>),
+    /// Formatting would change the meaning of a line.
+    Changed(Span<'a>),
+}
+
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Syntax(ref error) => write!(f, "{}", error),
+            Error::Changed(span) => write!(f, "formatting would change `{}`", span.fragment()),
+        }
+    }
+}
+
+impl<'a> error::Error for Error<'a> {}
+
+/// Formats code.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::format::{self, Options};
+///
+/// let source = "main:\n  lda.B  Table + 2 , x;load\n    if !debug\nstz $10 ; clear\n endif\n";
+/// assert_eq!(
+///     format::format(source, &Options::default()).unwrap(),
+///     "main:\n    LDA.b Table+2,x ;load\n    if !debug\n        STZ $10 ; clear\n    endif\n",
+/// );
+/// ```
+pub fn format<'a, I: Into<Input<'a>>>(input: I, options: &Options) -> Result<String, Error<'a>> {
+    let input = input.into();
+    let source = input.fragment();
+    // Mnemonics are compared regardless of case.
+    let parser_options = ParserOptions {
+        normalize_mnemonics: true,
+        ..input.options()
+    };
+    let original = grammar::parse_program(Input::with_options(source, parser_options))
+        .map_err(Error::Syntax)?;
+    let mnemonics: Vec<_> = semantic::semantic_tokens(input)
+        .into_iter()
+        .filter(|&(_, kind)| kind == semantic::TokenKind::Mnemonic)
+        .filter_map(|(span, _)| span.range(source))
+        .collect();
+    let mut formatter = Formatter {
+        source,
+        options,
+        mnemonics,
+        depth: 0,
+        lines: Vec::new(),
+    };
+    let mut tokens = Vec::new();
+    for token in Lexer::new(source) {
+        if token.kind == TokenKind::Newline {
+            formatter.add_line(&tokens);
+            tokens.clear();
+        } else {
+            tokens.push(token);
+        }
+    }
+    if !tokens.is_empty() {
+        formatter.add_line(&tokens);
+    }
+    let newline = if source.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let formatted = formatter.finish(newline);
+    // Lines stay in place, so a line which changed is found by its number.
+    let line = match grammar::parse_program(Input::with_options(&formatted, parser_options)) {
+        Ok(ref statements) if *statements == original => return Ok(formatted),
+        Ok(statements) => statements
+            .iter()
+            .zip(&original)
+            .find(|&(statement, original)| statement != original)
+            .map(|(statement, _)| statement)
+            .or_else(|| statements.last())
+            .and_then(|statement| statement.span.line_column(&formatted))
+            .map_or(1, |(line, _)| line),
+        Err(error) => error.line as usize,
+    };
+    let text = source.lines().nth(line - 1).unwrap_or("");
+    Err(Error::Changed(Span::new(text.trim())))
+}
+
+/// A line of formatted code, before alignment.
+#[derive(Default)]
+struct Line<'a> {
+    /// Block nesting level.
+    depth: usize,
+    /// Whether a line is indented one level deeper than labels.
+    indented: bool,
+    /// Labels declared at the start of a line, like `Main: .loop:`.
+    labels: String,
+    /// Mnemonic or directive, or nothing for statements like assignments
+    /// and macro calls.
+    keyword: String,
+    /// Whether a keyword is a mnemonic, whose operands are aligned.
+    instruction: bool,
+    operands: String,
+    comment: Option<&'a str>,
+}
+
+impl<'a> Line<'a> {
+    fn has_code(&self) -> bool {
+        !self.keyword.is_empty() || !self.operands.is_empty()
+    }
+
+    /// Code of a line, with a mnemonic padded to a width.
+    fn code(&self, width: usize) -> String {
+        if self.keyword.is_empty() || self.operands.is_empty() {
+            return format!("{}{}", self.keyword, self.operands);
+        }
+        let padding = if self.instruction {
+            width.saturating_sub(self.keyword.chars().count())
+        } else {
+            0
+        };
+        format!(
+            "{}{:padding$} {}",
+            self.keyword,
+            "",
+            self.operands,
+            padding = padding
+        )
+    }
+}
+
+struct Formatter<'a, 'o> {
+    source: &'a str,
+    options: &'o Options,
+    /// Byte ranges of instruction mnemonics, with width suffixes.
+    mnemonics: Vec<Range<usize>>,
+    depth: usize,
+    lines: Vec<Line<'a>>,
+}
+
+impl<'a, 'o> Formatter<'a, 'o> {
+    fn offset(&self, token: &Token) -> usize {
+        token.span.range(self.source).unwrap().start
+    }
+
+    /// Range of a mnemonic starting at an offset.
+    fn mnemonic(&self, start: usize) -> Option<&Range<usize>> {
+        self.mnemonics.iter().find(|range| range.start == start)
+    }
+
+    fn add_line(&mut self, mut tokens: &[Token<'a>]) {
+        let mut line = Line {
+            indented: tokens.first().is_some_and(|token| {
+                let start = self.offset(token);
+                start > 0 && !self.source[..start].ends_with('\n')
+            }),
+            ..Line::default()
+        };
+        while let Some(length) = label_length(tokens) {
+            if !line.labels.is_empty() {
+                line.labels.push(' ');
+            }
+            for token in &tokens[..length] {
+                line.labels += token.span.fragment();
+            }
+            tokens = &tokens[length..];
+        }
+        if let Some((last, rest)) = tokens.split_last() {
+            if last.kind == TokenKind::Comment {
+                line.comment = Some(last.span.fragment().trim_end());
+                tokens = rest;
+            }
+        }
+        let first = tokens.first().filter(|first| {
+            first.kind == TokenKind::Mnemonic || self.mnemonic(self.offset(first)).is_some()
+        });
+        if let Some(first) = first {
+            let start = self.offset(first);
+            let end = match self.mnemonic(start) {
+                Some(range) => {
+                    line.instruction = true;
+                    range.end
+                }
+                None => start + first.span.fragment().len(),
+            };
+            let keyword = &self.source[start..end];
+            line.keyword = if line.instruction {
+                with_case(keyword, self.options.case)
+            } else {
+                keyword.to_string()
+            };
+            let length = tokens
+                .iter()
+                .take_while(|token| self.offset(token) < end)
+                .count();
+            tokens = &tokens[length..];
+        }
+        let keyword = line.keyword.to_ascii_lowercase();
+        line.operands = if keyword == "warnings" {
+            // Categories like `unused-label` aren't expressions.
+            self.words(tokens)
+        } else {
+            self.render(tokens, line.instruction)
+        };
+        if matches!(&*keyword, "elseif" | "else" | "endif" | "endspcblock") {
+            self.depth = self.depth.saturating_sub(1);
+        }
+        line.depth = self.depth;
+        if matches!(&*keyword, "if" | "elseif" | "else" | "spcblock") {
+            self.depth += 1;
+        }
+        if line.has_code() {
+            let is_assignment = line.keyword.is_empty()
+                && tokens.get(1).is_some_and(|token| {
+                    token.kind == TokenKind::Operator && matches!(token.span.fragment(), "=" | ":=")
+                });
+            let is_declaration = matches!(&*keyword, "macro" | "endmacro" | "function");
+            line.indented = !is_assignment && !is_declaration;
+        } else if !line.labels.is_empty() {
+            line.indented = false;
+        }
+        self.lines.push(line);
+    }
+
+    /// Joins tokens with normalized spacing.
+    fn render(&self, tokens: &[Token], instruction: bool) -> String {
+        let mut text = String::new();
+        for (index, token) in tokens.iter().enumerate() {
+            if index > 0 && self.space_before(tokens, index, instruction) {
+                text.push(' ');
+            }
+            text += token.span.fragment();
+        }
+        text
+    }
+
+    /// Joins tokens with one space where source code had any.
+    fn words(&self, tokens: &[Token]) -> String {
+        let mut text = String::new();
+        for (index, token) in tokens.iter().enumerate() {
+            if index > 0 && self.spaced(&tokens[index - 1], token) {
+                text.push(' ');
+            }
+            text += token.span.fragment();
+        }
+        text
+    }
+
+    fn spaced(&self, previous: &Token, token: &Token) -> bool {
+        self.offset(previous) + previous.span.fragment().len() < self.offset(token)
+    }
+
+    fn space_before(&self, tokens: &[Token], index: usize, instruction: bool) -> bool {
+        let (previous, token) = (&tokens[index - 1], &tokens[index]);
+        let text = token.span.fragment();
+        match previous.span.fragment() {
+            _ if text == "," || text == ")" || text == "]" => false,
+            "," => !instruction,
+            "(" | "[" | "#" | "." => false,
+            _ if text == "(" && previous.kind == TokenKind::Identifier => false,
+            _ if is_binary(tokens, index) || is_binary(tokens, index - 1) => !instruction,
+            _ if previous.kind == TokenKind::Operator => false,
+            _ => self.spaced(previous, token),
+        }
+    }
+
+    fn finish(self, newline: &str) -> String {
+        let indent = self.options.indent;
+        let mut rendered = Vec::with_capacity(self.lines.len());
+        let mut start = 0;
+        while start < self.lines.len() {
+            let column = |line: &Line| {
+                let mut column = (line.depth + line.indented as usize) * indent;
+                if !line.labels.is_empty() && line.has_code() {
+                    column = column.max(line.depth * indent + line.labels.chars().count() + 1);
+                }
+                column
+            };
+            // A group of consecutive statements starting at the same column.
+            let first = &self.lines[start];
+            let end = if first.has_code() {
+                start
+                    + self.lines[start..]
+                        .iter()
+                        .take_while(|line| line.has_code() && column(line) == column(first))
+                        .count()
+            } else {
+                start + 1
+            };
+            let group = &self.lines[start..end];
+            let width = if self.options.align_operands {
+                group
+                    .iter()
+                    .filter(|line| line.instruction && !line.operands.is_empty())
+                    .map(|line| line.keyword.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            } else {
+                0
+            };
+            let codes: Vec<_> = group
+                .iter()
+                .map(|line| {
+                    let mut text = String::new();
+                    if !line.labels.is_empty() {
+                        text += &" ".repeat(line.depth * indent);
+                        text += &line.labels;
+                    }
+                    if line.has_code() {
+                        let padding = column(line) - text.chars().count();
+                        text += &" ".repeat(padding);
+                        text += &line.code(width);
+                    }
+                    text
+                })
+                .collect();
+            let comment_column = if self.options.align_comments {
+                codes.iter().map(|code| code.chars().count()).max()
+            } else {
+                None
+            };
+            for (line, mut text) in group.iter().zip(codes) {
+                if let Some(comment) = line.comment {
+                    if text.is_empty() {
+                        text = " ".repeat((line.depth + line.indented as usize) * indent);
+                    } else {
+                        let length = text.chars().count();
+                        text += &" ".repeat(comment_column.unwrap_or(length) - length + 1);
+                    }
+                    text += comment;
+                }
+                rendered.push(text);
+            }
+            start = end;
+        }
+        while rendered.last().is_some_and(String::is_empty) {
+            rendered.pop();
+        }
+        let mut formatted = String::new();
+        for line in rendered {
+            formatted += &line;
+            formatted += newline;
+        }
+        formatted
+    }
+}
+
+/// Number of tokens of a label declaration at the start of tokens, like
+/// `Main:`, `.loop:` or `--:`.
+fn label_length(tokens: &[Token]) -> Option<usize> {
+    let is_colon =
+        |token: &Token| token.kind == TokenKind::Punctuation && token.span.fragment() == ":";
+    let first = tokens.first()?;
+    let length = match first.kind {
+        TokenKind::Identifier => 1,
+        TokenKind::Punctuation if first.span.fragment() == "." => match tokens.get(1) {
+            Some(name) if name.kind == TokenKind::Identifier => 2,
+            _ => return None,
+        },
+        TokenKind::Operator if matches!(first.span.fragment(), "+" | "-") => tokens
+            .iter()
+            .take_while(|token| token.span.fragment() == first.span.fragment())
+            .count(),
+        _ => return None,
+    };
+    if tokens.get(length).is_some_and(is_colon) {
+        Some(length + 1)
+    } else {
+        None
+    }
+}
+
+/// Checks whether a token is a binary operator, rather than a unary one
+/// or a relative label.
+fn is_binary(tokens: &[Token], index: usize) -> bool {
+    if tokens[index].kind != TokenKind::Operator || index == 0 {
+        return false;
+    }
+    let previous = &tokens[index - 1];
+    match previous.kind {
+        TokenKind::Number | TokenKind::String | TokenKind::Identifier => true,
+        TokenKind::Punctuation => matches!(previous.span.fragment(), ")" | "]" | "$"),
+        _ => false,
+    }
+}
+
+/// A mnemonic in a case. Names which aren't 65816 mnemonics, like SPC700
+/// ones, keep their case, as they're compared as written.
+fn with_case(mnemonic: &str, case: Case) -> String {
+    let (name, suffix) = mnemonic.split_at(mnemonic.find('.').unwrap_or(mnemonic.len()));
+    if normalize_mnemonic(name).is_none() {
+        return mnemonic.to_string();
+    }
+    match case {
+        Case::Upper => name.to_ascii_uppercase() + &suffix.to_ascii_lowercase(),
+        Case::Lower => mnemonic.to_ascii_lowercase(),
+        Case::Preserve => mnemonic.to_string(),
+    }
+}
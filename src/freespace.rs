@@ -0,0 +1,352 @@
+//! Freespace in existing ROMs.
+//!
+//! Patches add code into unused parts of a ROM. To let several tools
+//! patch the same ROM, every block written into freespace is preceded by
+//! a RATS tag, which marks the block as used:
+//!
+//! ```text
+//! "STAR" size-1 !(size-1) data...
+//! ```
+//!
+//! Sizes are 16-bit little endian words, so a block holds at most 64KiB.
+//! Tagged blocks are never overwritten by freespace allocation.
+//!
+//! Freespace is found by `scan`, which looks for runs of a fill byte, and
+//! is handed out by an `Allocator`. Assembly has no directives for
+//! freespace, so the allocator is for tools using mvp as a library; its
+//! kinds of blocks follow Asar's `freecode` and `freedata`.
+
+use std::error;
+use std::fmt;
+use std::ops::Range;
+
+use mapping::Mapper;
+use rom::Layout;
+
+/// Signature starting a RATS tag.
+const SIGNATURE: &[u8; 4] = b"STAR";
+
+/// Size of a RATS tag in bytes.
+pub const TAG_SIZE: usize = 8;
+
+/// Largest block which can be protected by a single tag.
+pub const MAX_BLOCK_SIZE: usize = 0x1_0000;
+
+/// Size of internal header, which is never freespace.
+const HEADER_SIZE: usize = 0x40;
+
+/// Size of a half of a HiROM bank, where code can be placed.
+const HALF_BANK_SIZE: usize = 0x8000;
+
+/// A failure to write a block into freespace.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// Block is empty or larger than `MAX_BLOCK_SIZE`.
+    InvalidSize(usize),
+    /// Block would end past the end of a ROM.
+    OutOfBounds(usize),
+    /// Block would overwrite a block protected by a tag at an offset.
+    Protected(usize),
+    /// No freespace is large enough for a block of a given size.
+    Full(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidSize(size) => write!(
+                f,
+                "freespace block of {:#X} bytes can't be protected, blocks must be 1 to {:#X} bytes long",
+                size, MAX_BLOCK_SIZE
+            ),
+            Error::OutOfBounds(offset) => {
+                write!(f, "freespace block at {:#X} doesn't fit in ROM", offset)
+            }
+            Error::Protected(offset) => write!(
+                f,
+                "freespace block would overwrite data protected by a RATS tag at {:#X}",
+                offset
+            ),
+            Error::Full(size) => write!(f, "no freespace for a block of {:#X} bytes", size),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Creates a RATS tag protecting a block of a given size.
+///
+/// # Panics
+///
+/// Panics when the size is 0 or larger than `MAX_BLOCK_SIZE`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::freespace;
+///
+/// assert_eq!(freespace::tag(0x100), *b"STAR\xFF\x00\x00\xFF");
+/// ```
+pub fn tag(size: usize) -> [u8; TAG_SIZE] {
+    assert!(
+        (1..=MAX_BLOCK_SIZE).contains(&size),
+        "invalid RATS block size {:#X}",
+        size
+    );
+    let size = (size - 1) as u16;
+    let mut tag = [0; TAG_SIZE];
+    tag[..4].copy_from_slice(SIGNATURE);
+    tag[4..6].copy_from_slice(&size.to_le_bytes());
+    tag[6..].copy_from_slice(&(!size).to_le_bytes());
+    tag
+}
+
+/// Reads a RATS tag at an offset, returning the size of a block it
+/// protects.
+pub fn read_tag(rom: &[u8], offset: usize) -> Option<usize> {
+    let tag = rom.get(offset..offset + TAG_SIZE)?;
+    let size = u16::from_le_bytes([tag[4], tag[5]]);
+    let complement = u16::from_le_bytes([tag[6], tag[7]]);
+    if &tag[..4] == SIGNATURE && size == !complement {
+        Some(usize::from(size) + 1)
+    } else {
+        None
+    }
+}
+
+/// Finds regions of a ROM protected by RATS tags, including the tags.
+///
+/// Tags are searched after the end of a previous region, so data inside
+/// a protected block which happens to look like a tag is ignored.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::freespace;
+///
+/// let mut rom = vec![0; 0x20];
+/// rom[4..12].copy_from_slice(&freespace::tag(2));
+/// assert_eq!(freespace::protected(&rom), [4..14]);
+/// ```
+pub fn protected(rom: &[u8]) -> Vec<Range<usize>> {
+    let mut regions = Vec::new();
+    let mut offset = 0;
+    while offset + TAG_SIZE <= rom.len() {
+        match read_tag(rom, offset) {
+            Some(size) => {
+                let end = (offset + TAG_SIZE + size).min(rom.len());
+                regions.push(offset..end);
+                offset = end;
+            }
+            None => offset += 1,
+        }
+    }
+    regions
+}
+
+/// Writes a block at an offset, preceded by a RATS tag protecting it.
+///
+/// Returns the range of the block, without its tag. Blocks which would
+/// overlap a region protected by an existing tag are refused.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::freespace::{self, Error};
+///
+/// let mut rom = vec![0; 0x20];
+/// assert_eq!(freespace::write_protected(&mut rom, 0, &[0xEA, 0x6B]), Ok(8..10));
+/// assert_eq!(&rom[..10], b"STAR\x01\x00\xFE\xFF\xEA\x6B");
+/// assert_eq!(freespace::write_protected(&mut rom, 4, &[0x6B]), Err(Error::Protected(0)));
+/// ```
+pub fn write_protected(rom: &mut [u8], offset: usize, data: &[u8]) -> Result<Range<usize>, Error> {
+    if !(1..=MAX_BLOCK_SIZE).contains(&data.len()) {
+        return Err(Error::InvalidSize(data.len()));
+    }
+    let region = offset..offset + TAG_SIZE + data.len();
+    if region.end > rom.len() {
+        return Err(Error::OutOfBounds(offset));
+    }
+    if let Some(existing) = protected(rom)
+        .into_iter()
+        .find(|existing| existing.start < region.end && region.start < existing.end)
+    {
+        return Err(Error::Protected(existing.start));
+    }
+    rom[offset..offset + TAG_SIZE].copy_from_slice(&tag(data.len()));
+    rom[offset + TAG_SIZE..region.end].copy_from_slice(data);
+    Ok(offset + TAG_SIZE..region.end)
+}
+
+/// Finds runs of a repeated fill byte in a ROM, which can be used as
+/// freespace.
+///
+/// Runs don't cross bank boundaries, regions protected by RATS tags or
+/// internal header. Every byte in `fill` is accepted, but a single run
+/// always consists of one byte value, as a mix of them is more likely to
+/// be data.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::{freespace, rom};
+///
+/// let mut image = vec![0xFF; 0x10000];
+/// image[..0x100].copy_from_slice(&[0x42; 0x100]);
+/// assert_eq!(
+///     freespace::scan(&image, &rom::LOROM, &[0x00, 0xFF]),
+///     [0x100..0x7FC0, 0x8000..0x10000],
+/// );
+/// ```
+pub fn scan(rom: &[u8], layout: &Layout, fill: &[u8]) -> Vec<Range<usize>> {
+    let mut used = protected(rom);
+    used.push(layout.header..layout.header + HEADER_SIZE);
+    used.sort_by_key(|range| range.start);
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for range in used.into_iter().chain(Some(rom.len()..rom.len())) {
+        let end = range.start.min(rom.len());
+        if start < end {
+            scan_runs(rom, start..end, layout.bank_size, fill, &mut runs);
+        }
+        start = start.max(range.end);
+    }
+    runs
+}
+
+/// Finds runs within a range without protected regions.
+fn scan_runs(
+    rom: &[u8],
+    range: Range<usize>,
+    bank_size: usize,
+    fill: &[u8],
+    runs: &mut Vec<Range<usize>>,
+) {
+    let mut start = None;
+    for offset in range.start..=range.end {
+        let byte = rom.get(offset).filter(|_| offset < range.end);
+        if let Some(run) = start {
+            if byte == Some(&rom[run]) && offset % bank_size != 0 {
+                continue;
+            }
+            runs.push(run..offset);
+            start = None;
+        }
+        if byte.is_some_and(|byte| fill.contains(byte)) {
+            start = Some(offset);
+        }
+    }
+}
+
+/// A kind of a freespace block.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Kind {
+    /// Code, placed in banks `$00-$3F` or `$80-$BF`, which mirror low
+    /// work RAM and hardware registers, like Asar's `freecode`.
+    Code,
+    /// Data, placed anywhere in ROM, like Asar's `freedata`.
+    Data,
+}
+
+/// Allocation of freespace in a ROM.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::freespace::{Allocator, Kind};
+/// use mvp::mapping::Mapper;
+///
+/// let mut rom = vec![0xEA; 0x8000];
+/// rom.resize(0x10000, 0xFF);
+/// let mut allocator = Allocator::new(&rom, Mapper::LoRom, &[0xFF]);
+/// let address = allocator.allocate(&mut rom, &[0x6B], Kind::Code).unwrap();
+/// assert_eq!(address, 0x818008);
+/// assert_eq!(&rom[0x8000..0x8009], b"STAR\x00\x00\xFF\xFF\x6B");
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Allocator {
+    mapper: Mapper,
+    free: Vec<Range<usize>>,
+}
+
+impl Allocator {
+    /// Creates an allocator of freespace found with `scan`.
+    pub fn new(rom: &[u8], mapper: Mapper, fill: &[u8]) -> Allocator {
+        Allocator {
+            mapper,
+            free: scan(rom, &mapper.layout(), fill),
+        }
+    }
+
+    /// Remaining freespace, as file offsets.
+    pub fn free(&self) -> &[Range<usize>] {
+        &self.free
+    }
+
+    /// Writes a block protected by a RATS tag into the first freespace
+    /// large enough, returning the address of the block.
+    pub fn allocate(&mut self, rom: &mut [u8], data: &[u8], kind: Kind) -> Result<u32, Error> {
+        if !(1..=MAX_BLOCK_SIZE).contains(&data.len()) {
+            return Err(Error::InvalidSize(data.len()));
+        }
+        let size = TAG_SIZE + data.len();
+        let (index, start, address) = self
+            .free
+            .iter()
+            .enumerate()
+            .find_map(|(index, range)| {
+                let (start, address) = self.fit(range, size, kind)?;
+                Some((index, start, address))
+            })
+            .ok_or(Error::Full(data.len()))?;
+        write_protected(rom, start, data)?;
+        let range = self.free.remove(index);
+        let after = start + size..range.end;
+        for part in [after, range.start..start].iter().cloned() {
+            if !part.is_empty() {
+                self.free.insert(index, part);
+            }
+        }
+        Ok(address)
+    }
+
+    /// Finds a start of a block in a free range, and an address of data
+    /// after its tag.
+    fn fit(&self, range: &Range<usize>, size: usize, kind: Kind) -> Option<(usize, u32)> {
+        let boundaries = (range.start / HALF_BANK_SIZE + 1..)
+            .map(|half| half * HALF_BANK_SIZE)
+            .take_while(|&start| start < range.end);
+        Some(range.start)
+            .into_iter()
+            .chain(boundaries)
+            .find_map(|start| {
+                let end = start + size;
+                if end > range.end {
+                    return None;
+                }
+                let address = match kind {
+                    Kind::Data => self.mapper.address(start + TAG_SIZE)?,
+                    Kind::Code if start / HALF_BANK_SIZE == (end - 1) / HALF_BANK_SIZE => {
+                        self.code_address(start + TAG_SIZE)?
+                    }
+                    Kind::Code => return None,
+                };
+                Some((start, address))
+            })
+    }
+
+    /// Converts a file offset into an address in banks `$00-$3F` or
+    /// `$80-$BF`, if there is one.
+    fn code_address(&self, offset: usize) -> Option<u32> {
+        let address = self.mapper.address(offset)?;
+        if address & 0x40_0000 == 0 {
+            return Some(address);
+        }
+        let mirror = address & !0x40_0000;
+        if self.mapper.file_offset(mirror) == Some(offset) {
+            Some(mirror)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Resolution of included source files.
+//!
+//! Files included with `incsrc` are looked up relative to the including
+//! file first, and then in include directories. Files are read through a
+//! `FileSystem`, which allows an `Overlay` of unsaved editor buffers to
+//! take precedence over files on disk.
+//!
+//! A file with `includeonce` is only assembled where it's first included,
+//! so that a library used by several files can include its dependencies:
+//!
+//! ```asm
+//! includeonce
+//! incsrc "registers.asm"
+//! ```
+//!
+//! Files including each other are an error, unless one of them has
+//! `includeonce`, which ends the cycle.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use parser::ast::{Spanned, Statement};
+use parser::grammar::{self, Input};
+use parser::visit::{self, Visitor};
+
+/// A source of file contents.
+pub trait FileSystem {
+    /// Reads a file as text.
+    fn read(&self, path: &Path) -> io::Result<String>;
+
+    /// Reads a file as bytes, for files read by built-in functions like
+    /// `readfile1`.
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.read(path).map(String::into_bytes)
+    }
+
+    /// Checks whether a file exists.
+    fn exists(&self, path: &Path) -> bool;
+}
+
+/// Files on disk.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Disk;
+
+impl FileSystem for Disk {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+}
+
+/// In-memory file contents taking precedence over another file system.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+///
+/// use mvp::include::{Disk, FileSystem, Overlay};
+///
+/// let mut overlay = Overlay::new(Disk);
+/// overlay.insert("/project/main.asm", "LDA #1\n");
+/// let contents = overlay.read(Path::new("/project/./main.asm")).unwrap();
+/// assert_eq!(contents, "LDA #1\n");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Overlay<F> {
+    files: HashMap<PathBuf, String>,
+    base: F,
+}
+
+impl<F: FileSystem> Overlay<F> {
+    /// Creates an empty overlay over a file system.
+    pub fn new(base: F) -> Overlay<F> {
+        Overlay {
+            files: HashMap::new(),
+            base,
+        }
+    }
+
+    /// Sets contents of a file, usually an unsaved editor buffer.
+    pub fn insert<P: AsRef<Path>, S: Into<String>>(&mut self, path: P, contents: S) {
+        self.files.insert(normalize(path.as_ref()), contents.into());
+    }
+
+    /// Removes a file from the overlay, usually after a buffer was saved
+    /// or closed, so that the file is read from the base file system.
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) -> Option<String> {
+        self.files.remove(&normalize(path.as_ref()))
+    }
+}
+
+impl<F: FileSystem> FileSystem for Overlay<F> {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        match self.files.get(&normalize(path)) {
+            Some(contents) => Ok(contents.clone()),
+            None => self.base.read(path),
+        }
+    }
+
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.files.get(&normalize(path)) {
+            Some(contents) => Ok(contents.clone().into_bytes()),
+            None => self.base.read_bytes(path),
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize(path)) || self.base.exists(path)
+    }
+}
+
+/// Removes `.` and `..` components of a path without accessing disk, so
+/// that paths written differently refer to the same overlay entry.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// An include failure.
+#[derive(Debug)]
+pub enum Error {
+    /// Included file wasn't found.
+    NotFound { from: PathBuf, name: String },
+    /// File couldn't be read.
+    Io(PathBuf, io::Error),
+    /// File includes itself, directly or indirectly, with a chain of
+    /// includes starting and ending with the file.
+    Recursive(Vec<PathBuf>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NotFound { ref from, ref name } => {
+                write!(f, "{}: included file {} not found", from.display(), name)
+            }
+            Error::Io(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+            Error::Recursive(ref chain) => {
+                write!(f, "{} includes itself: ", chain[0].display())?;
+                for (i, path) in chain.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(" -> ")?;
+                    }
+                    write!(f, "{}", path.display())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(_, ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A loaded source file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Source {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// Finds and loads included files.
+#[derive(Clone, Debug, Default)]
+pub struct Resolver<F> {
+    file_system: F,
+    directories: Vec<PathBuf>,
+}
+
+impl<F: FileSystem> Resolver<F> {
+    /// Creates a resolver reading files from a file system.
+    pub fn new(file_system: F) -> Resolver<F> {
+        Resolver {
+            file_system,
+            directories: Vec::new(),
+        }
+    }
+
+    /// Adds a directory searched for included files.
+    pub fn add_directory<P: Into<PathBuf>>(&mut self, directory: P) {
+        self.directories.push(directory.into());
+    }
+
+    /// File system used by the resolver.
+    pub fn file_system(&self) -> &F {
+        &self.file_system
+    }
+
+    /// Mutable access to file system, for updating an overlay.
+    pub fn file_system_mut(&mut self) -> &mut F {
+        &mut self.file_system
+    }
+
+    /// Finds a file included as `name` from a file at `from`.
+    pub fn resolve(&self, from: &Path, name: &str) -> Option<PathBuf> {
+        let relative = from.parent().unwrap_or_else(|| Path::new(""));
+        Some(relative)
+            .into_iter()
+            .chain(self.directories.iter().map(PathBuf::as_path))
+            .map(|directory| normalize(&directory.join(name)))
+            .find(|path| self.file_system.exists(path))
+    }
+
+    /// Loads a file and all files it includes, recursively, in order of
+    /// their first inclusion.
+    ///
+    /// Files that can't be parsed are still loaded, but their includes
+    /// can't be followed.
+    pub fn load(&self, root: &Path) -> Result<Vec<Source>, Error> {
+        let mut sources = Vec::new();
+        self.load_into(&normalize(root), &mut Vec::new(), &mut sources)?;
+        Ok(sources)
+    }
+
+    /// Loads files included by a source which isn't read from the file
+    /// system, like standard input, starting with the source itself.
+    ///
+    /// Includes are looked up relative to the path of the source.
+    pub fn load_source(&self, source: Source) -> Result<Vec<Source>, Error> {
+        let mut sources = Vec::new();
+        self.add_source(source, &mut Vec::new(), &mut sources)?;
+        Ok(sources)
+    }
+
+    /// Loads a file unless it was loaded already. Files being loaded are
+    /// on a `stack`, with whether they have `includeonce`.
+    fn load_into(
+        &self,
+        path: &Path,
+        stack: &mut Vec<(PathBuf, bool)>,
+        sources: &mut Vec<Source>,
+    ) -> Result<(), Error> {
+        if let Some(start) = stack.iter().position(|(including, _)| including == path) {
+            if stack[start..].iter().any(|&(_, once)| once) {
+                return Ok(());
+            }
+            let mut chain: Vec<_> = stack[start..]
+                .iter()
+                .map(|(including, _)| including.clone())
+                .collect();
+            chain.push(path.to_path_buf());
+            return Err(Error::Recursive(chain));
+        }
+        if sources.iter().any(|source| source.path == path) {
+            return Ok(());
+        }
+        let contents = self
+            .file_system
+            .read(path)
+            .map_err(|e| Error::Io(path.to_path_buf(), e))?;
+        let source = Source {
+            path: path.to_path_buf(),
+            contents,
+        };
+        self.add_source(source, stack, sources)
+    }
+
+    fn add_source(
+        &self,
+        source: Source,
+        stack: &mut Vec<(PathBuf, bool)>,
+        sources: &mut Vec<Source>,
+    ) -> Result<(), Error> {
+        let path = source.path.clone();
+        let (statements, _) = grammar::program_with_recovery(Input::new(&source.contents));
+        let Includes { names, once } = Includes::of(&statements);
+        let includes = names
+            .into_iter()
+            .map(|name| {
+                self.resolve(&path, name).ok_or_else(|| Error::NotFound {
+                    from: path.clone(),
+                    name: name.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        sources.push(source);
+        stack.push((path, once));
+        for included in includes {
+            self.load_into(&included, stack, sources)?;
+        }
+        stack.pop();
+        Ok(())
+    }
+}
+
+/// Names of files included by a file, including ones in if blocks, and
+/// whether the file has `includeonce`.
+#[derive(Default)]
+pub(crate) struct Includes<'a> {
+    pub names: Vec<&'a str>,
+    pub once: bool,
+}
+
+impl<'a> Includes<'a> {
+    pub fn of(statements: &[Spanned<'a, Statement<'a>>]) -> Includes<'a> {
+        let mut includes = Includes::default();
+        visit::walk_statements(&mut includes, statements);
+        includes
+    }
+}
+
+impl<'a> Visitor<'a> for Includes<'a> {
+    fn visit_statement(&mut self, statement: &Spanned<'a, Statement<'a>>) {
+        match **statement {
+            Statement::Include(name) => self.names.push(name),
+            Statement::IncludeOnce => self.once = true,
+            _ => visit::walk_statement(self, statement),
+        }
+    }
+}
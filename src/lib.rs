@@ -1,6 +1,37 @@
+extern crate arrayvec;
+extern crate memchr;
 #[macro_use]
 extern crate nom;
+#[cfg(feature = "arbitrary")]
+extern crate proptest;
+#[macro_use]
+extern crate serde_json;
+extern crate sha2;
 extern crate unicode_xid;
+#[cfg(feature = "wasm-bindgen")]
+extern crate wasm_bindgen;
 
-mod encoder;
+pub mod analysis;
+pub mod asar;
+pub mod assembler;
+pub mod debugger;
+pub mod disassembler;
+pub mod encoder;
+pub mod eval;
+pub mod format;
+pub mod freespace;
+pub mod include;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod mapping;
 pub mod parser;
+pub mod project;
+pub mod rom;
+pub mod scaffold;
+pub mod signature;
+pub mod spc;
+pub mod symbols;
+pub mod variant;
+pub mod warning;
+#[cfg(feature = "wasm-bindgen")]
+pub mod wasm;
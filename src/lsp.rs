@@ -0,0 +1,696 @@
+//! Language server for editors.
+//!
+//! The server speaks the Language Server Protocol over a reader and
+//! a writer, usually standard input and output, see `mvp lsp`. It
+//! provides:
+//!
+//! - diagnostics of assembly errors and warnings, updated when a document
+//!   is opened, changed or saved,
+//! - definitions of labels, defines, macros and functions,
+//! - symbols of a document,
+//! - semantic tokens for highlighting, see `parser::semantic`.
+//!
+//! Every open document is assembled as a root file of a project, unless
+//! another open document includes it, in which case it's assembled as
+//! a part of that document. Contents of open documents take precedence
+//! over files on disk, so includes see unsaved changes.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+
+use serde_json::{self, Value};
+
+use assembler::flags::State;
+use include::{self, FileSystem, Overlay, Source};
+use parser::ast::{Label, Span, Spanned, Statement, VariableName};
+use parser::grammar::{self, Input, ParserOptions};
+use parser::semantic::{self, TokenKind};
+use project::{Diagnostic, Project};
+
+/// JSON-RPC error code of an unknown request.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// LSP severities of diagnostics.
+const ERROR: u32 = 1;
+const WARNING: u32 = 2;
+
+/// Name of the LSP token type of a semantic token kind.
+///
+/// Every kind has a different type, as clients look types up by their
+/// index in the legend.
+fn token_type(kind: TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Mnemonic => "keyword",
+        TokenKind::Directive => "modifier",
+        TokenKind::Label => "label",
+        TokenKind::Define => "variable",
+        TokenKind::Macro => "macro",
+        TokenKind::Function => "function",
+        TokenKind::Parameter => "parameter",
+        TokenKind::Number => "number",
+        TokenKind::String => "string",
+        TokenKind::Operator => "operator",
+        TokenKind::Comment => "comment",
+        TokenKind::Identifier => "property",
+    }
+}
+
+/// What a name is defined as.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DefinitionKind {
+    Label,
+    /// Label scoped to the named label before it, as in `.loop:`.
+    ScopedLabel,
+    /// Assignment, as in `!lives = 3`.
+    Define,
+    Macro,
+    Function,
+}
+
+impl DefinitionKind {
+    /// Number of an LSP symbol kind.
+    fn symbol_kind(self) -> u32 {
+        match self {
+            DefinitionKind::Label => 12,
+            DefinitionKind::ScopedLabel => 8,
+            DefinitionKind::Define => 14,
+            DefinitionKind::Macro => 6,
+            DefinitionKind::Function => 12,
+        }
+    }
+}
+
+/// A name defined by a statement.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Definition<'a> {
+    pub name: &'a str,
+    /// Named label of a scoped label.
+    pub scope: Option<&'a str>,
+    pub kind: DefinitionKind,
+    /// Statement defining a name.
+    pub span: Span<'a>,
+}
+
+/// Lists names defined by statements, in order, including ones in if
+/// blocks. Labels in macros are local to their expansions, so they
+/// aren't listed.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::lsp::{self, DefinitionKind};
+/// use mvp::parser::grammar::parse_program;
+///
+/// let statements = parse_program("Main:\n.loop:\nBRA .loop\n!speed = 2\n").unwrap();
+/// let definitions = lsp::definitions(&statements);
+/// assert_eq!(definitions[1].name, "loop");
+/// assert_eq!(definitions[1].scope, Some("Main"));
+/// assert_eq!(definitions[2].kind, DefinitionKind::Define);
+/// ```
+pub fn definitions<'a>(statements: &[Spanned<'a, Statement<'a>>]) -> Vec<Definition<'a>> {
+    let mut definitions = Vec::new();
+    collect_definitions(statements, &mut None, &mut definitions);
+    definitions
+}
+
+fn collect_definitions<'a>(
+    statements: &[Spanned<'a, Statement<'a>>],
+    scope: &mut Option<&'a str>,
+    definitions: &mut Vec<Definition<'a>>,
+) {
+    for statement in statements {
+        let (name, kind) = match **statement {
+            Statement::Label(Label::Named(VariableName(name))) => {
+                *scope = Some(name);
+                (name, DefinitionKind::Label)
+            }
+            Statement::Label(Label::Scoped(VariableName(name))) => {
+                (name, DefinitionKind::ScopedLabel)
+            }
+            Statement::Assignment(VariableName(name), _)
+            | Statement::StringAssignment(VariableName(name), _) => (name, DefinitionKind::Define),
+            Statement::Macro(ref definition) => (definition.name.0, DefinitionKind::Macro),
+            Statement::Function(ref function) => (function.name.0, DefinitionKind::Function),
+            Statement::If(ref conditions) => {
+                for condition in conditions {
+                    collect_definitions(&condition.statements, scope, definitions);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        definitions.push(Definition {
+            name,
+            scope: if kind == DefinitionKind::ScopedLabel {
+                *scope
+            } else {
+                None
+            },
+            kind,
+            span: statement.span,
+        });
+    }
+}
+
+/// Reads a message with a `Content-Length` header, returning `None` at
+/// the end of input.
+pub fn read_message<R: BufRead>(mut reader: R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| invalid_data("missing Content-Length"))?;
+    let mut content = vec![0; length];
+    reader.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|e| invalid_data(&e.to_string()))
+}
+
+/// Writes a message with a `Content-Length` header.
+pub fn write_message<W: Write>(mut writer: W, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush()
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Root document of a project, the project, and a result of loading it.
+type LoadedProject<'a, F> = (&'a Path, Project<Overlay<F>>, Result<(), include::Error>);
+
+/// State of a language server.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::include::Disk;
+/// use mvp::lsp::Server;
+///
+/// let message = |json| serde_json::from_str::<serde_json::Value>(json).unwrap();
+/// let mut server = Server::new(Disk);
+/// let responses = server.handle(&message(r#"{"jsonrpc":"2.0","id":1,"method":"shutdown"}"#));
+/// assert_eq!(responses[0].to_string(), r#"{"id":1,"jsonrpc":"2.0","result":null}"#);
+/// server.handle(&message(r#"{"jsonrpc":"2.0","method":"exit"}"#));
+/// assert!(server.exited());
+/// ```
+#[derive(Debug)]
+pub struct Server<F> {
+    file_system: F,
+    options: ParserOptions,
+    /// Contents of open documents.
+    documents: HashMap<PathBuf, String>,
+    /// Files with diagnostics published last time, which have to be
+    /// cleared when they have none.
+    published: HashSet<PathBuf>,
+    exited: bool,
+}
+
+impl<F: FileSystem + Clone> Server<F> {
+    /// Creates a server reading files which aren't open from a file
+    /// system.
+    pub fn new(file_system: F) -> Server<F> {
+        Server {
+            file_system,
+            options: ParserOptions::default(),
+            documents: HashMap::new(),
+            published: HashSet::new(),
+            exited: false,
+        }
+    }
+
+    /// Sets options used to parse documents.
+    pub fn set_options(&mut self, options: ParserOptions) {
+        self.options = options;
+    }
+
+    /// Checks whether a client asked the server to exit.
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Handles messages until the end of input or an exit notification.
+    pub fn run<R: BufRead, W: Write>(&mut self, mut reader: R, mut writer: W) -> io::Result<()> {
+        while let Some(message) = read_message(&mut reader)? {
+            for response in self.handle(&message) {
+                write_message(&mut writer, &response)?;
+            }
+            if self.exited {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a request or a notification, returning messages to send to
+    /// a client.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let params = &message["params"];
+        let method = message["method"].as_str().unwrap_or("");
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": {
+                        "openClose": true,
+                        "change": 1,
+                        "save": { "includeText": true },
+                    },
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                    "semanticTokensProvider": {
+                        "legend": {
+                            "tokenTypes": TokenKind::ALL
+                                .iter()
+                                .map(|&kind| token_type(kind))
+                                .collect::<Vec<_>>(),
+                            "tokenModifiers": [],
+                        },
+                        "full": true,
+                    },
+                },
+                "serverInfo": { "name": "mvp" },
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/definition" => self.definition(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/semanticTokens/full" => self.semantic_tokens(params),
+            _ if message.get("id").is_some() => {
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("unknown method {}", method),
+                    },
+                })];
+            }
+            _ => return self.notification(method, params),
+        };
+        match message.get("id") {
+            Some(id) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+            None => Vec::new(),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let document = &params["textDocument"];
+        let path = document["uri"].as_str().and_then(uri_path);
+        match (method, path) {
+            ("exit", _) => {
+                self.exited = true;
+                return Vec::new();
+            }
+            ("textDocument/didOpen", Some(path)) => {
+                let text = document["text"].as_str().unwrap_or("");
+                self.documents.insert(path, text.to_string());
+            }
+            ("textDocument/didChange", Some(path)) => {
+                let changes = params["contentChanges"].as_array();
+                match changes.and_then(|changes| changes.last()?["text"].as_str()) {
+                    Some(text) => self.documents.insert(path, text.to_string()),
+                    None => return Vec::new(),
+                };
+            }
+            ("textDocument/didSave", Some(path)) => {
+                if let Some(text) = params["text"].as_str() {
+                    self.documents.insert(path, text.to_string());
+                }
+            }
+            ("textDocument/didClose", Some(path)) => {
+                self.documents.remove(&path);
+            }
+            _ => return Vec::new(),
+        }
+        self.diagnostics()
+    }
+
+    /// Files of open documents, over the file system.
+    fn files(&self) -> Overlay<F> {
+        let mut files = Overlay::new(self.file_system.clone());
+        for (path, text) in &self.documents {
+            files.insert(path, text.as_str());
+        }
+        files
+    }
+
+    /// Loaded projects of open documents which no other open document
+    /// includes, along with their root documents.
+    fn projects(&self) -> Vec<LoadedProject<'_, F>> {
+        let mut projects = Vec::new();
+        for path in self.documents.keys() {
+            let mut project = Project::new(self.files());
+            project.set_options(self.options);
+            project.add_root(path.clone());
+            let loaded = project.load();
+            projects.push((path.as_path(), project, loaded));
+        }
+        let included: HashSet<PathBuf> = projects
+            .iter()
+            .flat_map(|(_, project, _)| project.sources().iter().skip(1))
+            .map(|source| source.path.clone())
+            .collect();
+        projects.retain(|(path, _, _)| !included.contains(*path));
+        projects
+    }
+
+    /// Assembles open documents, publishing diagnostics of every file.
+    fn diagnostics(&mut self) -> Vec<Value> {
+        let mut files: BTreeMap<PathBuf, Vec<Value>> = BTreeMap::new();
+        for path in self.documents.keys() {
+            files.insert(path.clone(), Vec::new());
+        }
+        for (root, project, loaded) in self.projects() {
+            let diagnostics = match loaded {
+                Err(error) => {
+                    let diagnostic = Diagnostic {
+                        location: None,
+                        message: error.to_string(),
+                    };
+                    vec![(diagnostic, ERROR)]
+                }
+                Ok(()) => match project.assemble(0x8000, State::new(true, true)) {
+                    // Labels left for fixups are errors when writing a ROM.
+                    Ok(output) => project
+                        .unresolved(&output.fixups)
+                        .into_iter()
+                        .map(|diagnostic| (diagnostic, ERROR))
+                        .chain(
+                            project
+                                .warnings(&output.warnings)
+                                .into_iter()
+                                .map(|diagnostic| (diagnostic, WARNING)),
+                        )
+                        .collect(),
+                    Err(error) => project
+                        .diagnostics(&error)
+                        .into_iter()
+                        .map(|diagnostic| (diagnostic, ERROR))
+                        .collect(),
+                },
+            };
+            for (diagnostic, severity) in diagnostics {
+                let (path, line, column) = diagnostic
+                    .location
+                    .unwrap_or_else(|| (root.to_path_buf(), 1, 1));
+                let range = line_range(project.sources(), &path, line, column);
+                files.entry(path).or_default().push(json!({
+                    "range": range,
+                    "severity": severity,
+                    "source": "mvp",
+                    "message": diagnostic.message,
+                }));
+            }
+        }
+        for path in self.published.drain() {
+            files.entry(path).or_default();
+        }
+        let mut notifications = Vec::new();
+        for (path, diagnostics) in files {
+            if !diagnostics.is_empty() {
+                self.published.insert(path.clone());
+            }
+            notifications.push(json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/publishDiagnostics",
+                "params": { "uri": path_uri(&path), "diagnostics": diagnostics },
+            }));
+        }
+        notifications
+    }
+
+    /// Calls a function with sources of the project a document belongs
+    /// to.
+    fn with_sources<T, G>(&self, path: &Path, f: G) -> Option<T>
+    where
+        G: FnOnce(&[Source]) -> Option<T>,
+    {
+        let projects = self.projects();
+        let (_, project, _) = projects
+            .iter()
+            .find(|(_, project, _)| project.sources().iter().any(|source| source.path == path))?;
+        f(project.sources())
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let path = match params["textDocument"]["uri"].as_str().and_then(uri_path) {
+            Some(path) => path,
+            None => return Value::Null,
+        };
+        let location = self.with_sources(&path, |sources| {
+            let document = sources.iter().find(|source| source.path == path)?;
+            let offset = position_offset(&document.contents, &params["position"])?;
+            let (word, start) = word_at(&document.contents, offset)?;
+            let statements = self.parse(&document.contents);
+            // A scoped label belongs to the named label above a reference.
+            let scope = definitions(&statements)
+                .iter()
+                .rev()
+                .filter(|definition| definition.kind == DefinitionKind::Label)
+                .find(|definition| {
+                    definition
+                        .span
+                        .range(&document.contents)
+                        .map(|range| range.start)
+                        <= Some(start)
+                })
+                .map(|definition| definition.name);
+            let scoped = word.strip_prefix('.');
+            sources.iter().find_map(|source| {
+                let statements = self.parse(&source.contents);
+                let definition = definitions(&statements)
+                    .into_iter()
+                    .find(|definition| match (definition.kind, scoped) {
+                        (DefinitionKind::ScopedLabel, Some(name)) => {
+                            definition.name == name && definition.scope == scope
+                        }
+                        (DefinitionKind::ScopedLabel, None) | (_, Some(_)) => false,
+                        (_, None) => definition.name == word,
+                    })?;
+                Some(json!({
+                    "uri": path_uri(&source.path),
+                    "range": span_range(&source.contents, definition.span)?,
+                }))
+            })
+        });
+        location.unwrap_or(Value::Null)
+    }
+
+    fn document_symbols(&self, params: &Value) -> Value {
+        let path = match params["textDocument"]["uri"].as_str().and_then(uri_path) {
+            Some(path) => path,
+            None => return Value::Null,
+        };
+        let contents = match self.contents(&path) {
+            Some(contents) => contents,
+            None => return Value::Null,
+        };
+        let statements = self.parse(&contents);
+        let symbols: Vec<_> = definitions(&statements)
+            .iter()
+            .filter_map(|definition| {
+                let mut symbol = json!({
+                    "name": definition.name,
+                    "kind": definition.kind.symbol_kind(),
+                    "location": {
+                        "uri": path_uri(&path),
+                        "range": span_range(&contents, definition.span)?,
+                    },
+                });
+                if let Some(scope) = definition.scope {
+                    symbol["containerName"] = json!(scope);
+                }
+                Some(symbol)
+            })
+            .collect();
+        Value::Array(symbols)
+    }
+
+    /// Tokens encoded as LSP relative positions, lengths and indices of
+    /// token types in `TokenKind::ALL`.
+    fn semantic_tokens(&self, params: &Value) -> Value {
+        let path = match params["textDocument"]["uri"].as_str().and_then(uri_path) {
+            Some(path) => path,
+            None => return Value::Null,
+        };
+        let contents = match self.contents(&path) {
+            Some(contents) => contents,
+            None => return Value::Null,
+        };
+        let tokens = semantic::semantic_tokens(Input::with_options(&contents, self.options));
+        let mut data = Vec::with_capacity(tokens.len() * 5);
+        let (mut line, mut line_start, mut scanned) = (0, 0, 0);
+        let (mut previous_line, mut previous_column) = (0, 0);
+        for (span, kind) in tokens {
+            let range = match span.range(&contents) {
+                Some(range) => range,
+                None => continue,
+            };
+            for (index, _) in contents[scanned..range.start].match_indices('\n') {
+                line += 1;
+                line_start = scanned + index + 1;
+            }
+            scanned = range.start;
+            let column = contents[line_start..range.start].encode_utf16().count();
+            let delta_column = if line == previous_line {
+                column - previous_column
+            } else {
+                column
+            };
+            let kind_index = TokenKind::ALL.iter().position(|&other| other == kind);
+            data.extend_from_slice(&[
+                line - previous_line,
+                delta_column,
+                span.fragment().encode_utf16().count(),
+                kind_index.unwrap_or(0),
+                0,
+            ]);
+            previous_line = line;
+            previous_column = column;
+        }
+        json!({ "data": data })
+    }
+
+    /// Contents of an open document, or of a file otherwise.
+    fn contents(&self, path: &Path) -> Option<String> {
+        match self.documents.get(path) {
+            Some(contents) => Some(contents.clone()),
+            None => self.file_system.read(path).ok(),
+        }
+    }
+
+    fn parse<'a>(&self, source: &'a str) -> Vec<Spanned<'a, Statement<'a>>> {
+        grammar::program_with_recovery(Input::with_options(source, self.options)).0
+    }
+}
+
+/// Path of a `file:` URI.
+fn uri_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// `file:` URI of a path.
+fn path_uri(path: &Path) -> String {
+    let mut uri = "file://".to_string();
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri += &format!("%{:02X}", byte),
+        }
+    }
+    uri
+}
+
+/// LSP position of a byte offset, with UTF-16 columns.
+fn offset_position(source: &str, offset: usize) -> Value {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+/// Byte offset of an LSP position.
+fn position_offset(source: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let line_start = if line == 0 {
+        0
+    } else {
+        source.match_indices('\n').nth(line - 1)?.0 + 1
+    };
+    let mut units = 0;
+    for (index, c) in source[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + index);
+        }
+        units += c.len_utf16();
+    }
+    Some(source.len())
+}
+
+/// Range of the first line of a span.
+fn span_range(source: &str, span: Span) -> Option<Value> {
+    let range = span.range(source)?;
+    let line_end = source[range.start..]
+        .find('\n')
+        .map_or(source.len(), |end| range.start + end);
+    Some(json!({
+        "start": offset_position(source, range.start),
+        "end": offset_position(source, range.end.min(line_end)),
+    }))
+}
+
+/// Range from a line and a column of a diagnostic, counted from one, to
+/// the end of the line.
+fn line_range(sources: &[Source], path: &Path, line: usize, column: usize) -> Value {
+    let contents = sources
+        .iter()
+        .find(|source| source.path == path)
+        .map_or("", |source| source.contents.as_str());
+    let text = contents.split('\n').nth(line - 1).unwrap_or("");
+    let start: usize = text.chars().take(column - 1).map(char::len_utf16).sum();
+    let end = text
+        .trim_end_matches('\r')
+        .encode_utf16()
+        .count()
+        .max(start);
+    json!({
+        "start": { "line": line - 1, "character": start },
+        "end": { "line": line - 1, "character": end },
+    })
+}
+
+/// A name at a byte offset, with the offset where it starts.
+fn word_at(source: &str, offset: usize) -> Option<(&str, usize)> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '!' || c == '.';
+    let start = source[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_word(c))
+        .last()
+        .map_or(offset, |(index, _)| index);
+    let end = source[offset..]
+        .char_indices()
+        .find(|&(_, c)| !is_word(c))
+        .map_or(source.len(), |(index, _)| offset + index);
+    if start == end {
+        None
+    } else {
+        Some((&source[start..end], start))
+    }
+}
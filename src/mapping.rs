@@ -0,0 +1,182 @@
+//! Memory mappings of SNES cartridges.
+//!
+//! A mapping determines where ROM data appears in SNES address space,
+//! which in turn determines where assembled code is written in a ROM file.
+//! It's selected for the whole assembly by a mapper directive, such as
+//! `lorom` or `hirom`.
+//!
+//! The same ROM byte is usually visible at several addresses, as banks
+//! are mirrored. Converting a file offset into an address gives the
+//! address which code is normally assembled for, such as `$808000`
+//! rather than `$008000` in LoROM, so that code runs in FastROM banks.
+
+use rom::{self, Layout};
+
+/// A cartridge memory mapping.
+///
+/// LoROM is used when no mapper is selected.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Mapper {
+    /// LoROM, 32KiB banks mapped at `$8000-$FFFF` (`lorom`).
+    #[default]
+    LoRom,
+    /// HiROM, 64KiB banks mapped at `$C0-$FF` (`hirom`).
+    HiRom,
+    /// Extended HiROM, for ROMs larger than 4MiB (`exhirom`).
+    ExHiRom,
+    /// SA-1 coprocessor mapping (`sa1rom`).
+    Sa1Rom,
+    /// Super FX coprocessor mapping (`sfxrom`).
+    SfxRom,
+}
+
+impl Mapper {
+    /// Finds a mapper by its directive name, ignoring case.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::mapping::Mapper;
+    ///
+    /// assert_eq!(Mapper::from_directive("HiROM"), Some(Mapper::HiRom));
+    /// assert_eq!(Mapper::from_directive("norom"), None);
+    /// ```
+    pub fn from_directive(name: &str) -> Option<Mapper> {
+        Some(match &*name.to_ascii_lowercase() {
+            "lorom" => Mapper::LoRom,
+            "hirom" => Mapper::HiRom,
+            "exhirom" => Mapper::ExHiRom,
+            "sa1rom" => Mapper::Sa1Rom,
+            "sfxrom" => Mapper::SfxRom,
+            _ => return None,
+        })
+    }
+
+    /// Name of a directive selecting this mapper.
+    pub fn directive(self) -> &'static str {
+        match self {
+            Mapper::LoRom => "lorom",
+            Mapper::HiRom => "hirom",
+            Mapper::ExHiRom => "exhirom",
+            Mapper::Sa1Rom => "sa1rom",
+            Mapper::SfxRom => "sfxrom",
+        }
+    }
+
+    /// Value of map mode field in internal header.
+    pub fn map_mode(self) -> u8 {
+        match self {
+            Mapper::LoRom | Mapper::SfxRom => 0x20,
+            Mapper::HiRom => 0x21,
+            Mapper::Sa1Rom => 0x23,
+            Mapper::ExHiRom => 0x25,
+        }
+    }
+
+    /// Value of cartridge type field in internal header, for a cartridge
+    /// with or without battery backed RAM.
+    pub fn cartridge_type(self, ram: bool) -> u8 {
+        match (self, ram) {
+            (Mapper::Sa1Rom, false) => 0x33,
+            (Mapper::Sa1Rom, true) => 0x35,
+            (Mapper::SfxRom, false) => 0x13,
+            (Mapper::SfxRom, true) => 0x15,
+            (_, false) => 0x00,
+            (_, true) => 0x02,
+        }
+    }
+
+    /// Converts a SNES address into an offset in a ROM file, or returns
+    /// `None` when the address isn't mapped to ROM.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::mapping::Mapper;
+    ///
+    /// assert_eq!(Mapper::LoRom.file_offset(0x818000), Some(0x8000));
+    /// assert_eq!(Mapper::HiRom.file_offset(0xC12345), Some(0x12345));
+    /// assert_eq!(Mapper::LoRom.file_offset(0x7E0000), None);
+    /// ```
+    pub fn file_offset(self, address: u32) -> Option<usize> {
+        let bank = (address >> 16) as usize;
+        let offset = address as usize & 0xFFFF;
+        let upper = offset >= 0x8000;
+        match self {
+            Mapper::LoRom => match bank {
+                0x00..=0x7D | 0x80..=0xFF if upper => Some((bank & 0x7F) << 15 | offset & 0x7FFF),
+                _ => None,
+            },
+            Mapper::HiRom => match bank {
+                0x40..=0x7D | 0xC0..=0xFF => Some((bank & 0x3F) << 16 | offset),
+                0x00..=0x3F | 0x80..=0xBF if upper => Some((bank & 0x3F) << 16 | offset),
+                _ => None,
+            },
+            Mapper::ExHiRom => match bank {
+                0xC0..=0xFF => Some((bank & 0x3F) << 16 | offset),
+                0x40..=0x7D => Some(0x40_0000 | (bank & 0x3F) << 16 | offset),
+                0x80..=0xBF if upper => Some((bank & 0x3F) << 16 | offset),
+                0x00..=0x3F if upper => Some(0x40_0000 | (bank & 0x3F) << 16 | offset),
+                _ => None,
+            },
+            // Default mapping of SA-1 memory controller, where banks
+            // `$80-$BF` show the third and fourth megabyte.
+            Mapper::Sa1Rom => match bank {
+                0xC0..=0xFF => Some((bank & 0x3F) << 16 | offset),
+                0x00..=0x3F if upper => Some(bank << 15 | offset & 0x7FFF),
+                0x80..=0xBF if upper => Some((bank - 0x40) << 15 | offset & 0x7FFF),
+                _ => None,
+            },
+            Mapper::SfxRom => match bank {
+                0x40..=0x5F | 0xC0..=0xDF => Some((bank & 0x1F) << 16 | offset),
+                0x00..=0x3F | 0x80..=0xBF if upper => Some((bank & 0x3F) << 15 | offset & 0x7FFF),
+                _ => None,
+            },
+        }
+    }
+
+    /// Converts an offset in a ROM file into a SNES address, or returns
+    /// `None` when the offset is beyond the largest ROM of a mapper.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::mapping::Mapper;
+    ///
+    /// assert_eq!(Mapper::LoRom.address(0x8000), Some(0x818000));
+    /// assert_eq!(Mapper::ExHiRom.address(0x40FFC0), Some(0x40FFC0));
+    /// assert_eq!(Mapper::SfxRom.address(0x200000), None);
+    /// ```
+    pub fn address(self, offset: usize) -> Option<u32> {
+        let address = match self {
+            Mapper::LoRom if offset < 0x40_0000 => 0x80_0000 | lorom_address(offset),
+            Mapper::HiRom if offset < 0x40_0000 => 0xC0_0000 | offset,
+            Mapper::ExHiRom if offset < 0x40_0000 => 0xC0_0000 | offset,
+            Mapper::ExHiRom if offset < 0x7E_0000 => offset,
+            // Banks `$7E-$7F` are used by work RAM, so the last 128KiB are
+            // only visible in upper halves of banks `$3E-$3F`.
+            Mapper::ExHiRom if offset < 0x80_0000 && offset & 0x8000 != 0 => offset - 0x40_0000,
+            Mapper::Sa1Rom | Mapper::SfxRom if offset < 0x20_0000 => lorom_address(offset),
+            Mapper::Sa1Rom if offset < 0x40_0000 => 0x80_0000 | lorom_address(offset - 0x20_0000),
+            _ => return None,
+        };
+        Some(address as u32)
+    }
+
+    /// Placement of banks and internal header in a ROM file.
+    pub fn layout(self) -> Layout {
+        match self {
+            Mapper::LoRom | Mapper::Sa1Rom | Mapper::SfxRom => rom::LOROM,
+            Mapper::HiRom => rom::HIROM,
+            Mapper::ExHiRom => Layout {
+                header: 0x40_FFC0,
+                ..rom::HIROM
+            },
+        }
+    }
+}
+
+/// Converts a file offset into an address in LoROM banks `$00-$7F`.
+fn lorom_address(offset: usize) -> usize {
+    (offset & !0x7FFF) << 1 | 0x8000 | offset & 0x7FFF
+}
@@ -0,0 +1,237 @@
+//! Random syntax trees for property tests.
+//!
+//! With the `arbitrary` feature, `Expression`, `Opcode` and `Statement`
+//! implement `Arbitrary` from `proptest`, so that `any::<Expression>()`
+//! generates trees, and failing trees shrink towards smaller ones, like
+//! operations towards their operands.
+//!
+//! Trees borrow their names and strings, so those are picked from short
+//! lists. Only trees the parser can produce are generated, so that a tree
+//! written with `Display` parses back into itself. That leaves out
+//! invalid code, relative labels in expressions, whose minus signs would
+//! be read as operators, and operators the grammar doesn't have.
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate mvp;
+//! # extern crate proptest;
+//! use mvp::parser::ast::Expression;
+//! use mvp::parser::grammar;
+//! use proptest::prelude::*;
+//!
+//! proptest! {
+//!     # #![proptest_config(ProptestConfig::with_cases(16))]
+//!     fn parses_written_expression(expression in any::<Expression>()) {
+//!         let text = expression.to_string();
+//!         let (parsed, _) = grammar::parse_expression(&*text).unwrap();
+//!         prop_assert_eq!(parsed, expression);
+//!     }
+//! }
+//! # fn main() {
+//! #     parses_written_expression();
+//! # }
+//! ```
+
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+use proptest::sample::select;
+
+use parser::ast::{
+    BinaryOperator, Condition, Expression, Function, Label, MacroCall, Number, NumberWidth, Opcode,
+    OpcodeMode, Register, Spanned, Statement, VariableName, MNEMONICS,
+};
+
+/// Names of labels and defines.
+const NAMES: &[&str] = &["Main", "loop", "value", "!speed", "_tmp"];
+
+/// Names of called functions and macros.
+const FUNCTIONS: &[&str] = &["double", "read1", "move"];
+
+const STRINGS: &[&str] = &["", "text", "two words", "file.asm"];
+
+/// Operators the grammar has, as `BinaryOperator` has some it doesn't.
+const OPERATORS: &[BinaryOperator] = &[
+    BinaryOperator::Add,
+    BinaryOperator::Sub,
+    BinaryOperator::Mul,
+    BinaryOperator::Div,
+    BinaryOperator::Eq,
+    BinaryOperator::Ne,
+    BinaryOperator::Lt,
+    BinaryOperator::Le,
+    BinaryOperator::Gt,
+    BinaryOperator::Ge,
+];
+
+fn name() -> impl Strategy<Value = VariableName<'static>> {
+    select(NAMES).prop_map(VariableName)
+}
+
+fn number() -> impl Strategy<Value = Number> {
+    // A width is written as a number of hexadecimal digits, so a number
+    // needs to fit in it.
+    prop_oneof![
+        any::<u32>().prop_map(|value| (value, NumberWidth::None)),
+        (0..0x100u32).prop_map(|value| (value, NumberWidth::OneByte)),
+        (0..0x1_0000u32).prop_map(|value| (value, NumberWidth::TwoBytes)),
+        (0..0x100_0000u32).prop_map(|value| (value, NumberWidth::ThreeBytes)),
+    ]
+    .prop_map(|(value, width)| Number { value, width })
+}
+
+impl Arbitrary for Expression<'static> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Expression<'static>>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let leaf = prop_oneof![
+            number().prop_map(Expression::Number),
+            name().prop_map(|name| Expression::Variable(Label::Named(name))),
+            name().prop_map(|name| Expression::Variable(Label::Scoped(name))),
+            select(STRINGS).prop_map(Expression::String),
+            Just(Expression::ProgramCounter),
+            Just(Expression::ArgumentCount),
+        ];
+        leaf.prop_recursive(4, 32, 3, |inner| {
+            prop_oneof![
+                (select(OPERATORS), inner.clone(), inner.clone()).prop_map(
+                    |(operator, left, right)| {
+                        Expression::Binary(operator, Box::new((left.into(), right.into())))
+                    }
+                ),
+                (select(FUNCTIONS), vec(inner.clone(), 0..3)).prop_map(|(name, arguments)| {
+                    Expression::Call(
+                        VariableName(name),
+                        arguments.into_iter().map(Spanned::from).collect(),
+                    )
+                }),
+                // `--` is a relative label rather than a double negation.
+                inner.prop_map(|operand| match operand {
+                    Expression::Negate(_) => operand,
+                    operand => Expression::Negate(Box::new(operand.into())),
+                }),
+            ]
+        })
+        .boxed()
+    }
+}
+
+impl Arbitrary for Opcode<'static> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Opcode<'static>>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let expression = any::<Expression>;
+        // An address starting with a parenthesis is indirect addressing.
+        let address = || {
+            expression().prop_filter("parenthesized address", |value| {
+                !value.to_string().starts_with('(')
+            })
+        };
+        let operand = prop_oneof![
+            Just((None, OpcodeMode::Implied)),
+            address().prop_map(|value| (Some(value), OpcodeMode::Address)),
+            (address(), expression()).prop_map(|(value, second)| (
+                Some(value),
+                OpcodeMode::Move {
+                    second: second.into()
+                }
+            )),
+            (
+                expression(),
+                select(vec![
+                    OpcodeMode::Immediate,
+                    OpcodeMode::Indirect,
+                    OpcodeMode::XIndirect,
+                    OpcodeMode::IndirectY,
+                    OpcodeMode::StackIndirectY,
+                    OpcodeMode::LongIndirect,
+                    OpcodeMode::LongIndirectY,
+                ])
+            )
+                .prop_map(|(value, mode)| (Some(value), mode)),
+        ];
+        (select(MNEMONICS), option::of(1..=3u32), operand)
+            .prop_map(|(name, width, (value, mode))| Opcode {
+                name,
+                width,
+                mode,
+                value: value.map(Spanned::from),
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for Statement<'static> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Statement<'static>>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let expression = any::<Expression>;
+        let leaf = prop_oneof![
+            name().prop_map(|name| Statement::Label(Label::Named(name))),
+            name().prop_map(|name| Statement::Label(Label::Scoped(name))),
+            prop_oneof![-3..0, 1..4].prop_map(|depth| Statement::Label(Label::Relative(depth))),
+            any::<Opcode>().prop_map(Statement::Opcode),
+            (name(), expression()).prop_map(|(name, value)| Statement::Assignment(name, value)),
+            (name(), expression())
+                .prop_map(|(name, value)| Statement::StringAssignment(name, value)),
+            name().prop_map(Statement::Undef),
+            option::of(expression()).prop_map(Statement::Bank),
+            expression().prop_map(Statement::WarnPc),
+            (expression(), option::of(select(STRINGS)))
+                .prop_map(|(condition, message)| Statement::Assert(condition, message)),
+            vec(expression(), 1..3).prop_map(Statement::Print),
+            (select(FUNCTIONS), vec(name(), 0..3), expression()).prop_map(
+                |(name, parameters, body)| {
+                    Statement::Function(Function {
+                        name: VariableName(name),
+                        parameters,
+                        body,
+                    })
+                }
+            ),
+            select(STRINGS).prop_map(Statement::Include),
+            Just(Statement::IncludeOnce),
+            (
+                select(vec![Register::Accumulator, Register::Index]),
+                1..=2u32
+            )
+                .prop_map(|(register, width)| Statement::RegisterWidth(register, width)),
+            (select(FUNCTIONS), vec(expression(), 0..3), any::<bool>()).prop_map(
+                |(name, arguments, splice)| {
+                    Statement::MacroCall(MacroCall {
+                        name: VariableName(name),
+                        arguments,
+                        splice,
+                    })
+                }
+            ),
+        ];
+        leaf.prop_recursive(3, 24, 4, move |inner| {
+            let statements = || {
+                vec(inner.clone(), 0..4)
+                    .prop_map(|statements| statements.into_iter().map(Spanned::from).collect())
+            };
+            let condition = move || {
+                (expression(), statements()).prop_map(|(predicate, statements)| Condition {
+                    predicate: Some(predicate),
+                    statements,
+                })
+            };
+            let otherwise = statements().prop_map(|statements| Condition {
+                predicate: None,
+                statements,
+            });
+            (vec(condition(), 1..3), option::of(otherwise)).prop_map(
+                |(mut conditions, otherwise)| {
+                    conditions.extend(otherwise);
+                    Statement::If(conditions)
+                },
+            )
+        })
+        .boxed()
+    }
+}
@@ -0,0 +1,174 @@
+//! Expressions allocated in an arena.
+//!
+//! Every operator in an `Expression` allocates a `Box` for its operands,
+//! which dominates parse time of large files. With the `arena` feature,
+//! expressions can instead be parsed into an `Arena`, where nodes are
+//! allocated in large chunks and freed all at once.
+
+use std::cell::RefCell;
+
+use parser::ast::{BinaryOperator, Expression, Label, Number, Span, Spanned, VariableName};
+use parser::grammar::{self, Builder, Expected, Input, SyntaxError};
+
+/// Capacity of the first chunk of an arena, later chunks double it.
+const INITIAL_CAPACITY: usize = 64;
+
+/// A typed arena, allocating values which live as long as the arena.
+pub struct Arena<T> {
+    chunks: RefCell<Vec<Vec<T>>>,
+}
+
+impl<T> Arena<T> {
+    /// Creates an empty arena.
+    pub fn new() -> Arena<T> {
+        Arena {
+            chunks: RefCell::new(vec![Vec::with_capacity(INITIAL_CAPACITY)]),
+        }
+    }
+
+    /// Moves a value into an arena.
+    pub fn alloc(&self, value: T) -> &T {
+        let mut chunks = self.chunks.borrow_mut();
+        let capacity = {
+            let chunk = chunks.last().unwrap();
+            if chunk.len() < chunk.capacity() {
+                None
+            } else {
+                Some(chunk.capacity() * 2)
+            }
+        };
+        if let Some(capacity) = capacity {
+            chunks.push(Vec::with_capacity(capacity));
+        }
+        let chunk = chunks.last_mut().unwrap();
+        chunk.push(value);
+        // SAFETY: A chunk is never pushed to past its capacity, so its
+        // values never move, and chunks are only dropped along with the
+        // arena.
+        unsafe { &*(chunk.last().unwrap() as *const T) }
+    }
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Arena<T> {
+        Arena::new()
+    }
+}
+
+/// An expression with operands allocated in an arena, see `Expression`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ArenaExpression<'arena, 'a> {
+    Number(Number),
+    Variable(Label<'a>),
+    Binary(
+        BinaryOperator,
+        Spanned<'a, &'arena ArenaExpression<'arena, 'a>>,
+        Spanned<'a, &'arena ArenaExpression<'arena, 'a>>,
+    ),
+    Call(
+        VariableName<'a>,
+        Vec<Spanned<'a, &'arena ArenaExpression<'arena, 'a>>>,
+    ),
+    String(&'a str),
+    ProgramCounter,
+    ArgumentCount,
+    Negate(Spanned<'a, &'arena ArenaExpression<'arena, 'a>>),
+    Error(Span<'a>),
+}
+
+impl<'arena, 'a> ArenaExpression<'arena, 'a> {
+    /// Copies an expression out of an arena.
+    pub fn to_expression(&self) -> Expression<'a> {
+        match *self {
+            ArenaExpression::Number(ref number) => Expression::Number(number.clone()),
+            ArenaExpression::Variable(ref label) => Expression::Variable(label.clone()),
+            ArenaExpression::Binary(operator, ref left, ref right) => {
+                Expression::Binary(operator, Box::new((to_spanned(left), to_spanned(right))))
+            }
+            ArenaExpression::Call(ref name, ref arguments) => {
+                Expression::Call(name.clone(), arguments.iter().map(to_spanned).collect())
+            }
+            ArenaExpression::String(string) => Expression::String(string),
+            ArenaExpression::ProgramCounter => Expression::ProgramCounter,
+            ArenaExpression::ArgumentCount => Expression::ArgumentCount,
+            ArenaExpression::Negate(ref operand) => {
+                Expression::Negate(Box::new(to_spanned(operand)))
+            }
+            ArenaExpression::Error(span) => Expression::Error(span),
+        }
+    }
+}
+
+fn to_spanned<'a>(operand: &Spanned<'a, &ArenaExpression<'_, 'a>>) -> Spanned<'a, Expression<'a>> {
+    Spanned {
+        node: operand.node.to_expression(),
+        span: operand.span,
+    }
+}
+
+#[derive(Clone, Copy)]
+struct InArena<'arena, 'a: 'arena>(&'arena Arena<ArenaExpression<'arena, 'a>>);
+
+impl<'arena, 'a> Builder<'a> for InArena<'arena, 'a> {
+    type Node = &'arena ArenaExpression<'arena, 'a>;
+
+    fn leaf(&self, expression: Expression<'a>) -> Self::Node {
+        self.0.alloc(match expression {
+            Expression::Number(number) => ArenaExpression::Number(number),
+            Expression::Variable(label) => ArenaExpression::Variable(label),
+            Expression::String(string) => ArenaExpression::String(string),
+            Expression::ProgramCounter => ArenaExpression::ProgramCounter,
+            Expression::ArgumentCount => ArenaExpression::ArgumentCount,
+            Expression::Error(span) => ArenaExpression::Error(span),
+            _ => unreachable!("expression with operands"),
+        })
+    }
+
+    fn binary(
+        &self,
+        operator: BinaryOperator,
+        left: Spanned<'a, Self::Node>,
+        right: Spanned<'a, Self::Node>,
+    ) -> Self::Node {
+        self.0.alloc(ArenaExpression::Binary(operator, left, right))
+    }
+
+    fn negate(&self, operand: Spanned<'a, Self::Node>) -> Self::Node {
+        self.0.alloc(ArenaExpression::Negate(operand))
+    }
+
+    fn call(&self, name: VariableName<'a>, arguments: Vec<Spanned<'a, Self::Node>>) -> Self::Node {
+        self.0.alloc(ArenaExpression::Call(name, arguments))
+    }
+}
+
+/// Parses an expression into an arena, like `grammar::parse_expression`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::arena::{self, Arena, ArenaExpression};
+/// use mvp::parser::ast::BinaryOperator;
+/// use mvp::parser::grammar;
+///
+/// let arena = Arena::new();
+/// let (parsed, rest) = arena::parse_expression(&arena, "2 + 3 * 4").unwrap();
+/// match *parsed {
+///     ArenaExpression::Binary(BinaryOperator::Add, _, _) => {}
+///     _ => panic!("unexpected expression {:?}", parsed),
+/// }
+/// assert!(rest.is_empty());
+///
+/// let (boxed, _) = grammar::parse_expression("2 + 3 * 4").unwrap();
+/// assert_eq!(parsed.to_expression(), boxed);
+/// ```
+pub fn parse_expression<'arena, 'a, I: Into<Input<'a>>>(
+    arena: &'arena Arena<ArenaExpression<'arena, 'a>>,
+    input: I,
+) -> Result<(&'arena ArenaExpression<'arena, 'a>, Input<'a>), SyntaxError<'a>> {
+    grammar::run(
+        input.into(),
+        |input| grammar::expression_with(input, InArena(arena)),
+        Expected::Expression,
+    )
+}
@@ -1,7 +1,120 @@
 //! Syntactic elements of assembly.
 
+use std::fmt;
+use std::ops::{Deref, Range};
+
+use asar::AsarVersion;
+use encoder::spc700;
+use encoder::superfx;
+use encoder::{self, Arch};
+use mapping::Mapper;
+use parser::grammar::{Precedence, SyntaxError};
+use warning::{Category, Level};
+
+/// A part of source code a node was parsed from.
+///
+/// As the AST borrows from the source, a span is the borrowed fragment,
+/// and its position is determined by comparing it with the source. Names,
+/// labels and strings in the AST are fragments of the source too, so
+/// `Span::new` can locate them as well.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::grammar::{self, Input};
+///
+/// let source = "warnpc $8000\n  LDA #1 ; load\n";
+/// let statements = grammar::parse_program(Input::new(source)).unwrap();
+/// assert_eq!(statements[1].span.fragment(), "LDA #1");
+/// assert_eq!(statements[1].span.range(source), Some(15..21));
+/// assert_eq!(statements[1].span.line_column(source), Some((2, 3)));
+/// ```
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Span<'a> {
+    fragment: &'a str,
+}
+
+impl<'a> Span<'a> {
+    /// Creates a span from a fragment of source code.
+    pub fn new(fragment: &'a str) -> Span<'a> {
+        Span { fragment }
+    }
+
+    /// Text of a span.
+    pub fn fragment(&self) -> &'a str {
+        self.fragment
+    }
+
+    /// Byte offsets of a span within the source, or `None` if the span
+    /// doesn't come from `source`.
+    pub fn range(&self, source: &str) -> Option<Range<usize>> {
+        let start = (self.fragment.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+        let end = start + self.fragment.len();
+        if end <= source.len() {
+            Some(start..end)
+        } else {
+            None
+        }
+    }
+
+    /// Line and column where a span starts, both counted from one.
+    ///
+    /// Columns count characters, not bytes.
+    pub fn line_column(&self, source: &str) -> Option<(usize, usize)> {
+        let start = self.range(source)?.start;
+        let before = &source[..start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line = before.matches('\n').count() + 1;
+        Some((line, before[line_start..].chars().count() + 1))
+    }
+}
+
+/// A node along with its span.
+///
+/// Spans are ignored when comparing nodes, so that the same code parsed
+/// from differently formatted sources compares equal.
+#[derive(Clone, Debug)]
+pub struct Spanned<'a, T> {
+    pub node: T,
+    pub span: Span<'a>,
+}
+
+impl<'a, T: PartialEq> PartialEq for Spanned<'a, T> {
+    fn eq(&self, other: &Spanned<'a, T>) -> bool {
+        self.node == other.node
+    }
+}
+
+impl<'a, T: Eq> Eq for Spanned<'a, T> {}
+
+impl<'a, T> Deref for Spanned<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+/// Writes the node, as spans don't affect how nodes are written.
+impl<'a, T: fmt::Display> fmt::Display for Spanned<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
+/// Wraps a node with an empty span, which is useful for constructing
+/// trees by hand.
+impl<'a, T> From<T> for Spanned<'a, T> {
+    fn from(node: T) -> Spanned<'a, T> {
+        Spanned {
+            node,
+            span: Span::default(),
+        }
+    }
+}
+
 /// A unit that can stand by itself in a program.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Statement<'a> {
     /// Label declaration.
     Label(Label<'a>),
@@ -11,6 +124,89 @@ pub enum Statement<'a> {
     If(Vec<Condition<'a>>),
     /// Assignment of `Expression` to `VariableName`.
     Assignment(VariableName<'a>, Expression<'a>),
+    /// Assignment of text, as in `!addr := hex(base + 4)`, where the
+    /// expression is evaluated with `eval::evaluate_string`.
+    StringAssignment(VariableName<'a>, Expression<'a>),
+    /// Removal of an assignment, as in `undef "!name"`, after which
+    /// `defined("!name")` is zero.
+    Undef(VariableName<'a>),
+    /// Check that output position doesn't go past an address.
+    ///
+    /// The address is inclusive, that is, `warnpc $8000` allows code to end
+    /// right before `$8000`, but not to write a byte at `$8000`.
+    WarnPc(Expression<'a>),
+    /// Check that an expression is non-zero, failing with an optional
+    /// message otherwise.
+    Assert(Expression<'a>, Option<&'a str>),
+    /// Print values of expressions during assembly.
+    Print(Vec<Expression<'a>>),
+    /// Search base ROM for a byte pattern, and bind its address to a name.
+    ///
+    /// A pattern consists of space separated hexadecimal bytes, with `??`
+    /// matching any byte.
+    FindPattern(&'a str, VariableName<'a>),
+    /// User-defined expression function.
+    Function(Function<'a>),
+    /// Selection of memory mapping, like `lorom`.
+    Mapper(Mapper),
+    /// Declaration of a base ROM variant, with its CRC-32 checksum.
+    RomVariant(VariableName<'a>, Expression<'a>),
+    /// A name with a different address in each base ROM variant.
+    VariantTable(VariableName<'a>, Vec<(VariableName<'a>, Expression<'a>)>),
+    /// List of CRC-32 checksums a base ROM is allowed to have.
+    ExpectCrc(Vec<Expression<'a>>),
+    /// Variables excluded from the interrupt safety lint.
+    InterruptSafe(Vec<VariableName<'a>>),
+    /// Labels of a macro kept as they are in expansions, rather than
+    /// renamed, as in `global Handler`.
+    Global(Vec<VariableName<'a>>),
+    /// Inclusion of another source file, as in `incsrc "file.asm"`.
+    Include(&'a str),
+    /// Marks a file to be assembled only where it's first included, with
+    /// `includeonce`.
+    IncludeOnce,
+    /// Width of a register in bytes assumed from this point on, as in
+    /// `a16`, for sizing immediate operands.
+    RegisterWidth(Register, u32),
+    /// Selection of an instruction set for following code, like
+    /// `arch spc700`.
+    Arch(Arch),
+    /// Level of a warning category from this point on, as in
+    /// `warnings deny unused-label`.
+    Warnings(Category, Level),
+    /// Bank the data bank register is assumed to point to from this point
+    /// on, as in `bank $7E`, or `None` for `bank auto`, which assumes the
+    /// bank of code.
+    Bank(Option<Expression<'a>>),
+    /// Version of Asar following code is written for, as in `asar 1.81`.
+    Asar(AsarVersion),
+    /// Precedence of operators on following lines, selected with
+    /// `math pri on` or `math pri off`.
+    Precedence(Precedence),
+    /// Use of an Asar feature mvp doesn't have, after `asar`, with a name
+    /// of the feature, like `org`.
+    AsarFeature(&'static str),
+    /// SPC700 instruction, used after `arch spc700`.
+    Spc700(Spc700Instruction<'a>),
+    /// Super FX instruction, used after `arch superfx`.
+    SuperFx(SuperFxInstruction<'a>),
+    /// SPC700 code between `spcblock` and `endspcblock`.
+    SpcBlock(SpcBlock<'a>),
+    /// Definition of a macro, between `macro` and `endmacro`.
+    Macro(Macro<'a>),
+    /// Call of a macro, replaced with an `Expansion` by
+    /// `assembler::macros::expand`.
+    MacroCall(MacroCall<'a>),
+    /// Statements of a macro expanded in place of a call, spanning the
+    /// call.
+    Expansion(Vec<Spanned<'a, Statement<'a>>>),
+    /// Code which couldn't be parsed, produced by `program_with_recovery`
+    /// in place of an invalid line or if block.
+    ///
+    /// Passes over a program skip invalid code, as its error was already
+    /// reported while parsing. An invalid line has the syntax error found
+    /// in it.
+    Error(Span<'a>, Option<SyntaxError<'a>>),
 }
 
 /// An unique name of an identifier in a program.
@@ -18,7 +214,7 @@ pub enum Statement<'a> {
 /// Most of time, a `Label` is used when a reference to a value is needed,
 /// however variable names are in grammar to support those cases where
 /// a relative label reference is not acceptable, in particular assignments.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct VariableName<'a>(pub &'a str);
 
 /// A reference to a location in assembly.
@@ -27,49 +223,322 @@ pub struct VariableName<'a>(pub &'a str);
 /// whose level of depth is determined by a number, negative integers
 /// mean backward references, while positive numbers mean forward
 /// references.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Label<'a> {
     Scoped(VariableName<'a>),
     Named(VariableName<'a>),
     Relative(i32),
 }
 
-#[derive(Debug, Eq, PartialEq)]
+/// Writes a label as it appears in source code.
+impl<'a> fmt::Display for Label<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Label::Named(VariableName(name)) => f.write_str(name),
+            Label::Scoped(VariableName(name)) => write!(f, ".{}", name),
+            Label::Relative(n) => {
+                let sign = if n < 0 { "-" } else { "+" };
+                f.write_str(&sign.repeat(n.unsigned_abs() as usize))
+            }
+        }
+    }
+}
+
+/// Mnemonics of 65816 instructions.
+pub const MNEMONICS: &[&str] = &[
+    "ADC", "AND", "ASL", "BCC", "BCS", "BEQ", "BIT", "BMI", "BNE", "BPL", "BRA", "BRK", "BRL",
+    "BVC", "BVS", "CLC", "CLD", "CLI", "CLV", "CMP", "COP", "CPX", "CPY", "DEC", "DEX", "DEY",
+    "EOR", "INC", "INX", "INY", "JML", "JMP", "JSL", "JSR", "LDA", "LDX", "LDY", "LSR", "MVN",
+    "MVP", "NOP", "ORA", "PEA", "PEI", "PER", "PHA", "PHB", "PHD", "PHK", "PHP", "PHX", "PHY",
+    "PLA", "PLB", "PLD", "PLP", "PLX", "PLY", "REP", "ROL", "ROR", "RTI", "RTL", "RTS", "SBC",
+    "SEC", "SED", "SEI", "SEP", "STA", "STP", "STX", "STY", "STZ", "TAX", "TAY", "TCD", "TCS",
+    "TDC", "TRB", "TSB", "TSC", "TSX", "TXA", "TXS", "TXY", "TYA", "TYX", "WAI", "WDM", "XBA",
+    "XCE",
+];
+
+/// Finds a mnemonic or an alias mnemonic regardless of its case,
+/// returning its uppercase spelling.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::ast::normalize_mnemonic;
+///
+/// assert_eq!(normalize_mnemonic("lda"), Some("LDA"));
+/// assert_eq!(normalize_mnemonic("Lda"), Some("LDA"));
+/// assert_eq!(normalize_mnemonic("dea"), Some("DEA"));
+/// assert_eq!(normalize_mnemonic("ldq"), None);
+/// ```
+pub fn normalize_mnemonic(name: &str) -> Option<&'static str> {
+    MNEMONICS
+        .iter()
+        .cloned()
+        .chain(encoder::ALIASES.iter().map(|alias| alias.0))
+        .find(|mnemonic| mnemonic.eq_ignore_ascii_case(name))
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Opcode<'a> {
+    /// Mnemonic as written, or in uppercase when parsed with
+    /// `ParserOptions::normalize_mnemonics`.
     pub name: &'a str,
     pub width: Option<u32>,
     pub mode: OpcodeMode<'a>,
-    pub value: Expression<'a>,
+    /// Operand, or `None` for implied addressing.
+    pub value: Option<Spanned<'a, Expression<'a>>>,
+}
+
+impl<'a> Opcode<'a> {
+    /// Index register used by an opcode, if any.
+    ///
+    /// Grammar doesn't distinguish indexed addressing (`LDA 19,x`) from
+    /// block moves (`MVN 1,2`), as both consist of two comma separated
+    /// expressions, so both end up as `OpcodeMode::Move`. This method
+    /// reinterprets the second operand as an index register when it's a
+    /// lone `x`, `y` or `s` variable and the opcode isn't `MVN` or `MVP`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::parser::ast::{IndexRegister, Statement};
+    /// use mvp::parser::grammar::parse_statement;
+    ///
+    /// match parse_statement("LDA $19,X") {
+    ///     Ok((Statement::Opcode(opcode), _)) => {
+    ///         assert_eq!(opcode.index_register(), Some(IndexRegister::X));
+    ///     }
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn index_register(&self) -> Option<IndexRegister> {
+        let second = match self.mode {
+            OpcodeMode::Move { ref second } => second,
+            _ => return None,
+        };
+        if self.name.eq_ignore_ascii_case("MVN") || self.name.eq_ignore_ascii_case("MVP") {
+            return None;
+        }
+        let name = match second.node {
+            Expression::Variable(Label::Named(VariableName(name))) => name,
+            _ => return None,
+        };
+        match &*name.to_ascii_lowercase() {
+            "x" => Some(IndexRegister::X),
+            "y" => Some(IndexRegister::Y),
+            "s" => Some(IndexRegister::S),
+            _ => None,
+        }
+    }
+}
+
+/// Writes an instruction as source code.
+///
+/// Widths other than 1, 2 and 3 bytes have no suffix, and are left out.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::ast::Statement;
+/// use mvp::parser::grammar::parse_statement;
+///
+/// match parse_statement("lda.w ( $10+2 ) , y") {
+///     Ok((Statement::Opcode(opcode), _)) => assert_eq!(opcode.to_string(), "lda.w ($10 + 2),y"),
+///     _ => unreachable!(),
+/// }
+/// ```
+impl<'a> fmt::Display for Opcode<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name)?;
+        match self.width {
+            Some(1) => f.write_str(".b")?,
+            Some(2) => f.write_str(".w")?,
+            Some(3) => f.write_str(".l")?,
+            _ => {}
+        }
+        let value = match self.value {
+            Some(ref value) => value,
+            None if self.mode == OpcodeMode::Accumulator => return f.write_str(" A"),
+            None => return Ok(()),
+        };
+        match self.mode {
+            OpcodeMode::Implied | OpcodeMode::Address | OpcodeMode::Accumulator => {
+                write!(f, " {}", value)
+            }
+            OpcodeMode::Immediate => write!(f, " #{}", value),
+            OpcodeMode::Indirect => write!(f, " ({})", value),
+            OpcodeMode::XIndirect => write!(f, " ({},x)", value),
+            OpcodeMode::IndirectY => write!(f, " ({}),y", value),
+            OpcodeMode::StackIndirectY => write!(f, " ({},s),y", value),
+            OpcodeMode::LongIndirect => write!(f, " [{}]", value),
+            OpcodeMode::LongIndirectY => write!(f, " [{}],y", value),
+            OpcodeMode::Move { ref second } => write!(f, " {},{}", value, second),
+        }
+    }
+}
+
+/// An instruction of the SPC700 sound processor.
+///
+/// ```asm
+/// arch spc700
+/// MOV A,#$12
+/// MOV $1234+X,A
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Spc700Instruction<'a> {
+    /// Mnemonic as written.
+    pub name: &'a str,
+    pub operands: Vec<spc700::Operand<Expression<'a>>>,
+}
+
+/// An instruction of the Super FX coprocessor.
+///
+/// ```asm
+/// arch superfx
+/// iwt r1,#$1234
+/// stw (r2)
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SuperFxInstruction<'a> {
+    /// Mnemonic as written.
+    pub name: &'a str,
+    pub operands: Vec<superfx::Operand<Expression<'a>>>,
+}
+
+/// SPC700 code assembled for audio RAM, to be uploaded by the main CPU.
+///
+/// Statements are SPC700 instructions and labels, placed from `address` on.
+/// `execute` is where the sound processor starts running after an upload.
+///
+/// ```asm
+/// spcblock $0400
+/// Start:
+///     MOV A,#$12
+///     BRA Start
+/// endspcblock execute Start
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SpcBlock<'a> {
+    pub address: Expression<'a>,
+    pub statements: Vec<Spanned<'a, Statement<'a>>>,
+    pub execute: Option<Expression<'a>>,
+}
+
+/// A macro, whose statements are expanded in place of its calls.
+///
+/// Parameters are referred to by name in expressions, like parameters of
+/// a `Function`.
+///
+/// ```asm
+/// macro add(value)
+///     CLC
+///     ADC #value
+/// endmacro
+/// %add($10)
+/// ```
+///
+/// A variadic macro, declared with `...` after its parameters, takes any
+/// number of additional arguments. Their count is `sizeof(...)`, and they
+/// can be passed on to another macro with `<...>`:
+///
+/// ```asm
+/// macro loads(first, ...)
+///     LDA #first
+///     if sizeof(...) > 0
+///         %loads(<...>)
+///     endif
+/// endmacro
+/// ```
+///
+/// Arguments are substituted for parameters as written, and evaluated
+/// where a parameter is used. Arguments of parameters declared with `#`,
+/// as in `macro back(#target)`, are evaluated once, at a call, see
+/// `assembler::macros`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Macro<'a> {
+    pub name: VariableName<'a>,
+    pub parameters: Vec<VariableName<'a>>,
+    /// Parameters declared with `#`, whose arguments are evaluated at
+    /// a call.
+    pub evaluated: Vec<VariableName<'a>>,
+    pub variadic: bool,
+    pub statements: Vec<Spanned<'a, Statement<'a>>>,
+}
+
+/// A call of a macro, as in `%add($10)`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MacroCall<'a> {
+    pub name: VariableName<'a>,
+    pub arguments: Vec<Expression<'a>>,
+    /// Whether variadic arguments of an enclosing macro are passed after
+    /// `arguments`, written as `<...>`.
+    pub splice: bool,
+}
+
+/// A register used for indexed addressing.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum IndexRegister {
+    /// X index register (`addr,x`).
+    X,
+    /// Y index register (`addr,y`).
+    Y,
+    /// Stack pointer (`sr,s`).
+    S,
+}
+
+/// A register whose width selects the size of immediate operands.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Register {
+    /// Accumulator, with width selected by the M flag.
+    Accumulator,
+    /// X and Y index registers, with width selected by the X flag.
+    Index,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum OpcodeMode<'a> {
-    Implied,                         // no argument
-    Immediate,                       // #$
-    Address,                         // $
-    Indirect,                        // ($)
-    XIndirect,                       // ($,x)
-    IndirectY,                       // ($),y
-    StackIndirectY,                  // ($,s),y
-    LongIndirect,                    // [$]
-    LongIndirectY,                   // [$],y
-    Move { second: Expression<'a> }, // $,$
-    Accumulator,                     // A
+    Implied,                                      // no argument
+    Immediate,                                    // #$
+    Address,                                      // $
+    Indirect,                                     // ($)
+    XIndirect,                                    // ($,x)
+    IndirectY,                                    // ($),y
+    StackIndirectY,                               // ($,s),y
+    LongIndirect,                                 // [$]
+    LongIndirectY,                                // [$],y
+    Move { second: Spanned<'a, Expression<'a>> }, // $,$
+    Accumulator,                                  // A
 }
 
 /// A single "if" block with predicate and statements.
 ///
 /// This is usually used in a `Vec`, and represents a single predicate along
 /// with statements to run if it is met.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Condition<'a> {
     pub predicate: Option<Expression<'a>>,
-    pub statements: Vec<Statement<'a>>,
+    pub statements: Vec<Spanned<'a, Statement<'a>>>,
+}
+
+/// A function that can be called in expressions.
+///
+/// Calling a function evaluates its body, with parameters bound to values
+/// of arguments.
+///
+/// ```asm
+/// function doubled(x) = x * 2
+/// LDA #doubled(4)
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Function<'a> {
+    pub name: VariableName<'a>,
+    pub parameters: Vec<VariableName<'a>>,
+    pub body: Expression<'a>,
 }
 
 /// An operator that takes two arguments
 ///
-/// Those operators map to mathematical operators on numbers.
+/// Those operators map to mathematical operators on numbers. Comparison
+/// operators evaluate to 1 when true, and 0 otherwise.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum BinaryOperator {
     /// Addition (`+`).
@@ -91,6 +560,51 @@ pub enum BinaryOperator {
     And,
     /// Bitwise or (`|`).
     Or,
+
+    /// Equality (`==`).
+    Eq,
+    /// Inequality (`!=`).
+    Ne,
+    /// Less than (`<`).
+    Lt,
+    /// Less than or equal (`<=`).
+    Le,
+    /// Greater than (`>`).
+    Gt,
+    /// Greater than or equal (`>=`).
+    Ge,
+}
+
+impl BinaryOperator {
+    /// Precedence with standard operator precedence, higher binds tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOperator::Mul | BinaryOperator::Div => 2,
+            BinaryOperator::Add | BinaryOperator::Sub => 1,
+            _ => 0,
+        }
+    }
+
+    /// Operator as written in source code.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Sub => "-",
+            BinaryOperator::Mul => "*",
+            BinaryOperator::Div => "/",
+            BinaryOperator::Shl => "<<",
+            BinaryOperator::Shr => ">>",
+            BinaryOperator::Xor => "^",
+            BinaryOperator::And => "&",
+            BinaryOperator::Or => "|",
+            BinaryOperator::Eq => "==",
+            BinaryOperator::Ne => "!=",
+            BinaryOperator::Lt => "<",
+            BinaryOperator::Le => "<=",
+            BinaryOperator::Gt => ">",
+            BinaryOperator::Ge => ">=",
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -109,6 +623,9 @@ pub struct Number {
 /// hexadecimal or binary literal that is exactly one or two bytes. However,
 /// because that special case does exist, it needs to be in AST.
 ///
+/// Three bytes wide literals, like `$7F8000`, don't select immediate size,
+/// but they select long addressing for instructions with an address.
+///
 /// For instance, the following program uses two different versions of the
 /// same opcode (A9). The distinction between those is at runtime, by checking
 /// processor flags.
@@ -126,12 +643,165 @@ pub enum NumberWidth {
     None,
     OneByte,
     TwoBytes,
+    ThreeBytes,
+}
+
+impl NumberWidth {
+    /// Number of bytes, or `None` for a literal without width.
+    pub fn bytes(self) -> Option<u32> {
+        match self {
+            NumberWidth::None => None,
+            NumberWidth::OneByte => Some(1),
+            NumberWidth::TwoBytes => Some(2),
+            NumberWidth::ThreeBytes => Some(3),
+        }
+    }
 }
 
+/// An expression, with spans of operands of operators and calls, so that
+/// errors can point to the operand they come from.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Expression<'a> {
     Number(Number),
     Variable(Label<'a>),
-    Binary(BinaryOperator, Box<(Expression<'a>, Expression<'a>)>),
-    Call(VariableName<'a>, Vec<Expression<'a>>),
+    Binary(
+        BinaryOperator,
+        Box<(Spanned<'a, Expression<'a>>, Spanned<'a, Expression<'a>>)>,
+    ),
+    Call(VariableName<'a>, Vec<Spanned<'a, Expression<'a>>>),
+    /// String literal, without surrounding quotes.
+    String(&'a str),
+    /// Current output address, written as `pc()` or a bare `$`.
+    ProgramCounter,
+    /// Unary minus, as in `-1`.
+    Negate(Box<Spanned<'a, Expression<'a>>>),
+    /// Number of variadic arguments of a macro, written as `sizeof(...)`
+    /// and replaced by macro expansion.
+    ArgumentCount,
+    /// Invalid operand after an operator, as in `2 + * 3`.
+    ///
+    /// Parsing continues after an invalid operand, so that errors in the
+    /// rest of an expression can be found too. `program` rejects statements
+    /// with invalid operands, while `program_with_recovery` reports them.
+    Error(Span<'a>),
+}
+
+impl<'a> Expression<'a> {
+    /// Width of an expression, used to select immediate size and
+    /// addressing mode.
+    ///
+    /// Width flows through simple arithmetic, like in Asar. Addition,
+    /// subtraction and bitwise operations keep width of their operands,
+    /// where an operand without width is ignored if it's a decimal
+    /// literal, and the wider width is used if operands differ. Other
+    /// operations, names and calls have no width.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::parser::ast::NumberWidth;
+    /// use mvp::parser::grammar::{self, Input};
+    ///
+    /// let width = |input| grammar::parse_expression(Input::new(input)).unwrap().0.width();
+    /// assert_eq!(width("$10+1"), NumberWidth::OneByte);
+    /// assert_eq!(width("$10+$1234"), NumberWidth::TwoBytes);
+    /// assert_eq!(width("$10*$200"), NumberWidth::None);
+    /// assert_eq!(width("$10+label"), NumberWidth::None);
+    /// ```
+    pub fn width(&self) -> NumberWidth {
+        match *self {
+            Expression::Number(ref number) => number.width,
+            Expression::Negate(ref operand) => operand.width(),
+            Expression::Binary(
+                BinaryOperator::Add
+                | BinaryOperator::Sub
+                | BinaryOperator::Xor
+                | BinaryOperator::And
+                | BinaryOperator::Or,
+                ref operands,
+            ) => combined_width(&operands.0, &operands.1),
+            _ => NumberWidth::None,
+        }
+    }
+}
+
+/// Writes an expression as source code, with standard operator
+/// precedence.
+///
+/// Parentheses are only written where precedence requires them, and
+/// numbers with a width are written in hexadecimal with as many digits as
+/// needed to keep the width.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::grammar::{self, Input};
+///
+/// let (expression, _) = grammar::parse_expression(Input::new("(a+$0010)*(2-(3-b))")).unwrap();
+/// assert_eq!(expression.to_string(), "(a + $0010) * (2 - (3 - b))");
+/// ```
+impl<'a> fmt::Display for Expression<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expression::Number(Number { value, width }) => match width.bytes() {
+                Some(bytes) => write!(f, "${:01$X}", value, 2 * bytes as usize),
+                None => write!(f, "{}", value),
+            },
+            Expression::Variable(ref label) => write!(f, "{}", label),
+            Expression::Binary(operator, ref operands) => {
+                let precedence = operator.precedence();
+                write_operand(f, &operands.0, precedence)?;
+                write!(f, " {} ", operator.symbol())?;
+                write_operand(f, &operands.1, precedence + 1)
+            }
+            Expression::Call(ref name, ref arguments) => {
+                write!(f, "{}(", name.0)?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                f.write_str(")")
+            }
+            Expression::String(string) => write!(f, "\"{}\"", string),
+            Expression::ProgramCounter => f.write_str("pc()"),
+            Expression::Negate(ref operand) => {
+                f.write_str("-")?;
+                write_operand(f, operand, u8::MAX)
+            }
+            Expression::ArgumentCount => f.write_str("sizeof(...)"),
+            Expression::Error(span) => f.write_str(span.fragment()),
+        }
+    }
+}
+
+/// Writes an operand, in parentheses if it's an operation binding less
+/// tightly than `precedence`.
+fn write_operand(f: &mut fmt::Formatter, operand: &Expression, precedence: u8) -> fmt::Result {
+    match *operand {
+        Expression::Binary(operator, _) if operator.precedence() < precedence => {
+            write!(f, "({})", operand)
+        }
+        _ => write!(f, "{}", operand),
+    }
+}
+
+fn combined_width(left: &Expression, right: &Expression) -> NumberWidth {
+    let is_decimal = |expression: &Expression| match *expression {
+        Expression::Number(ref number) => number.width == NumberWidth::None,
+        _ => false,
+    };
+    match (left.width(), right.width()) {
+        (width, NumberWidth::None) if is_decimal(right) => width,
+        (NumberWidth::None, width) if is_decimal(left) => width,
+        (NumberWidth::None, _) | (_, NumberWidth::None) => NumberWidth::None,
+        (left, right) => {
+            if left.bytes() >= right.bytes() {
+                left
+            } else {
+                right
+            }
+        }
+    }
 }
@@ -0,0 +1,278 @@
+//! Transformation of syntax trees.
+//!
+//! A `Folder` takes nodes by value and returns nodes to replace them with.
+//! By default, a method rebuilds its node from children passed through the
+//! folder, using a corresponding `fold_*` function, so an implementation
+//! only needs to override methods for nodes it substitutes. This is the
+//! counterpart of `visit`, for passes which rewrite a program rather than
+//! inspect it.
+//!
+//! # Examples
+//!
+//! Replacing a name with a number.
+//!
+//! ```
+//! use mvp::parser::ast::{Expression, Label, Number, NumberWidth, VariableName};
+//! use mvp::parser::fold;
+//! use mvp::parser::grammar::{parse_program, Input};
+//!
+//! let statements = parse_program(Input::new("warnpc Base + 2")).unwrap();
+//! let statements = fold::map_expressions(statements, |expression| match expression {
+//!     Expression::Variable(Label::Named(VariableName("Base"))) => Expression::Number(Number {
+//!         value: 0x8000,
+//!         width: NumberWidth::None,
+//!     }),
+//!     expression => expression,
+//! });
+//! let expected = parse_program(Input::new("warnpc 32768 + 2")).unwrap();
+//! assert_eq!(statements, expected);
+//! ```
+
+use parser::ast::{
+    Condition, Expression, Function, Label, Macro, MacroCall, Opcode, OpcodeMode, Spanned,
+    Spc700Instruction, SpcBlock, Statement, SuperFxInstruction,
+};
+
+/// A syntax tree transformer.
+pub trait Folder<'a> {
+    fn fold_statement(
+        &mut self,
+        statement: Spanned<'a, Statement<'a>>,
+    ) -> Spanned<'a, Statement<'a>> {
+        fold_statement(self, statement)
+    }
+
+    fn fold_opcode(&mut self, opcode: Opcode<'a>) -> Opcode<'a> {
+        fold_opcode(self, opcode)
+    }
+
+    fn fold_condition(&mut self, condition: Condition<'a>) -> Condition<'a> {
+        fold_condition(self, condition)
+    }
+
+    fn fold_function(&mut self, function: Function<'a>) -> Function<'a> {
+        fold_function(self, function)
+    }
+
+    fn fold_expression(&mut self, expression: Expression<'a>) -> Expression<'a> {
+        fold_expression(self, expression)
+    }
+
+    /// Folds a label, either declared by a statement or referred to by an
+    /// expression.
+    fn fold_label(&mut self, label: Label<'a>) -> Label<'a> {
+        label
+    }
+}
+
+/// Folds statements in order.
+pub fn fold_statements<'a, F: Folder<'a> + ?Sized>(
+    folder: &mut F,
+    statements: Vec<Spanned<'a, Statement<'a>>>,
+) -> Vec<Spanned<'a, Statement<'a>>> {
+    statements
+        .into_iter()
+        .map(|statement| folder.fold_statement(statement))
+        .collect()
+}
+
+pub fn fold_statement<'a, F: Folder<'a> + ?Sized>(
+    folder: &mut F,
+    statement: Spanned<'a, Statement<'a>>,
+) -> Spanned<'a, Statement<'a>> {
+    let Spanned { node, span } = statement;
+    let node = match node {
+        Statement::Label(label) => Statement::Label(folder.fold_label(label)),
+        Statement::Opcode(opcode) => Statement::Opcode(folder.fold_opcode(opcode)),
+        Statement::If(conditions) => Statement::If(
+            conditions
+                .into_iter()
+                .map(|condition| folder.fold_condition(condition))
+                .collect(),
+        ),
+        Statement::Assignment(name, value) => {
+            Statement::Assignment(name, folder.fold_expression(value))
+        }
+        Statement::StringAssignment(name, value) => {
+            Statement::StringAssignment(name, folder.fold_expression(value))
+        }
+        Statement::WarnPc(value) => Statement::WarnPc(folder.fold_expression(value)),
+        Statement::Bank(bank) => Statement::Bank(bank.map(|bank| folder.fold_expression(bank))),
+        Statement::Assert(value, message) => {
+            Statement::Assert(folder.fold_expression(value), message)
+        }
+        Statement::Print(values) => Statement::Print(fold_expressions(folder, values)),
+        Statement::Function(function) => Statement::Function(folder.fold_function(function)),
+        Statement::RomVariant(name, crc) => {
+            Statement::RomVariant(name, folder.fold_expression(crc))
+        }
+        Statement::VariantTable(name, entries) => Statement::VariantTable(
+            name,
+            entries
+                .into_iter()
+                .map(|(variant, address)| (variant, folder.fold_expression(address)))
+                .collect(),
+        ),
+        Statement::ExpectCrc(crcs) => Statement::ExpectCrc(fold_expressions(folder, crcs)),
+        Statement::Spc700(Spc700Instruction { name, operands }) => {
+            Statement::Spc700(Spc700Instruction {
+                name,
+                operands: operands
+                    .into_iter()
+                    .map(|operand| operand.map(|value| folder.fold_expression(value)))
+                    .collect(),
+            })
+        }
+        Statement::SuperFx(SuperFxInstruction { name, operands }) => {
+            Statement::SuperFx(SuperFxInstruction {
+                name,
+                operands: operands
+                    .into_iter()
+                    .map(|operand| operand.map(|value| folder.fold_expression(value)))
+                    .collect(),
+            })
+        }
+        Statement::SpcBlock(SpcBlock {
+            address,
+            statements,
+            execute,
+        }) => Statement::SpcBlock(SpcBlock {
+            address: folder.fold_expression(address),
+            statements: fold_statements(folder, statements),
+            execute: execute.map(|execute| folder.fold_expression(execute)),
+        }),
+        Statement::Macro(definition) => Statement::Macro(Macro {
+            statements: fold_statements(folder, definition.statements),
+            ..definition
+        }),
+        Statement::MacroCall(call) => Statement::MacroCall(MacroCall {
+            arguments: fold_expressions(folder, call.arguments),
+            ..call
+        }),
+        Statement::Expansion(statements) => {
+            Statement::Expansion(fold_statements(folder, statements))
+        }
+        node @ Statement::FindPattern(..)
+        | node @ Statement::Mapper(_)
+        | node @ Statement::InterruptSafe(_)
+        | node @ Statement::Global(_)
+        | node @ Statement::Include(_)
+        | node @ Statement::IncludeOnce
+        | node @ Statement::Undef(_)
+        | node @ Statement::RegisterWidth(..)
+        | node @ Statement::Arch(_)
+        | node @ Statement::Warnings(..)
+        | node @ Statement::Asar(_)
+        | node @ Statement::Precedence(_)
+        | node @ Statement::AsarFeature(_)
+        | node @ Statement::Error(..) => node,
+    };
+    Spanned { node, span }
+}
+
+pub fn fold_opcode<'a, F: Folder<'a> + ?Sized>(folder: &mut F, opcode: Opcode<'a>) -> Opcode<'a> {
+    Opcode {
+        value: opcode.value.map(|value| fold_spanned(folder, value)),
+        mode: match opcode.mode {
+            OpcodeMode::Move { second } => OpcodeMode::Move {
+                second: fold_spanned(folder, second),
+            },
+            mode => mode,
+        },
+        ..opcode
+    }
+}
+
+pub fn fold_condition<'a, F: Folder<'a> + ?Sized>(
+    folder: &mut F,
+    condition: Condition<'a>,
+) -> Condition<'a> {
+    Condition {
+        predicate: condition
+            .predicate
+            .map(|predicate| folder.fold_expression(predicate)),
+        statements: fold_statements(folder, condition.statements),
+    }
+}
+
+pub fn fold_function<'a, F: Folder<'a> + ?Sized>(
+    folder: &mut F,
+    function: Function<'a>,
+) -> Function<'a> {
+    Function {
+        body: folder.fold_expression(function.body),
+        ..function
+    }
+}
+
+pub fn fold_expression<'a, F: Folder<'a> + ?Sized>(
+    folder: &mut F,
+    expression: Expression<'a>,
+) -> Expression<'a> {
+    match expression {
+        Expression::Variable(label) => Expression::Variable(folder.fold_label(label)),
+        Expression::Binary(operator, operands) => {
+            let (left, right) = *operands;
+            Expression::Binary(
+                operator,
+                Box::new((fold_spanned(folder, left), fold_spanned(folder, right))),
+            )
+        }
+        Expression::Call(name, arguments) => Expression::Call(
+            name,
+            arguments
+                .into_iter()
+                .map(|argument| fold_spanned(folder, argument))
+                .collect(),
+        ),
+        Expression::Negate(value) => Expression::Negate(Box::new(fold_spanned(folder, *value))),
+        expression @ Expression::Number(_)
+        | expression @ Expression::String(_)
+        | expression @ Expression::ProgramCounter
+        | expression @ Expression::ArgumentCount
+        | expression @ Expression::Error(_) => expression,
+    }
+}
+
+/// Folds an operand, keeping its span.
+pub fn fold_spanned<'a, F: Folder<'a> + ?Sized>(
+    folder: &mut F,
+    expression: Spanned<'a, Expression<'a>>,
+) -> Spanned<'a, Expression<'a>> {
+    Spanned {
+        node: folder.fold_expression(expression.node),
+        span: expression.span,
+    }
+}
+
+fn fold_expressions<'a, F: Folder<'a> + ?Sized>(
+    folder: &mut F,
+    expressions: Vec<Expression<'a>>,
+) -> Vec<Expression<'a>> {
+    expressions
+        .into_iter()
+        .map(|expression| folder.fold_expression(expression))
+        .collect()
+}
+
+/// Applies a function to every expression in statements, children first.
+///
+/// An expression passed to `f` has its subexpressions already replaced.
+pub fn map_expressions<'a, M>(
+    statements: Vec<Spanned<'a, Statement<'a>>>,
+    f: M,
+) -> Vec<Spanned<'a, Statement<'a>>>
+where
+    M: FnMut(Expression<'a>) -> Expression<'a>,
+{
+    fold_statements(&mut MapExpressions(f), statements)
+}
+
+struct MapExpressions<M>(M);
+
+impl<'a, M: FnMut(Expression<'a>) -> Expression<'a>> Folder<'a> for MapExpressions<M> {
+    fn fold_expression(&mut self, expression: Expression<'a>) -> Expression<'a> {
+        let expression = fold_expression(self, expression);
+        (self.0)(expression)
+    }
+}
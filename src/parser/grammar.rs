@@ -1,73 +1,1418 @@
 //! Grammar AST parser.
 //!
-//! Many methods in this module return `Result<(CompleteStr, O), nom::Err<CompleteStr>>`
-//! as an argument. If the result is `Ok`, the variant contains a tuple where the first
-//! argument is text left to parse, and second is retrieved AST value.
-//! `Err` means that parse did fail.
+//! Parsing functions in this module take either a string or an `Input`
+//! with non-default `ParserOptions`. Functions parsing a part of a program
+//! return `Result<(O, Input), SyntaxError>`. If the result is `Ok`, the
+//! variant contains a tuple where the first argument is retrieved AST value,
+//! and second is text left to parse. `Err` means that parse did fail, and
+//! the error tells the line and column where it happened.
+//!
+//! Parsers are implemented with nom, which is an implementation detail not
+//! exposed by this module.
+//!
+//! Statements are terminated by line endings, so unlike `ws!`, parsers in
+//! this module only skip horizontal whitespace.
 
+use asar::{self, AsarVersion};
+use encoder::spc700;
+use encoder::superfx;
+use encoder::Arch;
+use mapping::Mapper;
 use parser::ast::*;
+use parser::scan;
+use parser::visit::{self, Visitor};
+use warning::{self, Category};
 
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::iter;
 use std::str::{self, FromStr};
 
-use nom::{self, ErrorKind};
-pub use nom::{types::CompleteStr, Err, IResult};
+use nom::IResult;
+use nom::{self, ErrorKind, Slice};
+pub use parser::input::Input;
 use unicode_xid::UnicodeXID;
 
-fn valid_identifier_first_character(c: char) -> bool {
+pub(crate) fn valid_identifier_first_character(c: char) -> bool {
     UnicodeXID::is_xid_start(c) || c == '!' || c == '_'
 }
 
-fn valid_later_character(c: char) -> bool {
+pub(crate) fn valid_later_character(c: char) -> bool {
     UnicodeXID::is_xid_continue(c) || c == '_'
 }
 
+/// A dialect of assembly language.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Dialect {
+    /// MVP syntax only.
+    Mvp,
+    /// Syntax compatible with xkas, which evaluates operators from left to
+    /// right.
+    Xkas,
+    /// Syntax compatible with Asar.
+    Asar,
+}
+
+/// Precedence of binary operators.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Precedence {
+    /// Multiplication and division bind tighter than addition and
+    /// subtraction, which bind tighter than comparisons.
+    Standard,
+    /// All operators have the same precedence, and are evaluated from left
+    /// to right.
+    LeftToRight,
+}
+
+/// Default value of `ParserOptions::max_depth`.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Options controlling which syntax is accepted by parsers.
+///
+/// Options are passed to parsers along with source code, with
+/// `Input::with_options`. The default options accept the same syntax as
+/// `Dialect::Asar`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::ast::{Expression, Number, NumberWidth};
+/// use mvp::parser::grammar::{self, Dialect, Input, ParserOptions};
+///
+/// let options = ParserOptions {
+///     c_style_literals: true,
+///     ..ParserOptions::default()
+/// };
+/// let (parsed, _) = grammar::parse_expression(Input::with_options("0x1F", options)).unwrap();
+/// let expected = Expression::Number(Number { value: 0x1F, width: NumberWidth::OneByte });
+/// assert_eq!(parsed, expected);
+/// assert!(grammar::parse_program("warnpc 0x1F").is_err());
+///
+/// let strict = ParserOptions::dialect(Dialect::Mvp);
+/// assert!(grammar::parse_program(Input::with_options("!base = 1", strict)).is_err());
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ParserOptions {
+    /// Allow identifiers starting with `!`, like xkas and Asar defines.
+    pub bang_identifiers: bool,
+    /// Precedence of binary operators.
+    pub precedence: Precedence,
+    /// Accept C-style `0x1F` hexadecimal and `0b1010` binary literals, in
+    /// addition to `$1F`.
+    pub c_style_literals: bool,
+    /// Store known mnemonics in uppercase, so that `lda`, `Lda` and `LDA`
+    /// produce the same syntax tree.
+    pub normalize_mnemonics: bool,
+    /// Maximum depth of an expression, counting nested operators,
+    /// parentheses and calls. Deeper expressions are rejected, as code
+    /// walking syntax trees is recursive.
+    pub max_depth: usize,
+    /// Instruction set of code, changed by the `arch` directive.
+    pub arch: Arch,
+    /// Version of Asar code is written for, set by the `asar` directive,
+    /// see `asar`.
+    pub asar_version: Option<AsarVersion>,
+}
+
+impl ParserOptions {
+    /// Options accepting syntax of a given dialect.
+    pub fn dialect(dialect: Dialect) -> ParserOptions {
+        match dialect {
+            Dialect::Mvp => ParserOptions {
+                bang_identifiers: false,
+                precedence: Precedence::Standard,
+                c_style_literals: false,
+                normalize_mnemonics: false,
+                max_depth: DEFAULT_MAX_DEPTH,
+                arch: Arch::W65816,
+                asar_version: None,
+            },
+            Dialect::Xkas => ParserOptions {
+                bang_identifiers: true,
+                precedence: Precedence::LeftToRight,
+                c_style_literals: false,
+                normalize_mnemonics: false,
+                max_depth: DEFAULT_MAX_DEPTH,
+                arch: Arch::W65816,
+                asar_version: None,
+            },
+            Dialect::Asar => ParserOptions {
+                bang_identifiers: true,
+                precedence: Precedence::Standard,
+                c_style_literals: false,
+                normalize_mnemonics: false,
+                max_depth: DEFAULT_MAX_DEPTH,
+                arch: Arch::W65816,
+                asar_version: None,
+            },
+        }
+    }
+
+    /// Options for code written for a version of Asar, keeping the
+    /// instruction set and limits. Operators are evaluated from left to
+    /// right, as in Asar without `math pri on`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::parser::ast::Label;
+    /// use mvp::parser::grammar::{self, Input, ParserOptions};
+    ///
+    /// let options = ParserOptions::default().for_asar("1.81".parse().unwrap());
+    /// let (parsed, _) = grammar::parse_expression(Input::with_options("2+1*2", options)).unwrap();
+    /// let context = |_: &Label| None;
+    /// assert_eq!(parsed.evaluate(&context), Ok(6));
+    /// ```
+    pub fn for_asar(self, version: AsarVersion) -> ParserOptions {
+        ParserOptions {
+            bang_identifiers: true,
+            precedence: Precedence::LeftToRight,
+            asar_version: Some(version),
+            ..self
+        }
+    }
+}
+
+impl Default for ParserOptions {
+    fn default() -> ParserOptions {
+        ParserOptions::dialect(Dialect::Asar)
+    }
+}
+
 const OPERATORS: &str = "+-*/";
 
+/// Names which cannot be used as opcodes, as they delimit blocks.
+const BLOCK_KEYWORDS: &[&str] = &[
+    "if",
+    "elseif",
+    "else",
+    "endif",
+    "spcblock",
+    "endspcblock",
+    "macro",
+    "endmacro",
+];
+
+fn space(input: Input) -> IResult<Input, Input> {
+    let end = scan::whitespace(&input);
+    Ok((input.slice(end..), input.slice(..end)))
+}
+
+/// Like `ws!`, but doesn't skip line endings.
+macro_rules! hs (
+    ($i:expr, $($args:tt)*) => (
+        match sep!($i, space, $($args)*) {
+            Ok((rest, output)) => space(rest).map(|(rest, _)| (rest, output)),
+            Err(e) => Err(e),
+        }
+    )
+);
+
 /// An identifier parser.
 ///
 /// It allows any Unicode identifier as specified by [Unicode Standard Annex #31:
 /// Unicode Identifier and Pattern Syntax](http://www.unicode.org/reports/tr31/tr31-9.html).
 /// In addition, it allows use of underscores in identifiers, as it is tradition
 /// in C like programming languages, and ! at beginning (like xkas and Asar require,
-/// but not MVP).
+/// but not MVP), unless disabled with `ParserOptions::bang_identifiers`.
+fn identifier(input: Input<'_>) -> IResult<Input<'_>, &str> {
+    let mut indices = input.char_indices();
+    match indices.next() {
+        Some((_, '!')) if !input.options().bang_identifiers => {}
+        Some((_, c)) if valid_identifier_first_character(c) => {
+            // Many alternatives start with an identifier, so an identifier
+            // alone doesn't count as progress for `SyntaxError`.
+            let end = scan::identifier_end(&input, c.len_utf8());
+            return Ok((input.skip(end), &input.fragment()[..end]));
+        }
+        _ => {}
+    }
+    Err(nom::Err::Error(error_position!(input, ErrorKind::Alpha)))
+}
+
+/// A keyword parser.
+///
+/// Keywords are case insensitive identifiers with a special meaning, like
+/// directive names. Identifiers which merely start with a keyword are not
+/// matched, so `warnpcx` is not treated as `warnpc` followed by `x`.
+fn keyword<'a>(input: Input<'a>, name: &str) -> IResult<Input<'a>, &'a str> {
+    let (rest, parsed) = identifier(input)?;
+    if parsed.eq_ignore_ascii_case(name) {
+        Ok((rest, parsed))
+    } else {
+        Err(nom::Err::Error(error_position!(input, ErrorKind::Tag)))
+    }
+}
+
+named!(
+/// A program parser.
+///
+/// A program consists of lines, each containing at most one statement.
+/// Comments start with `;` and continue until the end of a line.
+,
+program<Input, Vec<Spanned<Statement>>>, terminated!(block, eof!()));
+
+named!(block<Input, Vec<Spanned<Statement>>>, map!(
+    many0!(alt!(
+        end_of_line => { |_| None }
+        | call!(spanned, label_declaration) => { Some }
+        | terminated!(call!(spanned, valid_statement), end_of_line) => { Some }
+    )),
+    |lines| lines.into_iter().flatten().collect()
+));
+
+/// Runs a parser, and records the consumed input, without surrounding
+/// whitespace, as a span of its output.
+fn spanned<'a, T>(
+    input: Input<'a>,
+    parser: fn(Input<'a>) -> IResult<Input<'a>, T>,
+) -> IResult<Input<'a>, Spanned<'a, T>> {
+    let (rest, node) = parser(input)?;
+    let consumed = &input.fragment()[..input.len() - rest.len()];
+    let span = Span::new(consumed.trim());
+    Ok((rest, Spanned { node, span }))
+}
+
+/// A reason why a line couldn't be parsed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ParseErrorKind {
+    /// Line isn't a valid statement.
+    InvalidStatement,
+    /// `elseif`, `else` or `endif` outside of an if block,
+    /// `endspcblock` outside of an SPC700 block, or `endmacro` outside of
+    /// a macro.
+    UnexpectedBlockKeyword,
+    /// If block without `endif`.
+    UnterminatedIf,
+    /// SPC700 block without `endspcblock`.
+    UnterminatedSpcBlock,
+    /// Macro without `endmacro`.
+    UnterminatedMacro,
+    /// Invalid operand after an operator.
+    InvalidExpression,
+}
+
+/// A parse error found by `program_with_recovery`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError<'a> {
+    /// Line with an error, without surrounding whitespace and comment. For
+    /// unterminated blocks, this is the line starting a block, and for
+    /// invalid expressions, this is the invalid operand.
+    pub span: Span<'a>,
+    pub kind: ParseErrorKind,
+    /// Where an invalid line stops making sense, and what was expected
+    /// there.
+    pub syntax: Option<SyntaxError<'a>>,
+}
+
+impl<'a> ParseError<'a> {
+    /// Where an error starts, which is where parsing of an invalid line
+    /// failed, or the start of `span`.
+    pub fn position(&self) -> Span<'a> {
+        self.syntax.map_or(self.span, |syntax| syntax.span())
+    }
+}
+
+/// Writes what was expected, like `SyntaxError`, as the position is given
+/// by `ParseError::position`, or the reason and the span otherwise.
+impl<'a> fmt::Display for ParseError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref syntax) = self.syntax {
+            return syntax.write_expected(f);
+        }
+        let reason = match self.kind {
+            ParseErrorKind::InvalidStatement => "invalid statement",
+            ParseErrorKind::UnexpectedBlockKeyword => "unexpected block keyword",
+            ParseErrorKind::UnterminatedIf => "if block without `endif`",
+            ParseErrorKind::UnterminatedSpcBlock => "SPC700 block without `endspcblock`",
+            ParseErrorKind::UnterminatedMacro => "macro without `endmacro`",
+            ParseErrorKind::InvalidExpression => "invalid operand",
+        };
+        write!(f, "{} `{}`", reason, self.span.fragment())
+    }
+}
+
+impl<'a> error::Error for ParseError<'a> {}
+
+/// What a parser expected to find where a `SyntaxError` happened.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Expected {
+    /// A statement, like an opcode or a directive.
+    Statement,
+    /// A name.
+    Identifier,
+    /// An operand or an expression.
+    Expression,
+    /// A closing parenthesis or bracket.
+    Closing(char),
+    /// End of a line, after a complete statement.
+    EndOfLine,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expected::Statement => f.write_str("a statement"),
+            Expected::Identifier => f.write_str("an identifier"),
+            Expected::Expression => f.write_str("an expression"),
+            Expected::Closing(c) => write!(f, "`{}`", c),
+            Expected::EndOfLine => f.write_str("end of line"),
+        }
+    }
+}
+
+/// A syntax error returned by parsing functions.
+///
+/// An error points at the furthest place any parser managed to reach, which
+/// usually is where the code stops making sense.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SyntaxError<'a> {
+    /// Byte offset of an error within the source.
+    pub offset: usize,
+    /// Line of an error, counted from one.
+    pub line: u32,
+    /// Column of an error, counted from one.
+    pub column: u32,
+    /// Text from an error until the end of its line.
+    pub found: &'a str,
+    /// What was expected instead of `found`.
+    pub expected: Expected,
+}
+
+impl<'a> SyntaxError<'a> {
+    /// Span of the text an error was found at, for locating it in
+    /// a source.
+    pub fn span(&self) -> Span<'a> {
+        Span::new(self.found)
+    }
+
+    fn write_expected(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {}, found ", self.expected)?;
+        if self.found.is_empty() {
+            f.write_str("end of line")
+        } else {
+            write!(f, "`{}`", self.found)
+        }
+    }
+}
+
+impl<'a> fmt::Display for SyntaxError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        self.write_expected(f)
+    }
+}
+
+impl<'a> error::Error for SyntaxError<'a> {}
+
+/// Parses a program, returning a `SyntaxError` on failure.
+///
+/// A program consists of lines, each containing at most one statement.
+/// Comments start with `;` and continue until the end of a line.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::grammar::{self, Expected};
+///
+/// let statements = grammar::parse_program("
+///     ; Brightness
+///     LDA #$0F
+///     STA $2100
+/// ").unwrap();
+/// assert_eq!(statements.len(), 2);
+///
+/// let error = grammar::parse_program("LDA #1\nLDA ($19,,x)\n").unwrap_err();
+/// assert_eq!((error.line, error.column), (2, 10));
+/// assert_eq!(error.found, ",x)");
+/// assert_eq!(error.expected, Expected::Expression);
+/// assert_eq!(
+///     error.to_string(),
+///     "line 2, column 10: expected an expression, found `,x)`",
+/// );
+/// ```
+pub fn parse_program<'a, I: Into<Input<'a>>>(
+    input: I,
+) -> Result<Vec<Spanned<'a, Statement<'a>>>, SyntaxError<'a>> {
+    run(input.into(), program, Expected::Statement).map(|(statements, _)| statements)
+}
+
+/// Parses a single statement, which is terminated by a line ending or a
+/// comment.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::ast::{Expression, Number, NumberWidth, Statement, VariableName};
+/// use mvp::parser::grammar;
+///
+/// let (parsed, rest) = grammar::parse_statement("hello = 44 ; answer").unwrap();
+/// let expected = Statement::Assignment(
+///     VariableName("hello"),
+///     Expression::Number(Number { value: 44, width: NumberWidth::None }),
+/// );
+/// assert_eq!(parsed, expected);
+/// assert_eq!(rest.fragment(), "; answer");
+/// ```
+pub fn parse_statement<'a, I: Into<Input<'a>>>(
+    input: I,
+) -> Result<(Statement<'a>, Input<'a>), SyntaxError<'a>> {
+    run(input.into(), statement, Expected::Statement)
+}
+
+/// Parses an expression.
+///
+/// This can be used as math expression parser, however due to language
+/// limitations, it doesn't support types like decimal numbers.
+/// However, it does support mathematical operators like addition,
+/// subtraction, multiplication and division, as well as parenthesis.
+/// Comparison operators have the lowest precedence, and evaluate to 1 when
+/// a comparison is true, and 0 otherwise. With `Precedence::LeftToRight`,
+/// all operators are evaluated from left to right instead.
+///
+/// # Example
+///
+/// Parsing a mathematical expression:
+///
+/// ```
+/// use mvp::parser::grammar;
+/// use mvp::parser::ast::{BinaryOperator, Expression, Number, NumberWidth};
+///
+/// let (parsed, rest) = grammar::parse_expression("2 + 3").unwrap();
+/// let expected = Expression::Binary(
+///     BinaryOperator::Add,
+///     Box::new((
+///         Expression::Number(Number { value: 2, width: NumberWidth::None }).into(),
+///         Expression::Number(Number { value: 3, width: NumberWidth::None }).into(),
+///     )),
+/// );
+/// assert_eq!(parsed, expected);
+/// assert!(rest.is_empty());
+/// ```
+///
+/// Operands keep the spans they were parsed from:
+///
+/// ```
+/// use mvp::parser::grammar;
+/// use mvp::parser::ast::Expression;
+///
+/// let (parsed, _) = grammar::parse_expression("-(a + 1) * f(2, b)").unwrap();
+/// let operands = match parsed {
+///     Expression::Binary(_, operands) => operands,
+///     _ => unreachable!(),
+/// };
+/// assert_eq!(operands.0.span.fragment(), "-(a + 1)");
+/// assert_eq!(operands.1.span.fragment(), "f(2, b)");
+/// match operands.1.node {
+///     Expression::Call(_, ref arguments) => assert_eq!(arguments[1].span.fragment(), "b"),
+///     _ => unreachable!(),
+/// }
+/// ```
+pub fn parse_expression<'a, I: Into<Input<'a>>>(
+    input: I,
+) -> Result<(Expression<'a>, Input<'a>), SyntaxError<'a>> {
+    run(input.into(), expression, Expected::Expression)
+}
+
+/// Parses an identifier.
+///
+/// It allows any Unicode identifier as specified by [Unicode Standard Annex #31:
+/// Unicode Identifier and Pattern Syntax](http://www.unicode.org/reports/tr31/tr31-9.html).
+/// In addition, it allows use of underscores in identifiers, as it is tradition
+/// in C like programming languages, and ! at beginning (like xkas and Asar require,
+/// but not MVP), unless disabled with `ParserOptions::bang_identifiers`.
 ///
 /// # Examples
 ///
 /// Parsing an Unicode identifier.
 ///
 /// ```
-/// use mvp::parser::grammar::{self, CompleteStr};
+/// use mvp::parser::grammar;
 ///
-/// let parsed = grammar::identifier(CompleteStr("世界"));
-/// assert_eq!(parsed, Ok((CompleteStr(""), "世界")));
+/// let (parsed, rest) = grammar::parse_identifier("世界").unwrap();
+/// assert_eq!(parsed, "世界");
+/// assert!(rest.is_empty());
 /// ```
-pub fn identifier(input: CompleteStr) -> IResult<CompleteStr, &str> {
-    let mut indices = input.char_indices();
-    match indices.next() {
-        Some((_, c)) if valid_identifier_first_character(c) => {}
-        _ => return Err(nom::Err::Error(error_position!(input, ErrorKind::Alpha))),
+pub fn parse_identifier<'a, I: Into<Input<'a>>>(
+    input: I,
+) -> Result<(&'a str, Input<'a>), SyntaxError<'a>> {
+    run(input.into(), identifier, Expected::Identifier)
+}
+
+pub(crate) fn run<'a, T, F>(
+    input: Input<'a>,
+    parser: F,
+    at_start: Expected,
+) -> Result<(T, Input<'a>), SyntaxError<'a>>
+where
+    F: Fn(Input<'a>) -> IResult<Input<'a>, T>,
+{
+    match parser(input) {
+        Ok((rest, output)) => Ok((output, rest)),
+        Err(_) => Err(syntax_error(input, parser, at_start)),
+    }
+}
+
+/// Runs a parser again, tracking progress, to find where it fails.
+fn syntax_error<'a, T, F>(input: Input<'a>, parser: F, at_start: Expected) -> SyntaxError<'a>
+where
+    F: Fn(Input<'a>) -> IResult<Input<'a>, T>,
+{
+    let furthest = input.track(|input| {
+        let _ = parser(input);
+    });
+    let position = input.slice(furthest - input.offset()..);
+    let source = input.fragment();
+    let before = &source[..position.offset() - input.offset()];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let found = position.fragment().lines().next().unwrap_or("");
+    SyntaxError {
+        offset: position.offset(),
+        line: position.line(),
+        column: position.column(),
+        found: found.trim_end(),
+        expected: expected(&before[line_start..], at_start),
+    }
+}
+
+/// Guesses what was expected after a part of a line.
+fn expected(before: &str, at_start: Expected) -> Expected {
+    let before = before.trim_end();
+    match before.chars().last() {
+        None => return at_start,
+        Some(c) if "+-*/<>=!(,#[".contains(c) => return Expected::Expression,
+        _ => {}
+    }
+    let mut open = Vec::new();
+    for c in before.chars() {
+        match c {
+            '(' | '[' => open.push(c),
+            ')' | ']' => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    match open.pop() {
+        Some('(') => Expected::Closing(')'),
+        Some(_) => Expected::Closing(']'),
+        None => Expected::EndOfLine,
+    }
+}
+
+/// A program parser that doesn't stop at the first error.
+///
+/// When a line cannot be parsed, an error is recorded and parsing
+/// continues on the next line, so that all errors can be reported at
+/// once. Statements are returned along with errors, with invalid code
+/// replaced by `Statement::Error`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::ast::Statement;
+/// use mvp::parser::grammar::{self, Input, ParseErrorKind};
+///
+/// let source = "warnpc 1\nLDA #\nendif\nwarnpc 2\n";
+/// let (statements, errors) = grammar::program_with_recovery(Input::new(source));
+/// assert_eq!(statements.len(), 4);
+/// match statements[1].node {
+///     Statement::Error(span, _) => assert_eq!(span.fragment(), "LDA #"),
+///     ref statement => panic!("unexpected statement: {:?}", statement),
+/// }
+/// assert_eq!(errors[0].span.fragment(), "LDA #");
+/// assert_eq!(errors[0].to_string(), "expected an expression, found end of line");
+/// assert_eq!(errors[1].kind, ParseErrorKind::UnexpectedBlockKeyword);
+/// ```
+pub fn program_with_recovery(input: Input) -> (Vec<Spanned<Statement>>, Vec<ParseError>) {
+    let mut errors = Vec::new();
+    let (_, statements) = recovering_block(input, &mut errors, false);
+    (statements, errors)
+}
+
+fn recovering_block<'a>(
+    mut input: Input<'a>,
+    errors: &mut Vec<ParseError<'a>>,
+    nested: bool,
+) -> (Input<'a>, Vec<Spanned<'a, Statement<'a>>>) {
+    let mut statements = Vec::new();
+    while !input.is_empty() {
+        if let Ok((rest, _)) = end_of_line(input) {
+            input = rest;
+            continue;
+        }
+        let is_block_end = block_end(input).is_ok();
+        if nested && is_block_end {
+            break;
+        }
+        if let Ok((rest, statement)) = statement_line(input) {
+            report_invalid_operands(errors, |visitor| visitor.visit_statement(&statement));
+            statements.push(statement);
+            input = rest;
+        } else if let Ok((rest, predicate)) = call!(input, if_header, "if") {
+            report_invalid_operands(errors, |visitor| visitor.visit_expression(&predicate));
+            let (rest, statement) = recovering_if(input, rest, predicate, errors);
+            statements.push(statement);
+            input = rest;
+        } else if let Ok((rest, address)) = spc_block_header(input) {
+            report_invalid_operands(errors, |visitor| visitor.visit_expression(&address));
+            let (rest, statement) = recovering_spc_block(input, rest, address, errors);
+            statements.push(statement);
+            input = rest;
+        } else if let Ok((rest, header)) = macro_header(input) {
+            let (rest, statement) = recovering_macro(input, rest, header, errors);
+            statements.push(statement);
+            input = rest;
+        } else {
+            let (rest, line) = skip_line(input);
+            let (kind, syntax) = if is_block_end {
+                (ParseErrorKind::UnexpectedBlockKeyword, None)
+            } else {
+                let syntax = syntax_error(input, statement_line, Expected::Statement);
+                (ParseErrorKind::InvalidStatement, Some(syntax))
+            };
+            errors.push(ParseError {
+                span: line,
+                kind,
+                syntax,
+            });
+            statements.push(error_statement(line, syntax));
+            input = rest;
+        }
+    }
+    (input, statements)
+}
+
+/// Parses remaining part of an if block, after its first line.
+///
+/// When a condition couldn't be parsed, the block is replaced with an
+/// error statement.
+fn recovering_if<'a>(
+    start: Input<'a>,
+    mut input: Input<'a>,
+    predicate: Expression<'a>,
+    errors: &mut Vec<ParseError<'a>>,
+) -> (Input<'a>, Spanned<'a, Statement<'a>>) {
+    let mut conditions = Vec::new();
+    let mut predicate = Some(Some(predicate));
+    let mut valid = true;
+    loop {
+        let (rest, statements) = recovering_block(input, errors, true);
+        match predicate {
+            Some(predicate) => conditions.push(Condition {
+                predicate,
+                statements,
+            }),
+            None => {
+                if let Some(condition) = conditions.last_mut() {
+                    condition.statements.extend(statements);
+                }
+            }
+        }
+        input = rest;
+        if input.is_empty() {
+            let line = skip_line(start).1;
+            errors.push(ParseError {
+                span: line,
+                kind: ParseErrorKind::UnterminatedIf,
+                syntax: None,
+            });
+            return (input, error_statement(line, None));
+        }
+        if let Ok((rest, end)) = endif_line(input) {
+            let span = Span::new(start.fragment()[..start.len() - end.len()].trim());
+            let statement = if valid {
+                let node = Statement::If(conditions);
+                Spanned { node, span }
+            } else {
+                error_statement(span, None)
+            };
+            return (rest, statement);
+        }
+        if let Ok((rest, elseif)) = call!(input, if_header, "elseif") {
+            report_invalid_operands(errors, |visitor| visitor.visit_expression(&elseif));
+            predicate = Some(Some(elseif));
+            input = rest;
+        } else if let Ok((rest, _)) = terminated!(input, hs!(call!(keyword, "else")), end_of_line) {
+            predicate = Some(None);
+            input = rest;
+        } else {
+            let (rest, line) = skip_line(input);
+            let syntax = syntax_error(
+                input,
+                |input| if_header(input, "elseif"),
+                Expected::Statement,
+            );
+            errors.push(ParseError {
+                span: line,
+                kind: ParseErrorKind::InvalidStatement,
+                syntax: Some(syntax),
+            });
+            valid = false;
+            predicate = None;
+            input = rest;
+        }
+    }
+}
+
+/// Parses remaining part of an SPC700 block, after its first line.
+fn recovering_spc_block<'a>(
+    start: Input<'a>,
+    mut input: Input<'a>,
+    address: Expression<'a>,
+    errors: &mut Vec<ParseError<'a>>,
+) -> (Input<'a>, Spanned<'a, Statement<'a>>) {
+    let mut statements = Vec::new();
+    input = input.with_parser_options(spc700_options(start));
+    loop {
+        let (rest, block) = recovering_block(input, errors, true);
+        statements.extend(block);
+        input = rest;
+        if input.is_empty() {
+            let line = skip_line(start).1;
+            errors.push(ParseError {
+                span: line,
+                kind: ParseErrorKind::UnterminatedSpcBlock,
+                syntax: None,
+            });
+            return (input, error_statement(line, None));
+        }
+        if let Ok((end, execute)) = spc_block_end(input) {
+            if let Ok((rest, _)) = end_of_line(end) {
+                let span = Span::new(start.fragment()[..start.len() - end.len()].trim());
+                let node = Statement::SpcBlock(SpcBlock {
+                    address,
+                    statements,
+                    execute,
+                });
+                return (
+                    rest.with_parser_options(start.options()),
+                    Spanned { node, span },
+                );
+            }
+        }
+        let (kind, syntax) = if hs!(input, call!(keyword, "endspcblock")).is_ok() {
+            let end = |input: Input<'a>| terminated!(input, spc_block_end, end_of_line);
+            let syntax = syntax_error(input, end, Expected::EndOfLine);
+            (ParseErrorKind::InvalidStatement, Some(syntax))
+        } else {
+            (ParseErrorKind::UnexpectedBlockKeyword, None)
+        };
+        let (rest, line) = skip_line(input);
+        errors.push(ParseError {
+            span: line,
+            kind,
+            syntax,
+        });
+        statements.push(error_statement(line, syntax));
+        input = rest;
+    }
+}
+
+/// Parses remaining part of a macro, after its first line.
+fn recovering_macro<'a>(
+    start: Input<'a>,
+    mut input: Input<'a>,
+    (name, parameters, evaluated, variadic): MacroHeader<'a>,
+    errors: &mut Vec<ParseError<'a>>,
+) -> (Input<'a>, Spanned<'a, Statement<'a>>) {
+    let mut statements = Vec::new();
+    loop {
+        let (rest, block) = recovering_block(input, errors, true);
+        statements.extend(block);
+        input = rest;
+        if input.is_empty() {
+            let line = skip_line(start).1;
+            errors.push(ParseError {
+                span: line,
+                kind: ParseErrorKind::UnterminatedMacro,
+                syntax: None,
+            });
+            return (input, error_statement(line, None));
+        }
+        if let Ok((end, _)) = hs!(input, call!(keyword, "endmacro")) {
+            if let Ok((rest, _)) = end_of_line(end) {
+                let span = Span::new(start.fragment()[..start.len() - end.len()].trim());
+                let node = Statement::Macro(Macro {
+                    name,
+                    parameters,
+                    evaluated,
+                    variadic,
+                    statements,
+                });
+                return (rest, Spanned { node, span });
+            }
+        }
+        let (kind, syntax) = if hs!(input, call!(keyword, "endmacro")).is_ok() {
+            let end =
+                |input: Input<'a>| terminated!(input, hs!(call!(keyword, "endmacro")), end_of_line);
+            let syntax = syntax_error(input, end, Expected::EndOfLine);
+            (ParseErrorKind::InvalidStatement, Some(syntax))
+        } else {
+            (ParseErrorKind::UnexpectedBlockKeyword, None)
+        };
+        let (rest, line) = skip_line(input);
+        errors.push(ParseError {
+            span: line,
+            kind,
+            syntax,
+        });
+        statements.push(error_statement(line, syntax));
+        input = rest;
+    }
+}
+
+fn error_statement<'a>(
+    span: Span<'a>,
+    syntax: Option<SyntaxError<'a>>,
+) -> Spanned<'a, Statement<'a>> {
+    Spanned {
+        node: Statement::Error(span, syntax),
+        span,
+    }
+}
+
+/// Parses a line with a label or a statement.
+fn statement_line(input: Input) -> IResult<Input, Spanned<Statement>> {
+    spanned(input, label_declaration)
+        .or_else(|_| terminated!(input, call!(spanned, statement), end_of_line))
+}
+
+/// Collects spans of invalid operands.
+#[derive(Default)]
+struct InvalidOperands<'a>(Vec<Span<'a>>);
+
+impl<'a> Visitor<'a> for InvalidOperands<'a> {
+    fn visit_expression(&mut self, expression: &Expression<'a>) {
+        match *expression {
+            Expression::Error(span) => self.0.push(span),
+            _ => visit::walk_expression(self, expression),
+        }
+    }
+}
+
+fn report_invalid_operands<'a, F>(errors: &mut Vec<ParseError<'a>>, visit: F)
+where
+    F: FnOnce(&mut InvalidOperands<'a>),
+{
+    let mut operands = InvalidOperands::default();
+    visit(&mut operands);
+    errors.extend(operands.0.into_iter().map(|span| ParseError {
+        span,
+        kind: ParseErrorKind::InvalidExpression,
+        syntax: None,
+    }));
+}
+
+fn if_header<'a>(input: Input<'a>, name: &str) -> IResult<Input<'a>, Expression<'a>> {
+    do_parse!(
+        input,
+        hs!(call!(keyword, name)) >>
+        predicate: expression >>
+        end_of_line >>
+        (predicate)
+    )
+}
+
+/// Parses `endif` line, also returning input right after the keyword.
+fn endif_line(input: Input) -> IResult<Input, Input> {
+    let (end, _) = hs!(input, call!(keyword, "endif"))?;
+    let (rest, _) = end_of_line(end)?;
+    Ok((rest, end))
+}
+
+named!(
+    block_end<Input<'_>, &str>,
+    hs!(alt!(
+        call!(keyword, "elseif")
+            | call!(keyword, "else")
+            | call!(keyword, "endif")
+            | call!(keyword, "endspcblock")
+            | call!(keyword, "endmacro")
+    ))
+);
+
+/// Skips a line, returning its contents without surrounding whitespace
+/// and comment.
+fn skip_line(input: Input) -> (Input, Span) {
+    let end = input.find('\n').map_or(input.len(), |i| i + 1);
+    let line = &input.fragment()[..end];
+    let code = line.find(';').map_or(line, |i| &line[..i]);
+    (input.slice(end..), Span::new(code.trim()))
+}
+
+named!(label_declaration<Input, Statement>, hs!(do_parse!(
+    label: label >>
+    char!(':') >>
+    not!(char!('=')) >>
+    (Statement::Label(label))
+)));
+
+fn comment(input: Input) -> IResult<Input, Input> {
+    let (rest, _) = char!(input, ';')?;
+    let end = scan::line(&rest);
+    Ok((rest.slice(end..), rest.slice(..end)))
+}
+
+named!(end_of_line<Input, ()>, do_parse!(
+    space >>
+    opt!(comment) >>
+    alt!(eof!() | tag!("\n") | tag!("\r\n")) >>
+    ()
+));
+
+/// A statement parser rejecting statements with invalid operands.
+fn valid_statement(input: Input) -> IResult<Input, Statement> {
+    let (rest, statement) = statement(input)?;
+    let statement = Spanned::from(statement);
+    let mut operands = InvalidOperands::default();
+    operands.visit_statement(&statement);
+    if operands.0.is_empty() {
+        Ok((rest, statement.node))
+    } else {
+        Err(nom::Err::Error(error_position!(input, ErrorKind::Verify)))
+    }
+}
+
+named!(statement<Input, Statement>, hs!(alt!(
+    directive
+    | asar_define
+    | assignment
+    | string_assignment
+    | asar_feature
+    | instruction
+)));
+
+/// Parses an instruction of the instruction set selected with `arch`.
+fn instruction(input: Input) -> IResult<Input, Statement> {
+    match input.options().arch {
+        Arch::W65816 | Arch::W6502 | Arch::W65C02 => map!(input, opcode, Statement::Opcode),
+        Arch::Spc700 => map!(input, spc700_instruction, Statement::Spc700),
+        Arch::SuperFx => map!(input, superfx_instruction, Statement::SuperFx),
+    }
+}
+
+named!(if_statement<Input, Statement>, do_parse!(
+    first: call!(condition, "if") >>
+    others: many0!(call!(condition, "elseif")) >>
+    otherwise: opt!(do_parse!(
+        hs!(call!(keyword, "else")) >>
+        end_of_line >>
+        statements: block >>
+        (Condition { predicate: None, statements })
+    )) >>
+    hs!(call!(keyword, "endif")) >>
+    (Statement::If(iter::once(first).chain(others).chain(otherwise).collect()))
+));
+
+fn condition<'a>(input: Input<'a>, name: &str) -> IResult<Input<'a>, Condition<'a>> {
+    do_parse!(
+        input,
+        hs!(call!(keyword, name)) >>
+        predicate: expression >>
+        end_of_line >>
+        statements: block >>
+        (Condition { predicate: Some(predicate), statements })
+    )
+}
+
+named!(directive<Input, Statement>, alt!(
+    if_statement
+    | spc_block
+    | macro_definition
+    | macro_call
+    | warnpc
+    | assert
+    | print
+    | find_pattern
+    | function => { Statement::Function }
+    | mapper => { Statement::Mapper }
+    | rom_variant
+    | variant_table
+    | expect_crc
+    | interrupt_safe
+    | global
+    | include
+    | include_once
+    | undef
+    | register_width
+    | arch
+    | warnings
+    | bank
+    | asar
+    | math_priority
+));
+
+/// Parses `asar`, which makes following lines parse like in Asar.
+fn asar(input: Input) -> IResult<Input, Statement> {
+    let (rest, version) = hs!(
+        input,
+        do_parse!(
+            call!(keyword, "asar") >>
+            version: map_res!(
+                take_while1!(|c: char| c.is_ascii_digit() || c == '.'),
+                |version: Input| version.fragment().parse::<AsarVersion>()
+            ) >>
+            (version)
+        )
+    )?;
+    let options = input.options().for_asar(version);
+    Ok((rest.with_parser_options(options), Statement::Asar(version)))
+}
+
+/// Parses `math pri on` or `math pri off`, which change precedence of
+/// operators on following lines.
+fn math_priority(input: Input) -> IResult<Input, Statement> {
+    let (rest, precedence) = hs!(
+        input,
+        do_parse!(
+            call!(keyword, "math") >>
+            call!(keyword, "pri") >>
+            precedence: alt!(
+                call!(keyword, "on") => { |_| Precedence::Standard }
+                | call!(keyword, "off") => { |_| Precedence::LeftToRight }
+            ) >>
+            (precedence)
+        )
+    )?;
+    let options = ParserOptions {
+        precedence,
+        ..input.options()
     };
-    for (pos, c) in indices {
-        if !valid_later_character(c) {
-            return Ok((CompleteStr(&input[pos..]), &input[..pos]));
+    Ok((
+        rest.with_parser_options(options),
+        Statement::Precedence(precedence),
+    ))
+}
+
+/// Parses an Asar define, after `asar`.
+///
+/// Defines assigned expressions with `=` or `#=` are assignments, as
+/// they are evaluated the same way. Other defines hold text, which isn't
+/// supported.
+fn asar_define(input: Input) -> IResult<Input, Statement> {
+    if input.options().asar_version.is_none() {
+        return Err(nom::Err::Error(error_position!(input, ErrorKind::Verify)));
+    }
+    let (rest, (name, operator)) = hs!(
+        input,
+        tuple!(
+            verify!(identifier, |name: &str| name.starts_with('!')),
+            alt!(tag!("=") | tag!("#=") | tag!("?=") | tag!("+="))
+        )
+    )?;
+    let value = terminated!(rest, expression, peek!(end_of_line));
+    let feature = match (operator.fragment(), value) {
+        ("#=", Ok((rest, value))) => {
+            return Ok((rest, Statement::Assignment(VariableName(name), value)));
         }
+        // Left for `assignment`.
+        ("=", Ok(_)) => return Err(nom::Err::Error(error_position!(input, ErrorKind::Verify))),
+        ("?=", _) => "!define ?= text",
+        ("+=", _) => "!define += text",
+        _ => "!define = text",
+    };
+    Ok((skip_code(rest), Statement::AsarFeature(feature)))
+}
+
+/// Parses a line using an Asar directive mvp doesn't have, after `asar`.
+fn asar_feature(input: Input) -> IResult<Input, Statement> {
+    if input.options().asar_version.is_none() {
+        return Err(nom::Err::Error(error_position!(input, ErrorKind::Verify)));
     }
-    Ok((CompleteStr(""), &input))
+    let (rest, directive) = map_opt!(input, identifier, asar::unsupported_directive)?;
+    Ok((skip_code(rest), Statement::AsarFeature(directive)))
+}
+
+/// Skips code until a comment or the end of a line.
+fn skip_code(input: Input) -> Input {
+    let end = input.find([';', '\n']).unwrap_or_else(|| input.len());
+    input.slice(end..)
 }
 
-named!(pub statement<CompleteStr, Statement>, ws!(alt!(
-    opcode => { |opcode| Statement::Opcode(opcode) }
+/// Parses `arch`, which changes how following lines are parsed.
+fn arch(input: Input) -> IResult<Input, Statement> {
+    let (rest, arch) = hs!(
+        input,
+        do_parse!(
+            call!(keyword, "arch") >>
+            arch: map_opt!(
+                take_while1!(|c: char| c.is_alphanumeric()),
+                |name: Input| Arch::from_directive(name.fragment())
+            ) >>
+            (arch)
+        )
+    )?;
+    let options = ParserOptions {
+        arch,
+        ..input.options()
+    };
+    Ok((rest.with_parser_options(options), Statement::Arch(arch)))
+}
+
+/// Parses `spcblock`, whose statements are SPC700 code until `endspcblock`.
+fn spc_block(input: Input) -> IResult<Input, Statement> {
+    let (rest, address) = spc_block_header(input)?;
+    let (rest, statements) = block(rest.with_parser_options(spc700_options(input)))?;
+    let (rest, execute) = spc_block_end(rest)?;
+    let block = SpcBlock {
+        address,
+        statements,
+        execute,
+    };
+    Ok((
+        rest.with_parser_options(input.options()),
+        Statement::SpcBlock(block),
+    ))
+}
+
+/// Options of `input` with the SPC700 instruction set.
+fn spc700_options(input: Input) -> ParserOptions {
+    ParserOptions {
+        arch: Arch::Spc700,
+        ..input.options()
+    }
+}
+
+named!(spc_block_header<Input, Expression>, do_parse!(
+    hs!(call!(keyword, "spcblock")) >>
+    address: expression >>
+    end_of_line >>
+    (address)
+));
+
+named!(spc_block_end<Input, Option<Expression>>, hs!(do_parse!(
+    call!(keyword, "endspcblock") >>
+    execute: opt!(do_parse!(
+        call!(keyword, "execute") >>
+        execute: expression >>
+        (execute)
+    )) >>
+    (execute)
 )));
 
-named!(immediate<CompleteStr, (Expression, OpcodeMode)>, ws!(do_parse!(
+/// Parses `macro`, whose statements are expanded in place of its calls.
+fn macro_definition(input: Input) -> IResult<Input, Statement> {
+    do_parse!(
+        input,
+        header: macro_header >>
+        statements: block >>
+        hs!(call!(keyword, "endmacro")) >>
+        (Statement::Macro(Macro {
+            name: header.0,
+            parameters: header.1,
+            evaluated: header.2,
+            variadic: header.3,
+            statements,
+        }))
+    )
+}
+
+/// Name, parameters, parameters evaluated at a call and whether a macro
+/// is variadic.
+type MacroHeader<'a> = (
+    VariableName<'a>,
+    Vec<VariableName<'a>>,
+    Vec<VariableName<'a>>,
+    bool,
+);
+
+named!(macro_header<Input, MacroHeader>, do_parse!(
+    header: hs!(do_parse!(
+        call!(keyword, "macro") >>
+        name: identifier >>
+        char!('(') >>
+        parameters: separated_list!(char!(','), hs!(pair!(opt!(char!('#')), identifier))) >>
+        variadic: opt!(hs!(do_parse!(
+            cond!(!parameters.is_empty(), char!(',')) >>
+            tag!("...") >>
+            ()
+        ))) >>
+        char!(')') >>
+        (
+            VariableName(name),
+            parameters.iter().map(|&(_, name)| VariableName(name)).collect(),
+            parameters
+                .iter()
+                .filter(|(evaluated, _)| evaluated.is_some())
+                .map(|&(_, name)| VariableName(name))
+                .collect(),
+            variadic.is_some()
+        )
+    )) >>
+    end_of_line >>
+    (header)
+));
+
+named!(macro_call<Input, Statement>, hs!(do_parse!(
+    char!('%') >>
+    name: identifier >>
+    char!('(') >>
+    arguments: separated_list!(char!(','), expression) >>
+    splice: opt!(hs!(do_parse!(
+        cond!(!arguments.is_empty(), char!(',')) >>
+        tag!("<...>") >>
+        ()
+    ))) >>
+    char!(')') >>
+    (Statement::MacroCall(MacroCall {
+        name: VariableName(name),
+        arguments,
+        splice: splice.is_some(),
+    }))
+)));
+
+named!(mapper<Input, Mapper>, map_opt!(identifier, Mapper::from_directive));
+
+named!(register_width<Input, Statement>, map_opt!(identifier, |name: &str| {
+    let (register, width) = match &*name.to_ascii_lowercase() {
+        "a8" => (Register::Accumulator, 1),
+        "a16" => (Register::Accumulator, 2),
+        "i8" => (Register::Index, 1),
+        "i16" => (Register::Index, 2),
+        _ => return None,
+    };
+    Some(Statement::RegisterWidth(register, width))
+}));
+
+named!(warnings<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "warnings") >>
+    level: map_opt!(identifier, warning::Level::from_name) >>
+    category: map_opt!(
+        take_while1!(|c: char| c.is_alphanumeric() || c == '-'),
+        |name: Input| Category::from_name(name.fragment())
+    ) >>
+    (Statement::Warnings(category, level))
+)));
+
+named!(bank<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "bank") >>
+    bank: alt!(
+        call!(keyword, "auto") => { |_| None }
+        | expression => { Some }
+    ) >>
+    (Statement::Bank(bank))
+)));
+
+named!(rom_variant<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "romvariant") >>
+    name: identifier >>
+    char!(',') >>
+    crc: expression >>
+    (Statement::RomVariant(VariableName(name), crc))
+)));
+
+named!(variant_table<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "varianttable") >>
+    name: identifier >>
+    entries: many1!(hs!(do_parse!(
+        char!(',') >>
+        variant: identifier >>
+        char!('=') >>
+        address: expression >>
+        (VariableName(variant), address)
+    ))) >>
+    (Statement::VariantTable(VariableName(name), entries))
+)));
+
+named!(expect_crc<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "expectcrc") >>
+    checksums: separated_nonempty_list!(char!(','), expression) >>
+    (Statement::ExpectCrc(checksums))
+)));
+
+named!(include<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "incsrc") >>
+    path: string >>
+    (Statement::Include(path))
+)));
+
+named!(include_once<Input, Statement>, hs!(map!(
+    call!(keyword, "includeonce"),
+    |_| Statement::IncludeOnce
+)));
+
+named!(undef<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "undef") >>
+    name: string >>
+    (Statement::Undef(VariableName(name)))
+)));
+
+named!(interrupt_safe<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "interruptsafe") >>
+    names: separated_nonempty_list!(char!(','), hs!(identifier)) >>
+    (Statement::InterruptSafe(names.into_iter().map(VariableName).collect()))
+)));
+
+named!(global<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "global") >>
+    names: separated_nonempty_list!(char!(','), hs!(identifier)) >>
+    (Statement::Global(names.into_iter().map(VariableName).collect()))
+)));
+
+named!(warnpc<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "warnpc") >>
+    address: expression >>
+    (Statement::WarnPc(address))
+)));
+
+named!(assert<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "assert") >>
+    condition: expression >>
+    message: opt!(hs!(preceded!(char!(','), string))) >>
+    (Statement::Assert(condition, message))
+)));
+
+named!(print<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "print") >>
+    arguments: separated_nonempty_list!(char!(','), expression) >>
+    (Statement::Print(arguments))
+)));
+
+named!(find_pattern<Input, Statement>, hs!(do_parse!(
+    call!(keyword, "findpattern") >>
+    pattern: string >>
+    tag!("->") >>
+    name: identifier >>
+    (Statement::FindPattern(pattern, VariableName(name)))
+)));
+
+named!(function<Input, Function>, hs!(do_parse!(
+    call!(keyword, "function") >>
+    name: identifier >>
+    parameters: delimited!(
+        char!('('),
+        separated_list!(char!(','), hs!(identifier)),
+        char!(')')
+    ) >>
+    char!('=') >>
+    body: expression >>
+    (Function {
+        name: VariableName(name),
+        parameters: parameters.into_iter().map(VariableName).collect(),
+        body,
+    })
+)));
+
+named!(
+    string<Input<'_>, &str>,
+    delimited!(
+        char!('"'),
+        map!(take_while!(|c| c != '"'), |s: Input| s.fragment()),
+        char!('"')
+    )
+);
+
+named!(immediate<Input, (Spanned<Expression>, OpcodeMode)>, hs!(do_parse!(
     char!('#') >>
-    expression: expression >>
+    expression: spanned_expression >>
     (expression, OpcodeMode::Immediate)
 )));
 
-named!(indirect<CompleteStr, (Expression, OpcodeMode)>, ws!(do_parse!(
+named!(indirect<Input, (Spanned<Expression>, OpcodeMode)>, hs!(do_parse!(
     char!('(') >>
-    expression: expression >>
+    expression: spanned_expression >>
     char!(')') >>
     y: alt!(
         pair!(char!(','), tag_no_case!("y")) => { |_| OpcodeMode::IndirectY }
@@ -76,18 +1421,18 @@ named!(indirect<CompleteStr, (Expression, OpcodeMode)>, ws!(do_parse!(
     (expression, y)
 )));
 
-named!(x_indirect<CompleteStr, (Expression, OpcodeMode)>, ws!(do_parse!(
+named!(x_indirect<Input, (Spanned<Expression>, OpcodeMode)>, hs!(do_parse!(
     char!('(') >>
-    expression: expression >>
+    expression: spanned_expression >>
     char!(',') >>
     tag_no_case!("x") >>
     char!(')') >>
     (expression, OpcodeMode::XIndirect)
 )));
 
-named!(stack_indirect_y<CompleteStr, (Expression, OpcodeMode)>, ws!(do_parse!(
+named!(stack_indirect_y<Input, (Spanned<Expression>, OpcodeMode)>, hs!(do_parse!(
     char!('(') >>
-    expression: expression >>
+    expression: spanned_expression >>
     char!(',') >>
     tag_no_case!("s") >>
     char!(')') >>
@@ -96,158 +1441,746 @@ named!(stack_indirect_y<CompleteStr, (Expression, OpcodeMode)>, ws!(do_parse!(
     (expression, OpcodeMode::StackIndirectY)
 )));
 
-named!(long_indirect<CompleteStr, (Expression, OpcodeMode)>, ws!(do_parse!(
+named!(long_indirect<Input, (Spanned<Expression>, OpcodeMode)>, hs!(do_parse!(
     char!('[') >>
-    expression: expression >>
+    expression: spanned_expression >>
     char!(']') >>
     (expression, OpcodeMode::LongIndirect)
 )));
 
-named!(long_indirect_y<CompleteStr, (Expression, OpcodeMode)>, ws!(do_parse!(
+named!(long_indirect_y<Input, (Spanned<Expression>, OpcodeMode)>, hs!(do_parse!(
     res: long_indirect >>
     char!(',') >>
     one_of!("yY") >>
     (res.0, OpcodeMode::LongIndirectY)
 )));
 
-named!(address<CompleteStr, (Expression, OpcodeMode)>, do_parse!(
-    first: expression >>
-    second: opt!(preceded!(char!(','), expression)) >>
+named!(address<Input, (Spanned<Expression>, OpcodeMode)>, do_parse!(
+    first: spanned_expression >>
+    second: opt!(preceded!(char!(','), spanned_expression)) >>
     (first, match second {
         Some(second) => OpcodeMode::Move { second },
         None => OpcodeMode::Address,
     })
 ));
 
-named!(opcode<CompleteStr, Opcode>, do_parse!(
-    opcode: identifier >>
-    width: opt!(ws!(preceded!(char!('.'), alt!(
-        tag_no_case!("b") => {|_| 1}
-        | tag_no_case!("w") => {|_| 2}
-        | tag_no_case!("l") => {|_| 3}
-    )))) >>
+fn opcode(input: Input) -> IResult<Input, Opcode> {
+    let (rest, mut opcode) = opcode_as_written(input)?;
+    if input.options().normalize_mnemonics {
+        if let Some(name) = normalize_mnemonic(opcode.name) {
+            opcode.name = name;
+        }
+    }
+    Ok((rest, opcode))
+}
+
+/// Parses a width after a dot, as in `LDA.w`.
+///
+/// A width is a single letter, so that `BRA .loop` is a branch to a scoped
+/// label rather than a long branch to `oop`.
+fn width_suffix(input: Input) -> IResult<Input, u32> {
+    let width = match input.chars().next() {
+        Some('b') | Some('B') => 1,
+        Some('w') | Some('W') => 2,
+        Some('l') | Some('L') => 3,
+        _ => return Err(nom::Err::Error(error_position!(input, ErrorKind::Alt))),
+    };
+    let rest = input.slice(1..);
+    match rest.chars().next() {
+        Some(c) if valid_later_character(c) => {
+            Err(nom::Err::Error(error_position!(input, ErrorKind::Alt)))
+        }
+        _ => Ok((rest, width)),
+    }
+}
+
+named!(opcode_as_written<Input, Opcode>, do_parse!(
+    opcode: verify!(identifier, |name: &str| {
+        !BLOCK_KEYWORDS.iter().any(|keyword| name.eq_ignore_ascii_case(keyword))
+    }) >>
+    width: opt!(hs!(preceded!(char!('.'), width_suffix))) >>
     result: alt!(
-        immediate
-        | indirect
-        | x_indirect
-        | address
-        | long_indirect_y
-        | long_indirect
-        | stack_indirect_y
+        peek!(end_of_line) => { |_| (None, OpcodeMode::Implied) }
+        | map!(alt!(
+            immediate
+            | indirect
+            | x_indirect
+            | address
+            | long_indirect_y
+            | long_indirect
+            | stack_indirect_y
+        ), |(value, mode)| (Some(value), mode))
     ) >>
     (Opcode {
-        name: &opcode,
+        name: opcode,
         width,
         value: result.0,
         mode: result.1,
     })
 ));
 
+named!(spc700_instruction<Input, Spc700Instruction>, hs!(do_parse!(
+    name: verify!(identifier, |name: &str| {
+        !BLOCK_KEYWORDS.iter().any(|keyword| name.eq_ignore_ascii_case(keyword))
+    }) >>
+    operands: alt!(
+        peek!(end_of_line) => { |_| Vec::new() }
+        | separated_nonempty_list!(char!(','), spc700_operand)
+    ) >>
+    (Spc700Instruction { name, operands })
+)));
+
+/// Parses an SPC700 operand, like `#$12`, `(X)+` or `$1234+Y`.
+fn spc700_operand(input: Input) -> IResult<Input, spc700::Operand<Expression>> {
+    hs!(
+        input,
+        alt!(
+            preceded!(char!('#'), expression) => { spc700::Operand::Immediate }
+            | spc700_register_indirect
+            | spc700_indirect
+            | preceded!(char!('/'), spc700_bit) => { |(address, bit)| spc700::Operand::Bit {
+                address,
+                bit,
+                inverted: true,
+            } }
+            | preceded!(char!('!'), expression) => { |address| spc700_address(address, true) }
+            | spc700_register => { spc700::Operand::Register }
+            | spc700_bit => { |(address, bit)| spc700::Operand::Bit {
+                address,
+                bit,
+                inverted: false,
+            } }
+            | expression => { |address| spc700_address(address, false) }
+        )
+    )
+}
+
+/// Parses a register name, which isn't followed by an operator, so that
+/// `X+1` is an expression.
+fn spc700_register(input: Input) -> IResult<Input, spc700::Register> {
+    let (rest, name) = identifier(input)?;
+    let after = &rest[scan::whitespace(&rest)..];
+    match spc700::Register::from_name(name) {
+        Some(register) if !after.starts_with(|c| OPERATORS.contains(c)) => Ok((rest, register)),
+        _ => Err(nom::Err::Error(error_position!(input, ErrorKind::Tag))),
+    }
+}
+
+named!(spc700_register_indirect<Input, spc700::Operand<Expression>>, hs!(do_parse!(
+    char!('(') >>
+    register: spc700_register >>
+    char!(')') >>
+    increment: opt!(char!('+')) >>
+    operand: expr_opt!(match (register, increment) {
+        (spc700::Register::X, None) => Some(spc700::Operand::IndirectX),
+        (spc700::Register::X, Some(_)) => Some(spc700::Operand::IndirectXIncrement),
+        (spc700::Register::Y, None) => Some(spc700::Operand::IndirectY),
+        _ => None,
+    }) >>
+    (operand)
+)));
+
+/// Parses `(dp+X)`, `(dp)+Y` and the same with brackets.
+fn spc700_indirect(input: Input) -> IResult<Input, spc700::Operand<Expression>> {
+    let (rest, (open, absolute, address)) = hs!(
+        input,
+        tuple!(
+            one_of!("(["),
+            map!(opt!(char!('!')), |bang| bang.is_some()),
+            expression
+        )
+    )?;
+    let close = if open == '(' { ')' } else { ']' };
+    match spc700_index(address) {
+        (address, Some(spc700::Register::X)) => {
+            let (rest, _) = hs!(rest, char!(close))?;
+            Ok((rest, spc700::Operand::IndexedIndirect { address, absolute }))
+        }
+        (address, None) if !absolute => {
+            let (rest, _) = hs!(rest, tuple!(char!(close), char!('+'), tag_no_case!("y")))?;
+            Ok((rest, spc700::Operand::IndirectIndexed(address)))
+        }
+        _ => Err(nom::Err::Error(error_position!(input, ErrorKind::Verify))),
+    }
+}
+
+named!(spc700_bit<Input, (Expression, Expression)>, hs!(do_parse!(
+    address: expression >>
+    char!('.') >>
+    bit: expression >>
+    (address, bit)
+)));
+
+/// Splits an index register from an address, as in `$1234+X`.
+fn spc700_index(address: Expression) -> (Expression, Option<spc700::Register>) {
+    if let Expression::Binary(BinaryOperator::Add, operands) = address {
+        let (left, right) = *operands;
+        if let Expression::Variable(Label::Named(VariableName(name))) = right.node {
+            match spc700::Register::from_name(name) {
+                Some(register @ spc700::Register::X) | Some(register @ spc700::Register::Y) => {
+                    return (left.node, Some(register));
+                }
+                _ => {}
+            }
+        }
+        return (
+            Expression::Binary(BinaryOperator::Add, Box::new((left, right))),
+            None,
+        );
+    }
+    (address, None)
+}
+
+fn spc700_address(address: Expression, absolute: bool) -> spc700::Operand<Expression> {
+    match spc700_index(address) {
+        (address, Some(index)) => spc700::Operand::Indexed {
+            address,
+            index,
+            absolute,
+        },
+        (address, None) => spc700::Operand::Address { address, absolute },
+    }
+}
+
+named!(superfx_instruction<Input, SuperFxInstruction>, hs!(do_parse!(
+    name: verify!(identifier, |name: &str| {
+        !BLOCK_KEYWORDS.iter().any(|keyword| name.eq_ignore_ascii_case(keyword))
+    }) >>
+    operands: alt!(
+        peek!(end_of_line) => { |_| Vec::new() }
+        | separated_nonempty_list!(char!(','), superfx_operand)
+    ) >>
+    (SuperFxInstruction { name, operands })
+)));
+
+/// Parses a Super FX operand, like `r1`, `#$12`, `(r2)` or `($1234)`.
+fn superfx_operand(input: Input) -> IResult<Input, superfx::Operand<Expression>> {
+    hs!(
+        input,
+        alt!(
+            preceded!(char!('#'), expression) => { superfx::Operand::Immediate }
+            | delimited!(char!('('), superfx_register, char!(')')) => {
+                superfx::Operand::RegisterIndirect
+            }
+            | superfx_memory => { superfx::Operand::Memory }
+            | superfx_register => { superfx::Operand::Register }
+            | expression => { superfx::Operand::Address }
+        )
+    )
+}
+
+/// Parses a register name, from `r0` to `r15`, which isn't followed by an
+/// operator, so that `r1+2` is an expression.
+fn superfx_register(input: Input) -> IResult<Input, u8> {
+    let (rest, name) = identifier(input)?;
+    let after = &rest[scan::whitespace(&rest)..];
+    let number = match name.as_bytes().first() {
+        Some(b'r') | Some(b'R')
+            if name.len() > 1 && name[1..].bytes().all(|c| c.is_ascii_digit()) =>
+        {
+            name[1..].parse::<u8>().ok().filter(|&number| number < 16)
+        }
+        _ => None,
+    };
+    match number {
+        Some(number) if !after.starts_with(|c| OPERATORS.contains(c)) => Ok((rest, number)),
+        _ => Err(nom::Err::Error(error_position!(input, ErrorKind::Tag))),
+    }
+}
+
+/// Parses `(addr)`, unless parentheses are a part of a larger expression,
+/// like in a branch to `(addr)*2`.
+fn superfx_memory(input: Input) -> IResult<Input, Expression> {
+    hs!(
+        input,
+        terminated!(
+            delimited!(char!('('), expression, char!(')')),
+            peek!(alt!(map!(char!(','), |_| ()) | end_of_line))
+        )
+    )
+}
+
 named!(
 /// Assignment statement parser.
 ///
 /// It expects variable name, followed by `=` character, and an expression
 /// which marks expression to be stored as value.
-///
-/// # Examples
-///
-/// ```
-/// use mvp::parser::grammar::{self, CompleteStr};
-/// use mvp::parser::ast::{Expression, Number, NumberWidth, Statement, VariableName};
-///
-/// let parsed = grammar::assignment(CompleteStr("hello = 44"));
-/// let expected = Statement::Assignment(
-///     VariableName("hello"),
-///     Expression::Number(Number { value: 44, width: NumberWidth::None }),
-/// );
-/// assert_eq!(parsed, Ok((CompleteStr(""), expected)));
-/// ```
 ,
-pub assignment<CompleteStr, Statement>, ws!(do_parse!(
+assignment<Input, Statement>, hs!(do_parse!(
     name: identifier >>
     char!('=') >>
     value: expression >>
-    (Statement::Assignment(VariableName(&name), value))
+    (Statement::Assignment(VariableName(name), value))
 )));
 
-named!(label<CompleteStr, Label>, alt!(
+named!(
+/// Assignment of text, as in `name := expression`.
+,
+string_assignment<Input, Statement>, hs!(do_parse!(
+    name: identifier >>
+    tag!(":=") >>
+    value: expression >>
+    (Statement::StringAssignment(VariableName(name), value))
+)));
+
+named!(label<Input, Label>, alt!(
     preceded!(char!('.'), identifier) => { |name| Label::Scoped(VariableName(name)) }
     | identifier => { |name| Label::Named(VariableName(name)) }
-    | take_while1!(|x| x == '-') => { |s: CompleteStr| Label::Relative(-(s.len() as i32)) }
-    | take_while1!(|x| x == '+') => { |s: CompleteStr| Label::Relative(s.len() as i32) }
+    | take_while1!(|x| x == '-') => { |s: Input| Label::Relative(-(s.len() as i32)) }
+    | take_while1!(|x| x == '+') => { |s: Input| Label::Relative(s.len() as i32) }
 ));
 
-named!(
-/// An expression parser.
-///
-/// This can be used as math expression parser, however due to language
-/// limitations, it doesn't support types like decimal numbers.
-/// However, it does support mathematical operators like addition,
-/// subtraction, multiplication and division, as well as parenthesis.
-///
-/// # Example
-///
-/// Parsing a mathematical expression:
+/// Parses an expression into boxed nodes, see `expression_with`.
+fn expression(input: Input) -> IResult<Input, Expression> {
+    expression_with(input, Boxed)
+}
+
+fn spanned_expression(input: Input) -> IResult<Input, Spanned<Expression>> {
+    spanned(input, expression)
+}
+
+/// An expression parser, see `parse_expression`.
 ///
-/// ```
-/// use mvp::parser::grammar::{self, CompleteStr};
-/// use mvp::parser::ast::{BinaryOperator, Expression, Number, NumberWidth};
+/// Operators and parentheses are handled with explicit stacks rather than
+/// recursion, so that deeply nested input can't overflow the stack. Apart
+/// from that, it behaves like a recursive descent parser, including
+/// backtracking: a call whose arguments can't be parsed is a variable
+/// followed by a parenthesis, and a minus which cannot be a negation is
+/// a relative label.
 ///
-/// let parsed = grammar::expression(CompleteStr("2 + 3"));
-/// let expected = Ok((CompleteStr(""), Expression::Binary(
-///     BinaryOperator::Add,
-///     Box::new((
-///         Expression::Number(Number { value: 2, width: NumberWidth::None }),
-///         Expression::Number(Number { value: 3, width: NumberWidth::None }),
-///     )),
-/// )));
-/// assert_eq!(parsed, expected);
-/// ```
-,
-pub expression<CompleteStr, Expression>, ws!(do_parse!(
-    init: term >>
-    res: fold_many0!(
-        pair!(alt!(
-            char!('+') => {|_| BinaryOperator::Add}
-            | char!('-') => {|_| BinaryOperator::Sub}
-        ), term),
-        init,
-        |first, (operator, another)| {
-            Expression::Binary(operator, Box::new((first, another)))
+/// Nodes are created by a `Builder`, so that the same parser can produce
+/// arena-allocated expressions.
+pub(crate) fn expression_with<'a, B: Builder<'a>>(
+    input: Input<'a>,
+    builder: B,
+) -> IResult<Input<'a>, B::Node> {
+    let mut parser = ExpressionParser {
+        builder,
+        levels: vec![Level::new(Group::Root, input, input)],
+        input,
+    };
+    let mut state = parser.operand();
+    loop {
+        state = match state? {
+            State::Operand => parser.operand(),
+            State::Operator => parser.operator(),
+            State::Done(expression) => return Ok((parser.input, expression)),
+        };
+    }
+}
+
+/// Creates nodes of a parsed expression, from operands along with their
+/// spans.
+pub(crate) trait Builder<'a> {
+    type Node;
+
+    /// Creates a node without operands, like a number or a variable.
+    fn leaf(&self, expression: Expression<'a>) -> Self::Node;
+    fn binary(
+        &self,
+        operator: BinaryOperator,
+        left: Spanned<'a, Self::Node>,
+        right: Spanned<'a, Self::Node>,
+    ) -> Self::Node;
+    fn negate(&self, operand: Spanned<'a, Self::Node>) -> Self::Node;
+    fn call(&self, name: VariableName<'a>, arguments: Vec<Spanned<'a, Self::Node>>) -> Self::Node;
+}
+
+/// Builds an `Expression`, with operands in boxes.
+struct Boxed;
+
+impl<'a> Builder<'a> for Boxed {
+    type Node = Expression<'a>;
+
+    fn leaf(&self, expression: Expression<'a>) -> Expression<'a> {
+        expression
+    }
+
+    fn binary(
+        &self,
+        operator: BinaryOperator,
+        left: Spanned<'a, Expression<'a>>,
+        right: Spanned<'a, Expression<'a>>,
+    ) -> Expression<'a> {
+        Expression::Binary(operator, Box::new((left, right)))
+    }
+
+    fn negate(&self, operand: Spanned<'a, Expression<'a>>) -> Expression<'a> {
+        Expression::Negate(Box::new(operand))
+    }
+
+    fn call(
+        &self,
+        name: VariableName<'a>,
+        arguments: Vec<Spanned<'a, Expression<'a>>>,
+    ) -> Expression<'a> {
+        Expression::Call(name, arguments)
+    }
+}
+
+/// A part of an expression with its own operators.
+enum Group<'a, N> {
+    /// The whole expression.
+    Root,
+    /// An expression in parentheses.
+    Paren,
+    /// Arguments of a call, along with the largest depth among them.
+    Call(&'a str, Vec<Spanned<'a, N>>, usize),
+    /// An operand of a negation.
+    Negate,
+}
+
+struct Level<'a, N> {
+    group: Group<'a, N>,
+    /// Where the text of a group begins, including an opening parenthesis,
+    /// a function name or a minus.
+    begin: Input<'a>,
+    /// Where to continue when a group cannot be parsed.
+    start: Input<'a>,
+    operands: Vec<Operand<'a, N>>,
+    operators: Vec<BinaryOperator>,
+    /// Position of the last operator, while its right operand is missing.
+    pending_operator: Option<Input<'a>>,
+}
+
+impl<'a, N> Level<'a, N> {
+    fn new(group: Group<'a, N>, begin: Input<'a>, start: Input<'a>) -> Level<'a, N> {
+        Level {
+            group,
+            begin,
+            start,
+            operands: Vec::new(),
+            operators: Vec::new(),
+            pending_operator: None,
         }
-    ) >>
-    (res)
-)));
+    }
+}
 
-named!(term<CompleteStr, Expression>, do_parse!(
-    init: top_expression >>
-    res: fold_many0!(
-        pair!(alt!(
-            char!('*') => {|_| BinaryOperator::Mul}
-            | char!('/') => {|_| BinaryOperator::Div}
-        ), top_expression),
-        init,
-        |first, (operator, another)| {
-            Expression::Binary(operator, Box::new((first, another)))
+/// A parsed operand, along with its depth and position.
+struct Operand<'a, N> {
+    node: N,
+    depth: usize,
+    begin: Input<'a>,
+    end: Input<'a>,
+}
+
+impl<'a, N> Operand<'a, N> {
+    fn spanned(self) -> Spanned<'a, N> {
+        let length = self.end.offset() - self.begin.offset();
+        Spanned {
+            node: self.node,
+            span: Span::new(self.begin.fragment()[..length].trim()),
         }
-    ) >>
-    (res)
+    }
+}
+
+enum State<N> {
+    Operand,
+    Operator,
+    Done(N),
+}
+
+type Step<'a, N> = Result<State<N>, nom::Err<Input<'a>>>;
+
+struct ExpressionParser<'a, B: Builder<'a>> {
+    builder: B,
+    /// Groups being parsed, with the innermost one last.
+    levels: Vec<Level<'a, B::Node>>,
+    input: Input<'a>,
+}
+
+impl<'a, B: Builder<'a>> ExpressionParser<'a, B> {
+    fn level(&mut self) -> &mut Level<'a, B::Node> {
+        self.levels.last_mut().unwrap()
+    }
+
+    fn too_deep(&self, input: Input<'a>) -> nom::Err<Input<'a>> {
+        nom::Err::Failure(error_position!(input, ErrorKind::TooLarge))
+    }
+
+    /// Parses an operand, trying alternatives in the same order as a
+    /// recursive descent parser would.
+    fn operand(&mut self) -> Step<'a, B::Node> {
+        let (input, _) = space(self.input)?;
+        if input.starts_with('(') {
+            return self.open(Group::Paren, input, input, input.slice(1..));
+        }
+        if let Ok((rest, expression)) = alt!(
+            input,
+            c_style_number | number | hex_number | program_counter | argument_count
+        ) {
+            let node = self.builder.leaf(expression);
+            return self.push_operand(input, rest, node, 1);
+        }
+        if let Ok((rest, (name, paren))) = call_start(input) {
+            return self.open(Group::Call(name, Vec::new(), 0), input, paren, rest);
+        }
+        if let Ok((rest, _)) = negation_start(input) {
+            return self.open(Group::Negate, input, rest, rest);
+        }
+        if let Ok((rest, expression)) = alt!(input,
+            variable
+            | string => { Expression::String }
+            | char!('$') => { |_| Expression::ProgramCounter }
+        ) {
+            let node = self.builder.leaf(expression);
+            return self.push_operand(input, rest, node, 1);
+        }
+        self.operand_failed(input)
+    }
+
+    /// Parses an operator after an operand, or ends a group.
+    fn operator(&mut self) -> Step<'a, B::Node> {
+        let (input, _) = space(self.input)?;
+        match alt!(input, comparison_operator | sum_operator | term_operator) {
+            Ok((rest, operator)) => {
+                let precedence = precedence(input.options().precedence, operator);
+                self.reduce(input, precedence)?;
+                let level = self.level();
+                level.operators.push(operator);
+                level.pending_operator = Some(input);
+                self.input = rest;
+                Ok(State::Operand)
+            }
+            Err(_) => self.close(input),
+        }
+    }
+
+    fn open(
+        &mut self,
+        group: Group<'a, B::Node>,
+        begin: Input<'a>,
+        start: Input<'a>,
+        rest: Input<'a>,
+    ) -> Step<'a, B::Node> {
+        if self.levels.len() > rest.options().max_depth {
+            return Err(self.too_deep(start));
+        }
+        self.levels.push(Level::new(group, begin, start));
+        self.input = rest;
+        if let Group::Call(..) = self.level().group {
+            let (input, _) = space(rest)?;
+            if input.starts_with(')') {
+                let level = self.levels.pop().unwrap();
+                if let Group::Call(name, arguments, _) = level.group {
+                    let node = self.builder.call(VariableName(name), arguments);
+                    return self.push_operand(level.begin, input.slice(1..), node, 1);
+                }
+            }
+        }
+        Ok(State::Operand)
+    }
+
+    /// Adds a complete operand spanning from `begin` to `rest` to the
+    /// innermost group, applying pending negations.
+    fn push_operand(
+        &mut self,
+        begin: Input<'a>,
+        rest: Input<'a>,
+        node: B::Node,
+        depth: usize,
+    ) -> Step<'a, B::Node> {
+        self.input = rest;
+        let mut operand = Operand {
+            node,
+            depth,
+            begin,
+            end: rest,
+        };
+        while let Group::Negate = self.level().group {
+            let level = self.levels.pop().unwrap();
+            if operand.depth >= rest.options().max_depth {
+                return Err(self.too_deep(level.start));
+            }
+            operand = Operand {
+                depth: operand.depth + 1,
+                begin: level.begin,
+                end: rest,
+                node: self.builder.negate(operand.spanned()),
+            };
+        }
+        let level = self.level();
+        level.operands.push(operand);
+        level.pending_operator = None;
+        Ok(State::Operator)
+    }
+
+    /// Combines operands of operators with at least given precedence.
+    fn reduce(&mut self, input: Input<'a>, min_precedence: u8) -> Result<(), nom::Err<Input<'a>>> {
+        let max_depth = input.options().max_depth;
+        let mode = input.options().precedence;
+        loop {
+            let level = self.level();
+            match level.operators.last() {
+                Some(&operator) if precedence(mode, operator) >= min_precedence => {}
+                _ => return Ok(()),
+            }
+            let operator = level.operators.pop().unwrap();
+            let right = level.operands.pop().unwrap();
+            let left = level.operands.pop().unwrap();
+            let depth = cmp::max(left.depth, right.depth) + 1;
+            if depth > max_depth {
+                return Err(self.too_deep(input));
+            }
+            let (begin, end) = (left.begin, right.end);
+            let node = self
+                .builder
+                .binary(operator, left.spanned(), right.spanned());
+            self.level().operands.push(Operand {
+                node,
+                depth,
+                begin,
+                end,
+            });
+        }
+    }
+
+    /// Completes the innermost group, as there is no operator after its
+    /// last operand.
+    fn close(&mut self, input: Input<'a>) -> Step<'a, B::Node> {
+        self.input = input;
+        let is_call = match self.level().group {
+            Group::Root => {
+                self.reduce(input, 0)?;
+                let operand = self.level().operands.pop().unwrap();
+                return Ok(State::Done(operand.node));
+            }
+            Group::Paren if input.starts_with(')') => false,
+            Group::Call(..) if input.starts_with(')') || input.starts_with(',') => true,
+            _ => return self.group_failed(),
+        };
+        self.reduce(input, 0)?;
+        let operand = self.level().operands.pop().unwrap();
+        let rest = input.slice(1..);
+        if !is_call {
+            let level = self.levels.pop().unwrap();
+            return self.push_operand(level.begin, rest, operand.node, operand.depth);
+        }
+        let depth = operand.depth;
+        if input.starts_with(',') {
+            if let Group::Call(_, ref mut arguments, ref mut max_depth) = self.level().group {
+                arguments.push(operand.spanned());
+                *max_depth = cmp::max(*max_depth, depth);
+            }
+            self.input = rest;
+            return Ok(State::Operand);
+        }
+        let level = self.levels.pop().unwrap();
+        match level.group {
+            Group::Call(name, mut arguments, max_depth) => {
+                arguments.push(operand.spanned());
+                let depth = cmp::max(max_depth, depth) + 1;
+                if depth > input.options().max_depth {
+                    return Err(self.too_deep(input));
+                }
+                let node = self.builder.call(VariableName(name), arguments);
+                self.push_operand(level.begin, rest, node, depth)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Handles a missing operand, recovering after an operator.
+    fn operand_failed(&mut self, input: Input<'a>) -> Step<'a, B::Node> {
+        let operator = match self.level().pending_operator {
+            Some(operator) => operator,
+            None => return self.group_failed(),
+        };
+        match invalid_operand(input) {
+            Ok((rest, error)) => {
+                let node = self.builder.leaf(error);
+                self.push_operand(input, rest, node, 1)
+            }
+            Err(_) => {
+                // The operator turns out not to be a part of an expression.
+                let level = self.level();
+                level.operators.pop();
+                level.pending_operator = None;
+                self.close(operator)
+            }
+        }
+    }
+
+    /// Backtracks from a group which cannot be parsed, trying alternatives
+    /// of its first token.
+    fn group_failed(&mut self) -> Step<'a, B::Node> {
+        let level = self.levels.pop().unwrap();
+        let label = match level.group {
+            Group::Root => {
+                return Err(nom::Err::Error(error_position!(
+                    level.start,
+                    ErrorKind::Alt
+                )))
+            }
+            Group::Paren => return self.operand_failed(level.start),
+            Group::Call(name, ..) => Label::Named(VariableName(name)),
+            Group::Negate => Label::Relative(-1),
+        };
+        let node = self.builder.leaf(Expression::Variable(label));
+        self.push_operand(level.begin, level.start, node, 1)
+    }
+}
+
+/// Precedence of a binary operator, higher binds tighter.
+fn precedence(mode: Precedence, operator: BinaryOperator) -> u8 {
+    if mode == Precedence::LeftToRight {
+        return 0;
+    }
+    operator.precedence()
+}
+
+named!(comparison_operator<Input, BinaryOperator>, alt!(
+    tag!("==") => {|_| BinaryOperator::Eq}
+    | tag!("!=") => {|_| BinaryOperator::Ne}
+    | tag!("<=") => {|_| BinaryOperator::Le}
+    | tag!(">=") => {|_| BinaryOperator::Ge}
+    | char!('<') => {|_| BinaryOperator::Lt}
+    | char!('>') => {|_| BinaryOperator::Gt}
 ));
 
-named!(top_expression<CompleteStr, Expression>, ws!(alt!(
-    paren_expression
-    | number
-    | hex_number
-    | call
-    | variable
-)));
+named!(sum_operator<Input, BinaryOperator>, alt!(
+    char!('+') => {|_| BinaryOperator::Add}
+    | char!('-') => {|_| BinaryOperator::Sub}
+));
+
+named!(term_operator<Input, BinaryOperator>, alt!(
+    char!('*') => {|_| BinaryOperator::Mul}
+    | char!('/') => {|_| BinaryOperator::Div}
+));
+
+/// Characters which can't be a part of an invalid operand.
+const OPERAND_TERMINATORS: &str = "),;\r\n";
+
+/// Characters which end an invalid operand, unless it's the first one.
+const OPERAND_BOUNDARIES: &str = "+-*/<>=(),;\r\n";
 
-named!(paren_expression<CompleteStr, Expression>, ws!(delimited!(char!('('), expression, char!(')'))));
+/// Recovers from an invalid operand after an operator, by skipping input
+/// until the nearest operator or parenthesis.
+///
+/// Recovery is disabled while looking for a `SyntaxError`, so that errors
+/// point at invalid operands rather than past them.
+fn invalid_operand(input: Input) -> IResult<Input, Expression> {
+    if input.is_tracking() {
+        return Err(nom::Err::Error(error_position!(input, ErrorKind::Verify)));
+    }
+    let (input, _) = space(input)?;
+    let text = input.fragment();
+    let end = match text.chars().next() {
+        Some(c) if !OPERAND_TERMINATORS.contains(c) => text
+            .char_indices()
+            .skip(1)
+            .find(|&(_, c)| OPERAND_BOUNDARIES.contains(c))
+            .map_or(text.len(), |(i, _)| i),
+        _ => 0,
+    };
+    let span = Span::new(text[..end].trim_end());
+    Ok((input.slice(end..), Expression::Error(span)))
+}
 
-named!(number<CompleteStr, Expression>, map!(
+named!(number<Input, Expression>, map!(
     map_res!(
-        ws!(nom::digit),
-        |x: CompleteStr| u32::from_str(&x)
+        hs!(nom::digit),
+        |x: Input| u32::from_str(&x)
     ),
     |value| Expression::Number(Number { value, width: NumberWidth::None })
 ));
@@ -256,26 +2189,78 @@ fn hex_width_for_length(length: usize) -> NumberWidth {
     match length {
         2 => NumberWidth::OneByte,
         4 => NumberWidth::TwoBytes,
+        6 => NumberWidth::ThreeBytes,
         _ => NumberWidth::None,
     }
 }
 
-named!(pub hex_number<CompleteStr, Expression>, ws!(do_parse!(
+/// A binary literal has the width of a hexadecimal literal with the same
+/// number of bits.
+fn binary_width_for_length(length: usize) -> NumberWidth {
+    if length.is_multiple_of(4) {
+        hex_width_for_length(length / 4)
+    } else {
+        NumberWidth::None
+    }
+}
+
+named!(hex_number<Input, Expression>, hs!(do_parse!(
     char!('$') >>
-    number: map_res!(nom::hex_digit, |s: CompleteStr| u32::from_str_radix(&s, 16).map(|value| Number {
+    number: map_res!(nom::hex_digit, |s: Input| u32::from_str_radix(&s, 16).map(|value| Number {
         value, width: hex_width_for_length(s.len()),
     })) >>
     (Expression::Number(number))
 )));
 
-named!(call<CompleteStr, Expression>, ws!(do_parse!(
-    identifier: identifier >>
-    parts: delimited!(
-        char!('('),
-        separated_list!(char!(','), expression),
-        char!(')')
-    ) >>
-    (Expression::Call(VariableName(&identifier), parts))
+/// Parses a `0x` or `0b` prefixed literal, when enabled by options.
+fn c_style_number(input: Input) -> IResult<Input, Expression> {
+    if input.options().c_style_literals {
+        prefixed_number(input)
+    } else {
+        Err(nom::Err::Error(error_position!(input, ErrorKind::Tag)))
+    }
+}
+
+named!(prefixed_number<Input, Expression>, map!(alt!(
+    preceded!(tag_no_case!("0x"), map_res!(nom::hex_digit, |s: Input| {
+        u32::from_str_radix(&s, 16).map(|value| Number {
+            value, width: hex_width_for_length(s.len()),
+        })
+    }))
+    | preceded!(tag_no_case!("0b"), map_res!(take_while1!(|c| c == '0' || c == '1'), |s: Input| {
+        u32::from_str_radix(&s, 2).map(|value| Number {
+            value, width: binary_width_for_length(s.len()),
+        })
+    }))
+), Expression::Number));
+
+// A minus directly followed by an operand is a negation, otherwise it's
+// a relative label, like in `- - base`.
+named!(negation_start<Input, char>, terminated!(char!('-'), not!(one_of!(" \t-+"))));
+
+named!(program_counter<Input, Expression>, hs!(do_parse!(
+    tag_no_case!("pc") >>
+    not!(identifier) >>
+    char!('(') >>
+    char!(')') >>
+    (Expression::ProgramCounter)
 )));
 
-named!(variable<CompleteStr, Expression>, map!(label, Expression::Variable));
+named!(argument_count<Input, Expression>, hs!(do_parse!(
+    call!(keyword, "sizeof") >>
+    char!('(') >>
+    tag!("...") >>
+    char!(')') >>
+    (Expression::ArgumentCount)
+)));
+
+/// Parses a function name with an opening parenthesis, returning the name
+/// and the position of the parenthesis.
+fn call_start<'a>(input: Input<'a>) -> IResult<Input<'a>, (&'a str, Input<'a>)> {
+    let (rest, name) = identifier(input)?;
+    let (paren, _) = space(rest)?;
+    let (rest, _) = char!(paren, '(')?;
+    Ok((rest, (name, paren)))
+}
+
+named!(variable<Input, Expression>, map!(label, Expression::Variable));
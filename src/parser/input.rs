@@ -0,0 +1,310 @@
+//! Parser input with position tracking.
+//!
+//! Parsers in `grammar` consume an `Input`, which is a fragment of source
+//! code that remembers where it starts. Every remainder returned by a parser
+//! knows its byte offset, line and column, so errors and spans can be
+//! reported without searching the source.
+
+use std::cell::Cell;
+use std::cmp;
+use std::fmt::{self, Display, Formatter};
+use std::ops::{Deref, Range, RangeFrom, RangeFull, RangeTo};
+use std::str::{CharIndices, Chars, FromStr};
+
+use nom::{
+    AsBytes, AtEof, Compare, CompareResult, FindSubstring, FindToken, InputIter, InputLength,
+    InputTake, Offset, ParseTo, Slice, UnspecializedInput,
+};
+
+use parser::grammar::ParserOptions;
+use parser::scan;
+
+thread_local! {
+    /// Furthest offset reached by parsers of a tracked input.
+    static PROGRESS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A fragment of source code along with its position.
+///
+/// An input also carries parser options, so that parsers can check which
+/// syntax is accepted.
+///
+/// Inputs are compared by their text only, which allows comparing parser
+/// results with inputs created by `Input::new`.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::grammar;
+///
+/// let (_, rest) = grammar::parse_expression("1 + 2\n  foo").unwrap();
+/// assert_eq!(rest.fragment(), "\n  foo");
+/// assert_eq!((rest.offset(), rest.line(), rest.column()), (5, 1, 6));
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct Input<'a> {
+    fragment: &'a str,
+    offset: usize,
+    line: u32,
+    column: u32,
+    options: ParserOptions,
+    /// Whether parsers record the furthest offset they reach in `PROGRESS`.
+    tracking: bool,
+}
+
+impl<'a> Input<'a> {
+    /// Creates an input at the beginning of a source, using default
+    /// options.
+    pub fn new(source: &'a str) -> Input<'a> {
+        Input::with_options(source, ParserOptions::default())
+    }
+
+    /// Creates an input at the beginning of a source, with given options.
+    pub fn with_options(source: &'a str, options: ParserOptions) -> Input<'a> {
+        Input {
+            fragment: source,
+            offset: 0,
+            line: 1,
+            column: 1,
+            options,
+            tracking: false,
+        }
+    }
+
+    /// Text left to parse.
+    pub fn fragment(&self) -> &'a str {
+        self.fragment
+    }
+
+    /// Byte offset of the input within the source.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Line of the input, counted from one.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// Column of the input, counted from one.
+    ///
+    /// Columns count characters, not bytes.
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+
+    /// Options used to parse the input.
+    pub fn options(&self) -> ParserOptions {
+        self.options
+    }
+
+    /// The same input, parsed with other options from now on.
+    pub(crate) fn with_parser_options(&self, options: ParserOptions) -> Input<'a> {
+        Input { options, ..*self }
+    }
+
+    /// Runs a parser, returning the furthest offset it reached.
+    pub(crate) fn track<F: FnOnce(Input<'a>)>(self, parser: F) -> usize {
+        PROGRESS.with(|progress| progress.set(self.offset));
+        parser(Input {
+            tracking: true,
+            ..self
+        });
+        PROGRESS.with(Cell::get)
+    }
+
+    /// Whether progress of parsers is recorded.
+    pub(crate) fn is_tracking(&self) -> bool {
+        self.tracking
+    }
+
+    /// Skips `count` bytes without counting them as progress.
+    pub(crate) fn skip(&self, count: usize) -> Input<'a> {
+        let untracked = Input {
+            tracking: false,
+            ..*self
+        };
+        Input {
+            tracking: self.tracking,
+            ..untracked.advance(count)
+        }
+    }
+
+    /// Skips `count` bytes, updating the position.
+    fn advance(&self, count: usize) -> Input<'a> {
+        let skipped = &self.fragment[..count];
+        let (line, column) = match scan::line_breaks(skipped) {
+            (breaks, Some(line_start)) => (
+                self.line + breaks as u32,
+                skipped[line_start..].chars().count() as u32 + 1,
+            ),
+            (_, None) => (self.line, self.column + skipped.chars().count() as u32),
+        };
+        if self.tracking && count > 0 {
+            PROGRESS.with(|progress| progress.set(cmp::max(progress.get(), self.offset + count)));
+        }
+        Input {
+            fragment: &self.fragment[count..],
+            offset: self.offset + count,
+            line,
+            column,
+            ..*self
+        }
+    }
+
+    /// First `count` bytes, at the same position.
+    fn truncate(&self, count: usize) -> Input<'a> {
+        Input {
+            fragment: &self.fragment[..count],
+            ..*self
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Input<'a> {
+    fn from(source: &'a str) -> Input<'a> {
+        Input::new(source)
+    }
+}
+
+impl<'a, 'b> PartialEq<Input<'b>> for Input<'a> {
+    fn eq(&self, other: &Input<'b>) -> bool {
+        self.fragment == other.fragment
+    }
+}
+
+impl<'a> Eq for Input<'a> {}
+
+impl<'a> Display for Input<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.fragment)
+    }
+}
+
+impl<'a> Deref for Input<'a> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.fragment
+    }
+}
+
+impl<'a> AsRef<str> for Input<'a> {
+    fn as_ref(&self) -> &str {
+        self.fragment
+    }
+}
+
+impl<'a> AtEof for Input<'a> {
+    fn at_eof(&self) -> bool {
+        true
+    }
+}
+
+impl<'a> Slice<Range<usize>> for Input<'a> {
+    fn slice(&self, range: Range<usize>) -> Self {
+        self.advance(range.start).truncate(range.end - range.start)
+    }
+}
+
+impl<'a> Slice<RangeTo<usize>> for Input<'a> {
+    fn slice(&self, range: RangeTo<usize>) -> Self {
+        self.truncate(range.end)
+    }
+}
+
+impl<'a> Slice<RangeFrom<usize>> for Input<'a> {
+    fn slice(&self, range: RangeFrom<usize>) -> Self {
+        self.advance(range.start)
+    }
+}
+
+impl<'a> Slice<RangeFull> for Input<'a> {
+    fn slice(&self, _: RangeFull) -> Self {
+        *self
+    }
+}
+
+impl<'a> InputIter for Input<'a> {
+    type Item = char;
+    type RawItem = char;
+    type Iter = CharIndices<'a>;
+    type IterElem = Chars<'a>;
+
+    fn iter_indices(&self) -> CharIndices<'a> {
+        self.fragment.char_indices()
+    }
+
+    fn iter_elements(&self) -> Chars<'a> {
+        self.fragment.chars()
+    }
+
+    fn position<P>(&self, predicate: P) -> Option<usize>
+    where
+        P: Fn(char) -> bool,
+    {
+        self.fragment.position(predicate)
+    }
+
+    fn slice_index(&self, count: usize) -> Option<usize> {
+        self.fragment.slice_index(count)
+    }
+}
+
+impl<'a> InputTake for Input<'a> {
+    fn take(&self, count: usize) -> Self {
+        self.truncate(count)
+    }
+
+    fn take_split(&self, count: usize) -> (Self, Self) {
+        (self.advance(count), self.truncate(count))
+    }
+}
+
+impl<'a> UnspecializedInput for Input<'a> {}
+
+impl<'a> InputLength for Input<'a> {
+    fn input_len(&self) -> usize {
+        self.fragment.len()
+    }
+}
+
+impl<'a, 'b> Compare<&'b str> for Input<'a> {
+    fn compare(&self, t: &'b str) -> CompareResult {
+        self.fragment.compare(t)
+    }
+
+    fn compare_no_case(&self, t: &'b str) -> CompareResult {
+        self.fragment.compare_no_case(t)
+    }
+}
+
+impl<'a, 'b> FindSubstring<&'b str> for Input<'a> {
+    fn find_substring(&self, substr: &'b str) -> Option<usize> {
+        self.fragment.find_substring(substr)
+    }
+}
+
+impl<'a> FindToken<char> for Input<'a> {
+    fn find_token(&self, token: char) -> bool {
+        self.fragment.find_token(token)
+    }
+}
+
+impl<'a, R: FromStr> ParseTo<R> for Input<'a> {
+    fn parse_to(&self) -> Option<R> {
+        self.fragment.parse().ok()
+    }
+}
+
+impl<'a> Offset for Input<'a> {
+    fn offset(&self, second: &Input<'a>) -> usize {
+        second.offset - self.offset
+    }
+}
+
+impl<'a> AsBytes for Input<'a> {
+    fn as_bytes(&self) -> &[u8] {
+        self.fragment.as_bytes()
+    }
+}
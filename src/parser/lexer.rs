@@ -0,0 +1,166 @@
+//! Tokenizer for syntax highlighting and other tools.
+//!
+//! The lexer splits source code into tokens without parsing it, so it
+//! works on incomplete or invalid code too. Whitespace other than line
+//! endings isn't returned.
+
+use parser::ast::Span;
+use parser::grammar::{valid_identifier_first_character, valid_later_character};
+
+/// A category of a token.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum TokenKind {
+    /// First word of a statement, like an opcode or a directive.
+    Mnemonic,
+    /// Any other word, like a label or a function name.
+    Identifier,
+    /// Decimal, hexadecimal (`$1F`) or binary (`%101`) number.
+    Number,
+    /// String literal, including quotes.
+    String,
+    /// Arithmetic or comparison operator, like `+` or `<=`, or `:=` and
+    /// `->` of directives.
+    Operator,
+    /// Parentheses, brackets, `,`, `#`, `:`, `.` and bare `$`.
+    Punctuation,
+    /// Comment, starting with `;`.
+    Comment,
+    /// Line ending.
+    Newline,
+    /// A character that cannot start a token, or an unterminated string.
+    Error,
+}
+
+/// A token along with its position in source code.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub span: Span<'a>,
+}
+
+/// An iterator over tokens of source code.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::lexer::{Lexer, TokenKind};
+///
+/// let kinds: Vec<_> = Lexer::new("LDA #$10 ; load").map(|token| token.kind).collect();
+/// assert_eq!(
+///     kinds,
+///     [
+///         TokenKind::Mnemonic,
+///         TokenKind::Punctuation,
+///         TokenKind::Number,
+///         TokenKind::Comment,
+///     ],
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct Lexer<'a> {
+    source: &'a str,
+    position: usize,
+    line_start: bool,
+}
+
+impl<'a> Lexer<'a> {
+    /// Creates a lexer for source code.
+    pub fn new(source: &'a str) -> Lexer<'a> {
+        Lexer {
+            source,
+            position: 0,
+            line_start: true,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    /// Length of a token at the start of `rest`.
+    fn token(&self, rest: &str) -> (TokenKind, usize) {
+        let mut chars = rest.chars();
+        let first = chars.next().unwrap();
+        let second = chars.next();
+        match first {
+            '\n' => (TokenKind::Newline, 1),
+            '\r' if second == Some('\n') => (TokenKind::Newline, 2),
+            ';' => (
+                TokenKind::Comment,
+                rest.find(['\r', '\n']).unwrap_or(rest.len()),
+            ),
+            '"' => match rest[1..].find(['"', '\n']) {
+                Some(end) if rest[1 + end..].starts_with('"') => (TokenKind::String, end + 2),
+                _ => (TokenKind::Error, rest.find('\n').unwrap_or(rest.len())),
+            },
+            '0'..='9' => (
+                TokenKind::Number,
+                prefix_length(rest, |c| c.is_ascii_digit()),
+            ),
+            '$' => match prefix_length(&rest[1..], |c| c.is_ascii_hexdigit()) {
+                0 => (TokenKind::Punctuation, 1),
+                length => (TokenKind::Number, length + 1),
+            },
+            '%' => match prefix_length(&rest[1..], |c| c == '0' || c == '1') {
+                0 => (TokenKind::Operator, 1),
+                length => (TokenKind::Number, length + 1),
+            },
+            '<' | '>' if second == Some(first) => (TokenKind::Operator, 2),
+            '<' | '>' | '=' | '!' | ':' if second == Some('=') => (TokenKind::Operator, 2),
+            '-' if second == Some('>') => (TokenKind::Operator, 2),
+            '+' | '-' | '*' | '/' | '^' | '&' | '|' | '<' | '>' | '=' | '~' => {
+                (TokenKind::Operator, 1)
+            }
+            '(' | ')' | '[' | ']' | ',' | '#' | ':' | '.' => (TokenKind::Punctuation, 1),
+            c if valid_identifier_first_character(c) => {
+                let length =
+                    c.len_utf8() + prefix_length(&rest[c.len_utf8()..], valid_later_character);
+                let kind = if self.line_start && !is_label(&rest[length..]) {
+                    TokenKind::Mnemonic
+                } else {
+                    TokenKind::Identifier
+                };
+                (kind, length)
+            }
+            c => (TokenKind::Error, c.len_utf8()),
+        }
+    }
+}
+
+fn prefix_length<F: Fn(char) -> bool>(text: &str, predicate: F) -> usize {
+    text.find(|c| !predicate(c)).unwrap_or(text.len())
+}
+
+/// Checks whether a word is followed by `:` or `=`, making it a label
+/// declaration or an assignment rather than a mnemonic.
+fn is_label(rest: &str) -> bool {
+    let rest = rest.trim_start_matches([' ', '\t']);
+    rest.starts_with(':') || (rest.starts_with('=') && !rest.starts_with("=="))
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let rest = self.rest();
+        let rest = rest.trim_start_matches([' ', '\t']);
+        if rest.is_empty() {
+            self.position = self.source.len();
+            return None;
+        }
+        let start = self.source.len() - rest.len();
+        let (kind, length) = self.token(rest);
+        self.position = start + length;
+        // Label declarations can be followed by a statement.
+        self.line_start = match kind {
+            TokenKind::Newline => true,
+            TokenKind::Identifier | TokenKind::Comment => self.line_start,
+            TokenKind::Punctuation => self.line_start && matches!(&rest[..length], ":" | "."),
+            _ => false,
+        };
+        Some(Token {
+            kind,
+            span: Span::new(&rest[..length]),
+        })
+    }
+}
@@ -1,2 +1,13 @@
+#[cfg(feature = "arbitrary")]
+pub mod arbitrary;
+#[cfg(feature = "arena")]
+pub mod arena;
 pub mod ast;
+pub mod fold;
 pub mod grammar;
+pub mod input;
+pub mod lexer;
+pub mod owned;
+mod scan;
+pub mod semantic;
+pub mod visit;
@@ -0,0 +1,57 @@
+//! Parsed programs which own their source.
+//!
+//! Syntax trees borrow names and strings from the source they were parsed
+//! from, so they can't outlive it. An `OwnedProgram` keeps the source, so
+//! that a program can be stored, for instance between edits in an editor,
+//! and moved around freely. Statements are parsed from the source when
+//! they are accessed, with lifetimes bound to the program.
+
+use parser::ast::{Spanned, Statement};
+use parser::grammar::{self, Input, ParseError};
+
+/// Source of a program, which can be parsed while it's borrowed.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::owned::OwnedProgram;
+///
+/// let program = {
+///     let source = format!("warnpc ${:X}\nLDA (", 0x8000);
+///     OwnedProgram::new(source)
+/// };
+/// assert_eq!(program.statements()[0].span.fragment(), "warnpc $8000");
+/// assert_eq!(program.errors()[0].span.line_column(program.source()), Some((2, 1)));
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OwnedProgram {
+    source: String,
+}
+
+impl OwnedProgram {
+    /// Takes ownership of source of a program.
+    pub fn new(source: String) -> OwnedProgram {
+        OwnedProgram { source }
+    }
+
+    /// Source of a program.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Parses a program with `grammar::program_with_recovery`, returning
+    /// statements and errors found while parsing.
+    pub fn parse(&self) -> (Vec<Spanned<'_, Statement<'_>>>, Vec<ParseError<'_>>) {
+        grammar::program_with_recovery(Input::new(&self.source))
+    }
+
+    /// Parsed statements, with invalid code replaced by `Statement::Error`.
+    pub fn statements(&self) -> Vec<Spanned<'_, Statement<'_>>> {
+        self.parse().0
+    }
+
+    /// Errors found while parsing.
+    pub fn errors(&self) -> Vec<ParseError<'_>> {
+        self.parse().1
+    }
+}
@@ -0,0 +1,53 @@
+//! Scanning of common tokens.
+//!
+//! Whitespace, identifiers and comments make up most of a source file, and
+//! scanning them a character at a time with nom combinators dominated parse
+//! time. These functions work on bytes instead, with `memchr` for line
+//! endings, and only decode characters outside of ASCII.
+
+use memchr;
+
+use parser::grammar::valid_later_character;
+
+/// Length in bytes of spaces and tabs at the start of a text.
+pub(crate) fn whitespace(text: &str) -> usize {
+    text.bytes()
+        .position(|byte| byte != b' ' && byte != b'\t')
+        .unwrap_or(text.len())
+}
+
+/// Length in bytes of an identifier continuing after its first character
+/// at `start`.
+pub(crate) fn identifier_end(text: &str, start: usize) -> usize {
+    let bytes = text.as_bytes();
+    let mut end = start;
+    while end < bytes.len() {
+        let byte = bytes[end];
+        if byte.is_ascii_alphanumeric() || byte == b'_' {
+            end += 1;
+        } else if byte.is_ascii() {
+            return end;
+        } else {
+            return text[end..]
+                .char_indices()
+                .find(|&(_, c)| !valid_later_character(c))
+                .map_or(text.len(), |(position, _)| end + position);
+        }
+    }
+    end
+}
+
+/// Length in bytes of a line, without its line ending.
+pub(crate) fn line(text: &str) -> usize {
+    memchr::memchr(b'\n', text.as_bytes()).unwrap_or(text.len())
+}
+
+/// Number of lines after the first one in a text, and the start of the
+/// last line.
+pub(crate) fn line_breaks(text: &str) -> (usize, Option<usize>) {
+    let bytes = text.as_bytes();
+    match memchr::memrchr(b'\n', bytes) {
+        Some(last) => (memchr::memchr_iter(b'\n', bytes).count(), Some(last + 1)),
+        None => (0, None),
+    }
+}
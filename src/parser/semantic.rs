@@ -0,0 +1,284 @@
+//! Semantic classification of tokens for syntax highlighting.
+//!
+//! The lexer only knows what a word looks like, so a label reference and
+//! a define look the same to it. `semantic_tokens` combines tokens with
+//! a parsed program to tell which name is which. Names defined in other
+//! files can't be known, so `!name` is always a define and `%name` is
+//! always a macro, while other unknown names are plain identifiers.
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use parser::ast::{normalize_mnemonic, Label, Span, Spanned, Statement, VariableName};
+use parser::grammar::{self, Input};
+use parser::lexer::{self, Lexer, Token};
+use parser::visit::{self, Visitor};
+
+/// A meaning of a token.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum TokenKind {
+    /// Instruction mnemonic, with its width suffix, as in `LDA.b`.
+    Mnemonic,
+    /// Any other first word of a statement, like `incsrc` or `if`.
+    Directive,
+    /// Declaration of or reference to a label, including scoped labels
+    /// like `.loop`.
+    Label,
+    /// Name assigned to, as in `!lives = 3`.
+    Define,
+    /// Name of a macro, or a call like `%move`.
+    Macro,
+    /// Name of a function, either user-defined or built-in.
+    Function,
+    /// Parameter of a macro or a function, within its body.
+    Parameter,
+    Number,
+    /// String literal, including quotes.
+    String,
+    Operator,
+    Comment,
+    /// Any other word, like an index register or a name defined elsewhere.
+    Identifier,
+}
+
+impl TokenKind {
+    /// Every kind, in declaration order.
+    pub const ALL: &'static [TokenKind] = &[
+        TokenKind::Mnemonic,
+        TokenKind::Directive,
+        TokenKind::Label,
+        TokenKind::Define,
+        TokenKind::Macro,
+        TokenKind::Function,
+        TokenKind::Parameter,
+        TokenKind::Number,
+        TokenKind::String,
+        TokenKind::Operator,
+        TokenKind::Comment,
+        TokenKind::Identifier,
+    ];
+}
+
+/// Classifies tokens of source code, in order. Punctuation and line
+/// endings aren't returned.
+///
+/// Source code doesn't need to be valid, though names in invalid
+/// statements are only recognized if they're defined elsewhere.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::parser::semantic::{self, TokenKind};
+///
+/// let source = "!speed = 2\nMain:\n  LDA.b #!speed ; load\n  BRA Main\n";
+/// let tokens: Vec<_> = semantic::semantic_tokens(source)
+///     .iter()
+///     .map(|&(span, kind)| (span.fragment(), kind))
+///     .collect();
+/// assert_eq!(
+///     tokens,
+///     [
+///         ("!speed", TokenKind::Define),
+///         ("=", TokenKind::Operator),
+///         ("2", TokenKind::Number),
+///         ("Main", TokenKind::Label),
+///         ("LDA.b", TokenKind::Mnemonic),
+///         ("!speed", TokenKind::Define),
+///         ("; load", TokenKind::Comment),
+///         ("BRA", TokenKind::Mnemonic),
+///         ("Main", TokenKind::Label),
+///     ],
+/// );
+/// ```
+pub fn semantic_tokens<'a, I: Into<Input<'a>>>(input: I) -> Vec<(Span<'a>, TokenKind)> {
+    let input = input.into();
+    let source = input.fragment();
+    let (statements, _) = grammar::program_with_recovery(input);
+    let mut names = Names {
+        source,
+        ..Names::default()
+    };
+    visit::walk_statements(&mut names, &statements);
+    let tokens: Vec<_> = Lexer::new(source).collect();
+    let mut classified: Vec<(Span<'a>, TokenKind)> = Vec::new();
+    // Name of a macro call, merged with its `%`.
+    let mut merged = None;
+    for (index, token) in tokens.iter().enumerate() {
+        if merged == Some(index) {
+            continue;
+        }
+        let start = offset(source, token);
+        let kind = match token.kind {
+            // The lexer doesn't know that relative labels like `-:` can
+            // precede an instruction.
+            lexer::TokenKind::Identifier if names.instructions.contains(&start) => {
+                TokenKind::Mnemonic
+            }
+            lexer::TokenKind::Mnemonic => {
+                let name = token.span.fragment();
+                if names.instructions.contains(&start) || normalize_mnemonic(name).is_some() {
+                    TokenKind::Mnemonic
+                } else {
+                    TokenKind::Directive
+                }
+            }
+            lexer::TokenKind::Identifier => {
+                let end = start + token.span.fragment().len();
+                let dot = index
+                    .checked_sub(1)
+                    .map(|index| &tokens[index])
+                    .filter(|dot| is_punctuation(dot, ".") && adjacent(source, dot, token));
+                if let Some(dot) = dot {
+                    let dot_start = offset(source, dot);
+                    match classified.last_mut() {
+                        // A width suffix extends its mnemonic.
+                        Some(&mut (ref mut span, TokenKind::Mnemonic))
+                            if span.range(source).unwrap().end == dot_start =>
+                        {
+                            let mnemonic_start = span.range(source).unwrap().start;
+                            *span = Span::new(&source[mnemonic_start..end]);
+                        }
+                        _ => {
+                            classified.push((Span::new(&source[dot_start..end]), TokenKind::Label))
+                        }
+                    }
+                    continue;
+                }
+                let next = tokens.get(index + 1);
+                let is_call = next.is_some_and(|next| is_punctuation(next, "("));
+                if next.is_some_and(|next| is_punctuation(next, ":")) {
+                    TokenKind::Label
+                } else {
+                    names.classify(token.span.fragment(), start, is_call)
+                }
+            }
+            lexer::TokenKind::Operator if token.span.fragment() == "%" => {
+                match tokens.get(index + 1) {
+                    Some(name)
+                        if name.kind == lexer::TokenKind::Identifier
+                            && adjacent(source, token, name)
+                            && (names.calls.contains(&start) || starts_line(&tokens, index)) =>
+                    {
+                        let end = offset(source, name) + name.span.fragment().len();
+                        classified.push((Span::new(&source[start..end]), TokenKind::Macro));
+                        merged = Some(index + 1);
+                        continue;
+                    }
+                    _ => TokenKind::Operator,
+                }
+            }
+            lexer::TokenKind::Number => TokenKind::Number,
+            lexer::TokenKind::String => TokenKind::String,
+            lexer::TokenKind::Operator => TokenKind::Operator,
+            lexer::TokenKind::Comment => TokenKind::Comment,
+            lexer::TokenKind::Punctuation | lexer::TokenKind::Newline | lexer::TokenKind::Error => {
+                continue
+            }
+        };
+        classified.push((token.span, kind));
+    }
+    classified
+}
+
+/// Checks whether a token is the first one on its line, so that `%`
+/// starts a macro call even in an invalid statement.
+fn starts_line(tokens: &[Token], index: usize) -> bool {
+    index == 0 || tokens[index - 1].kind == lexer::TokenKind::Newline
+}
+
+fn offset(source: &str, token: &Token) -> usize {
+    token.span.range(source).unwrap().start
+}
+
+fn adjacent(source: &str, first: &Token, second: &Token) -> bool {
+    offset(source, first) + first.span.fragment().len() == offset(source, second)
+}
+
+fn is_punctuation(token: &Token, text: &str) -> bool {
+    token.kind == lexer::TokenKind::Punctuation && token.span.fragment() == text
+}
+
+/// Names defined by a program, and where instructions and macro calls
+/// start.
+#[derive(Default)]
+struct Names<'a> {
+    source: &'a str,
+    labels: HashSet<&'a str>,
+    defines: HashSet<&'a str>,
+    macros: HashSet<&'a str>,
+    functions: HashSet<&'a str>,
+    instructions: HashSet<usize>,
+    calls: HashSet<usize>,
+    /// Bodies of macros and functions along with their parameters.
+    scopes: Vec<(Range<usize>, Vec<&'a str>)>,
+}
+
+impl<'a> Names<'a> {
+    fn classify(&self, name: &str, start: usize, is_call: bool) -> TokenKind {
+        let is_parameter = self
+            .scopes
+            .iter()
+            .any(|(range, parameters)| range.contains(&start) && parameters.contains(&name));
+        if is_parameter {
+            TokenKind::Parameter
+        } else if self.macros.contains(name) {
+            TokenKind::Macro
+        } else if name.starts_with('!') || self.defines.contains(name) {
+            TokenKind::Define
+        } else if self.labels.contains(name) {
+            TokenKind::Label
+        } else if is_call || self.functions.contains(name) {
+            TokenKind::Function
+        } else {
+            TokenKind::Identifier
+        }
+    }
+
+    fn range(&self, span: Span) -> Option<Range<usize>> {
+        span.range(self.source)
+    }
+
+    fn add_scope(&mut self, range: Range<usize>, parameters: &[VariableName<'a>]) {
+        let parameters = parameters.iter().map(|parameter| parameter.0).collect();
+        self.scopes.push((range, parameters));
+    }
+}
+
+impl<'a> Visitor<'a> for Names<'a> {
+    fn visit_statement(&mut self, statement: &Spanned<'a, Statement<'a>>) {
+        let range = match self.range(statement.span) {
+            Some(range) => range,
+            None => return,
+        };
+        match **statement {
+            Statement::Label(Label::Named(VariableName(name))) => {
+                self.labels.insert(name);
+            }
+            Statement::Assignment(VariableName(name), _)
+            | Statement::StringAssignment(VariableName(name), _) => {
+                self.defines.insert(name);
+            }
+            Statement::Macro(ref definition) => {
+                self.macros.insert(definition.name.0);
+                let end = definition
+                    .statements
+                    .last()
+                    .and_then(|last| self.range(last.span))
+                    .map_or(range.end, |last| last.end.max(range.end));
+                self.add_scope(range.start..end, &definition.parameters);
+            }
+            Statement::Function(ref function) => {
+                self.functions.insert(function.name.0);
+                self.add_scope(range, &function.parameters);
+            }
+            Statement::MacroCall(_) => {
+                self.calls.insert(range.start);
+            }
+            Statement::Opcode(_) | Statement::Spc700(_) | Statement::SuperFx(_) => {
+                self.instructions.insert(range.start);
+            }
+            _ => {}
+        }
+        visit::walk_statement(self, statement);
+    }
+}
@@ -0,0 +1,195 @@
+//! Traversal of syntax trees.
+//!
+//! A `Visitor` has a method for every kind of node, which by default
+//! visits children of that node with a corresponding `walk_*` function.
+//! An analysis overrides methods for nodes it's interested in, calling
+//! `walk_*` from an override when it needs to look deeper.
+//!
+//! # Examples
+//!
+//! Counting references to a label, including ones in if blocks.
+//!
+//! ```
+//! use mvp::parser::ast::{Label, VariableName};
+//! use mvp::parser::grammar::{parse_program, Input};
+//! use mvp::parser::visit::{self, Visitor};
+//!
+//! struct References(usize);
+//!
+//! impl<'a> Visitor<'a> for References {
+//!     fn visit_label(&mut self, label: &Label<'a>) {
+//!         if *label == Label::Named(VariableName("Timer")) {
+//!             self.0 += 1;
+//!         }
+//!     }
+//! }
+//!
+//! let source = "
+//! if Timer > 1
+//!     LDA Timer
+//! endif
+//! STZ Timer+1
+//! ";
+//! let statements = parse_program(Input::new(source)).unwrap();
+//! let mut references = References(0);
+//! visit::walk_statements(&mut references, &statements);
+//! assert_eq!(references.0, 3);
+//! ```
+
+use parser::ast::{Condition, Expression, Function, Label, Opcode, OpcodeMode, Spanned, Statement};
+
+/// A syntax tree visitor.
+pub trait Visitor<'a> {
+    fn visit_statement(&mut self, statement: &Spanned<'a, Statement<'a>>) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_opcode(&mut self, opcode: &Opcode<'a>) {
+        walk_opcode(self, opcode);
+    }
+
+    fn visit_condition(&mut self, condition: &Condition<'a>) {
+        walk_condition(self, condition);
+    }
+
+    fn visit_function(&mut self, function: &Function<'a>) {
+        walk_function(self, function);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression<'a>) {
+        walk_expression(self, expression);
+    }
+
+    /// Visits a label, either declared by a statement or referred to by an
+    /// expression.
+    fn visit_label(&mut self, _label: &Label<'a>) {}
+}
+
+/// Visits statements in order.
+pub fn walk_statements<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    statements: &[Spanned<'a, Statement<'a>>],
+) {
+    for statement in statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    statement: &Spanned<'a, Statement<'a>>,
+) {
+    match **statement {
+        Statement::Label(ref label) => visitor.visit_label(label),
+        Statement::Opcode(ref opcode) => visitor.visit_opcode(opcode),
+        Statement::If(ref conditions) => {
+            for condition in conditions {
+                visitor.visit_condition(condition);
+            }
+        }
+        Statement::Function(ref function) => visitor.visit_function(function),
+        Statement::Assignment(_, ref value)
+        | Statement::StringAssignment(_, ref value)
+        | Statement::WarnPc(ref value)
+        | Statement::Assert(ref value, _)
+        | Statement::RomVariant(_, ref value) => visitor.visit_expression(value),
+        Statement::Print(ref values) | Statement::ExpectCrc(ref values) => {
+            for value in values {
+                visitor.visit_expression(value);
+            }
+        }
+        Statement::VariantTable(_, ref entries) => {
+            for (_, address) in entries {
+                visitor.visit_expression(address);
+            }
+        }
+        Statement::Spc700(ref instruction) => {
+            for operand in &instruction.operands {
+                for value in operand.values() {
+                    visitor.visit_expression(value);
+                }
+            }
+        }
+        Statement::SuperFx(ref instruction) => {
+            for operand in &instruction.operands {
+                if let Some(value) = operand.value() {
+                    visitor.visit_expression(value);
+                }
+            }
+        }
+        Statement::Bank(ref bank) => {
+            if let Some(ref bank) = *bank {
+                visitor.visit_expression(bank);
+            }
+        }
+        Statement::SpcBlock(ref block) => {
+            visitor.visit_expression(&block.address);
+            walk_statements(visitor, &block.statements);
+            if let Some(ref execute) = block.execute {
+                visitor.visit_expression(execute);
+            }
+        }
+        Statement::Macro(ref definition) => walk_statements(visitor, &definition.statements),
+        Statement::MacroCall(ref call) => {
+            for argument in &call.arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        Statement::Expansion(ref statements) => walk_statements(visitor, statements),
+        Statement::FindPattern(..)
+        | Statement::Mapper(_)
+        | Statement::InterruptSafe(_)
+        | Statement::Global(_)
+        | Statement::Include(_)
+        | Statement::IncludeOnce
+        | Statement::Undef(_)
+        | Statement::RegisterWidth(..)
+        | Statement::Arch(_)
+        | Statement::Warnings(..)
+        | Statement::Asar(_)
+        | Statement::Precedence(_)
+        | Statement::AsarFeature(_)
+        | Statement::Error(..) => {}
+    }
+}
+
+pub fn walk_opcode<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, opcode: &Opcode<'a>) {
+    if let Some(ref value) = opcode.value {
+        visitor.visit_expression(value);
+    }
+    if let OpcodeMode::Move { ref second } = opcode.mode {
+        visitor.visit_expression(second);
+    }
+}
+
+pub fn walk_condition<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, condition: &Condition<'a>) {
+    if let Some(ref predicate) = condition.predicate {
+        visitor.visit_expression(predicate);
+    }
+    walk_statements(visitor, &condition.statements);
+}
+
+pub fn walk_function<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, function: &Function<'a>) {
+    visitor.visit_expression(&function.body);
+}
+
+pub fn walk_expression<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, expression: &Expression<'a>) {
+    match *expression {
+        Expression::Variable(ref label) => visitor.visit_label(label),
+        Expression::Binary(_, ref operands) => {
+            visitor.visit_expression(&operands.0);
+            visitor.visit_expression(&operands.1);
+        }
+        Expression::Call(_, ref arguments) => {
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        Expression::Negate(ref value) => visitor.visit_expression(value),
+        Expression::Number(_)
+        | Expression::String(_)
+        | Expression::ProgramCounter
+        | Expression::ArgumentCount
+        | Expression::Error(_) => {}
+    }
+}
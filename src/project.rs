@@ -0,0 +1,556 @@
+//! Assembly of programs spread over several files.
+//!
+//! A `Project` has root files, which are assembled in order of their
+//! registration into a single output, as if they were one file. Files
+//! included with `incsrc` are inserted in place of their `incsrc`
+//! statements, so all files share the same symbols, and a label defined
+//! in one file can be used in any other. A file with `includeonce` is
+//! only inserted where it's first included, or as a root file.
+//!
+//! Symbols can be defined and a mapper selected before the first root
+//! file, as if by statements preceding it.
+//!
+//! Files read by built-in functions, like `readfile1`, are loaded along
+//! with sources, and are looked up like included files.
+//!
+//! Errors and warnings point into sources of a project, and can be turned
+//! into diagnostics with a path, a line and a column with `diagnostics`
+//! and `warnings`.
+//!
+//! Analyses of whole programs, like `analysis::stack`, are run on
+//! assembled statements when they are configured, reporting warnings with
+//! levels of their categories set for a project.
+
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use analysis::{interrupt, stack};
+use assembler::branch::{self, LongJump};
+use assembler::flags::State;
+use assembler::macros::{self, Names, DEFAULT_DEPTH_LIMIT};
+use assembler::output::{self, Fixup, Output};
+use assembler::sfc;
+use encoder::{self, AddressingMode, Aliases, EncodeError};
+use eval;
+use include::{self, FileSystem, Includes, Resolver, Source};
+use mapping::Mapper;
+use parser::ast::{Label, Span, Spanned, Statement, VariableName};
+use parser::grammar::{self, Input, ParseError, ParserOptions};
+use symbols::Symbols;
+use warning::{Category, Level, Levels, Warning};
+
+/// A failure to assemble a project.
+#[derive(Debug)]
+pub enum Error<'a> {
+    /// Files couldn't be loaded.
+    Include(include::Error),
+    /// Lines which couldn't be parsed, in all files.
+    Parse(Vec<ParseError<'a>>),
+    Macro(macros::Error<'a>),
+    Branch(branch::Error<'a>),
+    Assembly(output::Error<'a>),
+}
+
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Include(ref error) => write!(f, "{}", error),
+            Error::Parse(ref errors) => write!(f, "{} lines couldn't be parsed", errors.len()),
+            Error::Macro(ref error) => write!(f, "{}", error),
+            Error::Branch(ref error) => write!(f, "{}", error),
+            Error::Assembly(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl<'a> error::Error for Error<'a> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Include(ref error) => error.source(),
+            Error::Parse(_) => None,
+            Error::Macro(ref error) => error.source(),
+            Error::Branch(ref error) => error.source(),
+            Error::Assembly(ref error) => error.source(),
+        }
+    }
+}
+
+/// An error message, with a location in a file when an error has one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    /// Path, line and column, counted from one.
+    pub location: Option<(PathBuf, usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((ref path, line, column)) = self.location {
+            write!(f, "{}:{}:{}: ", path.display(), line, column)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+/// Source files assembled together.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::flags::State;
+/// use mvp::include::{Disk, Overlay};
+/// use mvp::project::Project;
+///
+/// let mut files = Overlay::new(Disk);
+/// files.insert("/game/main.asm", "Main:\nincsrc \"lib/wait.asm\"\nJSR Wait\n");
+/// files.insert("/game/lib/wait.asm", "BRA Main\n");
+/// files.insert("/game/wait.asm", "Wait:\nWAI\nRTS\n");
+/// let mut project = Project::new(files);
+/// project.add_root("/game/main.asm");
+/// project.add_root("/game/wait.asm");
+/// project.load().unwrap();
+/// let output = project.assemble(0x808000, State::new(true, true)).unwrap();
+/// assert_eq!(output.data, [0x80, 0xFE, 0x20, 0x05, 0x80, 0xCB, 0x60]);
+/// ```
+#[derive(Debug)]
+pub struct Project<F> {
+    resolver: Resolver<F>,
+    /// Paths of root files, with contents of ones not read from the file
+    /// system.
+    roots: Vec<(PathBuf, Option<String>)>,
+    sources: Vec<Source>,
+    /// Contents of files read by built-in functions, by paths as written,
+    /// see `eval::file_paths`.
+    files: HashMap<String, Vec<u8>>,
+    /// Indices of loaded root files in `sources`.
+    loaded_roots: Vec<usize>,
+    /// Statements assembled before root files, see `define`.
+    prelude: String,
+    options: ParserOptions,
+    levels: Levels,
+    aliases: Aliases,
+    /// Jump out-of-range branches are rewritten into, if they are.
+    long_branches: Option<LongJump>,
+    names: Names,
+    base_rom: Option<Vec<u8>>,
+    /// Stack budget in bytes, with a call depth bound.
+    stack_budget: Option<(u32, u32)>,
+    /// Interrupt handler and main loop entry points.
+    interrupt_entries: Option<(String, String)>,
+}
+
+impl<F: FileSystem> Project<F> {
+    /// Creates a project without files, reading files from a file system.
+    pub fn new(file_system: F) -> Project<F> {
+        Project {
+            resolver: Resolver::new(file_system),
+            roots: Vec::new(),
+            sources: Vec::new(),
+            files: HashMap::new(),
+            loaded_roots: Vec::new(),
+            prelude: String::new(),
+            options: ParserOptions::default(),
+            levels: Levels::default(),
+            aliases: Aliases::default(),
+            long_branches: None,
+            names: Names::new(),
+            base_rom: None,
+            stack_budget: None,
+            interrupt_entries: None,
+        }
+    }
+
+    /// Adds a root file, assembled after root files added before it.
+    pub fn add_root<P: Into<PathBuf>>(&mut self, path: P) {
+        self.roots.push((path.into(), None));
+    }
+
+    /// Adds a root file with contents which aren't read from the file
+    /// system, like standard input. Its includes are looked up relative to
+    /// its path.
+    pub fn add_source<P: Into<PathBuf>, S: Into<String>>(&mut self, path: P, contents: S) {
+        self.roots.push((path.into(), Some(contents.into())));
+    }
+
+    /// Adds a directory searched for included files.
+    pub fn add_directory<P: Into<PathBuf>>(&mut self, directory: P) {
+        self.resolver.add_directory(directory);
+    }
+
+    /// Sets options used to parse sources.
+    pub fn set_options(&mut self, options: ParserOptions) {
+        self.options = options;
+    }
+
+    /// Sets the level of a warning category at the start of code, which
+    /// `warnings` directives can still change.
+    pub fn set_warning_level(&mut self, category: Category, level: Level) {
+        self.levels.set(category, level);
+    }
+
+    /// Sets whether alias mnemonics, like `DEA`, are accepted, which they
+    /// are by default.
+    pub fn set_aliases(&mut self, aliases: Aliases) {
+        self.aliases = aliases;
+    }
+
+    /// Rewrites branches to labels out of their range into jumps after
+    /// expanding macros, with instruction sizes estimated before assembly,
+    /// see `branch::rewrite_long_branches`.
+    pub fn set_long_branches(&mut self, jump: LongJump) {
+        self.long_branches = Some(jump);
+    }
+
+    /// Assigns an expression to a name before the first root file, like
+    /// `name = value`.
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.prelude += &format!("{} = {}\n", name, value);
+    }
+
+    /// Selects a mapper before the first root file, which can still be
+    /// changed by a mapper directive.
+    pub fn set_mapper(&mut self, mapper: Mapper) {
+        self.prelude += &format!("{}\n", mapper.directive());
+    }
+
+    /// Sets a ROM being patched, see `Symbols::set_base_rom`.
+    pub fn set_base_rom(&mut self, rom: Vec<u8>) {
+        self.base_rom = Some(rom);
+    }
+
+    /// Reports routines using more than `budget` bytes of stack, following
+    /// at most `call_depth` nested calls, see `stack::estimate`.
+    pub fn set_stack_budget(&mut self, budget: u32, call_depth: u32) {
+        self.stack_budget = Some((budget, call_depth));
+    }
+
+    /// Reports multi-byte variables written one byte at a time by routines
+    /// reachable from both an interrupt handler and the main loop, given
+    /// by names of their labels, see `interrupt::check`.
+    pub fn set_interrupt_entries<I: Into<String>, M: Into<String>>(
+        &mut self,
+        interrupt: I,
+        main: M,
+    ) {
+        self.interrupt_entries = Some((interrupt.into(), main.into()));
+    }
+
+    /// ROM being patched, if there is one.
+    pub fn base_rom(&self) -> Option<&[u8]> {
+        self.base_rom.as_deref()
+    }
+
+    /// Resolver finding included files, with the file system of a project.
+    pub fn resolver(&self) -> &Resolver<F> {
+        &self.resolver
+    }
+
+    /// Mutable access to the resolver, for updating an overlay.
+    pub fn resolver_mut(&mut self) -> &mut Resolver<F> {
+        &mut self.resolver
+    }
+
+    /// Reads root files and files they include, replacing sources read
+    /// before.
+    ///
+    /// Files read by built-in functions are read too, relative to the
+    /// first source reading them, or from include directories. Files
+    /// which can't be read are skipped, so that `canread` can check them.
+    pub fn load(&mut self) -> Result<(), include::Error> {
+        self.sources.clear();
+        self.files.clear();
+        self.loaded_roots.clear();
+        for (path, contents) in &self.roots {
+            let loaded = match contents {
+                Some(contents) => self.resolver.load_source(Source {
+                    path: path.clone(),
+                    contents: contents.clone(),
+                })?,
+                None => self.resolver.load(path)?,
+            };
+            let index = self.index(&loaded[0].path).unwrap_or(self.sources.len());
+            self.loaded_roots.push(index);
+            for source in loaded {
+                if self.index(&source.path).is_none() {
+                    self.sources.push(source);
+                }
+            }
+        }
+        for source in &self.sources {
+            let input = Input::with_options(&source.contents, self.options);
+            let (statements, _) = grammar::program_with_recovery(input);
+            for name in eval::file_paths(&statements) {
+                if self.files.contains_key(name) {
+                    continue;
+                }
+                let contents = self
+                    .resolver
+                    .resolve(&source.path, name)
+                    .and_then(|path| self.resolver.file_system().read_bytes(&path).ok());
+                if let Some(contents) = contents {
+                    self.files.insert(name.to_string(), contents);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Loaded sources, starting with the first root file.
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    fn index(&self, path: &Path) -> Option<usize> {
+        self.sources.iter().position(|source| source.path == path)
+    }
+
+    /// Assembles loaded sources, see `output::assemble_with_levels`.
+    ///
+    /// Macros are expanded before assembly, with macros of every file
+    /// available in all of them.
+    pub fn assemble(&self, origin: u32, state: State) -> Result<Output<'_>, Error<'_>> {
+        let parse =
+            |source| grammar::program_with_recovery(Input::with_options(source, self.options));
+        let (mut statements, mut errors) = parse(&self.prelude);
+        let mut parsed = Vec::with_capacity(self.sources.len());
+        for source in &self.sources {
+            let (source_statements, source_errors) = parse(&source.contents);
+            parsed.push(source_statements);
+            errors.extend(source_errors);
+        }
+        if !errors.is_empty() {
+            return Err(Error::Parse(errors));
+        }
+        let mut included_once = HashSet::new();
+        for &root in &self.loaded_roots {
+            if Includes::of(&parsed[root]).once && !included_once.insert(root) {
+                continue;
+            }
+            let path = &self.sources[root].path;
+            statements.extend(self.include(
+                path,
+                parsed[root].clone(),
+                &parsed,
+                &mut included_once,
+            )?);
+        }
+        let statements =
+            macros::expand(statements, &self.names, DEFAULT_DEPTH_LIMIT).map_err(Error::Macro)?;
+        if self.aliases == Aliases::Reject {
+            if let Some((span, error)) = find_alias(&statements) {
+                let error = EncodeError::from(error);
+                return Err(Error::Assembly(output::Error::Encode(span, error)));
+            }
+        }
+        let statements = match self.long_branches {
+            Some(jump) => branch::rewrite_long_branches(statements, jump, branch::estimated_size)
+                .map_err(Error::Branch)?,
+            None => statements,
+        };
+        let mut symbols = Symbols::new();
+        if let Some(ref rom) = self.base_rom {
+            symbols.set_base_rom(rom);
+        }
+        for (path, contents) in &self.files {
+            symbols.add_file(path.as_str(), contents.clone());
+        }
+        let lints = self.lints(&statements);
+        let mut output =
+            output::assemble_with_levels(statements, origin, state, symbols, self.levels)
+                .map_err(Error::Assembly)?;
+        for warning in lints {
+            match self.levels.get(warning.category) {
+                Level::Allow => {}
+                Level::Warn => output.warnings.push(warning),
+                Level::Deny => return Err(Error::Assembly(output::Error::Denied(warning))),
+            }
+        }
+        Ok(output)
+    }
+
+    /// Runs configured analyses, with warnings pointing to labels of
+    /// routines, and checks labels of macros.
+    fn lints<'a>(&'a self, statements: &[Spanned<'a, Statement<'a>>]) -> Vec<Warning<'a>> {
+        let labels: HashMap<_, _> = statements
+            .iter()
+            .filter_map(|statement| match **statement {
+                Statement::Label(Label::Named(VariableName(name))) => Some((name, statement.span)),
+                _ => None,
+            })
+            .collect();
+        let mut warnings: Vec<_> = macros::captures(statements)
+            .into_iter()
+            .map(|capture| Warning {
+                category: Category::MacroCapture,
+                span: capture.span,
+                message: capture.to_string(),
+            })
+            .collect();
+        if let Some((budget, call_depth)) = self.stack_budget {
+            for usage in stack::estimate(statements, call_depth) {
+                if usage.exceeds(budget) {
+                    warnings.push(Warning {
+                        category: Category::StackUsage,
+                        span: labels[usage.name],
+                        message: format!(
+                            "routine {} uses {}{} bytes of stack, over the budget of {}",
+                            usage.name,
+                            if usage.exact { "" } else { "at least " },
+                            usage.bytes,
+                            budget
+                        ),
+                    });
+                }
+            }
+        }
+        if let Some((ref interrupt, ref main)) = self.interrupt_entries {
+            for warning in interrupt::check(statements, interrupt, main) {
+                warnings.push(Warning {
+                    category: Category::InterruptSafety,
+                    span: labels[warning.routine],
+                    message: format!(
+                        "routine {} is called from {} and {}, and writes {} one byte at a time",
+                        warning.routine, interrupt, main, warning.variable
+                    ),
+                });
+            }
+        }
+        warnings
+    }
+
+    /// Replaces includes in statements of a file at `path` with
+    /// statements of included files, which must have been loaded.
+    ///
+    /// Indices of files with `includeonce` which were already inserted
+    /// are in `included_once`.
+    fn include<'a>(
+        &'a self,
+        path: &Path,
+        statements: Vec<Spanned<'a, Statement<'a>>>,
+        parsed: &[Vec<Spanned<'a, Statement<'a>>>],
+        included_once: &mut HashSet<usize>,
+    ) -> Result<Vec<Spanned<'a, Statement<'a>>>, Error<'a>> {
+        let mut included = Vec::with_capacity(statements.len());
+        for mut statement in statements {
+            match statement.node {
+                Statement::Include(name) => {
+                    let index = self
+                        .resolver
+                        .resolve(path, name)
+                        .and_then(|resolved| self.index(&resolved))
+                        .ok_or_else(|| {
+                            Error::Include(include::Error::NotFound {
+                                from: path.to_path_buf(),
+                                name: name.to_string(),
+                            })
+                        })?;
+                    if Includes::of(&parsed[index]).once && !included_once.insert(index) {
+                        continue;
+                    }
+                    let path = &self.sources[index].path;
+                    included.extend(self.include(
+                        path,
+                        parsed[index].clone(),
+                        parsed,
+                        included_once,
+                    )?);
+                    continue;
+                }
+                Statement::Macro(ref mut definition) => {
+                    let statements = definition.statements.split_off(0);
+                    definition.statements =
+                        self.include(path, statements, parsed, included_once)?;
+                }
+                Statement::If(ref mut conditions) => {
+                    for condition in conditions {
+                        let statements = condition.statements.split_off(0);
+                        condition.statements =
+                            self.include(path, statements, parsed, included_once)?;
+                    }
+                }
+                _ => {}
+            }
+            included.push(statement);
+        }
+        Ok(included)
+    }
+
+    /// Finds a path, a line and a column where a span starts.
+    pub fn locate(&self, span: Span) -> Option<(&Path, usize, usize)> {
+        self.sources.iter().find_map(|source| {
+            let (line, column) = span.line_column(&source.contents)?;
+            Some((source.path.as_path(), line, column))
+        })
+    }
+
+    /// Turns an error into diagnostics, one for every parse error, and
+    /// a single one otherwise.
+    pub fn diagnostics(&self, error: &Error) -> Vec<Diagnostic> {
+        match *error {
+            Error::Include(ref error) => vec![self.diagnostic(None, error.to_string())],
+            Error::Parse(ref errors) => errors
+                .iter()
+                .map(|error| self.diagnostic(Some(error.position()), error.to_string()))
+                .collect(),
+            Error::Macro(ref error) => {
+                vec![self.diagnostic(Some(error.span()), error.to_string())]
+            }
+            Error::Branch(ref error) => {
+                vec![self.diagnostic(Some(error.span()), error.to_string())]
+            }
+            Error::Assembly(ref error) => {
+                vec![self.diagnostic(Some(error.span()), error.to_string())]
+            }
+        }
+    }
+
+    /// Turns fixups left by an assembly into diagnostics, as they refer
+    /// to labels which aren't defined.
+    pub fn unresolved(&self, fixups: &[Fixup]) -> Vec<Diagnostic> {
+        fixups
+            .iter()
+            .map(|fixup| {
+                let error = sfc::Error::Unresolved(fixup.span);
+                self.diagnostic(Some(fixup.span), error.to_string())
+            })
+            .collect()
+    }
+
+    /// Turns warnings of an assembly into diagnostics.
+    pub fn warnings(&self, warnings: &[Warning]) -> Vec<Diagnostic> {
+        warnings
+            .iter()
+            .map(|warning| self.diagnostic(Some(warning.span), warning.to_string()))
+            .collect()
+    }
+
+    fn diagnostic(&self, span: Option<Span>, message: String) -> Diagnostic {
+        Diagnostic {
+            location: span
+                .and_then(|span| self.locate(span))
+                .map(|(path, line, column)| (path.to_path_buf(), line, column)),
+            message,
+        }
+    }
+}
+
+/// Finds the first instruction using an alias mnemonic, including ones in
+/// if blocks and macro expansions.
+fn find_alias<'a>(
+    statements: &[Spanned<'a, Statement<'a>>],
+) -> Option<(Span<'a>, encoder::LookupError)> {
+    statements.iter().find_map(|statement| match **statement {
+        Statement::Opcode(ref opcode) => {
+            encoder::resolve_alias(opcode.name, AddressingMode::Implied, Aliases::Reject)
+                .err()
+                .map(|error| (statement.span, error))
+        }
+        Statement::If(ref conditions) => conditions
+            .iter()
+            .find_map(|condition| find_alias(&condition.statements)),
+        Statement::Expansion(ref statements) => find_alias(statements),
+        _ => None,
+    })
+}
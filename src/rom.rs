@@ -0,0 +1,543 @@
+//! ROM image manipulation.
+//!
+//! Functions in this module work on complete ROM images, as written into
+//! `.sfc` files, without a copier header.
+
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use mapping::Mapper;
+
+/// Placement of data in a ROM image.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Layout {
+    /// Size of a single bank in bytes.
+    pub bank_size: usize,
+    /// File offset of internal header.
+    pub header: usize,
+}
+
+/// Layout used by LoROM images.
+pub const LOROM: Layout = Layout {
+    bank_size: 0x8000,
+    header: 0x7FC0,
+};
+
+/// Layout used by HiROM images.
+pub const HIROM: Layout = Layout {
+    bank_size: 0x10000,
+    header: 0xFFC0,
+};
+
+/// Offset of ROM size field within internal header.
+const ROM_SIZE_FIELD: usize = 0x17;
+
+/// Smallest ROM size that can be described by internal header.
+const MINIMUM_SIZE: usize = 0x8000;
+
+/// A way to determine the size of output ROM.
+///
+/// Emulators and flashcarts are picky about ROM sizes in different ways,
+/// so no single policy works for everyone.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SizePolicy {
+    /// Keep the size as is.
+    Keep,
+    /// Remove trailing banks which contain only fill bytes. The first bank
+    /// is always kept, as it contains internal header.
+    Trim,
+    /// Pad to the next power of two.
+    PadToValid,
+    /// Pad or truncate to an exact size in bytes.
+    Exact(usize),
+}
+
+/// Resizes a ROM image according to a policy.
+///
+/// Newly added bytes are set to `fill`. Afterwards the ROM size field of
+/// internal header is updated to match the new size, as long as the image
+/// is large enough to contain a header.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::rom::{self, SizePolicy};
+///
+/// let mut image = vec![0; 0x28000];
+/// rom::resize(&mut image, SizePolicy::PadToValid, &rom::LOROM, 0);
+/// assert_eq!(image.len(), 0x40000);
+/// assert_eq!(image[0x7FD7], 8);
+/// ```
+pub fn resize(rom: &mut Vec<u8>, policy: SizePolicy, layout: &Layout, fill: u8) {
+    let size = match policy {
+        SizePolicy::Keep => rom.len(),
+        SizePolicy::Trim => trimmed_size(rom, layout, fill),
+        SizePolicy::PadToValid => cmp::max(rom.len(), MINIMUM_SIZE).next_power_of_two(),
+        SizePolicy::Exact(size) => size,
+    };
+    rom.resize(size, fill);
+    update_size_field(rom, layout);
+}
+
+fn trimmed_size(rom: &[u8], layout: &Layout, fill: u8) -> usize {
+    let used_banks = rom
+        .chunks(layout.bank_size)
+        .rposition(|bank| bank.iter().any(|&byte| byte != fill))
+        .map_or(1, |last| last + 1);
+    cmp::min(rom.len(), used_banks * layout.bank_size)
+}
+
+/// Returns a value of ROM size field for a given size in bytes.
+///
+/// The field stores the size as a power of two in kilobytes, rounding up
+/// sizes which aren't a power of two.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::rom;
+///
+/// assert_eq!(rom::size_code(0x100000), 0x0A);
+/// assert_eq!(rom::size_code(0x300000), 0x0C);
+/// ```
+pub fn size_code(size: usize) -> u8 {
+    let kilobytes = cmp::max(size, MINIMUM_SIZE).div_ceil(0x400);
+    kilobytes.next_power_of_two().trailing_zeros() as u8
+}
+
+/// Sets ROM size field of internal header to match the image size.
+///
+/// Images too small to contain a header are left unchanged.
+pub fn update_size_field(rom: &mut [u8], layout: &Layout) {
+    let size = size_code(rom.len());
+    if let Some(field) = rom.get_mut(layout.header + ROM_SIZE_FIELD) {
+        *field = size;
+    }
+}
+
+/// Length of title field in internal header.
+pub const TITLE_LENGTH: usize = 21;
+
+/// Offset of map mode field within internal header.
+const MAP_MODE_FIELD: usize = 0x15;
+
+/// Offset of version field within internal header.
+const VERSION_FIELD: usize = 0x1B;
+
+/// Offset of native mode vectors within internal header.
+const NATIVE_VECTORS: usize = 0x24;
+
+/// Offset of emulation mode vectors within internal header.
+const EMULATION_VECTORS: usize = 0x34;
+
+/// Addresses of interrupt handlers in bank `$00`.
+///
+/// The same handlers are used in native and emulation mode, except for
+/// reset, which always happens in emulation mode. Vectors set to 0 are
+/// left unused.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Vectors {
+    pub reset: u16,
+    pub nmi: u16,
+    pub irq: u16,
+    pub brk: u16,
+    pub cop: u16,
+}
+
+/// Contents of internal header, which describes a cartridge to an
+/// emulator or a flashcart.
+///
+/// The ROM size field and checksums aren't included, as they depend on
+/// a whole image.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Header<'a> {
+    /// Title, see `title`.
+    pub title: &'a str,
+    pub mapper: Mapper,
+    /// Size of battery backed RAM in bytes, 0 for none.
+    pub ram_size: usize,
+    /// Destination code, see `region_name`.
+    pub region: u8,
+    pub version: u8,
+    pub vectors: Vectors,
+}
+
+impl<'a> Header<'a> {
+    /// Creates a header for a North American cartridge without RAM.
+    pub fn new(title: &'a str, mapper: Mapper) -> Header<'a> {
+        Header {
+            title,
+            mapper,
+            ram_size: 0,
+            region: 0x01,
+            version: 0,
+            vectors: Vectors::default(),
+        }
+    }
+
+    /// Writes a header into a ROM image at a location given by its mapper.
+    ///
+    /// The ROM size field is set to match the image. The checksum is set
+    /// to 0 with its complement, which is a placeholder until it's fixed
+    /// with `fix_checksum`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when an image is too small to contain a header.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::mapping::Mapper;
+    /// use mvp::rom::Header;
+    ///
+    /// let mut image = vec![0; 0x10000];
+    /// Header::new("Test", Mapper::HiRom).write(&mut image);
+    /// assert_eq!(&image[0xFFC0..0xFFC6], b"TEST  ");
+    /// assert_eq!(image[0xFFD5], 0x21);
+    /// ```
+    pub fn write(&self, rom: &mut [u8]) {
+        let layout = self.mapper.layout();
+        let size = size_code(rom.len());
+        let header = &mut rom[layout.header..layout.header + 0x40];
+        header[..TITLE_LENGTH].copy_from_slice(&title(self.title));
+        header[MAP_MODE_FIELD] = self.mapper.map_mode();
+        header[MAP_MODE_FIELD + 1] = self.mapper.cartridge_type(self.ram_size > 0);
+        header[ROM_SIZE_FIELD] = size;
+        header[ROM_SIZE_FIELD + 1] = ram_size_code(self.ram_size);
+        header[REGION_FIELD] = self.region;
+        header[VERSION_FIELD] = self.version;
+        header[COMPLEMENT_FIELD..COMPLEMENT_FIELD + 4].copy_from_slice(&[0xFF, 0xFF, 0, 0]);
+        let vectors = &self.vectors;
+        let native = [vectors.cop, vectors.brk, 0, vectors.nmi, 0, vectors.irq];
+        let emulation = [vectors.cop, 0, 0, vectors.nmi, vectors.reset, vectors.irq];
+        for (i, vector) in native.iter().enumerate() {
+            let field = NATIVE_VECTORS + 2 * i;
+            header[field..field + 2].copy_from_slice(&vector.to_le_bytes());
+        }
+        for (i, vector) in emulation.iter().enumerate() {
+            let field = EMULATION_VECTORS + 2 * i;
+            header[field..field + 2].copy_from_slice(&vector.to_le_bytes());
+        }
+    }
+}
+
+/// Converts a name into a title field of internal header, in uppercase
+/// ASCII padded with spaces.
+///
+/// Other characters are replaced with underscores, and names which are
+/// too long are truncated.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::rom;
+///
+/// assert_eq!(&rom::title("Żółw")[..6], b"___W  ");
+/// ```
+pub fn title(name: &str) -> [u8; TITLE_LENGTH] {
+    let mut title = [b' '; TITLE_LENGTH];
+    for (byte, c) in title.iter_mut().zip(name.chars()) {
+        *byte = if c.is_ascii_graphic() || c == ' ' {
+            c.to_ascii_uppercase() as u8
+        } else {
+            b'_'
+        };
+    }
+    title
+}
+
+/// Returns a value of RAM size field for a given size in bytes, with 0
+/// meaning no RAM.
+fn ram_size_code(size: usize) -> u8 {
+    if size == 0 {
+        0
+    } else {
+        size.div_ceil(0x400).next_power_of_two().trailing_zeros() as u8
+    }
+}
+
+/// Computes the checksum of a ROM image, as stored in internal header.
+///
+/// The checksum is a 16-bit sum of all bytes, including the checksum
+/// itself and its complement, whose bytes always add up to `$1FE` when
+/// they are valid. Images with a size which isn't a power of two are
+/// summed as if their last part was mirrored, as it is on a cartridge.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::rom;
+///
+/// let mut image = vec![0; 0x30000];
+/// image[0x20000] = 1;
+/// assert_eq!(rom::checksum(&image), 2);
+/// ```
+pub fn checksum(rom: &[u8]) -> u16 {
+    mirrored_sum(rom, rom.len().next_power_of_two())
+}
+
+/// Sums bytes of data mirrored up to a power of two size.
+///
+/// The largest part with a power of two size is kept, and the rest is
+/// mirrored until it's as large as that part, recursively.
+fn mirrored_sum(data: &[u8], size: usize) -> u16 {
+    if data.is_empty() {
+        return 0;
+    }
+    let first = 1 << (usize::BITS - 1 - data.len().leading_zeros());
+    let (sum, length) = if first == data.len() {
+        (sum(data), first)
+    } else {
+        let rest = mirrored_sum(&data[first..], first);
+        (sum(&data[..first]).wrapping_add(rest), 2 * first)
+    };
+    sum.wrapping_mul((size / length) as u16)
+}
+
+fn sum(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(byte.into()))
+}
+
+/// Writes the checksum and its complement into internal header of a ROM
+/// image, returning the checksum.
+///
+/// # Panics
+///
+/// Panics when an image is too small to contain a header.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::rom;
+///
+/// let mut image = vec![1; 0x8000];
+/// let checksum = rom::fix_checksum(&mut image, &rom::LOROM);
+/// assert_eq!(checksum, 0x81FA);
+/// assert_eq!(image[0x7FDC..0x7FE0], [0x05, 0x7E, 0xFA, 0x81]);
+/// assert_eq!(rom::checksum(&image), checksum);
+/// ```
+pub fn fix_checksum(rom: &mut [u8], layout: &Layout) -> u16 {
+    let field = layout.header + COMPLEMENT_FIELD;
+    rom[field..field + 4].copy_from_slice(&[0xFF, 0xFF, 0x00, 0x00]);
+    let checksum = checksum(rom);
+    rom[field..field + 2].copy_from_slice(&(!checksum).to_le_bytes());
+    rom[field + 2..field + 4].copy_from_slice(&checksum.to_le_bytes());
+    checksum
+}
+
+/// Computes CRC-32 checksum of a ROM image.
+///
+/// This is the checksum commonly used to identify ROM dumps, for instance
+/// by No-Intro.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::rom;
+///
+/// assert_eq!(rom::crc32(b"123456789"), 0xCBF43926);
+/// ```
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// Size of copier header added by some dumping devices.
+const COPIER_HEADER_SIZE: usize = 0x200;
+
+/// Offset of destination code field within internal header.
+const REGION_FIELD: usize = 0x19;
+
+/// Offset of checksum complement field within internal header.
+const COMPLEMENT_FIELD: usize = 0x1C;
+
+/// A reason why a base ROM isn't one of the expected dumps.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BaseRomError {
+    /// ROM would match after removing 512 byte copier header.
+    Headered,
+    /// ROM would match after removing extra data at the end.
+    Overdumped { expected: usize, actual: usize },
+    /// ROM doesn't match any checksum. The region is read from internal
+    /// header, when one could be found.
+    Unrecognized { crc: u32, region: Option<u8> },
+}
+
+impl fmt::Display for BaseRomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BaseRomError::Headered => write!(
+                f,
+                "base ROM has a 512 byte copier header, remove it before patching"
+            ),
+            BaseRomError::Overdumped { expected, actual } => write!(
+                f,
+                "base ROM is overdumped, it's {:#X} bytes long instead of {:#X}",
+                actual, expected
+            ),
+            BaseRomError::Unrecognized { crc, region } => {
+                write!(f, "base ROM with CRC-32 {:08X} is not recognized", crc)?;
+                if let Some(name) = region.and_then(region_name) {
+                    write!(f, ", it may be a wrong version of the game ({})", name)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl error::Error for BaseRomError {}
+
+/// Returns a name of a region for a destination code in internal header.
+pub fn region_name(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "Japan",
+        0x01 => "North America",
+        0x02 => "Europe",
+        0x03 => "Scandinavia",
+        0x06 => "France",
+        0x07 => "Netherlands",
+        0x08 => "Spain",
+        0x09 => "Germany",
+        0x0A => "Italy",
+        0x0B => "China",
+        0x0D => "Korea",
+        0x0F => "Canada",
+        0x10 => "Brazil",
+        0x11 => "Australia",
+        _ => return None,
+    })
+}
+
+/// Checks that a base ROM is one of the expected dumps.
+///
+/// When a checksum doesn't match, common mistakes are diagnosed, like a
+/// copier header or an overdump, so that a user doesn't end up with a
+/// corrupted output.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::rom::{self, BaseRomError};
+///
+/// let mut dump = vec![0; 0x8000];
+/// let crc = rom::crc32(&dump);
+/// assert_eq!(rom::check_base_rom(&dump, &[crc]), Ok(()));
+/// dump.splice(0..0, vec![0; 0x200]);
+/// assert_eq!(rom::check_base_rom(&dump, &[crc]), Err(BaseRomError::Headered));
+/// ```
+pub fn check_base_rom(rom: &[u8], allowed: &[u32]) -> Result<(), BaseRomError> {
+    let crc = crc32(rom);
+    if allowed.contains(&crc) {
+        return Ok(());
+    }
+    if rom.len() % MINIMUM_SIZE == COPIER_HEADER_SIZE
+        && allowed.contains(&crc32(&rom[COPIER_HEADER_SIZE..]))
+    {
+        return Err(BaseRomError::Headered);
+    }
+    let mut size = MINIMUM_SIZE;
+    while size < rom.len() {
+        if allowed.contains(&crc32(&rom[..size])) {
+            return Err(BaseRomError::Overdumped {
+                expected: size,
+                actual: rom.len(),
+            });
+        }
+        size += MINIMUM_SIZE;
+    }
+    Err(BaseRomError::Unrecognized {
+        crc,
+        region: [LOROM, HIROM]
+            .iter()
+            .find(|layout| has_valid_header(rom, layout))
+            .map(|layout| rom[layout.header + REGION_FIELD]),
+    })
+}
+
+fn has_valid_header(rom: &[u8], layout: &Layout) -> bool {
+    let field = layout.header + COMPLEMENT_FIELD;
+    match rom.get(field..field + 4) {
+        Some(bytes) => {
+            let complement = u16::from_le_bytes([bytes[0], bytes[1]]);
+            let checksum = u16::from_le_bytes([bytes[2], bytes[3]]);
+            complement ^ checksum == 0xFFFF
+        }
+        None => false,
+    }
+}
+
+/// A byte signature, possibly with wildcards.
+///
+/// Patterns are used to find code in a base ROM, which makes patches
+/// resilient to small layout differences between game versions.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::rom::Pattern;
+///
+/// let pattern: Pattern = "A9 ?? 8D 00 21".parse().unwrap();
+/// let rom = [0xEA, 0xA9, 0x0F, 0x8D, 0x00, 0x21];
+/// assert_eq!(pattern.find(&rom), Some(1));
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pattern {
+    bytes: Vec<Option<u8>>,
+}
+
+/// A pattern couldn't be parsed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InvalidPattern;
+
+impl FromStr for Pattern {
+    type Err = InvalidPattern;
+
+    fn from_str(pattern: &str) -> Result<Pattern, InvalidPattern> {
+        let bytes = pattern
+            .split_whitespace()
+            .map(|byte| match byte {
+                "??" => Ok(None),
+                _ if byte.len() == 2 => u8::from_str_radix(byte, 16)
+                    .map(Some)
+                    .map_err(|_| InvalidPattern),
+                _ => Err(InvalidPattern),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if bytes.is_empty() {
+            return Err(InvalidPattern);
+        }
+        Ok(Pattern { bytes })
+    }
+}
+
+impl Pattern {
+    /// Returns file offset of the first match in a ROM.
+    pub fn find(&self, rom: &[u8]) -> Option<usize> {
+        self.find_all(rom).next()
+    }
+
+    /// Returns file offsets of all matches in a ROM.
+    ///
+    /// Matches may overlap.
+    pub fn find_all<'a>(&'a self, rom: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        rom.windows(self.bytes.len())
+            .enumerate()
+            .filter(move |&(_, window)| self.matches(window))
+            .map(|(offset, _)| offset)
+    }
+
+    fn matches(&self, window: &[u8]) -> bool {
+        self.bytes
+            .iter()
+            .zip(window)
+            .all(|(pattern, byte)| pattern.is_none_or(|pattern| pattern == *byte))
+    }
+}
@@ -0,0 +1,237 @@
+//! Project scaffolding, as done by `mvp init`.
+//!
+//! A new project consists of a manifest, a main source file with code
+//! booting into a black screen, and a file for variables in RAM. Its
+//! initial build is assembled from these sources, with an internal header
+//! generated by `sfc::build`.
+//!
+//! The manifest names the main source file and the ROM built from it,
+//! which `mvp build` reads as `Settings`.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use assembler::Assembler;
+use include::{Disk, Overlay};
+use mapping::Mapper;
+use serde_json::Value;
+
+/// Name of a project manifest.
+pub const MANIFEST: &str = "mvp.json";
+
+/// Directory template sources are assembled in, which isn't read from
+/// disk.
+const SOURCE_DIRECTORY: &str = "/mvp-template";
+
+/// A scaffolding failure.
+#[derive(Debug)]
+pub enum Error {
+    /// Template doesn't support a mapper.
+    UnsupportedMapper(Mapper),
+    /// A file to be created already exists.
+    FileExists(PathBuf),
+    /// Template sources couldn't be assembled, which is a bug.
+    Assembly(String),
+    /// A manifest isn't valid JSON, or is missing a field.
+    InvalidManifest,
+    /// File couldn't be written.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnsupportedMapper(mapper) => write!(
+                f,
+                "{} projects can't be created yet, use lorom or hirom",
+                mapper.directive()
+            ),
+            Error::FileExists(ref path) => write!(f, "{} already exists", path.display()),
+            Error::Assembly(ref message) => write!(f, "template doesn't assemble: {}", message),
+            Error::InvalidManifest => write!(f, "invalid project manifest"),
+            Error::Io(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// Settings of a project, read from its manifest.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+///
+/// use mvp::scaffold::Settings;
+///
+/// let json = r#"{"name": "game", "main": "main.asm", "output": "build/game.sfc"}"#;
+/// let settings = Settings::from_json(json).unwrap();
+/// assert_eq!(settings.name, "game");
+/// assert_eq!(settings.main, Path::new("main.asm"));
+/// assert_eq!(settings.output, Path::new("build/game.sfc"));
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Settings {
+    /// Name of a project, used as a title of its ROM.
+    pub name: String,
+    /// Main source file, relative to project directory.
+    pub main: PathBuf,
+    /// ROM produced by a build, relative to project directory.
+    pub output: PathBuf,
+}
+
+impl Settings {
+    /// Parses settings from a manifest.
+    pub fn from_json(json: &str) -> Result<Settings, Error> {
+        let value: Value = serde_json::from_str(json).map_err(|_| Error::InvalidManifest)?;
+        let field = |name| value[name].as_str().ok_or(Error::InvalidManifest);
+        Ok(Settings {
+            name: field("name")?.to_string(),
+            main: PathBuf::from(field("main")?),
+            output: PathBuf::from(field("output")?),
+        })
+    }
+
+    /// Reads settings from a manifest in a project directory, with paths
+    /// relative to the current directory.
+    pub fn read(directory: &Path) -> Result<Settings, Error> {
+        let json = fs::read_to_string(directory.join(MANIFEST))?;
+        let settings = Settings::from_json(&json)?;
+        Ok(Settings {
+            main: directory.join(settings.main),
+            output: directory.join(settings.output),
+            ..settings
+        })
+    }
+}
+
+/// A template of a new project.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::mapping::Mapper;
+/// use mvp::scaffold::Template;
+///
+/// let template = Template::new("hello", Mapper::LoRom).unwrap();
+/// let rom = template.rom().unwrap();
+/// assert_eq!(&rom[0x7FC0..0x7FC5], b"HELLO");
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Template<'a> {
+    name: &'a str,
+    mapper: Mapper,
+}
+
+impl<'a> Template<'a> {
+    /// Creates a template for a project using a given mapper.
+    ///
+    /// Only LoROM and HiROM are supported, as other mappers require
+    /// initialization of coprocessors or larger ROMs.
+    pub fn new(name: &'a str, mapper: Mapper) -> Result<Template<'a>, Error> {
+        match mapper {
+            Mapper::LoRom | Mapper::HiRom => Ok(Template { name, mapper }),
+            _ => Err(Error::UnsupportedMapper(mapper)),
+        }
+    }
+
+    /// Path of ROM produced by a build, relative to project directory.
+    pub fn output(&self) -> String {
+        format!("build/{}.sfc", self.name)
+    }
+
+    /// Source files of a project, with paths relative to project directory.
+    pub fn files(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (MANIFEST, self.manifest()),
+            ("main.asm", self.main()),
+            ("ram.asm", RAM.to_string()),
+            (".gitignore", "/build/\n".to_string()),
+        ]
+    }
+
+    fn manifest(&self) -> String {
+        let manifest = json!({
+            "name": self.name,
+            "mapper": self.mapper.directive(),
+            "main": "main.asm",
+            "output": self.output(),
+        });
+        serde_json::to_string_pretty(&manifest).unwrap() + "\n"
+    }
+
+    fn main(&self) -> String {
+        format!(
+            include_str!("scaffold/main.asm"),
+            name = self.name,
+            mapper = self.mapper.directive(),
+        )
+    }
+
+    /// Builds a ROM by assembling the template sources, with the project
+    /// name as its title.
+    pub fn rom(&self) -> Result<Vec<u8>, Error> {
+        let directory = Path::new(SOURCE_DIRECTORY);
+        let mut files = Overlay::new(Disk);
+        for (name, contents) in self.files() {
+            files.insert(directory.join(name), contents);
+        }
+        let mut assembler = Assembler::builder()
+            .file_system(files)
+            .root(directory.join("main.asm"))
+            .title(self.name)
+            .build();
+        let error = |e: &dyn error::Error| Error::Assembly(e.to_string());
+        assembler.load().map_err(|e| error(&e))?;
+        let output = assembler.assemble().map_err(|e| error(&e))?;
+        assembler.write(&output).map_err(|e| error(&e))
+    }
+
+    /// Writes a project into a directory, along with an initial build.
+    ///
+    /// Existing files are never overwritten.
+    pub fn write(&self, directory: &Path) -> Result<(), Error> {
+        let rom = self.rom()?;
+        let files = self.files();
+        let output = directory.join(self.output());
+        for path in files
+            .iter()
+            .map(|&(name, _)| directory.join(name))
+            .chain(Some(output.clone()))
+        {
+            if path.exists() {
+                return Err(Error::FileExists(path));
+            }
+        }
+        fs::create_dir_all(directory.join("build"))?;
+        for (name, contents) in files {
+            fs::write(directory.join(name), contents)?;
+        }
+        fs::write(output, rom)?;
+        Ok(())
+    }
+}
+
+const RAM: &str = "\
+; Variables in RAM. Bank $7E is the first 64KiB of work RAM, where the
+; first 8KiB are also mirrored in banks $00-$3F.
+
+FrameCounter = $7E0000
+";
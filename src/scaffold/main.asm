@@ -0,0 +1,20 @@
+; {name}
+;
+; The program boots into a black screen. The internal header is written
+; by mvp, with the reset vector pointing to the start of code.
+
+{mapper}
+
+incsrc "ram.asm"
+
+Reset:
+    SEI
+    CLC
+    XCE             ; Switch to native mode
+    SEP #$30        ; with 8-bit registers
+    LDA #$80
+    STA $2100       ; Enable forced blank, so the screen stays black
+    STZ $4200       ; Disable NMI and joypad autoread
+-:
+    WAI
+    BRA -
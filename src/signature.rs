@@ -0,0 +1,294 @@
+//! Integrity checking of produced ROMs.
+//!
+//! After a build, a digest of ranges written by the assembler can be
+//! stored in a `Manifest`. The manifest can be kept as a separate JSON
+//! file, or serialized into a binary block appended to or embedded into
+//! the ROM itself, and later used to confirm that a ROM was produced by a
+//! given build.
+
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::ops::Range;
+
+use serde_json::{self, Value};
+use sha2::{self, Digest as Sha2Digest};
+
+/// A hash function used to sign ROMs.
+///
+/// SHA-256 is provided by `Sha256`, but other algorithms can be plugged in
+/// by implementing this trait.
+pub trait Digest {
+    /// Name of an algorithm, as stored in manifests.
+    fn name(&self) -> &str;
+
+    /// Computes a digest of concatenated parts.
+    fn digest(&self, parts: &[&[u8]]) -> Vec<u8>;
+}
+
+/// SHA-256 hash function.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Sha256;
+
+impl Digest for Sha256 {
+    fn name(&self) -> &str {
+        "sha256"
+    }
+
+    fn digest(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = sha2::Sha256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().to_vec()
+    }
+}
+
+/// A signature failure.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// Range is not within the ROM.
+    RangeOutOfBounds(Range<usize>),
+    /// Signature block would overwrite signed data.
+    BlockOverlapsSignedRange(Range<usize>),
+    /// Manifest was made with a different hash function.
+    AlgorithmMismatch(String),
+    /// Manifest couldn't be parsed.
+    InvalidManifest,
+    /// Manifest has a field too long for a signature block.
+    BlockFieldTooLong(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::RangeOutOfBounds(ref range) => write!(
+                f,
+                "range {:#X}..{:#X} is outside of the ROM",
+                range.start, range.end
+            ),
+            Error::BlockOverlapsSignedRange(ref range) => write!(
+                f,
+                "signature block at {:#X}..{:#X} overlaps signed data",
+                range.start, range.end
+            ),
+            Error::AlgorithmMismatch(ref name) => write!(f, "unsupported algorithm {}", name),
+            Error::InvalidManifest => write!(f, "invalid manifest"),
+            Error::BlockFieldTooLong(field) => {
+                write!(f, "{} is too long for a signature block", field)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+const BLOCK_MAGIC: &[u8] = b"MVPSIG";
+
+/// Digest of ROM ranges written by a build.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Manifest {
+    /// Name of a hash function.
+    pub algorithm: String,
+    /// Signed ranges of a ROM, in file offsets.
+    pub ranges: Vec<Range<usize>>,
+    /// Digest of concatenated ranges.
+    pub digest: Vec<u8>,
+}
+
+impl Manifest {
+    /// Signs ranges of a ROM.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::signature::{Manifest, Sha256};
+    ///
+    /// let mut rom = vec![0; 0x8000];
+    /// let manifest = Manifest::sign(&rom, vec![0..0x100], &Sha256).unwrap();
+    /// assert_eq!(manifest.verify(&rom, &Sha256), Ok(true));
+    /// rom[0x80] = 1;
+    /// assert_eq!(manifest.verify(&rom, &Sha256), Ok(false));
+    /// ```
+    pub fn sign<D: Digest>(
+        rom: &[u8],
+        ranges: Vec<Range<usize>>,
+        digest: &D,
+    ) -> Result<Manifest, Error> {
+        let digest_value = digest_ranges(rom, &ranges, digest)?;
+        Ok(Manifest {
+            algorithm: digest.name().to_string(),
+            ranges,
+            digest: digest_value,
+        })
+    }
+
+    /// Checks whether signed ranges of a ROM match the digest.
+    pub fn verify<D: Digest>(&self, rom: &[u8], digest: &D) -> Result<bool, Error> {
+        if digest.name() != self.algorithm {
+            return Err(Error::AlgorithmMismatch(self.algorithm.clone()));
+        }
+        Ok(digest_ranges(rom, &self.ranges, digest)? == self.digest)
+    }
+
+    /// Serializes a manifest to JSON.
+    pub fn to_json(&self) -> String {
+        let ranges: Vec<_> = self
+            .ranges
+            .iter()
+            .map(|range| json!([range.start, range.end]))
+            .collect();
+        let digest: String = self.digest.iter().map(|b| format!("{:02x}", b)).collect();
+        json!({
+            "algorithm": self.algorithm,
+            "ranges": ranges,
+            "digest": digest,
+        })
+        .to_string()
+    }
+
+    /// Parses a manifest from JSON.
+    pub fn from_json(json: &str) -> Result<Manifest, Error> {
+        let value: Value = serde_json::from_str(json).map_err(|_| Error::InvalidManifest)?;
+        let algorithm = value["algorithm"].as_str().ok_or(Error::InvalidManifest)?;
+        let ranges = value["ranges"]
+            .as_array()
+            .ok_or(Error::InvalidManifest)?
+            .iter()
+            .map(|range| match (range[0].as_u64(), range[1].as_u64()) {
+                (Some(start), Some(end)) => Ok(start as usize..end as usize),
+                _ => Err(Error::InvalidManifest),
+            })
+            .collect::<Result<_, _>>()?;
+        let digest = value["digest"].as_str().ok_or(Error::InvalidManifest)?;
+        Ok(Manifest {
+            algorithm: algorithm.to_string(),
+            ranges,
+            digest: parse_hex(digest).ok_or(Error::InvalidManifest)?,
+        })
+    }
+
+    /// Serializes a manifest into a binary signature block.
+    ///
+    /// The block starts with `MVPSIG` magic, followed by algorithm name,
+    /// ranges and digest. Lengths and offsets are little endian, as
+    /// everything else on SNES. Fields which don't fit their lengths, like
+    /// an algorithm name longer than 255 bytes, are an error.
+    pub fn to_block(&self) -> Result<Vec<u8>, Error> {
+        let mut block = BLOCK_MAGIC.to_vec();
+        block.push(field_length("algorithm name", self.algorithm.len())?);
+        block.extend(self.algorithm.as_bytes());
+        block.extend(&field_length::<u16>("range list", self.ranges.len())?.to_le_bytes());
+        for range in &self.ranges {
+            block.extend(&field_length::<u32>("range", range.start)?.to_le_bytes());
+            block.extend(&field_length::<u32>("range", range.end)?.to_le_bytes());
+        }
+        block.push(field_length("digest", self.digest.len())?);
+        block.extend(&self.digest);
+        Ok(block)
+    }
+
+    /// Finds the last signature block in a ROM and parses it, so that
+    /// a ROM signed again is checked with its latest block.
+    pub fn find_block(rom: &[u8]) -> Result<Manifest, Error> {
+        let start = rom
+            .windows(BLOCK_MAGIC.len())
+            .rposition(|window| window == BLOCK_MAGIC)
+            .ok_or(Error::InvalidManifest)?;
+        let mut reader = BlockReader(&rom[start + BLOCK_MAGIC.len()..]);
+        let algorithm_length = reader.read(1)?[0];
+        let algorithm = String::from_utf8(reader.read(algorithm_length.into())?.to_vec())
+            .map_err(|_| Error::InvalidManifest)?;
+        let range_count = reader.read_u16()?;
+        let mut ranges = Vec::with_capacity(range_count.into());
+        for _ in 0..range_count {
+            ranges.push(reader.read_u32()? as usize..reader.read_u32()? as usize);
+        }
+        let digest_length = reader.read(1)?[0];
+        let digest = reader.read(digest_length.into())?.to_vec();
+        Ok(Manifest {
+            algorithm,
+            ranges,
+            digest,
+        })
+    }
+
+    /// Appends a signature block at the end of a ROM.
+    pub fn append_block(&self, rom: &mut Vec<u8>) -> Result<(), Error> {
+        rom.extend(self.to_block()?);
+        Ok(())
+    }
+
+    /// Writes a signature block into a ROM at a given file offset.
+    ///
+    /// The block must not overlap signed ranges, as otherwise writing it
+    /// would invalidate the digest.
+    pub fn embed_block(&self, rom: &mut [u8], offset: usize) -> Result<(), Error> {
+        let block = self.to_block()?;
+        let range = offset..offset + block.len();
+        if range.end > rom.len() {
+            return Err(Error::RangeOutOfBounds(range));
+        }
+        if self.ranges.iter().any(|signed| overlaps(signed, &range)) {
+            return Err(Error::BlockOverlapsSignedRange(range));
+        }
+        rom[range].copy_from_slice(&block);
+        Ok(())
+    }
+}
+
+fn field_length<T: TryFrom<usize>>(field: &'static str, length: usize) -> Result<T, Error> {
+    T::try_from(length).map_err(|_| Error::BlockFieldTooLong(field))
+}
+
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+fn digest_ranges<D: Digest>(
+    rom: &[u8],
+    ranges: &[Range<usize>],
+    digest: &D,
+) -> Result<Vec<u8>, Error> {
+    let parts = ranges
+        .iter()
+        .map(|range| {
+            rom.get(range.clone())
+                .ok_or_else(|| Error::RangeOutOfBounds(range.clone()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(digest.digest(&parts))
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+struct BlockReader<'a>(&'a [u8]);
+
+impl<'a> BlockReader<'a> {
+    fn read(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < length {
+            return Err(Error::InvalidManifest);
+        }
+        let (read, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(read)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.read(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.read(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
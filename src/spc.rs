@@ -0,0 +1,140 @@
+//! SPC state files.
+//!
+//! An `.spc` file is a snapshot of the SNES sound module: registers of the
+//! SPC700, 64 KiB of audio RAM and registers of the DSP. SPC players run
+//! code from the snapshot as is, which makes them an easy way to listen to
+//! sound code without building a whole ROM.
+//!
+//! Files written by this module follow version 0.30 of the format, with
+//! an ID666 tag in its text form.
+
+use assembler::spcblock::Upload;
+
+/// Text at the start of every file.
+const SIGNATURE: &[u8] = b"SNES-SPC700 Sound File Data v0.30";
+
+/// Size of a complete file.
+const FILE_SIZE: usize = 0x1_0200;
+
+/// Offset of audio RAM.
+const RAM: usize = 0x100;
+
+/// Offset of DSP registers.
+const DSP: usize = 0x1_0100;
+
+/// Offset of extra RAM, a copy of the last 64 bytes of audio RAM, which
+/// the boot ROM can hide.
+const EXTRA_RAM: usize = 0x1_01C0;
+
+/// Address of `FLG` DSP register.
+const FLG: usize = 0x6C;
+
+/// Flag of `FLG` disabling writes into the echo buffer.
+const ECHO_WRITE_DISABLE: u8 = 0x20;
+
+/// State of the sound module stored in a file, other than audio RAM.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    /// Program counter, or `None` for the entry point of an upload.
+    pub pc: Option<u16>,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub psw: u8,
+    /// Low byte of the stack pointer, the stack is in page 1.
+    pub sp: u8,
+    /// DSP registers.
+    pub dsp: [u8; 128],
+    /// Song title of the ID666 tag, up to 32 bytes.
+    pub song: String,
+    /// Game title, up to 32 bytes.
+    pub game: String,
+    /// Artist, up to 32 bytes.
+    pub artist: String,
+    /// Name of the person who made the file, up to 16 bytes.
+    pub dumper: String,
+    /// Comments, up to 32 bytes.
+    pub comments: String,
+    /// Seconds to play before fading out, up to 999.
+    pub seconds: u32,
+    /// Length of a fade out in milliseconds, up to 99999.
+    pub fade: u32,
+}
+
+impl Default for Config {
+    /// State after the boot ROM jumps to uploaded code, with writes into
+    /// the echo buffer disabled, so that they don't overwrite the start
+    /// of audio RAM.
+    fn default() -> Config {
+        let mut dsp = [0; 128];
+        dsp[FLG] = ECHO_WRITE_DISABLE;
+        Config {
+            pc: None,
+            a: 0,
+            x: 0,
+            y: 0,
+            psw: 0,
+            sp: 0xEF,
+            dsp,
+            song: String::new(),
+            game: String::new(),
+            artist: String::new(),
+            dumper: String::new(),
+            comments: String::new(),
+            seconds: 0,
+            fade: 0,
+        }
+    }
+}
+
+/// Writes an SPC file with blocks of an upload placed in audio RAM.
+///
+/// Text fields longer than their space in the tag are truncated.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::assembler::spcblock::{Block, Upload};
+/// use mvp::spc::{self, Config};
+///
+/// let block = Block { address: 0x0400, data: vec![0x2F, 0xFE], execute: None };
+/// let upload = Upload::new(vec![block]).unwrap();
+/// let config = Config { song: "Silence".into(), ..Config::default() };
+/// let file = spc::write(&upload, &config);
+/// assert_eq!(file.len(), 0x10200);
+/// assert!(file.starts_with(b"SNES-SPC700 Sound File Data v0.30"));
+/// assert_eq!(file[0x25..0x27], [0x00, 0x04]);
+/// assert_eq!(file[0x2E..0x35], *b"Silence");
+/// assert_eq!(file[0x500..0x502], [0x2F, 0xFE]);
+/// ```
+pub fn write(upload: &Upload, config: &Config) -> Vec<u8> {
+    let mut file = vec![0; FILE_SIZE];
+    file[..SIGNATURE.len()].copy_from_slice(SIGNATURE);
+    // Two bytes of 26, then 26 for a file with a tag, and minor version.
+    file[0x21..0x25].copy_from_slice(&[26, 26, 26, 30]);
+    let pc = config.pc.unwrap_or(upload.entry);
+    file[0x25..0x27].copy_from_slice(&pc.to_le_bytes());
+    file[0x27..0x2C].copy_from_slice(&[config.a, config.x, config.y, config.psw, config.sp]);
+    write_text(&mut file[0x2E..0x4E], &config.song);
+    write_text(&mut file[0x4E..0x6E], &config.game);
+    write_text(&mut file[0x6E..0x7E], &config.dumper);
+    write_text(&mut file[0x7E..0x9E], &config.comments);
+    write_text(&mut file[0xA9..0xAC], &config.seconds.min(999).to_string());
+    write_text(&mut file[0xAC..0xB1], &config.fade.min(99_999).to_string());
+    write_text(&mut file[0xB1..0xD1], &config.artist);
+    for block in &upload.blocks {
+        for (offset, &byte) in block.data.iter().enumerate() {
+            let address = usize::from(block.address.wrapping_add(offset as u16));
+            file[RAM + address] = byte;
+        }
+    }
+    file.copy_within(RAM + 0xFFC0..DSP, EXTRA_RAM);
+    file[DSP..DSP + 0x80].copy_from_slice(&config.dsp);
+    file
+}
+
+/// Writes text into a field, padded with zeros.
+fn write_text(field: &mut [u8], text: &str) {
+    let length = text.len().min(field.len());
+    field[..length].copy_from_slice(&text.as_bytes()[..length]);
+}
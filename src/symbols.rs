@@ -0,0 +1,536 @@
+//! Symbol tables.
+//!
+//! A symbol table holds values of labels and assignments. Programs are
+//! assembled in two passes: the first one defines every symbol, while the
+//! second one evaluates operands, including forward references to symbols
+//! defined later in a program.
+//!
+//! ```asm
+//! Main:
+//!     BRA +       ; forward reference, known in the second pass
+//! .loop:          ; Main.loop
+//!     BRA .loop
+//! +:
+//!     RTS
+//! ```
+//!
+//! Scoped labels belong to the named label they follow, so `.loop` can be
+//! declared once per routine. Relative labels are counted: `-` refers to
+//! the nearest `-` above a reference and `+` to the nearest `+` below it,
+//! with labels of a different length, like `--`, being separate.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use eval::{self, Context};
+use mapping::Mapper;
+use parser::ast::{Expression, Function, Label, Span, VariableName};
+
+/// A pass over a program.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Pass {
+    /// Symbols are being defined, and references to symbols which aren't
+    /// defined yet may be forward references.
+    First,
+    /// Every symbol was defined.
+    Second,
+}
+
+/// What defined a symbol.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SymbolKind {
+    Label,
+    /// `name = value`, which may be redefined.
+    Assignment,
+}
+
+/// A defined label or assignment.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Symbol<'a> {
+    /// Named label a scoped label belongs to.
+    pub scope: Option<&'a str>,
+    pub name: &'a str,
+    pub value: i64,
+    pub kind: SymbolKind,
+}
+
+impl<'a> fmt::Display for Symbol<'a> {
+    /// Writes a name of a symbol, with a scope separated by a dot.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(scope) = self.scope {
+            write!(f, "{}.", scope)?;
+        }
+        f.write_str(self.name)
+    }
+}
+
+/// A symbol failure.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error<'a> {
+    /// Symbol was already defined in the same pass, at a span.
+    Redefined(Label<'a>, Span<'a>),
+    /// Symbol isn't defined anywhere in a program. Scoped labels include
+    /// the named label they were looked up in.
+    Undefined(Label<'a>, Option<&'a str>),
+    /// Expression couldn't be evaluated for another reason.
+    Eval(eval::Error<'a>),
+}
+
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Redefined(ref label, _) => {
+                write!(f, "{} is already defined", label)
+            }
+            Error::Undefined(ref label @ Label::Scoped(_), Some(scope)) => {
+                write!(f, "{} isn't defined in {}", label, scope)
+            }
+            Error::Undefined(ref label @ Label::Scoped(_), None) => write!(
+                f,
+                "{} isn't defined, scoped labels must follow a named label",
+                label
+            ),
+            Error::Undefined(ref label @ Label::Relative(n), _) => write!(
+                f,
+                "there is no {} label {} this reference",
+                label,
+                if n < 0 { "above" } else { "below" }
+            ),
+            Error::Undefined(ref label, _) => write!(f, "{} isn't defined", label),
+            Error::Eval(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl<'a> error::Error for Error<'a> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Eval(ref error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> From<eval::Error<'a>> for Error<'a> {
+    fn from(error: eval::Error<'a>) -> Error<'a> {
+        Error::Eval(error)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Definition<'a> {
+    value: i64,
+    kind: SymbolKind,
+    span: Span<'a>,
+    /// Whether a symbol was defined in the current pass.
+    current: bool,
+}
+
+/// Values of labels and assignments of a program.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::eval::Context;
+/// use mvp::parser::ast::{Label, Span, VariableName};
+/// use mvp::symbols::{Pass, Symbols};
+///
+/// let span = Span::new("");
+/// let mut symbols = Symbols::new();
+/// symbols.define_label(&Label::Named(VariableName("Main")), 0x8000, span).unwrap();
+/// symbols.define_label(&Label::Scoped(VariableName("loop")), 0x8002, span).unwrap();
+/// assert_eq!(symbols.label(&Label::Scoped(VariableName("loop"))), Some(0x8002));
+/// assert_eq!(symbols.label(&Label::Named(VariableName("Other"))), None);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Symbols<'a> {
+    pass: Pass,
+    named: HashMap<&'a str, Definition<'a>>,
+    scoped: HashMap<(&'a str, &'a str), Definition<'a>>,
+    /// Addresses of relative labels, in order of definition.
+    relative: HashMap<i32, Vec<i64>>,
+    /// Number of relative labels defined so far in the current pass.
+    relative_count: HashMap<i32, usize>,
+    /// Named label which scoped labels belong to.
+    scope: Option<&'a str>,
+    program_counter: Option<u32>,
+    /// Files read by built-in functions, by path.
+    files: HashMap<String, Vec<u8>>,
+    mapper: Mapper,
+    /// ROM being patched, see `set_base_rom`.
+    base_rom: Option<&'a [u8]>,
+    /// Address of the end of code in the previous pass, see `set_end`.
+    end: Option<i64>,
+    /// Text assigned with `:=`, by name. Like defines in Asar, text is
+    /// only known after its assignment, so it's cleared by every pass.
+    texts: HashMap<&'a str, String>,
+    /// User-defined functions, by name, see `define_function`.
+    functions: HashMap<&'a str, Function<'a>>,
+}
+
+impl<'a> Default for Symbols<'a> {
+    fn default() -> Symbols<'a> {
+        Symbols::new()
+    }
+}
+
+impl<'a> Symbols<'a> {
+    /// Creates an empty table in the first pass.
+    pub fn new() -> Symbols<'a> {
+        Symbols {
+            pass: Pass::First,
+            named: HashMap::new(),
+            scoped: HashMap::new(),
+            relative: HashMap::new(),
+            relative_count: HashMap::new(),
+            scope: None,
+            program_counter: None,
+            files: HashMap::new(),
+            mapper: Mapper::default(),
+            base_rom: None,
+            end: None,
+            texts: HashMap::new(),
+            functions: HashMap::new(),
+        }
+    }
+
+    /// Sets a mapper used to convert addresses by `snestopc` and
+    /// `pctosnes`.
+    pub fn set_mapper(&mut self, mapper: Mapper) {
+        self.mapper = mapper;
+    }
+
+    /// Provides a file read by built-in functions, like `readfile1`, under
+    /// a path as written in source code, see `eval::file_paths`.
+    pub fn add_file<P: Into<String>>(&mut self, path: P, contents: Vec<u8>) {
+        self.files.insert(path.into(), contents);
+    }
+
+    /// Provides a ROM being patched, which `findpattern` searches.
+    pub fn set_base_rom(&mut self, rom: &'a [u8]) {
+        self.base_rom = Some(rom);
+    }
+
+    /// ROM being patched, if there is one.
+    pub fn base_rom(&self) -> Option<&'a [u8]> {
+        self.base_rom
+    }
+
+    /// Current pass.
+    pub fn pass(&self) -> Pass {
+        self.pass
+    }
+
+    /// Starts another pass from the beginning of a program.
+    ///
+    /// Values from the previous pass are kept, so that forward references
+    /// can be resolved, but symbols can be defined again.
+    pub fn begin_pass(&mut self, pass: Pass) {
+        self.pass = pass;
+        self.scope = None;
+        self.relative_count.clear();
+        self.texts.clear();
+        self.functions.clear();
+        for definition in self.named.values_mut().chain(self.scoped.values_mut()) {
+            definition.current = false;
+        }
+    }
+
+    /// Sets an address where code ends, used by `datasize` of the last
+    /// label. It's known once a pass ends, and is kept for the next one.
+    pub fn set_end(&mut self, address: i64) {
+        self.end = Some(address);
+    }
+
+    /// Sets an address `$` evaluates to, or `None` outside of code.
+    pub fn set_program_counter(&mut self, address: Option<u32>) {
+        self.program_counter = address;
+    }
+
+    /// Defines a label at an address.
+    ///
+    /// Named labels start a new scope for scoped labels. A named or scoped
+    /// label can only be defined once per pass, while relative labels can
+    /// repeat.
+    pub fn define_label(
+        &mut self,
+        label: &Label<'a>,
+        address: i64,
+        span: Span<'a>,
+    ) -> Result<(), Error<'a>> {
+        match *label {
+            Label::Named(VariableName(name)) => {
+                self.scope = Some(name);
+                let previous = self.named.get(name).cloned();
+                let definition = define(label, previous, address, SymbolKind::Label, span)?;
+                self.named.insert(name, definition);
+            }
+            Label::Scoped(VariableName(name)) => {
+                let scope = self.scope.unwrap_or("");
+                let previous = self.scoped.get(&(scope, name)).cloned();
+                let definition = define(label, previous, address, SymbolKind::Label, span)?;
+                self.scoped.insert((scope, name), definition);
+            }
+            Label::Relative(n) => {
+                let count = self.relative_count.entry(n).or_insert(0);
+                let addresses = self.relative.entry(n).or_default();
+                if *count < addresses.len() {
+                    addresses[*count] = address;
+                } else {
+                    addresses.push(address);
+                }
+                *count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Defines a function which expressions can call.
+    ///
+    /// Like text, functions can only be called after their definition,
+    /// so they are cleared by every pass. A function can be defined again,
+    /// with calls using the latest definition.
+    pub fn define_function(&mut self, function: &Function<'a>) {
+        self.functions.insert(function.name.0, function.clone());
+    }
+
+    /// Assigns a value to a name.
+    ///
+    /// Assignments can be repeated, with references seeing the latest
+    /// value, but a name of a label can't be assigned.
+    pub fn assign(
+        &mut self,
+        name: &VariableName<'a>,
+        value: i64,
+        span: Span<'a>,
+    ) -> Result<(), Error<'a>> {
+        let label = Label::Named(name.clone());
+        let previous = self
+            .named
+            .get(name.0)
+            .cloned()
+            .filter(|previous| previous.kind == SymbolKind::Label);
+        let definition = define(&label, previous, value, SymbolKind::Assignment, span)?;
+        self.named.insert(name.0, definition);
+        self.texts.remove(name.0);
+        Ok(())
+    }
+
+    /// Assigns text to a name, replacing its value if it had one.
+    ///
+    /// Like values, text can be assigned again, but a name of a label
+    /// can't be assigned.
+    pub fn assign_string(
+        &mut self,
+        name: &VariableName<'a>,
+        text: String,
+    ) -> Result<(), Error<'a>> {
+        match self.named.get(name.0) {
+            Some(previous) if previous.kind == SymbolKind::Label => {
+                return Err(Error::Redefined(Label::Named(name.clone()), previous.span));
+            }
+            Some(_) => {
+                self.named.remove(name.0);
+            }
+            None => {}
+        }
+        self.texts.insert(name.0, text);
+        Ok(())
+    }
+
+    /// Removes an assignment or text, so that it can't be referred to
+    /// until it's assigned again. Labels can't be removed.
+    pub fn undefine(&mut self, name: &str) {
+        if self
+            .named
+            .get(name)
+            .is_some_and(|definition| definition.kind == SymbolKind::Assignment)
+        {
+            self.named.remove(name);
+        }
+        self.texts.remove(name);
+    }
+
+    /// Evaluates an expression using symbols.
+    ///
+    /// In the first pass, an expression with a label which isn't defined
+    /// yet is a forward reference, and `None` is returned. In the second
+    /// pass such labels are reported as undefined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::parser::ast::{Label, Span, VariableName};
+    /// use mvp::parser::grammar::{self, Input};
+    /// use mvp::symbols::{Error, Pass, Symbols};
+    ///
+    /// let (expression, _) = grammar::parse_expression(Input::new("Later + 1")).unwrap();
+    /// let mut symbols = Symbols::new();
+    /// assert_eq!(symbols.resolve(&expression), Ok(None));
+    /// symbols.define_label(&Label::Named(VariableName("Later")), 0x8010, Span::new("")).unwrap();
+    /// symbols.begin_pass(Pass::Second);
+    /// assert_eq!(symbols.resolve(&expression), Ok(Some(0x8011)));
+    ///
+    /// let (expression, _) = grammar::parse_expression(Input::new("Missing")).unwrap();
+    /// assert_eq!(
+    ///     symbols.resolve(&expression),
+    ///     Err(Error::Undefined(Label::Named(VariableName("Missing")), None)),
+    /// );
+    /// ```
+    pub fn resolve(&self, expression: &Expression<'a>) -> Result<Option<i64>, Error<'a>> {
+        self.resolved(eval::evaluate(expression, self))
+    }
+
+    /// Evaluates an expression to text with `eval::evaluate_string`,
+    /// treating forward references like `resolve`.
+    pub fn resolve_string(&self, expression: &Expression<'a>) -> Result<Option<String>, Error<'a>> {
+        self.resolved(eval::evaluate_string(expression, self))
+    }
+
+    /// Evaluates an expression like `resolve`, in a context wrapping the
+    /// symbols, like `variant::VariantContext`.
+    pub fn resolve_in<C: Context<'a>>(
+        &self,
+        expression: &Expression<'a>,
+        context: &C,
+    ) -> Result<Option<i64>, Error<'a>> {
+        self.resolved(eval::evaluate(expression, context))
+    }
+
+    /// Evaluates an expression to text like `resolve_string`, in a context
+    /// wrapping the symbols.
+    pub fn resolve_string_in<C: Context<'a>>(
+        &self,
+        expression: &Expression<'a>,
+        context: &C,
+    ) -> Result<Option<String>, Error<'a>> {
+        self.resolved(eval::evaluate_string(expression, context))
+    }
+
+    fn resolved<T>(&self, result: Result<T, eval::Error<'a>>) -> Result<Option<T>, Error<'a>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(eval::Error::UndefinedLabel(_)) if self.pass == Pass::First => Ok(None),
+            Err(eval::Error::UndefinedLabel(label)) => {
+                let scope = match label {
+                    Label::Scoped(_) => self.scope,
+                    _ => None,
+                };
+                Err(Error::Undefined(label, scope))
+            }
+            Err(error) => Err(Error::Eval(error)),
+        }
+    }
+
+    /// Lists named and scoped symbols, ordered by value and then by name.
+    ///
+    /// Relative labels have no names, so they aren't included.
+    pub fn symbols(&self) -> Vec<Symbol<'a>> {
+        let named = self.named.iter().map(|(&name, definition)| Symbol {
+            scope: None,
+            name,
+            value: definition.value,
+            kind: definition.kind,
+        });
+        let scoped = self
+            .scoped
+            .iter()
+            .map(|(&(scope, name), definition)| Symbol {
+                scope: Some(scope).filter(|scope| !scope.is_empty()),
+                name,
+                value: definition.value,
+                kind: definition.kind,
+            });
+        let mut symbols: Vec<_> = named.chain(scoped).collect();
+        symbols.sort_by_key(|symbol| (symbol.value, symbol.scope, symbol.name));
+        symbols
+    }
+}
+
+/// Checks whether a symbol can be defined, given its previous definition.
+fn define<'a>(
+    label: &Label<'a>,
+    previous: Option<Definition<'a>>,
+    value: i64,
+    kind: SymbolKind,
+    span: Span<'a>,
+) -> Result<Definition<'a>, Error<'a>> {
+    match previous {
+        Some(previous)
+            if previous.current && !(kind == SymbolKind::Assignment && previous.kind == kind) =>
+        {
+            Err(Error::Redefined(label.clone(), previous.span))
+        }
+        _ => Ok(Definition {
+            value,
+            kind,
+            span,
+            current: true,
+        }),
+    }
+}
+
+impl<'a> Context<'a> for Symbols<'a> {
+    fn label(&self, label: &Label) -> Option<i64> {
+        match *label {
+            Label::Named(VariableName(name)) => self.named.get(name).map(|d| d.value),
+            Label::Scoped(VariableName(name)) => self
+                .scoped
+                .get(&(self.scope.unwrap_or(""), name))
+                .map(|d| d.value),
+            Label::Relative(n) => {
+                let count = self.relative_count.get(&n).cloned().unwrap_or(0);
+                let index = if n < 0 { count.checked_sub(1)? } else { count };
+                self.relative.get(&n)?.get(index).cloned()
+            }
+        }
+    }
+
+    fn function(&self, name: &VariableName) -> Option<&Function<'a>> {
+        self.functions.get(name.0)
+    }
+
+    fn program_counter(&self) -> Option<u32> {
+        self.program_counter
+    }
+
+    fn file(&self, path: &str) -> Option<&[u8]> {
+        self.files.get(path).map(Vec::as_slice)
+    }
+
+    /// Only symbols defined so far in the current pass are defined, so
+    /// that `defined` gives the same result in both passes.
+    fn defined(&self, name: &str) -> bool {
+        self.texts.contains_key(name)
+            || self
+                .named
+                .get(name)
+                .is_some_and(|definition| definition.current)
+    }
+
+    /// Labels defined after a label in code may be unknown in the first
+    /// pass, so the size is only known when a later label already has
+    /// a value, or in the second pass.
+    fn datasize(&self, label: &Label) -> Option<i64> {
+        let start = self.label(label)?;
+        let end = self
+            .named
+            .values()
+            .chain(self.scoped.values())
+            .filter(|definition| definition.kind == SymbolKind::Label)
+            .map(|definition| definition.value)
+            .filter(|&value| value > start)
+            .min()
+            .or(self.end)?;
+        Some(end - start)
+    }
+
+    fn text(&self, name: &str) -> Option<&str> {
+        self.texts.get(name).map(String::as_str)
+    }
+
+    fn mapper(&self) -> Mapper {
+        self.mapper
+    }
+}
@@ -0,0 +1,274 @@
+//! Base ROM variants.
+//!
+//! Games were often released in multiple versions (for instance Japanese,
+//! American and European), with code at different addresses. A single
+//! patch can target all of them by declaring each variant along with its
+//! checksum, and providing a table of addresses for every variant.
+//!
+//! ```asm
+//! romvariant US, $B19ED489
+//! romvariant JP, $A31BEAD4
+//! varianttable SetBrightness, US = $808000, JP = $808010
+//!
+//! if romversion == JP
+//!     ; Japanese specific code
+//! endif
+//! ```
+//!
+//! While evaluating, `romversion` is the index of selected variant, and
+//! names of variants are their indexes. Names of tables resolve to the
+//! address for selected variant. The assembler detects a variant of the
+//! base ROM before assembly, and evaluates expressions in its context.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use eval::{self, Context};
+use mapping::Mapper;
+use parser::ast::{Expression, Function, Label, Spanned, Statement, VariableName};
+use rom;
+
+/// Name evaluating to index of the selected variant.
+const ROM_VERSION: &str = "romversion";
+
+/// A declared base ROM variant.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Variant<'a> {
+    pub name: VariableName<'a>,
+    pub crc: u32,
+    addresses: HashMap<&'a str, u32>,
+}
+
+/// A variant failure.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error<'a> {
+    /// Variant was declared more than once.
+    DuplicateVariant(VariableName<'a>),
+    /// Table refers to a variant which wasn't declared.
+    UnknownVariant(VariableName<'a>),
+    /// Table has more than one address for a variant.
+    DuplicateAddress(VariableName<'a>, VariableName<'a>),
+    /// Checksum or address couldn't be evaluated.
+    Eval(eval::Error<'a>),
+    /// Base ROM doesn't match checksum of any variant.
+    UnrecognizedRom(u32),
+}
+
+impl<'a> fmt::Display for Error<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::DuplicateVariant(ref name) => {
+                write!(f, "variant {} is already declared", name.0)
+            }
+            Error::UnknownVariant(ref name) => write!(f, "variant {} isn't declared", name.0),
+            Error::DuplicateAddress(ref table, ref variant) => write!(
+                f,
+                "table {} has more than one address for {}",
+                table.0, variant.0
+            ),
+            Error::Eval(ref error) => write!(f, "{}", error),
+            Error::UnrecognizedRom(crc) => {
+                write!(
+                    f,
+                    "base ROM with checksum {:08X} isn't a known variant",
+                    crc
+                )
+            }
+        }
+    }
+}
+
+impl<'a> error::Error for Error<'a> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Eval(ref error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> From<eval::Error<'a>> for Error<'a> {
+    fn from(error: eval::Error<'a>) -> Error<'a> {
+        Error::Eval(error)
+    }
+}
+
+/// Variants declared by a program.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Variants<'a> {
+    variants: Vec<Variant<'a>>,
+}
+
+impl<'a> Variants<'a> {
+    /// Collects variant declarations and tables from top-level statements.
+    ///
+    /// Checksums and addresses must be constant expressions. Errors have
+    /// a span of the statement which caused them.
+    pub fn new(
+        statements: &[Spanned<'a, Statement<'a>>],
+    ) -> Result<Variants<'a>, Spanned<'a, Error<'a>>> {
+        let mut variants = Variants::default();
+        for statement in statements {
+            if let Statement::RomVariant(ref name, ref crc) = **statement {
+                variants.declare(name, crc).map_err(|node| Spanned {
+                    node,
+                    span: statement.span,
+                })?;
+            }
+        }
+        for statement in statements {
+            if let Statement::VariantTable(ref name, ref entries) = **statement {
+                variants.add_table(name, entries).map_err(|node| Spanned {
+                    node,
+                    span: statement.span,
+                })?;
+            }
+        }
+        Ok(variants)
+    }
+
+    fn declare(&mut self, name: &VariableName<'a>, crc: &Expression<'a>) -> Result<(), Error<'a>> {
+        if self.position(name.0).is_some() {
+            return Err(Error::DuplicateVariant(name.clone()));
+        }
+        self.variants.push(Variant {
+            name: name.clone(),
+            crc: eval::truncate(eval::evaluate(crc, &no_labels)?, 4)?,
+            addresses: HashMap::new(),
+        });
+        Ok(())
+    }
+
+    fn add_table(
+        &mut self,
+        name: &VariableName<'a>,
+        entries: &[(VariableName<'a>, Expression<'a>)],
+    ) -> Result<(), Error<'a>> {
+        for (variant_name, address) in entries {
+            let index = self
+                .position(variant_name.0)
+                .ok_or_else(|| Error::UnknownVariant(variant_name.clone()))?;
+            let address = eval::truncate(eval::evaluate(address, &no_labels)?, 4)?;
+            let addresses = &mut self.variants[index].addresses;
+            if addresses.insert(name.0, address).is_some() {
+                return Err(Error::DuplicateAddress(name.clone(), variant_name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Declared variants, in declaration order.
+    pub fn variants(&self) -> &[Variant<'a>] {
+        &self.variants
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.variants.iter().position(|v| v.name.0 == name)
+    }
+
+    /// Finds a variant by name.
+    pub fn get(&self, name: &str) -> Option<&Variant<'a>> {
+        self.position(name).map(|index| &self.variants[index])
+    }
+
+    /// Identifies a variant of a base ROM by its checksum.
+    ///
+    /// This validates a base ROM before patching it. When no variants were
+    /// declared, any ROM is accepted and `None` is returned.
+    pub fn detect(&self, rom: &[u8]) -> Result<Option<&Variant<'a>>, Error<'a>> {
+        if self.variants.is_empty() {
+            return Ok(None);
+        }
+        let crc = rom::crc32(rom);
+        match self.variants.iter().find(|variant| variant.crc == crc) {
+            Some(variant) => Ok(Some(variant)),
+            None => Err(Error::UnrecognizedRom(crc)),
+        }
+    }
+
+    /// Creates an evaluation context for a selected variant.
+    ///
+    /// Names not related to variants are looked up in `parent` context.
+    pub fn context<'v, C: Context<'a>>(
+        &'v self,
+        selected: &'v Variant<'a>,
+        parent: &'v C,
+    ) -> VariantContext<'v, 'a, C> {
+        VariantContext {
+            variants: self,
+            selected,
+            parent,
+        }
+    }
+}
+
+fn no_labels(_: &Label) -> Option<i64> {
+    None
+}
+
+/// An evaluation context for a selected base ROM variant.
+#[derive(Debug)]
+pub struct VariantContext<'v, 'a: 'v, C: 'v> {
+    variants: &'v Variants<'a>,
+    selected: &'v Variant<'a>,
+    parent: &'v C,
+}
+
+impl<'v, 'a, C: Context<'a>> VariantContext<'v, 'a, C> {
+    /// Value of a name related to variants, which shadows other symbols.
+    fn value(&self, name: &str) -> Option<i64> {
+        if name == ROM_VERSION {
+            return self
+                .variants
+                .position(self.selected.name.0)
+                .map(|i| i as i64);
+        }
+        if let Some(index) = self.variants.position(name) {
+            return Some(index as i64);
+        }
+        self.selected
+            .addresses
+            .get(name)
+            .map(|&address| address.into())
+    }
+}
+
+impl<'v, 'a, C: Context<'a>> Context<'a> for VariantContext<'v, 'a, C> {
+    fn label(&self, label: &Label) -> Option<i64> {
+        if let Label::Named(VariableName(name)) = *label {
+            if let Some(value) = self.value(name) {
+                return Some(value);
+            }
+        }
+        self.parent.label(label)
+    }
+
+    fn function(&self, name: &VariableName) -> Option<&Function<'a>> {
+        self.parent.function(name)
+    }
+
+    fn program_counter(&self) -> Option<u32> {
+        self.parent.program_counter()
+    }
+
+    fn file(&self, path: &str) -> Option<&[u8]> {
+        self.parent.file(path)
+    }
+
+    fn defined(&self, name: &str) -> bool {
+        self.value(name).is_some() || self.parent.defined(name)
+    }
+
+    fn mapper(&self) -> Mapper {
+        self.parent.mapper()
+    }
+
+    fn datasize(&self, label: &Label) -> Option<i64> {
+        self.parent.datasize(label)
+    }
+
+    fn text(&self, name: &str) -> Option<&str> {
+        self.parent.text(name)
+    }
+}
@@ -0,0 +1,200 @@
+//! Warnings about code which assembles, but may not do what was meant.
+//!
+//! Warnings belong to categories, and the level of a category decides
+//! whether its warnings are ignored, reported along with output, or fail
+//! assembly. Levels are set for a whole assembly with `Levels`, and
+//! changed from a point in code on with a directive:
+//!
+//! ```asm
+//! warnings deny implicit-dp
+//! LDA Value   ; fails when Value is below $100
+//! ```
+//!
+//! Categories which used to be errors, like `unknown-state`, are denied
+//! by default, and allowing them makes the assembler pick a fallback
+//! instead.
+
+use std::fmt;
+
+use parser::ast::Span;
+
+/// A kind of warnings.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Category {
+    /// Direct page addressing chosen by the value of an operand which
+    /// isn't a literal, as in `LDA Value` (`implicit-dp`).
+    ImplicitDirectPage,
+    /// Immediate operand whose register width isn't known, which is
+    /// assumed to be 8-bit (`unknown-state`).
+    UnknownState,
+    /// Named label which isn't referred to (`unused-label`).
+    UnusedLabel,
+    /// Operand which doesn't fit in its instruction and loses its higher
+    /// bytes (`truncated-operand`).
+    TruncatedOperand,
+    /// Absolute address outside of the bank assumed with `bank`, or of the
+    /// bank of code for jumps (`bank-mismatch`).
+    BankMismatch,
+    /// Routine using more stack than its budget, see `analysis::stack`
+    /// (`stack-usage`).
+    StackUsage,
+    /// Multi-byte variable written one byte at a time by a routine called
+    /// from an interrupt handler and the main loop, see
+    /// `analysis::interrupt` (`interrupt-safety`).
+    InterruptSafety,
+    /// Label reached with an 8-bit register from one place and a 16-bit
+    /// one from another, or with a width code after it isn't assembled
+    /// for, see `analysis::flags` (`width-conflict`).
+    WidthConflict,
+    /// Named label of a macro which is also a label of a program, see
+    /// `assembler::macros::captures` (`macro-capture`).
+    MacroCapture,
+}
+
+impl Category {
+    /// Every category, in declaration order.
+    pub const ALL: &'static [Category] = &[
+        Category::ImplicitDirectPage,
+        Category::UnknownState,
+        Category::UnusedLabel,
+        Category::TruncatedOperand,
+        Category::BankMismatch,
+        Category::StackUsage,
+        Category::InterruptSafety,
+        Category::WidthConflict,
+        Category::MacroCapture,
+    ];
+
+    /// Finds a category by its name, ignoring case.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mvp::warning::Category;
+    ///
+    /// assert_eq!(Category::from_name("Unused-Label"), Some(Category::UnusedLabel));
+    /// assert_eq!(Category::from_name("unused"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<Category> {
+        Category::ALL
+            .iter()
+            .cloned()
+            .find(|category| category.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Name of a category, as used in directives and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::ImplicitDirectPage => "implicit-dp",
+            Category::UnknownState => "unknown-state",
+            Category::UnusedLabel => "unused-label",
+            Category::TruncatedOperand => "truncated-operand",
+            Category::BankMismatch => "bank-mismatch",
+            Category::StackUsage => "stack-usage",
+            Category::InterruptSafety => "interrupt-safety",
+            Category::WidthConflict => "width-conflict",
+            Category::MacroCapture => "macro-capture",
+        }
+    }
+
+    /// Level of a category when none is set.
+    pub fn default_level(self) -> Level {
+        match self {
+            Category::ImplicitDirectPage | Category::UnusedLabel => Level::Allow,
+            Category::UnknownState | Category::TruncatedOperand | Category::BankMismatch => {
+                Level::Deny
+            }
+            // These analyses only run when they are configured.
+            Category::StackUsage | Category::InterruptSafety => Level::Warn,
+            Category::WidthConflict | Category::MacroCapture => Level::Warn,
+        }
+    }
+}
+
+/// What happens with warnings of a category.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Level {
+    /// Warnings are ignored.
+    Allow,
+    /// Warnings are reported, see `Output::warnings`.
+    Warn,
+    /// Warnings fail assembly.
+    Deny,
+}
+
+impl Level {
+    /// Finds a level by its name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Level> {
+        Some(match &*name.to_ascii_lowercase() {
+            "allow" => Level::Allow,
+            "warn" => Level::Warn,
+            "deny" => Level::Deny,
+            _ => return None,
+        })
+    }
+
+    /// Name of a level, as used in directives.
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Allow => "allow",
+            Level::Warn => "warn",
+            Level::Deny => "deny",
+        }
+    }
+}
+
+/// Levels of all categories.
+///
+/// # Examples
+///
+/// ```
+/// use mvp::warning::{Category, Level, Levels};
+///
+/// let mut levels = Levels::default();
+/// assert_eq!(levels.get(Category::UnusedLabel), Level::Allow);
+/// levels.set(Category::UnusedLabel, Level::Warn);
+/// assert_eq!(levels.get(Category::UnusedLabel), Level::Warn);
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Levels {
+    levels: [Level; Category::ALL.len()],
+}
+
+impl Levels {
+    /// Level of a category.
+    pub fn get(&self, category: Category) -> Level {
+        self.levels[category as usize]
+    }
+
+    /// Changes the level of a category.
+    pub fn set(&mut self, category: Category, level: Level) {
+        self.levels[category as usize] = level;
+    }
+}
+
+impl Default for Levels {
+    fn default() -> Levels {
+        let mut levels = Levels {
+            levels: [Level::Allow; Category::ALL.len()],
+        };
+        for &category in Category::ALL {
+            levels.set(category, category.default_level());
+        }
+        levels
+    }
+}
+
+/// A warning about a statement.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Warning<'a> {
+    pub category: Category,
+    /// Statement a warning is about.
+    pub span: Span<'a>,
+    pub message: String,
+}
+
+impl<'a> fmt::Display for Warning<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{}]", self.message, self.category.name())
+    }
+}
@@ -0,0 +1,248 @@
+//! Bindings for JavaScript, with the `wasm-bindgen` feature.
+//!
+//! Browser tools can assemble code without a server by linking this crate
+//! into a WebAssembly module. There is no file system in a browser, so
+//! files are given to `Sources` by a caller, and `incsrc` finds files
+//! among them.
+//!
+//! Results which aren't bytes are JSON strings, to be read with
+//! `JSON.parse`. Offsets in source code count UTF-16 code units, like
+//! indices of JavaScript strings.
+//!
+//! ```js
+//! const { parse, Sources } = await import("./mvp.js");
+//! const sources = new Sources();
+//! sources.insert("main.asm", 'incsrc "lib.asm"\nJSR Wait\n');
+//! sources.insert("lib.asm", "Wait:\nWAI\nRTS\n");
+//! const code = sources.assemble("main.asm", 0x8000);
+//! ```
+
+use std::io;
+use std::path::Path;
+
+use serde_json::Value;
+use wasm_bindgen::prelude::*;
+
+use assembler::{Assembler, OutputKind};
+use include::{FileSystem, Overlay};
+use parser::grammar::{self, Input};
+use parser::semantic::{self, TokenKind};
+use project::Diagnostic;
+
+/// Path of a source passed to `assemble` and `diagnostics`.
+const MAIN: &str = "main.asm";
+
+/// Parses source code, returning its highlighted tokens and syntax
+/// errors.
+///
+/// Tokens have a `kind`, like `"mnemonic"` or `"label"`, see
+/// `parser::semantic::TokenKind`, and errors have a `message`. Both have
+/// `start` and `end` offsets.
+#[wasm_bindgen]
+pub fn parse(source: &str) -> String {
+    let mut offsets = Utf16Offsets::new(source);
+    let tokens: Vec<_> = semantic::semantic_tokens(source)
+        .into_iter()
+        .map(|(span, kind)| {
+            let range = span.range(source).unwrap();
+            json!({
+                "kind": kind_name(kind),
+                "start": offsets.get(range.start),
+                "end": offsets.get(range.end),
+            })
+        })
+        .collect();
+    let mut offsets = Utf16Offsets::new(source);
+    let (_, errors) = grammar::program_with_recovery(Input::new(source));
+    let errors: Vec<_> = errors
+        .iter()
+        .map(|error| {
+            let range = error.span.range(source).unwrap();
+            json!({
+                "message": error.to_string(),
+                "start": offsets.get(range.start),
+                "end": offsets.get(range.end),
+            })
+        })
+        .collect();
+    json!({ "tokens": tokens, "errors": errors }).to_string()
+}
+
+/// Assembles a single source file starting at `origin`, see
+/// `Sources::assemble`.
+#[wasm_bindgen]
+pub fn assemble(source: &str, origin: u32) -> Result<Vec<u8>, JsError> {
+    single(source).assemble(MAIN, origin)
+}
+
+/// Finds errors and warnings of a single source file, see
+/// `Sources::diagnostics`.
+#[wasm_bindgen]
+pub fn diagnostics(source: &str, origin: u32) -> String {
+    single(source).diagnostics(MAIN, origin)
+}
+
+fn single(source: &str) -> Sources {
+    let mut sources = Sources::new();
+    sources.insert(MAIN, source);
+    sources
+}
+
+/// Source files of a project, by path.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct Sources {
+    files: Overlay<NoFiles>,
+}
+
+#[wasm_bindgen]
+impl Sources {
+    /// Creates a project without files.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Sources {
+        Sources::default()
+    }
+
+    /// Sets contents of a file.
+    pub fn insert(&mut self, path: &str, contents: &str) {
+        self.files.insert(path, contents);
+    }
+
+    /// Removes a file, returning whether it existed.
+    pub fn remove(&mut self, path: &str) -> bool {
+        self.files.remove(path).is_some()
+    }
+
+    /// Assembles a root file and files it includes, starting at `origin`
+    /// with 8-bit registers, and returns assembled bytes.
+    ///
+    /// Errors are thrown as an `Error` with a message listing all of them.
+    pub fn assemble(&self, root: &str, origin: u32) -> Result<Vec<u8>, JsError> {
+        self.build(root, origin)
+            .map(|(code, _)| code)
+            .map_err(|errors| {
+                let messages: Vec<_> = errors.iter().map(ToString::to_string).collect();
+                JsError::new(&messages.join("\n"))
+            })
+    }
+
+    /// Assembles a root file like `assemble`, returning its errors, or
+    /// warnings if there are none.
+    ///
+    /// Every diagnostic has a `severity`, either `"error"` or `"warning"`,
+    /// and a `message`. A `path`, a `line` and a `column`, counted from
+    /// one, are `null` for errors without a location.
+    pub fn diagnostics(&self, root: &str, origin: u32) -> String {
+        let (diagnostics, severity) = match self.build(root, origin) {
+            Ok((_, warnings)) => (warnings, "warning"),
+            Err(errors) => (errors, "error"),
+        };
+        let diagnostics: Vec<_> = diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                let (path, line, column) = match diagnostic.location {
+                    Some((path, line, column)) => (
+                        Value::from(path.to_string_lossy()),
+                        Value::from(line),
+                        Value::from(column),
+                    ),
+                    None => (Value::Null, Value::Null, Value::Null),
+                };
+                json!({
+                    "severity": severity,
+                    "message": diagnostic.message,
+                    "path": path,
+                    "line": line,
+                    "column": column,
+                })
+            })
+            .collect();
+        Value::from(diagnostics).to_string()
+    }
+}
+
+impl Sources {
+    /// Assembles a root file, returning assembled bytes with warnings, or
+    /// errors.
+    fn build(
+        &self,
+        root: &str,
+        origin: u32,
+    ) -> Result<(Vec<u8>, Vec<Diagnostic>), Vec<Diagnostic>> {
+        let mut assembler = Assembler::builder()
+            .file_system(self.files.clone())
+            .root(root)
+            .origin(origin)
+            .output(OutputKind::Raw)
+            .build();
+        if let Err(error) = assembler.load() {
+            return Err(vec![Diagnostic {
+                location: None,
+                message: error.to_string(),
+            }]);
+        }
+        let project = assembler.project();
+        match assembler.assemble() {
+            Ok(output) => Ok((output.data.clone(), project.warnings(&output.warnings))),
+            Err(error) => Err(project.diagnostics(&error)),
+        }
+    }
+}
+
+/// File system without any files, under files of `Sources`.
+#[derive(Copy, Clone, Debug, Default)]
+struct NoFiles;
+
+impl FileSystem for NoFiles {
+    fn read(&self, _: &Path) -> io::Result<String> {
+        Err(io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+
+    fn exists(&self, _: &Path) -> bool {
+        false
+    }
+}
+
+fn kind_name(kind: TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Mnemonic => "mnemonic",
+        TokenKind::Directive => "directive",
+        TokenKind::Label => "label",
+        TokenKind::Define => "define",
+        TokenKind::Macro => "macro",
+        TokenKind::Function => "function",
+        TokenKind::Parameter => "parameter",
+        TokenKind::Number => "number",
+        TokenKind::String => "string",
+        TokenKind::Operator => "operator",
+        TokenKind::Comment => "comment",
+        TokenKind::Identifier => "identifier",
+    }
+}
+
+/// Converts increasing byte offsets into UTF-16 offsets, without counting
+/// from the start every time.
+struct Utf16Offsets<'a> {
+    source: &'a str,
+    byte: usize,
+    utf16: usize,
+}
+
+impl<'a> Utf16Offsets<'a> {
+    fn new(source: &'a str) -> Utf16Offsets<'a> {
+        Utf16Offsets {
+            source,
+            byte: 0,
+            utf16: 0,
+        }
+    }
+
+    fn get(&mut self, byte: usize) -> usize {
+        if byte < self.byte {
+            *self = Utf16Offsets::new(self.source);
+        }
+        self.utf16 += self.source[self.byte..byte].encode_utf16().count();
+        self.byte = byte;
+        self.utf16
+    }
+}
@@ -0,0 +1,35 @@
+#![cfg(feature = "arbitrary")]
+
+extern crate mvp;
+extern crate proptest;
+
+use mvp::assembler::flags::State;
+use mvp::assembler::output;
+use mvp::parser::ast::{Expression, Opcode, Spanned, Statement};
+use mvp::parser::grammar;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn expressions_roundtrip(expression in any::<Expression>()) {
+        let text = expression.to_string();
+        let (parsed, rest) = grammar::parse_expression(&*text).unwrap();
+        prop_assert_eq!(parsed, expression);
+        prop_assert!(rest.is_empty(), "{}", text);
+    }
+
+    #[test]
+    fn opcodes_roundtrip(opcode in any::<Opcode>()) {
+        let text = opcode.to_string();
+        let (parsed, rest) = grammar::parse_statement(&*text).unwrap();
+        prop_assert_eq!(parsed, Statement::Opcode(opcode));
+        prop_assert!(rest.is_empty(), "{}", text);
+    }
+
+    #[test]
+    fn assembling_never_panics(statements in vec(any::<Statement>(), 0..8)) {
+        let statements = statements.into_iter().map(Spanned::from).collect();
+        let _ = output::assemble(statements, 0x8000, State::new(true, true));
+    }
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "arena")]
+
+extern crate mvp;
+
+use mvp::parser::arena::{self, Arena};
+use mvp::parser::grammar;
+
+#[test]
+fn same_as_boxed_expressions() {
+    let sources = [
+        "1",
+        "2 + 3 * 4",
+        "((1 + 2) * 3) < $10",
+        "-a - -(b)",
+        "read1($8000, 2) + pc()",
+        "\"text\" + $",
+        "f (1) + 2",
+        "2 + * 3",
+    ];
+    let arena = Arena::new();
+    for source in &sources {
+        let (parsed, rest) = arena::parse_expression(&arena, *source).unwrap();
+        let (boxed, boxed_rest) = grammar::parse_expression(*source).unwrap();
+        assert_eq!(parsed.to_expression(), boxed, "{}", source);
+        assert_eq!(rest, boxed_rest, "{}", source);
+    }
+}
+
+#[test]
+fn large_expressions() {
+    let source = vec!["(1 + 2) * 3"; 200].join(" - ");
+    let arena = Arena::new();
+    let (parsed, rest) = arena::parse_expression(&arena, source.as_str()).unwrap();
+    assert_eq!(
+        parsed.to_expression(),
+        grammar::parse_expression(source.as_str()).unwrap().0
+    );
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn syntax_errors() {
+    let arena = Arena::new();
+    assert_eq!(
+        arena::parse_expression(&arena, "(1 + 2").unwrap_err(),
+        grammar::parse_expression("(1 + 2").unwrap_err()
+    );
+    assert!(arena::parse_expression(&arena, "(".repeat(100_000).as_str()).is_err());
+}
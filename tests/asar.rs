@@ -0,0 +1,98 @@
+extern crate mvp;
+
+use mvp::asar::AsarVersion;
+use mvp::assembler::flags::State;
+use mvp::assembler::output::{self, Error, Output};
+use mvp::parser::ast::Statement;
+use mvp::parser::grammar::{parse_program, parse_statement, Precedence};
+
+fn assemble(source: &str) -> Result<Output<'_>, Error<'_>> {
+    output::assemble(
+        parse_program(source).unwrap(),
+        0x808000,
+        State::new(true, true),
+    )
+}
+
+fn feature(source: &str) -> (&str, String) {
+    match assemble(source) {
+        Err(error @ Error::AsarFeature(..)) => (error.span().fragment(), error.to_string()),
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn directives() {
+    let version = AsarVersion {
+        major: 1,
+        minor: 81,
+    };
+    assert_eq!(
+        parse_statement("asar 1.81").unwrap().0,
+        Statement::Asar(version)
+    );
+    assert_eq!(
+        parse_statement("MATH PRI on").unwrap().0,
+        Statement::Precedence(Precedence::Standard)
+    );
+}
+
+#[test]
+fn precedence() {
+    let source = "
+        assert 2+1*2 == 4
+        asar 1.81
+        !a = 2+1*2
+        assert !a == 6
+        math pri on
+        !b #= 2+1*2
+        assert !b == 4
+        math pri off
+        assert 2+1*2 == 6
+    ";
+    assert!(assemble(source).is_ok());
+}
+
+#[test]
+fn unsupported_features() {
+    assert_eq!(
+        feature("asar 1.81\n  org $008000 ; reset\n"),
+        (
+            "org $008000",
+            "Asar feature `org` isn't supported".to_string()
+        )
+    );
+    assert_eq!(
+        feature("asar 1.81\nFreeCode cleaned\n").1,
+        "Asar feature `freecode` isn't supported"
+    );
+    assert_eq!(
+        feature("asar 1.81\n!register = $4200,x\n").1,
+        "Asar feature `!define = text` isn't supported"
+    );
+    assert_eq!(
+        feature("asar 1.81\n!speed ?= 2\n").1,
+        "Asar feature `!define ?= text` isn't supported"
+    );
+    match assemble("org $008000\n") {
+        Err(Error::Encode(..)) => {}
+        result => panic!("{:?}", result),
+    }
+    // Mnemonics aren't Asar directives of the same name.
+    assert_eq!(
+        assemble("asar 1.81\nREP #$20\n").unwrap().data,
+        [0xC2, 0x20]
+    );
+}
+
+#[test]
+fn versions() {
+    assert!(assemble("asar 1.37\nasar 1.8\n").is_ok());
+    match assemble("asar 1.9\n") {
+        Err(error @ Error::AsarVersion(..)) => assert_eq!(
+            error.to_string(),
+            "code requires Asar 1.90, mvp is compatible with Asar 1.81 and older"
+        ),
+        result => panic!("{:?}", result),
+    }
+}
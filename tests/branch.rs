@@ -0,0 +1,118 @@
+extern crate mvp;
+
+use mvp::assembler::branch::{self, Error, LongJump};
+use mvp::parser::ast::{Opcode, OpcodeMode, Spanned, Statement};
+use mvp::parser::grammar::parse_program;
+
+fn size(opcode: &Opcode) -> Option<u32> {
+    match opcode.mode {
+        OpcodeMode::Implied => Some(1),
+        OpcodeMode::Address => Some(3),
+        _ => None,
+    }
+}
+
+fn rewrite(source: &str, jump: LongJump) -> Vec<Spanned<'_, Statement<'_>>> {
+    let statements = parse_program(source).unwrap();
+    branch::rewrite_long_branches(statements, jump, size).unwrap()
+}
+
+#[test]
+fn branches_in_range_are_kept() {
+    let source = format!(
+        "Start:\n{}BNE Start\nBRA End\n{}End:\n",
+        "NOP\n".repeat(126),
+        "NOP\n".repeat(127)
+    );
+    assert_eq!(
+        rewrite(&source, LongJump::Brl),
+        parse_program(source.as_str()).unwrap()
+    );
+}
+
+#[test]
+fn branches_out_of_range_are_rewritten() {
+    let nops = "NOP\n".repeat(127);
+    let source = format!("Start:\n{}BNE Start\nBRA End\n{}NOP\nEnd:\n", nops, nops);
+    let expected = format!(
+        "Start:\n{}BEQ $+5\nBRL Start\nBRL End\n{}NOP\nEnd:\n",
+        nops, nops
+    );
+    assert_eq!(
+        rewrite(&source, LongJump::Brl),
+        parse_program(expected.as_str()).unwrap()
+    );
+    let expected = expected.replace("BRL", "JMP");
+    assert_eq!(
+        rewrite(&source, LongJump::Jmp),
+        parse_program(expected.as_str()).unwrap()
+    );
+}
+
+#[test]
+fn rewritten_branches_can_push_others_out_of_range() {
+    let source = format!(
+        "BEQ Near\nBNE Far\n{}Near:\n{}Far:\n",
+        "NOP\n".repeat(124),
+        "NOP\n".repeat(200)
+    );
+    let expected = format!(
+        "BNE $+5\nBRL Near\nBEQ $+5\nBRL Far\n{}Near:\n{}Far:\n",
+        "NOP\n".repeat(124),
+        "NOP\n".repeat(200)
+    );
+    assert_eq!(
+        rewrite(&source, LongJump::Brl),
+        parse_program(expected.as_str()).unwrap()
+    );
+}
+
+#[test]
+fn scoped_and_relative_labels() {
+    let nops = "NOP\n".repeat(130);
+    let source = format!(
+        "First:\n.loop:\n{}BCC .loop\nSecond:\n.loop:\nBCS .loop\n-:\n{}BVS -\n",
+        nops, nops
+    );
+    let expected = format!(
+        "First:\n.loop:\n{}BCS $+5\nBRL .loop\nSecond:\n.loop:\nBCS .loop\n-:\n{}BVC $+5\nBRL -\n",
+        nops, nops
+    );
+    assert_eq!(
+        rewrite(&source, LongJump::Brl),
+        parse_program(expected.as_str()).unwrap()
+    );
+}
+
+#[test]
+fn layout_errors() {
+    let statements = parse_program("LDA #1\nBRA Start\nStart:\n").unwrap();
+    let error = branch::rewrite_long_branches(statements, LongJump::Brl, size).unwrap_err();
+    assert_eq!(
+        error,
+        Error::UnknownSize(parse_program("LDA #1").unwrap()[0].span)
+    );
+    assert_eq!(error.span().fragment(), "LDA #1");
+}
+
+#[test]
+fn if_blocks_and_expansions() {
+    let nops = "NOP\n".repeat(130);
+    // Both if blocks count, so a branch in the second one is rewritten too.
+    let source = format!("Start:\nif !a\n{}BEQ Start\nelse\nBNE Start\nendif\n", nops);
+    let expected = format!(
+        "Start:\nif !a\n{}BNE $+5\nBRL Start\nelse\nBEQ $+5\nBRL Start\nendif\n",
+        nops
+    );
+    assert_eq!(
+        rewrite(&source, LongJump::Brl),
+        parse_program(expected.as_str()).unwrap()
+    );
+    let source = format!("Start:\n{}", nops);
+    let mut statements = parse_program(source.as_str()).unwrap();
+    let expansion = parse_program("BRA Start\nBRA Next\nNext:\n").unwrap();
+    statements.push(Statement::Expansion(expansion).into());
+    let rewritten = branch::rewrite_long_branches(statements, LongJump::Jmp, size).unwrap();
+    let expected = parse_program("JMP Start\nBRA Next\nNext:\n").unwrap();
+    assert_eq!(*rewritten[131], Statement::Expansion(expected));
+}
@@ -0,0 +1,123 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+
+/// Creates an empty directory for a test.
+fn directory(name: &str) -> PathBuf {
+    let directory = env::temp_dir().join(format!("mvp-cli-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// Runs mvp in a directory.
+fn mvp(directory: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mvp"))
+        .args(args)
+        .current_dir(directory)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn reject_aliases() {
+    let directory = directory("aliases");
+    fs::write(directory.join("main.asm"), "ina\n").unwrap();
+    let output = mvp(&directory, &["main.asm"]);
+    assert!(output.status.success(), "{:?}", output);
+    let output = mvp(&directory, &["--reject-aliases", "main.asm"]);
+    fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: main.asm:1:1: INA is an alias, use INC A\n"
+    );
+}
+
+#[test]
+fn syntax_errors() {
+    let directory = directory("syntax");
+    fs::write(directory.join("pe.asm"), "LDA ($19,,x)\nNOP\nSTA #\n").unwrap();
+    let output = mvp(&directory, &["pe.asm"]);
+    fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: pe.asm:1:10: expected an expression, found `,x)`\n\
+         error: pe.asm:3:6: expected an expression, found end of line\n"
+    );
+}
+
+#[test]
+fn undefined_labels() {
+    let directory = directory("undefined");
+    fs::write(directory.join("main.asm"), "NOP\nJMP Missing\nJSL Other\n").unwrap();
+    let output = mvp(&directory, &["main.asm"]);
+    fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: main.asm:2:1: `JMP Missing` uses a label which is not defined\n\
+         error: main.asm:3:1: `JSL Other` uses a label which is not defined\n"
+    );
+}
+
+#[test]
+fn files_read_by_functions() {
+    let directory = directory("readfile");
+    fs::create_dir(directory.join("src")).unwrap();
+    fs::write(directory.join("src/data.bin"), [0x12, 0x34, 0x56]).unwrap();
+    fs::write(
+        directory.join("src/main.asm"),
+        "LDA #readfile1(\"data.bin\", 1)\nLDX #filesize(\"data.bin\")\n\
+         LDY #canread(\"data.bin\")+canread(\"missing.bin\")\n",
+    )
+    .unwrap();
+    let output = mvp(&directory, &["src/main.asm", "-o", "main.sfc"]);
+    assert!(output.status.success(), "{:?}", output);
+    let rom = fs::read(directory.join("main.sfc")).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(rom[..6], [0xA9, 0x34, 0xA2, 0x03, 0xA0, 0x01]);
+}
+
+#[test]
+fn manifests() {
+    let directory = directory("manifest");
+    fs::write(directory.join("main.asm"), "LDA #$12\nRTS\n").unwrap();
+    let output = mvp(
+        &directory,
+        &["--manifest", "main.json", "--embed-manifest", "main.asm"],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let verified = mvp(&directory, &["verify", "main.sfc", "main.json"]);
+    let embedded = mvp(&directory, &["verify", "main.sfc"]);
+    let mut rom = fs::read(directory.join("main.sfc")).unwrap();
+    rom[1] = 0x34;
+    fs::write(directory.join("main.sfc"), rom).unwrap();
+    let tampered = mvp(&directory, &["verify", "main.sfc", "main.json"]);
+    fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(String::from_utf8_lossy(&verified.stdout), "main.sfc: OK\n");
+    assert_eq!(String::from_utf8_lossy(&embedded.stdout), "main.sfc: OK\n");
+    assert_eq!(tampered.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&tampered.stdout),
+        "main.sfc: digest mismatch\n"
+    );
+}
+
+#[test]
+fn init_and_build() {
+    let directory = directory("init");
+    let output = mvp(&directory, &["init", "."]);
+    assert!(output.status.success(), "{:?}", output);
+    let name = directory.file_name().unwrap().to_str().unwrap();
+    let rom = directory.join("build").join(format!("{}.sfc", name));
+    let initial = fs::read(&rom).unwrap();
+    fs::remove_dir_all(directory.join("build")).unwrap();
+    let output = mvp(&directory, &["build"]);
+    let built = fs::read(&rom);
+    fs::remove_dir_all(&directory).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(built.unwrap(), initial);
+}
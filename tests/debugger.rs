@@ -0,0 +1,118 @@
+extern crate mvp;
+extern crate serde_json;
+
+use mvp::assembler::flags::State;
+use mvp::assembler::output;
+use mvp::debugger;
+use mvp::include::Source;
+use mvp::parser::grammar::parse_program;
+
+#[test]
+fn bsnes_plus() {
+    let source = "\
+Buffer = $7E2000
+Count = 16
+Offset = -2
+Main:
+    LDA #$00
+.loop:
+    BRA .loop
+-:
+    RTS
+";
+    let output = output::assemble(
+        parse_program(source).unwrap(),
+        0xC08000,
+        State::new(true, true),
+    )
+    .unwrap();
+    let file = debugger::bsnes_plus(
+        &output.symbols,
+        &[(0xC08002, "wait \"forever\"\n"), (0xC08004, "")],
+    );
+    assert_eq!(
+        file,
+        "\
+#SNES65816
+
+[SYMBOL]
+00:0010 Count ANY 1
+7E:2000 Buffer ANY 1
+C0:8000 Main ANY 1
+C0:8002 Main.loop ANY 1
+
+[COMMENT]
+C0:8002 \"wait 'forever'\"
+C0:8004 \"\"
+"
+    );
+}
+
+#[test]
+fn empty() {
+    assert_eq!(
+        debugger::bsnes_plus(&[], &[]),
+        "#SNES65816\n\n[SYMBOL]\n\n[COMMENT]\n"
+    );
+}
+
+#[test]
+fn source_map() {
+    let sources = [
+        Source {
+            path: "other.asm".into(),
+            contents: String::new(),
+        },
+        Source {
+            path: "main.asm".into(),
+            contents: "Main:\n    LDA #$12\n    RTL\n".to_string(),
+        },
+    ];
+    let output = output::assemble(
+        parse_program(sources[1].contents.as_str()).unwrap(),
+        0x808000,
+        State::new(true, true),
+    )
+    .unwrap();
+    let map: serde_json::Value =
+        serde_json::from_str(&debugger::source_map(&output.mappings, &sources)).unwrap();
+    assert_eq!(
+        map,
+        serde_json::json!({
+            "version": 1,
+            "files": ["other.asm", "main.asm"],
+            "mappings": [
+                {
+                    "start": 0x808000,
+                    "end": 0x808002,
+                    "file": 1,
+                    "line": 2,
+                    "column": 5,
+                    "expansion": [],
+                },
+                {
+                    "start": 0x808002,
+                    "end": 0x808003,
+                    "file": 1,
+                    "line": 3,
+                    "column": 5,
+                    "expansion": [],
+                },
+            ],
+        })
+    );
+}
+
+#[test]
+fn source_map_without_sources() {
+    let output = output::assemble(
+        parse_program("NOP\n").unwrap(),
+        0x8000,
+        State::new(true, true),
+    )
+    .unwrap();
+    let map: serde_json::Value =
+        serde_json::from_str(&debugger::source_map(&output.mappings, &[])).unwrap();
+    assert_eq!(map["mappings"][0]["start"], 0x8000);
+    assert_eq!(map["mappings"][0]["file"], serde_json::Value::Null);
+}
@@ -0,0 +1,241 @@
+extern crate mvp;
+
+use mvp::mapping::Mapper;
+use mvp::parser::ast::{
+    Expression, Function, Label, Number, NumberWidth, Opcode, OpcodeMode, Register, Statement,
+    VariableName,
+};
+use mvp::parser::grammar::{parse_statement, Input};
+
+fn address() -> Expression<'static> {
+    Expression::Number(Number {
+        value: 0x8000,
+        width: NumberWidth::TwoBytes,
+    })
+}
+
+#[test]
+fn warnpc() {
+    let input = Input::new("warnpc $8000");
+    let result = parse_statement(input);
+    let expected = Ok((Statement::WarnPc(address()), Input::new("")));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn uppercase_warnpc() {
+    let input = Input::new(" WARNPC $8000 ");
+    let result = parse_statement(input);
+    let expected = Ok((Statement::WarnPc(address()), Input::new("")));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn warnpc_prefix_is_an_opcode() {
+    let input = Input::new("warnpcx $8000");
+    let result = parse_statement(input);
+    let expected = Ok((
+        Statement::Opcode(Opcode {
+            name: "warnpcx",
+            width: None,
+            mode: OpcodeMode::Address,
+            value: Some(address().into()),
+        }),
+        Input::new(""),
+    ));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn assert() {
+    let input = Input::new("assert $8000");
+    let result = parse_statement(input);
+    let expected = Ok((Statement::Assert(address(), None), Input::new("")));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn assert_with_message() {
+    let input = Input::new("ASSERT $8000 , \"too big, sorry\"");
+    let result = parse_statement(input);
+    let expected = Ok((
+        Statement::Assert(address(), Some("too big, sorry")),
+        Input::new(""),
+    ));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn assert_with_empty_message() {
+    let input = Input::new("assert $8000, \"\"");
+    let result = parse_statement(input);
+    let expected = Ok((Statement::Assert(address(), Some("")), Input::new("")));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn print() {
+    let input = Input::new("print \"end: \" , $8000");
+    let result = parse_statement(input);
+    let expected = Ok((
+        Statement::Print(vec![Expression::String("end: "), address()]),
+        Input::new(""),
+    ));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn find_pattern() {
+    let input = Input::new("findpattern \"A9 ?? 8D 00 21\" -> set_brightness");
+    let result = parse_statement(input);
+    let expected = Ok((
+        Statement::FindPattern("A9 ?? 8D 00 21", VariableName("set_brightness")),
+        Input::new(""),
+    ));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn function() {
+    let input = Input::new("function offset ( base , x ) = base");
+    let result = parse_statement(input);
+    let expected = Ok((
+        Statement::Function(Function {
+            name: VariableName("offset"),
+            parameters: vec![VariableName("base"), VariableName("x")],
+            body: Expression::Variable(Label::Named(VariableName("base"))),
+        }),
+        Input::new(""),
+    ));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn function_without_parameters() {
+    let input = Input::new("function end() = $8000");
+    let result = parse_statement(input);
+    let expected = Ok((
+        Statement::Function(Function {
+            name: VariableName("end"),
+            parameters: vec![],
+            body: address(),
+        }),
+        Input::new(""),
+    ));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn mappers() {
+    let mappers = [
+        ("lorom", Mapper::LoRom),
+        ("HIROM", Mapper::HiRom),
+        ("exhirom", Mapper::ExHiRom),
+        ("sa1rom", Mapper::Sa1Rom),
+        ("SfxRom", Mapper::SfxRom),
+    ];
+    for &(input, mapper) in &mappers {
+        let result = parse_statement(Input::new(input));
+        let expected = Ok((Statement::Mapper(mapper), Input::new("")));
+        assert_eq!(result, expected);
+    }
+}
+
+#[test]
+fn rom_variant() {
+    let input = Input::new("romvariant JP, $8000");
+    let result = parse_statement(input);
+    let expected = Ok((
+        Statement::RomVariant(VariableName("JP"), address()),
+        Input::new(""),
+    ));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn variant_table() {
+    let input = Input::new("varianttable Reset, US = $8000, JP = $8000");
+    let result = parse_statement(input);
+    let expected = Ok((
+        Statement::VariantTable(
+            VariableName("Reset"),
+            vec![
+                (VariableName("US"), address()),
+                (VariableName("JP"), address()),
+            ],
+        ),
+        Input::new(""),
+    ));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn expect_crc() {
+    let input = Input::new("expectcrc $8000, $8000");
+    let result = parse_statement(input);
+    let expected = Ok((
+        Statement::ExpectCrc(vec![address(), address()]),
+        Input::new(""),
+    ));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn interrupt_safe() {
+    let input = Input::new("interruptsafe Timer , Score");
+    let result = parse_statement(input);
+    let expected = Ok((
+        Statement::InterruptSafe(vec![VariableName("Timer"), VariableName("Score")]),
+        Input::new(""),
+    ));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn global() {
+    let input = Input::new("global Handler, Return");
+    let result = parse_statement(input);
+    let expected = Ok((
+        Statement::Global(vec![VariableName("Handler"), VariableName("Return")]),
+        Input::new(""),
+    ));
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn include() {
+    let input = Input::new("incsrc \"lib/math.asm\"");
+    let result = parse_statement(input);
+    let expected = Ok((Statement::Include("lib/math.asm"), Input::new("")));
+    assert_eq!(result, expected);
+    let result = parse_statement(Input::new("IncludeOnce"));
+    assert_eq!(result, Ok((Statement::IncludeOnce, Input::new(""))));
+}
+
+#[test]
+fn bank() {
+    let result = parse_statement(Input::new("bank $7E"));
+    let bank = Expression::Number(Number {
+        value: 0x7E,
+        width: NumberWidth::OneByte,
+    });
+    let expected = Ok((Statement::Bank(Some(bank)), Input::new("")));
+    assert_eq!(result, expected);
+    let result = parse_statement(Input::new("BANK auto"));
+    assert_eq!(result, Ok((Statement::Bank(None), Input::new(""))));
+}
+
+#[test]
+fn register_widths() {
+    let widths = [
+        ("a8", Register::Accumulator, 1),
+        ("A16", Register::Accumulator, 2),
+        ("i8", Register::Index, 1),
+        ("i16", Register::Index, 2),
+    ];
+    for &(input, register, width) in &widths {
+        let result = parse_statement(Input::new(input));
+        let expected = Ok((Statement::RegisterWidth(register, width), Input::new("")));
+        assert_eq!(result, expected);
+    }
+}
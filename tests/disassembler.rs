@@ -0,0 +1,105 @@
+extern crate mvp;
+
+use mvp::assembler::flags::State;
+use mvp::assembler::output;
+use mvp::disassembler::{self, Error};
+use mvp::encoder;
+use mvp::parser::grammar::parse_program;
+
+/// Assembles disassembled code at the same origin.
+fn reassemble(code: &[u8], origin: u32, state: State) -> Vec<u8> {
+    let text: String = disassembler::disassemble(code, origin, state)
+        .iter()
+        .map(|instruction| format!("{}\n", instruction))
+        .collect();
+    let statements = parse_program(&*text).unwrap();
+    match output::assemble(statements, origin, state) {
+        Ok(output) => output.data,
+        Err(error) => panic!("{}: {}", error.span().fragment(), error),
+    }
+}
+
+#[test]
+fn every_instruction() {
+    for &(m_flag, x_flag) in &[(true, true), (false, false)] {
+        for &(name, mode, opcode) in encoder::instructions() {
+            let size = encoder::instruction_size(name, mode, m_flag, x_flag).unwrap();
+            let mut code = vec![opcode, 0x12, 0x34, 0x56];
+            code.truncate(size);
+            let state = State::new(m_flag, x_flag);
+            assert_eq!(
+                reassemble(&code, 0x808000, state),
+                code,
+                "{} {:?}",
+                name,
+                mode
+            );
+        }
+    }
+}
+
+#[test]
+fn register_widths() {
+    let code = [
+        0xC2, 0x30, 0xA2, 0x34, 0x12, 0xE2, 0x20, 0xA9, 0x12, 0x28, 0xA0, 0x12,
+    ];
+    let text: Vec<_> = disassembler::disassemble(&code, 0x8000, State::new(true, true))
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        text,
+        [
+            "REP.b #$30",
+            "LDX.w #$1234",
+            "SEP.b #$20",
+            "LDA.b #$12",
+            "PLP",
+            "LDY.b #$12",
+        ]
+    );
+}
+
+#[test]
+fn branches() {
+    let instructions = disassembler::disassemble(
+        &[0x80, 0xFE, 0x82, 0xFD, 0x7F, 0xEA],
+        0x808000,
+        State::default(),
+    );
+    assert_eq!(instructions[0].target(), Some(0x808000));
+    assert_eq!(instructions[1].address, 0x808002);
+    // Branches wrap around within a bank.
+    assert_eq!(instructions[1].target(), Some(0x800002));
+    assert_eq!(instructions[1].to_string(), "BRL $800002");
+    assert_eq!(instructions[2].target(), None);
+}
+
+#[test]
+fn roundtrips() {
+    let source = "\
+Main:
+    REP #$30
+    LDA #$1234
+    LDX.b #$12
+    STA [$10],y
+    MVN $7E,$7F
+    JSL External
+.loop:
+    DEX
+    BNE .loop
+    BRL Main
+";
+    assert_eq!(disassembler::verify_roundtrip(source), Ok(()));
+    match disassembler::verify_roundtrip("LDA ($10\n") {
+        Err(Error::Syntax(error)) => assert_eq!(error.line, 1),
+        result => panic!("{:?}", result),
+    }
+    match disassembler::verify_roundtrip("Main:\nMain:\n") {
+        Err(Error::Assemble(error)) => assert_eq!(error.to_string(), "Main is already defined"),
+        result => panic!("{:?}", result),
+    }
+    let error = disassembler::verify_roundtrip("LDX.w #$1234\n").unwrap_err();
+    assert_eq!(error, Error::Truncated(0x8002));
+    assert_eq!(error.to_string(), "instruction at $008002 is cut off");
+}
@@ -0,0 +1,537 @@
+extern crate mvp;
+
+use mvp::encoder::{self, AddressingMode, Aliases, Conditions, EncodeError, Flags, LookupError};
+use mvp::parser::ast::Statement;
+use mvp::parser::grammar::parse_statement;
+
+/// Mnemonics of opcodes, as documented in the WDC 65C816 datasheet.
+const OPCODE_MAP: [&str; 256] = [
+    "BRK", "ORA", "COP", "ORA", "TSB", "ORA", "ASL", "ORA", "PHP", "ORA", "ASL", "PHD", "TSB",
+    "ORA", "ASL", "ORA", // 0_
+    "BPL", "ORA", "ORA", "ORA", "TRB", "ORA", "ASL", "ORA", "CLC", "ORA", "INC", "TCS", "TRB",
+    "ORA", "ASL", "ORA", // 1_
+    "JSR", "AND", "JSL", "AND", "BIT", "AND", "ROL", "AND", "PLP", "AND", "ROL", "PLD", "BIT",
+    "AND", "ROL", "AND", // 2_
+    "BMI", "AND", "AND", "AND", "BIT", "AND", "ROL", "AND", "SEC", "AND", "DEC", "TSC", "BIT",
+    "AND", "ROL", "AND", // 3_
+    "RTI", "EOR", "WDM", "EOR", "MVP", "EOR", "LSR", "EOR", "PHA", "EOR", "LSR", "PHK", "JMP",
+    "EOR", "LSR", "EOR", // 4_
+    "BVC", "EOR", "EOR", "EOR", "MVN", "EOR", "LSR", "EOR", "CLI", "EOR", "PHY", "TCD", "JML",
+    "EOR", "LSR", "EOR", // 5_
+    "RTS", "ADC", "PER", "ADC", "STZ", "ADC", "ROR", "ADC", "PLA", "ADC", "ROR", "RTL", "JMP",
+    "ADC", "ROR", "ADC", // 6_
+    "BVS", "ADC", "ADC", "ADC", "STZ", "ADC", "ROR", "ADC", "SEI", "ADC", "PLY", "TDC", "JMP",
+    "ADC", "ROR", "ADC", // 7_
+    "BRA", "STA", "BRL", "STA", "STY", "STA", "STX", "STA", "DEY", "BIT", "TXA", "PHB", "STY",
+    "STA", "STX", "STA", // 8_
+    "BCC", "STA", "STA", "STA", "STY", "STA", "STX", "STA", "TYA", "STA", "TXS", "TXY", "STZ",
+    "STA", "STZ", "STA", // 9_
+    "LDY", "LDA", "LDX", "LDA", "LDY", "LDA", "LDX", "LDA", "TAY", "LDA", "TAX", "PLB", "LDY",
+    "LDA", "LDX", "LDA", // A_
+    "BCS", "LDA", "LDA", "LDA", "LDY", "LDA", "LDX", "LDA", "CLV", "LDA", "TSX", "TYX", "LDY",
+    "LDA", "LDX", "LDA", // B_
+    "CPY", "CMP", "REP", "CMP", "CPY", "CMP", "DEC", "CMP", "INY", "CMP", "DEX", "WAI", "CPY",
+    "CMP", "DEC", "CMP", // C_
+    "BNE", "CMP", "CMP", "CMP", "PEI", "CMP", "DEC", "CMP", "CLD", "CMP", "PHX", "STP", "JML",
+    "CMP", "DEC", "CMP", // D_
+    "CPX", "SBC", "SEP", "SBC", "CPX", "SBC", "INC", "SBC", "INX", "SBC", "NOP", "XBA", "CPX",
+    "SBC", "INC", "SBC", // E_
+    "BEQ", "SBC", "SBC", "SBC", "PEA", "SBC", "INC", "SBC", "SED", "SBC", "PLX", "XCE", "JSR",
+    "SBC", "INC", "SBC", // F_
+];
+
+#[test]
+fn every_opcode_is_defined_once() {
+    let mut mnemonics = [None; 256];
+    for &(name, mode, opcode) in encoder::instructions() {
+        assert_eq!(
+            mnemonics[opcode as usize], None,
+            "${:02X} is defined twice",
+            opcode
+        );
+        mnemonics[opcode as usize] = Some(name);
+        assert_eq!(encoder::get_opcode(name, mode), Ok(opcode));
+    }
+    for (opcode, &name) in OPCODE_MAP.iter().enumerate() {
+        assert_eq!(mnemonics[opcode], Some(name), "${:02X}", opcode);
+    }
+}
+
+#[test]
+fn addressing_modes() {
+    let opcodes = [
+        ("LDA", AddressingMode::Immediate, 0xA9),
+        ("ADC", AddressingMode::SrIndirectIndexedY, 0x73),
+        ("ASL", AddressingMode::Accumulator, 0x0A),
+        ("BNE", AddressingMode::Relative, 0xD0),
+        ("PER", AddressingMode::RelativeLong, 0x62),
+        ("MVN", AddressingMode::BlockMove, 0x54),
+        ("JMP", AddressingMode::AbsoluteIndirect, 0x6C),
+        ("JSR", AddressingMode::AbsoluteIndexedIndirect, 0xFC),
+        ("JML", AddressingMode::AbsoluteIndirectLong, 0xDC),
+        ("STX", AddressingMode::DpIndexedY, 0x96),
+        ("PEI", AddressingMode::DpIndirect, 0xD4),
+    ];
+    for &(name, mode, opcode) in &opcodes {
+        assert_eq!(encoder::get_opcode(name, mode), Ok(opcode), "{}", name);
+    }
+    assert!(matches!(
+        encoder::get_opcode("STA", AddressingMode::Immediate),
+        Err(LookupError::UnsupportedMode("STA", _))
+    ));
+}
+
+#[test]
+fn opcodes() {
+    assert_eq!(
+        encoder::get_opcode("ADC", AddressingMode::DirectPage),
+        Ok(0x65)
+    );
+    assert!(matches!(
+        encoder::get_opcode("ADC", AddressingMode::Implied),
+        Err(LookupError::UnsupportedMode("ADC", _))
+    ));
+    assert_eq!(
+        encoder::get_opcode("XYZ", AddressingMode::Implied),
+        Err(LookupError::UnknownMnemonic(vec![]))
+    );
+}
+
+#[test]
+fn get_opcode_ignores_case() {
+    assert_eq!(
+        encoder::get_opcode("adc", AddressingMode::DirectPage),
+        Ok(0x65)
+    );
+    assert_eq!(
+        encoder::get_opcode("xBa", AddressingMode::Implied),
+        Ok(0xEB)
+    );
+    assert_eq!(
+        encoder::get_opcode("tda", AddressingMode::Implied),
+        Ok(0x7B)
+    );
+    assert_eq!(
+        encoder::get_opcode("add", AddressingMode::Immediate),
+        Err(LookupError::UnknownMnemonic(vec!["ADC", "AND"]))
+    );
+    assert_eq!(
+        encoder::get_opcode("jsl", AddressingMode::Absolute),
+        Err(LookupError::UnsupportedMode(
+            "JSL",
+            vec![AddressingMode::AbsoluteLong]
+        ))
+    );
+}
+
+#[test]
+fn unknown_mnemonics_suggest_near_matches() {
+    assert_eq!(
+        encoder::lookup("adc", AddressingMode::Immediate),
+        Err(LookupError::UnknownMnemonic(vec!["ADC"]))
+    );
+    assert_eq!(
+        encoder::lookup("LDQ", AddressingMode::Immediate),
+        Err(LookupError::UnknownMnemonic(vec!["LDA", "LDX", "LDY"]))
+    );
+    assert_eq!(
+        encoder::lookup("QQQ", AddressingMode::Immediate),
+        Err(LookupError::UnknownMnemonic(vec![]))
+    );
+    assert_eq!(
+        encoder::lookup("ADD", AddressingMode::Immediate)
+            .unwrap_err()
+            .to_string(),
+        "unknown instruction, did you mean ADC or AND?"
+    );
+}
+
+#[test]
+fn unsupported_modes_list_supported_ones() {
+    let error = encoder::lookup("ADC", AddressingMode::DpIndexedY).unwrap_err();
+    match error {
+        LookupError::UnsupportedMode("ADC", ref modes) => {
+            assert_eq!(modes.len(), 15);
+            assert!(modes.contains(&AddressingMode::Immediate));
+            assert!(!modes.contains(&AddressingMode::DpIndexedY));
+        }
+        _ => panic!("unexpected error {:?}", error),
+    }
+    assert!(error
+        .to_string()
+        .starts_with("ADC doesn't support this addressing mode, it supports dp, addr, long,"));
+}
+
+#[test]
+fn encoding() {
+    let encode = |name, mode, operand, width| {
+        encoder::encode(name, mode, operand, width).map(|bytes| bytes.to_vec())
+    };
+    assert_eq!(encode("NOP", AddressingMode::Implied, 0, 0), Ok(vec![0xEA]));
+    assert_eq!(
+        encode("LDA", AddressingMode::DirectPage, 0x12, 1),
+        Ok(vec![0xA5, 0x12])
+    );
+    assert_eq!(
+        encode("LDA", AddressingMode::Immediate, 0x12, 1),
+        Ok(vec![0xA9, 0x12])
+    );
+    assert_eq!(
+        encode("LDA", AddressingMode::Immediate, 0x1234, 2),
+        Ok(vec![0xA9, 0x34, 0x12])
+    );
+    assert_eq!(
+        encode("JMP", AddressingMode::AbsoluteIndirect, 0xFFFC, 2),
+        Ok(vec![0x6C, 0xFC, 0xFF])
+    );
+    assert_eq!(
+        encode("STA", AddressingMode::AbsoluteLongIndexedX, 0x7F0000, 3),
+        Ok(vec![0x9F, 0x00, 0x00, 0x7F])
+    );
+    assert_eq!(
+        encode("MVN", AddressingMode::BlockMove, 0x7E7F, 2),
+        Ok(vec![0x54, 0x7F, 0x7E])
+    );
+    assert_eq!(
+        encode("BRL", AddressingMode::RelativeLong, -0x8000i32 as u32, 2),
+        Ok(vec![0x82, 0x00, 0x80])
+    );
+}
+
+#[test]
+fn encoding_errors() {
+    let encode = encoder::encode;
+    assert_eq!(
+        encode("LDA", AddressingMode::DirectPage, 0x12, 2),
+        Err(EncodeError::InvalidWidth(2))
+    );
+    assert_eq!(
+        encode("LDA", AddressingMode::Immediate, 0x12, 3),
+        Err(EncodeError::InvalidWidth(3))
+    );
+    assert_eq!(
+        encode("REP", AddressingMode::Immediate, 0x30, 2),
+        Err(EncodeError::InvalidWidth(2))
+    );
+    assert_eq!(
+        encode("LDA", AddressingMode::Absolute, 0x10000, 2),
+        Err(EncodeError::OutOfRange(0x10000))
+    );
+    assert_eq!(
+        encode("BRA", AddressingMode::Relative, 128, 1),
+        Err(EncodeError::OutOfRange(128))
+    );
+    assert_eq!(
+        encode("BRA", AddressingMode::Relative, -129i32 as u32, 1),
+        Err(EncodeError::OutOfRange(-129i32 as u32))
+    );
+    assert_eq!(
+        encode("STA", AddressingMode::Immediate, 0, 1),
+        Err(EncodeError::Lookup(
+            encoder::lookup("STA", AddressingMode::Immediate).unwrap_err()
+        ))
+    );
+}
+
+#[test]
+fn instruction_sizes() {
+    for &(name, mode, _) in encoder::instructions() {
+        for &(m_flag, x_flag) in &[(false, false), (false, true), (true, false), (true, true)] {
+            let size = encoder::instruction_size(name, mode, m_flag, x_flag).unwrap();
+            assert!((1..=4).contains(&size), "{} {:?}", name, mode);
+            let width = size as u32 - 1;
+            assert!(
+                encoder::encode(name, mode, 0, width).is_ok(),
+                "{} {:?} with {} bytes",
+                name,
+                mode,
+                width
+            );
+        }
+    }
+    let size = |name| encoder::instruction_size(name, AddressingMode::Immediate, true, true);
+    assert_eq!(size("REP"), Some(2));
+    assert_eq!(size("LDY"), Some(2));
+    let size = |name| encoder::instruction_size(name, AddressingMode::Immediate, false, false);
+    assert_eq!(size("SEP"), Some(2));
+    assert_eq!(size("COP"), Some(2));
+    assert_eq!(size("CPY"), Some(3));
+    assert_eq!(size("BIT"), Some(3));
+}
+
+#[test]
+fn every_instruction_has_info() {
+    for &(name, mode, opcode) in encoder::instructions() {
+        let info = encoder::instruction_info(name, mode).unwrap();
+        assert_eq!((info.name, info.mode, info.opcode), (name, mode, opcode));
+        assert!(info.cycles.base >= 2, "{} {:?}", name, mode);
+    }
+    assert_eq!(
+        encoder::instruction_info("STA", AddressingMode::Immediate),
+        None
+    );
+    assert_eq!(
+        encoder::instruction_info("ABC", AddressingMode::Implied),
+        None
+    );
+}
+
+#[test]
+fn cycle_counts() {
+    let native = Conditions {
+        m_flag: true,
+        x_flag: true,
+        ..Conditions::default()
+    };
+    let emulation = Conditions {
+        emulation: true,
+        ..native
+    };
+    let wide = Conditions {
+        m_flag: false,
+        x_flag: false,
+        ..native
+    };
+    let count = |name, mode, conditions: &Conditions| {
+        encoder::instruction_info(name, mode)
+            .unwrap()
+            .cycles
+            .count(conditions)
+    };
+    assert_eq!(count("NOP", AddressingMode::Implied, &native), 2);
+    assert_eq!(count("LDA", AddressingMode::Immediate, &wide), 3);
+    assert_eq!(count("ASL", AddressingMode::AbsoluteIndexedX, &native), 7);
+    assert_eq!(count("ASL", AddressingMode::AbsoluteIndexedX, &wide), 9);
+    assert_eq!(count("LDX", AddressingMode::AbsoluteIndexedY, &wide), 6);
+    assert_eq!(count("STA", AddressingMode::AbsoluteIndexedY, &native), 5);
+    assert_eq!(
+        count("JSR", AddressingMode::AbsoluteIndexedIndirect, &native),
+        8
+    );
+    assert_eq!(count("RTI", AddressingMode::Implied, &native), 7);
+    assert_eq!(count("RTI", AddressingMode::Implied, &emulation), 6);
+    assert_eq!(count("MVN", AddressingMode::BlockMove, &native), 7);
+    let unaligned = Conditions {
+        unaligned_direct_page: true,
+        page_crossed: true,
+        ..wide
+    };
+    assert_eq!(
+        count("LDA", AddressingMode::DpIndirectIndexedIndexY, &unaligned),
+        8
+    );
+    assert_eq!(count("PEI", AddressingMode::DpIndirect, &unaligned), 7);
+    assert_eq!(count("LDA", AddressingMode::Absolute, &unaligned), 5);
+    let taken = Conditions {
+        branch_taken: true,
+        page_crossed: true,
+        ..emulation
+    };
+    assert_eq!(count("BNE", AddressingMode::Relative, &emulation), 2);
+    assert_eq!(count("BNE", AddressingMode::Relative, &taken), 4);
+    assert_eq!(count("BRA", AddressingMode::Relative, &native), 3);
+}
+
+#[test]
+fn affected_flags() {
+    let flags = |name, mode| encoder::instruction_info(name, mode).unwrap().flags;
+    assert_eq!(flags("STA", AddressingMode::Absolute), Flags::NONE);
+    assert_eq!(flags("CLC", AddressingMode::Implied), Flags::CARRY);
+    assert_eq!(flags("BIT", AddressingMode::Immediate), Flags::ZERO);
+    assert!(flags("BIT", AddressingMode::Absolute).contains(Flags::OVERFLOW));
+    assert!(flags("SBC", AddressingMode::Immediate).contains(Flags::CARRY | Flags::OVERFLOW));
+    assert_eq!(flags("TXS", AddressingMode::Implied), Flags::NONE);
+    assert_eq!(flags("REP", AddressingMode::Immediate), Flags::ALL);
+}
+
+#[test]
+fn block_moves() {
+    let encode = |name, source, destination| {
+        encoder::encode_block_move(name, source, destination).map(|bytes| bytes.to_vec())
+    };
+    assert_eq!(encode("MVN", 0x12, 0x34), Ok(vec![0x54, 0x34, 0x12]));
+    assert_eq!(encode("MVP", 0x7E, 0x00), Ok(vec![0x44, 0x00, 0x7E]));
+    assert_eq!(encode("MVP", 0x7E, -1), Err(EncodeError::InvalidBank(-1)));
+    assert_eq!(
+        encode("MVN", 0x7E0000, 0),
+        Err(EncodeError::InvalidBank(0x7E0000))
+    );
+    assert!(matches!(
+        encode("LDA", 0, 0),
+        Err(EncodeError::Lookup(LookupError::UnsupportedMode("LDA", _)))
+    ));
+}
+
+#[test]
+fn interrupt_signatures() {
+    let encode = |name, mode, operand, width| {
+        encoder::encode(name, mode, operand, width).map(|bytes| bytes.to_vec())
+    };
+    assert_eq!(
+        encode("BRK", AddressingMode::Immediate, 0x12, 1),
+        Ok(vec![0x00, 0x12])
+    );
+    assert_eq!(
+        encode("COP", AddressingMode::Immediate, 0xFF, 1),
+        Ok(vec![0x02, 0xFF])
+    );
+    assert_eq!(
+        encode("BRK", AddressingMode::Implied, 0, 0),
+        Ok(vec![0x00, 0x00])
+    );
+    assert_eq!(
+        encode("COP", AddressingMode::Implied, 0, 0),
+        Ok(vec![0x02, 0x00])
+    );
+    assert_eq!(
+        encode("BRK", AddressingMode::Implied, 0, 1),
+        Err(EncodeError::InvalidWidth(1))
+    );
+    assert_eq!(
+        encode("COP", AddressingMode::Immediate, 0x100, 1),
+        Err(EncodeError::OutOfRange(0x100))
+    );
+    for &mode in &[AddressingMode::Implied, AddressingMode::Immediate] {
+        assert_eq!(
+            encoder::instruction_size("BRK", mode, false, false),
+            Some(2)
+        );
+    }
+    assert_eq!(
+        encoder::instruction_size("WDM", AddressingMode::Implied, false, false),
+        None
+    );
+}
+
+#[test]
+fn aliases() {
+    let aliases = [
+        ("DEA", AddressingMode::Implied, 0x3A),
+        ("INA", AddressingMode::Implied, 0x1A),
+        ("SWA", AddressingMode::Implied, 0xEB),
+        ("TAD", AddressingMode::Implied, 0x5B),
+        ("TDA", AddressingMode::Implied, 0x7B),
+        ("BLT", AddressingMode::Relative, 0x90),
+        ("BGE", AddressingMode::Relative, 0xB0),
+    ];
+    for &(alias, mode, opcode) in &aliases {
+        assert_eq!(encoder::get_opcode(alias, mode), Ok(opcode), "{}", alias);
+        assert!(encoder::resolve_alias(alias, mode, Aliases::Reject).is_err());
+    }
+    assert_eq!(
+        encoder::get_opcode("DEA", AddressingMode::Accumulator),
+        Ok(0x3A)
+    );
+    let bytes = encoder::encode("BLT", AddressingMode::Relative, 4, 1).unwrap();
+    assert_eq!(bytes.as_slice(), &[0x90, 0x04]);
+    assert_eq!(
+        encoder::instruction_size("INA", AddressingMode::Implied, true, true),
+        Some(1)
+    );
+    let info = encoder::instruction_info("SWA", AddressingMode::Implied).unwrap();
+    assert_eq!(info.name, "XBA");
+}
+
+#[test]
+fn strict_mode_rejects_aliases() {
+    let error =
+        encoder::resolve_alias("INA", AddressingMode::Implied, Aliases::Reject).unwrap_err();
+    assert_eq!(error.to_string(), "INA is an alias, use INC A");
+    let error =
+        encoder::resolve_alias("BGE", AddressingMode::Relative, Aliases::Reject).unwrap_err();
+    assert_eq!(error.to_string(), "BGE is an alias, use BCS");
+    assert_eq!(
+        encoder::resolve_alias("BCS", AddressingMode::Relative, Aliases::Reject),
+        Ok(("BCS", AddressingMode::Relative))
+    );
+}
+
+fn resolve(source: &str, value: Option<u32>) -> Result<AddressingMode, LookupError> {
+    match parse_statement(source) {
+        Ok((Statement::Opcode(opcode), _)) => encoder::resolve_mode(&opcode, value, Some(0x80)),
+        statement => panic!("{:?}", statement),
+    }
+}
+
+#[test]
+fn resolving_modes() {
+    use mvp::encoder::AddressingMode::*;
+    let cases = [
+        ("LDA $12", None, DirectPage),
+        ("LDA $0012", None, Absolute),
+        ("LDA $000012", None, AbsoluteLong),
+        ("LDA.l $12", None, AbsoluteLong),
+        ("LDA Label", None, Absolute),
+        ("LDA Label", Some(0xFF), DirectPage),
+        ("LDA Label", Some(0x2100), Absolute),
+        ("LDA Label", Some(0x80_8000), Absolute),
+        ("LDA Label", Some(0x7E_2000), AbsoluteLong),
+        ("LDA.b Label", Some(0x7E_2000), DirectPage),
+        ("LDA Label,x", Some(0x12), DpIndexedX),
+        ("LDA Label,y", Some(0x12), AbsoluteIndexedY),
+        ("LDX Label,y", Some(0x12), DpIndexedY),
+        ("LDA $03,s", None, StackRelative),
+        ("LDA ($12)", None, DpIndirect),
+        ("LDA (Pointer)", None, DpIndirect),
+        ("JMP (Pointer)", None, AbsoluteIndirect),
+        ("JMP (Table,x)", None, AbsoluteIndexedIndirect),
+        ("JML [Pointer]", None, AbsoluteIndirectLong),
+        ("LDA [Pointer],y", None, DpIndirectLongIndexedY),
+        ("JMP $12", None, Absolute),
+        ("JSL Routine", None, AbsoluteLong),
+        ("PEA $12", None, Absolute),
+        ("BNE Loop", None, Relative),
+        ("BRL Loop", None, RelativeLong),
+        ("MVN $7E,$7F", None, BlockMove),
+        ("INC A", None, Accumulator),
+        ("LDA #$12", None, Immediate),
+        ("RTS", None, Implied),
+        ("lda.w $12", None, Absolute),
+    ];
+    for &(source, value, mode) in &cases {
+        assert_eq!(resolve(source, value), Ok(mode), "{}", source);
+    }
+}
+
+#[test]
+fn resolving_unsupported_modes() {
+    let unsupported = |source| match resolve(source, None) {
+        Err(LookupError::UnsupportedMode(..)) => {}
+        result => panic!("{}: {:?}", source, result),
+    };
+    unsupported("STA.l $12,y");
+    unsupported("LDA.w $03,s");
+    unsupported("JMP.l (Pointer)");
+    unsupported("BRA.l Loop");
+    unsupported("STZ $123456,x");
+    unsupported("LDA $12,q");
+    assert_eq!(
+        resolve("LDB $12", None),
+        Err(LookupError::UnknownMnemonic(vec!["LDA", "LDX", "LDY"]))
+    );
+}
+
+#[test]
+fn introspection() {
+    let mnemonics = encoder::mnemonics();
+    assert!(mnemonics.windows(2).all(|pair| pair[0] < pair[1]));
+    for &(name, mode, _) in encoder::instructions() {
+        assert!(mnemonics.contains(&name));
+        assert!(encoder::supported_modes(name).unwrap().contains(&mode));
+    }
+    let total: usize = mnemonics
+        .iter()
+        .map(|name| encoder::supported_modes(name).unwrap().len())
+        .sum();
+    assert_eq!(total, 256);
+    assert_eq!(
+        encoder::supported_modes("Xce"),
+        Ok(vec![AddressingMode::Implied])
+    );
+    let matrix = encoder::opcode_matrix();
+    for (opcode, &name) in OPCODE_MAP.iter().enumerate() {
+        let (mnemonic, mode) = matrix[opcode >> 4][opcode & 0xF];
+        assert_eq!(mnemonic, name, "${:02X}", opcode);
+        assert_eq!(encoder::get_opcode(mnemonic, mode), Ok(opcode as u8));
+    }
+}
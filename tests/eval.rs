@@ -0,0 +1,536 @@
+extern crate mvp;
+
+use mvp::eval::{self, ConstantFolder, Context, Error};
+use mvp::mapping::Mapper;
+use mvp::parser::ast::{Expression, Function, Label, Statement, VariableName};
+use mvp::parser::fold::{self, Folder};
+use mvp::parser::grammar::{self, Input};
+
+fn context(label: &Label) -> Option<i64> {
+    match *label {
+        Label::Named(VariableName("base")) => Some(0x8000),
+        Label::Relative(-1) => Some(0x8010),
+        _ => None,
+    }
+}
+
+fn evaluate(input: &str) -> Result<i64, Error<'_>> {
+    let (expression, rest) = grammar::parse_expression(Input::new(input)).unwrap();
+    assert_eq!(rest, Input::new(""));
+    eval::evaluate(&expression, &context)
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(evaluate("2 + 3 * 4 - 10 / 5"), Ok(12));
+}
+
+#[test]
+fn labels() {
+    assert_eq!(evaluate("- - base"), Ok(0x10));
+}
+
+#[test]
+fn undefined_label() {
+    assert_eq!(
+        evaluate("base + missing"),
+        Err(Error::UndefinedLabel(Label::Named(VariableName("missing"))))
+    );
+}
+
+#[test]
+fn unknown_function() {
+    assert_eq!(
+        evaluate("f(2)"),
+        Err(Error::UnknownFunction(VariableName("f")))
+    );
+}
+
+#[test]
+fn division_by_zero() {
+    assert_eq!(evaluate("2 / (1 - 1)"), Err(Error::DivisionByZero));
+}
+
+#[test]
+fn overflow() {
+    assert_eq!(
+        evaluate("$10000 * $10000 * $10000 * $10000"),
+        Err(Error::Overflow)
+    );
+}
+
+#[test]
+fn negative_numbers() {
+    assert_eq!(evaluate("1 - 2"), Ok(-1));
+    assert_eq!(evaluate("-base + 2"), Ok(-0x7FFE));
+    assert_eq!(evaluate("-$10 < 0"), Ok(1));
+    assert_eq!(evaluate("-7 / 2"), Ok(-3));
+}
+
+#[test]
+fn truncation() {
+    assert_eq!(eval::truncate(-1, 2), Ok(0xFFFF));
+    assert_eq!(eval::truncate(-0x8000, 2), Ok(0x8000));
+    assert_eq!(eval::truncate(-0x8001, 2), Err(Error::Overflow));
+    assert_eq!(eval::truncate(0xFF_FFFF, 3), Ok(0xFF_FFFF));
+    assert_eq!(eval::truncate(-1, 4), Ok(0xFFFF_FFFF));
+    assert_eq!(eval::truncate(0, 0), Err(Error::Overflow));
+    assert_eq!(eval::truncate(1, 5), Err(Error::Overflow));
+}
+
+fn assert(input: &str) -> Result<(), Error<'_>> {
+    let (condition, message) = match grammar::parse_statement(Input::new(input)) {
+        Ok((Statement::Assert(condition, message), _)) => (condition, message),
+        result => panic!("unexpected parse result: {:?}", result),
+    };
+    eval::assert(&condition, message, &context)
+}
+
+#[test]
+fn passing_assert() {
+    assert_eq!(assert("assert base - $8000 + 1"), Ok(()));
+}
+
+#[test]
+fn failing_assert() {
+    assert_eq!(
+        assert("assert base - $8000, \"base moved\""),
+        Err(Error::AssertionFailed(Some("base moved")))
+    );
+    assert_eq!(assert("assert 0"), Err(Error::AssertionFailed(None)));
+}
+
+fn print(input: &str) -> Result<String, Error<'_>> {
+    let arguments = match grammar::parse_statement(Input::new(input)) {
+        Ok((Statement::Print(arguments), _)) => arguments,
+        result => panic!("unexpected parse result: {:?}", result),
+    };
+    eval::print(&arguments, &context)
+}
+
+#[test]
+fn print_strings() {
+    assert_eq!(print("print \"a, b\", \"c\""), Ok("a, bc".to_string()));
+}
+
+#[test]
+fn print_numbers() {
+    assert_eq!(print("print base + 1"), Ok("32769".to_string()));
+}
+
+#[test]
+fn print_negative_numbers() {
+    assert_eq!(
+        print("print -2, \" \", hex(-$1F, 4), \" \", bin(-2)"),
+        Ok("-2 -001F -10".to_string())
+    );
+}
+
+#[test]
+fn print_string_functions() {
+    assert_eq!(
+        print("print concat(\"a\", hex(base + 4), 1), \" \", stringify(base+$0010*(2- -1))"),
+        Ok("a80041 base + $0010 * (2 - -1)".to_string())
+    );
+    assert_eq!(
+        print("print stringify(1, 2)"),
+        Err(Error::WrongArgumentCount(VariableName("stringify")))
+    );
+    assert_eq!(evaluate("concat(\"1\")"), Err(Error::ExpectedNumber));
+}
+
+#[test]
+fn print_formatting_functions() {
+    assert_eq!(
+        print("print \"freespace at $\", hex(base), \" \", dec($10), \" \", bin(5)"),
+        Ok("freespace at $8000 16 101".to_string())
+    );
+}
+
+#[test]
+fn print_padded_numbers() {
+    assert_eq!(
+        print("print hex($1F, 4), bin(5, 8)"),
+        Ok("001F00000101".to_string())
+    );
+}
+
+#[test]
+fn print_wrong_argument_count() {
+    assert_eq!(
+        print("print dec(1, 2)"),
+        Err(Error::WrongArgumentCount(VariableName("dec")))
+    );
+    assert_eq!(
+        print("print hex()"),
+        Err(Error::WrongArgumentCount(VariableName("hex")))
+    );
+}
+
+#[test]
+fn strings_are_not_numbers() {
+    assert_eq!(evaluate("\"a\" + 1"), Err(Error::ExpectedNumber));
+    assert_eq!(evaluate("hex(1) + 1"), Err(Error::ExpectedNumber));
+}
+
+struct Functions<'a>(Vec<Function<'a>>);
+
+impl<'a> Context<'a> for Functions<'a> {
+    fn label(&self, label: &Label) -> Option<i64> {
+        context(label)
+    }
+
+    fn function(&self, name: &VariableName) -> Option<&Function<'a>> {
+        self.0.iter().find(|function| function.name == *name)
+    }
+
+    fn datasize(&self, label: &Label) -> Option<i64> {
+        self.label(label).map(|_| 4)
+    }
+
+    fn text(&self, name: &str) -> Option<&str> {
+        match name {
+            "message" => Some("hello"),
+            _ => None,
+        }
+    }
+}
+
+fn functions(definitions: &[&'static str]) -> Functions<'static> {
+    let functions = definitions
+        .iter()
+        .map(
+            |definition| match grammar::parse_statement(Input::new(definition)) {
+                Ok((Statement::Function(function), ref rest)) if rest.is_empty() => function,
+                result => panic!("unexpected parse result: {:?}", result),
+            },
+        )
+        .collect();
+    Functions(functions)
+}
+
+fn call(functions: &Functions<'static>, input: &'static str) -> Result<i64, Error<'static>> {
+    let (expression, _) = grammar::parse_expression(Input::new(input)).unwrap();
+    eval::evaluate(&expression, functions)
+}
+
+#[test]
+fn user_defined_functions() {
+    let functions = functions(&[
+        "function doubled(x) = x * 2",
+        "function offset(base, x) = base + doubled(x)",
+    ]);
+    assert_eq!(call(&functions, "doubled(21)"), Ok(42));
+    assert_eq!(call(&functions, "offset(1, 2)"), Ok(5));
+}
+
+#[test]
+fn parameters_shadow_labels() {
+    let functions = functions(&["function f(base) = base + 1", "function g() = base"]);
+    assert_eq!(call(&functions, "f(1)"), Ok(2));
+    assert_eq!(call(&functions, "g()"), Ok(0x8000));
+}
+
+#[test]
+fn function_bodies_see_context() {
+    let functions = functions(&[
+        "function size() = datasize(base)",
+        "function shadowed(base) = datasize(base)",
+        "function known(x) = defined(\"x\")",
+        "function message() = message",
+    ]);
+    assert_eq!(call(&functions, "size()"), Ok(4));
+    assert_eq!(
+        call(&functions, "shadowed(1)"),
+        Err(Error::UndefinedLabel(Label::Named(VariableName("base"))))
+    );
+    assert_eq!(call(&functions, "known(1)"), Ok(1));
+    assert_eq!(call(&functions, "message()"), Err(Error::ExpectedNumber));
+}
+
+#[test]
+fn function_argument_count() {
+    let functions = functions(&["function f(a, b) = a + b"]);
+    assert_eq!(
+        call(&functions, "f(1)"),
+        Err(Error::WrongArgumentCount(VariableName("f")))
+    );
+}
+
+#[test]
+fn recursive_function() {
+    let functions = functions(&["function f(x) = f(x + 1)"]);
+    assert_eq!(
+        call(&functions, "f(1)"),
+        Err(Error::CallDepthExceeded(VariableName("f")))
+    );
+}
+
+#[test]
+fn user_defined_functions_override_formatting_functions() {
+    let functions = functions(&["function hex(x) = x + 1"]);
+    assert_eq!(call(&functions, "hex(1)"), Ok(2));
+    let (expression, _) = grammar::parse_expression(Input::new("hex(1)")).unwrap();
+    assert_eq!(
+        eval::evaluate_string(&expression, &functions),
+        Ok("2".to_string())
+    );
+}
+
+#[test]
+fn comparisons() {
+    assert_eq!(evaluate("base == $8000"), Ok(1));
+    assert_eq!(evaluate("base != $8000"), Ok(0));
+    assert_eq!(evaluate("1 < 2"), Ok(1));
+    assert_eq!(evaluate("2 <= 1"), Ok(0));
+    assert_eq!(evaluate("2 > 1"), Ok(1));
+    assert_eq!(evaluate("2 >= 2"), Ok(1));
+}
+
+struct Code(u32);
+
+impl<'a> Context<'a> for Code {
+    fn label(&self, label: &Label) -> Option<i64> {
+        context(label)
+    }
+
+    fn program_counter(&self) -> Option<u32> {
+        Some(self.0)
+    }
+}
+
+#[test]
+fn program_counter() {
+    let (expression, _) = grammar::parse_expression(Input::new("base + $20 - pc()")).unwrap();
+    assert_eq!(eval::evaluate(&expression, &Code(0x8008)), Ok(0x18));
+    assert_eq!(
+        eval::evaluate(&expression, &context),
+        Err(Error::UnknownProgramCounter)
+    );
+}
+
+fn fold(input: &str) -> Expression<'_> {
+    let (expression, _) = grammar::parse_expression(Input::new(input)).unwrap();
+    ConstantFolder.fold_expression(expression)
+}
+
+#[test]
+fn evaluate_method() {
+    let (expression, _) = grammar::parse_expression(Input::new("base - 1")).unwrap();
+    assert_eq!(expression.evaluate(&context), Ok(0x7FFF));
+}
+
+#[test]
+fn constant_folding() {
+    assert_eq!(fold("(2 + 3) * 4 == 20"), fold("1"));
+    assert_eq!(fold("base + 2 * 3"), fold("base + 6"));
+    assert_eq!(fold("1 - 3 - 4"), fold("-6"));
+    assert_eq!(fold("-6 + 10"), fold("4"));
+    assert_eq!(fold("$10 + 2 * 3"), fold("$16"));
+    assert_eq!(fold("$10 * 2"), fold("32"));
+    for input in &["1 / 0", "$FFFFFFFF * 2", "base * 2"] {
+        let (expression, _) = grammar::parse_expression(Input::new(input)).unwrap();
+        assert_eq!(fold(input), expression);
+    }
+}
+
+#[test]
+fn folded_values_match_evaluation() {
+    for input in &[
+        "2 + 3 * 4 - 10 / 5",
+        "- - base",
+        "base - 3 * 2 + 1",
+        "1 - 7 / 2",
+    ] {
+        let folded = fold(input);
+        assert_eq!(eval::evaluate(&folded, &context), evaluate(input));
+    }
+}
+
+#[test]
+fn folding_statements() {
+    let source = "warnpc base + $10 * 2\nif 1 == 2\nLDA #1 + 1\nendif";
+    let statements = grammar::parse_program(Input::new(source)).unwrap();
+    let expected =
+        grammar::parse_program(Input::new("warnpc base + 32\nif 0\nLDA #2\nendif")).unwrap();
+    assert_eq!(
+        fold::fold_statements(&mut ConstantFolder, statements),
+        expected
+    );
+}
+
+#[test]
+fn math_functions() {
+    assert_eq!(evaluate("sin(0, 127)"), Ok(0));
+    assert_eq!(evaluate("sin(64, 127)"), Ok(127));
+    assert_eq!(evaluate("sin(192, 127)"), Ok(-127));
+    assert_eq!(evaluate("sin(32, 100)"), Ok(71));
+    assert_eq!(evaluate("cos(0, 100)"), Ok(100));
+    assert_eq!(evaluate("cos(-128, 100)"), Ok(-100));
+    // Angles wrap around before the quarter turn is added.
+    let max = "$7FFF * $10000 * $10000 * $10000 + ($10000 * $10000 * $10000 - 1)";
+    assert_eq!(evaluate(&format!("cos({}, 100)", max)), Ok(100));
+    assert_eq!(evaluate("sqrt(99)"), Ok(9));
+    assert_eq!(evaluate("sqrt(100)"), Ok(10));
+    assert_eq!(evaluate("log(256)"), Ok(8));
+    assert_eq!(evaluate("log(999, 10)"), Ok(2));
+    assert_eq!(evaluate("min(3, -1, 2)"), Ok(-1));
+    assert_eq!(evaluate("max(3, base, 2)"), Ok(0x8000));
+    assert_eq!(evaluate("clamp(300, 0, 255)"), Ok(255));
+    assert_eq!(evaluate("clamp(-5, 0, 255)"), Ok(0));
+    assert_eq!(evaluate("floor(7, 2)"), Ok(3));
+    assert_eq!(evaluate("floor(-7, 2)"), Ok(-4));
+    assert_eq!(evaluate("-7 / 2"), Ok(-3));
+}
+
+#[test]
+fn math_function_errors() {
+    assert_eq!(
+        evaluate("sqrt(-1)"),
+        Err(Error::InvalidArgument(VariableName("sqrt")))
+    );
+    assert_eq!(
+        evaluate("log(0)"),
+        Err(Error::InvalidArgument(VariableName("log")))
+    );
+    assert_eq!(
+        evaluate("clamp(1, 2, 0)"),
+        Err(Error::InvalidArgument(VariableName("clamp")))
+    );
+    assert_eq!(evaluate("floor(1, 0)"), Err(Error::DivisionByZero));
+    assert_eq!(
+        evaluate("min()"),
+        Err(Error::WrongArgumentCount(VariableName("min")))
+    );
+    assert_eq!(
+        evaluate("sin(1)"),
+        Err(Error::WrongArgumentCount(VariableName("sin")))
+    );
+}
+
+#[test]
+fn user_defined_functions_override_math_functions() {
+    let functions = functions(&["function min(x) = x * 2"]);
+    assert_eq!(call(&functions, "min(4)"), Ok(8));
+}
+
+struct Files;
+
+impl<'a> Context<'a> for Files {
+    fn label(&self, label: &Label) -> Option<i64> {
+        context(label)
+    }
+
+    fn file(&self, path: &str) -> Option<&[u8]> {
+        match path {
+            "rom.sfc" => Some(&[0x12, 0x34, 0x56, 0x78, 0x9A]),
+            _ => None,
+        }
+    }
+}
+
+fn read(input: &str) -> Result<i64, Error<'_>> {
+    let (expression, _) = grammar::parse_expression(Input::new(input)).unwrap();
+    eval::evaluate(&expression, &Files)
+}
+
+#[test]
+fn file_functions() {
+    assert_eq!(read("readfile1(\"rom.sfc\", 1)"), Ok(0x34));
+    assert_eq!(read("readfile2(\"rom.sfc\", 0)"), Ok(0x3412));
+    assert_eq!(read("readfile3(\"rom.sfc\", 2)"), Ok(0x9A7856));
+    assert_eq!(read("readfile4(\"rom.sfc\", 1)"), Ok(0x9A785634));
+    assert_eq!(read("readfile2(\"rom.sfc\", 4, -1)"), Ok(-1));
+    assert_eq!(read("readfile1(\"other.sfc\", 0, 7)"), Ok(7));
+    assert_eq!(read("filesize(\"rom.sfc\")"), Ok(5));
+    assert_eq!(read("canread(\"rom.sfc\")"), Ok(1));
+    assert_eq!(read("canread(\"rom.sfc\", 4)"), Ok(1));
+    assert_eq!(read("canread(\"rom.sfc\", 4, 2)"), Ok(0));
+    assert_eq!(read("canread(\"other.sfc\")"), Ok(0));
+}
+
+#[test]
+fn file_function_errors() {
+    assert_eq!(
+        read("readfile1(\"rom.sfc\", 5)"),
+        Err(Error::UnreadableFile("rom.sfc"))
+    );
+    assert_eq!(
+        read("readfile1(\"rom.sfc\", -1)"),
+        Err(Error::UnreadableFile("rom.sfc"))
+    );
+    assert_eq!(
+        read("filesize(\"other.sfc\")"),
+        Err(Error::UnreadableFile("other.sfc"))
+    );
+    assert_eq!(read("filesize(1)"), Err(Error::ExpectedString));
+    assert_eq!(
+        read("readfile1(\"rom.sfc\")"),
+        Err(Error::WrongArgumentCount(VariableName("readfile1")))
+    );
+    assert_eq!(
+        evaluate("filesize(\"rom.sfc\")"),
+        Err(Error::UnreadableFile("rom.sfc"))
+    );
+}
+
+struct HiRom;
+
+impl<'a> Context<'a> for HiRom {
+    fn label(&self, label: &Label) -> Option<i64> {
+        context(label)
+    }
+
+    fn mapper(&self) -> Mapper {
+        Mapper::HiRom
+    }
+}
+
+#[test]
+fn mapping_functions() {
+    assert_eq!(evaluate("snestopc($818000)"), Ok(0x8000));
+    assert_eq!(evaluate("pctosnes($8000)"), Ok(0x818000));
+    assert_eq!(
+        evaluate("snestopc($7E0000)"),
+        Err(Error::Unmapped(0x7E0000))
+    );
+    assert_eq!(evaluate("pctosnes(-1)"), Err(Error::Unmapped(-1)));
+    assert_eq!(
+        evaluate("snestopc(-1)").unwrap_err().to_string(),
+        "-1 is negative, so it isn't mapped to ROM"
+    );
+    assert_eq!(
+        Error::Unmapped(0x7E0000).to_string(),
+        "0x7E0000 isn't mapped to ROM"
+    );
+    let (expression, _) = grammar::parse_expression(Input::new("snestopc($C12345)")).unwrap();
+    assert_eq!(eval::evaluate(&expression, &HiRom), Ok(0x12345));
+}
+
+#[test]
+fn defined() {
+    assert_eq!(evaluate("defined(\"base\")"), Ok(1));
+    assert_eq!(evaluate("defined(\"!other\")"), Ok(0));
+    assert_eq!(evaluate("defined(base)"), Err(Error::ExpectedString));
+    assert_eq!(
+        evaluate("defined()"),
+        Err(Error::WrongArgumentCount(VariableName("defined")))
+    );
+}
+
+#[test]
+fn error_messages() {
+    let messages: Vec<_> = ["missing + 1", "1 / 0", "nothing(1)", "sqrt(-1)"]
+        .iter()
+        .map(|input| evaluate(input).unwrap_err().to_string())
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "missing isn't defined",
+            "division by zero",
+            "function nothing doesn't exist",
+            "invalid argument of sqrt",
+        ]
+    );
+}
@@ -1,7 +1,9 @@
 extern crate mvp;
 
-use mvp::parser::ast::{BinaryOperator, Expression, Label, Number, NumberWidth, VariableName};
-use mvp::parser::grammar::{self, CompleteStr};
+use mvp::parser::ast::{
+    BinaryOperator, Expression, Label, Number, NumberWidth, Span, VariableName,
+};
+use mvp::parser::grammar::{self, Dialect, Input, ParserOptions};
 
 macro_rules! binary_op {
     (+) => {
@@ -34,6 +36,12 @@ macro_rules! tree_meta {
             width: NumberWidth::TwoBytes,
         })
     };
+    ((three $number:expr)) => {
+        Expression::Number(Number {
+            value: $number,
+            width: NumberWidth::ThreeBytes,
+        })
+    };
     (($f:tt $($arg:tt)*)) => {{
         let args = vec![$(tree_meta!($arg)),*];
         #[allow(unreachable_code, unused_variables)]
@@ -41,10 +49,10 @@ macro_rules! tree_meta {
             "+"|"-"|"*"|"/" => {
                 // Expression::Binary expects two arguments, but the macro can be expanded
                 // even when there is more.
-                let items = (args[0].clone(), args[1].clone());
+                let items = (args[0].clone().into(), args[1].clone().into());
                 Expression::Binary(binary_op!($f), Box::new(items))
             }
-            name => Expression::Call(VariableName(name), args),
+            name => Expression::Call(VariableName(name), args.into_iter().map(Into::into).collect()),
         }
     }};
     ($number:expr) => {
@@ -68,9 +76,9 @@ macro_rules! test {
     ($name:ident : $input:expr => $token:tt) => {
         #[test]
         fn $name() {
-            let input = CompleteStr($input);
-            let result = grammar::expression(input);
-            let expected = Ok((CompleteStr(""), tree!($token)));
+            let input = Input::new($input);
+            let result = grammar::parse_expression(input);
+            let expected = Ok((tree!($token), Input::new("")));
             assert_eq!(result, expected);
         }
     };
@@ -84,86 +92,318 @@ test!(call: " sqrt ( 42 ) " => (sqrt 42));
 test!(complex_calls: "f(1, 8 + g(2, 3) + 9, 4) * 2" => (* (f 1 (+ (+ 8 (g 2 3)) 9) 4) 2));
 test!(hex_digits: " $ Fe " => (one 0xFE));
 test!(two_byte_hex_digits: " $ FeDc " => (two 0xFEDC));
+test!(three_byte_hex_digits: " $7F8000 " => (three 0x7F_8000));
 test!(invalid_hex_digit_size: " $ FeD " => 0xFED);
 
 #[test]
 fn reject_huge_numbers() {
-    let input = CompleteStr("2859421875392683928732568");
-    let result = grammar::expression(input);
+    let input = Input::new("2859421875392683928732568");
+    let result = grammar::parse_expression(input);
     assert!(result.is_err());
 }
 
 #[test]
 fn no_function_call_tuples() {
-    let input = CompleteStr("f((1, 2))");
-    let result = grammar::expression(input);
+    let input = Input::new("f((1, 2))");
+    let result = grammar::parse_expression(input);
     let expected = Ok((
-        CompleteStr("((1, 2))"),
         Expression::Variable(Label::Named(VariableName("f"))),
+        Input::new("((1, 2))"),
     ));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn hex_digits_cannot_have_spaces() {
-    let input = CompleteStr(" $ FE DC ");
-    let result = grammar::expression(input);
-    assert_eq!(result, Ok((CompleteStr("DC "), tree!(one 0xFE))));
+    let input = Input::new(" $ FE DC ");
+    let result = grammar::parse_expression(input);
+    assert_eq!(result, Ok((tree!(one 0xFE), Input::new("DC "))));
 }
 
 #[test]
 fn label_math() {
-    let input = CompleteStr("+ + ++");
-    let result = grammar::expression(input);
+    let input = Input::new("+ + ++");
+    let result = grammar::parse_expression(input);
     assert_eq!(
         result,
         Ok((
-            CompleteStr(""),
             Expression::Binary(
                 BinaryOperator::Add,
                 Box::new((
-                    Expression::Variable(Label::Relative(1)),
-                    Expression::Variable(Label::Relative(2))
+                    Expression::Variable(Label::Relative(1)).into(),
+                    Expression::Variable(Label::Relative(2)).into()
                 ))
-            )
+            ),
+            Input::new("")
         ))
     )
 }
 
 #[test]
 fn label_subtraction() {
-    let input = CompleteStr(" +-++ ");
-    let result = grammar::expression(input);
+    let input = Input::new(" +-++ ");
+    let result = grammar::parse_expression(input);
     assert_eq!(
         result,
         Ok((
-            CompleteStr(""),
             Expression::Binary(
                 BinaryOperator::Sub,
                 Box::new((
-                    Expression::Variable(Label::Relative(1)),
-                    Expression::Variable(Label::Relative(2))
+                    Expression::Variable(Label::Relative(1)).into(),
+                    Expression::Variable(Label::Relative(2)).into()
                 ))
-            )
+            ),
+            Input::new("")
         )),
     )
 }
 
 #[test]
 fn scoped_labels() {
-    let input = CompleteStr(".a - .b");
-    let result = grammar::expression(input);
+    let input = Input::new(".a - .b");
+    let result = grammar::parse_expression(input);
     assert_eq!(
         result,
         Ok((
-            CompleteStr(""),
             Expression::Binary(
                 BinaryOperator::Sub,
                 Box::new((
-                    Expression::Variable(Label::Scoped(VariableName("a"))),
-                    Expression::Variable(Label::Scoped(VariableName("b"))),
+                    Expression::Variable(Label::Scoped(VariableName("a"))).into(),
+                    Expression::Variable(Label::Scoped(VariableName("b"))).into(),
                 )),
             ),
+            Input::new(""),
         )),
     )
 }
+
+#[test]
+fn comparison() {
+    let input = Input::new("1 + 2 == 3 != 0 < 4 <= 5 > 6 >= 7");
+    let result = grammar::parse_expression(input);
+    let number = |value| {
+        Expression::Number(Number {
+            value,
+            width: NumberWidth::None,
+        })
+    };
+    let binary = |operator, a: Expression<'static>, b: Expression<'static>| {
+        Expression::Binary(operator, Box::new((a.into(), b.into())))
+    };
+    let sum = binary(BinaryOperator::Add, number(1), number(2));
+    let mut expected = binary(BinaryOperator::Eq, sum, number(3));
+    let comparisons = [
+        (BinaryOperator::Ne, 0),
+        (BinaryOperator::Lt, 4),
+        (BinaryOperator::Le, 5),
+        (BinaryOperator::Gt, 6),
+        (BinaryOperator::Ge, 7),
+    ];
+    for &(operator, value) in &comparisons {
+        expected = binary(operator, expected, number(value));
+    }
+    assert_eq!(result, Ok((expected, Input::new(""))));
+}
+
+#[test]
+fn program_counter() {
+    for &input in &["end - pc()", "end - PC ( )", "end - $"] {
+        let result = grammar::parse_expression(Input::new(input));
+        let expected = Expression::Binary(
+            BinaryOperator::Sub,
+            Box::new((
+                Expression::Variable(Label::Named(VariableName("end"))).into(),
+                Expression::ProgramCounter.into(),
+            )),
+        );
+        assert_eq!(result, Ok((expected, Input::new(""))));
+    }
+}
+
+test!(pc_prefixed_call: "pcx(1)" => (pcx 1));
+
+#[test]
+fn negation() {
+    let input = Input::new("2 - -$10");
+    let result = grammar::parse_expression(input);
+    let expected = Expression::Binary(
+        BinaryOperator::Sub,
+        Box::new((
+            Expression::Number(Number {
+                value: 2,
+                width: NumberWidth::None,
+            })
+            .into(),
+            Expression::Negate(Box::new(
+                Expression::Number(Number {
+                    value: 0x10,
+                    width: NumberWidth::OneByte,
+                })
+                .into(),
+            ))
+            .into(),
+        )),
+    );
+    assert_eq!(result, Ok((expected, Input::new(""))));
+}
+
+#[test]
+fn minus_followed_by_space_is_relative_label() {
+    let input = Input::new("- - base");
+    let result = grammar::parse_expression(input);
+    let expected = Expression::Binary(
+        BinaryOperator::Sub,
+        Box::new((
+            Expression::Variable(Label::Relative(-1)).into(),
+            Expression::Variable(Label::Named(VariableName("base"))).into(),
+        )),
+    );
+    assert_eq!(result, Ok((expected, Input::new(""))));
+}
+
+#[test]
+fn invalid_operands() {
+    let input = Input::new("2 + * 3 - 4 * ) 5");
+    let result = grammar::parse_expression(input);
+    let expected = Expression::Binary(
+        BinaryOperator::Sub,
+        Box::new((
+            Expression::Binary(
+                BinaryOperator::Add,
+                Box::new((tree!(2).into(), Expression::Error(Span::new("* 3")).into())),
+            )
+            .into(),
+            Expression::Binary(
+                BinaryOperator::Mul,
+                Box::new((tree!(4).into(), Expression::Error(Span::new("")).into())),
+            )
+            .into(),
+        )),
+    );
+    assert_eq!(result, Ok((expected, Input::new(") 5"))));
+}
+
+#[test]
+fn width_propagation() {
+    let width = |input| {
+        grammar::parse_expression(Input::new(input))
+            .unwrap()
+            .0
+            .width()
+    };
+    assert_eq!(width("$10"), NumberWidth::OneByte);
+    assert_eq!(width("-$10"), NumberWidth::OneByte);
+    assert_eq!(width("$10 + 1 - 2"), NumberWidth::OneByte);
+    assert_eq!(width("1 + $1000"), NumberWidth::TwoBytes);
+    assert_eq!(width("$7E0000 + $10"), NumberWidth::ThreeBytes);
+    assert_eq!(width("($10 + 1) * 2"), NumberWidth::None);
+    assert_eq!(width("$10 / $02"), NumberWidth::None);
+    assert_eq!(width("$10 == $10"), NumberWidth::None);
+    assert_eq!(width("label + $10"), NumberWidth::None);
+    assert_eq!(width("1 + 2"), NumberWidth::None);
+}
+
+#[test]
+fn c_style_literals() {
+    let options = ParserOptions {
+        c_style_literals: true,
+        ..ParserOptions::default()
+    };
+    let cases = [
+        ("0x1F", tree!(one 0x1F)),
+        ("0XBEEF + 0x7", tree!(+ (two 0xBEEF) 7)),
+        ("0b1010", tree!(10)),
+        ("0B0000000100000001", tree!(two 0x101)),
+        ("0 - 0x10", tree!(- 0 (one 0x10))),
+        ("10", tree!(10)),
+    ];
+    for &(source, ref expected) in &cases {
+        let result = grammar::parse_expression(Input::with_options(source, options));
+        assert_eq!(result, Ok((expected.clone(), Input::new(""))), "{}", source);
+    }
+}
+
+#[test]
+fn c_style_literals_are_opt_in() {
+    let result = grammar::parse_expression(Input::new("0x1F"));
+    assert_eq!(result, Ok((tree!(0), Input::new("x1F"))));
+}
+
+#[test]
+fn left_to_right_precedence() {
+    let options = ParserOptions::dialect(Dialect::Xkas);
+    let cases = [
+        ("2 + 3 * 4", tree!(* (+ 2 3) 4)),
+        ("2 * 3 + 4 / 5", tree!(/ (+ (* 2 3) 4) 5)),
+        ("2 + (3 * 4)", tree!(+ 2 (* 3 4))),
+        ("f(1 - 2 * 3)", tree!(f (* (- 1 2) 3))),
+    ];
+    for &(source, ref expected) in &cases {
+        let result = grammar::parse_expression(Input::with_options(source, options));
+        assert_eq!(result, Ok((expected.clone(), Input::new(""))), "{}", source);
+    }
+}
+
+#[test]
+fn left_to_right_comparisons() {
+    let options = ParserOptions::dialect(Dialect::Xkas);
+    let result = grammar::parse_expression(Input::with_options("1 == 2 - 1", options));
+    let expected = Expression::Binary(
+        BinaryOperator::Sub,
+        Box::new((
+            Expression::Binary(
+                BinaryOperator::Eq,
+                Box::new((tree!(1).into(), tree!(2).into())),
+            )
+            .into(),
+            tree!(1).into(),
+        )),
+    );
+    assert_eq!(result, Ok((expected, Input::new(""))));
+}
+
+#[test]
+fn deeply_nested_parentheses_are_rejected() {
+    let source = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+    assert!(grammar::parse_expression(source.as_str()).is_err());
+    assert!(grammar::parse_program(format!("warnpc {}\n", source).as_str()).is_err());
+}
+
+#[test]
+fn long_operator_chains_are_rejected() {
+    let source = vec!["1"; 100_000].join("+");
+    assert!(grammar::parse_expression(source.as_str()).is_err());
+}
+
+#[test]
+fn configurable_depth_limit() {
+    let options = ParserOptions {
+        max_depth: 3,
+        ..ParserOptions::default()
+    };
+    let parse = |source| grammar::parse_expression(Input::with_options(source, options));
+    assert_eq!(
+        parse("((1 + 2) * 3)"),
+        Ok((tree!(* (+ 1 2) 3), Input::new("")))
+    );
+    assert_eq!(parse("f(g(1))"), Ok((tree!(f (g 1)), Input::new(""))));
+    assert!(parse("1 + 2 * 3 - 4").is_err());
+    assert!(parse("((((1))))").is_err());
+    assert!(parse("f(g(-1))").is_err());
+}
+
+#[test]
+fn backtracking_from_invalid_groups() {
+    let parse = |source| grammar::parse_expression(source).map(|(_, rest)| rest.fragment());
+    assert_eq!(parse("f(1,) + 2"), Ok("(1,) + 2"));
+    assert_eq!(parse("f(1"), Ok("(1"));
+    assert_eq!(
+        grammar::parse_expression("-(1, 2)"),
+        Ok((
+            Expression::Variable(Label::Relative(-1)),
+            Input::new("(1, 2)")
+        ))
+    );
+    assert!(grammar::parse_expression("(1, 2)").is_err());
+    assert_eq!(parse("2 * -f(1, (2)) + 3"), Ok(""));
+}
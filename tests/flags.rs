@@ -0,0 +1,226 @@
+extern crate mvp;
+
+use mvp::analysis;
+use mvp::assembler::flags::{self, State, UnknownWidth};
+use mvp::assembler::output;
+use mvp::parser::ast::{Register, Span, Spanned, Statement};
+use mvp::parser::grammar::parse_program;
+
+fn size(source: &str, state: State) -> (Vec<Spanned<'_, Statement<'_>>>, Vec<UnknownWidth<'_>>) {
+    let mut statements = parse_program(source).unwrap();
+    let unknown = flags::size_immediates(&mut statements, state);
+    (statements, unknown)
+}
+
+fn sized(source: &str, state: State) -> Vec<Spanned<'_, Statement<'_>>> {
+    let (statements, unknown) = size(source, state);
+    assert_eq!(unknown, []);
+    statements
+}
+
+#[test]
+fn rep_and_sep_select_operand_sizes() {
+    let source = "REP #$30\nLDA #1\nLDY #2\nSEP #$20\nADC #3\nCPX #4\nSEP #$10\nLDX #5\n";
+    let expected =
+        "REP #$30\nLDA.w #1\nLDY.w #2\nSEP #$20\nADC.b #3\nCPX.w #4\nSEP #$10\nLDX.b #5\n";
+    assert_eq!(
+        sized(source, State::default()),
+        parse_program(expected).unwrap()
+    );
+}
+
+#[test]
+fn other_immediates_are_unchanged() {
+    let source = "SEP #$30\nREP #$08\nLDA.w #1\nBRK #0\nPEA $1234\n";
+    assert_eq!(
+        sized(source, State::default()),
+        parse_program(source).unwrap()
+    );
+}
+
+#[test]
+fn unknown_widths_are_reported() {
+    let source = "REP #$20\nLDA #1\nLDX #2\nPLP\nLDA #3\n";
+    let (statements, unknown) = size(source, State::default());
+    assert_eq!(
+        statements,
+        parse_program("REP #$20\nLDA.w #1\nLDX #2\nPLP\nLDA #3\n").unwrap()
+    );
+    let unknown: Vec<_> = unknown
+        .iter()
+        .map(|unknown| (unknown.span.fragment(), unknown.register))
+        .collect();
+    assert_eq!(
+        unknown,
+        [
+            ("LDX #2", Register::Index),
+            ("LDA #3", Register::Accumulator)
+        ]
+    );
+}
+
+#[test]
+fn named_labels_reset_state() {
+    let source = "REP #$20\n.loop:\nLDA #1\nRoutine:\nLDA #2\n";
+    let expected = "REP #$20\n.loop:\nLDA.w #1\nRoutine:\nLDA.b #2\n";
+    assert_eq!(
+        sized(source, State::new(true, true)),
+        parse_program(expected).unwrap()
+    );
+}
+
+#[test]
+fn unknown_rep_operands_forget_state() {
+    let (_, unknown) = size("REP #Flags\nLDA #1\n", State::new(true, true));
+    assert_eq!(unknown.len(), 1);
+}
+
+#[test]
+fn if_blocks_join_states() {
+    let source = "if a\nREP #$30\nelse\nREP #$20\nendif\nLDA #1\nLDX #2\n";
+    let (statements, unknown) = size(source, State::new(true, true));
+    assert_eq!(
+        statements[1..],
+        parse_program("LDA.w #1\nLDX #2\n").unwrap()[..]
+    );
+    assert_eq!(unknown.len(), 1);
+    let (_, unknown) = size("if a\nREP #$20\nendif\nLDA #1\n", State::new(true, true));
+    assert_eq!(unknown.len(), 1);
+}
+
+#[test]
+fn width_directives_set_state() {
+    let source = "a16\nLDA #1\ni8\nLDX #2\nSEP #$20\nLDA #3\nRoutine:\nLDA #4\nLDY #5\n";
+    let expected =
+        "a16\nLDA.w #1\ni8\nLDX.b #2\nSEP #$20\nLDA.b #3\nRoutine:\nLDA.w #4\nLDY.b #5\n";
+    assert_eq!(
+        sized(source, State::default()),
+        parse_program(expected).unwrap()
+    );
+}
+
+#[test]
+fn width_directives_in_if_blocks() {
+    let source = "if a\na16\nelse\na8\nendif\nRoutine:\nLDA #1\n";
+    let (_, unknown) = size(source, State::default());
+    assert_eq!(unknown.len(), 1);
+    let source = "if a\na16\nelse\na16\nendif\nRoutine:\nLDA #1\n";
+    let statements = sized(source, State::default());
+    assert_eq!(statements[2..], parse_program("LDA.w #1\n").unwrap()[..]);
+}
+
+/// Conflicts found with `state` at the start, with an empty statement
+/// for widths code is assembled for.
+fn conflicts_from(source: &str, state: State) -> Vec<(&str, Register, &str, &str)> {
+    let statements = parse_program(source).unwrap();
+    analysis::flags::check(&statements, state)
+        .into_iter()
+        .map(|conflict| {
+            (
+                conflict.label.fragment(),
+                conflict.register,
+                fragment(conflict.narrow),
+                fragment(conflict.wide),
+            )
+        })
+        .collect()
+}
+
+fn fragment(span: Option<Span<'_>>) -> &str {
+    span.map_or("", |span| span.fragment())
+}
+
+fn conflicts(source: &str) -> Vec<(&str, Register, &str, &str)> {
+    conflicts_from(source, State::default())
+}
+
+#[test]
+fn empty_programs() {
+    assert_eq!(conflicts(""), []);
+    assert_eq!(conflicts("; comment\n"), []);
+    let output = output::assemble(Vec::new(), 0x8000, State::new(true, true)).unwrap();
+    assert!(output.data.is_empty());
+}
+
+#[test]
+fn consistent_paths_are_accepted() {
+    let source = "REP #$30\nBEQ .skip\nLDA #1\n.skip:\nJSR Routine\nRTS\nRoutine:\nSEP #$20\nRTS\n";
+    assert_eq!(conflicts(source), []);
+}
+
+#[test]
+fn conflicting_branches_are_reported() {
+    let source = "SEP #$30\nBEQ .store\nREP #$10\n.store:\nSTX $12\n";
+    assert_eq!(
+        conflicts(source),
+        [(".store:", Register::Index, "BEQ .store", "REP #$10")]
+    );
+}
+
+#[test]
+fn conflicting_calls_are_reported() {
+    let source =
+        "Main:\nSEP #$20\nJSL Routine\nREP #$20\nJSL Routine\nRTL\nRoutine:\nLDA #1\nRTL\n";
+    assert_eq!(
+        conflicts(source),
+        [(
+            "Routine:",
+            Register::Accumulator,
+            "JSL Routine",
+            "JSL Routine"
+        )]
+    );
+}
+
+#[test]
+fn unknown_states_are_not_conflicts() {
+    assert_eq!(conflicts("SEP #$20\nBEQ Store\nPLP\nStore:\nRTS\n"), []);
+    assert_eq!(conflicts("Handler:\nBEQ Done\nSEP #$20\nDone:\nRTI\n"), []);
+}
+
+#[test]
+fn if_blocks_are_both_followed() {
+    let source = "if a\nSEP #$20\nelse\nREP #$20\nendif\nJoin:\nRTS\n";
+    assert_eq!(conflicts(source).len(), 1);
+    let source = "SEP #$20\nBRA Done\nREP #$20\nDone:\nRTS\n";
+    assert_eq!(conflicts(source), []);
+}
+
+#[test]
+fn assumed_widths_are_checked() {
+    let state = State::new(true, true);
+    assert_eq!(
+        conflicts_from("REP #$20\nSub:\nLDA #$12\n", state),
+        [("Sub:", Register::Accumulator, "", "REP #$20")]
+    );
+    assert_eq!(
+        conflicts_from("REP #$20\nBRA Sub\nSub:\nLDA #$12\n", state),
+        [("Sub:", Register::Accumulator, "", "BRA Sub")]
+    );
+    assert_eq!(
+        conflicts_from(
+            "SEP #$10\nJSR Sub\nRTS\nSub:\nRTS\n",
+            State::new(true, false)
+        ),
+        [("Sub:", Register::Index, "JSR Sub", "")]
+    );
+    // Width directives change widths code after labels is assembled for.
+    assert_eq!(conflicts_from("a16\nBRA Sub\nSub:\nLDA #$12\n", state), []);
+    assert_eq!(
+        conflicts_from("if a\na16\nelse\na16\nendif\nSub:\nLDA #1\n", state),
+        []
+    );
+    assert_eq!(
+        conflicts_from("REP #$20\nSub:\nLDA #$12\n", State::default()),
+        []
+    );
+}
+
+#[test]
+fn unreached_routines_are_checked() {
+    let source = "RTS\nHandler:\nSEP #$20\nBNE .done\nREP #$20\n.done:\nRTI\n";
+    assert_eq!(
+        conflicts(source),
+        [(".done:", Register::Accumulator, "BNE .done", "REP #$20")]
+    );
+}
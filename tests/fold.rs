@@ -0,0 +1,81 @@
+extern crate mvp;
+
+use mvp::parser::ast::{
+    BinaryOperator, Expression, Label, Number, NumberWidth, Spanned, Statement, VariableName,
+};
+use mvp::parser::fold::{self, Folder};
+use mvp::parser::grammar::{parse_program, Input};
+
+fn parse(source: &str) -> Vec<Spanned<'_, Statement<'_>>> {
+    parse_program(Input::new(source)).unwrap()
+}
+
+struct Rename;
+
+impl<'a> Folder<'a> for Rename {
+    fn fold_label(&mut self, label: Label<'a>) -> Label<'a> {
+        match label {
+            Label::Named(VariableName("Old")) => Label::Named(VariableName("New")),
+            label => label,
+        }
+    }
+}
+
+#[test]
+fn renames_labels() {
+    let source = "
+Old:
+    MVN Old, Old+1
+    function f(x) = x + Old
+    if Old == 1
+        print \"Old\", -Old
+    endif
+";
+    let expected = parse(
+        "
+New:
+    MVN New, New+1
+    function f(x) = x + New
+    if New == 1
+        print \"Old\", -New
+    endif
+",
+    );
+    let folded = fold::fold_statements(&mut Rename, parse(source));
+    assert_eq!(folded, expected);
+}
+
+#[test]
+fn preserves_spans() {
+    let source = "LDA Old\n  STA Old+2 ; store\n";
+    let folded = fold::fold_statements(&mut Rename, parse(source));
+    assert_eq!(folded[1].span.fragment(), "STA Old+2");
+}
+
+#[test]
+fn maps_children_first() {
+    let mut seen = Vec::new();
+    let folded = fold::map_expressions(parse("warnpc (1 + 2) * 3"), |expression| {
+        seen.push(expression.clone());
+        match expression {
+            Expression::Number(Number { value, width }) => Expression::Number(Number {
+                value: value * 10,
+                width,
+            }),
+            expression => expression,
+        }
+    });
+    assert_eq!(folded, parse("warnpc (10 + 20) * 30"));
+    let number = |value| {
+        Expression::Number(Number {
+            value,
+            width: NumberWidth::None,
+        })
+    };
+    let sum = Expression::Binary(
+        BinaryOperator::Add,
+        Box::new((number(10).into(), number(20).into())),
+    );
+    assert_eq!(seen[..4], [number(1), number(2), sum, number(3)]);
+    assert_eq!(seen.len(), 5);
+}
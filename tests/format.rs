@@ -0,0 +1,141 @@
+extern crate mvp;
+
+use mvp::format::{self, Case, Error, Options};
+
+fn format(source: &str) -> String {
+    let formatted = format::format(source, &Options::default()).unwrap();
+    assert_eq!(
+        format::format(&*formatted, &Options::default()).unwrap(),
+        formatted
+    );
+    formatted
+}
+
+#[test]
+fn layout() {
+    let source = "\
+; Entry point
+!lives = 3
+Main:   LDA #!lives ; load
+ STA $10,x   ; store
+  lda.w Table+1   ; table
+
+      ; loop
+-: DEX
+bne -
+.done : rts
+
+
+";
+    assert_eq!(
+        format(source),
+        "\
+; Entry point
+!lives = 3
+Main: LDA #!lives ; load
+    STA   $10,x   ; store
+    LDA.w Table+1 ; table
+
+    ; loop
+-:  DEX
+    BNE -
+.done: RTS
+",
+    );
+}
+
+#[test]
+fn blocks() {
+    let source = "\
+macro store(value, address)
+if value == 0
+stz address
+else
+lda #value
+sta address
+endif
+endmacro
+function double(x) = x*2
+%store( double(1) , $10 )
+%store(0, $10)
+";
+    assert_eq!(
+        format(source),
+        "\
+macro store(value, address)
+    if value == 0
+        STZ address
+    else
+        LDA #value
+        STA address
+    endif
+endmacro
+function double(x) = x * 2
+    %store(double(1), $10)
+    %store(0, $10)
+",
+    );
+}
+
+#[test]
+fn spacing() {
+    assert_eq!(
+        format("LDA ( $10 ) , y\nJML [ Vector ]\nprint \"a;b\" ,1+2\nBRA ++\nLDA #-1\nLDA # Mask * 2 - 1\n"),
+        "    LDA ($10),y
+    JML [Vector]
+    print \"a;b\", 1 + 2
+    BRA ++
+    LDA #-1
+    LDA #Mask*2-1
+",
+    );
+}
+
+#[test]
+fn directives() {
+    assert_eq!(format("!y := hex(5)\n"), "!y := hex(5)\n");
+    assert_eq!(format("!y:=hex(5)\n"), "!y := hex(5)\n");
+    assert_eq!(
+        format("warnings deny unused-label\n"),
+        "    warnings deny unused-label\n"
+    );
+    assert_eq!(
+        format("findpattern \"A9 ?? 8D\" -> Found\n"),
+        "    findpattern \"A9 ?? 8D\" -> Found\n"
+    );
+    assert_eq!(
+        format("findpattern \"A9 ?? 8D\"->Found\n"),
+        "    findpattern \"A9 ?? 8D\" -> Found\n"
+    );
+}
+
+#[test]
+fn options() {
+    let source = "Main:\n\tlda #1 ; one\n\tSTA.W $1234 ; store\r\n";
+    let options = Options {
+        indent: 2,
+        case: Case::Lower,
+        align_operands: false,
+        align_comments: false,
+    };
+    assert_eq!(
+        format::format(source, &options).unwrap(),
+        "Main:\r\n  lda #1 ; one\r\n  sta.w $1234 ; store\r\n"
+    );
+    let options = Options {
+        case: Case::Preserve,
+        ..Options::default()
+    };
+    assert_eq!(
+        format::format(source, &options).unwrap(),
+        "Main:\r\n    lda   #1    ; one\r\n    STA.W $1234 ; store\r\n"
+    );
+}
+
+#[test]
+fn errors() {
+    match format::format("LDA ($10\n", &Options::default()) {
+        Err(Error::Syntax(error)) => assert_eq!(error.line, 1),
+        result => panic!("{:?}", result),
+    }
+}
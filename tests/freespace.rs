@@ -0,0 +1,142 @@
+extern crate mvp;
+
+use mvp::freespace::{self, Allocator, Error, Kind, MAX_BLOCK_SIZE, TAG_SIZE};
+use mvp::mapping::Mapper;
+use mvp::rom;
+
+#[test]
+fn tags() {
+    assert_eq!(freespace::tag(1), *b"STAR\x00\x00\xFF\xFF");
+    assert_eq!(freespace::tag(MAX_BLOCK_SIZE), *b"STAR\xFF\xFF\x00\x00");
+    let mut rom = vec![0; 0x10];
+    rom[2..2 + TAG_SIZE].copy_from_slice(&freespace::tag(0x1234));
+    assert_eq!(freespace::read_tag(&rom, 2), Some(0x1234));
+    assert_eq!(freespace::read_tag(&rom, 1), None);
+    assert_eq!(freespace::read_tag(&rom, 9), None);
+    rom[7] ^= 1;
+    assert_eq!(freespace::read_tag(&rom, 2), None);
+}
+
+#[test]
+fn protected_regions() {
+    let mut rom = vec![0; 0x40];
+    rom[0x10..0x18].copy_from_slice(&freespace::tag(0x10));
+    // Data inside of a protected block isn't a tag.
+    rom[0x18..0x20].copy_from_slice(&freespace::tag(0x100));
+    rom[0x30..0x38].copy_from_slice(&freespace::tag(0x100));
+    assert_eq!(freespace::protected(&rom), [0x10..0x28, 0x30..0x40]);
+}
+
+#[test]
+fn write_protected() {
+    let mut rom = vec![0xFF; 0x40];
+    assert_eq!(
+        freespace::write_protected(&mut rom, 0x10, &[1, 2, 3]),
+        Ok(0x18..0x1B)
+    );
+    assert_eq!(
+        freespace::write_protected(&mut rom, 0x0C, &[1]),
+        Err(Error::Protected(0x10))
+    );
+    assert_eq!(
+        freespace::write_protected(&mut rom, 0x1A, &[1]),
+        Err(Error::Protected(0x10))
+    );
+    assert_eq!(
+        freespace::write_protected(&mut rom, 0x1B, &[1]),
+        Ok(0x23..0x24)
+    );
+    assert_eq!(freespace::protected(&rom), [0x10..0x1B, 0x1B..0x24]);
+    assert_eq!(
+        freespace::write_protected(&mut rom, 0x38, &[1]),
+        Err(Error::OutOfBounds(0x38))
+    );
+    assert_eq!(
+        freespace::write_protected(&mut rom, 0x30, &[]),
+        Err(Error::InvalidSize(0))
+    );
+    assert_eq!(
+        freespace::write_protected(&mut vec![0; 0x20000], 0, &vec![0; MAX_BLOCK_SIZE + 1]),
+        Err(Error::InvalidSize(MAX_BLOCK_SIZE + 1))
+    );
+}
+
+#[test]
+fn scan() {
+    let mut rom = vec![0x00; 0x18000];
+    rom[0x100] = 0x42;
+    rom[0x200..0x300].copy_from_slice(&[0xFF; 0x100]);
+    rom[0x400..0x500].copy_from_slice(&[0xFF; 0x100]);
+    freespace::write_protected(&mut rom, 0x1000, &[0; 0x100]).unwrap();
+    assert_eq!(
+        freespace::scan(&rom, &rom::LOROM, &[0x00, 0xFF]),
+        [
+            0x0000..0x0100,
+            0x0101..0x0200,
+            0x0200..0x0300,
+            0x0300..0x0400,
+            0x0400..0x0500,
+            0x0500..0x1000,
+            0x1108..0x7FC0,
+            0x8000..0x10000,
+            0x10000..0x18000,
+        ]
+    );
+    assert_eq!(
+        freespace::scan(&rom, &rom::HIROM, &[0xFF]),
+        [0x0200..0x0300, 0x0400..0x0500]
+    );
+}
+
+#[test]
+fn allocate_code() {
+    let mut rom = vec![0xFF; 0x20000];
+    rom[..0x9000].copy_from_slice(&[0x42; 0x9000]);
+    let mut allocator = Allocator::new(&rom, Mapper::HiRom, &[0xFF]);
+    assert_eq!(allocator.free(), [0x9000..0xFFC0, 0x10000..0x20000]);
+    // Banks `$C0-$FF` don't mirror low RAM, so code is placed at `$80-$BF`.
+    assert_eq!(
+        allocator.allocate(&mut rom, &[0x6B; 0x10], Kind::Code),
+        Ok(0x809008)
+    );
+    assert_eq!(
+        allocator.allocate(&mut rom, &[0x6B; 0x7000], Kind::Code),
+        Ok(0x818008)
+    );
+    assert_eq!(
+        allocator.allocate(&mut rom, &[0x6B; 0x10], Kind::Data),
+        Ok(0xC09020)
+    );
+    assert_eq!(
+        allocator.free(),
+        [0x9030..0xFFC0, 0x10000..0x18000, 0x1F008..0x20000]
+    );
+    assert_eq!(freespace::read_tag(&rom, 0x18000), Some(0x7000));
+    assert_eq!(
+        allocator.allocate(&mut rom, &[0x6B; 0x7000], Kind::Code),
+        Err(Error::Full(0x7000))
+    );
+}
+
+#[test]
+fn allocate_lorom() {
+    let mut rom = vec![0x00; 0x400000];
+    let mut allocator = Allocator::new(&rom, Mapper::LoRom, &[0x00]);
+    assert_eq!(
+        allocator.allocate(&mut rom, &[0; 0x7FB8], Kind::Code),
+        Ok(0x808008)
+    );
+    assert_eq!(
+        allocator.allocate(&mut rom, &[0; 0x8000], Kind::Data),
+        Err(Error::Full(0x8000))
+    );
+    assert_eq!(
+        allocator.allocate(&mut rom, &[0; 0x7FF8], Kind::Data),
+        Ok(0x818008)
+    );
+    assert_eq!(allocator.free()[0], 0x10000..0x18000);
+    assert_eq!(
+        allocator.allocate(&mut rom, &[], Kind::Data),
+        Err(Error::InvalidSize(0))
+    );
+}
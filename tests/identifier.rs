@@ -1,6 +1,6 @@
 extern crate mvp;
 
-use mvp::parser::grammar::{self, CompleteStr};
+use mvp::parser::grammar::{self, Dialect, Input, ParserOptions};
 
 macro_rules! test {
     ($name:ident, $input:expr) => {
@@ -10,8 +10,8 @@ macro_rules! test {
     ($name:ident, $input:expr, $unparsed:expr, $output:expr) => {
         #[test]
         fn $name() {
-            let parsed = grammar::identifier(CompleteStr($input));
-            assert_eq!(parsed, Ok((CompleteStr($unparsed), $output)));
+            let parsed = grammar::parse_identifier(Input::new($input));
+            assert_eq!(parsed, Ok(($output, Input::new($unparsed))));
         }
     };
 }
@@ -29,12 +29,21 @@ test!(unicode_continue, "a﹏b");
 
 #[test]
 fn parse_failure() {
-    let parsed = grammar::identifier(CompleteStr("4"));
+    let parsed = grammar::parse_identifier(Input::new("4"));
     assert!(parsed.is_err());
 }
 
 #[test]
 fn unicode_continue_cannot_be_at_beginning() {
-    let parsed = grammar::identifier(CompleteStr("﹏"));
+    let parsed = grammar::parse_identifier(Input::new("﹏"));
     assert!(parsed.is_err());
 }
+
+#[test]
+fn exclamation_rejected_in_mvp_dialect() {
+    let options = ParserOptions::dialect(Dialect::Mvp);
+    let parsed = grammar::parse_identifier(Input::with_options("!variable", options));
+    assert!(parsed.is_err());
+    let parsed = grammar::parse_identifier(Input::with_options("variable", options));
+    assert_eq!(parsed, Ok(("variable", Input::new(""))));
+}
@@ -0,0 +1,112 @@
+extern crate mvp;
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use mvp::include::{Error, FileSystem, Overlay, Resolver};
+
+/// File system without any files.
+struct Empty;
+
+impl FileSystem for Empty {
+    fn read(&self, _: &Path) -> io::Result<String> {
+        Err(io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+
+    fn exists(&self, _: &Path) -> bool {
+        false
+    }
+}
+
+fn resolver(files: &[(&str, &str)]) -> Resolver<Overlay<Empty>> {
+    let mut overlay = Overlay::new(Empty);
+    for &(path, contents) in files {
+        overlay.insert(path, contents);
+    }
+    Resolver::new(overlay)
+}
+
+fn paths(resolver: &Resolver<Overlay<Empty>>, root: &str) -> Vec<PathBuf> {
+    resolver
+        .load(Path::new(root))
+        .unwrap()
+        .into_iter()
+        .map(|source| source.path)
+        .collect()
+}
+
+#[test]
+fn relative_includes() {
+    let resolver = resolver(&[
+        ("/p/main.asm", "incsrc \"lib/a.asm\"\nincsrc \"b.asm\"\n"),
+        ("/p/lib/a.asm", "incsrc \"../b.asm\"\n"),
+        ("/p/b.asm", "if 1\n incsrc \"c.asm\"\nendif\n"),
+        ("/p/c.asm", "LDA #1\n"),
+    ]);
+    assert_eq!(
+        paths(&resolver, "/p/main.asm"),
+        ["/p/main.asm", "/p/lib/a.asm", "/p/b.asm", "/p/c.asm"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn include_directories() {
+    let mut resolver = resolver(&[
+        ("/p/main.asm", "incsrc \"defines.asm\"\n"),
+        ("/include/defines.asm", ""),
+    ]);
+    assert!(resolver
+        .resolve(Path::new("/p/main.asm"), "defines.asm")
+        .is_none());
+    resolver.add_directory("/include");
+    assert_eq!(
+        resolver.resolve(Path::new("/p/main.asm"), "defines.asm"),
+        Some(PathBuf::from("/include/defines.asm"))
+    );
+}
+
+#[test]
+fn overlay_takes_precedence() {
+    let mut resolver = resolver(&[("/p/main.asm", "incsrc \"missing.asm\"\n")]);
+    match resolver.load(Path::new("/p/main.asm")) {
+        Err(Error::NotFound { name, .. }) => assert_eq!(name, "missing.asm"),
+        result => panic!("unexpected result: {:?}", result),
+    }
+    resolver
+        .file_system_mut()
+        .insert("/p/main.asm", "LDA #2 ; unsaved\n");
+    let sources = resolver.load(Path::new("/p/main.asm")).unwrap();
+    assert_eq!(sources[0].contents, "LDA #2 ; unsaved\n");
+    resolver.file_system_mut().remove("/p/main.asm");
+    assert!(resolver.load(Path::new("/p/main.asm")).is_err());
+}
+
+#[test]
+fn recursive_include() {
+    let resolver = resolver(&[
+        ("/p/a.asm", "incsrc \"b.asm\"\n"),
+        ("/p/b.asm", "incsrc \"a.asm\"\n"),
+    ]);
+    match resolver.load(Path::new("/p/a.asm")) {
+        Err(error @ Error::Recursive(_)) => assert_eq!(
+            error.to_string(),
+            "/p/a.asm includes itself: /p/a.asm -> /p/b.asm -> /p/a.asm"
+        ),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn include_once_ends_cycles() {
+    let resolver = resolver(&[
+        ("/p/a.asm", "incsrc \"b.asm\"\n"),
+        ("/p/b.asm", "includeonce\nincsrc \"a.asm\"\n"),
+    ]);
+    assert_eq!(
+        paths(&resolver, "/p/a.asm"),
+        [PathBuf::from("/p/a.asm"), PathBuf::from("/p/b.asm")]
+    );
+}
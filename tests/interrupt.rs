@@ -0,0 +1,65 @@
+extern crate mvp;
+
+use mvp::analysis::{self, interrupt};
+use mvp::parser::ast::{Spanned, Statement};
+use mvp::parser::grammar::{parse_program, Input};
+
+fn statements(source: &str) -> Vec<Spanned<'_, Statement<'_>>> {
+    parse_program(Input::new(source)).unwrap()
+}
+
+fn warnings(source: &str) -> Vec<(&str, &str)> {
+    interrupt::check(&statements(source), "Nmi", "Main")
+        .into_iter()
+        .map(|warning| (warning.routine, warning.variable))
+        .collect()
+}
+
+const ENTRIES: &str = "
+Main:
+    JSR Shared
+    JSR MainOnly
+    RTS
+Nmi:
+    JSL Shared
+    RTI
+";
+
+#[test]
+fn call_graph() {
+    let source = format!("{}Shared:\n JMP MainOnly\nMainOnly:\n RTS\n", ENTRIES);
+    let statements = statements(&source);
+    let graph = analysis::call_graph(&statements);
+    assert_eq!(graph["Main"], vec!["Shared", "MainOnly"]);
+    let reachable = analysis::reachable(&graph, "Nmi");
+    let mut reachable: Vec<_> = reachable.into_iter().collect();
+    reachable.sort();
+    assert_eq!(reachable, vec!["MainOnly", "Nmi", "Shared"]);
+}
+
+#[test]
+fn split_write_in_shared_routine() {
+    let source = format!(
+        "{}Shared:\n STA Timer\n STX Timer+1\n STA Frame\n STA Frame\n RTL\nMainOnly:\n STA Score\n STA Score+1\n RTS\n",
+        ENTRIES
+    );
+    assert_eq!(warnings(&source), vec![("Shared", "Timer")]);
+}
+
+#[test]
+fn guarded_writes() {
+    let source = format!(
+        "{}Shared:\n SEI\n STA Timer\n STA Timer+1\n CLI\n STZ $4200\n STA Score\n STA Score+1\n LDA #$80\n STA $4200\n RTL\nMainOnly:\n RTS\n",
+        ENTRIES
+    );
+    assert_eq!(warnings(&source), vec![]);
+}
+
+#[test]
+fn interrupt_safe_variables() {
+    let source = format!(
+        "interruptsafe Timer\n{}Shared:\n STA Timer\n STA Timer+1\n RTL\nMainOnly:\n RTS\n",
+        ENTRIES
+    );
+    assert_eq!(warnings(&source), vec![]);
+}
@@ -0,0 +1,106 @@
+extern crate mvp;
+
+use mvp::parser::lexer::{Lexer, TokenKind};
+
+fn tokens(source: &str) -> Vec<(TokenKind, &str)> {
+    Lexer::new(source)
+        .map(|token| (token.kind, token.span.fragment()))
+        .collect()
+}
+
+#[test]
+fn statement() {
+    assert_eq!(
+        tokens("  LDA.b ($19,x) ; comment\r\n"),
+        vec![
+            (TokenKind::Mnemonic, "LDA"),
+            (TokenKind::Punctuation, "."),
+            (TokenKind::Identifier, "b"),
+            (TokenKind::Punctuation, "("),
+            (TokenKind::Number, "$19"),
+            (TokenKind::Punctuation, ","),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Punctuation, ")"),
+            (TokenKind::Comment, "; comment"),
+            (TokenKind::Newline, "\r\n"),
+        ]
+    );
+}
+
+#[test]
+fn labels() {
+    assert_eq!(
+        tokens("Main: .loop: BRA .loop\nvalue = 2"),
+        vec![
+            (TokenKind::Identifier, "Main"),
+            (TokenKind::Punctuation, ":"),
+            (TokenKind::Punctuation, "."),
+            (TokenKind::Identifier, "loop"),
+            (TokenKind::Punctuation, ":"),
+            (TokenKind::Mnemonic, "BRA"),
+            (TokenKind::Punctuation, "."),
+            (TokenKind::Identifier, "loop"),
+            (TokenKind::Newline, "\n"),
+            (TokenKind::Identifier, "value"),
+            (TokenKind::Operator, "="),
+            (TokenKind::Number, "2"),
+        ]
+    );
+}
+
+#[test]
+fn operators_and_numbers() {
+    assert_eq!(
+        tokens("if a<<2 >= %101 != $ - 1"),
+        vec![
+            (TokenKind::Mnemonic, "if"),
+            (TokenKind::Identifier, "a"),
+            (TokenKind::Operator, "<<"),
+            (TokenKind::Number, "2"),
+            (TokenKind::Operator, ">="),
+            (TokenKind::Number, "%101"),
+            (TokenKind::Operator, "!="),
+            (TokenKind::Punctuation, "$"),
+            (TokenKind::Operator, "-"),
+            (TokenKind::Number, "1"),
+        ]
+    );
+    assert_eq!(
+        tokens("!y:=1\nfindpattern \"EA\"->Found"),
+        vec![
+            (TokenKind::Identifier, "!y"),
+            (TokenKind::Operator, ":="),
+            (TokenKind::Number, "1"),
+            (TokenKind::Newline, "\n"),
+            (TokenKind::Mnemonic, "findpattern"),
+            (TokenKind::String, "\"EA\""),
+            (TokenKind::Operator, "->"),
+            (TokenKind::Identifier, "Found"),
+        ]
+    );
+}
+
+#[test]
+fn strings_and_errors() {
+    assert_eq!(
+        tokens("print \"a; b\", @\nprint \"open"),
+        vec![
+            (TokenKind::Mnemonic, "print"),
+            (TokenKind::String, "\"a; b\""),
+            (TokenKind::Punctuation, ","),
+            (TokenKind::Error, "@"),
+            (TokenKind::Newline, "\n"),
+            (TokenKind::Mnemonic, "print"),
+            (TokenKind::Error, "\"open"),
+        ]
+    );
+}
+
+#[test]
+fn spans_point_into_source() {
+    let source = "LDA #1\n  STA $2100";
+    let token = Lexer::new(source).nth(4).unwrap();
+    assert_eq!(token.kind, TokenKind::Mnemonic);
+    assert_eq!(token.span.range(source), Some(9..12));
+    assert_eq!(token.span.line_column(source), Some((2, 3)));
+}
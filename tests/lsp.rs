@@ -0,0 +1,295 @@
+#![cfg(feature = "lsp")]
+
+extern crate mvp;
+#[macro_use]
+extern crate serde_json;
+
+use std::io;
+use std::path::Path;
+
+use mvp::include::FileSystem;
+use mvp::lsp::{self, Server};
+use serde_json::Value;
+
+/// File system without any files.
+#[derive(Clone)]
+struct Empty;
+
+impl FileSystem for Empty {
+    fn read(&self, _: &Path) -> io::Result<String> {
+        Err(io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+
+    fn exists(&self, _: &Path) -> bool {
+        false
+    }
+}
+
+fn open(server: &mut Server<Empty>, uri: &str, text: &str) -> Vec<Value> {
+    server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": { "uri": uri, "languageId": "asm", "version": 1, "text": text },
+        },
+    }))
+}
+
+fn request(server: &mut Server<Empty>, method: &str, params: Value) -> Value {
+    let mut responses = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "id": 7,
+        "method": method,
+        "params": params,
+    }));
+    assert_eq!(responses.len(), 1);
+    responses.remove(0)
+}
+
+/// Messages of diagnostics published for every file, by URI.
+fn published(notifications: &[Value]) -> Vec<(&str, Vec<&str>)> {
+    notifications
+        .iter()
+        .map(|notification| {
+            assert_eq!(notification["method"], "textDocument/publishDiagnostics");
+            let params = &notification["params"];
+            let messages = params["diagnostics"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|diagnostic| diagnostic["message"].as_str().unwrap())
+                .collect();
+            (params["uri"].as_str().unwrap(), messages)
+        })
+        .collect()
+}
+
+#[test]
+fn messages() {
+    let mut input = Vec::new();
+    let shutdown = json!({ "jsonrpc": "2.0", "id": 1, "method": "shutdown" });
+    lsp::write_message(&mut input, &shutdown).unwrap();
+    assert!(input.starts_with(b"Content-Length: 44\r\n\r\n{"));
+    lsp::write_message(&mut input, &json!({ "jsonrpc": "2.0", "method": "exit" })).unwrap();
+    lsp::write_message(&mut input, &shutdown).unwrap();
+    let mut output = Vec::new();
+    let mut server = Server::new(Empty);
+    server.run(&input[..], &mut output).unwrap();
+    assert!(server.exited());
+    let message = lsp::read_message(&output[..]).unwrap().unwrap();
+    assert_eq!(
+        message,
+        json!({ "jsonrpc": "2.0", "id": 1, "result": null })
+    );
+    let response = request(&mut server, "unknown", Value::Null);
+    assert_eq!(response["error"]["code"], -32601);
+}
+
+#[test]
+fn initialize() {
+    let mut server = Server::new(Empty);
+    let response = request(&mut server, "initialize", json!({ "capabilities": {} }));
+    let capabilities = &response["result"]["capabilities"];
+    assert_eq!(capabilities["definitionProvider"], true);
+    assert_eq!(capabilities["documentSymbolProvider"], true);
+    let legend = &capabilities["semanticTokensProvider"]["legend"];
+    let types = legend["tokenTypes"].as_array().unwrap();
+    assert_eq!(types[0], "keyword");
+    for (index, token_type) in types.iter().enumerate() {
+        assert!(!types[..index].contains(token_type), "{}", token_type);
+    }
+}
+
+#[test]
+fn diagnostics() {
+    let mut server = Server::new(Empty);
+    let notifications = open(
+        &mut server,
+        "file:///game/lib.asm",
+        "Wait:\n  JMP Missing\nLDA ($10\n",
+    );
+    assert_eq!(
+        published(&notifications),
+        [(
+            "file:///game/lib.asm",
+            vec!["expected `)`, found end of line"]
+        )]
+    );
+    let diagnostic = &notifications[0]["params"]["diagnostics"][0];
+    assert_eq!(
+        diagnostic["range"],
+        json!({
+            "start": { "line": 2, "character": 8 },
+            "end": { "line": 2, "character": 8 },
+        })
+    );
+    assert_eq!(diagnostic["severity"], 1);
+    // A file included by another open document is assembled as its part,
+    // and diagnostics of a file are cleared once fixed.
+    let notifications = open(
+        &mut server,
+        "file:///game/main.asm",
+        "Main:\n  incsrc \"lib.asm\"\nMain:\n",
+    );
+    assert_eq!(
+        published(&notifications),
+        [
+            (
+                "file:///game/lib.asm",
+                vec!["expected `)`, found end of line"]
+            ),
+            ("file:///game/main.asm", vec![]),
+        ]
+    );
+    let notifications = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": "file:///game/lib.asm", "version": 2 },
+            "contentChanges": [{ "text": "Wait:\nRTS\n" }],
+        },
+    }));
+    assert_eq!(
+        published(&notifications),
+        [
+            ("file:///game/lib.asm", vec![]),
+            ("file:///game/main.asm", vec!["Main is already defined"]),
+        ]
+    );
+    let notifications = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didClose",
+        "params": { "textDocument": { "uri": "file:///game/main.asm" } },
+    }));
+    assert_eq!(
+        published(&notifications),
+        [
+            ("file:///game/lib.asm", vec![]),
+            ("file:///game/main.asm", vec![]),
+        ]
+    );
+}
+
+#[test]
+fn unresolved_labels() {
+    let mut server = Server::new(Empty);
+    let notifications = open(&mut server, "file:///main.asm", "Main:\n  JMP Missing\n");
+    assert_eq!(
+        published(&notifications),
+        [(
+            "file:///main.asm",
+            vec!["`JMP Missing` uses a label which is not defined"]
+        )]
+    );
+    let diagnostic = &notifications[0]["params"]["diagnostics"][0];
+    assert_eq!(
+        diagnostic["range"]["start"],
+        json!({ "line": 1, "character": 2 })
+    );
+    assert_eq!(diagnostic["severity"], 1);
+}
+
+#[test]
+fn definitions() {
+    let mut server = Server::new(Empty);
+    open(
+        &mut server,
+        "file:///my%20game/defines.asm",
+        "!speed = 2\nmacro move()\nendmacro\n",
+    );
+    open(
+        &mut server,
+        "file:///my%20game/main.asm",
+        "incsrc \"defines.asm\"\nMain:\n.loop:\n  LDA #!speed\n  %move()\n  BRA .loop\nOther:\n.loop:\n",
+    );
+    let definition = |server: &mut Server<Empty>, line, character| {
+        let params = json!({
+            "textDocument": { "uri": "file:///my%20game/main.asm" },
+            "position": { "line": line, "character": character },
+        });
+        request(server, "textDocument/definition", params)["result"].clone()
+    };
+    assert_eq!(
+        definition(&mut server, 3, 9),
+        json!({
+            "uri": "file:///my%20game/defines.asm",
+            "range": {
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": 0, "character": 10 },
+            },
+        })
+    );
+    assert_eq!(
+        definition(&mut server, 4, 4)["range"]["start"],
+        json!({ "line": 1, "character": 0 })
+    );
+    assert_eq!(
+        definition(&mut server, 5, 7)["range"]["start"],
+        json!({ "line": 2, "character": 0 })
+    );
+    assert_eq!(definition(&mut server, 3, 2), Value::Null);
+}
+
+#[test]
+fn document_symbols() {
+    let mut server = Server::new(Empty);
+    open(
+        &mut server,
+        "file:///main.asm",
+        "Main:\n.loop:\nBRA .loop\nif 1\n!lives = 3\nendif\nfunction double(x) = x*2\n",
+    );
+    let params = json!({ "textDocument": { "uri": "file:///main.asm" } });
+    let response = request(&mut server, "textDocument/documentSymbol", params);
+    let symbols: Vec<_> = response["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|symbol| {
+            (
+                symbol["name"].as_str().unwrap(),
+                symbol["kind"].as_u64().unwrap(),
+                symbol["containerName"].as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        symbols,
+        [
+            ("Main", 12, None),
+            ("loop", 8, Some("Main")),
+            ("!lives", 14, None),
+            ("double", 12, None),
+        ]
+    );
+}
+
+#[test]
+fn semantic_tokens() {
+    let mut server = Server::new(Empty);
+    open(
+        &mut server,
+        "file:///main.asm",
+        "Main:\n  LDA \"\u{e9}\" ; \u{1F600}\n  BRA Main\n",
+    );
+    let params = json!({ "textDocument": { "uri": "file:///main.asm" } });
+    let response = request(&mut server, "textDocument/semanticTokens/full", params);
+    let data: Vec<_> = response["result"]["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| value.as_u64().unwrap())
+        .collect();
+    // Lines and columns are relative to the previous token, and columns
+    // and lengths count UTF-16 code units.
+    assert_eq!(
+        data.chunks(5).collect::<Vec<_>>(),
+        [
+            [0, 0, 4, 2, 0],
+            [1, 2, 3, 0, 0],
+            [0, 4, 3, 8, 0],
+            [0, 4, 4, 10, 0],
+            [1, 2, 3, 0, 0],
+            [0, 4, 4, 2, 0],
+        ]
+    );
+}
@@ -0,0 +1,405 @@
+extern crate mvp;
+
+use mvp::assembler::flags::State;
+use mvp::assembler::macros::{self, Error, Names, DEFAULT_DEPTH_LIMIT};
+use mvp::assembler::output::{self, Output};
+use mvp::eval;
+use mvp::parser::ast::{Macro, MacroCall, Statement, VariableName};
+use mvp::parser::grammar::{parse_program, program_with_recovery, Input, ParseErrorKind};
+use mvp::symbols;
+
+fn assemble<'a>(source: &'a str, names: &'a Names) -> Output<'a> {
+    let statements =
+        macros::expand(parse_program(source).unwrap(), names, DEFAULT_DEPTH_LIMIT).unwrap();
+    output::assemble(statements, 0x808000, State::new(true, true)).unwrap()
+}
+
+fn expand_error<'a>(source: &'a str, names: &'a Names) -> Error<'a> {
+    macros::expand(parse_program(source).unwrap(), names, 4).unwrap_err()
+}
+
+#[test]
+fn parse() {
+    let statements = parse_program("macro store(value, address)\n LDA #value\n STA address\nendmacro\n%store(1, $10)\n%none()\n").unwrap();
+    assert_eq!(statements.len(), 3);
+    match statements[0].node {
+        Statement::Macro(Macro {
+            ref name,
+            ref parameters,
+            ref evaluated,
+            variadic: false,
+            ref statements,
+        }) => {
+            assert_eq!(*name, VariableName("store"));
+            assert_eq!(
+                *parameters,
+                [VariableName("value"), VariableName("address")]
+            );
+            assert!(evaluated.is_empty());
+            assert_eq!(statements.len(), 2);
+        }
+        ref statement => panic!("unexpected statement: {:?}", statement),
+    }
+    assert_eq!(
+        statements[2].node,
+        Statement::MacroCall(MacroCall {
+            name: VariableName("none"),
+            arguments: Vec::new(),
+            splice: false,
+        })
+    );
+    assert_eq!(statements[1].span.fragment(), "%store(1, $10)");
+    let statements = parse_program("macro list(...)\nendmacro\n%list(1, 2, <...>)\n").unwrap();
+    match statements[0].node {
+        Statement::Macro(ref definition) => assert!(definition.variadic),
+        ref statement => panic!("unexpected statement: {:?}", statement),
+    }
+    match statements[1].node {
+        Statement::MacroCall(ref call) => assert!(call.splice && call.arguments.len() == 2),
+        ref statement => panic!("unexpected statement: {:?}", statement),
+    }
+}
+
+#[test]
+fn parse_errors() {
+    let (_, errors) = program_with_recovery(Input::new("macro open()\nNOP\n"));
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind, ParseErrorKind::UnterminatedMacro);
+    assert_eq!(errors[0].span.fragment(), "macro open()");
+    let (statements, errors) =
+        program_with_recovery(Input::new("endmacro\nmacro a()\nLDA #\nendmacro\n"));
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].kind, ParseErrorKind::UnexpectedBlockKeyword);
+    assert_eq!(errors[1].span.fragment(), "LDA #");
+    match statements[1].node {
+        Statement::Macro(ref definition) => assert_eq!(definition.statements.len(), 1),
+        ref statement => panic!("unexpected statement: {:?}", statement),
+    }
+}
+
+#[test]
+fn expansion() {
+    let names = Names::new();
+    let source = "\
+%add($10)
+macro add(value)
+    CLC
+    ADC #value
+endmacro
+%add(2 * 3)
+";
+    let output = assemble(source, &names);
+    assert_eq!(output.data, [0x18, 0x69, 0x10, 0x18, 0x69, 0x06]);
+}
+
+#[test]
+fn hygienic_labels() {
+    let names = Names::new();
+    let source = "\
+macro wait(frames)
+wait:
+    LDX #frames
+.loop:
+    WAI
+    DEX
+    BNE .loop
+    BRA done
+done:
+endmacro
+Main:
+    %wait(2)
+    %wait(3)
+done:
+    BRA Main
+";
+    let output = assemble(source, &names);
+    assert_eq!(
+        output.data,
+        [
+            0xA2, 0x02, 0xCB, 0xCA, 0xD0, 0xFC, 0x80, 0x00, //
+            0xA2, 0x03, 0xCB, 0xCA, 0xD0, 0xFC, 0x80, 0x00, //
+            0x80, 0xEE,
+        ]
+    );
+    let names: Vec<_> = output
+        .symbols
+        .iter()
+        .map(|symbol| symbol.to_string())
+        .collect();
+    assert!(names.contains(&"Main".to_string()));
+    assert!(names.contains(&"done".to_string()));
+    assert!(names.iter().any(|name| name.starts_with("wait#")));
+}
+
+#[test]
+fn arguments_are_not_renamed() {
+    let names = Names::new();
+    let source = "\
+macro jump(target)
+target:
+    JMP target
+endmacro
+target:
+    %jump(target)
+";
+    let output = assemble(source, &names);
+    assert_eq!(output.data, [0x4C, 0x00, 0x80]);
+}
+
+#[test]
+fn global_labels() {
+    let names = Names::new();
+    let source = "\
+macro handler()
+    global Handler, Return
+Handler:
+    JSR Update
+    JMP Return
+Update:
+    RTS
+endmacro
+%handler()
+Return:
+    JMP Handler
+";
+    let output = assemble(source, &names);
+    assert_eq!(
+        output.data,
+        [0x20, 0x06, 0x80, 0x4C, 0x07, 0x80, 0x60, 0x4C, 0x00, 0x80]
+    );
+    let names: Vec<_> = output
+        .symbols
+        .iter()
+        .map(|symbol| symbol.to_string())
+        .collect();
+    assert!(names.contains(&"Handler".to_string()));
+    assert!(names.iter().any(|name| name.starts_with("Update#")));
+    assert_eq!(macros::captures(&parse_program(source).unwrap()), []);
+}
+
+#[test]
+fn captures() {
+    let source = "\
+macro spin()
+    BRA Loop
+endmacro
+macro wait()
+Loop:
+    WAI
+    BRA Loop
+endmacro
+Loop:
+    %spin()
+";
+    let statements = parse_program(source).unwrap();
+    let captures: Vec<_> = macros::captures(&statements)
+        .iter()
+        .map(|capture| (capture.span.fragment(), capture.to_string()))
+        .collect();
+    assert_eq!(
+        captures,
+        [
+            (
+                "BRA Loop",
+                "macro spin refers to Loop of the program, without listing it with `global`"
+                    .to_string()
+            ),
+            (
+                "Loop:",
+                "macro wait declares its own Loop, which hides a label of the program".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn index_registers() {
+    let names = Names::new();
+    let source = "\
+macro load(x, y)
+    LDA x,x
+    LDA (y),y
+endmacro
+%load($10, $20)
+";
+    let output = assemble(source, &names);
+    assert_eq!(output.data, [0xB5, 0x10, 0xB1, 0x20]);
+}
+
+#[test]
+fn nested_calls() {
+    let names = Names::new();
+    let source = "\
+macro inner(value)
+    LDA #value
+endmacro
+macro outer(value)
+    %inner(value + 1)
+    if value > 1
+        %inner(value)
+    endif
+endmacro
+%outer(2)
+";
+    let statements =
+        macros::expand(parse_program(source).unwrap(), &names, DEFAULT_DEPTH_LIMIT).unwrap();
+    match statements[2].node {
+        Statement::Expansion(ref expansion) => match expansion[0].node {
+            Statement::Expansion(ref inner) => {
+                assert_eq!(inner[0].node, parse_program("LDA #2 + 1").unwrap()[0].node)
+            }
+            ref statement => panic!("unexpected statement: {:?}", statement),
+        },
+        ref statement => panic!("unexpected statement: {:?}", statement),
+    }
+    let output = output::assemble(statements, 0x808000, State::new(true, true)).unwrap();
+    assert_eq!(output.data, [0xA9, 0x03, 0xA9, 0x02]);
+}
+
+#[test]
+fn source_map_expansions() {
+    let names = Names::new();
+    let source = "macro two()\nNOP\n%one()\nendmacro\nmacro one()\nRTS\nendmacro\n%two()\n";
+    let output = assemble(source, &names);
+    assert_eq!(output.mappings.len(), 2);
+    let spans: Vec<_> = output.mappings[0]
+        .expansion
+        .iter()
+        .map(|span| span.fragment())
+        .collect();
+    assert_eq!(spans, ["%two()"]);
+    let spans: Vec<_> = output.mappings[1]
+        .expansion
+        .iter()
+        .map(|span| span.fragment())
+        .collect();
+    assert_eq!(spans, ["%one()", "%two()"]);
+    assert_eq!(output.mappings[1].span.fragment(), "RTS");
+}
+
+#[test]
+fn errors() {
+    let names = Names::new();
+    match expand_error("%missing()\n", &names) {
+        Error::Undefined(span, "missing") => assert_eq!(span.fragment(), "%missing()"),
+        error => panic!("{:?}", error),
+    }
+    match expand_error("macro one(a)\nendmacro\n%one(1, 2)\n", &names) {
+        Error::Arguments {
+            expected: 1,
+            found: 2,
+            ..
+        } => {}
+        error => panic!("{:?}", error),
+    }
+    match expand_error("macro loop()\n%loop()\nendmacro\n%loop()\n", &names) {
+        Error::DepthLimit(span) => assert_eq!(span.fragment(), "%loop()"),
+        error => panic!("{:?}", error),
+    }
+    match expand_error(
+        "macro one()\nendmacro\nmacro one()\nNOP\nendmacro\n",
+        &names,
+    ) {
+        Error::Redefined(_, "one") => {}
+        error => panic!("{:?}", error),
+    }
+    match expand_error("if 1\nmacro one()\nendmacro\nendif\n", &names) {
+        Error::Nested(span) => assert!(span.fragment().starts_with("macro one()")),
+        error => panic!("{:?}", error),
+    }
+    assert_eq!(
+        expand_error("%missing()\n", &names).to_string(),
+        "macro `missing` is not defined"
+    );
+}
+
+#[test]
+fn unexpanded_call() {
+    let statements = parse_program("%missing(1)\n").unwrap();
+    match output::assemble(statements, 0x8000, State::new(true, true)) {
+        Err(output::Error::UnsupportedStatement(span)) => {
+            assert_eq!(span.fragment(), "%missing(1)")
+        }
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn evaluated_arguments() {
+    let statements = parse_program("macro back(#target, label)\nendmacro\n").unwrap();
+    match statements[0].node {
+        Statement::Macro(ref definition) => {
+            assert_eq!(
+                definition.parameters,
+                [VariableName("target"), VariableName("label")]
+            );
+            assert_eq!(definition.evaluated, [VariableName("target")]);
+        }
+        ref statement => panic!("unexpected statement: {:?}", statement),
+    }
+    let names = Names::new();
+    let source = "\
+macro back(target)
+    NOP
+    BRA target
+endmacro
+macro evaluated_back(#target)
+    NOP
+    BRA target
+endmacro
+macro load(#value)
+    LDA value
+endmacro
+%back($)
+%evaluated_back($)
+%load($10 + 2)
+";
+    let output = assemble(source, &names);
+    assert_eq!(
+        output.data,
+        [0xEA, 0x80, 0xFE, 0xEA, 0x80, 0xFD, 0xA5, 0x12]
+    );
+}
+
+#[test]
+fn variadic() {
+    let names = Names::new();
+    let source = "\
+macro loads(first, ...)
+    LDA #first
+    if sizeof(...) > 0
+        %loads(<...>)
+    endif
+endmacro
+macro count(...)
+    LDX #sizeof(...)
+    %loads(1, <...>)
+endmacro
+%count(2, 3)
+";
+    let output = assemble(source, &names);
+    assert_eq!(
+        output.data,
+        [0xA2, 0x02, 0xA9, 0x01, 0xA9, 0x02, 0xA9, 0x03]
+    );
+}
+
+#[test]
+fn variadic_errors() {
+    let names = Names::new();
+    let source = "macro two(a, b, ...)\nendmacro\n%two(1)\n";
+    let error = expand_error(source, &names);
+    assert_eq!(
+        error.to_string(),
+        "macro takes at least 2 arguments, but 1 were given"
+    );
+    match expand_error("macro one()\n%one(<...>)\nendmacro\n%one()\n", &names) {
+        Error::Splice(span) => assert_eq!(span.fragment(), "%one(<...>)"),
+        error => panic!("{:?}", error),
+    }
+    let statements = parse_program("LDA #sizeof(...)\n").unwrap();
+    match output::assemble(statements, 0x8000, State::new(true, true)) {
+        Err(output::Error::Symbol(_, symbols::Error::Eval(eval::Error::ArgumentCount))) => {}
+        result => panic!("{:?}", result),
+    }
+}
@@ -0,0 +1,183 @@
+extern crate mvp;
+
+use mvp::mapping::Mapper;
+
+const MAPPERS: [Mapper; 5] = [
+    Mapper::LoRom,
+    Mapper::HiRom,
+    Mapper::ExHiRom,
+    Mapper::Sa1Rom,
+    Mapper::SfxRom,
+];
+
+fn check(mapper: Mapper, pairs: &[(u32, Option<usize>)]) {
+    for &(address, offset) in pairs {
+        assert_eq!(
+            mapper.file_offset(address),
+            offset,
+            "{:?} ${:06X}",
+            mapper,
+            address
+        );
+    }
+}
+
+#[test]
+fn lorom() {
+    check(
+        Mapper::LoRom,
+        &[
+            (0x000000, None),
+            (0x007FFF, None),
+            (0x008000, Some(0x000000)),
+            (0x00FFFF, Some(0x007FFF)),
+            (0x018000, Some(0x008000)),
+            (0x3FFFFF, Some(0x1FFFFF)),
+            (0x408000, Some(0x200000)),
+            (0x7DFFFF, Some(0x3EFFFF)),
+            (0x7E8000, None),
+            (0x7FFFFF, None),
+            (0x808000, Some(0x000000)),
+            (0xFE8000, Some(0x3F0000)),
+            (0xFFFFFF, Some(0x3FFFFF)),
+        ],
+    );
+}
+
+#[test]
+fn hirom() {
+    check(
+        Mapper::HiRom,
+        &[
+            (0x000000, None),
+            (0x007FFF, None),
+            (0x008000, Some(0x008000)),
+            (0x3FFFFF, Some(0x3FFFFF)),
+            (0x400000, Some(0x000000)),
+            (0x7DFFFF, Some(0x3DFFFF)),
+            (0x7E0000, None),
+            (0x7FFFFF, None),
+            (0x807FFF, None),
+            (0x808000, Some(0x008000)),
+            (0xBFFFFF, Some(0x3FFFFF)),
+            (0xC00000, Some(0x000000)),
+            (0xFFFFFF, Some(0x3FFFFF)),
+        ],
+    );
+}
+
+#[test]
+fn exhirom() {
+    check(
+        Mapper::ExHiRom,
+        &[
+            (0x007FFF, None),
+            (0x008000, Some(0x408000)),
+            (0x00FFC0, Some(0x40FFC0)),
+            (0x3FFFFF, Some(0x7FFFFF)),
+            (0x400000, Some(0x400000)),
+            (0x7DFFFF, Some(0x7DFFFF)),
+            (0x7E0000, None),
+            (0x807FFF, None),
+            (0x808000, Some(0x008000)),
+            (0xBFFFFF, Some(0x3FFFFF)),
+            (0xC00000, Some(0x000000)),
+            (0xFFFFFF, Some(0x3FFFFF)),
+        ],
+    );
+}
+
+#[test]
+fn sa1rom() {
+    check(
+        Mapper::Sa1Rom,
+        &[
+            (0x007FFF, None),
+            (0x008000, Some(0x000000)),
+            (0x3FFFFF, Some(0x1FFFFF)),
+            (0x408000, None),
+            (0x808000, Some(0x200000)),
+            (0xBFFFFF, Some(0x3FFFFF)),
+            (0xC00000, Some(0x000000)),
+            (0xFFFFFF, Some(0x3FFFFF)),
+        ],
+    );
+}
+
+#[test]
+fn sfxrom() {
+    check(
+        Mapper::SfxRom,
+        &[
+            (0x007FFF, None),
+            (0x008000, Some(0x000000)),
+            (0x3FFFFF, Some(0x1FFFFF)),
+            (0x400000, Some(0x000000)),
+            (0x5FFFFF, Some(0x1FFFFF)),
+            (0x600000, None),
+            (0x808000, Some(0x000000)),
+            (0xBFFFFF, Some(0x1FFFFF)),
+            (0xC00000, Some(0x000000)),
+            (0xDFFFFF, Some(0x1FFFFF)),
+            (0xE00000, None),
+        ],
+    );
+}
+
+#[test]
+fn addresses() {
+    for &(mapper, offset, address) in &[
+        (Mapper::LoRom, 0x000000, Some(0x808000)),
+        (Mapper::LoRom, 0x3F0000, Some(0xFE8000)),
+        (Mapper::LoRom, 0x3FFFFF, Some(0xFFFFFF)),
+        (Mapper::LoRom, 0x400000, None),
+        (Mapper::HiRom, 0x000000, Some(0xC00000)),
+        (Mapper::HiRom, 0x3FFFFF, Some(0xFFFFFF)),
+        (Mapper::HiRom, 0x400000, None),
+        (Mapper::ExHiRom, 0x3FFFFF, Some(0xFFFFFF)),
+        (Mapper::ExHiRom, 0x400000, Some(0x400000)),
+        (Mapper::ExHiRom, 0x7DFFFF, Some(0x7DFFFF)),
+        (Mapper::ExHiRom, 0x7E0000, None),
+        (Mapper::ExHiRom, 0x7E8000, Some(0x3E8000)),
+        (Mapper::ExHiRom, 0x7FFFFF, Some(0x3FFFFF)),
+        (Mapper::ExHiRom, 0x800000, None),
+        (Mapper::Sa1Rom, 0x1FFFFF, Some(0x3FFFFF)),
+        (Mapper::Sa1Rom, 0x200000, Some(0x808000)),
+        (Mapper::Sa1Rom, 0x3FFFFF, Some(0xBFFFFF)),
+        (Mapper::Sa1Rom, 0x400000, None),
+        (Mapper::SfxRom, 0x1FFFFF, Some(0x3FFFFF)),
+        (Mapper::SfxRom, 0x200000, None),
+    ] {
+        assert_eq!(
+            mapper.address(offset),
+            address,
+            "{:?} {:#X}",
+            mapper,
+            offset
+        );
+    }
+}
+
+/// Every mapped address round trips through its file offset, and every
+/// file offset round trips through its address.
+#[test]
+fn round_trip() {
+    for &mapper in &MAPPERS {
+        for bank in 0..0x100 {
+            for &low in &[0x0000, 0x7FFF, 0x8000, 0xFFFF] {
+                let address = bank << 16 | low;
+                if let Some(offset) = mapper.file_offset(address) {
+                    let canonical = mapper.address(offset).unwrap();
+                    assert_eq!(mapper.file_offset(canonical), Some(offset));
+                }
+            }
+        }
+        for offset in (0..0x80_0000).step_by(0x8000) {
+            for &offset in &[offset, offset + 0x7FFF] {
+                if let Some(address) = mapper.address(offset) {
+                    assert_eq!(mapper.file_offset(address), Some(offset), "{:?}", mapper);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+extern crate mvp;
+
+use mvp::assembler::flags::State;
+use mvp::assembler::output;
+use mvp::encoder::{self, infer_mode, AddressingMode, OperandKind};
+use mvp::parser::ast::IndexRegister;
+use mvp::parser::grammar::parse_program;
+use mvp::symbols::Symbols;
+use mvp::warning::{Category, Level, Levels};
+
+#[test]
+fn documentation_is_up_to_date() {
+    let documentation = include_str!("../docs/addressing-modes.md");
+    let table = encoder::mode_table_markdown();
+    assert!(
+        documentation.ends_with(&table),
+        "docs/addressing-modes.md is outdated, the table should be:\n{}",
+        table
+    );
+}
+
+#[test]
+fn json_matches_table() {
+    let table = encoder::mode_table();
+    let json = encoder::mode_table_json();
+    let rows = json.as_array().unwrap();
+    assert_eq!(rows.len(), table.len());
+    for (row, json) in table.iter().zip(rows) {
+        let mode = row.mode.map(AddressingMode::syntax);
+        assert_eq!(json["mode"].as_str(), mode);
+    }
+}
+
+/// Source of an operand of each kind, with definitions it needs.
+fn operand(kind: OperandKind) -> &'static str {
+    match kind {
+        OperandKind::ByteLiteral => "$12",
+        OperandKind::WordLiteral => "$0012",
+        OperandKind::LongLiteral => "$000012",
+        OperandKind::DirectPage => "Near",
+        OperandKind::InBank => "Data",
+        OperandKind::OtherBank => "Far",
+        OperandKind::Unknown => "Later",
+    }
+}
+
+#[test]
+fn assembler_follows_table() {
+    let mut levels = Levels::default();
+    levels.set(Category::TruncatedOperand, Level::Allow);
+    levels.set(Category::BankMismatch, Level::Allow);
+    for row in encoder::mode_table() {
+        // LDA has no modes indexed by Y which would tell sizes apart.
+        let name = if row.index == Some(IndexRegister::Y) {
+            "LDX"
+        } else {
+            "LDA"
+        };
+        let suffix = match row.suffix {
+            None => "",
+            Some(1) => ".b",
+            Some(2) => ".w",
+            Some(_) => ".l",
+        };
+        let index = match row.index {
+            None => "",
+            Some(IndexRegister::X) => ",x",
+            Some(IndexRegister::Y) => ",y",
+            Some(IndexRegister::S) => ",s",
+        };
+        let source = format!(
+            "Near = 18\nData = $1234\nFar = $7F1234\n{}{} {}{}\nLater = $12\n",
+            name,
+            suffix,
+            operand(row.operand),
+            index
+        );
+        let statements = parse_program(&*source).unwrap();
+        let result = output::assemble_with_levels(
+            statements,
+            0x8000,
+            State::new(true, true),
+            Symbols::new(),
+            levels,
+        );
+        match (row.mode, result) {
+            (Some(mode), Ok(output)) => {
+                let opcode = encoder::lookup(name, mode).unwrap();
+                let size = mode.operand_size().unwrap() as usize;
+                assert_eq!(output.data[0], opcode, "{:?}: {}", row, source);
+                assert_eq!(output.data.len(), 1 + size, "{:?}: {}", row, source);
+            }
+            (None, Err(_)) => {}
+            (_, result) => panic!("{:?}: {} assembled to {:?}", row, source, result),
+        }
+    }
+}
+
+#[test]
+fn suffix_wins() {
+    for &operand in &[
+        OperandKind::ByteLiteral,
+        OperandKind::LongLiteral,
+        OperandKind::DirectPage,
+        OperandKind::Unknown,
+    ] {
+        for &(suffix, mode) in &[
+            (1, AddressingMode::DirectPage),
+            (2, AddressingMode::Absolute),
+            (3, AddressingMode::AbsoluteLong),
+        ] {
+            assert_eq!(infer_mode(operand, Some(suffix), None), Some(mode));
+        }
+    }
+}
+
+#[test]
+fn operand_kinds() {
+    let infer = |operand| infer_mode(operand, None, Some(IndexRegister::X));
+    assert_eq!(
+        infer(OperandKind::ByteLiteral),
+        Some(AddressingMode::DpIndexedX)
+    );
+    assert_eq!(
+        infer(OperandKind::WordLiteral),
+        Some(AddressingMode::AbsoluteIndexedX)
+    );
+    assert_eq!(
+        infer(OperandKind::DirectPage),
+        Some(AddressingMode::DpIndexedX)
+    );
+    assert_eq!(
+        infer(OperandKind::InBank),
+        Some(AddressingMode::AbsoluteIndexedX)
+    );
+    assert_eq!(
+        infer(OperandKind::OtherBank),
+        Some(AddressingMode::AbsoluteLongIndexedX)
+    );
+    assert_eq!(
+        infer(OperandKind::Unknown),
+        Some(AddressingMode::AbsoluteIndexedX)
+    );
+}
+
+#[test]
+fn invalid_combinations() {
+    let infer = |suffix, index| infer_mode(OperandKind::Unknown, suffix, index);
+    assert_eq!(infer(Some(3), Some(IndexRegister::Y)), None);
+    assert_eq!(infer(Some(2), Some(IndexRegister::S)), None);
+    assert_eq!(infer(Some(4), None), None);
+    assert_eq!(
+        infer(None, Some(IndexRegister::S)),
+        Some(AddressingMode::StackRelative)
+    );
+}
+
+#[test]
+fn long_literals() {
+    let infer = |index| infer_mode(OperandKind::LongLiteral, None, index);
+    assert_eq!(infer(None), Some(AddressingMode::AbsoluteLong));
+    assert_eq!(
+        infer(Some(IndexRegister::X)),
+        Some(AddressingMode::AbsoluteLongIndexedX)
+    );
+    assert_eq!(infer(Some(IndexRegister::Y)), None);
+}
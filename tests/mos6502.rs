@@ -0,0 +1,113 @@
+extern crate mvp;
+
+use mvp::assembler::flags::{self, State};
+use mvp::encoder::{self, lookup_in, resolve_mode_in, AddressingMode, Arch, LookupError};
+use mvp::parser::ast::Statement;
+use mvp::parser::grammar::{parse_program, parse_statement};
+
+fn available(arch: Arch) -> usize {
+    encoder::instructions()
+        .iter()
+        .filter(|&&(name, mode, opcode)| lookup_in(arch, name, mode) == Ok(opcode))
+        .count()
+}
+
+#[test]
+fn instruction_counts() {
+    assert_eq!(available(Arch::W6502), 151);
+    assert_eq!(available(Arch::W65C02), 180);
+    assert_eq!(available(Arch::W65816), 256);
+    assert_eq!(available(Arch::Spc700), 0);
+}
+
+#[test]
+fn rejected_instructions() {
+    assert_eq!(
+        lookup_in(Arch::W65C02, "XBA", AddressingMode::Implied),
+        Err(LookupError::UnavailableInstruction("XBA", Arch::W65C02))
+    );
+    assert_eq!(
+        lookup_in(Arch::W6502, "STZ", AddressingMode::Absolute),
+        Err(LookupError::UnavailableInstruction("STZ", Arch::W6502))
+    );
+    assert_eq!(
+        lookup_in(Arch::W6502, "INC", AddressingMode::Accumulator),
+        Err(LookupError::UnsupportedMode(
+            "INC",
+            vec![
+                AddressingMode::DirectPage,
+                AddressingMode::Absolute,
+                AddressingMode::DpIndexedX,
+                AddressingMode::AbsoluteIndexedX,
+            ]
+        ))
+    );
+    assert_eq!(
+        lookup_in(Arch::W65C02, "INA", AddressingMode::Implied),
+        Ok(0x1A)
+    );
+    assert_eq!(
+        lookup_in(Arch::W6502, "LDZ", AddressingMode::Implied),
+        Err(LookupError::UnknownMnemonic(vec!["LDA", "LDX", "LDY"]))
+    );
+    assert_eq!(
+        LookupError::UnavailableInstruction("BRL", Arch::W6502).to_string(),
+        "BRL isn't available with arch 6502"
+    );
+}
+
+#[test]
+fn resolving_modes() {
+    let mode = |arch, source, value| match parse_statement(source) {
+        Ok((Statement::Opcode(opcode), _)) => resolve_mode_in(arch, &opcode, value, None),
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        mode(Arch::W6502, "LDA Value", Some(0x12)),
+        Ok(AddressingMode::DirectPage)
+    );
+    assert_eq!(
+        mode(Arch::W6502, "JMP Value", Some(0x12)),
+        Ok(AddressingMode::Absolute)
+    );
+    assert_eq!(
+        mode(Arch::W65C02, "BRA Value", None),
+        Ok(AddressingMode::Relative)
+    );
+    assert!(mode(Arch::W6502, "LDA Value", Some(0x7E0000)).is_err());
+    assert!(mode(Arch::W6502, "LDA [$12],y", None).is_err());
+    assert!(mode(Arch::W65C02, "LDA $12,s", None).is_err());
+    assert_eq!(
+        mode(Arch::W6502, "LDA.l Value", None),
+        Err(LookupError::UnsupportedMode(
+            "LDA",
+            vec![
+                AddressingMode::DirectPage,
+                AddressingMode::Absolute,
+                AddressingMode::Immediate,
+                AddressingMode::DpIndexedX,
+                AddressingMode::AbsoluteIndexedX,
+                AddressingMode::AbsoluteIndexedY,
+                AddressingMode::DpIndexedIndirectX,
+                AddressingMode::DpIndirectIndexedIndexY,
+            ]
+        ))
+    );
+}
+
+#[test]
+fn arch_directive() {
+    let statements = parse_program("arch 6502\nLDA #1\narch 65C02\nSTZ $12\n").unwrap();
+    assert_eq!(*statements[0], Statement::Arch(Arch::W6502));
+    assert_eq!(*statements[2], Statement::Arch(Arch::W65C02));
+    assert!(matches!(*statements[3], Statement::Opcode(_)));
+}
+
+#[test]
+fn registers_are_8_bit() {
+    let mut statements = parse_program("arch 6502\nLDA #1\nMain:\nLDX #2\n").unwrap();
+    let unknown = flags::size_immediates(&mut statements, State::default());
+    assert!(unknown.is_empty());
+    let expected = parse_program("arch 6502\nLDA.b #1\nMain:\nLDX.b #2\n").unwrap();
+    assert_eq!(statements, expected);
+}
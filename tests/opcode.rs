@@ -1,243 +1,400 @@
 extern crate mvp;
 
 use mvp::parser::ast::{
-    BinaryOperator, Expression, Label, Number, NumberWidth, Opcode, OpcodeMode, Statement,
-    VariableName,
+    BinaryOperator, Expression, IndexRegister, Label, Number, NumberWidth, Opcode, OpcodeMode,
+    Statement, VariableName,
 };
-use mvp::parser::grammar::{statement, CompleteStr};
+use mvp::parser::grammar::{parse_statement, Input, ParserOptions};
 
 fn opcode(width: Option<u32>, mode: OpcodeMode) -> Statement {
     Statement::Opcode(Opcode {
         name: "LDA",
         width: width,
         mode: mode,
-        value: Expression::Number(Number {
-            value: 19,
-            width: NumberWidth::None,
-        }),
+        value: Some(
+            Expression::Number(Number {
+                value: 19,
+                width: NumberWidth::None,
+            })
+            .into(),
+        ),
     })
 }
 
 #[test]
 fn address() {
-    let input = CompleteStr("LDA 19 :");
-    let result = statement(input);
-    let expected = Ok((CompleteStr(":"), opcode(None, OpcodeMode::Address)));
+    let input = Input::new("LDA 19 :");
+    let result = parse_statement(input);
+    let expected = Ok((opcode(None, OpcodeMode::Address), Input::new(":")));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn indirect() {
-    let input = CompleteStr("LDA (19) :");
-    let result = statement(input);
-    let expected = Ok((CompleteStr(":"), opcode(None, OpcodeMode::Indirect)));
+    let input = Input::new("LDA (19) :");
+    let result = parse_statement(input);
+    let expected = Ok((opcode(None, OpcodeMode::Indirect), Input::new(":")));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn tricky_address() {
-    let input = CompleteStr("LDA ($19)+2 :");
-    let result = statement(input);
+    let input = Input::new("LDA ($19)+2 :");
+    let result = parse_statement(input);
     let expected = Ok((
-        CompleteStr(":"),
         Statement::Opcode(Opcode {
             name: "LDA",
             width: None,
             mode: OpcodeMode::Address,
-            value: Expression::Binary(
-                BinaryOperator::Add,
-                Box::new((
-                    Expression::Number(Number {
-                        value: 0x19,
-                        width: NumberWidth::OneByte,
-                    }),
-                    Expression::Number(Number {
-                        value: 2,
-                        width: NumberWidth::None,
-                    }),
-                )),
+            value: Some(
+                Expression::Binary(
+                    BinaryOperator::Add,
+                    Box::new((
+                        Expression::Number(Number {
+                            value: 0x19,
+                            width: NumberWidth::OneByte,
+                        })
+                        .into(),
+                        Expression::Number(Number {
+                            value: 2,
+                            width: NumberWidth::None,
+                        })
+                        .into(),
+                    )),
+                )
+                .into(),
             ),
         }),
+        Input::new(":"),
     ));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn tricky_address_with_spaces() {
-    let input = CompleteStr("LDA ( $19 ) + 2 :");
-    let result = statement(input);
+    let input = Input::new("LDA ( $19 ) + 2 :");
+    let result = parse_statement(input);
     let expected = Ok((
-        CompleteStr(":"),
         Statement::Opcode(Opcode {
             name: "LDA",
             width: None,
             mode: OpcodeMode::Address,
-            value: Expression::Binary(
-                BinaryOperator::Add,
-                Box::new((
-                    Expression::Number(Number {
-                        value: 0x19,
-                        width: NumberWidth::OneByte,
-                    }),
-                    Expression::Number(Number {
-                        value: 2,
-                        width: NumberWidth::None,
-                    }),
-                )),
+            value: Some(
+                Expression::Binary(
+                    BinaryOperator::Add,
+                    Box::new((
+                        Expression::Number(Number {
+                            value: 0x19,
+                            width: NumberWidth::OneByte,
+                        })
+                        .into(),
+                        Expression::Number(Number {
+                            value: 2,
+                            width: NumberWidth::None,
+                        })
+                        .into(),
+                    )),
+                )
+                .into(),
             ),
         }),
+        Input::new(":"),
     ));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn immediate() {
-    let input = CompleteStr("LDA # 19");
-    let result = statement(input);
-    let expected = Ok((CompleteStr(""), opcode(None, OpcodeMode::Immediate)));
+    let input = Input::new("LDA # 19");
+    let result = parse_statement(input);
+    let expected = Ok((opcode(None, OpcodeMode::Immediate), Input::new("")));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn opcode_width() {
-    let input = CompleteStr("LDA.w # ( 19 )");
-    let result = statement(input);
-    let expected = Ok((CompleteStr(""), opcode(Some(2), OpcodeMode::Immediate)));
+    let input = Input::new("LDA.w # ( 19 )");
+    let result = parse_statement(input);
+    let expected = Ok((opcode(Some(2), OpcodeMode::Immediate), Input::new("")));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn uppercase_opcode_width() {
-    let input = CompleteStr("LDA.W # (19)");
-    let result = statement(input);
-    let expected = Ok((CompleteStr(""), opcode(Some(2), OpcodeMode::Immediate)));
+    let input = Input::new("LDA.W # (19)");
+    let result = parse_statement(input);
+    let expected = Ok((opcode(Some(2), OpcodeMode::Immediate), Input::new("")));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn opcode_width_with_spaces() {
-    let input = CompleteStr("LDA . w #19");
-    let result = statement(input);
-    let expected = Ok((CompleteStr(""), opcode(Some(2), OpcodeMode::Immediate)));
+    let input = Input::new("LDA . w #19");
+    let result = parse_statement(input);
+    let expected = Ok((opcode(Some(2), OpcodeMode::Immediate), Input::new("")));
     assert_eq!(result, expected);
 }
 
+#[test]
+fn scoped_label_is_not_a_width() {
+    let (statement, _) = parse_statement("BRA .loop").unwrap();
+    let expected = Statement::Opcode(Opcode {
+        name: "BRA",
+        width: None,
+        mode: OpcodeMode::Address,
+        value: Some(Expression::Variable(Label::Scoped(VariableName("loop"))).into()),
+    });
+    assert_eq!(statement, expected);
+}
+
 #[test]
 fn x_address() {
-    let input = CompleteStr("LDA 19,x:");
-    let result = statement(input);
+    let input = Input::new("LDA 19,x:");
+    let result = parse_statement(input);
     let second = Expression::Variable(Label::Named(VariableName("x")));
     let expected = Ok((
-        CompleteStr(":"),
-        opcode(None, OpcodeMode::Move { second: second }),
+        opcode(
+            None,
+            OpcodeMode::Move {
+                second: second.into(),
+            },
+        ),
+        Input::new(":"),
     ));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn case_insensitive_x_address() {
-    let input = CompleteStr("LDA 19 , X:");
-    let result = statement(input);
+    let input = Input::new("LDA 19 , X:");
+    let result = parse_statement(input);
     let second = Expression::Variable(Label::Named(VariableName("X")));
     let expected = Ok((
-        CompleteStr(":"),
-        opcode(None, OpcodeMode::Move { second: second }),
+        opcode(
+            None,
+            OpcodeMode::Move {
+                second: second.into(),
+            },
+        ),
+        Input::new(":"),
     ));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn y_address() {
-    let input = CompleteStr("LDA 19 , y :");
-    let result = statement(input);
+    let input = Input::new("LDA 19 , y :");
+    let result = parse_statement(input);
     let second = Expression::Variable(Label::Named(VariableName("y")));
     let expected = Ok((
-        CompleteStr(":"),
-        opcode(None, OpcodeMode::Move { second: second }),
+        opcode(
+            None,
+            OpcodeMode::Move {
+                second: second.into(),
+            },
+        ),
+        Input::new(":"),
     ));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn stack_address() {
-    let input = CompleteStr(" LDA 19    ,    s  :");
-    let result = statement(input);
+    let input = Input::new(" LDA 19    ,    s  :");
+    let result = parse_statement(input);
     let second = Expression::Variable(Label::Named(VariableName("s")));
     let expected = Ok((
-        CompleteStr(":"),
-        opcode(None, OpcodeMode::Move { second: second }),
+        opcode(
+            None,
+            OpcodeMode::Move {
+                second: second.into(),
+            },
+        ),
+        Input::new(":"),
     ));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn x_indirect() {
-    let input = CompleteStr("LDA ( 19 , x ) ");
-    let result = statement(input);
-    let expected = Ok((CompleteStr(""), opcode(None, OpcodeMode::XIndirect)));
+    let input = Input::new("LDA ( 19 , x ) ");
+    let result = parse_statement(input);
+    let expected = Ok((opcode(None, OpcodeMode::XIndirect), Input::new("")));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn indirect_y() {
-    let input = CompleteStr(" LDA ( 19 ) , y ");
-    let result = statement(input);
-    let expected = Ok((CompleteStr(""), opcode(None, OpcodeMode::IndirectY)));
+    let input = Input::new(" LDA ( 19 ) , y ");
+    let result = parse_statement(input);
+    let expected = Ok((opcode(None, OpcodeMode::IndirectY), Input::new("")));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn stack_indirect_y() {
-    let input = CompleteStr(" LDA ( 19 , s ) , y ");
-    let result = statement(input);
-    let expected = Ok((CompleteStr(""), opcode(None, OpcodeMode::StackIndirectY)));
+    let input = Input::new(" LDA ( 19 , s ) , y ");
+    let result = parse_statement(input);
+    let expected = Ok((opcode(None, OpcodeMode::StackIndirectY), Input::new("")));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn case_insensitive_stack_indirect_y() {
-    let input = CompleteStr(" LDA ( 19 , S ) , Y ");
-    let result = statement(input);
-    let expected = Ok((CompleteStr(""), opcode(None, OpcodeMode::StackIndirectY)));
+    let input = Input::new(" LDA ( 19 , S ) , Y ");
+    let result = parse_statement(input);
+    let expected = Ok((opcode(None, OpcodeMode::StackIndirectY), Input::new("")));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn long_indirect() {
-    let input = CompleteStr(" LDA [ 19 ] :");
-    let result = statement(input);
-    let expected = Ok((CompleteStr(":"), opcode(None, OpcodeMode::LongIndirect)));
+    let input = Input::new(" LDA [ 19 ] :");
+    let result = parse_statement(input);
+    let expected = Ok((opcode(None, OpcodeMode::LongIndirect), Input::new(":")));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn long_indirect_y() {
-    let input = CompleteStr(" LDA [ 19 ] , y ");
-    let result = statement(input);
-    let expected = Ok((CompleteStr(""), opcode(None, OpcodeMode::LongIndirectY)));
+    let input = Input::new(" LDA [ 19 ] , y ");
+    let result = parse_statement(input);
+    let expected = Ok((opcode(None, OpcodeMode::LongIndirectY), Input::new("")));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn move_mode() {
-    let input = CompleteStr(" LDA 19 , 2 ");
-    let result = statement(input);
+    let input = Input::new(" LDA 19 , 2 ");
+    let result = parse_statement(input);
     let second = Expression::Number(Number {
         value: 2,
         width: NumberWidth::None,
     });
     let expected = Ok((
-        CompleteStr(""),
-        opcode(None, OpcodeMode::Move { second: second }),
+        opcode(
+            None,
+            OpcodeMode::Move {
+                second: second.into(),
+            },
+        ),
+        Input::new(""),
     ));
     assert_eq!(result, expected);
 }
 
 #[test]
 fn prefers_move_mode() {
-    let input = CompleteStr(" LDA 19 , s ");
-    let result = statement(input);
+    let input = Input::new(" LDA 19 , s ");
+    let result = parse_statement(input);
     let second = Expression::Variable(Label::Named(VariableName("s")));
-    let expected = Ok((CompleteStr(""), opcode(None, OpcodeMode::Move { second })));
+    let expected = Ok((
+        opcode(
+            None,
+            OpcodeMode::Move {
+                second: second.into(),
+            },
+        ),
+        Input::new(""),
+    ));
+    assert_eq!(result, expected);
+}
+
+fn index_register(input: &str) -> Option<IndexRegister> {
+    match parse_statement(Input::new(input)) {
+        Ok((Statement::Opcode(opcode), _)) => opcode.index_register(),
+        result => panic!("unexpected parse result: {:?}", result),
+    }
+}
+
+#[test]
+fn move_mode_index_registers() {
+    assert_eq!(index_register("LDA 19,x"), Some(IndexRegister::X));
+    assert_eq!(index_register("LDA 19,Y"), Some(IndexRegister::Y));
+    assert_eq!(index_register("LDA 19,s"), Some(IndexRegister::S));
+}
+
+#[test]
+fn block_moves_have_no_index_register() {
+    assert_eq!(index_register("MVN 19,x"), None);
+    assert_eq!(index_register("mvp 19,y"), None);
+}
+
+#[test]
+fn non_register_operands_have_no_index_register() {
+    assert_eq!(index_register("LDA 19,2"), None);
+    assert_eq!(index_register("LDA 19,xy"), None);
+    assert_eq!(index_register("LDA 19"), None);
+}
+
+#[test]
+fn implied() {
+    let input = Input::new("PHA ; save accumulator");
+    let result = parse_statement(input);
+    let expected = Ok((
+        Statement::Opcode(Opcode {
+            name: "PHA",
+            width: None,
+            mode: OpcodeMode::Implied,
+            value: None,
+        }),
+        Input::new("; save accumulator"),
+    ));
     assert_eq!(result, expected);
 }
+
+#[test]
+fn normalized_mnemonics() {
+    let options = ParserOptions {
+        normalize_mnemonics: true,
+        ..ParserOptions::default()
+    };
+    for &source in &["lda 19", "Lda 19", "LDA 19"] {
+        let result = parse_statement(Input::with_options(source, options));
+        let expected = Ok((opcode(None, OpcodeMode::Address), Input::new("")));
+        assert_eq!(result, expected, "{}", source);
+    }
+}
+
+#[test]
+fn mnemonics_are_kept_as_written_by_default() {
+    match parse_statement(Input::new("lda 19")) {
+        Ok((Statement::Opcode(opcode), _)) => assert_eq!(opcode.name, "lda"),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn unknown_mnemonics_are_not_normalized() {
+    let options = ParserOptions {
+        normalize_mnemonics: true,
+        ..ParserOptions::default()
+    };
+    match parse_statement(Input::with_options("ldq 19", options)) {
+        Ok((Statement::Opcode(opcode), _)) => assert_eq!(opcode.name, "ldq"),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn interrupt_signatures() {
+    let (statement, _) = parse_statement("BRK").unwrap();
+    match statement {
+        Statement::Opcode(ref opcode) => assert_eq!(opcode.mode, OpcodeMode::Implied),
+        _ => panic!("unexpected statement {:?}", statement),
+    }
+    let (statement, _) = parse_statement("COP #$12").unwrap();
+    let expected = Statement::Opcode(Opcode {
+        name: "COP",
+        width: None,
+        mode: OpcodeMode::Immediate,
+        value: Some(
+            Expression::Number(Number {
+                value: 0x12,
+                width: NumberWidth::OneByte,
+            })
+            .into(),
+        ),
+    });
+    assert_eq!(statement, expected);
+}
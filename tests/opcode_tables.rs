@@ -0,0 +1,54 @@
+//! Checks of instruction tables generated from `src/encoder/tables`.
+
+extern crate mvp;
+
+use std::collections::HashSet;
+
+use mvp::encoder::{self, spc700};
+
+mod w65816 {
+    use mvp::encoder::AddressingMode::{self, *};
+
+    pub const INSTRUCTIONS: &[(&str, AddressingMode, u8)] =
+        include!(concat!(env!("OUT_DIR"), "/w65816.rs"));
+}
+
+mod spc700_table {
+    use mvp::encoder::spc700::Mode::{self, *};
+
+    pub const INSTRUCTIONS: &[(&str, &[Mode], u8)] =
+        include!(concat!(env!("OUT_DIR"), "/spc700.rs"));
+}
+
+#[test]
+fn w65816_table_is_used_for_lookups() {
+    assert_eq!(encoder::instructions(), w65816::INSTRUCTIONS);
+    for &(name, mode, opcode) in w65816::INSTRUCTIONS {
+        assert_eq!(
+            encoder::lookup(name, mode),
+            Ok(opcode),
+            "{} {:?}",
+            name,
+            mode
+        );
+    }
+}
+
+#[test]
+fn w65816_opcodes_are_unique() {
+    let opcodes: HashSet<u8> = w65816::INSTRUCTIONS
+        .iter()
+        .map(|&(_, _, opcode)| opcode)
+        .collect();
+    assert_eq!(opcodes.len(), 256);
+    assert_eq!(w65816::INSTRUCTIONS.len(), 256);
+}
+
+#[test]
+fn spc700_base_opcodes_are_unique() {
+    assert_eq!(spc700::instructions(), spc700_table::INSTRUCTIONS);
+    let mut opcodes = HashSet::new();
+    for &(name, _, opcode) in spc700_table::INSTRUCTIONS {
+        assert!(opcodes.insert(opcode), "{} ${:02X}", name, opcode);
+    }
+}
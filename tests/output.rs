@@ -0,0 +1,400 @@
+extern crate mvp;
+
+use mvp::assembler::flags::State;
+use mvp::assembler::output::{self, Error, FixupKind, Output};
+use mvp::encoder::EncodeError;
+use mvp::eval;
+use mvp::parser::ast::{Expression, Label, Register, VariableName};
+use mvp::parser::grammar::parse_program;
+use mvp::rom::{self, BaseRomError};
+use mvp::symbols::{self, Symbols};
+use mvp::warning::Category;
+
+fn assemble(source: &str) -> Result<Output<'_>, Error<'_>> {
+    output::assemble(
+        parse_program(source).unwrap(),
+        0x808000,
+        State::new(true, true),
+    )
+}
+
+fn data(source: &str) -> Vec<u8> {
+    let output = assemble(source).unwrap();
+    assert!(output.fixups.is_empty(), "{:?}", output.fixups);
+    output.data
+}
+
+fn patch<'a>(source: &'a str, rom: &'a [u8]) -> Result<Output<'a>, Error<'a>> {
+    let mut symbols = Symbols::new();
+    symbols.set_base_rom(rom);
+    let statements = parse_program(source).unwrap();
+    output::assemble_with_symbols(statements, 0x808000, State::new(true, true), symbols)
+}
+
+fn variable(name: &str) -> Expression<'_> {
+    Expression::Variable(Label::Named(VariableName(name)))
+}
+
+#[test]
+fn instructions() {
+    assert_eq!(
+        data("NOP\nlda $12\nSTA $1234\nJSL $7E8000\n"),
+        [0xEA, 0xA5, 0x12, 0x8D, 0x34, 0x12, 0x22, 0x00, 0x80, 0x7E,]
+    );
+    assert_eq!(data("MVN $7E,$7F\nBRK\n"), [0x54, 0x7F, 0x7E, 0x00, 0x00]);
+    assert_eq!(data("DEA\nINC A\n"), [0x3A, 0x1A]);
+    assert_eq!(
+        data("dea\nina\nswa\ntad\ntda\nLoop:\nblt Loop\nbge Loop\n"),
+        [0x3A, 0x1A, 0xEB, 0x5B, 0x7B, 0x90, 0xFE, 0xB0, 0xFC]
+    );
+}
+
+#[test]
+fn immediates_follow_register_widths() {
+    assert_eq!(
+        data("LDA #$12\nREP #$30\nLDA #$1234\nLDX #$12\n"),
+        [0xA9, 0x12, 0xC2, 0x30, 0xA9, 0x34, 0x12, 0xA2, 0x12, 0x00,]
+    );
+    let statements = parse_program("LDA #$12\n").unwrap();
+    match output::assemble(statements, 0x8000, State::default()) {
+        Err(Error::UnknownWidth(_, Register::Accumulator)) => {}
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn forward_references() {
+    let source = "BRA End\nJMP End\nLDA Data,x\nEnd:\nRTS\nData = $808100\n";
+    assert_eq!(
+        data(source),
+        [0x80, 0x06, 0x4C, 0x08, 0x80, 0xBD, 0x00, 0x81, 0x60]
+    );
+    // Unknown operands are assumed to be absolute in the first pass.
+    match assemble("LDA Data\nData = $7E1234\n") {
+        Err(Error::Denied(ref warning)) if warning.category == Category::BankMismatch => {}
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn program_counter() {
+    assert_eq!(
+        data("NOP\nJMP $\nBRA $\n"),
+        [0xEA, 0x4C, 0x01, 0x80, 0x80, 0xFE]
+    );
+}
+
+#[test]
+fn labels_are_listed() {
+    let output = assemble("Main:\nNOP\n.loop:\nBRA .loop\n").unwrap();
+    let names: Vec<_> = output
+        .symbols
+        .iter()
+        .map(|symbol| (symbol.to_string(), symbol.value))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            ("Main".to_string(), 0x808000),
+            ("Main.loop".to_string(), 0x808001)
+        ]
+    );
+}
+
+#[test]
+fn unresolved_operands_become_fixups() {
+    let output = assemble("JSL External\nLDA Table+2,x\nBNE Far\nBRL Far\nMVN 0,Bank\n").unwrap();
+    assert_eq!(
+        output.data,
+        [0x22, 0, 0, 0, 0xBD, 0, 0, 0xD0, 0, 0x82, 0, 0, 0x54, 0, 0,]
+    );
+    let fixups: Vec<_> = output
+        .fixups
+        .iter()
+        .map(|fixup| (fixup.offset, fixup.width, fixup.kind))
+        .collect();
+    assert_eq!(
+        fixups,
+        [
+            (1, 3, FixupKind::Absolute),
+            (5, 2, FixupKind::Absolute),
+            (8, 1, FixupKind::Relative(0x808009)),
+            (10, 2, FixupKind::Relative(0x80800C)),
+            (13, 1, FixupKind::Absolute),
+        ]
+    );
+    assert_eq!(output.fixups[0].expression, variable("External"));
+    assert_eq!(output.fixups[0].span.fragment(), "JSL External");
+    assert_eq!(output.fixups[4].expression, variable("Bank"));
+}
+
+#[test]
+fn if_blocks() {
+    let source = "mode = 2\nif mode == 1\nNOP\nelseif mode == 2\nRTS\nelse\nRTL\nendif\n";
+    assert_eq!(data(source), [0x60]);
+    match assemble("if Later\nNOP\nendif\nLater:\n") {
+        Err(Error::UnresolvedCondition(_)) => {}
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn errors() {
+    match assemble("Main:\nMain:\n") {
+        Err(Error::Symbol(_, symbols::Error::Redefined(..))) => {}
+        result => panic!("{:?}", result),
+    }
+    match assemble("BRA Far\nFar = $809000\n") {
+        Err(Error::Encode(_, EncodeError::OutOfRange(_))) => {}
+        result => panic!("{:?}", result),
+    }
+    match assemble("NOP\nNOP\nwarnpc $808000\n") {
+        Err(Error::WarnPc(_, 0x808002)) => {}
+        result => panic!("{:?}", result),
+    }
+    // A byte written at the limit is past it.
+    match assemble("NOP\nwarnpc $808000\n") {
+        Err(Error::WarnPc(_, 0x808001)) => {}
+        result => panic!("{:?}", result),
+    }
+    assert!(assemble("NOP\nwarnpc $808001\n").is_ok());
+    match assemble("assert 0, \"failed\"\n") {
+        Err(Error::Symbol(_, symbols::Error::Eval(_))) => {}
+        result => panic!("{:?}", result),
+    }
+    match assemble("arch spc700\nNOP\n") {
+        Err(Error::UnsupportedStatement(_)) => {}
+        result => panic!("{:?}", result),
+    }
+    for source in &["bank $100\n", "bank Later\nLater = $7E\n"] {
+        match assemble(source) {
+            Err(Error::InvalidBank(_)) => {}
+            result => panic!("{:?}", result),
+        }
+    }
+}
+
+#[test]
+fn error_spans() {
+    let span = |source| match assemble(source) {
+        Err(Error::Symbol(span, _)) => span.fragment(),
+        result => panic!("{:?}", result),
+    };
+    assert_eq!(span("LDA #2 * (1 + 1/0)\n"), "1/0");
+    assert_eq!(span("LDA #1/0\n"), "1/0");
+    assert_eq!(span("MVN 1, 2 - -(1/0)\n"), "(1/0)");
+    assert_eq!(span("warnpc 1 + -(2 * \"a\")\n"), "\"a\"");
+    assert_eq!(span("!text := \"a\"\nLDA #!text + 1\n"), "!text");
+    assert_eq!(span("warnpc 1/0\n"), "warnpc 1/0");
+}
+
+#[test]
+fn mapping_functions_follow_mapper() {
+    assert_eq!(
+        data("LDA.w #snestopc($818000)\nhirom\nLDA.w #snestopc($C01234)\n"),
+        [0xA9, 0x00, 0x80, 0xA9, 0x34, 0x12]
+    );
+}
+
+#[test]
+fn defined_and_undef() {
+    let source = "\
+if defined(\"!debug\")
+    BRK
+endif
+!debug = 1
+if defined(\"!debug\")
+    NOP
+endif
+undef \"!debug\"
+if defined(\"!debug\") + defined(\"Main\")
+    BRK
+endif
+Main:
+    RTS
+";
+    assert_eq!(data(source), [0xEA, 0x60]);
+    let output = assemble("!debug = 1\nundef \"!debug\"\nLDA #!debug\n").unwrap();
+    assert!(output.symbols.is_empty());
+    assert_eq!(output.fixups[0].expression, variable("!debug"));
+    assert!(assemble("Main:\nundef \"Main\"\nLDA Main\n").is_ok());
+}
+
+#[test]
+fn string_assignments() {
+    let source = "\
+!prefix := \"sprite\"
+!name := concat(!prefix, \"_\", hex(Table + 4, 6))
+print !name, \" \", stringify(!prefix)
+!prefix = 1
+print hex(!prefix), defined(\"!name\")
+undef \"!name\"
+print defined(\"!name\")
+Table:
+";
+    let output = assemble(source).unwrap();
+    assert_eq!(output.messages, ["sprite_808004 !prefix", "11", "0"]);
+    match assemble("!text := \"a\"\nLDA #!text\n") {
+        Err(Error::Symbol(_, symbols::Error::Eval(eval::Error::ExpectedNumber))) => {}
+        result => panic!("{:?}", result),
+    }
+    match assemble("Main:\nMain := \"a\"\n") {
+        Err(Error::Symbol(span, symbols::Error::Redefined(..))) => {
+            assert_eq!(span.fragment(), "Main := \"a\"")
+        }
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn datasize() {
+    let source = "\
+    LDX.w #datasize(Table)
+    LDY.w #datasize(Last)
+Table:
+    NOP
+    NOP
+.end:
+    LDA.w #datasize(Table)
+Last:
+    RTS
+";
+    assert_eq!(
+        data(source),
+        [0xA2, 0x02, 0x00, 0xA0, 0x01, 0x00, 0xEA, 0xEA, 0xA9, 0x02, 0x00, 0x60]
+    );
+    match assemble("LDA.w #datasize(1 + 2)\n") {
+        Err(Error::Symbol(_, symbols::Error::Eval(eval::Error::InvalidArgument(_)))) => {}
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn find_pattern() {
+    let mut rom = vec![0; 0x10000];
+    rom[0x8123..0x8126].copy_from_slice(&[0xA9, 0x0F, 0x8D]);
+    let source = "JSL Found\nfindpattern \"A9 ?? 8D\" -> Found\n";
+    assert_eq!(patch(source, &rom).unwrap().data, [0x22, 0x23, 0x81, 0x81]);
+    match patch("findpattern \"A9 ?? 8E\" -> Found\n", &rom) {
+        Err(error @ Error::PatternMatches(_, 0)) => {
+            assert_eq!(error.to_string(), "pattern isn't found in the base ROM")
+        }
+        result => panic!("{:?}", result),
+    }
+    rom[0x9000..0x9003].copy_from_slice(&[0xA9, 0x01, 0x8D]);
+    match patch("findpattern \"A9 ?? 8D\" -> Found\n", &rom) {
+        Err(error @ Error::PatternMatches(_, 2)) => assert_eq!(
+            error.to_string(),
+            "pattern is found 2 times in the base ROM, it must be found once"
+        ),
+        result => panic!("{:?}", result),
+    }
+    match patch("findpattern \"A9 ?\" -> Found\n", &rom) {
+        Err(Error::InvalidPattern(_)) => {}
+        result => panic!("{:?}", result),
+    }
+    match assemble("findpattern \"A9\" -> Found\n") {
+        Err(Error::NoBaseRom(span)) => assert_eq!(span.fragment(), "findpattern \"A9\" -> Found"),
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn functions() {
+    let source =
+        "function f() = 1\nfunction scaled(x, y) = f() + x * y\nLDA #f()\nLDX.w #scaled(2, $10)\n";
+    assert_eq!(data(source), [0xA9, 0x01, 0xA2, 0x21, 0x00]);
+    // Functions are called after their definitions, which can change.
+    assert_eq!(
+        data("function f() = 1\nLDA #f()\nfunction f() = 2\nLDA #f()\n"),
+        [0xA9, 0x01, 0xA9, 0x02]
+    );
+    match assemble("LDA #f()\nfunction f() = 1\n") {
+        Err(error) => assert_eq!(error.to_string(), "function f doesn't exist"),
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn variants() {
+    let rom = vec![0; 0x10000];
+    let source = format!(
+        "romvariant US, $12345678\nromvariant JP, ${:08X}\n\
+         varianttable Routine, US = $808000, JP = $808010\n\
+         if romversion == JP\nLDA #1\nelse\nLDA #2\nendif\nJSL Routine\n",
+        rom::crc32(&rom)
+    );
+    assert_eq!(
+        patch(&source, &rom).unwrap().data,
+        [0xA9, 0x01, 0x22, 0x10, 0x80, 0x80]
+    );
+    match patch(&source, &[1]) {
+        Err(error @ Error::Variant(..)) => {
+            assert_eq!(error.span().fragment(), "romvariant US, $12345678");
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "base ROM with checksum {:08X} isn't a known variant",
+                    rom::crc32(&[1])
+                )
+            );
+        }
+        result => panic!("{:?}", result),
+    }
+    match assemble(&source) {
+        Err(Error::NoBaseRom(span)) => assert_eq!(span.fragment(), "romvariant US, $12345678"),
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn expect_crc() {
+    let mut rom = vec![0; 0x8000];
+    let source = format!("expectcrc $12345678, ${:08X}\nNOP\n", rom::crc32(&rom));
+    assert_eq!(patch(&source, &rom).unwrap().data, [0xEA]);
+    rom.splice(0..0, vec![0; 0x200]);
+    match patch(&source, &rom) {
+        Err(Error::BaseRom(span, BaseRomError::Headered)) => {
+            assert_eq!(span.fragment(), source.lines().next().unwrap())
+        }
+        result => panic!("{:?}", result),
+    }
+    rom.drain(..0x200);
+    rom.extend(vec![0xFF; 0x8000]);
+    match patch(&source, &rom) {
+        Err(error @ Error::BaseRom(..)) => assert_eq!(
+            error.to_string(),
+            "base ROM is overdumped, it's 0x10000 bytes long instead of 0x8000"
+        ),
+        result => panic!("{:?}", result),
+    }
+    match patch(&source, &[1]) {
+        Err(Error::BaseRom(_, BaseRomError::Unrecognized { region: None, .. })) => {}
+        result => panic!("{:?}", result),
+    }
+    match assemble("expectcrc $12345678\n") {
+        Err(Error::NoBaseRom(_)) => {}
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn width_suffix_mismatch() {
+    match assemble("LDA.b $1234\n") {
+        Err(error @ Error::WidthMismatch(..)) => {
+            assert_eq!(error.span().fragment(), "LDA.b $1234");
+            assert_eq!(
+                error.to_string(),
+                "operand needs 2 bytes, but its .b suffix selects 1"
+            );
+        }
+        result => panic!("{:?}", result),
+    }
+    match assemble("LDA.w #$123456\n") {
+        Err(error @ Error::WidthMismatch(..)) => assert_eq!(
+            error.to_string(),
+            "operand needs 3 bytes, but its .w suffix selects 2"
+        ),
+        result => panic!("{:?}", result),
+    }
+}
@@ -0,0 +1,40 @@
+extern crate mvp;
+
+use mvp::parser::ast::{Expression, Number, NumberWidth, Statement};
+use mvp::parser::grammar::{parse_program, Input, ParseErrorKind};
+use mvp::parser::owned::OwnedProgram;
+
+fn cached() -> Vec<OwnedProgram> {
+    (1..4)
+        .map(|value| OwnedProgram::new(format!("warnpc {}\nif 1\n", value)))
+        .collect()
+}
+
+#[test]
+fn outlives_source() {
+    let programs = cached();
+    for (value, program) in (1..).zip(&programs) {
+        let expected = Statement::WarnPc(Expression::Number(Number {
+            value,
+            width: NumberWidth::None,
+        }));
+        assert_eq!(program.statements()[0].node, expected);
+        assert_eq!(program.errors()[0].kind, ParseErrorKind::UnterminatedIf);
+        assert_eq!(
+            program.errors()[0].span.range(program.source()),
+            Some(9..13)
+        );
+    }
+}
+
+#[test]
+fn matches_borrowed_parse() {
+    let source = "Main:\n  LDA #$10 ; load\n  STA $2100\n";
+    let owned = OwnedProgram::new(source.to_string());
+    let borrowed = parse_program(Input::new(source)).unwrap();
+    assert_eq!(owned.statements(), &borrowed[..]);
+    assert!(owned.errors().is_empty());
+    let cloned = owned.clone();
+    drop(owned);
+    assert_eq!(cloned.statements()[1].span.fragment(), "LDA #$10");
+}
@@ -0,0 +1,282 @@
+extern crate mvp;
+
+use mvp::parser::ast::{
+    BinaryOperator, Condition, Expression, Label, Number, NumberWidth, Span, Spanned, Statement,
+    VariableName,
+};
+use mvp::parser::grammar::{parse_program, program_with_recovery, Expected, Input, ParseErrorKind};
+
+fn number(value: u32) -> Expression<'static> {
+    Expression::Number(Number {
+        value,
+        width: NumberWidth::None,
+    })
+}
+
+fn warnpc(value: u32) -> Spanned<'static, Statement<'static>> {
+    Statement::WarnPc(number(value)).into()
+}
+
+fn error(code: &'static str) -> Spanned<'static, Statement<'static>> {
+    Statement::Error(Span::new(code), None).into()
+}
+
+fn is_version(value: u32) -> Expression<'static> {
+    Expression::Binary(
+        BinaryOperator::Eq,
+        Box::new((
+            Expression::Variable(Label::Named(VariableName("version"))).into(),
+            number(value).into(),
+        )),
+    )
+}
+
+#[test]
+fn empty_program() {
+    assert_eq!(parse_program(Input::new("")), Ok(vec![]));
+}
+
+#[test]
+fn lines() {
+    let input = Input::new("warnpc 1\n\n  warnpc 2 ; comment\r\n; comment\nwarnpc 3");
+    let expected = vec![warnpc(1), warnpc(2), warnpc(3)];
+    assert_eq!(parse_program(input), Ok(expected));
+}
+
+#[test]
+fn statements_end_at_line_endings() {
+    let input = Input::new("warnpc 1\n+ 2");
+    assert!(parse_program(input).is_err());
+}
+
+#[test]
+fn if_statement() {
+    let input = Input::new(
+        "
+        if version == 1
+            warnpc 1
+        elseif version == 2 ; second version
+            warnpc 2
+            warnpc 3
+        ELSE
+        endif
+        warnpc 4
+        ",
+    );
+    let expected = vec![
+        Statement::If(vec![
+            Condition {
+                predicate: Some(is_version(1)),
+                statements: vec![warnpc(1)],
+            },
+            Condition {
+                predicate: Some(is_version(2)),
+                statements: vec![warnpc(2), warnpc(3)],
+            },
+            Condition {
+                predicate: None,
+                statements: vec![],
+            },
+        ])
+        .into(),
+        warnpc(4),
+    ];
+    assert_eq!(parse_program(input), Ok(expected));
+}
+
+#[test]
+fn nested_if_statement() {
+    let input = Input::new("if 1\nif 2\nwarnpc 1\nendif\nendif");
+    let expected = vec![Statement::If(vec![Condition {
+        predicate: Some(number(1)),
+        statements: vec![Statement::If(vec![Condition {
+            predicate: Some(number(2)),
+            statements: vec![warnpc(1)],
+        }])
+        .into()],
+    }])
+    .into()];
+    assert_eq!(parse_program(input), Ok(expected));
+}
+
+#[test]
+fn unterminated_if_statement() {
+    let input = Input::new("if 1\nwarnpc 1\n");
+    assert!(parse_program(input).is_err());
+}
+
+#[test]
+fn label_declarations() {
+    let input = Input::new("Main:\n.loop: warnpc 1\n");
+    let expected = vec![
+        Statement::Label(Label::Named(VariableName("Main"))).into(),
+        Statement::Label(Label::Scoped(VariableName("loop"))).into(),
+        warnpc(1),
+    ];
+    assert_eq!(parse_program(input), Ok(expected));
+}
+
+#[test]
+fn statement_spans() {
+    let source = "Main:\n  if 1 ; comment\n    warnpc 1  \n  endif\n";
+    let statements = parse_program(Input::new(source)).unwrap();
+    assert_eq!(statements[0].span.fragment(), "Main:");
+    assert_eq!(statements[1].span.range(source), Some(8..45));
+    assert_eq!(statements[1].span.line_column(source), Some((2, 3)));
+    match statements[1].node {
+        Statement::If(ref conditions) => {
+            let span = conditions[0].statements[0].span;
+            assert_eq!(span.fragment(), "warnpc 1");
+            assert_eq!(span.line_column(source), Some((3, 5)));
+        }
+        ref statement => panic!("unexpected statement: {:?}", statement),
+    }
+}
+
+#[test]
+fn recovery() {
+    let source = "
+warnpc 1 ; first
+LDA ($19,,x) ; bad
+if 1
+    warnpc 2
+    warnpc 3 +
+else
+    warnpc 4
+endif
+if 2
+    warnpc 5
+elseif
+    warnpc 6
+endif
+else
+if 3
+    warnpc 7
+";
+    let (statements, errors) = program_with_recovery(Input::new(source));
+    let expected = vec![
+        warnpc(1),
+        Statement::Error(Span::new("LDA ($19,,x)"), errors[0].syntax).into(),
+        Statement::If(vec![
+            Condition {
+                predicate: Some(number(1)),
+                statements: vec![
+                    warnpc(2),
+                    Statement::WarnPc(Expression::Binary(
+                        BinaryOperator::Add,
+                        Box::new((number(3).into(), Expression::Error(Span::default()).into())),
+                    ))
+                    .into(),
+                ],
+            },
+            Condition {
+                predicate: None,
+                statements: vec![warnpc(4)],
+            },
+        ])
+        .into(),
+        error("if 2\n    warnpc 5\nelseif\n    warnpc 6\nendif"),
+        error("else"),
+        error("if 3"),
+    ];
+    assert_eq!(statements, expected);
+    let errors: Vec<_> = errors
+        .iter()
+        .map(|error| {
+            let found = error.syntax.map(|syntax| syntax.found);
+            (error.span.fragment(), error.kind, found)
+        })
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            (
+                "LDA ($19,,x)",
+                ParseErrorKind::InvalidStatement,
+                Some(",x) ; bad")
+            ),
+            ("", ParseErrorKind::InvalidExpression, None),
+            ("elseif", ParseErrorKind::InvalidStatement, Some("elseif")),
+            ("else", ParseErrorKind::UnexpectedBlockKeyword, None),
+            ("if 3", ParseErrorKind::UnterminatedIf, None),
+        ]
+    );
+}
+
+#[test]
+fn recovered_if_span() {
+    let source = "if 1\n    warnpc 1 +\nendif ; done\nif 2\nendif  \n";
+    let (statements, errors) = program_with_recovery(Input::new(source));
+    assert_eq!(errors.len(), 1);
+    assert_eq!(statements[0].span.fragment(), "if 1\n    warnpc 1 +\nendif");
+    assert_eq!(statements[1].span.fragment(), "if 2\nendif");
+}
+
+#[test]
+fn error_position() {
+    let input = Input::new("warnpc 1\n  warnpc 2 +\n");
+    let error = parse_program(input).unwrap_err();
+    assert_eq!((error.offset, error.line, error.column), (21, 2, 13));
+}
+
+#[test]
+fn syntax_errors() {
+    let cases = [
+        ("LDA 19 :", (1, 8), ":", Expected::EndOfLine),
+        ("LDA (19\n", (1, 8), "", Expected::Closing(')')),
+        ("LDA [19\n", (1, 8), "", Expected::Closing(']')),
+        (
+            "warnpc 1\nwarnpc 2 + ] ; comment\n",
+            (2, 12),
+            "] ; comment",
+            Expected::Expression,
+        ),
+        (
+            "if 1\n  LDA ($19,,x)\nendif\n",
+            (2, 12),
+            ",x)",
+            Expected::Expression,
+        ),
+        ("warnpc 1\n  endif\n", (2, 3), "endif", Expected::Statement),
+        ("if 1\n  warnpc 1\n", (3, 1), "", Expected::Statement),
+    ];
+    for &(source, position, found, expected) in &cases {
+        let error = parse_program(Input::new(source)).unwrap_err();
+        assert_eq!(
+            ((error.line, error.column), error.found, error.expected),
+            (position, found, expected),
+            "{:?}",
+            source,
+        );
+        assert_eq!(&source[error.offset..][..found.len()], found);
+    }
+}
+
+#[test]
+fn parse_program_success() {
+    let statements = parse_program("warnpc 1\nLDA #2\nbase = $8000\n").unwrap();
+    assert_eq!(statements.len(), 3);
+    match statements[2].node {
+        Statement::Assignment(VariableName("base"), _) => {}
+        ref statement => panic!("unexpected statement: {:?}", statement),
+    }
+}
+
+#[test]
+fn invalid_operands() {
+    let source = "warnpc 1 + * 2 - 3\nif (1 +) == 1\nendif\n";
+    assert!(parse_program(Input::new(source)).is_err());
+    let (statements, errors) = program_with_recovery(Input::new(source));
+    assert_eq!(statements.len(), 2);
+    let errors: Vec<_> = errors
+        .iter()
+        .map(|error| (error.span.line_column(source), error.kind))
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            (Some((1, 12)), ParseErrorKind::InvalidExpression),
+            (Some((2, 8)), ParseErrorKind::InvalidExpression),
+        ]
+    );
+}
@@ -0,0 +1,365 @@
+extern crate mvp;
+
+use std::error::Error as _;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use mvp::assembler::branch::LongJump;
+use mvp::assembler::flags::State;
+use mvp::assembler::{Assembler, OutputKind};
+use mvp::encoder::Aliases;
+use mvp::include::{FileSystem, Overlay};
+use mvp::mapping::Mapper;
+use mvp::project::{Diagnostic, Error, Project};
+use mvp::warning::{Category, Level};
+
+/// File system without any files.
+struct Empty;
+
+impl FileSystem for Empty {
+    fn read(&self, _: &Path) -> io::Result<String> {
+        Err(io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+
+    fn exists(&self, _: &Path) -> bool {
+        false
+    }
+}
+
+fn load(files: &[(&str, &str)], roots: &[&str]) -> Project<Overlay<Empty>> {
+    let mut overlay = Overlay::new(Empty);
+    for &(path, contents) in files {
+        overlay.insert(path, contents);
+    }
+    let mut project = Project::new(overlay);
+    project.add_directory("/lib");
+    for &root in roots {
+        project.add_root(root);
+    }
+    project.load().unwrap();
+    project
+}
+
+fn diagnostics(project: &Project<Overlay<Empty>>) -> Vec<Diagnostic> {
+    let error = project
+        .assemble(0x8000, State::new(true, true))
+        .unwrap_err();
+    project.diagnostics(&error)
+}
+
+#[test]
+fn shared_symbols() {
+    let project = load(
+        &[
+            (
+                "/game/main.asm",
+                "incsrc \"macros.asm\"\nMain:\n%clear()\nJMP Loop\n",
+            ),
+            (
+                "/lib/macros.asm",
+                "macro clear()\nif 1\nincsrc \"stz.asm\"\nendif\nendmacro\n",
+            ),
+            ("/lib/stz.asm", "STZ $10\n"),
+            ("/game/loop.asm", "Loop:\nincsrc \"stz.asm\"\nBRA Main\n"),
+        ],
+        &["/game/main.asm", "/game/loop.asm"],
+    );
+    let paths: Vec<_> = project
+        .sources()
+        .iter()
+        .map(|source| source.path.clone())
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            PathBuf::from("/game/main.asm"),
+            PathBuf::from("/lib/macros.asm"),
+            PathBuf::from("/lib/stz.asm"),
+            PathBuf::from("/game/loop.asm"),
+        ]
+    );
+    let output = project.assemble(0x8000, State::new(true, true)).unwrap();
+    assert_eq!(
+        output.data,
+        [0x64, 0x10, 0x4C, 0x05, 0x80, 0x64, 0x10, 0x80, 0xF7]
+    );
+}
+
+#[test]
+fn include_once() {
+    let project = load(
+        &[
+            (
+                "/game/main.asm",
+                "incsrc \"sound.asm\"\nincsrc \"wait.asm\"\nJSR Wait\n",
+            ),
+            ("/game/sound.asm", "incsrc \"wait.asm\"\nJSR Wait\n"),
+            ("/lib/wait.asm", "includeonce\nWait:\nWAI\nRTS\n"),
+        ],
+        &["/game/main.asm", "/lib/wait.asm"],
+    );
+    let output = project.assemble(0x8000, State::new(true, true)).unwrap();
+    assert_eq!(
+        output.data,
+        [0xCB, 0x60, 0x20, 0x00, 0x80, 0x20, 0x00, 0x80]
+    );
+}
+
+#[test]
+fn parse_errors_in_every_file() {
+    let project = load(
+        &[
+            ("/main.asm", "incsrc \"other.asm\"\nNOP\nLDA #\n"),
+            ("/other.asm", "\nLDA ($10\n"),
+        ],
+        &["/main.asm"],
+    );
+    let messages: Vec<_> = diagnostics(&project)
+        .iter()
+        .map(Diagnostic::to_string)
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "/main.asm:3:6: expected an expression, found end of line",
+            "/other.asm:2:9: expected `)`, found end of line",
+        ]
+    );
+}
+
+#[test]
+fn assembly_errors() {
+    let project = load(
+        &[
+            ("/main.asm", "incsrc \"a.asm\"\n"),
+            ("/a.asm", "Main:\n Main:\n"),
+        ],
+        &["/main.asm"],
+    );
+    assert_eq!(
+        diagnostics(&project),
+        [Diagnostic {
+            location: Some((PathBuf::from("/a.asm"), 2, 2)),
+            message: "Main is already defined".to_string(),
+        }]
+    );
+    let missing = load(&[("/main.asm", "%missing()\n")], &["/main.asm"]);
+    match missing.assemble(0x8000, State::new(true, true)) {
+        Err(Error::Macro(_)) => {}
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn defines_and_mapper() {
+    let mut project = load(&[("/main.asm", "LDA #!value+1\n")], &["/main.asm"]);
+    project.define("!value", "$10");
+    project.set_mapper(Mapper::HiRom);
+    let output = project.assemble(0xC00000, State::new(true, true)).unwrap();
+    assert_eq!(output.data, [0xA9, 0x11]);
+    assert_eq!(output.mapper, Mapper::HiRom);
+    project.define("!value", "*");
+    assert_eq!(
+        diagnostics(&project),
+        [Diagnostic {
+            location: None,
+            message: "expected an expression, found `*`".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn operand_errors() {
+    let project = load(
+        &[("/main.asm", "Main:\n    LDA.w #Main + 4/0\n")],
+        &["/main.asm"],
+    );
+    assert_eq!(
+        diagnostics(&project),
+        [Diagnostic {
+            location: Some((PathBuf::from("/main.asm"), 2, 19)),
+            message: "division by zero".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn sources_and_writers() {
+    let mut files = Overlay::new(Empty);
+    files.insert("/lib/value.asm", "LDA #$12\n");
+    let mut assembler = Assembler::builder()
+        .file_system(files)
+        .source("/lib/<stdin>", "incsrc \"value.asm\"\nRTS\n")
+        .output(OutputKind::Raw)
+        .build();
+    assembler.load().unwrap();
+    let output = assembler.assemble().unwrap();
+    let mut written = Vec::new();
+    assembler.write_to(&output, &mut written).unwrap();
+    assert_eq!(written, [0xA9, 0x12, 0x60]);
+}
+
+#[test]
+fn rejected_aliases() {
+    let assembler = |aliases| {
+        let mut files = Overlay::new(Empty);
+        files.insert(
+            "/main.asm",
+            "macro decrement()\ndea\nendmacro\nDEC A\n%decrement()\n",
+        );
+        let mut assembler = Assembler::builder()
+            .file_system(files)
+            .root("/main.asm")
+            .aliases(aliases)
+            .build();
+        assembler.load().unwrap();
+        assembler
+    };
+    let accepting = assembler(Aliases::Accept);
+    assert_eq!(accepting.assemble().unwrap().data, [0x3A, 0x3A]);
+    let assembler = assembler(Aliases::Reject);
+    let error = assembler.assemble().unwrap_err();
+    assert_eq!(
+        assembler.project().diagnostics(&error),
+        [Diagnostic {
+            location: Some((PathBuf::from("/main.asm"), 2, 1)),
+            message: "DEA is an alias, use DEC A".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn long_branches() {
+    let assembler = |long_branches| {
+        let mut files = Overlay::new(Empty);
+        files.insert(
+            "/main.asm",
+            format!(
+                "macro back()\nBRA Start\nendmacro\nStart:\nif 1\n{}BEQ Start\nendif\n%back()\n",
+                "NOP\n".repeat(130)
+            ),
+        );
+        let mut builder = Assembler::builder().file_system(files).root("/main.asm");
+        if let Some(jump) = long_branches {
+            builder = builder.long_branches(jump);
+        }
+        let mut assembler = builder.build();
+        assembler.load().unwrap();
+        assembler
+    };
+    assert!(assembler(None).assemble().is_err());
+    let assembler = assembler(Some(LongJump::Brl));
+    let data = assembler.assemble().unwrap().data;
+    assert_eq!(data[..130], [0xEA; 130]);
+    assert_eq!(
+        data[130..],
+        [0xD0, 0x03, 0x82, 0x79, 0xFF, 0x82, 0x76, 0xFF]
+    );
+}
+
+#[test]
+fn error_sources() {
+    let mut project = Project::new(Empty);
+    project.add_root("/missing.asm");
+    let error = Error::Include(project.load().unwrap_err());
+    assert_eq!(error.to_string(), "/missing.asm: no such file");
+    let source = error.source().unwrap();
+    let io = source.downcast_ref::<io::Error>().unwrap();
+    assert_eq!(io.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn warnings() {
+    let mut project = load(
+        &[
+            ("/main.asm", "incsrc \"a.asm\"\nJMP Used\n"),
+            ("/a.asm", "Used:\nUnused:\n"),
+        ],
+        &["/main.asm"],
+    );
+    project.set_warning_level(Category::UnusedLabel, Level::Warn);
+    let output = project.assemble(0x8000, State::new(true, true)).unwrap();
+    assert_eq!(
+        project.warnings(&output.warnings),
+        [Diagnostic {
+            location: Some((PathBuf::from("/a.asm"), 2, 1)),
+            message: "label Unused is never used [unused-label]".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn macro_captures() {
+    let source = "macro spin()\nBRA Loop\nendmacro\nLoop:\n%spin()\n";
+    let mut project = load(&[("/main.asm", source)], &["/main.asm"]);
+    let output = project.assemble(0x8000, State::new(true, true)).unwrap();
+    assert_eq!(
+        project.warnings(&output.warnings),
+        [Diagnostic {
+            location: Some((PathBuf::from("/main.asm"), 2, 1)),
+            message: "macro spin refers to Loop of the program, without listing it with \
+                      `global` [macro-capture]"
+                .to_string(),
+        }]
+    );
+    project.set_warning_level(Category::MacroCapture, Level::Allow);
+    let output = project.assemble(0x8000, State::new(true, true)).unwrap();
+    assert!(output.warnings.is_empty());
+}
+
+#[test]
+fn base_rom() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x1234..0x1237].copy_from_slice(&[0xA9, 0x0F, 0x8D]);
+    let mut files = Overlay::new(Empty);
+    files.insert(
+        "/patch.asm",
+        "findpattern \"A9 0F 8D\" -> Found\nJSL Found\n",
+    );
+    let mut assembler = Assembler::builder()
+        .file_system(files)
+        .root("/patch.asm")
+        .base_rom(rom.clone())
+        .build();
+    assembler.load().unwrap();
+    let output = assembler.assemble().unwrap();
+    assert_eq!(output.data, [0x22, 0x34, 0x92, 0x80]);
+    let patched = assembler.write(&output).unwrap();
+    assert_eq!(patched.len(), rom.len());
+    assert_eq!(patched[..4], [0x22, 0x34, 0x92, 0x80]);
+    // Only the code and the checksum of the base ROM change.
+    assert!(patched[4..0x7FDC] == rom[4..0x7FDC]);
+}
+
+#[test]
+fn analyses() {
+    let source = "Main:\nJSR Tick\nPHA\nPLA\nRTS\nNmi:\nJSR Tick\nRTI\n\
+                  Tick:\nSTA Timer\nSTX Timer+1\nRTS\nTimer = $10\n";
+    let mut project = load(&[("/main.asm", source)], &["/main.asm"]);
+    let output = project.assemble(0x8000, State::new(true, true)).unwrap();
+    assert!(output.warnings.is_empty());
+    project.set_stack_budget(1, 8);
+    project.set_interrupt_entries("Nmi", "Main");
+    let output = project.assemble(0x8000, State::new(true, true)).unwrap();
+    let messages: Vec<_> = project
+        .warnings(&output.warnings)
+        .iter()
+        .map(Diagnostic::to_string)
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "/main.asm:1:1: routine Main uses 2 bytes of stack, over the budget of 1 \
+             [stack-usage]",
+            "/main.asm:6:1: routine Nmi uses 2 bytes of stack, over the budget of 1 \
+             [stack-usage]",
+            "/main.asm:9:1: routine Tick is called from Nmi and Main, and writes Timer \
+             one byte at a time [interrupt-safety]",
+        ]
+    );
+    project.set_warning_level(Category::InterruptSafety, Level::Deny);
+    match project.assemble(0x8000, State::new(true, true)) {
+        Err(Error::Assembly(error)) => assert_eq!(error.span().fragment(), "Tick:"),
+        result => panic!("{:?}", result),
+    }
+}
@@ -0,0 +1,209 @@
+extern crate mvp;
+
+use mvp::rom::{self, BaseRomError, InvalidPattern, Pattern, SizePolicy};
+
+const SIZE_FIELD: usize = 0x7FD7;
+
+#[test]
+fn keep() {
+    let mut image = vec![0; 0x30000];
+    rom::resize(&mut image, SizePolicy::Keep, &rom::LOROM, 0);
+    assert_eq!(image.len(), 0x30000);
+    assert_eq!(image[SIZE_FIELD], 8);
+}
+
+#[test]
+fn trim() {
+    let mut image = vec![0xFF; 0x40000];
+    image[0x10000] = 0x42;
+    rom::resize(&mut image, SizePolicy::Trim, &rom::LOROM, 0xFF);
+    assert_eq!(image.len(), 0x18000);
+    assert_eq!(image[SIZE_FIELD], 7);
+}
+
+#[test]
+fn trim_keeps_header_bank() {
+    let mut image = vec![0; 0x40000];
+    rom::resize(&mut image, SizePolicy::Trim, &rom::LOROM, 0);
+    assert_eq!(image.len(), 0x8000);
+    assert_eq!(image[SIZE_FIELD], 5);
+}
+
+#[test]
+fn trim_uses_bank_size() {
+    let mut image = vec![0; 0x40000];
+    image[0x18000] = 1;
+    rom::resize(&mut image, SizePolicy::Trim, &rom::HIROM, 0);
+    assert_eq!(image.len(), 0x20000);
+    assert_eq!(image[0xFFD7], 7);
+}
+
+#[test]
+fn pad_to_valid() {
+    let mut image = vec![1; 0x100001];
+    rom::resize(&mut image, SizePolicy::PadToValid, &rom::LOROM, 0);
+    assert_eq!(image.len(), 0x200000);
+    assert_eq!(image[0x100001], 0);
+    assert_eq!(image[SIZE_FIELD], 0x0B);
+}
+
+#[test]
+fn pad_small_rom_to_valid() {
+    let mut image = vec![1; 0x100];
+    rom::resize(&mut image, SizePolicy::PadToValid, &rom::LOROM, 0);
+    assert_eq!(image.len(), 0x8000);
+    assert_eq!(image[SIZE_FIELD], 5);
+}
+
+#[test]
+fn exact() {
+    let mut image = vec![1; 0x80000];
+    rom::resize(&mut image, SizePolicy::Exact(0x60000), &rom::LOROM, 0);
+    assert_eq!(image.len(), 0x60000);
+    assert_eq!(image[SIZE_FIELD], 9);
+}
+
+#[test]
+fn no_header() {
+    let mut image = vec![1; 0x100];
+    rom::resize(&mut image, SizePolicy::Keep, &rom::LOROM, 0);
+    assert_eq!(image, vec![1; 0x100]);
+}
+
+#[test]
+fn pattern_search() {
+    let pattern: Pattern = "A9 ?? 8d 00 21".parse().unwrap();
+    let rom = [0xA9, 0x0F, 0x8D, 0x00, 0x22, 0xA9, 0x80, 0x8D, 0x00, 0x21];
+    assert_eq!(pattern.find(&rom), Some(5));
+}
+
+#[test]
+fn pattern_not_found() {
+    let pattern: Pattern = "A9 ?? 8D".parse().unwrap();
+    assert_eq!(pattern.find(&[0xA9, 0x00]), None);
+}
+
+#[test]
+fn overlapping_patterns() {
+    let pattern: Pattern = "?? 00 ??".parse().unwrap();
+    let rom = [0, 0, 0, 0, 1];
+    assert_eq!(pattern.find_all(&rom).collect::<Vec<_>>(), vec![0, 1, 2]);
+}
+
+#[test]
+fn invalid_patterns() {
+    for pattern in &["", "A9 ?", "A9 1234", "G0", "A9 ?? *"] {
+        assert_eq!(pattern.parse::<Pattern>(), Err(InvalidPattern));
+    }
+}
+
+#[test]
+fn crc32() {
+    assert_eq!(rom::crc32(b""), 0);
+    assert_eq!(rom::crc32(&[0; 0x8000]), 0x011F_FCA6);
+}
+
+fn dump() -> Vec<u8> {
+    let mut dump = vec![0; 0x10000];
+    // Valid LoROM header for Europe.
+    dump[0x7FD9] = 2;
+    dump[0x7FDC..0x7FE0].copy_from_slice(&[0xFF, 0xFF, 0, 0]);
+    dump
+}
+
+#[test]
+fn expected_base_rom() {
+    let dump = dump();
+    assert_eq!(rom::check_base_rom(&dump, &[1, rom::crc32(&dump)]), Ok(()));
+}
+
+#[test]
+fn headered_base_rom() {
+    let dump = dump();
+    let mut headered = vec![0; 0x200];
+    headered.extend(&dump);
+    assert_eq!(
+        rom::check_base_rom(&headered, &[rom::crc32(&dump)]),
+        Err(BaseRomError::Headered)
+    );
+}
+
+#[test]
+fn overdumped_base_rom() {
+    let dump = dump();
+    let mut overdumped = dump.clone();
+    overdumped.extend(&dump);
+    assert_eq!(
+        rom::check_base_rom(&overdumped, &[rom::crc32(&dump)]),
+        Err(BaseRomError::Overdumped {
+            expected: 0x10000,
+            actual: 0x20000
+        })
+    );
+}
+
+#[test]
+fn unrecognized_base_rom() {
+    let dump = dump();
+    let error = rom::check_base_rom(&dump, &[0]).unwrap_err();
+    assert_eq!(
+        error,
+        BaseRomError::Unrecognized {
+            crc: rom::crc32(&dump),
+            region: Some(2)
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "base ROM with CRC-32 {:08X} is not recognized, \
+             it may be a wrong version of the game (Europe)",
+            rom::crc32(&dump)
+        )
+    );
+}
+
+#[test]
+fn unrecognized_base_rom_without_header() {
+    assert_eq!(
+        rom::check_base_rom(&[1; 0x100], &[0]),
+        Err(BaseRomError::Unrecognized {
+            crc: rom::crc32(&[1; 0x100]),
+            region: None
+        })
+    );
+}
+
+#[test]
+fn checksum() {
+    let mut image = vec![0; 0x8000];
+    image[0] = 0x12;
+    image[1] = 0xFF;
+    assert_eq!(rom::fix_checksum(&mut image, &rom::LOROM), 0x030F);
+    assert_eq!(&image[0x7FDC..0x7FE0], &[0xF0, 0xFC, 0x0F, 0x03]);
+    assert_eq!(rom::checksum(&image), 0x030F);
+    image[0x7FDC] = 0;
+    assert_eq!(rom::fix_checksum(&mut image, &rom::LOROM), 0x030F);
+}
+
+#[test]
+fn checksum_mirrors_last_part() {
+    // 3 MiB ROM is summed as 2 MiB followed by the last 1 MiB twice.
+    let mut image = vec![0; 0x300000];
+    image[0x100000] = 1;
+    image[0x200000] = 2;
+    assert_eq!(rom::checksum(&image), 5);
+    // 2.5 MiB ROM is summed as 2 MiB followed by the last 512 KiB four times.
+    image.truncate(0x280000);
+    assert_eq!(rom::checksum(&image), 9);
+    // 3.5 MiB ROM is summed as 2 MiB, 1 MiB, and the last 512 KiB twice.
+    image.resize(0x380000, 0);
+    image[0x300000] = 3;
+    assert_eq!(rom::checksum(&image), 9);
+}
+
+#[test]
+fn checksum_wraps() {
+    let image = vec![0xFF; 0x8000];
+    assert_eq!(rom::checksum(&image), 0x8000);
+}
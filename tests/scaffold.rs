@@ -0,0 +1,109 @@
+extern crate mvp;
+
+use std::env;
+use std::fs;
+
+use mvp::mapping::Mapper;
+use mvp::rom;
+use mvp::scaffold::{Error, Settings, Template, MANIFEST};
+
+fn word(rom: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([rom[offset], rom[offset + 1]])
+}
+
+fn check_rom(mapper: Mapper, size: usize) {
+    let rom = Template::new("black screen", mapper)
+        .unwrap()
+        .rom()
+        .unwrap();
+    let header = mapper.layout().header;
+    assert_eq!(rom.len(), size);
+    assert_eq!(&rom[header..header + 21], b"BLACK SCREEN         ");
+    assert_eq!(rom[header + 0x15], mapper.map_mode());
+    assert_eq!(rom[header + 0x17], rom::size_code(size));
+    assert_eq!(word(&rom, header + 0x3C), 0x8000);
+    let checksum = rom
+        .iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(byte.into()));
+    assert_eq!(word(&rom, header + 0x1E), checksum);
+    assert_eq!(word(&rom, header + 0x1C), !checksum);
+    let code = header & !0x7FFF;
+    assert_eq!(&rom[code..code + 3], &[0x78, 0x18, 0xFB]);
+}
+
+#[test]
+fn lorom_rom() {
+    check_rom(Mapper::LoRom, 0x8000);
+}
+
+#[test]
+fn hirom_rom() {
+    check_rom(Mapper::HiRom, 0x10000);
+}
+
+#[test]
+fn unsupported_mapper() {
+    match Template::new("game", Mapper::Sa1Rom) {
+        Err(Error::UnsupportedMapper(Mapper::Sa1Rom)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn sources() {
+    let template = Template::new("game", Mapper::HiRom).unwrap();
+    let files = template.files();
+    let main = &files
+        .iter()
+        .find(|&&(name, _)| name == "main.asm")
+        .unwrap()
+        .1;
+    assert!(main.contains("\nhirom\n"));
+    assert!(main.contains("\nincsrc \"ram.asm\"\n"));
+    let manifest = &files
+        .iter()
+        .find(|&&(name, _)| name == "mvp.json")
+        .unwrap()
+        .1;
+    assert!(manifest.contains("\"output\": \"build/game.sfc\""));
+}
+
+#[test]
+fn settings() {
+    let template = Template::new("game", Mapper::LoRom).unwrap();
+    let (_, manifest) = template
+        .files()
+        .into_iter()
+        .find(|&(name, _)| name == MANIFEST)
+        .unwrap();
+    assert_eq!(
+        Settings::from_json(&manifest).unwrap(),
+        Settings {
+            name: "game".to_string(),
+            main: "main.asm".into(),
+            output: template.output().into(),
+        }
+    );
+    match Settings::from_json("{\"name\": \"game\"}") {
+        Err(Error::InvalidManifest) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn write_project() {
+    let directory = env::temp_dir().join(format!("mvp-scaffold-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    let template = Template::new("game", Mapper::LoRom).unwrap();
+    template.write(&directory).unwrap();
+    assert_eq!(
+        fs::read(directory.join("build/game.sfc")).unwrap(),
+        template.rom().unwrap()
+    );
+    assert!(directory.join("ram.asm").exists());
+    match template.write(&directory) {
+        Err(Error::FileExists(path)) => assert_eq!(path, directory.join("mvp.json")),
+        result => panic!("unexpected result: {:?}", result),
+    }
+    fs::remove_dir_all(&directory).unwrap();
+}
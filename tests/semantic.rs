@@ -0,0 +1,115 @@
+extern crate mvp;
+
+use mvp::encoder::Arch;
+use mvp::parser::grammar::{Input, ParserOptions};
+use mvp::parser::semantic::{semantic_tokens, TokenKind};
+
+fn tokens(source: &str) -> Vec<(&str, TokenKind)> {
+    semantic_tokens(source)
+        .iter()
+        .map(|&(span, kind)| (span.fragment(), kind))
+        .collect()
+}
+
+#[test]
+fn labels() {
+    assert_eq!(
+        tokens("Main: .loop: DEX\n  BNE .loop\n  JMP Main+2\n  JMP Missing\n"),
+        [
+            ("Main", TokenKind::Label),
+            (".loop", TokenKind::Label),
+            ("DEX", TokenKind::Mnemonic),
+            ("BNE", TokenKind::Mnemonic),
+            (".loop", TokenKind::Label),
+            ("JMP", TokenKind::Mnemonic),
+            ("Main", TokenKind::Label),
+            ("+", TokenKind::Operator),
+            ("2", TokenKind::Number),
+            ("JMP", TokenKind::Mnemonic),
+            ("Missing", TokenKind::Identifier),
+        ]
+    );
+}
+
+#[test]
+fn instructions_and_directives() {
+    assert_eq!(
+        tokens("incsrc \"a.asm\"\nif 1\n  lda.w ($10,x)\nendif\narch spc700\nMOV A, #$12\n"),
+        [
+            ("incsrc", TokenKind::Directive),
+            ("\"a.asm\"", TokenKind::String),
+            ("if", TokenKind::Directive),
+            ("1", TokenKind::Number),
+            ("lda.w", TokenKind::Mnemonic),
+            ("$10", TokenKind::Number),
+            ("x", TokenKind::Identifier),
+            ("endif", TokenKind::Directive),
+            ("arch", TokenKind::Directive),
+            ("spc700", TokenKind::Identifier),
+            ("MOV", TokenKind::Mnemonic),
+            ("A", TokenKind::Identifier),
+            ("$12", TokenKind::Number),
+        ]
+    );
+}
+
+#[test]
+fn macros_and_functions() {
+    let source = "macro store(value)\n  STA value ; store\nendmacro\n\
+                  function double(x) = x*2\n%store(double(!x) + 3)\n";
+    assert_eq!(
+        tokens(source),
+        [
+            ("macro", TokenKind::Directive),
+            ("store", TokenKind::Macro),
+            ("value", TokenKind::Parameter),
+            ("STA", TokenKind::Mnemonic),
+            ("value", TokenKind::Parameter),
+            ("; store", TokenKind::Comment),
+            ("endmacro", TokenKind::Directive),
+            ("function", TokenKind::Directive),
+            ("double", TokenKind::Function),
+            ("x", TokenKind::Parameter),
+            ("=", TokenKind::Operator),
+            ("x", TokenKind::Parameter),
+            ("*", TokenKind::Operator),
+            ("2", TokenKind::Number),
+            ("%store", TokenKind::Macro),
+            ("double", TokenKind::Function),
+            ("!x", TokenKind::Define),
+            ("+", TokenKind::Operator),
+            ("3", TokenKind::Number),
+        ]
+    );
+}
+
+#[test]
+fn invalid_code() {
+    let source = "value = 2\nLDA (value\n%missing(\nLDA bank(Other)\n";
+    assert_eq!(
+        tokens(source),
+        [
+            ("value", TokenKind::Define),
+            ("=", TokenKind::Operator),
+            ("2", TokenKind::Number),
+            ("LDA", TokenKind::Mnemonic),
+            ("value", TokenKind::Define),
+            ("%missing", TokenKind::Macro),
+            ("LDA", TokenKind::Mnemonic),
+            ("bank", TokenKind::Function),
+            ("Other", TokenKind::Identifier),
+        ]
+    );
+}
+
+#[test]
+fn options() {
+    let source = "MOV A, #$12\n";
+    assert_eq!(tokens(source)[0], ("MOV", TokenKind::Directive));
+    let options = ParserOptions {
+        arch: Arch::Spc700,
+        ..ParserOptions::default()
+    };
+    let tokens = semantic_tokens(Input::with_options(source, options));
+    assert_eq!(tokens[0].1, TokenKind::Mnemonic);
+}
@@ -0,0 +1,137 @@
+extern crate mvp;
+
+use std::env;
+use std::fs;
+
+use mvp::assembler::flags::State;
+use mvp::assembler::output::{self, Output};
+use mvp::assembler::sfc::{self, Error};
+use mvp::mapping::Mapper;
+use mvp::parser::grammar::parse_program;
+use mvp::rom::{self, Header, Vectors};
+
+fn assemble(source: &str, origin: u32) -> Output<'_> {
+    output::assemble(
+        parse_program(source).unwrap(),
+        origin,
+        State::new(true, true),
+    )
+    .unwrap()
+}
+
+fn word(rom: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([rom[offset], rom[offset + 1]])
+}
+
+#[test]
+fn header() {
+    let output = assemble("Reset:\nWAI\nBRA Reset\nNmi:\nRTI\n", 0x808000);
+    let header = Header {
+        ram_size: 0x2000,
+        region: 0x00,
+        version: 2,
+        vectors: Vectors {
+            nmi: 0x8003,
+            ..Vectors::default()
+        },
+        ..Header::new("Example game", Mapper::HiRom)
+    };
+    let rom = sfc::build(&output, &header).unwrap();
+    assert_eq!(rom.len(), 0x8000);
+    assert_eq!(&rom[..4], &[0xCB, 0x80, 0xFD, 0x40]);
+    assert_eq!(&rom[0x7FC0..0x7FD5], b"EXAMPLE GAME         ");
+    assert_eq!(
+        &rom[0x7FD5..0x7FDC],
+        &[0x20, 0x02, 0x05, 0x03, 0x00, 0x00, 0x02]
+    );
+    assert_eq!(word(&rom, 0x7FDC) ^ word(&rom, 0x7FDE), 0xFFFF);
+    assert_eq!(word(&rom, 0x7FDE), rom::checksum(&rom));
+    assert_eq!(word(&rom, 0x7FEA), 0x8003);
+    assert_eq!(word(&rom, 0x7FFA), 0x8003);
+    assert_eq!(word(&rom, 0x7FFC), 0x8000);
+}
+
+#[test]
+fn coprocessor_headers() {
+    for &(mapper, origin, map_mode, cartridge_type) in &[
+        (Mapper::Sa1Rom, 0x008000, 0x23, 0x33),
+        (Mapper::SfxRom, 0x008000, 0x20, 0x13),
+    ] {
+        let source = format!("{}\nRTS\n", mapper.directive());
+        let output = assemble(&source, origin);
+        let mut header = Header::new("", mapper);
+        header.vectors.reset = 0x8000;
+        let rom = sfc::build(&output, &header).unwrap();
+        assert_eq!(rom[0], 0x60);
+        assert_eq!(&rom[0x7FD5..0x7FD7], &[map_mode, cartridge_type]);
+    }
+}
+
+#[test]
+fn code_is_placed_by_mapper() {
+    let output = assemble("RTL\n", 0x828000);
+    let rom = sfc::build(&output, &Header::new("", Mapper::LoRom)).unwrap();
+    assert_eq!(rom.len(), 0x20000);
+    assert_eq!(rom[0x10000], 0x6B);
+    assert_eq!(rom[0x7FD7], 0x07);
+}
+
+#[test]
+fn exhirom() {
+    let output = assemble("exhirom\nRTS\n", 0x400000);
+    let rom = sfc::build(&output, &Header::new("", Mapper::LoRom)).unwrap();
+    assert_eq!(rom.len(), 0x800000);
+    assert_eq!(rom[0x400000], 0x60);
+    assert_eq!(rom[0x40FFD5], 0x25);
+}
+
+#[test]
+fn errors() {
+    let header = Header::new("", Mapper::LoRom);
+    let output = assemble("NOP\n", 0x7E0000);
+    match sfc::build(&output, &header) {
+        Err(Error::Unmapped(0x7E0000)) => {}
+        result => panic!("{:?}", result),
+    }
+    let output = assemble("NOP\n", 0x00FFC0);
+    match sfc::build(&output, &header) {
+        Err(Error::HeaderOverlap(0x00FFC0)) => {}
+        result => panic!("{:?}", result),
+    }
+    let output = assemble("JSL External\n", 0x008000);
+    match sfc::build(&output, &header) {
+        Err(Error::Unresolved(span)) => assert_eq!(span.fragment().trim(), "JSL External"),
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn write() {
+    let path = env::temp_dir().join(format!("mvp-sfc-{}.sfc", std::process::id()));
+    let output = assemble("STP\n", 0x008000);
+    sfc::write(&path, &output, &Header::new("", Mapper::LoRom)).unwrap();
+    let rom = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(rom.len(), 0x8000);
+    assert_eq!(rom[0], 0xDB);
+}
+
+#[test]
+fn patch() {
+    let mut rom = sfc::build(
+        &assemble("NOP\n", 0x808000),
+        &Header::new("", Mapper::LoRom),
+    )
+    .unwrap();
+    let original = rom.clone();
+    sfc::patch(&mut rom, &assemble("LDA #$12\n", 0x808010)).unwrap();
+    assert_eq!(rom[..0x10], original[..0x10]);
+    assert_eq!(rom[0x10..0x12], [0xA9, 0x12]);
+    assert_eq!(word(&rom, 0x7FDE), rom::checksum(&rom));
+    assert_eq!(
+        sfc::patch(&mut rom, &assemble("NOP\n", 0x7E0000))
+            .unwrap_err()
+            .to_string(),
+        "address $7E0000 is not mapped to ROM"
+    );
+}
@@ -0,0 +1,114 @@
+#![allow(clippy::single_range_in_vec_init)]
+
+extern crate mvp;
+
+use mvp::signature::{Error, Manifest, Sha256};
+
+fn rom() -> Vec<u8> {
+    (0..0x10000).map(|i| i as u8).collect()
+}
+
+#[test]
+fn sha256_digest() {
+    let manifest = Manifest::sign(b"abc", vec![0..3], &Sha256).unwrap();
+    assert_eq!(manifest.algorithm, "sha256");
+    assert_eq!(
+        manifest.to_json(),
+        concat!(
+            r#"{"algorithm":"sha256","digest":"#,
+            r#""ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad","#,
+            r#""ranges":[[0,3]]}"#
+        )
+    );
+}
+
+#[test]
+fn unsigned_ranges_can_change() {
+    let mut rom = rom();
+    let manifest = Manifest::sign(&rom, vec![0..0x100, 0x8000..0x8100], &Sha256).unwrap();
+    rom[0x200] = 0;
+    assert_eq!(manifest.verify(&rom, &Sha256), Ok(true));
+    rom[0x8080] = 0;
+    assert_eq!(manifest.verify(&rom, &Sha256), Ok(false));
+}
+
+#[test]
+fn range_out_of_bounds() {
+    assert_eq!(
+        Manifest::sign(&rom(), vec![0xFF00..0x10001], &Sha256),
+        Err(Error::RangeOutOfBounds(0xFF00..0x10001))
+    );
+}
+
+#[test]
+fn json_round_trip() {
+    let manifest = Manifest::sign(&rom(), vec![0..0x100, 0x200..0x300], &Sha256).unwrap();
+    assert_eq!(Manifest::from_json(&manifest.to_json()), Ok(manifest));
+}
+
+#[test]
+fn invalid_json() {
+    assert_eq!(
+        Manifest::from_json(r#"{"algorithm":"sha256","ranges":[],"digest":"x"}"#),
+        Err(Error::InvalidManifest)
+    );
+    assert_eq!(Manifest::from_json("{"), Err(Error::InvalidManifest));
+}
+
+#[test]
+fn algorithm_mismatch() {
+    let mut manifest = Manifest::sign(&rom(), vec![0..0x100], &Sha256).unwrap();
+    manifest.algorithm = "md5".to_string();
+    assert_eq!(
+        manifest.verify(&rom(), &Sha256),
+        Err(Error::AlgorithmMismatch("md5".to_string()))
+    );
+}
+
+#[test]
+fn appended_block() {
+    let mut rom = rom();
+    let manifest = Manifest::sign(&rom, vec![0..0x100], &Sha256).unwrap();
+    manifest.append_block(&mut rom).unwrap();
+    assert_eq!(rom.len(), 0x10000 + manifest.to_block().unwrap().len());
+    assert_eq!(Manifest::find_block(&rom[0x10000..]), Ok(manifest));
+}
+
+#[test]
+fn embedded_block() {
+    let mut rom = vec![0; 0x10000];
+    let manifest = Manifest::sign(&rom, vec![0..0x100], &Sha256).unwrap();
+    manifest.embed_block(&mut rom, 0x8000).unwrap();
+    let found = Manifest::find_block(&rom).unwrap();
+    assert_eq!(found.verify(&rom, &Sha256), Ok(true));
+}
+
+#[test]
+fn embedded_block_cannot_overlap_signed_data() {
+    let mut rom = rom();
+    let manifest = Manifest::sign(&rom, vec![0..0x100], &Sha256).unwrap();
+    let length = manifest.to_block().unwrap().len();
+    assert_eq!(
+        manifest.embed_block(&mut rom, 0xF0),
+        Err(Error::BlockOverlapsSignedRange(0xF0..0xF0 + length))
+    );
+}
+
+#[test]
+fn oversized_block_fields() {
+    let mut manifest = Manifest::sign(&rom(), vec![0..0x100], &Sha256).unwrap();
+    manifest.digest = vec![0; 0x100];
+    assert_eq!(manifest.to_block(), Err(Error::BlockFieldTooLong("digest")));
+    manifest.digest.truncate(0x20);
+    manifest.algorithm = "x".repeat(0x100);
+    assert_eq!(
+        manifest.to_block(),
+        Err(Error::BlockFieldTooLong("algorithm name"))
+    );
+    manifest.algorithm = "sha256".to_string();
+    manifest.ranges = vec![0..0x100; 0x10000];
+    assert_eq!(
+        manifest.append_block(&mut rom()),
+        Err(Error::BlockFieldTooLong("range list"))
+    );
+}
@@ -0,0 +1,96 @@
+extern crate mvp;
+
+use mvp::assembler::spcblock::{self, Block, Upload};
+use mvp::parser::ast::Label;
+use mvp::parser::grammar::parse_program;
+use mvp::spc::{self, Config};
+
+fn upload(blocks: Vec<Block>) -> Upload {
+    Upload::new(blocks).unwrap()
+}
+
+fn nop() -> Upload {
+    upload(vec![Block {
+        address: 0x0200,
+        data: vec![0x00],
+        execute: None,
+    }])
+}
+
+#[test]
+fn assembled_code() {
+    let source = "
+        spcblock $0200
+        Loop:
+            MOV $F2,#$6C
+            BRA Loop
+        endspcblock execute Loop
+    ";
+    let statements = parse_program(source).unwrap();
+    let upload = spcblock::upload(&statements, &|_: &Label| None)
+        .unwrap()
+        .unwrap();
+    let file = spc::write(&upload, &Config::default());
+    assert_eq!(file.len(), 0x10200);
+    assert_eq!(file[0x21..0x25], [26, 26, 26, 30]);
+    assert_eq!(file[0x25..0x2C], [0x00, 0x02, 0, 0, 0, 0, 0xEF]);
+    assert_eq!(file[0x300..0x305], [0x8F, 0x6C, 0xF2, 0x2F, 0xFB]);
+    assert_eq!(file[0x10100 + 0x6C], 0x20);
+}
+
+#[test]
+fn registers() {
+    let config = Config {
+        pc: Some(0x1234),
+        a: 1,
+        x: 2,
+        y: 3,
+        psw: 4,
+        sp: 5,
+        ..Config::default()
+    };
+    let file = spc::write(&nop(), &config);
+    assert_eq!(file[0x25..0x2C], [0x34, 0x12, 1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn tag() {
+    let config = Config {
+        song: "Overworld".into(),
+        game: "A very long game title exceeding 32 bytes".into(),
+        artist: "Composer".into(),
+        dumper: "mvp".into(),
+        comments: "Test".into(),
+        seconds: 1500,
+        fade: 10_000,
+        ..Config::default()
+    };
+    let file = spc::write(&nop(), &config);
+    assert_eq!(file[0x2E..0x38], *b"Overworld\0");
+    assert_eq!(file[0x4E..0x6E], *b"A very long game title exceeding");
+    assert_eq!(file[0x6E..0x72], *b"mvp\0");
+    assert_eq!(file[0x7E..0x82], *b"Test");
+    assert_eq!(file[0xA9..0xAC], *b"999");
+    assert_eq!(file[0xAC..0xB1], *b"10000");
+    assert_eq!(file[0xB1..0xB9], *b"Composer");
+}
+
+#[test]
+fn blocks_at_end_of_ram() {
+    let blocks = vec![
+        Block {
+            address: 0x0200,
+            data: vec![0x00],
+            execute: None,
+        },
+        Block {
+            address: 0xFFC0,
+            data: vec![0xAB; 0x40],
+            execute: Some(0xFFC0),
+        },
+    ];
+    let file = spc::write(&upload(blocks), &Config::default());
+    assert_eq!(file[0x25..0x27], [0xC0, 0xFF]);
+    assert_eq!(file[0x100FF], 0xAB);
+    assert_eq!(file[0x101C0..0x10200], [0xAB; 0x40][..]);
+}
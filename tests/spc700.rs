@@ -0,0 +1,263 @@
+extern crate mvp;
+
+use std::collections::HashSet;
+
+use mvp::encoder::spc700::{self, Error, Mode, Operand, Register};
+use mvp::encoder::Arch;
+use mvp::parser::ast::{Expression, Number, NumberWidth, Spc700Instruction, Statement};
+use mvp::parser::grammar::{parse_program, parse_statement, Input, ParserOptions};
+
+fn spc700(source: &str) -> Statement<'_> {
+    let options = ParserOptions {
+        arch: Arch::Spc700,
+        ..ParserOptions::default()
+    };
+    let (statement, rest) = parse_statement(Input::with_options(source, options)).unwrap();
+    assert_eq!(rest, Input::new(""));
+    statement
+}
+
+fn operands(source: &str) -> Vec<Operand<Expression<'_>>> {
+    match spc700(source) {
+        Statement::Spc700(instruction) => instruction.operands,
+        statement => panic!("{:?}", statement),
+    }
+}
+
+fn number(value: u32) -> Expression<'static> {
+    Expression::Number(Number {
+        value,
+        width: if value < 0x100 {
+            NumberWidth::OneByte
+        } else {
+            NumberWidth::TwoBytes
+        },
+    })
+}
+
+#[test]
+fn arch_directive_selects_syntax() {
+    let statements = parse_program("arch spc700\nmov a,#$12\narch 65816\nLDA #$12\n").unwrap();
+    assert_eq!(*statements[0], Statement::Arch(Arch::Spc700));
+    assert_eq!(
+        *statements[1],
+        Statement::Spc700(Spc700Instruction {
+            name: "mov",
+            operands: vec![
+                Operand::Register(Register::A),
+                Operand::Immediate(number(0x12)),
+            ],
+        })
+    );
+    assert_eq!(*statements[2], Statement::Arch(Arch::W65816));
+    match *statements[3] {
+        Statement::Opcode(_) => {}
+        ref statement => panic!("{:?}", statement),
+    }
+}
+
+#[test]
+fn arch_applies_to_if_blocks() {
+    let statements = parse_program("arch spc700\nif 1\nret\nendif\n").unwrap();
+    match *statements[1] {
+        Statement::If(ref conditions) => match *conditions[0].statements[0] {
+            Statement::Spc700(_) => {}
+            ref statement => panic!("{:?}", statement),
+        },
+        ref statement => panic!("{:?}", statement),
+    }
+}
+
+#[test]
+fn unknown_arch_is_not_a_directive() {
+    let statements = parse_program("arch z80\n").unwrap();
+    assert!(!matches!(*statements[0], Statement::Arch(_)));
+}
+
+#[test]
+fn operand_syntax() {
+    let address = |address, absolute| Operand::Address {
+        address: number(address),
+        absolute,
+    };
+    let cases = vec![
+        (
+            "mov a,x",
+            vec![
+                Operand::Register(Register::A),
+                Operand::Register(Register::X),
+            ],
+        ),
+        (
+            "movw ya,$12",
+            vec![Operand::Register(Register::YA), address(0x12, false)],
+        ),
+        (
+            "mov $1234,a",
+            vec![address(0x1234, false), Operand::Register(Register::A)],
+        ),
+        (
+            "mov !$12,a",
+            vec![address(0x12, true), Operand::Register(Register::A)],
+        ),
+        (
+            "mov a,$1234+Y",
+            vec![
+                Operand::Register(Register::A),
+                Operand::Indexed {
+                    address: number(0x1234),
+                    index: Register::Y,
+                    absolute: false,
+                },
+            ],
+        ),
+        (
+            "mov a , ( x ) +",
+            vec![Operand::Register(Register::A), Operand::IndirectXIncrement],
+        ),
+        ("adc (x),(y)", vec![Operand::IndirectX, Operand::IndirectY]),
+        (
+            "mov a,[$12+x]",
+            vec![
+                Operand::Register(Register::A),
+                Operand::IndexedIndirect {
+                    address: number(0x12),
+                    absolute: false,
+                },
+            ],
+        ),
+        (
+            "mov a,($12)+y",
+            vec![
+                Operand::Register(Register::A),
+                Operand::IndirectIndexed(number(0x12)),
+            ],
+        ),
+        (
+            "and1 c,/$123.4",
+            vec![
+                Operand::Register(Register::C),
+                Operand::Bit {
+                    address: Expression::Number(Number {
+                        value: 0x123,
+                        width: NumberWidth::None,
+                    }),
+                    bit: Expression::Number(Number {
+                        value: 4,
+                        width: NumberWidth::None,
+                    }),
+                    inverted: true,
+                },
+            ],
+        ),
+        ("sleep", vec![]),
+    ];
+    for (source, expected) in cases {
+        assert_eq!(operands(source), expected, "{}", source);
+    }
+}
+
+#[test]
+fn registers_in_expressions_are_addresses() {
+    match operands("mov a,x+1")[1] {
+        Operand::Address { .. } => {}
+        ref operand => panic!("{:?}", operand),
+    }
+}
+
+fn encode(name: &str, operands: &[Operand<u32>]) -> Result<Vec<u8>, Error> {
+    spc700::encode(name, operands, 0x0400).map(|bytes| bytes.to_vec())
+}
+
+#[test]
+fn encoding() {
+    let a = Operand::Register(Register::A);
+    let address = |address| Operand::Address {
+        address,
+        absolute: false,
+    };
+    assert_eq!(
+        encode("MOV", &[a.clone(), address(0x12)]),
+        Ok(vec![0xE4, 0x12])
+    );
+    assert_eq!(
+        encode("MOV", &[a.clone(), address(0x1234)]),
+        Ok(vec![0xE5, 0x34, 0x12])
+    );
+    let absolute = Operand::Address {
+        address: 0x12,
+        absolute: true,
+    };
+    assert_eq!(
+        encode("mov", &[a.clone(), absolute]),
+        Ok(vec![0xE5, 0x12, 0x00])
+    );
+    assert_eq!(
+        encode("MOV", &[address(0x34), address(0x12)]),
+        Ok(vec![0xFA, 0x12, 0x34])
+    );
+    assert_eq!(encode("JMP", &[address(0x12)]), Ok(vec![0x5F, 0x12, 0x00]));
+    assert_eq!(encode("BRA", &[address(0x0400)]), Ok(vec![0x2F, 0xFE]));
+    assert_eq!(
+        encode("CBNE", &[address(0x12), address(0x0403)]),
+        Ok(vec![0x2E, 0x12, 0x00])
+    );
+    assert_eq!(encode("TCALL", &[address(15)]), Ok(vec![0xF1]));
+    assert_eq!(encode("PCALL", &[address(0xFF80)]), Ok(vec![0x4F, 0x80]));
+    let bit = Operand::Bit {
+        address: 0x1234,
+        bit: 5,
+        inverted: false,
+    };
+    assert_eq!(
+        encode("MOV1", &[bit, Operand::Register(Register::C)]),
+        Ok(vec![0xCA, 0x34, 0xB2])
+    );
+    let bit = Operand::Bit {
+        address: 0x12,
+        bit: 7,
+        inverted: false,
+    };
+    assert_eq!(encode("CLR1", &[bit]), Ok(vec![0xF2, 0x12]));
+}
+
+#[test]
+fn encoding_errors() {
+    let a = Operand::Register(Register::A);
+    assert_eq!(encode("LDA", &[]), Err(Error::UnknownMnemonic));
+    assert_eq!(
+        encode("mov", &[a.clone(), Operand::IndirectY]),
+        Err(Error::UnsupportedOperands("MOV"))
+    );
+    assert_eq!(
+        encode("MOV", &[a, Operand::Immediate(0x100)]),
+        Err(Error::OutOfRange(0x100))
+    );
+    let far = Operand::Address {
+        address: 0x0500,
+        absolute: false,
+    };
+    assert_eq!(encode("BRA", &[far]), Err(Error::OutOfRange(0x0500)));
+    let bit = Operand::Bit {
+        address: 0x12,
+        bit: 8,
+        inverted: false,
+    };
+    assert_eq!(encode("SET1", &[bit]), Err(Error::OutOfRange(8)));
+}
+
+#[test]
+fn every_opcode_is_encoded_once() {
+    let mut opcodes = HashSet::new();
+    for &(name, modes, opcode) in spc700::instructions() {
+        let variants = match modes.first() {
+            Some(&Mode::DirectBit) => (0..8).map(|bit| opcode + 0x20 * bit).collect(),
+            Some(&Mode::TableIndex) => (0..16).map(|index| opcode + 0x10 * index).collect(),
+            _ => vec![opcode],
+        };
+        for opcode in variants {
+            assert!(opcodes.insert(opcode), "{} {:02X}", name, opcode);
+        }
+    }
+    assert_eq!(opcodes.len(), 256);
+}
@@ -0,0 +1,264 @@
+extern crate mvp;
+
+use mvp::assembler::flags::State;
+use mvp::assembler::output;
+use mvp::assembler::spcblock::{self, Block, Error, Upload};
+use mvp::encoder::spc700;
+use mvp::eval::{self, Context};
+use mvp::mapping::Mapper;
+use mvp::parser::ast::{Label, Span, Statement, VariableName};
+use mvp::parser::grammar::{parse_program, program_with_recovery, Input, ParseErrorKind};
+
+fn no_labels(_: &Label) -> Option<i64> {
+    None
+}
+
+fn assemble(source: &str) -> Result<Option<Upload>, Error<'_>> {
+    let statements = parse_program(source).unwrap();
+    spcblock::upload(&statements, &no_labels)
+}
+
+fn data(source: &str) -> Vec<u8> {
+    assemble(source).unwrap().unwrap().blocks[0].data.clone()
+}
+
+#[test]
+fn parsing() {
+    let source = "spcblock $0400\nMOV A,#$12 ; SPC700\nendspcblock execute $0400\nLDA #$12\n";
+    let statements = parse_program(source).unwrap();
+    assert_eq!(statements.len(), 2);
+    assert_eq!(
+        statements[0].span.fragment(),
+        "spcblock $0400\nMOV A,#$12 ; SPC700\nendspcblock execute $0400"
+    );
+    match *statements[0] {
+        Statement::SpcBlock(ref block) => {
+            assert!(block.execute.is_some());
+            assert!(matches!(*block.statements[0], Statement::Spc700(_)));
+        }
+        ref statement => panic!("{:?}", statement),
+    }
+    assert!(matches!(*statements[1], Statement::Opcode(_)));
+}
+
+#[test]
+fn block_keywords_are_not_instructions() {
+    assert!(parse_program("spcblock $0400\nNOP\n").is_err());
+    assert!(parse_program("endspcblock\n").is_err());
+}
+
+#[test]
+fn recovery() {
+    let source = "spcblock $0400\nMOV A,(\nendif\nNOP\nendspcblock\nendspcblock\n";
+    let (statements, errors) = program_with_recovery(Input::new(source));
+    let kinds: Vec<_> = errors.iter().map(|error| error.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            ParseErrorKind::InvalidStatement,
+            ParseErrorKind::UnexpectedBlockKeyword,
+            ParseErrorKind::UnexpectedBlockKeyword,
+        ]
+    );
+    assert_eq!(errors[2].span, Span::new("endspcblock"));
+    match *statements[0] {
+        Statement::SpcBlock(ref block) => assert_eq!(block.statements.len(), 3),
+        ref statement => panic!("{:?}", statement),
+    }
+
+    let (_, errors) = program_with_recovery(Input::new("LDA #1\nspcblock $0400\nNOP\n"));
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].span, Span::new("spcblock $0400"));
+    assert_eq!(errors[0].kind, ParseErrorKind::UnterminatedSpcBlock);
+}
+
+#[test]
+fn forward_references() {
+    let source = "
+        spcblock $0400
+        Start:
+            MOV $12,#Data - $0400
+            BEQ .skip
+            MOV A,!Data
+        .skip:
+            BNE +
+            NOP
+        +:
+            BRA Start
+        Data:
+        endspcblock
+    ";
+    assert_eq!(
+        data(source),
+        [0x8F, 0x0D, 0x12, 0xF0, 0x03, 0xE5, 0x0D, 0x04, 0xD0, 0x01, 0x00, 0x2F, 0xF3]
+    );
+}
+
+#[test]
+fn direct_page_labels() {
+    let source = "spcblock $00F0\nMOV A,Value\nMOV Value,#1\nValue:\nendspcblock\n";
+    assert_eq!(data(source), [0xE4, 0xF5, 0x8F, 0x01, 0xF5]);
+}
+
+#[test]
+fn outer_labels() {
+    let source = "spcblock Engine\nMOV A,#Volume\nendspcblock\n";
+    let statements = parse_program(source).unwrap();
+    let context = |label: &Label| match *label {
+        Label::Named(VariableName("Engine")) => Some(0x0800),
+        Label::Named(VariableName("Volume")) => Some(0x7F),
+        _ => None,
+    };
+    let upload = spcblock::upload(&statements, &context).unwrap().unwrap();
+    assert_eq!(
+        upload.blocks,
+        [Block {
+            address: 0x0800,
+            data: vec![0xE8, 0x7F],
+            execute: None,
+        }]
+    );
+}
+
+/// Context of the main program with every kind of symbol.
+struct Program;
+
+impl<'a> Context<'a> for Program {
+    fn label(&self, label: &Label) -> Option<i64> {
+        match *label {
+            Label::Named(VariableName("Table")) => Some(0x10),
+            _ => None,
+        }
+    }
+
+    fn file(&self, path: &str) -> Option<&[u8]> {
+        match path {
+            "data.bin" => Some(&[0x34]),
+            _ => None,
+        }
+    }
+
+    fn defined(&self, name: &str) -> bool {
+        name == "Table" || name == "Later"
+    }
+
+    fn mapper(&self) -> Mapper {
+        Mapper::HiRom
+    }
+
+    fn datasize(&self, label: &Label) -> Option<i64> {
+        match *label {
+            Label::Named(VariableName("Table")) => Some(3),
+            _ => None,
+        }
+    }
+
+    fn text(&self, name: &str) -> Option<&str> {
+        match name {
+            "Message" => Some("hello"),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn program_context() {
+    let source = "spcblock $0400\nStart:\nMOV A,#datasize(Start)\nEnd:\n\
+                  MOV A,#datasize(Table)\nMOV A,#defined(\"Later\")\nMOV A,#defined(\"End\")\n\
+                  MOV A,#readfile1(\"data.bin\", 0)\nMOV A,#snestopc($C00056)\nendspcblock\n";
+    let statements = parse_program(source).unwrap();
+    let upload = spcblock::upload(&statements, &Program).unwrap().unwrap();
+    assert_eq!(
+        upload.blocks[0].data,
+        [0xE8, 0x02, 0xE8, 0x03, 0xE8, 0x01, 0xE8, 0x01, 0xE8, 0x34, 0xE8, 0x56]
+    );
+    let statements = parse_program("spcblock $0400\nMOV A,#Message\nendspcblock\n").unwrap();
+    match spcblock::upload(&statements, &Program) {
+        Err(Error::Eval(_, eval::Error::ExpectedNumber)) => {}
+        result => panic!("{:?}", result),
+    }
+}
+
+fn assemble_code(source: &str) -> Result<Vec<u8>, output::Error<'_>> {
+    let statements = parse_program(source).unwrap();
+    output::assemble(statements, 0x8000, State::new(true, true)).map(|output| output.data)
+}
+
+#[test]
+fn transfer_table_in_code() {
+    let source = "LDA #1\nspcblock $0400\nMOV A,#$12\nendspcblock execute $0400\nLDA #2\n";
+    assert_eq!(
+        assemble_code(source),
+        Ok(vec![
+            0xA9, 0x01, 0x02, 0x00, 0x00, 0x04, 0xE8, 0x12, 0x00, 0x00, 0x00, 0x04, 0xA9, 0x02,
+        ])
+    );
+    // Labels of the program can be defined after a block.
+    let source = "JMP After\nspcblock $0400\nMOV A,#Volume\nendspcblock\nAfter:\nVolume = $7F\n";
+    assert_eq!(
+        assemble_code(source),
+        Ok(vec![0x4C, 0x09, 0x80, 0x02, 0x00, 0x00, 0x04, 0xE8, 0x7F])
+    );
+    let source = "spcblock $0400\nMOV A,Value\nendspcblock\nValue = $1234\n";
+    match assemble_code(source) {
+        Err(output::Error::SpcBlock(Error::UnstableLayout(span))) => {
+            assert!(span.fragment().ends_with("endspcblock"))
+        }
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn upload_of_several_blocks() {
+    let source = "
+        spcblock $0200
+        Main:
+            BRA Main
+        endspcblock execute Main
+        spcblock $1000
+            NOP
+        endspcblock
+    ";
+    let upload = assemble(source).unwrap().unwrap();
+    assert_eq!(upload.entry, 0x0200);
+    assert_eq!(upload.blocks[0].execute, Some(0x0200));
+    assert_eq!(
+        upload.transfer_table(),
+        [
+            0x02, 0x00, 0x00, 0x02, 0x2F, 0xFE, 0x01, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+            0x02,
+        ]
+    );
+    assert_eq!(assemble("LDA #$12\n"), Ok(None));
+}
+
+#[test]
+fn errors() {
+    assert!(matches!(
+        assemble("spcblock $0400\nMOV A,#Missing\nendspcblock\n"),
+        Err(Error::Eval(span, _)) if span.fragment() == "MOV A,#Missing"
+    ));
+    assert_eq!(
+        assemble("spcblock $0400\nMOV A,#$100\nendspcblock\n"),
+        Err(Error::Encode(
+            Span::new("MOV A,#$100"),
+            spc700::Error::OutOfRange(0x100)
+        ))
+    );
+    assert_eq!(
+        assemble("spcblock $0400\nA:\nA:\nendspcblock\n"),
+        Err(Error::DuplicateLabel(Span::new("A:")))
+    );
+    assert_eq!(
+        assemble("spcblock $0400\narch 65816\nLDA #1\nendspcblock\n"),
+        Err(Error::UnsupportedStatement(Span::new("arch 65816")))
+    );
+    assert_eq!(
+        assemble("spcblock $FFFF\nNOP\nNOP\nendspcblock\n"),
+        Err(Error::OutOfRam(Span::new("NOP")))
+    );
+    assert!(matches!(
+        assemble("spcblock $10000\nendspcblock\n"),
+        Err(Error::OutOfRam(_))
+    ));
+}
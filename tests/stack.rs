@@ -0,0 +1,82 @@
+extern crate mvp;
+
+use mvp::analysis::stack::{self, Usage};
+use mvp::parser::grammar::{parse_program, program_with_recovery, Input};
+
+fn estimate(source: &str, call_depth: u32) -> Vec<Usage<'_>> {
+    let statements = parse_program(Input::new(source)).unwrap();
+    stack::estimate(&statements, call_depth)
+}
+
+#[test]
+fn pushes_and_pulls() {
+    let usage = estimate("Main:\n PHP\n PHB\n PLB\n PEA $7E7E\n PLX\n PLP\n RTL\n", 0);
+    assert_eq!(
+        usage,
+        vec![Usage {
+            name: "Main",
+            bytes: 3,
+            exact: true
+        }]
+    );
+}
+
+#[test]
+fn nested_calls() {
+    let source = "
+Main:
+    PHA
+    JSL Outer
+    PLA
+    RTS
+Outer:
+    PHD
+    JSR Inner
+    JSR Inner
+    PLD
+    RTL
+Inner:
+    PHX
+    PLX
+    RTS
+";
+    let usage = estimate(source, 8);
+    let bytes: Vec<_> = usage
+        .iter()
+        .map(|usage| (usage.name, usage.bytes))
+        .collect();
+    assert_eq!(bytes, vec![("Main", 11), ("Outer", 6), ("Inner", 2)]);
+    assert!(usage.iter().all(|usage| usage.exact));
+    assert!(usage[0].exceeds(10));
+    assert!(!usage[1].exceeds(10));
+}
+
+#[test]
+fn recursion_is_bounded() {
+    let usage = estimate("Recurse:\n PHA\n JSR Recurse\n PLA\n RTS\n", 3);
+    assert_eq!(
+        usage,
+        vec![Usage {
+            name: "Recurse",
+            bytes: 16,
+            exact: false
+        }]
+    );
+}
+
+#[test]
+fn indirect_calls_are_inexact() {
+    let usage = estimate("Main:\n JSR (Table,X)\n RTS\n", 8);
+    assert_eq!(usage[0].bytes, 2);
+    assert!(!usage[0].exact);
+}
+
+#[test]
+fn invalid_code_is_inexact() {
+    let source = "Main:\n PHA\n LDA ($19,,x)\n PLA\n RTS\n";
+    let (statements, errors) = program_with_recovery(Input::new(source));
+    assert_eq!(errors.len(), 1);
+    let usage = stack::estimate(&statements, 8);
+    assert_eq!(usage[0].bytes, 2);
+    assert!(!usage[0].exact);
+}
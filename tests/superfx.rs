@@ -0,0 +1,259 @@
+extern crate mvp;
+
+use std::collections::HashSet;
+
+use mvp::encoder::superfx::{self, Error, Operand};
+use mvp::encoder::Arch;
+use mvp::parser::ast::{Expression, Number, NumberWidth, Statement, SuperFxInstruction};
+use mvp::parser::grammar::{parse_program, parse_statement, Input, ParserOptions};
+
+fn operands(source: &str) -> Vec<Operand<Expression<'_>>> {
+    let options = ParserOptions {
+        arch: Arch::SuperFx,
+        ..ParserOptions::default()
+    };
+    let (statement, rest) = parse_statement(Input::with_options(source, options)).unwrap();
+    assert_eq!(rest, Input::new(""));
+    match statement {
+        Statement::SuperFx(instruction) => instruction.operands,
+        statement => panic!("{:?}", statement),
+    }
+}
+
+fn number(value: u32) -> Expression<'static> {
+    Expression::Number(Number {
+        value,
+        width: if value < 0x100 {
+            NumberWidth::OneByte
+        } else {
+            NumberWidth::TwoBytes
+        },
+    })
+}
+
+fn encode(name: &str, operands: &[Operand<u32>]) -> Result<Vec<u8>, Error> {
+    superfx::encode(name, operands, 0x8000).map(|bytes| bytes.to_vec())
+}
+
+#[test]
+fn arch_directive_selects_syntax() {
+    let statements = parse_program("arch superfx\niwt r1,#$1234\nstop\n").unwrap();
+    assert_eq!(*statements[0], Statement::Arch(Arch::SuperFx));
+    assert_eq!(
+        *statements[1],
+        Statement::SuperFx(SuperFxInstruction {
+            name: "iwt",
+            operands: vec![Operand::Register(1), Operand::Immediate(number(0x1234))],
+        })
+    );
+    assert_eq!(
+        *statements[2],
+        Statement::SuperFx(SuperFxInstruction {
+            name: "stop",
+            operands: vec![],
+        })
+    );
+}
+
+#[test]
+fn operand_syntax() {
+    assert_eq!(operands("with R15"), [Operand::Register(15)]);
+    assert_eq!(operands("stw (r2)"), [Operand::RegisterIndirect(2)]);
+    assert_eq!(
+        operands("lm r1,($1234)"),
+        [Operand::Register(1), Operand::Memory(number(0x1234))]
+    );
+    assert_eq!(
+        operands("sms ($0104),r3"),
+        [Operand::Memory(number(0x104)), Operand::Register(3)]
+    );
+    assert_eq!(operands("bra $8000"), [Operand::Address(number(0x8000))]);
+}
+
+#[test]
+fn register_names_in_expressions() {
+    match operands("iwt r1,#r16")[1] {
+        Operand::Immediate(Expression::Variable(_)) => {}
+        ref operand => panic!("{:?}", operand),
+    }
+    match operands("bra r1+2")[0] {
+        Operand::Address(Expression::Binary(..)) => {}
+        ref operand => panic!("{:?}", operand),
+    }
+    match operands("bra ($8000)*2")[0] {
+        Operand::Address(Expression::Binary(..)) => {}
+        ref operand => panic!("{:?}", operand),
+    }
+}
+
+#[test]
+fn prefixes_select_registers() {
+    assert_eq!(encode("WITH", &[Operand::Register(1)]), Ok(vec![0x21]));
+    assert_eq!(encode("to", &[Operand::Register(3)]), Ok(vec![0x13]));
+    assert_eq!(encode("from", &[Operand::Register(15)]), Ok(vec![0xBF]));
+    assert_eq!(encode("add", &[Operand::Register(2)]), Ok(vec![0x52]));
+    assert_eq!(encode("lsr", &[]), Ok(vec![0x03]));
+}
+
+#[test]
+fn alternate_instructions() {
+    assert_eq!(encode("adc", &[Operand::Register(2)]), Ok(vec![0x3D, 0x52]));
+    assert_eq!(
+        encode("adc", &[Operand::Immediate(2)]),
+        Ok(vec![0x3F, 0x52])
+    );
+    assert_eq!(encode("cmp", &[Operand::Register(4)]), Ok(vec![0x3F, 0x64]));
+    assert_eq!(
+        encode("ldb", &[Operand::RegisterIndirect(11)]),
+        Ok(vec![0x3D, 0x4B])
+    );
+    assert_eq!(encode("getbs", &[]), Ok(vec![0x3F, 0xEF]));
+    assert_eq!(
+        encode("ljmp", &[Operand::Register(8)]),
+        Ok(vec![0x3D, 0x98])
+    );
+}
+
+#[test]
+fn immediates() {
+    let ibt = |value| encode("ibt", &[Operand::Register(2), Operand::Immediate(value)]);
+    assert_eq!(ibt(0x7F), Ok(vec![0xA2, 0x7F]));
+    assert_eq!(ibt(0xFF), Ok(vec![0xA2, 0xFF]));
+    assert_eq!(ibt(-1i32 as u32), Ok(vec![0xA2, 0xFF]));
+    assert_eq!(ibt(0x100), Err(Error::OutOfRange(0x100)));
+    assert_eq!(
+        encode("lea", &[Operand::Register(4), Operand::Immediate(0xABCD)]),
+        Ok(vec![0xF4, 0xCD, 0xAB])
+    );
+    assert_eq!(encode("link", &[Operand::Immediate(4)]), Ok(vec![0x94]));
+    assert_eq!(
+        encode("link", &[Operand::Immediate(5)]),
+        Err(Error::OutOfRange(5))
+    );
+}
+
+#[test]
+fn memory() {
+    assert_eq!(
+        encode("lm", &[Operand::Register(1), Operand::Memory(0x1234)]),
+        Ok(vec![0x3D, 0xF1, 0x34, 0x12])
+    );
+    assert_eq!(
+        encode("sm", &[Operand::Memory(0x1234), Operand::Register(1)]),
+        Ok(vec![0x3E, 0xF1, 0x34, 0x12])
+    );
+    assert_eq!(
+        encode("sms", &[Operand::Memory(0x1FE), Operand::Register(1)]),
+        Ok(vec![0x3E, 0xA1, 0xFF])
+    );
+    assert_eq!(
+        encode("lms", &[Operand::Register(1), Operand::Memory(0x101)]),
+        Err(Error::OutOfRange(0x101))
+    );
+    assert_eq!(
+        encode("lms", &[Operand::Register(1), Operand::Memory(0x200)]),
+        Err(Error::OutOfRange(0x200))
+    );
+}
+
+#[test]
+fn branches() {
+    assert_eq!(
+        encode("beq", &[Operand::Address(0x8002)]),
+        Ok(vec![0x09, 0x00])
+    );
+    assert_eq!(
+        encode("bne", &[Operand::Address(0x8081)]),
+        Ok(vec![0x08, 0x7F])
+    );
+    assert_eq!(
+        encode("bne", &[Operand::Address(0x7F82)]),
+        Ok(vec![0x08, 0x80])
+    );
+    assert_eq!(
+        encode("bne", &[Operand::Address(0x8082)]),
+        Err(Error::OutOfRange(0x8082))
+    );
+}
+
+#[test]
+fn move_pseudo_instructions() {
+    let registers = [Operand::Register(1), Operand::Register(2)];
+    assert_eq!(encode("move", &registers), Ok(vec![0x22, 0x11]));
+    assert_eq!(encode("MOVES", &registers), Ok(vec![0x21, 0xB2]));
+    assert_eq!(
+        encode("move", &[Operand::Register(1)]),
+        Err(Error::UnsupportedOperands("MOVE"))
+    );
+}
+
+#[test]
+fn register_ranges() {
+    assert_eq!(
+        encode("and", &[Operand::Register(0)]),
+        Err(Error::UnsupportedOperands("AND"))
+    );
+    assert_eq!(
+        encode("inc", &[Operand::Register(15)]),
+        Err(Error::UnsupportedOperands("INC"))
+    );
+    assert_eq!(
+        encode("ldw", &[Operand::RegisterIndirect(12)]),
+        Err(Error::UnsupportedOperands("LDW"))
+    );
+}
+
+#[test]
+fn unknown_mnemonic() {
+    assert_eq!(encode("lda", &[]), Err(Error::UnknownMnemonic));
+}
+
+#[test]
+fn opcodes_are_unique() {
+    let mut opcodes = HashSet::new();
+    for &(name, modes, prefix, opcode) in superfx::instructions() {
+        // LEA is another name of IWT.
+        if name == "LEA" {
+            continue;
+        }
+        let operands: Vec<Vec<Operand<u32>>> = modes
+            .iter()
+            .map(|&mode| match mode {
+                superfx::Mode::Register(low, high) => (low..=high).map(Operand::Register).collect(),
+                superfx::Mode::RegisterIndirect(low, high) => {
+                    (low..=high).map(Operand::RegisterIndirect).collect()
+                }
+                superfx::Mode::Nibble(low, high) => (low..=high)
+                    .map(|value| Operand::Immediate(u32::from(value)))
+                    .collect(),
+                superfx::Mode::Byte | superfx::Mode::Word => vec![Operand::Immediate(0)],
+                superfx::Mode::ShortAddress | superfx::Mode::Address => vec![Operand::Memory(0)],
+                superfx::Mode::Relative => vec![Operand::Address(0x8002)],
+            })
+            .collect();
+        let combinations = operands.iter().fold(vec![vec![]], |combinations, choices| {
+            combinations
+                .iter()
+                .flat_map(|combination: &Vec<Operand<u32>>| {
+                    choices.iter().map(move |choice| {
+                        let mut combination = combination.clone();
+                        combination.push(choice.clone());
+                        combination
+                    })
+                })
+                .collect()
+        });
+        for combination in combinations {
+            let bytes = encode(name, &combination).unwrap();
+            let size = if prefix.is_some() { 2 } else { 1 };
+            assert_eq!(bytes[0], prefix.unwrap_or(bytes[0]));
+            assert!(bytes[size - 1] >= opcode, "{}", name);
+            assert!(
+                opcodes.insert(bytes[..size].to_vec()),
+                "{} {:?}",
+                name,
+                bytes
+            );
+        }
+    }
+}
@@ -0,0 +1,173 @@
+extern crate mvp;
+
+use mvp::eval::Context;
+use mvp::parser::ast::{Expression, Label, Span, Statement, VariableName};
+use mvp::parser::grammar::{self, parse_program, Input};
+use mvp::symbols::{Error, Pass, Symbol, SymbolKind, Symbols};
+
+fn expression(source: &str) -> Expression<'_> {
+    grammar::parse_expression(Input::new(source)).unwrap().0
+}
+
+fn named(name: &str) -> Label<'_> {
+    Label::Named(VariableName(name))
+}
+
+fn scoped(name: &str) -> Label<'_> {
+    Label::Scoped(VariableName(name))
+}
+
+/// Runs a pass over labels of a program, giving every other statement
+/// a size of one byte, and returns values of `BRA` operands.
+fn run_pass<'a>(
+    symbols: &mut Symbols<'a>,
+    source: &'a str,
+    pass: Pass,
+) -> Result<Vec<Option<i64>>, Error<'a>> {
+    symbols.begin_pass(pass);
+    let mut address = 0x8000;
+    let mut operands = Vec::new();
+    for statement in parse_program(source).unwrap() {
+        match *statement {
+            Statement::Label(ref label) => symbols.define_label(label, address, statement.span)?,
+            Statement::Assignment(ref name, ref value) => {
+                let value = symbols.resolve(value)?.unwrap_or(0);
+                symbols.assign(name, value, statement.span)?;
+            }
+            Statement::Opcode(ref opcode) => {
+                if let Some(ref value) = opcode.value {
+                    operands.push(symbols.resolve(value)?);
+                }
+                address += 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(operands)
+}
+
+fn assemble(source: &str) -> Result<Vec<i64>, Error<'_>> {
+    let mut symbols = Symbols::new();
+    run_pass(&mut symbols, source, Pass::First)?;
+    let operands = run_pass(&mut symbols, source, Pass::Second)?;
+    Ok(operands.into_iter().map(Option::unwrap).collect())
+}
+
+#[test]
+fn forward_references() {
+    let mut symbols = Symbols::new();
+    let source = "BRA Later\nNOP\nLater:\nBRA Later\n";
+    let first = run_pass(&mut symbols, source, Pass::First).unwrap();
+    assert_eq!(first, [None, Some(0x8002)]);
+    let second = run_pass(&mut symbols, source, Pass::Second).unwrap();
+    assert_eq!(second, [Some(0x8002), Some(0x8002)]);
+}
+
+#[test]
+fn scoped_labels_belong_to_named_labels() {
+    let source = "First:\nNOP\n.loop:\nBRA .loop\nSecond:\n.loop:\nBRA .loop\n";
+    assert_eq!(assemble(source), Ok(vec![0x8001, 0x8002]));
+}
+
+#[test]
+fn scoped_forward_references() {
+    let source = "Main:\nBRA .end\nNOP\n.end:\nOther:\n.end:\nBRA .end\n";
+    assert_eq!(assemble(source), Ok(vec![0x8002, 0x8002]));
+}
+
+#[test]
+fn relative_labels() {
+    let source = "-:\nNOP\nBRA -\nBRA +\nNOP\n+:\n-:\nBRA -\nBRA ++\n++:\n";
+    assert_eq!(assemble(source), Ok(vec![0x8000, 0x8004, 0x8004, 0x8006]));
+}
+
+#[test]
+fn assignments_can_be_repeated() {
+    let source = "count = 1\nBRA count\ncount = count + 1\nBRA count\n";
+    assert_eq!(assemble(source), Ok(vec![1, 2]));
+}
+
+#[test]
+fn redefined_labels() {
+    let source = "Main:\nNOP\nMain:\n";
+    match assemble(source) {
+        Err(Error::Redefined(label, span)) => {
+            assert_eq!(label, named("Main"));
+            assert_eq!(span.fragment(), "Main:");
+        }
+        result => panic!("{:?}", result),
+    }
+    assert!(assemble("Main:\nMain = 1\n").is_err());
+    assert!(assemble("Main = 1\nMain:\n").is_err());
+    assert!(assemble("A:\n.x:\n.x:\n").is_err());
+    assert!(assemble("A:\n.x:\nB:\n.x:\n").is_ok());
+}
+
+#[test]
+fn undefined_symbols() {
+    assert_eq!(
+        assemble("BRA Missing\n"),
+        Err(Error::Undefined(named("Missing"), None))
+    );
+    let error = assemble("Main:\nBRA .missing\n").unwrap_err();
+    assert_eq!(error, Error::Undefined(scoped("missing"), Some("Main")));
+    assert_eq!(error.to_string(), ".missing isn't defined in Main");
+    let error = assemble("BRA --\n").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "there is no -- label above this reference"
+    );
+    assert_eq!(
+        Error::Undefined(named("Missing"), None).to_string(),
+        "Missing isn't defined"
+    );
+}
+
+#[test]
+fn undefined_labels_are_forward_references_in_first_pass() {
+    let symbols = Symbols::new();
+    assert_eq!(symbols.pass(), Pass::First);
+    assert_eq!(symbols.resolve(&expression("Missing * 2")), Ok(None));
+    assert_eq!(
+        symbols.resolve(&expression("1 / 0")),
+        Err(Error::Eval(mvp::eval::Error::DivisionByZero))
+    );
+}
+
+#[test]
+fn listing_symbols() {
+    let mut symbols = Symbols::new();
+    let span = Span::new("");
+    symbols.define_label(&named("Main"), 0x8000, span).unwrap();
+    symbols.define_label(&scoped("loop"), 0x8004, span).unwrap();
+    symbols
+        .define_label(&Label::Relative(-1), 0x8005, span)
+        .unwrap();
+    symbols.assign(&VariableName("speed"), 2, span).unwrap();
+    let listed = symbols.symbols();
+    assert_eq!(
+        listed,
+        [
+            Symbol {
+                scope: None,
+                name: "speed",
+                value: 2,
+                kind: SymbolKind::Assignment,
+            },
+            Symbol {
+                scope: None,
+                name: "Main",
+                value: 0x8000,
+                kind: SymbolKind::Label,
+            },
+            Symbol {
+                scope: Some("Main"),
+                name: "loop",
+                value: 0x8004,
+                kind: SymbolKind::Label,
+            },
+        ]
+    );
+    assert_eq!(listed[2].to_string(), "Main.loop");
+    assert_eq!(symbols.label(&Label::Relative(-1)), Some(0x8005));
+}
@@ -0,0 +1,94 @@
+#![cfg(feature = "template-smoke-test")]
+
+extern crate mvp;
+extern crate serde_json;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use mvp::assembler::Assembler;
+use mvp::mapping::Mapper;
+use mvp::rom;
+use mvp::scaffold::{Template, MANIFEST};
+
+fn word(rom: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([rom[offset], rom[offset + 1]])
+}
+
+/// Converts a bank `$00` address to a file offset.
+fn file_offset(mapper: Mapper, address: u16) -> usize {
+    match mapper {
+        Mapper::HiRom => address.into(),
+        _ => usize::from(address) - 0x8000,
+    }
+}
+
+fn create_project(mapper: Mapper) -> PathBuf {
+    let directory = env::temp_dir().join(format!(
+        "mvp-template-{}-{}",
+        mapper.directive(),
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&directory);
+    Template::new("smoke", mapper)
+        .unwrap()
+        .write(&directory)
+        .unwrap();
+    directory
+}
+
+fn boot(mapper: Mapper) {
+    let directory = create_project(mapper);
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(directory.join(MANIFEST)).unwrap()).unwrap();
+    assert_eq!(manifest["mapper"], mapper.directive());
+    let build = fs::read(directory.join(manifest["output"].as_str().unwrap())).unwrap();
+    let mut assembler = Assembler::builder()
+        .root(directory.join(manifest["main"].as_str().unwrap()))
+        .title("smoke")
+        .build();
+    assembler.load().unwrap();
+    let output = assembler.assemble().unwrap();
+    assert!(output.warnings.is_empty(), "{:?}", output.warnings);
+    let rom = assembler.write(&output).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(rom, build);
+
+    let header = mapper.layout().header;
+    assert_eq!(&rom[header..header + 5], b"SMOKE");
+    assert_eq!(rom[header + 0x15], mapper.map_mode());
+    assert_eq!(rom[header + 0x17], rom::size_code(rom.len()));
+
+    let checksum = rom
+        .iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(byte.into()));
+    assert_eq!(word(&rom, header + 0x1E), checksum);
+    assert_eq!(word(&rom, header + 0x1C) ^ checksum, 0xFFFF);
+
+    // Every used vector points to code in bank $00.
+    for vector in (0x24..0x40).step_by(2) {
+        let address = word(&rom, header + vector);
+        if address != 0 {
+            assert!(address >= 0x8000, "vector {:#X} is {:#X}", vector, address);
+            assert_ne!(rom[file_offset(mapper, address)], 0);
+        }
+    }
+    assert_eq!(word(&rom, header + 0x3C), 0x8000);
+    let reset = file_offset(mapper, 0x8000);
+    // SEI, CLC, XCE, SEP #$30, then forced blank
+    assert_eq!(
+        &rom[reset..reset + 10],
+        &[0x78, 0x18, 0xFB, 0xE2, 0x30, 0xA9, 0x80, 0x8D, 0x00, 0x21]
+    );
+}
+
+#[test]
+fn lorom_template_boots() {
+    boot(Mapper::LoRom);
+}
+
+#[test]
+fn hirom_template_boots() {
+    boot(Mapper::HiRom);
+}
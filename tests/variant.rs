@@ -0,0 +1,126 @@
+extern crate mvp;
+
+use mvp::eval::{self, Context};
+use mvp::mapping::Mapper;
+use mvp::parser::ast::{Condition, Expression, Label, Spanned, Statement, VariableName};
+use mvp::parser::grammar::{parse_program, Input};
+use mvp::rom;
+use mvp::symbols::Symbols;
+use mvp::variant::{Error, Variants};
+
+const SOURCE: &str = "
+romvariant US, $CBF43926
+romvariant JP, 1
+varianttable SetBrightness, US = $808000, JP = $808010
+if romversion == JP
+    assert SetBrightness == $808010
+endif
+";
+
+fn statements() -> Vec<Spanned<'static, Statement<'static>>> {
+    parse_program(Input::new(SOURCE)).unwrap()
+}
+
+fn no_labels(_: &Label) -> Option<i64> {
+    None
+}
+
+#[test]
+fn detect_variant() {
+    let statements = statements();
+    let variants = Variants::new(&statements).unwrap();
+    let variant = variants.detect(b"123456789").unwrap().unwrap();
+    assert_eq!(variant.name, VariableName("US"));
+}
+
+#[test]
+fn unrecognized_rom() {
+    let statements = statements();
+    let variants = Variants::new(&statements).unwrap();
+    assert_eq!(
+        variants.detect(b"12345678"),
+        Err(Error::UnrecognizedRom(rom::crc32(b"12345678")))
+    );
+}
+
+#[test]
+fn no_variants_accept_any_rom() {
+    let variants = Variants::new(&[]).unwrap();
+    assert_eq!(variants.detect(b"123"), Ok(None));
+}
+
+#[test]
+fn variant_context() {
+    let statements = statements();
+    let variants = Variants::new(&statements).unwrap();
+    let (predicate, body) = match statements[3].node {
+        Statement::If(ref conditions) => match conditions[0] {
+            Condition {
+                predicate: Some(ref predicate),
+                ref statements,
+            } => (predicate, statements),
+            ref condition => panic!("unexpected condition: {:?}", condition),
+        },
+        ref statement => panic!("unexpected statement: {:?}", statement),
+    };
+
+    let us = variants.context(variants.get("US").unwrap(), &no_labels);
+    assert_eq!(eval::evaluate(predicate, &us), Ok(0));
+    let jp = variants.context(variants.get("JP").unwrap(), &no_labels);
+    assert_eq!(eval::evaluate(predicate, &jp), Ok(1));
+    match body[0].node {
+        Statement::Assert(ref condition, None) => {
+            assert_eq!(eval::assert(condition, None, &jp), Ok(()));
+            assert!(eval::assert(condition, None, &us).is_err());
+        }
+        ref statement => panic!("unexpected statement: {:?}", statement),
+    }
+}
+
+#[test]
+fn missing_table_entry() {
+    let statements = parse_program(Input::new(
+        "romvariant US, 1\nromvariant JP, 2\nvarianttable A, US = 3\n",
+    ))
+    .unwrap();
+    let variants = Variants::new(&statements).unwrap();
+    let jp = variants.context(variants.get("JP").unwrap(), &no_labels);
+    let name = Label::Named(VariableName("A"));
+    assert_eq!(
+        eval::evaluate(&Expression::Variable(name.clone()), &jp),
+        Err(eval::Error::UndefinedLabel(name))
+    );
+}
+
+fn error(source: &'static str) -> Error<'static> {
+    let statements = parse_program(Input::new(source)).unwrap();
+    Variants::new(&statements).unwrap_err().node
+}
+
+#[test]
+fn invalid_declarations() {
+    assert_eq!(
+        error("romvariant US, 1\nromvariant US, 2"),
+        Error::DuplicateVariant(VariableName("US"))
+    );
+    assert_eq!(
+        error("romvariant US, 1\nvarianttable A, EU = 2"),
+        Error::UnknownVariant(VariableName("EU"))
+    );
+    assert_eq!(
+        error("romvariant US, 1\nvarianttable A, US = 2, US = 3"),
+        Error::DuplicateAddress(VariableName("A"), VariableName("US"))
+    );
+}
+
+#[test]
+fn context_forwards_to_parent() {
+    let statements = statements();
+    let variants = Variants::new(&statements).unwrap();
+    let mut symbols = Symbols::new();
+    symbols.set_mapper(Mapper::HiRom);
+    let jp = variants.context(variants.get("JP").unwrap(), &symbols);
+    assert_eq!(jp.mapper(), Mapper::HiRom);
+    assert!(jp.defined("romversion") && jp.defined("JP") && jp.defined("SetBrightness"));
+    assert!(!jp.defined("Missing"));
+}
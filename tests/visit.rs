@@ -0,0 +1,65 @@
+extern crate mvp;
+
+use mvp::parser::ast::{Condition, Label, Spanned, Statement, VariableName};
+use mvp::parser::grammar::{parse_program, Input};
+use mvp::parser::visit::{self, Visitor};
+
+fn parse(source: &str) -> Vec<Spanned<'_, Statement<'_>>> {
+    parse_program(Input::new(source)).unwrap()
+}
+
+#[derive(Default)]
+struct Labels<'a> {
+    labels: Vec<&'a str>,
+    skip_conditions: bool,
+}
+
+impl<'a> Visitor<'a> for Labels<'a> {
+    fn visit_condition(&mut self, condition: &Condition<'a>) {
+        if !self.skip_conditions {
+            visit::walk_condition(self, condition);
+        }
+    }
+
+    fn visit_label(&mut self, label: &Label<'a>) {
+        if let Label::Named(VariableName(name)) = *label {
+            self.labels.push(name);
+        }
+    }
+}
+
+const SOURCE: &str = "
+Start:
+    MVN Source, Target
+    function offset(x) = x + Base
+    varianttable Reset, US = Us, JP = -Jp
+    if Version == 1
+        print \"version\", Message
+    endif
+    LDA (Pointer,x)
+";
+
+#[test]
+fn visits_labels_in_order() {
+    let mut labels = Labels::default();
+    visit::walk_statements(&mut labels, &parse(SOURCE));
+    assert_eq!(
+        labels.labels,
+        vec![
+            "Start", "Source", "Target", "x", "Base", "Us", "Jp", "Version", "Message", "Pointer",
+        ]
+    );
+}
+
+#[test]
+fn overridden_methods_control_recursion() {
+    let mut labels = Labels {
+        skip_conditions: true,
+        ..Labels::default()
+    };
+    visit::walk_statements(&mut labels, &parse(SOURCE));
+    assert_eq!(
+        labels.labels,
+        vec!["Start", "Source", "Target", "x", "Base", "Us", "Jp", "Pointer"]
+    );
+}
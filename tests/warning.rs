@@ -0,0 +1,200 @@
+extern crate mvp;
+
+use mvp::assembler::flags::State;
+use mvp::assembler::output::{self, Error, Output};
+use mvp::eval;
+use mvp::parser::ast::{Register, Statement};
+use mvp::parser::grammar::{parse_program, parse_statement};
+use mvp::symbols::{self, Symbols};
+use mvp::warning::{Category, Level, Levels};
+
+fn assemble<'a>(source: &'a str, levels: &[(Category, Level)]) -> Result<Output<'a>, Error<'a>> {
+    let mut defaults = Levels::default();
+    for &(category, level) in levels {
+        defaults.set(category, level);
+    }
+    output::assemble_with_levels(
+        parse_program(source).unwrap(),
+        0x808000,
+        State::new(true, true),
+        Symbols::new(),
+        defaults,
+    )
+}
+
+fn warnings<'a>(source: &'a str, levels: &[(Category, Level)]) -> Vec<(Category, &'a str)> {
+    assemble(source, levels)
+        .unwrap()
+        .warnings
+        .iter()
+        .map(|warning| (warning.category, warning.span.fragment()))
+        .collect()
+}
+
+#[test]
+fn directive() {
+    assert_eq!(
+        parse_statement("warnings deny Unused-Label").unwrap().0,
+        Statement::Warnings(Category::UnusedLabel, Level::Deny)
+    );
+    assert!(parse_program("warnings deny unused\n").is_err());
+    assert!(parse_program("warnings forbid unused-label\n").is_err());
+}
+
+#[test]
+fn implicit_direct_page() {
+    let source = "Value = $12\nLDA Value\nLDA $12\nLDA.b Value\nLDA Value+$1000,x\nSTA Value,x\n";
+    assert_eq!(warnings(source, &[]), []);
+    let level = [(Category::ImplicitDirectPage, Level::Warn)];
+    assert_eq!(
+        warnings(source, &level),
+        [
+            (Category::ImplicitDirectPage, "LDA Value"),
+            (Category::ImplicitDirectPage, "STA Value,x"),
+        ]
+    );
+    let output = assemble(source, &level).unwrap();
+    assert_eq!(
+        output.warnings[0].to_string(),
+        "`LDA Value` uses direct page addressing because of the value of its operand [implicit-dp]"
+    );
+}
+
+#[test]
+fn unknown_state() {
+    let source = "PLP\nLDA #$12\nLDX #$34\n";
+    match assemble(source, &[]) {
+        Err(Error::UnknownWidth(span, Register::Accumulator)) => {
+            assert_eq!(span.fragment(), "LDA #$12")
+        }
+        result => panic!("{:?}", result),
+    }
+    let level = [(Category::UnknownState, Level::Warn)];
+    let output = assemble(source, &level).unwrap();
+    assert_eq!(output.data, [0x28, 0xA9, 0x12, 0xA2, 0x34]);
+    assert_eq!(
+        output.warnings[1].message,
+        "width of index registers isn't known, assuming 8 bits"
+    );
+}
+
+#[test]
+fn unused_labels() {
+    let source = "Main:\nJSR Wait\nUnused:\nWait:\n.loop:\nBRA .loop\nOther:\n";
+    let level = [(Category::UnusedLabel, Level::Warn)];
+    assert_eq!(
+        warnings(source, &level),
+        [
+            (Category::UnusedLabel, "Main:"),
+            (Category::UnusedLabel, "Unused:"),
+            (Category::UnusedLabel, "Other:"),
+        ]
+    );
+    let source = "warnings warn unused-label\nMain:\nwarnings allow unused-label\nOther:\n";
+    assert_eq!(warnings(source, &[]), [(Category::UnusedLabel, "Main:")]);
+}
+
+#[test]
+fn truncated_operands() {
+    match assemble("LDA #$1234\n", &[]) {
+        Err(Error::Symbol(_, symbols::Error::Eval(eval::Error::Overflow))) => {}
+        result => panic!("{:?}", result),
+    }
+    let source = "LDA.b $1234\n";
+    match assemble(source, &[]) {
+        Err(Error::WidthMismatch(mismatch)) => {
+            assert_eq!((mismatch.suffix, mismatch.required), (1, 2))
+        }
+        result => panic!("{:?}", result),
+    }
+    let output = assemble(source, &[(Category::TruncatedOperand, Level::Allow)]).unwrap();
+    assert_eq!(output.data, [0xA5, 0x34]);
+    assert!(output.warnings.is_empty());
+    let output = assemble("warnings warn truncated-operand\nLDA #$1234\n", &[]).unwrap();
+    assert_eq!(output.data, [0xA9, 0x34]);
+    assert_eq!(
+        output.warnings[0].message,
+        "operand $1234 doesn't fit in 1 bytes, truncated to $34"
+    );
+    let output = assemble("warnings warn truncated-operand\nLDA.b #$1234\n", &[]).unwrap();
+    assert_eq!(
+        output.warnings[0].message,
+        "operand needs 2 bytes, but its .b suffix selects 1, truncated to $34"
+    );
+}
+
+#[test]
+fn bank_mismatch() {
+    let source = "Buffer = $7E2000\nbank $7E\nLDA Buffer\nLDA.w $7F2000\n";
+    match assemble(source, &[]) {
+        Err(Error::Denied(warning)) => {
+            assert_eq!(warning.span.fragment(), "LDA.w $7F2000");
+            assert_eq!(
+                warning.to_string(),
+                "absolute address $7F2000 is outside of assumed bank $7E [bank-mismatch]"
+            );
+        }
+        result => panic!("{:?}", result),
+    }
+    let output = assemble(source, &[(Category::BankMismatch, Level::Warn)]).unwrap();
+    assert_eq!(output.data, [0xAD, 0x00, 0x20, 0xAD, 0x00, 0x20]);
+    assert_eq!(output.warnings.len(), 1);
+    // Jumps stay in the bank of code, and `bank auto` assumes it for data.
+    let source = "bank $7E\nMain:\nJMP Main\nLDA Main\nbank auto\nLDA Main\n";
+    assert_eq!(
+        assemble(source, &[]).unwrap().data,
+        [0x4C, 0x00, 0x80, 0xAF, 0x00, 0x80, 0x80, 0xAD, 0x00, 0x80]
+    );
+}
+
+#[test]
+fn denied_warnings() {
+    let source = "Value = $12\nwarnings deny implicit-dp\nLDA Value\n";
+    match assemble(source, &[]) {
+        Err(Error::Denied(warning)) => {
+            assert_eq!(warning.category, Category::ImplicitDirectPage);
+            assert_eq!(warning.span.fragment(), "LDA Value");
+        }
+        result => panic!("{:?}", result),
+    }
+    match assemble("Unused:\n", &[(Category::UnusedLabel, Level::Deny)]) {
+        Err(error) => assert_eq!(
+            error.to_string(),
+            "label Unused is never used [unused-label]"
+        ),
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn width_conflict() {
+    let source = "SEP #$20\nBEQ Store\nREP #$20\nStore:\nLDA #$12\n";
+    assert_eq!(warnings(source, &[]), [(Category::WidthConflict, "Store:")]);
+    let output = assemble(source, &[]).unwrap();
+    assert_eq!(output.data[6..], [0xA9, 0x12]);
+    assert_eq!(
+        output.warnings[0].message,
+        "label Store is reached with an 8-bit accumulator from `BEQ Store`, and a 16-bit one \
+         from `REP #$20`"
+    );
+    let level = [(Category::WidthConflict, Level::Allow)];
+    assert_eq!(warnings(source, &level), []);
+}
+
+#[test]
+fn assumed_width_conflict() {
+    for source in &[
+        "REP #$20\nSub:\nLDA #$12\n",
+        "REP #$20\nBRA Sub\nSub:\nLDA #$12\n",
+    ] {
+        assert_eq!(warnings(source, &[]), [(Category::WidthConflict, "Sub:")]);
+        let output = assemble(source, &[]).unwrap();
+        assert_eq!(output.data[output.data.len() - 2..], [0xA9, 0x12]);
+    }
+    let output = assemble("REP #$20\nSub:\nLDA #$12\n", &[]).unwrap();
+    assert_eq!(
+        output.warnings[0].message,
+        "label Sub is reached with a 16-bit accumulator from `REP #$20`, but is assembled for \
+         an 8-bit one"
+    );
+}
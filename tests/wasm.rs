@@ -0,0 +1,102 @@
+#![cfg(feature = "wasm-bindgen")]
+
+extern crate mvp;
+extern crate serde_json;
+
+use mvp::wasm::{self, Sources};
+use serde_json::Value;
+
+fn json(text: &str) -> Value {
+    serde_json::from_str(text).unwrap()
+}
+
+#[test]
+fn parse() {
+    let parsed = json(&wasm::parse(
+        "!speed = 2 ; \u{1F600}\nMain:\n  BRA Main\nif 1\n",
+    ));
+    let tokens: Vec<_> = parsed["tokens"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|token| {
+            (
+                token["kind"].as_str().unwrap(),
+                token["start"].as_u64().unwrap(),
+                token["end"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        tokens,
+        [
+            ("define", 0, 6),
+            ("operator", 7, 8),
+            ("number", 9, 10),
+            ("comment", 11, 15),
+            ("label", 16, 20),
+            ("mnemonic", 24, 27),
+            ("label", 28, 32),
+            ("directive", 33, 35),
+            ("number", 36, 37),
+        ]
+    );
+    assert_eq!(
+        parsed["errors"][0]["message"],
+        "if block without `endif` `if 1`"
+    );
+    assert_eq!(parsed["errors"][0]["start"], 33);
+}
+
+#[test]
+fn assemble() {
+    assert_eq!(
+        wasm::assemble("Main:\nLDA #$12\nBRA Main\n", 0x8000).unwrap(),
+        [0xA9, 0x12, 0x80, 0xFC]
+    );
+    let mut sources = Sources::new();
+    sources.insert("game/main.asm", "incsrc \"lib/wait.asm\"\nJSR Wait\n");
+    sources.insert("game/lib/wait.asm", "Wait:\nWAI\nRTS\n");
+    assert_eq!(
+        sources.assemble("game/main.asm", 0x8000).unwrap(),
+        [0xCB, 0x60, 0x20, 0x00, 0x80]
+    );
+}
+
+#[test]
+fn diagnostics() {
+    assert_eq!(wasm::diagnostics("RTS\n", 0x8000), "[]");
+    let mut sources = Sources::new();
+    sources.insert("main.asm", "incsrc \"lib.asm\"\n");
+    sources.insert("lib.asm", "warnings warn unused-label\nUnused:\n");
+    let diagnostics = json(&sources.diagnostics("main.asm", 0x8000));
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(diagnostics[0]["severity"], "warning");
+    assert_eq!(diagnostics[0]["path"], "lib.asm");
+    assert_eq!(diagnostics[0]["line"], 2);
+    sources.insert("main.asm", "Main:\nincsrc \"lib.asm\"\n  Main:\n");
+    let diagnostics = json(&sources.diagnostics("main.asm", 0x8000));
+    assert_eq!(
+        diagnostics,
+        serde_json::json!([{
+            "severity": "error",
+            "message": "Main is already defined",
+            "path": "main.asm",
+            "line": 3,
+            "column": 3,
+        }])
+    );
+    assert!(sources.remove("lib.asm"));
+    assert!(!sources.remove("lib.asm"));
+    let diagnostics = json(&sources.diagnostics("main.asm", 0x8000));
+    assert_eq!(
+        diagnostics,
+        serde_json::json!([{
+            "severity": "error",
+            "message": "main.asm: included file lib.asm not found",
+            "path": null,
+            "line": null,
+            "column": null,
+        }])
+    );
+}
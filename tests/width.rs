@@ -0,0 +1,58 @@
+extern crate mvp;
+
+use mvp::analysis::width::{self, Mismatch};
+use mvp::parser::ast::Span;
+use mvp::parser::grammar::{parse_program, Input};
+
+fn check(source: &str) -> Vec<Mismatch<'_>> {
+    let statements = parse_program(Input::new(source)).unwrap();
+    width::check(&statements)
+}
+
+fn mismatch(span: &'static str, suffix: u32, required: u32) -> Mismatch<'static> {
+    Mismatch {
+        span: Span::new(span),
+        suffix,
+        required,
+    }
+}
+
+#[test]
+fn fitting_operands() {
+    let source = "
+LDA.b $12
+LDA.w #$1234
+LDA.b #-1
+LDA.l $7E0000,x
+LDA.b Label
+LDA.b (1 + 2),y
+MVN.b $7E, $7F
+NOP
+";
+    assert_eq!(check(source), vec![]);
+}
+
+#[test]
+fn too_wide_operands() {
+    let source = "
+LDA.b $1234
+LDA.b #256
+LDA.w $7E0000,x
+if 1
+    STA.b ($100),y
+endif
+LDA.b #-129
+LDA.l $10000*$10000
+";
+    assert_eq!(
+        check(source),
+        vec![
+            mismatch("LDA.b $1234", 1, 2),
+            mismatch("LDA.b #256", 1, 2),
+            mismatch("LDA.w $7E0000,x", 2, 3),
+            mismatch("STA.b ($100),y", 1, 2),
+            mismatch("LDA.b #-129", 1, 2),
+            mismatch("LDA.l $10000*$10000", 3, 5),
+        ]
+    );
+}